@@ -6,7 +6,6 @@
 
 use crate::actor_info::ActorInfo;
 use crate::event::Actor;
-use std::any::Any;
 use std::fmt;
 
 /// A handle to an actor that allows sending messages.
@@ -29,7 +29,7 @@ use std::marker::PhantomData;
 /// assert_eq!(id.as_u64(), 123);
 /// assert_eq!(id.scheduler_id(), 1);
 /// ```
-#[derive(Debug, Copy)]
+#[derive(Debug)]
 pub struct ActorId<T> {
     /// The unique numeric ID of the actor.
     id: u64,
@@ -37,8 +37,8 @@ pub struct ActorId<T> {
     scheduler_id: u32,
     /// The generation counter for detecting stale references.
     generation: u32,
-    /// Phantom data for the actor type (uses *const T for !Send/Safe).
-    _phantom: PhantomData<*const T>,
+    /// Phantom data for the actor type; the id itself is plain data.
+    _phantom: PhantomData<fn() -> T>,
 }
 
 // Manual implementations to avoid requiring T: Trait bounds
@@ -48,6 +48,8 @@ impl<T> Clone for ActorId<T> {
     }
 }
 
+impl<T> Copy for ActorId<T> {}
+
 impl<T> PartialEq for ActorId<T> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
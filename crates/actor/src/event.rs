@@ -11,7 +11,6 @@ use std::fmt;
 ///
 /// Events represent the internal messages that drive the actor system,
 /// including lifecycle events, timeouts, and message passing.
-#[derive(Debug)]
 pub enum Event {
     /// Start the actor (begin execution).
     Start,
@@ -29,6 +28,20 @@ pub enum Event {
     Raw(u64, Box<dyn Any + Send>),
 }
 
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Start => write!(f, "Start"),
+            Self::Stop => write!(f, "Stop"),
+            Self::Yield => write!(f, "Yield"),
+            Self::Timeout => write!(f, "Timeout"),
+            Self::Hangup => write!(f, "Hangup"),
+            Self::Closure(_) => write!(f, "Closure(..)"),
+            Self::Raw(id, _) => f.debug_tuple("Raw").field(id).finish(),
+        }
+    }
+}
+
 /// Trait object for actor operations in events.
 pub trait ActorTrait: Send {
     /// Get a reference to the actor as `Any` for downcasting.
@@ -96,7 +96,8 @@ pub mod response;
 pub mod supervisor;
 
 // Re-exports for convenience
-pub use actor::{Actor, ActorExt, ActorHandle};
+pub use actor::{ActorExt, ActorHandle};
+pub use event::Actor;
 pub use actor_id::ActorId;
 pub use actor_info::{ActorInfo, ActorState};
 pub use error::{ActorError, Result};
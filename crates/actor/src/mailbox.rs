@@ -6,7 +6,6 @@
 
 use crate::event::Event;
 use crossbeam::queue::SegQueue;
-use std::fmt;
 
 /// A per-actor mailbox for message queuing.
 ///
@@ -162,12 +161,7 @@ impl Mailbox {
     /// assert_eq!(mailbox.len(), 1);
     /// ```
     pub fn len(&self) -> usize {
-        let mut count = 0;
-        let mut iter = self.queue.iter();
-        while iter.next().is_some() {
-            count += 1;
-        }
-        count
+        self.queue.len()
     }
 
     /// Returns the capacity of the mailbox.
@@ -8,7 +8,7 @@ use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::task::{Context, Poll, Waker};
+use std::task::{Context, Poll};
 use tokio::sync::{oneshot, Notify};
 
 /// A future that resolves when a response is received.
@@ -75,8 +75,7 @@ impl<T> ResponseFuture<T> {
     pub fn is_done(&self) -> bool {
         self.inner
             .as_ref()
-            .map(|rx| rx.is_closed())
-            .unwrap_or(false)
+            .map_or(true, |rx| !rx.is_empty() || rx.is_terminated())
     }
 }
 
@@ -96,7 +95,7 @@ impl<T> Future for ResponseFuture<T> {
             None => return Poll::Ready(Err(ResponseError::Canceled)),
         };
 
-        match rx.poll(cx) {
+        match Pin::new(rx).poll(cx) {
             Poll::Ready(Ok(value)) => {
                 self.inner.take();
                 Poll::Ready(Ok(value))
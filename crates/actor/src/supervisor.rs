@@ -4,8 +4,6 @@
 
 //! Supervisor strategies for fault tolerance.
 
-use crate::actor_info::ActorInfo;
-use std::fmt;
 use std::time::Duration;
 
 /// Supervisor strategy for handling child actor failures.
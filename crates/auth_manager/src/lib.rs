@@ -60,6 +60,8 @@
 #![allow(clippy::module_name_repetitions)]
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
+mod login;
+mod network_api;
 mod state;
 
 #[cfg(test)]
@@ -84,6 +86,11 @@ use std::time::Duration;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
+pub use login::{
+    AuthApi, AuthorizationResult, LoginFlow, LoginState, LoginTokenResult, PasswordState,
+    SentCodeInfo,
+};
+pub use network_api::NetworkAuthApi;
 pub use state::State;
 
 /// Maximum number of retry attempts for network operations
@@ -1346,6 +1353,12 @@ pub enum AuthManagerError {
     /// Invalid state for operation
     InvalidState(State),
 
+    /// Login flow method called in the wrong state
+    InvalidLoginState(&'static str),
+
+    /// Password proof could not be computed
+    Password(String),
+
     /// Operation failed
     Failed {
         /// Error code
@@ -1365,6 +1378,8 @@ impl std::fmt::Display for AuthManagerError {
             Self::EmptyPassword => write!(f, "Empty password"),
             Self::NotAuthenticated => write!(f, "Not authenticated"),
             Self::InvalidState(state) => write!(f, "Invalid state: {:?}", state),
+            Self::InvalidLoginState(state) => write!(f, "Invalid login state: {}", state),
+            Self::Password(message) => write!(f, "Password error: {}", message),
             Self::Failed { code, message } => {
                 write!(f, "Operation failed ({}): {}", code, message)
             }
@@ -1372,6 +1387,25 @@ impl std::fmt::Display for AuthManagerError {
     }
 }
 
+impl AuthManagerError {
+    /// Returns true if this is the RPC error with the given message
+    pub fn is_rpc_error(&self, error_message: &str) -> bool {
+        matches!(self, Self::Failed { message, .. } if message == error_message)
+    }
+}
+
+impl From<QueryError> for AuthManagerError {
+    fn from(error: QueryError) -> Self {
+        match error {
+            QueryError::WithMessage { code, message } => Self::Failed { code, message },
+            error => Self::Failed {
+                code: error.code(),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
 impl std::error::Error for AuthManagerError {}
 
 #[cfg(test)]
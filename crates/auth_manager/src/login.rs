@@ -0,0 +1,618 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Interactive login flow
+//!
+//! [`LoginFlow`] drives a user through authorization: it sends the code to a
+//! phone number, checks the code, asks for the 2FA password and proves it
+//! with SRP, registers new accounts after the terms of service were shown,
+//! and supports logging in by scanning a QR code on an authorized device.
+//!
+//! The flow talks to Telegram through the [`AuthApi`] trait, so it can run
+//! against a real DC with [`NetworkAuthApi`](crate::NetworkAuthApi) or
+//! against a scripted fake one in tests.
+//!
+//! ## States
+//!
+//! ```text
+//! WaitPhoneNumber -> WaitCode -> Ready
+//!        |              |-> WaitPassword -> Ready
+//!        |              `-> WaitRegistration -> Ready
+//!        `-> WaitOtherDeviceConfirmation -> Ready
+//!                               `-> WaitPassword -> Ready
+//! ```
+//!
+//! Errors such as a wrong code or password leave the state unchanged, so
+//! the input can simply be retried.
+
+use async_trait::async_trait;
+use rustgram_auth::QrCodeLogin;
+use rustgram_password_manager::{SrpCalculator, SrpParams};
+use rustgram_send_code_helper::{AuthenticationCodeInfo, SendCodeHelper};
+use rustgram_terms_of_service::TermsOfService;
+use tracing::{debug, info, warn};
+
+use crate::AuthManagerError;
+
+/// Error returned when 2FA is enabled for the account
+const SESSION_PASSWORD_NEEDED: &str = "SESSION_PASSWORD_NEEDED";
+
+/// Error returned when the SRP parameters used for the password are outdated
+const SRP_ID_INVALID: &str = "SRP_ID_INVALID";
+
+/// Prefixes of the errors asking to repeat a request on another DC
+const MIGRATE_ERROR_PREFIXES: [&str; 3] = ["PHONE_MIGRATE_", "NETWORK_MIGRATE_", "USER_MIGRATE_"];
+
+/// Result of an `auth.sendCode` request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentCodeInfo {
+    /// Hash identifying the sent code
+    pub phone_code_hash: String,
+
+    /// How the code was sent
+    pub code_info: AuthenticationCodeInfo,
+
+    /// How the code will be sent if it is requested again
+    pub next_code_info: Option<AuthenticationCodeInfo>,
+
+    /// Seconds before the code can be requested again
+    pub timeout: i32,
+}
+
+/// Result of a request authorizing the session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthorizationResult {
+    /// The session is authorized
+    Authorized {
+        /// Identifier of the logged in user
+        user_id: i64,
+    },
+
+    /// The phone number is not registered yet
+    SignUpRequired {
+        /// Terms of service the user must accept to register
+        terms_of_service: Option<TermsOfService>,
+    },
+}
+
+/// 2FA password settings of an account, as returned by `account.getPassword`
+#[derive(Debug, Clone, Default)]
+pub struct PasswordState {
+    /// Whether a password is set
+    pub has_password: bool,
+
+    /// Password hint
+    pub hint: String,
+
+    /// Whether a recovery email is set
+    pub has_recovery: bool,
+
+    /// SRP parameters of the current password
+    pub srp: SrpParams,
+}
+
+/// Result of an `auth.exportLoginToken` or `auth.importLoginToken` request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginTokenResult {
+    /// A token to show as a QR code
+    Token {
+        /// Login token
+        token: Vec<u8>,
+
+        /// Unix time when the token expires
+        expires: i32,
+    },
+
+    /// The token was accepted on another DC and must be imported there
+    MigrateTo {
+        /// DC to import the token on
+        dc_id: i32,
+
+        /// Token to import
+        token: Vec<u8>,
+    },
+
+    /// The token was accepted and the session is authorized
+    Success(AuthorizationResult),
+}
+
+/// Authorization requests sent by the login flow
+///
+/// Errors returned by the server are reported as
+/// [`AuthManagerError::Failed`] with the RPC error code and message.
+#[async_trait]
+pub trait AuthApi: Send + Sync {
+    /// Sends `auth.sendCode`
+    async fn send_code(&self, phone_number: &str) -> Result<SentCodeInfo, AuthManagerError>;
+
+    /// Sends `auth.signIn`
+    async fn sign_in(
+        &self,
+        phone_number: &str,
+        phone_code_hash: &str,
+        code: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError>;
+
+    /// Sends `auth.signUp`
+    async fn sign_up(
+        &self,
+        phone_number: &str,
+        phone_code_hash: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError>;
+
+    /// Sends `account.getPassword`
+    async fn get_password(&self) -> Result<PasswordState, AuthManagerError>;
+
+    /// Sends `auth.checkPassword` with an `inputCheckPasswordSRP`
+    async fn check_password(
+        &self,
+        srp_id: i64,
+        a: &[u8],
+        m1: &[u8],
+    ) -> Result<AuthorizationResult, AuthManagerError>;
+
+    /// Sends `help.acceptTermsOfService`
+    async fn accept_terms_of_service(&self, terms_id: &str) -> Result<(), AuthManagerError>;
+
+    /// Sends `auth.exportLoginToken`
+    async fn export_login_token(&self) -> Result<LoginTokenResult, AuthManagerError>;
+
+    /// Sends `auth.importLoginToken`
+    async fn import_login_token(&self, token: &[u8]) -> Result<LoginTokenResult, AuthManagerError>;
+
+    /// Sends the following requests to another DC
+    fn set_main_dc_id(&self, dc_id: i32);
+}
+
+/// State of a [`LoginFlow`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginState {
+    /// A phone number or a QR code login is expected
+    WaitPhoneNumber,
+
+    /// The code sent to the phone number is expected
+    WaitCode {
+        /// How the code was sent
+        code_info: AuthenticationCodeInfo,
+
+        /// Seconds before the code can be requested again
+        timeout: i32,
+    },
+
+    /// The 2FA password is expected
+    WaitPassword {
+        /// Password hint
+        hint: String,
+
+        /// Whether a recovery email is set
+        has_recovery: bool,
+    },
+
+    /// The name of the new account is expected
+    WaitRegistration {
+        /// Terms of service accepted by registering
+        terms_of_service: Option<TermsOfService>,
+    },
+
+    /// The QR code must be scanned on an authorized device
+    WaitOtherDeviceConfirmation {
+        /// `tg://login` link to show as a QR code
+        link: String,
+    },
+
+    /// The session is authorized
+    Ready {
+        /// Identifier of the logged in user
+        user_id: i64,
+    },
+}
+
+impl LoginState {
+    /// Returns the name of the state
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::WaitPhoneNumber => "WaitPhoneNumber",
+            Self::WaitCode { .. } => "WaitCode",
+            Self::WaitPassword { .. } => "WaitPassword",
+            Self::WaitRegistration { .. } => "WaitRegistration",
+            Self::WaitOtherDeviceConfirmation { .. } => "WaitOtherDeviceConfirmation",
+            Self::Ready { .. } => "Ready",
+        }
+    }
+}
+
+/// Login state machine
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustgram_auth_manager::{AuthApi, LoginFlow, LoginState};
+///
+/// # async fn login<A: AuthApi>(api: A) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut flow = LoginFlow::new(api, 2);
+/// flow.set_phone_number("+1234567890").await?;
+/// if let LoginState::WaitCode { .. } = flow.state() {
+///     flow.check_code("12345").await?;
+/// }
+/// if let LoginState::WaitPassword { .. } = flow.state() {
+///     flow.check_password("secret").await?;
+/// }
+/// assert!(flow.is_ready());
+/// # Ok(())
+/// # }
+/// ```
+pub struct LoginFlow<A> {
+    /// Authorization requests
+    api: A,
+
+    /// Current state
+    state: LoginState,
+
+    /// Phone number and sent code
+    send_code_helper: SendCodeHelper,
+
+    /// Password settings, while waiting for the password
+    password_state: Option<PasswordState>,
+
+    /// Active QR code login
+    qr_code_login: Option<QrCodeLogin>,
+
+    /// DC the requests are sent to
+    main_dc_id: i32,
+}
+
+impl<A: AuthApi> LoginFlow<A> {
+    /// Creates a flow sending its requests to `main_dc_id`
+    pub fn new(api: A, main_dc_id: i32) -> Self {
+        Self {
+            api,
+            state: LoginState::WaitPhoneNumber,
+            send_code_helper: SendCodeHelper::new(),
+            password_state: None,
+            qr_code_login: None,
+            main_dc_id,
+        }
+    }
+
+    /// Returns the current state
+    pub fn state(&self) -> &LoginState {
+        &self.state
+    }
+
+    /// Returns whether the session is authorized
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, LoginState::Ready { .. })
+    }
+
+    /// Returns the identifier of the logged in user
+    pub fn user_id(&self) -> Option<i64> {
+        match self.state {
+            LoginState::Ready { user_id } => Some(user_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the DC the session is authorized on
+    pub fn main_dc_id(&self) -> i32 {
+        self.main_dc_id
+    }
+
+    /// Returns the phone number and code information
+    pub fn send_code_helper(&self) -> &SendCodeHelper {
+        &self.send_code_helper
+    }
+
+    /// Returns the authorization requests
+    pub fn api(&self) -> &A {
+        &self.api
+    }
+
+    /// Sends a login code to a phone number
+    ///
+    /// Can also be used while waiting for the code, to change the number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the number is invalid or the request fails.
+    pub async fn set_phone_number(
+        &mut self,
+        phone_number: &str,
+    ) -> Result<&LoginState, AuthManagerError> {
+        self.expect_state(matches!(
+            self.state,
+            LoginState::WaitPhoneNumber | LoginState::WaitCode { .. }
+        ))?;
+        let phone_number = phone_number.trim();
+        if phone_number.is_empty() || !phone_number.starts_with('+') {
+            return Err(AuthManagerError::InvalidPhoneNumber(
+                phone_number.to_string(),
+            ));
+        }
+
+        info!("Sending login code");
+        let sent_code = match self.api.send_code(phone_number).await {
+            Err(error) if self.migrate(&error) => self.api.send_code(phone_number).await?,
+            result => result?,
+        };
+
+        self.send_code_helper = SendCodeHelper::new();
+        self.send_code_helper.set_phone_number(phone_number);
+        self.send_code_helper
+            .set_phone_code_hash(sent_code.phone_code_hash);
+        self.send_code_helper
+            .set_sent_code_info(sent_code.code_info.clone());
+        if let Some(next_code_info) = sent_code.next_code_info {
+            self.send_code_helper.set_next_code_info(next_code_info);
+        }
+        self.qr_code_login = None;
+        self.state = LoginState::WaitCode {
+            code_info: sent_code.code_info,
+            timeout: sent_code.timeout,
+        };
+        Ok(&self.state)
+    }
+
+    /// Checks the login code
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the code is wrong or the request fails; the code
+    /// can then be entered again.
+    pub async fn check_code(&mut self, code: &str) -> Result<&LoginState, AuthManagerError> {
+        self.expect_state(matches!(self.state, LoginState::WaitCode { .. }))?;
+        let code = code.trim();
+        if code.is_empty() || code.len() > 16 {
+            return Err(AuthManagerError::InvalidCode(code.to_string()));
+        }
+
+        let result = self
+            .api
+            .sign_in(
+                self.send_code_helper.phone_number(),
+                self.send_code_helper.phone_code_hash(),
+                code,
+            )
+            .await;
+        self.on_authorization(result).await
+    }
+
+    /// Checks the 2FA password
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password is wrong or the request fails; the
+    /// password can then be entered again.
+    pub async fn check_password(
+        &mut self,
+        password: &str,
+    ) -> Result<&LoginState, AuthManagerError> {
+        self.expect_state(matches!(self.state, LoginState::WaitPassword { .. }))?;
+        if password.is_empty() {
+            return Err(AuthManagerError::EmptyPassword);
+        }
+
+        let mut result = self.send_password(password).await;
+        if matches!(&result, Err(error) if error.is_rpc_error(SRP_ID_INVALID)) {
+            // The password was changed or the parameters expired
+            debug!("SRP parameters are outdated, reloading them");
+            self.password_state = Some(self.api.get_password().await?);
+            result = self.send_password(password).await;
+        }
+        self.on_authorization(result).await
+    }
+
+    /// Registers a new account
+    ///
+    /// Registering accepts the terms of service shown in
+    /// [`LoginState::WaitRegistration`]. The account exists once it is
+    /// created, so failing to accept the terms is only logged and the login
+    /// still ends in [`LoginState::Ready`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is invalid or the sign up fails.
+    pub async fn register(
+        &mut self,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<&LoginState, AuthManagerError> {
+        let LoginState::WaitRegistration { terms_of_service } = &self.state else {
+            return Err(self.invalid_state());
+        };
+        let terms_id = terms_of_service
+            .as_ref()
+            .map(|terms| terms.id().to_string());
+        let first_name = first_name.trim();
+        if first_name.is_empty() {
+            return Err(AuthManagerError::Failed {
+                code: 400,
+                message: "FIRSTNAME_INVALID".to_string(),
+            });
+        }
+
+        let result = self
+            .api
+            .sign_up(
+                self.send_code_helper.phone_number(),
+                self.send_code_helper.phone_code_hash(),
+                first_name,
+                last_name.trim(),
+            )
+            .await;
+        let state = self.on_authorization(result).await?;
+        if let (LoginState::Ready { .. }, Some(terms_id)) = (state, terms_id) {
+            if let Err(error) = self.api.accept_terms_of_service(&terms_id).await {
+                warn!("Failed to accept terms of service {}: {}", terms_id, error);
+            }
+        }
+        Ok(&self.state)
+    }
+
+    /// Starts a QR code login
+    ///
+    /// The link of [`LoginState::WaitOtherDeviceConfirmation`] must be shown
+    /// as a QR code and scanned in the Devices settings of an authorized
+    /// app. Call [`LoginFlow::check_qr_code`] afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn request_qr_code(&mut self) -> Result<&LoginState, AuthManagerError> {
+        self.expect_state(matches!(
+            self.state,
+            LoginState::WaitPhoneNumber | LoginState::WaitOtherDeviceConfirmation { .. }
+        ))?;
+        let result = self.api.export_login_token().await;
+        self.on_login_token(result).await
+    }
+
+    /// Checks whether the QR code was scanned
+    ///
+    /// Refreshes the link if the token expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn check_qr_code(&mut self) -> Result<&LoginState, AuthManagerError> {
+        self.expect_state(matches!(
+            self.state,
+            LoginState::WaitOtherDeviceConfirmation { .. }
+        ))?;
+        let result = self.api.export_login_token().await;
+        self.on_login_token(result).await
+    }
+
+    /// Returns the active QR code login
+    pub fn qr_code_login(&self) -> Option<&QrCodeLogin> {
+        self.qr_code_login.as_ref()
+    }
+
+    /// Computes the SRP proof of a password and sends it
+    async fn send_password(
+        &mut self,
+        password: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        let password_state = match self.password_state.take() {
+            Some(password_state) => password_state,
+            None => self.api.get_password().await?,
+        };
+        let srp = SrpCalculator::new()
+            .compute(password, &password_state.srp)
+            .map_err(|e| AuthManagerError::Password(e.to_string()))?;
+        let srp_id = password_state.srp.srp_id;
+        self.password_state = Some(password_state);
+
+        self.api.check_password(srp_id, srp.a(), srp.m1()).await
+    }
+
+    /// Applies the result of a request authorizing the session
+    async fn on_authorization(
+        &mut self,
+        result: Result<AuthorizationResult, AuthManagerError>,
+    ) -> Result<&LoginState, AuthManagerError> {
+        match result {
+            Ok(AuthorizationResult::Authorized { user_id }) => {
+                info!("Logged in as user {}", user_id);
+                self.password_state = None;
+                self.qr_code_login = None;
+                self.state = LoginState::Ready { user_id };
+            }
+            Ok(AuthorizationResult::SignUpRequired { terms_of_service }) => {
+                self.state = LoginState::WaitRegistration { terms_of_service };
+            }
+            Err(error) if error.is_rpc_error(SESSION_PASSWORD_NEEDED) => {
+                let password_state = self.api.get_password().await?;
+                self.state = LoginState::WaitPassword {
+                    hint: password_state.hint.clone(),
+                    has_recovery: password_state.has_recovery,
+                };
+                self.password_state = Some(password_state);
+            }
+            Err(error) => return Err(error),
+        }
+        Ok(&self.state)
+    }
+
+    /// Applies the result of a login token request
+    async fn on_login_token(
+        &mut self,
+        mut result: Result<LoginTokenResult, AuthManagerError>,
+    ) -> Result<&LoginState, AuthManagerError> {
+        if let Ok(LoginTokenResult::MigrateTo { dc_id, token }) = &result {
+            info!("Login token was accepted on DC {}", dc_id);
+            self.set_main_dc_id(*dc_id);
+            result = self.api.import_login_token(token).await;
+        }
+
+        match result {
+            Ok(LoginTokenResult::Token { token, expires }) => {
+                let expires_in = i64::from(expires) - unix_time();
+                let qr_code_login = QrCodeLogin::new(token, self.main_dc_id, expires_in);
+                self.state = LoginState::WaitOtherDeviceConfirmation {
+                    link: qr_code_login.to_url(),
+                };
+                self.qr_code_login = Some(qr_code_login);
+                Ok(&self.state)
+            }
+            Ok(LoginTokenResult::MigrateTo { .. }) => Err(AuthManagerError::Failed {
+                code: 500,
+                message: "Repeated login token migration".to_string(),
+            }),
+            Ok(LoginTokenResult::Success(authorization)) => {
+                self.on_authorization(Ok(authorization)).await
+            }
+            Err(error) => self.on_authorization(Err(error)).await,
+        }
+    }
+
+    /// Switches to the DC named by a migration error
+    ///
+    /// Returns false if the error is not a migration error.
+    fn migrate(&mut self, error: &AuthManagerError) -> bool {
+        let AuthManagerError::Failed { code: 303, message } = error else {
+            return false;
+        };
+        let dc_id = MIGRATE_ERROR_PREFIXES
+            .iter()
+            .find_map(|prefix| message.strip_prefix(prefix)?.parse::<i32>().ok());
+        match dc_id {
+            Some(dc_id) => {
+                info!("Migrating to DC {}", dc_id);
+                self.set_main_dc_id(dc_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the DC the requests are sent to
+    fn set_main_dc_id(&mut self, dc_id: i32) {
+        self.main_dc_id = dc_id;
+        self.api.set_main_dc_id(dc_id);
+    }
+
+    /// Fails with an invalid state error unless `expected` holds
+    fn expect_state(&self, expected: bool) -> Result<(), AuthManagerError> {
+        if expected {
+            Ok(())
+        } else {
+            Err(self.invalid_state())
+        }
+    }
+
+    /// Returns an error for a method called in the wrong state
+    fn invalid_state(&self) -> AuthManagerError {
+        AuthManagerError::InvalidLoginState(self.state.as_str())
+    }
+}
+
+/// Returns the current Unix time in seconds
+fn unix_time() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,640 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Authorization requests over the network
+//!
+//! [`NetworkAuthApi`] implements [`AuthApi`] by sending the requests through
+//! a [`NetQueryDispatcher`] and waiting for their results. The requests and
+//! responses are encoded with the TL constructors of the current API layer;
+//! only the fields used by the login flow are parsed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use rustgram_net::{
+    AuthFlag, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryType, QueryError,
+};
+use rustgram_password_manager::SrpParams;
+use rustgram_send_code_helper::{AuthenticationCodeInfo, AuthenticationCodeType};
+use rustgram_terms_of_service::TermsOfService;
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::TlHelper;
+use tokio::sync::oneshot;
+
+use crate::login::{AuthApi, AuthorizationResult, LoginTokenResult, PasswordState, SentCodeInfo};
+use crate::AuthManagerError;
+
+/// Timeout of a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request constructors
+const TL_AUTH_SEND_CODE: u32 = 0xa677_244f;
+const TL_AUTH_SIGN_IN: u32 = 0x8d52_a951;
+const TL_AUTH_SIGN_UP: u32 = 0xaac7_b717;
+const TL_AUTH_CHECK_PASSWORD: u32 = 0xd18b_4d16;
+const TL_AUTH_EXPORT_LOGIN_TOKEN: u32 = 0xb7e0_85fe;
+const TL_AUTH_IMPORT_LOGIN_TOKEN: u32 = 0x95ac_5ce4;
+const TL_ACCOUNT_GET_PASSWORD: u32 = 0x548a_30f5;
+const TL_HELP_ACCEPT_TERMS_OF_SERVICE: u32 = 0xee72_f79a;
+
+/// Argument constructors
+const TL_CODE_SETTINGS: u32 = 0xad25_3d78;
+const TL_INPUT_CHECK_PASSWORD_SRP: u32 = 0xd27f_f082;
+const TL_DATA_JSON: u32 = 0x7d74_8d04;
+const TL_VECTOR: u32 = 0x1cb5_c415;
+
+/// Response constructors
+const TL_SENT_CODE: u32 = 0x5e00_2502;
+const TL_SENT_CODE_SUCCESS: u32 = 0x2390_fe44;
+const TL_SENT_CODE_TYPE_APP: u32 = 0x3dbb_5986;
+const TL_SENT_CODE_TYPE_SMS: u32 = 0xc000_bba2;
+const TL_SENT_CODE_TYPE_CALL: u32 = 0x5353_e5a7;
+const TL_SENT_CODE_TYPE_FLASH_CALL: u32 = 0xab03_c6d0;
+const TL_SENT_CODE_TYPE_MISSED_CALL: u32 = 0x8200_6484;
+const TL_SENT_CODE_TYPE_FRAGMENT_SMS: u32 = 0xd956_5c39;
+const TL_CODE_TYPE_SMS: u32 = 0x72a3_158c;
+const TL_CODE_TYPE_CALL: u32 = 0x741c_d3e3;
+const TL_CODE_TYPE_FLASH_CALL: u32 = 0x226c_cefb;
+const TL_CODE_TYPE_MISSED_CALL: u32 = 0xd61a_d6ee;
+const TL_CODE_TYPE_FRAGMENT_SMS: u32 = 0x06ed_998c;
+const TL_AUTHORIZATION: u32 = 0x2ea2_c0d4;
+const TL_AUTHORIZATION_SIGN_UP_REQUIRED: u32 = 0x4474_7e9a;
+const TL_USER_EMPTY: u32 = 0xd3bc_4b7a;
+const TL_TERMS_OF_SERVICE: u32 = 0x780a_0310;
+const TL_ACCOUNT_PASSWORD: u32 = 0x957b_50fb;
+const TL_PASSWORD_KDF_ALGO_MOD_POW: u32 = 0x3a91_2d4a;
+const TL_LOGIN_TOKEN: u32 = 0x629f_1980;
+const TL_LOGIN_TOKEN_MIGRATE_TO: u32 = 0x068e_9916;
+const TL_LOGIN_TOKEN_SUCCESS: u32 = 0x390d_5c5e;
+const TL_BOOL_TRUE: u32 = 0x9972_75b5;
+
+/// [`AuthApi`] sending the requests to Telegram
+pub struct NetworkAuthApi {
+    /// Application identifier
+    api_id: i32,
+
+    /// Application hash
+    api_hash: String,
+
+    /// Dispatcher sending the requests
+    dispatcher: Arc<NetQueryDispatcher>,
+
+    /// Identifier of the next query
+    next_query_id: AtomicU64,
+}
+
+impl NetworkAuthApi {
+    /// Creates the API for an application
+    pub fn new(api_id: i32, api_hash: String, dispatcher: Arc<NetQueryDispatcher>) -> Self {
+        Self {
+            api_id,
+            api_hash,
+            dispatcher,
+            next_query_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Returns the dispatcher sending the requests
+    pub fn dispatcher(&self) -> &Arc<NetQueryDispatcher> {
+        &self.dispatcher
+    }
+
+    /// Sends a request to the main DC and waits for its result
+    async fn invoke(&self, request: BytesMut) -> Result<TlBytes, AuthManagerError> {
+        let request = request.freeze();
+        let constructor = request
+            .get(..4)
+            .map(|id| i32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            .unwrap_or(0);
+        let query = NetQuery::new(
+            self.next_query_id.fetch_add(1, Ordering::Relaxed),
+            request,
+            self.dispatcher.main_dc_id(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::Off,
+            constructor,
+        );
+
+        let (sender, receiver) = oneshot::channel();
+        query.set_callback(Box::new(ResultCallback {
+            sender: parking_lot::Mutex::new(Some(sender)),
+        }));
+        self.dispatcher
+            .dispatch(query)
+            .map_err(|e| AuthManagerError::Failed {
+                code: 500,
+                message: format!("Dispatch error: {}", e),
+            })?;
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, receiver).await {
+            Ok(Ok(result)) => Ok(TlBytes::new(result?)),
+            Ok(Err(_)) => Err(AuthManagerError::Failed {
+                code: 500,
+                message: "Request was dropped".to_string(),
+            }),
+            Err(_) => Err(AuthManagerError::Failed {
+                code: 500,
+                message: "Request timed out".to_string(),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthApi for NetworkAuthApi {
+    async fn send_code(&self, phone_number: &str) -> Result<SentCodeInfo, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_SEND_CODE);
+        TlHelper::write_string(&mut buf, phone_number);
+        TlHelper::write_i32(&mut buf, self.api_id);
+        TlHelper::write_string(&mut buf, &self.api_hash);
+        TlHelper::write_constructor_id(&mut buf, TL_CODE_SETTINGS);
+        TlHelper::write_i32(&mut buf, 0);
+
+        parse_sent_code(&mut self.invoke(buf).await?)
+    }
+
+    async fn sign_in(
+        &self,
+        phone_number: &str,
+        phone_code_hash: &str,
+        code: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_SIGN_IN);
+        // flags.0: phone_code
+        TlHelper::write_i32(&mut buf, 1);
+        TlHelper::write_string(&mut buf, phone_number);
+        TlHelper::write_string(&mut buf, phone_code_hash);
+        TlHelper::write_string(&mut buf, code);
+
+        parse_authorization(&mut self.invoke(buf).await?)
+    }
+
+    async fn sign_up(
+        &self,
+        phone_number: &str,
+        phone_code_hash: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_SIGN_UP);
+        TlHelper::write_i32(&mut buf, 0);
+        TlHelper::write_string(&mut buf, phone_number);
+        TlHelper::write_string(&mut buf, phone_code_hash);
+        TlHelper::write_string(&mut buf, first_name);
+        TlHelper::write_string(&mut buf, last_name);
+
+        parse_authorization(&mut self.invoke(buf).await?)
+    }
+
+    async fn get_password(&self) -> Result<PasswordState, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_ACCOUNT_GET_PASSWORD);
+
+        parse_password(&mut self.invoke(buf).await?)
+    }
+
+    async fn check_password(
+        &self,
+        srp_id: i64,
+        a: &[u8],
+        m1: &[u8],
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_CHECK_PASSWORD);
+        TlHelper::write_constructor_id(&mut buf, TL_INPUT_CHECK_PASSWORD_SRP);
+        TlHelper::write_i64(&mut buf, srp_id);
+        TlHelper::write_bytes(&mut buf, a);
+        TlHelper::write_bytes(&mut buf, m1);
+
+        parse_authorization(&mut self.invoke(buf).await?)
+    }
+
+    async fn accept_terms_of_service(&self, terms_id: &str) -> Result<(), AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_HELP_ACCEPT_TERMS_OF_SERVICE);
+        TlHelper::write_constructor_id(&mut buf, TL_DATA_JSON);
+        TlHelper::write_string(&mut buf, terms_id);
+
+        let mut response = self.invoke(buf).await?;
+        match read_u32(&mut response)? {
+            TL_BOOL_TRUE => Ok(()),
+            _ => Err(AuthManagerError::Failed {
+                code: 400,
+                message: "Terms of service were not accepted".to_string(),
+            }),
+        }
+    }
+
+    async fn export_login_token(&self) -> Result<LoginTokenResult, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_EXPORT_LOGIN_TOKEN);
+        TlHelper::write_i32(&mut buf, self.api_id);
+        TlHelper::write_string(&mut buf, &self.api_hash);
+        // except_ids
+        TlHelper::write_constructor_id(&mut buf, TL_VECTOR);
+        TlHelper::write_i32(&mut buf, 0);
+
+        parse_login_token(&mut self.invoke(buf).await?)
+    }
+
+    async fn import_login_token(&self, token: &[u8]) -> Result<LoginTokenResult, AuthManagerError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_AUTH_IMPORT_LOGIN_TOKEN);
+        TlHelper::write_bytes(&mut buf, token);
+
+        parse_login_token(&mut self.invoke(buf).await?)
+    }
+
+    fn set_main_dc_id(&self, dc_id: i32) {
+        self.dispatcher.set_main_dc_id(dc_id);
+    }
+}
+
+/// Callback passing the result of a query to the waiting request
+struct ResultCallback {
+    /// Channel to the request, taken by the first result
+    sender: parking_lot::Mutex<Option<oneshot::Sender<Result<Bytes, QueryError>>>>,
+}
+
+#[async_trait]
+impl NetQueryCallback for ResultCallback {
+    async fn on_result(&self, query: NetQuery) {
+        let result = if query.is_error() {
+            Err(query.error())
+        } else {
+            Ok(query.ok())
+        };
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+/// Returns an error for a malformed response
+fn malformed(what: impl std::fmt::Display) -> AuthManagerError {
+    AuthManagerError::Failed {
+        code: 500,
+        message: format!("Malformed response: {}", what),
+    }
+}
+
+fn read_u32(buf: &mut TlBytes) -> Result<u32, AuthManagerError> {
+    TlHelper::read_constructor_id(buf).map_err(malformed)
+}
+
+fn read_i32(buf: &mut TlBytes) -> Result<i32, AuthManagerError> {
+    TlHelper::read_i32(buf).map_err(malformed)
+}
+
+fn read_i64(buf: &mut TlBytes) -> Result<i64, AuthManagerError> {
+    TlHelper::read_i64(buf).map_err(malformed)
+}
+
+fn read_bytes(buf: &mut TlBytes) -> Result<Vec<u8>, AuthManagerError> {
+    TlHelper::read_bytes(buf).map_err(malformed)
+}
+
+fn read_string(buf: &mut TlBytes) -> Result<String, AuthManagerError> {
+    TlHelper::read_string(buf).map_err(malformed)
+}
+
+/// Parses `auth.SentCode`
+fn parse_sent_code(buf: &mut TlBytes) -> Result<SentCodeInfo, AuthManagerError> {
+    match read_u32(buf)? {
+        TL_SENT_CODE => {}
+        TL_SENT_CODE_SUCCESS => {
+            // Logged in with a future auth token, no code is needed
+            return Err(AuthManagerError::Failed {
+                code: 400,
+                message: "Unexpected auth.sentCodeSuccess".to_string(),
+            });
+        }
+        id => return Err(malformed(format_args!("auth.SentCode {:#010x}", id))),
+    }
+    let flags = read_i32(buf)?;
+    let code_info = parse_sent_code_type(buf)?;
+    let phone_code_hash = read_string(buf)?;
+    let next_code_info = if flags & (1 << 1) != 0 {
+        parse_code_type(buf)?
+    } else {
+        None
+    };
+    let timeout = if flags & (1 << 2) != 0 {
+        read_i32(buf)?
+    } else {
+        0
+    };
+
+    Ok(SentCodeInfo {
+        phone_code_hash,
+        code_info,
+        next_code_info,
+        timeout,
+    })
+}
+
+/// Parses `auth.SentCodeType`
+fn parse_sent_code_type(buf: &mut TlBytes) -> Result<AuthenticationCodeInfo, AuthManagerError> {
+    let (type_, length, pattern) = match read_u32(buf)? {
+        TL_SENT_CODE_TYPE_APP => (
+            AuthenticationCodeType::Message,
+            read_i32(buf)?,
+            String::new(),
+        ),
+        TL_SENT_CODE_TYPE_SMS => (AuthenticationCodeType::Sms, read_i32(buf)?, String::new()),
+        TL_SENT_CODE_TYPE_CALL => (AuthenticationCodeType::Call, read_i32(buf)?, String::new()),
+        TL_SENT_CODE_TYPE_FLASH_CALL => (AuthenticationCodeType::FlashCall, 0, read_string(buf)?),
+        TL_SENT_CODE_TYPE_MISSED_CALL => {
+            let prefix = read_string(buf)?;
+            (AuthenticationCodeType::MissedCall, read_i32(buf)?, prefix)
+        }
+        TL_SENT_CODE_TYPE_FRAGMENT_SMS => {
+            let url = read_string(buf)?;
+            (AuthenticationCodeType::Fragment, read_i32(buf)?, url)
+        }
+        id => return Err(malformed(format_args!("auth.SentCodeType {:#010x}", id))),
+    };
+    Ok(AuthenticationCodeInfo::new(type_, length, pattern))
+}
+
+/// Parses `auth.CodeType`, returning `None` for unknown types
+fn parse_code_type(buf: &mut TlBytes) -> Result<Option<AuthenticationCodeInfo>, AuthManagerError> {
+    let type_ = match read_u32(buf)? {
+        TL_CODE_TYPE_SMS => AuthenticationCodeType::Sms,
+        TL_CODE_TYPE_CALL => AuthenticationCodeType::Call,
+        TL_CODE_TYPE_FLASH_CALL => AuthenticationCodeType::FlashCall,
+        TL_CODE_TYPE_MISSED_CALL => AuthenticationCodeType::MissedCall,
+        TL_CODE_TYPE_FRAGMENT_SMS => AuthenticationCodeType::Fragment,
+        _ => return Ok(None),
+    };
+    Ok(Some(AuthenticationCodeInfo::new(type_, 0, "")))
+}
+
+/// Parses `auth.Authorization`
+fn parse_authorization(buf: &mut TlBytes) -> Result<AuthorizationResult, AuthManagerError> {
+    match read_u32(buf)? {
+        TL_AUTHORIZATION => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 1) != 0 {
+                // otherwise_relogin_days
+                read_i32(buf)?;
+            }
+            if flags & (1 << 0) != 0 {
+                // tmp_sessions
+                read_i32(buf)?;
+            }
+            if flags & (1 << 2) != 0 {
+                // future_auth_token
+                read_bytes(buf)?;
+            }
+            let user_id = parse_user_id(buf)?;
+            Ok(AuthorizationResult::Authorized { user_id })
+        }
+        TL_AUTHORIZATION_SIGN_UP_REQUIRED => {
+            let flags = read_i32(buf)?;
+            let terms_of_service = if flags & (1 << 0) != 0 {
+                Some(parse_terms_of_service(buf)?)
+            } else {
+                None
+            };
+            Ok(AuthorizationResult::SignUpRequired { terms_of_service })
+        }
+        id => Err(malformed(format_args!("auth.Authorization {:#010x}", id))),
+    }
+}
+
+/// Parses the identifier of a `User`
+///
+/// Every layer of `user` starts with `flags`, `flags2` and `id`, so the rest
+/// of the object is not read.
+fn parse_user_id(buf: &mut TlBytes) -> Result<i64, AuthManagerError> {
+    if read_u32(buf)? != TL_USER_EMPTY {
+        read_i32(buf)?;
+        read_i32(buf)?;
+    }
+    read_i64(buf)
+}
+
+/// Parses `help.termsOfService`
+///
+/// The minimum age follows the text entities, so it is only read when the
+/// text has none.
+fn parse_terms_of_service(buf: &mut TlBytes) -> Result<TermsOfService, AuthManagerError> {
+    let id = read_u32(buf)?;
+    if id != TL_TERMS_OF_SERVICE {
+        return Err(malformed(format_args!("help.TermsOfService {:#010x}", id)));
+    }
+    let flags = read_i32(buf)?;
+    if read_u32(buf)? != TL_DATA_JSON {
+        return Err(malformed("DataJSON"));
+    }
+    let id = read_string(buf)?;
+    let text = read_string(buf)?;
+    let mut min_user_age = 0;
+    if read_u32(buf)? == TL_VECTOR && read_i32(buf)? == 0 && flags & (1 << 1) != 0 {
+        min_user_age = read_i32(buf)?;
+    }
+
+    Ok(TermsOfService::new(id, text, min_user_age, flags & 1 != 0))
+}
+
+/// Parses `account.password`
+fn parse_password(buf: &mut TlBytes) -> Result<PasswordState, AuthManagerError> {
+    let id = read_u32(buf)?;
+    if id != TL_ACCOUNT_PASSWORD {
+        return Err(malformed(format_args!("account.Password {:#010x}", id)));
+    }
+    let flags = read_i32(buf)?;
+    let mut state = PasswordState {
+        has_password: flags & (1 << 2) != 0,
+        has_recovery: flags & (1 << 0) != 0,
+        ..PasswordState::default()
+    };
+    if state.has_password {
+        let algo = read_u32(buf)?;
+        if algo != TL_PASSWORD_KDF_ALGO_MOD_POW {
+            return Err(AuthManagerError::Password(format!(
+                "Unsupported password algorithm {:#010x}",
+                algo
+            )));
+        }
+        let salt1 = read_bytes(buf)?;
+        let salt2 = read_bytes(buf)?;
+        let g = read_i32(buf)?;
+        let p = read_bytes(buf)?;
+        let b = read_bytes(buf)?;
+        let srp_id = read_i64(buf)?;
+        state.srp = SrpParams::new(g, p, b, srp_id, salt1, salt2, Vec::new(), Vec::new());
+    }
+    if flags & (1 << 3) != 0 {
+        state.hint = read_string(buf)?;
+    }
+
+    Ok(state)
+}
+
+/// Parses `auth.LoginToken`
+fn parse_login_token(buf: &mut TlBytes) -> Result<LoginTokenResult, AuthManagerError> {
+    match read_u32(buf)? {
+        TL_LOGIN_TOKEN => {
+            let expires = read_i32(buf)?;
+            let token = read_bytes(buf)?;
+            Ok(LoginTokenResult::Token { token, expires })
+        }
+        TL_LOGIN_TOKEN_MIGRATE_TO => {
+            let dc_id = read_i32(buf)?;
+            let token = read_bytes(buf)?;
+            Ok(LoginTokenResult::MigrateTo { dc_id, token })
+        }
+        TL_LOGIN_TOKEN_SUCCESS => Ok(LoginTokenResult::Success(parse_authorization(buf)?)),
+        id => Err(malformed(format_args!("auth.LoginToken {:#010x}", id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tl(build: impl FnOnce(&mut BytesMut)) -> TlBytes {
+        let mut buf = BytesMut::new();
+        build(&mut buf);
+        TlBytes::new(buf.freeze())
+    }
+
+    fn write_user(buf: &mut BytesMut, id: i64) {
+        TlHelper::write_constructor_id(buf, 0x8331_4fca);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i64(buf, id);
+    }
+
+    #[test]
+    fn test_parse_sent_code() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_SENT_CODE);
+            TlHelper::write_i32(buf, 0b110);
+            TlHelper::write_constructor_id(buf, TL_SENT_CODE_TYPE_APP);
+            TlHelper::write_i32(buf, 5);
+            TlHelper::write_string(buf, "hash");
+            TlHelper::write_constructor_id(buf, TL_CODE_TYPE_SMS);
+            TlHelper::write_i32(buf, 60);
+        });
+
+        let sent_code = parse_sent_code(&mut buf).unwrap();
+        assert_eq!(sent_code.phone_code_hash, "hash");
+        assert_eq!(
+            sent_code.code_info,
+            AuthenticationCodeInfo::new(AuthenticationCodeType::Message, 5, "")
+        );
+        assert_eq!(
+            sent_code.next_code_info.map(|info| info.type_()),
+            Some(AuthenticationCodeType::Sms)
+        );
+        assert_eq!(sent_code.timeout, 60);
+    }
+
+    #[test]
+    fn test_parse_authorization() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_AUTHORIZATION);
+            TlHelper::write_i32(buf, 0b10);
+            TlHelper::write_i32(buf, 180);
+            write_user(buf, 42);
+        });
+        assert_eq!(
+            parse_authorization(&mut buf).unwrap(),
+            AuthorizationResult::Authorized { user_id: 42 }
+        );
+    }
+
+    #[test]
+    fn test_parse_sign_up_required() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_AUTHORIZATION_SIGN_UP_REQUIRED);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_TERMS_OF_SERVICE);
+            TlHelper::write_i32(buf, 0b11);
+            TlHelper::write_constructor_id(buf, TL_DATA_JSON);
+            TlHelper::write_string(buf, "tos-1");
+            TlHelper::write_string(buf, "Be nice");
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 16);
+        });
+
+        let expected = TermsOfService::new("tos-1".to_string(), "Be nice".to_string(), 16, true);
+        assert_eq!(
+            parse_authorization(&mut buf).unwrap(),
+            AuthorizationResult::SignUpRequired {
+                terms_of_service: Some(expected)
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_password() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_ACCOUNT_PASSWORD);
+            TlHelper::write_i32(buf, 0b1101);
+            TlHelper::write_constructor_id(buf, TL_PASSWORD_KDF_ALGO_MOD_POW);
+            TlHelper::write_bytes(buf, &[1, 2]);
+            TlHelper::write_bytes(buf, &[3, 4]);
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_bytes(buf, &[5; 256]);
+            TlHelper::write_bytes(buf, &[6; 256]);
+            TlHelper::write_i64(buf, 77);
+            TlHelper::write_string(buf, "pet name");
+        });
+
+        let state = parse_password(&mut buf).unwrap();
+        assert!(state.has_password);
+        assert!(state.has_recovery);
+        assert_eq!(state.hint, "pet name");
+        assert_eq!(state.srp.srp_id, 77);
+        assert_eq!(state.srp.g, 3);
+        assert_eq!(state.srp.salt1, vec![1, 2]);
+        assert_eq!(state.srp.salt2, vec![3, 4]);
+        assert_eq!(state.srp.b, vec![6; 256]);
+    }
+
+    #[test]
+    fn test_parse_login_token() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_LOGIN_TOKEN_MIGRATE_TO);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_bytes(buf, b"token");
+        });
+        assert_eq!(
+            parse_login_token(&mut buf).unwrap(),
+            LoginTokenResult::MigrateTo {
+                dc_id: 4,
+                token: b"token".to_vec()
+            }
+        );
+
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_LOGIN_TOKEN_SUCCESS);
+            TlHelper::write_constructor_id(buf, TL_AUTHORIZATION);
+            TlHelper::write_i32(buf, 0);
+            write_user(buf, 7);
+        });
+        assert_eq!(
+            parse_login_token(&mut buf).unwrap(),
+            LoginTokenResult::Success(AuthorizationResult::Authorized { user_id: 7 })
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown_constructor() {
+        let mut buf = tl(|buf| TlHelper::write_constructor_id(buf, 0xdead_beef));
+        assert!(parse_authorization(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Login flow tests against a scripted fake DC.
+//!
+//! Every test lists the requests the DC expects, in order, together with
+//! the replies it sends back. A request that doesn't match the script fails
+//! the test.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rustgram_auth_manager::{
+    AuthApi, AuthManagerError, AuthorizationResult, LoginFlow, LoginState, LoginTokenResult,
+    PasswordState, SentCodeInfo,
+};
+use rustgram_password_manager::SrpParams;
+use rustgram_send_code_helper::{AuthenticationCodeInfo, AuthenticationCodeType};
+use rustgram_terms_of_service::TermsOfService;
+
+const PHONE: &str = "+15550001111";
+
+/// Request received by the fake DC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Call {
+    SendCode(String),
+    SignIn(String),
+    SignUp(String, String),
+    GetPassword,
+    CheckPassword(i64),
+    AcceptTerms(String),
+    ExportLoginToken,
+    ImportLoginToken(Vec<u8>),
+    SetMainDc(i32),
+}
+
+/// Reply sent by the fake DC.
+enum Reply {
+    SentCode(SentCodeInfo),
+    Authorization(AuthorizationResult),
+    Password(PasswordState),
+    LoginToken(LoginTokenResult),
+    Done,
+    Error(i32, &'static str),
+}
+
+/// Fake DC answering with a fixed script.
+#[derive(Clone)]
+struct ScriptedDc {
+    script: Arc<Mutex<VecDeque<(Call, Reply)>>>,
+}
+
+impl ScriptedDc {
+    fn new(script: Vec<(Call, Reply)>) -> Self {
+        Self {
+            script: Arc::new(Mutex::new(script.into())),
+        }
+    }
+
+    fn reply(&self, call: Call) -> Result<Reply, AuthManagerError> {
+        let (expected, reply) = self
+            .script
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| panic!("Unexpected request {:?}", call));
+        assert_eq!(call, expected);
+        match reply {
+            Reply::Error(code, message) => Err(AuthManagerError::Failed {
+                code,
+                message: message.to_string(),
+            }),
+            reply => Ok(reply),
+        }
+    }
+
+    fn assert_done(&self) {
+        let script = self.script.lock().unwrap();
+        assert!(
+            script.is_empty(),
+            "Requests not received: {:?}",
+            script.iter().map(|(call, _)| call).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[async_trait]
+impl AuthApi for ScriptedDc {
+    async fn send_code(&self, phone_number: &str) -> Result<SentCodeInfo, AuthManagerError> {
+        match self.reply(Call::SendCode(phone_number.to_string()))? {
+            Reply::SentCode(sent_code) => Ok(sent_code),
+            _ => panic!("Wrong reply to auth.sendCode"),
+        }
+    }
+
+    async fn sign_in(
+        &self,
+        phone_number: &str,
+        phone_code_hash: &str,
+        code: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        assert_eq!(phone_number, PHONE);
+        assert_eq!(phone_code_hash, "code-hash");
+        authorization(self.reply(Call::SignIn(code.to_string()))?)
+    }
+
+    async fn sign_up(
+        &self,
+        _phone_number: &str,
+        phone_code_hash: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        assert_eq!(phone_code_hash, "code-hash");
+        authorization(self.reply(Call::SignUp(first_name.to_string(), last_name.to_string()))?)
+    }
+
+    async fn get_password(&self) -> Result<PasswordState, AuthManagerError> {
+        match self.reply(Call::GetPassword)? {
+            Reply::Password(state) => Ok(state),
+            _ => panic!("Wrong reply to account.getPassword"),
+        }
+    }
+
+    async fn check_password(
+        &self,
+        srp_id: i64,
+        a: &[u8],
+        m1: &[u8],
+    ) -> Result<AuthorizationResult, AuthManagerError> {
+        assert_eq!(a.len(), 256);
+        assert_eq!(m1.len(), 32);
+        authorization(self.reply(Call::CheckPassword(srp_id))?)
+    }
+
+    async fn accept_terms_of_service(&self, terms_id: &str) -> Result<(), AuthManagerError> {
+        self.reply(Call::AcceptTerms(terms_id.to_string()))
+            .map(|_| ())
+    }
+
+    async fn export_login_token(&self) -> Result<LoginTokenResult, AuthManagerError> {
+        login_token(self.reply(Call::ExportLoginToken)?)
+    }
+
+    async fn import_login_token(&self, token: &[u8]) -> Result<LoginTokenResult, AuthManagerError> {
+        login_token(self.reply(Call::ImportLoginToken(token.to_vec()))?)
+    }
+
+    fn set_main_dc_id(&self, dc_id: i32) {
+        self.reply(Call::SetMainDc(dc_id)).unwrap();
+    }
+}
+
+fn authorization(reply: Reply) -> Result<AuthorizationResult, AuthManagerError> {
+    match reply {
+        Reply::Authorization(result) => Ok(result),
+        _ => panic!("Wrong reply to an authorization request"),
+    }
+}
+
+fn login_token(reply: Reply) -> Result<LoginTokenResult, AuthManagerError> {
+    match reply {
+        Reply::LoginToken(result) => Ok(result),
+        _ => panic!("Wrong reply to a login token request"),
+    }
+}
+
+fn sent_code() -> Reply {
+    Reply::SentCode(SentCodeInfo {
+        phone_code_hash: "code-hash".to_string(),
+        code_info: AuthenticationCodeInfo::new(AuthenticationCodeType::Message, 5, ""),
+        next_code_info: Some(AuthenticationCodeInfo::new(
+            AuthenticationCodeType::Sms,
+            0,
+            "",
+        )),
+        timeout: 60,
+    })
+}
+
+fn authorized(user_id: i64) -> Reply {
+    Reply::Authorization(AuthorizationResult::Authorized { user_id })
+}
+
+fn password(srp_id: i64) -> Reply {
+    Reply::Password(PasswordState {
+        has_password: true,
+        hint: "pet name".to_string(),
+        has_recovery: true,
+        srp: SrpParams::new(
+            3,
+            vec![0xff; 256],
+            vec![0x7f; 256],
+            srp_id,
+            vec![1; 8],
+            vec![2; 16],
+            Vec::new(),
+            Vec::new(),
+        ),
+    })
+}
+
+#[tokio::test]
+async fn test_code_login() {
+    let dc = ScriptedDc::new(vec![
+        (Call::SendCode(PHONE.to_string()), sent_code()),
+        (
+            Call::SignIn("00000".to_string()),
+            Reply::Error(400, "PHONE_CODE_INVALID"),
+        ),
+        (Call::SignIn("12345".to_string()), authorized(42)),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+
+    let state = flow.set_phone_number(PHONE).await.unwrap();
+    assert!(matches!(state, LoginState::WaitCode { timeout: 60, .. }));
+    assert_eq!(flow.send_code_helper().phone_code_hash(), "code-hash");
+    assert_eq!(
+        flow.send_code_helper().next_code_info().type_(),
+        AuthenticationCodeType::Sms
+    );
+
+    // A wrong code can be retried
+    let error = flow.check_code("00000").await.unwrap_err();
+    assert!(error.is_rpc_error("PHONE_CODE_INVALID"));
+    assert_eq!(flow.state().as_str(), "WaitCode");
+
+    flow.check_code("12345").await.unwrap();
+    assert_eq!(flow.state(), &LoginState::Ready { user_id: 42 });
+    assert_eq!(flow.user_id(), Some(42));
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_phone_migrate() {
+    let dc = ScriptedDc::new(vec![
+        (
+            Call::SendCode(PHONE.to_string()),
+            Reply::Error(303, "PHONE_MIGRATE_4"),
+        ),
+        (Call::SetMainDc(4), Reply::Done),
+        (Call::SendCode(PHONE.to_string()), sent_code()),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+
+    flow.set_phone_number(PHONE).await.unwrap();
+    assert_eq!(flow.main_dc_id(), 4);
+    assert_eq!(flow.state().as_str(), "WaitCode");
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_two_factor_login() {
+    let dc = ScriptedDc::new(vec![
+        (Call::SendCode(PHONE.to_string()), sent_code()),
+        (
+            Call::SignIn("12345".to_string()),
+            Reply::Error(401, "SESSION_PASSWORD_NEEDED"),
+        ),
+        (Call::GetPassword, password(10)),
+        (
+            Call::CheckPassword(10),
+            Reply::Error(400, "PASSWORD_HASH_INVALID"),
+        ),
+        (Call::CheckPassword(10), Reply::Error(400, "SRP_ID_INVALID")),
+        (Call::GetPassword, password(11)),
+        (Call::CheckPassword(11), authorized(7)),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+    flow.set_phone_number(PHONE).await.unwrap();
+
+    let state = flow.check_code("12345").await.unwrap();
+    assert_eq!(
+        state,
+        &LoginState::WaitPassword {
+            hint: "pet name".to_string(),
+            has_recovery: true,
+        }
+    );
+
+    assert!(matches!(
+        flow.check_password("").await,
+        Err(AuthManagerError::EmptyPassword)
+    ));
+    let error = flow.check_password("wrong").await.unwrap_err();
+    assert!(error.is_rpc_error("PASSWORD_HASH_INVALID"));
+    assert_eq!(flow.state().as_str(), "WaitPassword");
+
+    // Outdated SRP parameters are reloaded once
+    flow.check_password("secret").await.unwrap();
+    assert_eq!(flow.user_id(), Some(7));
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_sign_up_with_terms_of_service() {
+    let terms = TermsOfService::new("tos-1".to_string(), "Be nice".to_string(), 16, true);
+    let dc = ScriptedDc::new(vec![
+        (Call::SendCode(PHONE.to_string()), sent_code()),
+        (
+            Call::SignIn("12345".to_string()),
+            Reply::Authorization(AuthorizationResult::SignUpRequired {
+                terms_of_service: Some(terms.clone()),
+            }),
+        ),
+        (
+            Call::SignUp("Ada".to_string(), "Lovelace".to_string()),
+            authorized(99),
+        ),
+        (Call::AcceptTerms("tos-1".to_string()), Reply::Done),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+    flow.set_phone_number(PHONE).await.unwrap();
+
+    let state = flow.check_code("12345").await.unwrap();
+    assert_eq!(
+        state,
+        &LoginState::WaitRegistration {
+            terms_of_service: Some(terms),
+        }
+    );
+    assert!(flow.register(" ", "").await.is_err());
+
+    flow.register("Ada", "Lovelace").await.unwrap();
+    assert_eq!(flow.user_id(), Some(99));
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_sign_up_when_terms_of_service_fail() {
+    let terms = TermsOfService::new("tos-2".to_string(), "Be nice".to_string(), 16, true);
+    let dc = ScriptedDc::new(vec![
+        (Call::SendCode(PHONE.to_string()), sent_code()),
+        (
+            Call::SignIn("12345".to_string()),
+            Reply::Authorization(AuthorizationResult::SignUpRequired {
+                terms_of_service: Some(terms),
+            }),
+        ),
+        (
+            Call::SignUp("Ada".to_string(), "Lovelace".to_string()),
+            authorized(99),
+        ),
+        (
+            Call::AcceptTerms("tos-2".to_string()),
+            Reply::Error(500, "INTERNAL"),
+        ),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+    flow.set_phone_number(PHONE).await.unwrap();
+    flow.check_code("12345").await.unwrap();
+
+    // The account is created even if the terms can't be accepted
+    let state = flow.register("Ada", "Lovelace").await.unwrap();
+    assert_eq!(state.as_str(), "Ready");
+    assert_eq!(flow.user_id(), Some(99));
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_qr_code_login_with_migration() {
+    let expires = i32::try_from(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    )
+    .unwrap()
+        + 30;
+    let dc = ScriptedDc::new(vec![
+        (
+            Call::ExportLoginToken,
+            Reply::LoginToken(LoginTokenResult::Token {
+                token: b"token".to_vec(),
+                expires,
+            }),
+        ),
+        (
+            Call::ExportLoginToken,
+            Reply::LoginToken(LoginTokenResult::MigrateTo {
+                dc_id: 5,
+                token: b"migrated".to_vec(),
+            }),
+        ),
+        (Call::SetMainDc(5), Reply::Done),
+        (
+            Call::ImportLoginToken(b"migrated".to_vec()),
+            Reply::LoginToken(LoginTokenResult::Success(AuthorizationResult::Authorized {
+                user_id: 5,
+            })),
+        ),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+
+    let state = flow.request_qr_code().await.unwrap();
+    let LoginState::WaitOtherDeviceConfirmation { link } = state else {
+        panic!("Unexpected state {:?}", state);
+    };
+    assert!(link.starts_with("tg://login?token="));
+    assert!(flow.qr_code_login().is_some());
+
+    flow.check_qr_code().await.unwrap();
+    assert_eq!(flow.main_dc_id(), 5);
+    assert_eq!(flow.user_id(), Some(5));
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_qr_code_login_with_password() {
+    let dc = ScriptedDc::new(vec![
+        (
+            Call::ExportLoginToken,
+            Reply::Error(401, "SESSION_PASSWORD_NEEDED"),
+        ),
+        (Call::GetPassword, password(3)),
+    ]);
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+
+    flow.request_qr_code().await.unwrap();
+    assert_eq!(flow.state().as_str(), "WaitPassword");
+    dc.assert_done();
+}
+
+#[tokio::test]
+async fn test_invalid_state() {
+    let dc = ScriptedDc::new(Vec::new());
+    let mut flow = LoginFlow::new(dc.clone(), 2);
+
+    assert_eq!(
+        flow.check_code("12345").await.unwrap_err(),
+        AuthManagerError::InvalidLoginState("WaitPhoneNumber")
+    );
+    assert!(matches!(
+        flow.set_phone_number("12345").await,
+        Err(AuthManagerError::InvalidPhoneNumber(_))
+    ));
+    dc.assert_done();
+}
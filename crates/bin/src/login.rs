@@ -0,0 +1,156 @@
+//! Interactive login.
+//!
+//! Drives a [`LoginFlow`] from the terminal: the user is asked for the code,
+//! the 2FA password or the name of a new account as the flow needs them.
+//! Saved sessions are restored from and written to the [`SessionStore`].
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rustgram_auth_manager::{AuthApi, AuthManagerError, LoginFlow, LoginState};
+use rustgram_net::{AuthKey, ConnectionPool, DcId};
+use rustgram_td_db::{AuthSession, SessionStore};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// Interval between checks of a shown QR code
+const QR_CODE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Restores the saved session, if any.
+///
+/// The auth key of the session is handed to the pool, so the connections
+/// to the main DC reuse the authorization. A session without an auth key
+/// isn't authorized anymore and is ignored.
+pub fn restore_session(store: &SessionStore, pool: &ConnectionPool) -> Result<Option<AuthSession>> {
+    let Some(session) = store.load().context("Failed to load the saved session")? else {
+        return Ok(None);
+    };
+    let Some((id, key)) = &session.auth_key else {
+        warn!("The saved session has no auth key, logging in again");
+        return Ok(None);
+    };
+    pool.set_auth_key(
+        DcId::internal(session.main_dc_id),
+        AuthKey::new(*id, key.clone()),
+    );
+    Ok(Some(session))
+}
+
+/// Saves the session of a logged in flow.
+///
+/// The authorization belongs to the auth key of the main DC, so the session
+/// is only saved together with it.
+pub fn save_session<A: AuthApi>(
+    store: &SessionStore,
+    pool: &ConnectionPool,
+    flow: &LoginFlow<A>,
+) -> Result<()> {
+    let Some(user_id) = flow.user_id() else {
+        bail!("Can't save the session before logging in");
+    };
+    let main_dc_id = flow.main_dc_id();
+    let Some(auth_key) = pool.auth_key(DcId::internal(main_dc_id)) else {
+        bail!("Auth key of DC {} is unknown, can't save the session", main_dc_id);
+    };
+
+    store
+        .save(&AuthSession {
+            main_dc_id,
+            user_id,
+            auth_key: Some((auth_key.id, auth_key.key)),
+        })
+        .context("Failed to save the session")
+}
+
+/// Runs the flow until the session is authorized.
+///
+/// `phone_number` is asked for if it is empty or rejected. With `use_qr_code` a QR code
+/// link is shown instead of sending a code.
+pub async fn login<A: AuthApi>(
+    flow: &mut LoginFlow<A>,
+    phone_number: &str,
+    use_qr_code: bool,
+) -> Result<()> {
+    let mut phone_number = phone_number.to_string();
+    let mut shown_link = String::new();
+    loop {
+        let result = match flow.state().clone() {
+            LoginState::WaitPhoneNumber if use_qr_code => flow.request_qr_code().await.map(drop),
+            LoginState::WaitPhoneNumber => {
+                // The configured number is only tried once
+                let phone_number = match std::mem::take(&mut phone_number) {
+                    number if number.is_empty() => prompt("Enter your phone number: ")?,
+                    number => number,
+                };
+                info!("🔐 Requesting verification code for {}...", phone_number);
+                flow.set_phone_number(&phone_number).await.map(drop)
+            }
+            LoginState::WaitCode { code_info, .. } => {
+                let code = prompt(&format!(
+                    "Enter the code sent via {:?}: ",
+                    code_info.type_()
+                ))?;
+                flow.check_code(&code).await.map(drop)
+            }
+            LoginState::WaitPassword { hint, .. } => {
+                info!("🔒 Two-factor authentication is enabled");
+                let password = if hint.is_empty() {
+                    prompt("Enter your password: ")?
+                } else {
+                    prompt(&format!("Enter your password (hint: {}): ", hint))?
+                };
+                flow.check_password(&password).await.map(drop)
+            }
+            LoginState::WaitRegistration { terms_of_service } => {
+                info!("📝 The phone number is not registered yet");
+                if let Some(terms) = &terms_of_service {
+                    println!("\n{}\n", terms.text());
+                    let answer = prompt("Accept the terms of service? [y/N]: ")?;
+                    if !answer.eq_ignore_ascii_case("y") {
+                        bail!("The terms of service were declined");
+                    }
+                }
+                let first_name = prompt("First name: ")?;
+                let last_name = prompt("Last name: ")?;
+                flow.register(&first_name, &last_name).await.map(drop)
+            }
+            LoginState::WaitOtherDeviceConfirmation { link } => {
+                if link != shown_link {
+                    println!(
+                        "\nScan this link as a QR code in Settings > Devices:\n{}\n",
+                        link
+                    );
+                    shown_link = link;
+                }
+                sleep(QR_CODE_POLL_INTERVAL).await;
+                flow.check_qr_code().await.map(drop)
+            }
+            LoginState::Ready { user_id } => {
+                info!("✅ Logged in as user {}", user_id);
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(()) => {}
+            // Wrong input; ask for it again
+            Err(e @ AuthManagerError::Failed { code: 400, .. })
+            | Err(e @ AuthManagerError::InvalidCode(_))
+            | Err(e @ AuthManagerError::InvalidPhoneNumber(_))
+            | Err(e @ AuthManagerError::EmptyPassword) => warn!("{}", e),
+            Err(e) => bail!("Authentication failed: {}", e),
+        }
+    }
+}
+
+/// Asks for a line of input.
+fn prompt(message: &str) -> Result<String> {
+    print!("{}", message);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        bail!("Input was closed");
+    }
+    Ok(line.trim().to_string())
+}
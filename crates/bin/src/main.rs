@@ -13,30 +13,69 @@
 //!
 //! # Or via environment variable
 //! RUSTGRAM_TEST=1 cargo run
+//!
+//! # Log in by scanning a QR code instead of entering a code
+//! cargo run -- --qr
+//!
+//! # Start counting the network usage statistics anew
+//! cargo run -- --reset-net-stats
+//!
+//! # Search for messages in all chats
+//! cargo run -- --search "query"
+//!
+//! # Show the chats in the terminal UI once the client is set up
+//! cargo run --features tui
 //! ```
+//!
+//! The session is saved in the data directory after the first login, so
+//! later runs skip the login.
+
+mod login;
 
-use std::io::{self, Write};
 use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use rustgram_auth_manager::AuthManager;
+use rustgram_auth_manager::{LoginFlow, NetworkAuthApi};
+use rustgram_dialog_list_id::DialogListId;
+use rustgram_message_query_manager::{MessageQueryManager, NetworkSearchClient};
+use rustgram_message_search_filter::MessageSearchFilter;
+use rustgram_messages_manager::{
+    MessageNetworkClient, MessageNetworkConfig, MessagesManager, MessagesManagerConfig,
+    NetworkMessagesClient,
+};
 use rustgram_net::{
-    set_test_mode, ConnectionPool, DcId, DcOption, DcOptionsSet,
-    NetQueryDispatcher, SessionConnectionConfig,
+    set_test_mode, ConnectionCreator, ConnectionPool, DcId, DcOption, DcOptionsSet,
+    NetQueryDispatcher, NetType, NetworkStats, SavedDcOptionStats, SessionConnectionConfig,
 };
 use rustgram_storage::{DbConnection, DialogDb};
-use tokio::time::sleep;
-use tracing::{error, info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use rustgram_td_db::{DbKey, SessionStore, TdDbParameters};
+use rustgram_types::UserId;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload};
+
+/// Interval between saves of the network usage statistics
+const NET_STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of messages shown by `--search`
+const SEARCH_LIMIT: i32 = 20;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Check CLI args FIRST (before loading .env) to detect flags
     let args: Vec<String> = std::env::args().collect();
     let has_dev_flag = args.iter().any(|a| a == "--dev");
+    let use_qr_code = args.iter().any(|a| a == "--qr");
+    let reset_net_stats_flag = args.iter().any(|a| a == "--reset-net-stats");
+    let search_query = args
+        .iter()
+        .position(|a| a == "--search")
+        .and_then(|i| args.get(i + 1))
+        .cloned();
 
     // Check for test mode via environment variable
     let is_test_env = std::env::var("RUSTGRAM_TEST").is_ok();
@@ -48,13 +87,15 @@ async fn main() -> Result<()> {
         dotenv::dotenv().ok();
     }
 
-    // Initialize logging
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("setting default subscriber failed");
+    // Initialize logging; the level can be changed later, e.g. to keep the
+    // TUI screen clean
+    #[cfg_attr(not(feature = "tui"), allow(unused_variables))]
+    let (log_filter, log_filter_handle) = reload::Layer::new(LevelFilter::INFO);
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(fmt::layer())
+        .try_init()
+        .context("Failed to set the default subscriber")?;
 
     // Test mode can be enabled via --dev flag or RUSTGRAM_TEST env var
     let test_dc = has_dev_flag || is_test_env;
@@ -76,10 +117,6 @@ async fn main() -> Result<()> {
         bail!("Missing RUSTGRAM_API_HASH. Get it from https://my.telegram.org/apps");
     }
 
-    if config.phone_number.is_empty() {
-        bail!("Missing RUSTGRAM_PHONE. Set your phone number (e.g., +1234567890)");
-    }
-
     info!("Configuration loaded:");
     info!("  API ID: {}", config.api_id);
     info!("  Phone: {}", config.phone_number);
@@ -133,138 +170,170 @@ async fn main() -> Result<()> {
     info!("Loaded {} DC options", dc_options.get_options().len());
     pool.set_dc_options(dc_options);
 
+    // Restore the saved session before the first connection is made
+    let db_params = TdDbParameters::new(
+        config.data_path.clone(),
+        data_path.join("files").to_string_lossy().into_owned(),
+        config.test_dc,
+        false,
+    );
+    let db_key = config
+        .db_password
+        .clone()
+        .map(DbKey::password)
+        .unwrap_or_else(DbKey::empty);
+    let session_store = Arc::new(
+        SessionStore::open_with_key(&db_params, &db_key)
+            .context("Failed to open the session database")?,
+    );
+    restore_dc_option_stats(&session_store, &pool);
+    restore_net_stats(&session_store, &pool);
+    if reset_net_stats_flag {
+        reset_net_stats(&session_store, &pool);
+        info!("Network statistics were reset");
+    }
+    // The network type of the statistics comes from the connection creator
+    let connection_creator = ConnectionCreator::new().with_net_stats(pool.net_stats());
+    connection_creator.set_net_type(config.net_type);
+    tokio::spawn(save_net_stats_periodically(
+        session_store.clone(),
+        pool.clone(),
+    ));
+    let session = login::restore_session(&session_store, &pool)?;
+    let main_dc_id = session.as_ref().map_or(2, |session| session.main_dc_id);
+
     // Create network dispatcher
-    let dispatcher = NetQueryDispatcher::new();
-    dispatcher.set_main_dc_id(2); // DC 2 is default for new auth
+    let dispatcher = Arc::new(NetQueryDispatcher::new());
+    dispatcher.set_main_dc_id(main_dc_id); // DC 2 is default for new auth
     dispatcher.set_session_pool(pool.clone());
     dispatcher.set_session_config(
-        SessionConnectionConfig::new(DcId::internal(2))
+        SessionConnectionConfig::new(DcId::internal(main_dc_id))
             .with_pfs(false)
             .with_main(true),
     );
 
-    info!("Starting authentication flow");
-
-    // Create auth manager
-    let auth_manager = AuthManager::new(
-        config.api_id as i32,
-        config.api_hash.clone(),
-        dispatcher,
-    );
-
-    // Start authentication flow
-    info!("\n📱 Starting authentication flow...");
-
-    // Set phone number (this automatically triggers code sending)
-    info!("🔐 Requesting verification code for {}...", config.phone_number);
-    if let Err(e) = auth_manager.set_phone_number(config.phone_number.clone()).await {
-        error!("Failed to send code request: {}", e);
-        bail!("Authentication failed: {}", e);
-    }
-
-    // Wait for state transition to WaitCode (with timeout)
-    info!("Waiting for server response...");
-    let mut wait_count = 0;
-    while wait_count < 30 {
-        sleep(Duration::from_millis(500)).await;
-        wait_count += 1;
-
-        let state = auth_manager.get_state();
-        let state_str = format!("{:?}", state);
-
-        if state_str.contains("WaitCode") || state_str.contains("WaitCode") {
-            info!("✅ Code sent! Check your phone for the verification code.");
-            break;
-        }
-
-        if state_str.contains("Ok") {
-            info!("✅ Already authorized!");
-            return Ok(());
-        }
-
-        if wait_count % 4 == 0 {
-            info!("Still waiting... (state: {})", state_str);
+    let my_user_id = if let Some(session) = session {
+        info!(
+            "✅ Restored the session of user {} on DC {}",
+            session.user_id, session.main_dc_id
+        );
+        session.user_id
+    } else {
+        if config.phone_number.is_empty() && !use_qr_code {
+            info!("RUSTGRAM_PHONE is not set, the phone number will be asked for");
         }
 
-        if state_str.contains("Error") || state_str.contains("Closed") {
-            bail!("Authentication failed with state: {}", state_str);
-        }
-    }
-
-    // Prompt for code
-    print!("Enter verification code: ");
-    io::stdout().flush()?;
-    let mut code = String::new();
-    io::stdin().read_line(&mut code)?;
-    let code = code.trim();
+        info!("\n📱 Starting authentication flow...");
+        let auth_api = NetworkAuthApi::new(
+            config.api_id as i32,
+            config.api_hash.clone(),
+            dispatcher.clone(),
+        );
+        let mut flow = LoginFlow::new(auth_api, main_dc_id);
+        login::login(&mut flow, &config.phone_number, use_qr_code).await?;
+        login::save_session(&session_store, &pool, &flow)?;
+        info!("Session saved, the next start will skip the login");
+        flow.user_id().context("Logged in without a user ID")?
+    };
 
-    if code.is_empty() {
-        bail!("Verification code cannot be empty");
-    }
+    // Messages are sent as TL queries through the dispatcher
+    let messages_client = Arc::new(NetworkMessagesClient::new(
+        dispatcher.clone(),
+        UserId::new(my_user_id)?,
+    ));
+    let messages_manager = Arc::new(MessagesManager::new(
+        Arc::new(MessageNetworkClient::new(
+            dispatcher.clone(),
+            MessageNetworkConfig::default(),
+        )),
+        MessagesManagerConfig::default(),
+    ));
+    messages_manager.set_media_client(messages_client.clone());
+    messages_manager.set_edit_client(messages_client.clone());
+    messages_manager.set_history_client(messages_client.clone());
+
+    // Remember which DC options worked for the next start
+    save_dc_option_stats(&session_store, &pool);
+    save_net_stats(&session_store, &pool);
 
-    // Check code with the server
-    info!("🔑 Verifying code...");
+    info!("\n✅ Client setup complete!");
+    info!("The client is now ready to use.");
 
-    match auth_manager
-        .check_code(code.to_string(), None)
-        .await
-    {
-        Ok(_) => {
-            info!("✅ Code verification successful!");
-        }
-        Err(e) => {
-            error!("Code verification failed: {}", e);
-            bail!("Authentication failed: {}", e);
-        }
+    if let Some(query) = search_query {
+        search_messages(dispatcher.clone(), query).await?;
     }
 
-    // Wait for authorization to complete
-    info!("Waiting for authorization to complete...");
-    let mut auth_wait_count = 0;
-    while auth_wait_count < 60 {
-        sleep(Duration::from_millis(500)).await;
-        auth_wait_count += 1;
+    #[cfg(feature = "tui")]
+    run_tui(
+        dispatcher,
+        messages_manager,
+        UserId::new(my_user_id)?,
+        &log_filter_handle,
+    )
+    .await?;
 
-        let state = auth_manager.get_state();
-        let state_str = format!("{:?}", state);
+    Ok(())
+}
 
-        if state_str.contains("Ok") {
-            info!("\n✅ Authentication successful!");
-            info!("You are now logged in as {}", config.phone_number);
-            break;
-        }
+/// Handle changing the level of the logs
+#[cfg(feature = "tui")]
+type LogFilterHandle = reload::Handle<LevelFilter, tracing_subscriber::Registry>;
 
-        if state_str.contains("WaitPassword") {
-            info!("\n🔒 Two-factor authentication enabled.");
-            print!("Enter your password: ");
-            io::stdout().flush()?;
-            let mut password = String::new();
-            io::stdin().read_line(&mut password)?;
-            let password = password.trim();
-
-            if let Err(e) = auth_manager.check_password(password.to_string()).await {
-                error!("Password verification failed: {}", e);
-                bail!("Authentication failed: {}", e);
-            }
-            continue;
-        }
+/// Shows the chats in the terminal UI until it is closed.
+///
+/// The logs are silenced while the UI draws on the terminal.
+#[cfg(feature = "tui")]
+async fn run_tui(
+    dispatcher: Arc<NetQueryDispatcher>,
+    messages_manager: Arc<MessagesManager>,
+    my_user_id: UserId,
+    log_filter: &LogFilterHandle,
+) -> Result<()> {
+    use rustgram_dialog_manager::{DialogManager, NetworkClient};
+    use rustgram_tui::{ManagersBackend, RustgramTuiApp};
+    use rustgram_types::DialogId;
+    use rustgram_updates_manager::UpdatesManager;
+
+    let backend = ManagersBackend::new(
+        tokio::runtime::Handle::current(),
+        DialogManager::new(),
+        NetworkClient::new(dispatcher),
+        messages_manager,
+        Arc::new(UpdatesManager::new()),
+        DialogId::from_user(my_user_id),
+    );
 
-        if auth_wait_count % 4 == 0 {
-            info!("Still authenticating... (state: {})", state_str);
-        }
+    log_filter.modify(|filter| *filter = LevelFilter::OFF)?;
+    // The UI loop blocks, so it runs outside of the runtime workers
+    let result =
+        tokio::task::spawn_blocking(move || RustgramTuiApp::with_backend(Box::new(backend))?.run())
+            .await;
+    log_filter.modify(|filter| *filter = LevelFilter::INFO)?;
+    result.context("The TUI stopped unexpectedly")??;
+    Ok(())
+}
 
-        if state_str.contains("Error") || state_str.contains("Closed") {
-            bail!("Authentication failed with state: {}", state_str);
-        }
-    }
+/// Searches for messages in all chats and logs the found ones.
+async fn search_messages(dispatcher: Arc<NetQueryDispatcher>, query: String) -> Result<()> {
+    let manager = MessageQueryManager::new()
+        .with_search_client(Arc::new(NetworkSearchClient::new(dispatcher)));
+    let found = manager
+        .search_messages(
+            DialogListId::main(),
+            query.clone(),
+            String::new(),
+            SEARCH_LIMIT,
+            MessageSearchFilter::Empty,
+            0,
+            0,
+        )
+        .await
+        .with_context(|| format!("Failed to search for {:?}", query))?;
 
-    if auth_wait_count >= 60 {
-        bail!("Authentication timeout - please try again");
+    info!("🔍 Found {} messages for {:?}", found.total_count(), query);
+    for message_full_id in found.messages() {
+        info!("  {}", message_full_id);
     }
-
-    info!("\n✅ Client setup complete!");
-    info!("The client is now ready to use.");
-
     Ok(())
 }
 
@@ -292,6 +361,82 @@ fn load_dc_options(config: &Config) -> Result<DcOptionsSet> {
     Ok(set)
 }
 
+/// Restores the saved connection statistics of the DC options.
+///
+/// Options that failed before are then tried after the working ones.
+fn restore_dc_option_stats(store: &SessionStore, pool: &ConnectionPool) {
+    let stats = match store.load_dc_option_stats() {
+        Ok(Some(stats)) => stats,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to load DC option statistics: {}", e);
+            return;
+        }
+    };
+    match serde_json::from_slice::<Vec<SavedDcOptionStats>>(&stats) {
+        Ok(stats) => pool.restore_dc_option_stats(&stats),
+        Err(e) => warn!("Ignoring malformed DC option statistics: {}", e),
+    }
+}
+
+/// Saves the connection statistics of the DC options.
+fn save_dc_option_stats(store: &SessionStore, pool: &ConnectionPool) {
+    let result = serde_json::to_vec(&pool.dc_option_stats())
+        .map_err(anyhow::Error::from)
+        .and_then(|stats| Ok(store.save_dc_option_stats(&stats)?));
+    if let Err(e) = result {
+        warn!("Failed to save DC option statistics: {}", e);
+    }
+}
+
+/// Restores the saved network usage statistics.
+fn restore_net_stats(store: &SessionStore, pool: &ConnectionPool) {
+    let stats = match store.load_net_stats() {
+        Ok(Some(stats)) => stats,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to load network statistics: {}", e);
+            return;
+        }
+    };
+    match serde_json::from_slice::<NetworkStats>(&stats) {
+        Ok(stats) => pool.net_stats().lock().restore(&stats),
+        Err(e) => warn!("Ignoring malformed network statistics: {}", e),
+    }
+}
+
+/// Saves the network usage statistics if they changed since the last save.
+fn save_net_stats(store: &SessionStore, pool: &ConnectionPool) {
+    let Some(stats) = pool.net_stats().lock().take_changed() else {
+        return;
+    };
+    let result = serde_json::to_vec(&stats)
+        .map_err(anyhow::Error::from)
+        .and_then(|stats| Ok(store.save_net_stats(&stats)?));
+    if let Err(e) = result {
+        warn!("Failed to save network statistics: {}", e);
+    }
+}
+
+/// Resets the network usage statistics, like TDLib's
+/// `resetNetworkStatistics`.
+///
+/// The reset is saved at once, so it isn't lost if the client stops before
+/// the next periodic save.
+fn reset_net_stats(store: &SessionStore, pool: &ConnectionPool) {
+    pool.net_stats().lock().reset();
+    save_net_stats(store, pool);
+}
+
+/// Saves the network usage statistics every [`NET_STATS_SAVE_INTERVAL`].
+async fn save_net_stats_periodically(store: Arc<SessionStore>, pool: Arc<ConnectionPool>) {
+    let mut interval = tokio::time::interval(NET_STATS_SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        save_net_stats(&store, &pool);
+    }
+}
+
 /// Loads RSA key from .pem file.
 ///
 /// Reads the PEM file and parses it as an RSA public key. The key can then
@@ -343,6 +488,13 @@ struct Config {
 
     /// Optional DC2 IP override (format: "IP:PORT" or "IP")
     dc2_override: Option<String>,
+
+    /// Optional password encrypting the session database; requires the
+    /// "sqlcipher" feature
+    db_password: Option<String>,
+
+    /// Type of the network the statistics are counted for
+    net_type: NetType,
 }
 
 impl Config {
@@ -355,6 +507,8 @@ impl Config {
     /// - `RUSTGRAM_DATA_PATH`: Path to data directory (optional, defaults to ~/.local/share/rustgram-client)
     /// - `RUSTGRAM_RSA_KEY_PATH`: Path to RSA public key file (optional, defaults to ./rsa_public.pem)
     /// - `RUSTGRAM_DC2`: DC2 IP override (optional, format: "IP:PORT" or "IP")
+    /// - `RUSTGRAM_DB_PASSWORD`: Password encrypting the session database (optional)
+    /// - `RUSTGRAM_NET_TYPE`: Network type: "wifi", "mobile", "mobile_roaming" or "other" (optional, defaults to "other")
     fn load(test_dc: bool) -> Result<Self> {
         let api_id = std::env::var("RUSTGRAM_API_ID")
             .unwrap_or_default()
@@ -379,6 +533,18 @@ impl Config {
         // DC2 override (format: "IP:PORT" or "IP")
         let dc2_override = std::env::var("RUSTGRAM_DC2").ok();
 
+        let db_password = std::env::var("RUSTGRAM_DB_PASSWORD")
+            .ok()
+            .filter(|password| !password.is_empty());
+
+        let net_type = match std::env::var("RUSTGRAM_NET_TYPE") {
+            Ok(net_type) => net_type
+                .parse::<NetType>()
+                .map_err(anyhow::Error::msg)
+                .context("Invalid RUSTGRAM_NET_TYPE")?,
+            Err(_) => NetType::Other,
+        };
+
         Ok(Self {
             api_id,
             api_hash,
@@ -387,6 +553,8 @@ impl Config {
             test_dc,
             rsa_key_path,
             dc2_override,
+            db_password,
+            net_type,
         })
     }
 
@@ -456,6 +624,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: Some("192.168.1.100:443".to_string()),
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         let (ip, port) = config.parse_dc2_override().unwrap();
@@ -473,6 +643,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: Some("192.168.1.100".to_string()),
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         let (ip, port) = config.parse_dc2_override().unwrap();
@@ -490,6 +662,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: None,
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         assert!(config.parse_dc2_override().is_none());
@@ -505,6 +679,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: Some("invalid".to_string()),
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         assert!(config.parse_dc2_override().is_none());
@@ -520,6 +696,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: Some("[::1]:443".to_string()),
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         let (ip, port) = config.parse_dc2_override().unwrap();
@@ -537,6 +715,8 @@ mod tests {
             test_dc: false,
             rsa_key_path: PathBuf::new(),
             dc2_override: Some("::1".to_string()),
+            db_password: None,
+            net_type: NetType::Other,
         };
 
         let (ip, port) = config.parse_dc2_override().unwrap();
@@ -61,9 +61,11 @@
 
 mod error;
 mod in_memory;
+mod partial;
 
 pub use error::{FileDbError, FileDbResult};
 pub use in_memory::InMemoryFileDb;
+pub use partial::{LocalFileFingerprint, PartialTransfer, PartialTransferStore, PrefixHasher};
 
 // Re-export FileDbId for convenience
 pub use rustgram_file_db_id::FileDbId;
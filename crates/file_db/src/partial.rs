@@ -0,0 +1,386 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Partial transfer state.
+//!
+//! Uploads and downloads save their progress here, so that they are resumed
+//! after a restart instead of starting over. TDLib keeps the same information
+//! in the `PartialLocalFileLocation` and `PartialRemoteFileLocation` of the
+//! file data.
+//!
+//! The local file may change while the client isn't running. The saved
+//! state therefore carries a fingerprint of the source file and the SHA-256
+//! of the transferred prefix, and is only used if both still match.
+
+use crate::{FileDb, FileDbError, FileDbId, FileDbResult};
+use bytes::Bytes;
+use rustgram_file_bitmask::FileBitmask;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Size and modification time of a local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalFileFingerprint {
+    /// File size in bytes
+    pub size: u64,
+    /// Modification time in nanoseconds since Unix epoch
+    pub mtime_nsec: u64,
+}
+
+impl LocalFileFingerprint {
+    /// Reads the fingerprint of a file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's metadata can't be read.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime_nsec = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| {
+                u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+            });
+        Ok(Self {
+            size: metadata.len(),
+            mtime_nsec,
+        })
+    }
+}
+
+/// Running SHA-256 of a prefix of a local file.
+///
+/// The prefix is extended as more parts become ready, so every byte is read
+/// only once.
+#[derive(Debug, Clone)]
+pub struct PrefixHasher {
+    /// Path of the hashed file
+    path: PathBuf,
+    /// Hash state after `size` bytes
+    hasher: Sha256,
+    /// Number of hashed bytes
+    size: i64,
+}
+
+impl PrefixHasher {
+    /// Creates a hasher of an empty prefix.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the number of hashed bytes.
+    #[must_use]
+    pub const fn size(&self) -> i64 {
+        self.size
+    }
+
+    /// Returns the SHA-256 of the hashed prefix.
+    #[must_use]
+    pub fn hash(&self) -> Vec<u8> {
+        self.hasher.clone().finalize().to_vec()
+    }
+
+    /// Extends the hashed prefix to `size` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or is shorter than `size`.
+    pub fn advance_to(&mut self, size: i64) -> io::Result<()> {
+        if size <= self.size {
+            return Ok(());
+        }
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.size.unsigned_abs()))?;
+        let mut buf = vec![0u8; 64 * 1024];
+        while self.size < size {
+            let len = buf
+                .len()
+                .min(usize::try_from(size - self.size).unwrap_or(usize::MAX));
+            file.read_exact(&mut buf[..len])?;
+            self.hasher.update(&buf[..len]);
+            self.size += len as i64;
+        }
+        Ok(())
+    }
+}
+
+/// Saved progress of an upload or a download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialTransfer {
+    /// ID of the transferred file
+    pub file_id: i32,
+    /// Path of the local file
+    pub path: String,
+    /// File size
+    pub size: i64,
+    /// Part size
+    pub part_size: i64,
+    /// Ready parts
+    pub ready_bitmask: FileBitmask,
+    /// Random ID of the upload on the server, 0 for downloads
+    pub upload_file_id: i64,
+    /// Fingerprint of the source file of an upload
+    pub source: Option<LocalFileFingerprint>,
+    /// Size of the hashed prefix of the local file
+    pub hash_size: i64,
+    /// SHA-256 of the prefix of the local file
+    pub hash: Vec<u8>,
+}
+
+impl PartialTransfer {
+    /// Returns the key of a download's state.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote` - Unique description of the remote location
+    #[must_use]
+    pub fn download_key(remote: &str) -> String {
+        format!("partial_download#{remote}")
+    }
+
+    /// Returns the key of an upload's state.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the uploaded file
+    #[must_use]
+    pub fn upload_key(path: &str) -> String {
+        format!("partial_upload#{path}")
+    }
+
+    /// Returns the indices of the ready parts.
+    #[must_use]
+    pub fn ready_parts(&self) -> Vec<i32> {
+        self.ready_bitmask.as_vector()
+    }
+
+    /// Checks that the local file hasn't changed since the state was saved.
+    ///
+    /// Returns the hasher of the verified prefix, to continue hashing from,
+    /// or `None` if the transfer must start over.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read for reasons other than
+    /// being missing or too short.
+    pub fn validate(&self) -> io::Result<Option<PrefixHasher>> {
+        let path = Path::new(&self.path);
+        if let Some(source) = self.source {
+            match LocalFileFingerprint::of(path) {
+                Ok(fingerprint) if fingerprint == source => {}
+                Ok(_) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut hasher = PrefixHasher::new(path);
+        match hasher.advance_to(self.hash_size) {
+            Ok(()) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+        Ok((hasher.hash() == self.hash).then_some(hasher))
+    }
+}
+
+/// Stored form of a [`PartialTransfer`].
+#[derive(Serialize, Deserialize)]
+struct StoredTransfer {
+    file_db_id: u64,
+    transfer: PartialTransfer,
+}
+
+/// Saves and loads [`PartialTransfer`]s in a file database.
+#[derive(Clone)]
+pub struct PartialTransferStore {
+    db: Arc<dyn FileDb>,
+}
+
+impl std::fmt::Debug for PartialTransferStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartialTransferStore")
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialTransferStore {
+    /// Creates a store backed by the file database.
+    #[must_use]
+    pub fn new(db: Arc<dyn FileDb>) -> Self {
+        Self { db }
+    }
+
+    async fn load_stored(&self, key: &str) -> FileDbResult<Option<StoredTransfer>> {
+        match self.db.get_file_data(key).await {
+            // State saved by an incompatible version is dropped
+            Ok(data) => Ok(serde_json::from_slice(&data).ok()),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Loads the state saved under the key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database fails.
+    pub async fn load(&self, key: &str) -> FileDbResult<Option<PartialTransfer>> {
+        Ok(self.load_stored(key).await?.map(|stored| stored.transfer))
+    }
+
+    /// Saves the state under the key, replacing a previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database fails.
+    pub async fn save(&self, key: &str, transfer: &PartialTransfer) -> FileDbResult<()> {
+        let file_db_id = match self.load_stored(key).await? {
+            Some(stored) => stored.file_db_id,
+            None => self.db.get_next_file_db_id().await?.get(),
+        };
+        let stored = StoredTransfer {
+            file_db_id,
+            transfer: transfer.clone(),
+        };
+        let data =
+            serde_json::to_vec(&stored).map_err(|e| FileDbError::Serialization(e.to_string()))?;
+        self.db
+            .set_file_data(FileDbId::new(file_db_id), key, Bytes::from(data))
+            .await
+    }
+
+    /// Removes the state saved under the key, e.g. after the transfer
+    /// finished.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database fails.
+    pub async fn clear(&self, key: &str) -> FileDbResult<()> {
+        match self.load_stored(key).await? {
+            Some(stored) => self.db.delete_file(FileDbId::new(stored.file_db_id)).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::InMemoryFileDb;
+    use rustgram_file_bitmask::BitmaskOnes;
+    use tempfile::tempdir;
+
+    fn transfer(path: &Path, hash_size: i64) -> PartialTransfer {
+        let mut hasher = PrefixHasher::new(path);
+        hasher.advance_to(hash_size).unwrap();
+        PartialTransfer {
+            file_id: 1,
+            path: path.to_string_lossy().into_owned(),
+            size: 1000,
+            part_size: 100,
+            ready_bitmask: FileBitmask::with_ones(BitmaskOnes, 3),
+            upload_file_id: 42,
+            source: Some(LocalFileFingerprint::of(path).unwrap()),
+            hash_size,
+            hash: hasher.hash(),
+        }
+    }
+
+    #[test]
+    fn test_prefix_hasher() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file");
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let mut hasher = PrefixHasher::new(&path);
+        hasher.advance_to(1000).unwrap();
+        hasher.advance_to(500).unwrap();
+        hasher.advance_to(150_000).unwrap();
+        assert_eq!(hasher.size(), 150_000);
+        assert_eq!(hasher.hash(), Sha256::digest(&data[..150_000]).to_vec());
+
+        assert!(hasher.advance_to(300_000).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, vec![7u8; 1000]).unwrap();
+
+        let state = transfer(&path, 300);
+        let hasher = state.validate().unwrap().unwrap();
+        assert_eq!(hasher.size(), 300);
+        assert_eq!(state.ready_parts(), vec![0, 1, 2]);
+
+        // Same size, different contents
+        std::fs::write(&path, vec![8u8; 1000]).unwrap();
+        let mut state = transfer(&path, 300);
+        state.hash = Sha256::digest([7u8; 300]).to_vec();
+        assert!(state.validate().unwrap().is_none());
+
+        // Changed source
+        let state = transfer(&path, 300);
+        std::fs::write(&path, vec![8u8; 2000]).unwrap();
+        assert!(state.validate().unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(state.validate().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, vec![7u8; 1000]).unwrap();
+
+        let db = Arc::new(InMemoryFileDb::new());
+        let store = PartialTransferStore::new(db.clone());
+        let key = PartialTransfer::upload_key("file");
+        assert_eq!(store.load(&key).await.unwrap(), None);
+
+        let mut state = transfer(&path, 100);
+        store.save(&key, &state).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state.clone()));
+
+        state.ready_bitmask.set(3);
+        store.save(&key, &state).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state));
+        assert_eq!(db.get_file_count().await.unwrap(), 1);
+
+        store.clear(&key).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+        assert_eq!(db.get_file_count().await.unwrap(), 0);
+    }
+}
@@ -15,6 +15,7 @@
 //! Download information tracking.
 
 use crate::error::{DownloadStateInternal, Result};
+use file_db::PartialTransfer;
 use rustgram_file_id::FileId;
 use rustgram_file_location::{FullRemoteFileLocation, LocalFileLocation};
 use rustgram_file_source_id::FileSourceId;
@@ -128,6 +129,12 @@ impl DownloadInfo {
         &self.local
     }
 
+    /// Returns the key of the download's saved progress in the file database.
+    #[must_use]
+    pub fn partial_key(&self) -> String {
+        PartialTransfer::download_key(&self.remote.to_string())
+    }
+
     /// Returns the file size.
     #[must_use]
     pub const fn size(&self) -> i64 {
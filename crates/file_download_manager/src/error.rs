@@ -15,6 +15,7 @@
 //! Error types for file download manager.
 
 use rustgram_file_downloader::Error as DownloaderError;
+use rustgram_file_reference_manager::Error as FileReferenceError;
 use rustgram_resource_manager::Error as ResourceManagerError;
 use thiserror::Error;
 
@@ -71,6 +72,14 @@ pub enum Error {
     /// Invalid priority value.
     #[error("invalid priority value: {0}")]
     InvalidPriority(i8),
+
+    /// I/O error while checking a partially downloaded file.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The file reference couldn't be repaired.
+    #[error("file reference error: {0}")]
+    FileReference(#[from] FileReferenceError),
 }
 
 /// Internal download state for state machine validation.
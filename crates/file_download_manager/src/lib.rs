@@ -29,6 +29,9 @@
 //! - **Progress tracking**: Per-download progress monitoring
 //! - **Pause/resume/cancel**: Full download control
 //! - **Auto-retry**: Failed downloads can be automatically retried
+//! - **Resuming**: Started downloads can be saved and continued after a restart
+//! - **File reference repair**: Expired file references are repaired through
+//!   the `FileReferenceManager`
 //!
 //! ## Usage
 //!
@@ -70,9 +73,11 @@ mod config;
 mod download_info;
 mod error;
 
+use file_db::{PartialTransfer, PrefixHasher};
 use rustgram_file_downloader::{FileDownloader, FileDownloaderConfig};
 use rustgram_file_id::FileId;
 use rustgram_file_location::{FullRemoteFileLocation, LocalFileLocation};
+use rustgram_file_reference_manager::{FileReferenceManager, FileSourceReloader};
 use rustgram_file_source_id::FileSourceId;
 use rustgram_resource_manager::{ResourceManager, ResourcePriority};
 use std::collections::{HashMap, VecDeque};
@@ -99,6 +104,17 @@ pub struct FileDownloadManager {
     callback: Box<dyn FileDownloadManagerCallback>,
     /// Next download ID counter.
     next_download_id: AtomicU64,
+    /// Progress of downloads to keep across restarts.
+    partial_downloads: parking_lot::RwLock<HashMap<u64, PartialDownload>>,
+}
+
+/// Progress of a download kept across restarts and pauses.
+#[derive(Debug)]
+struct PartialDownload {
+    /// Parts to skip when the download is started
+    ready_parts: Vec<i32>,
+    /// Hasher of the ready prefix of the local file
+    hasher: PrefixHasher,
 }
 
 impl FileDownloadManager {
@@ -132,6 +148,7 @@ impl FileDownloadManager {
             resource_manager,
             callback,
             next_download_id: AtomicU64::new(1),
+            partial_downloads: parking_lot::RwLock::new(HashMap::new()),
         }
     }
 
@@ -315,6 +332,8 @@ impl FileDownloadManager {
         let mut queue = self.queue.write();
         queue.retain(|&id| id != download_id);
 
+        self.partial_downloads.write().remove(&download_id);
+
         self.callback.on_download_cancelled(download_id, info.file_id());
         Ok(())
     }
@@ -424,6 +443,7 @@ impl FileDownloadManager {
         let mut queue = self.queue.write();
         let mut downloads = self.downloads.write();
         let mut active = self.active_downloads.write();
+        let mut partials = self.partial_downloads.write();
 
         let mut started = 0;
         while started < available_slots && !queue.is_empty() {
@@ -443,7 +463,22 @@ impl FileDownloadManager {
                     let config = FileDownloaderConfig::new(info.remote().clone(), info.size())
                         .with_local(info.local().clone());
 
-                    if let Ok(downloader) = FileDownloader::new(config) {
+                    let downloader = match partials.get(&download_id) {
+                        Some(partial) if !partial.ready_parts.is_empty() => {
+                            FileDownloader::new(
+                                config.clone().with_ready_parts(partial.ready_parts.clone()),
+                            )
+                            .or_else(|_| {
+                                // The saved parts don't fit the file anymore
+                                partials.remove(&download_id);
+                                info.update_downloaded_size(0);
+                                FileDownloader::new(config)
+                            })
+                        }
+                        _ => FileDownloader::new(config),
+                    };
+
+                    if let Ok(downloader) = downloader {
                         self.callback
                             .on_download_started(download_id, info.file_id());
                         active.insert(download_id, downloader);
@@ -489,6 +524,7 @@ impl FileDownloadManager {
             // Remove from active
             let mut active = self.active_downloads.write();
             active.remove(&download_id);
+            self.partial_downloads.write().remove(&download_id);
 
             // Trim completed history if needed
             if self.config.auto_remove_completed()
@@ -563,6 +599,172 @@ impl FileDownloadManager {
         }
     }
 
+    /// Runs `f` on the downloader of an active download.
+    ///
+    /// # Arguments
+    ///
+    /// * `download_id` - The download ID
+    /// * `f` - Function to run on the downloader
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the download is not active.
+    pub fn with_downloader<R>(
+        &self,
+        download_id: u64,
+        f: impl FnOnce(&mut FileDownloader) -> R,
+    ) -> Result<R> {
+        let mut active = self.active_downloads.write();
+        let downloader = active
+            .get_mut(&download_id)
+            .ok_or(Error::DownloadNotFound(download_id))?;
+        Ok(f(downloader))
+    }
+
+    /// Repairs the expired file reference of a download.
+    ///
+    /// Should be called when the downloader of the download needs a file
+    /// reference repair. Repairs requested for many parts at once re-fetch
+    /// the sources of the file only once. After the repair the failed parts
+    /// can be requested again with the fresh reference.
+    ///
+    /// # Arguments
+    ///
+    /// * `download_id` - The download ID
+    /// * `file_references` - The file reference manager knowing the sources
+    ///   of the file
+    /// * `reloader` - Re-fetches the sources of the file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the download is not found or the file reference
+    /// can't be repaired.
+    pub async fn repair_file_reference(
+        &self,
+        download_id: u64,
+        file_references: &FileReferenceManager,
+        reloader: &dyn FileSourceReloader,
+    ) -> Result<()> {
+        let file_id = self.get_download_info(download_id)?.file_id();
+        file_references
+            .repair_file_reference(file_id, reloader)
+            .await?;
+
+        if let Some(downloader) = self.active_downloads.write().get_mut(&download_id) {
+            downloader.on_file_reference_repaired();
+        }
+        Ok(())
+    }
+
+    /// Restores the saved progress of a download after a restart.
+    ///
+    /// The progress is used only if the partially downloaded file hasn't
+    /// changed since it was saved. Otherwise the download starts over.
+    ///
+    /// # Arguments
+    ///
+    /// * `download_id` - The download ID
+    /// * `partial` - Progress returned by [`Self::partial_downloads`]
+    ///
+    /// # Returns
+    ///
+    /// `true` if the download will continue from the saved progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the download is not found or already started,
+    /// or if the partially downloaded file can't be read.
+    pub fn restore_partial_download(
+        &self,
+        download_id: u64,
+        partial: &PartialTransfer,
+    ) -> Result<bool> {
+        let mut downloads = self.downloads.write();
+        let info = downloads
+            .get_mut(&download_id)
+            .ok_or(Error::DownloadNotFound(download_id))?;
+
+        if self.active_downloads.read().contains_key(&download_id) {
+            return Err(Error::AlreadyActive(download_id));
+        }
+
+        if partial.size != info.size() || info.local().file_name() != Some(partial.path.as_str()) {
+            return Ok(false);
+        }
+
+        let Some(hasher) = partial.validate()? else {
+            return Ok(false);
+        };
+
+        info.update_downloaded_size(
+            partial
+                .ready_bitmask
+                .get_total_size(partial.part_size, partial.size),
+        );
+        self.partial_downloads.write().insert(
+            download_id,
+            PartialDownload {
+                ready_parts: partial.ready_parts(),
+                hasher,
+            },
+        );
+        Ok(true)
+    }
+
+    /// Returns the progress of the active downloads to save, so that they
+    /// can continue after a restart.
+    ///
+    /// Downloads without a local file are skipped, as are downloads whose
+    /// partially downloaded file can't be read.
+    #[must_use]
+    pub fn partial_downloads(&self) -> Vec<(u64, PartialTransfer)> {
+        let downloads = self.downloads.read();
+        let active = self.active_downloads.read();
+        let mut partials = self.partial_downloads.write();
+
+        let mut result = Vec::new();
+        for (&download_id, downloader) in active.iter() {
+            let Some(info) = downloads.get(&download_id) else {
+                continue;
+            };
+            let Some(path) = info.local().file_name() else {
+                continue;
+            };
+
+            let part_size = downloader.part_size() as i64;
+            let ready_bitmask = downloader.ready_bitmask().clone();
+            let partial = partials
+                .entry(download_id)
+                .or_insert_with(|| PartialDownload {
+                    ready_parts: Vec::new(),
+                    hasher: PrefixHasher::new(path),
+                });
+            // Parts after the first missing one are hashed once the gap
+            // is filled
+            let prefix_size = ready_bitmask.get_ready_prefix_size(0, part_size, info.size());
+            if partial.hasher.advance_to(prefix_size).is_err() {
+                continue;
+            }
+            partial.ready_parts = ready_bitmask.as_vector();
+
+            result.push((
+                download_id,
+                PartialTransfer {
+                    file_id: info.file_id().get(),
+                    path: path.to_string(),
+                    size: info.size(),
+                    part_size,
+                    ready_bitmask,
+                    upload_file_id: 0,
+                    source: None,
+                    hash_size: partial.hasher.size(),
+                    hash: partial.hasher.hash(),
+                },
+            ));
+        }
+        result
+    }
+
     /// Returns the resource manager for bandwidth control.
     #[must_use]
     pub const fn resource_manager(&self) -> &ResourceManager {
@@ -875,4 +1077,212 @@ mod tests {
         // Queue should be ordered by priority
         assert_eq!(manager.pending_count(), 3);
     }
+
+    /// Starts a download of a 500KB file, downloading its first two parts.
+    fn start_partial_download(path: &std::path::Path) -> (FileDownloadManager, u64, Vec<u8>) {
+        let file: Vec<u8> = (0..500_000).map(|i| (i % 251) as u8).collect();
+        let local =
+            LocalFileLocation::partial(rustgram_file_location::PartialLocalFileLocation::new(
+                rustgram_file_type::FileType::Document,
+                0,
+                path.to_string_lossy().into_owned(),
+                String::new(),
+                String::new(),
+                0,
+            ));
+
+        let manager = FileDownloadManager::new(FileDownloadManagerConfig::new());
+        let download_id = manager
+            .add_download(
+                FullRemoteFileLocation::common(123, 456),
+                local,
+                file.len() as i64,
+                ResourcePriority::Normal,
+            )
+            .unwrap();
+        manager.process_queue();
+
+        let mut written = vec![0u8; file.len()];
+        manager
+            .with_downloader(download_id, |downloader| {
+                downloader.start().unwrap();
+                for _ in 0..2 {
+                    let part = downloader.get_next_part().unwrap();
+                    let start = part.offset as usize;
+                    written[start..start + part.size]
+                        .copy_from_slice(&file[start..start + part.size]);
+                    downloader.on_part_ok(part, part.size).unwrap();
+                }
+            })
+            .unwrap();
+        std::fs::write(path, &written).unwrap();
+        (manager, download_id, file)
+    }
+
+    #[test]
+    fn test_restore_partial_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let (manager, download_id, file) = start_partial_download(&path);
+
+        let partials = manager.partial_downloads();
+        assert_eq!(partials.len(), 1);
+        let (_, partial) = &partials[0];
+        assert_eq!(partial.ready_parts(), vec![0, 1]);
+        assert_eq!(partial.hash_size, 2 * partial.part_size);
+        let info = manager.get_download_info(download_id).unwrap();
+        assert!(info.partial_key().starts_with("partial_download#"));
+
+        // Restart
+        let manager = FileDownloadManager::new(FileDownloadManagerConfig::new());
+        let download_id = manager
+            .add_download(
+                info.remote().clone(),
+                info.local().clone(),
+                file.len() as i64,
+                ResourcePriority::Normal,
+            )
+            .unwrap();
+        assert!(manager
+            .restore_partial_download(download_id, partial)
+            .unwrap());
+        assert_eq!(
+            manager
+                .get_download_info(download_id)
+                .unwrap()
+                .downloaded_size(),
+            2 * partial.part_size
+        );
+
+        manager.process_queue();
+        let (downloaded, next_offset) = manager
+            .with_downloader(download_id, |downloader| {
+                downloader.start().unwrap();
+                let part = downloader.get_next_part().unwrap();
+                (downloader.downloaded_bytes(), part.offset)
+            })
+            .unwrap();
+        assert_eq!(downloaded, 2 * partial.part_size);
+        assert_eq!(next_offset, 2 * partial.part_size);
+    }
+
+    #[test]
+    fn test_restore_changed_partial_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let (manager, _, file) = start_partial_download(&path);
+        let (_, partial) = manager.partial_downloads().remove(0);
+
+        // The file was changed while the client wasn't running
+        std::fs::write(&path, vec![1u8; file.len()]).unwrap();
+
+        let manager = FileDownloadManager::new(FileDownloadManagerConfig::new());
+        let download_id = manager
+            .add_download(
+                FullRemoteFileLocation::common(123, 456),
+                LocalFileLocation::empty(),
+                file.len() as i64,
+                ResourcePriority::Normal,
+            )
+            .unwrap();
+        // A different local file
+        assert!(!manager
+            .restore_partial_download(download_id, &partial)
+            .unwrap());
+
+        let info = manager.get_download_info(download_id).unwrap();
+        let download_id = manager
+            .add_download(
+                info.remote().clone(),
+                LocalFileLocation::partial(rustgram_file_location::PartialLocalFileLocation::new(
+                    rustgram_file_type::FileType::Document,
+                    0,
+                    partial.path.clone(),
+                    String::new(),
+                    String::new(),
+                    0,
+                )),
+                file.len() as i64,
+                ResourcePriority::Normal,
+            )
+            .unwrap();
+        assert!(!manager
+            .restore_partial_download(download_id, &partial)
+            .unwrap());
+        assert_eq!(
+            manager
+                .get_download_info(download_id)
+                .unwrap()
+                .downloaded_size(),
+            0
+        );
+    }
+
+    /// Reloads every source successfully, counting the reloads.
+    #[derive(Default)]
+    struct CountingReloader {
+        reload_count: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl FileSourceReloader for CountingReloader {
+        async fn reload(
+            &self,
+            _source: &rustgram_file_reference_manager::FileSource,
+        ) -> std::result::Result<(), String> {
+            self.reload_count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repair_file_reference() {
+        let manager = FileDownloadManager::new(FileDownloadManagerConfig::new());
+        let download_id = manager
+            .add_download(
+                FullRemoteFileLocation::common(123, 456),
+                LocalFileLocation::empty(),
+                1_000_000,
+                ResourcePriority::Normal,
+            )
+            .unwrap();
+        manager.process_queue();
+
+        let mut file_references = FileReferenceManager::new();
+        let file_id = manager.get_download_info(download_id).unwrap().file_id();
+        let source = file_references.create_saved_animations_file_source();
+        file_references.add_file_source(file_id, source);
+
+        let need_repair = manager
+            .with_downloader(download_id, |downloader| {
+                downloader.start().unwrap();
+                for _ in 0..2 {
+                    let part = downloader.get_next_part().unwrap();
+                    downloader.on_part_error(&part, "FILE_REFERENCE_EXPIRED");
+                }
+                downloader.need_file_reference_repair()
+            })
+            .unwrap();
+        assert!(need_repair);
+
+        // Both failed parts ask for a repair
+        let reloader = CountingReloader::default();
+        let (first, second) = tokio::join!(
+            manager.repair_file_reference(download_id, &file_references, &reloader),
+            manager.repair_file_reference(download_id, &file_references, &reloader),
+        );
+        assert!(first.is_ok() && second.is_ok());
+        assert!(!manager
+            .with_downloader(download_id, |downloader| downloader
+                .need_file_reference_repair())
+            .unwrap());
+
+        // Without sources the reference can't be repaired
+        assert!(matches!(
+            manager
+                .repair_file_reference(download_id, &FileReferenceManager::new(), &reloader)
+                .await,
+            Err(Error::FileReference(_))
+        ));
+    }
 }
@@ -0,0 +1,246 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CDN downloads.
+//!
+//! Popular files may be served by a CDN DC. The main DC answers
+//! `upload.getFile` with `upload.fileCdnRedirect`, carrying a file token for
+//! the CDN and the AES-256-CTR key and IV the CDN's copy is encrypted with.
+//! CDN DCs are not trusted, so every chunk of the file is checked against
+//! the SHA-256 hashes the main DC returns from `upload.getCdnFileHashes`.
+
+use aes::Aes256;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::{Error, Result};
+
+/// AES-256 in big-endian counter mode.
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// Size of the chunks CDN file hashes are computed over.
+pub const CDN_HASH_CHUNK_SIZE: i64 = 128 * 1024;
+
+/// Hash of a chunk of a CDN file.
+///
+/// Corresponds to TL `fileHash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdnFileHash {
+    /// Offset of the chunk
+    pub offset: i64,
+    /// Size of the chunk
+    pub limit: i32,
+    /// SHA-256 of the chunk
+    pub hash: Vec<u8>,
+}
+
+impl CdnFileHash {
+    /// Computes the hash of a chunk.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the chunk
+    /// * `data` - The chunk
+    #[must_use]
+    pub fn compute(offset: i64, data: &[u8]) -> Self {
+        Self {
+            offset,
+            limit: data.len() as i32,
+            hash: Sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+/// Redirect of a download to a CDN DC.
+///
+/// Corresponds to TL `upload.fileCdnRedirect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdnRedirect {
+    /// CDN DC ID
+    pub dc_id: i32,
+    /// Token of the file on the CDN
+    pub file_token: Vec<u8>,
+    /// AES-256 key of the CDN's copy
+    pub encryption_key: Vec<u8>,
+    /// AES-CTR IV of the CDN's copy
+    pub encryption_iv: Vec<u8>,
+    /// Hashes of the first chunks
+    pub file_hashes: Vec<CdnFileHash>,
+}
+
+/// Decrypts a part of a CDN file in place.
+///
+/// The counter of the IV is replaced by the index of the 16-byte block the
+/// part starts at, so parts can be decrypted independently.
+///
+/// # Errors
+///
+/// Returns an error if the key or IV has the wrong size or the offset is
+/// not block-aligned.
+pub fn decrypt_cdn_part(key: &[u8], iv: &[u8], offset: i64, data: &mut [u8]) -> Result<()> {
+    if key.len() != 32 || iv.len() != 16 {
+        return Err(Error::CdnError("invalid encryption key".to_string()));
+    }
+    if offset % 16 != 0 || offset < 0 {
+        return Err(Error::CdnError(format!("unaligned part offset {offset}")));
+    }
+    let block = u32::try_from(offset / 16)
+        .map_err(|_| Error::CdnError(format!("part offset {offset} is too large")))?;
+
+    let mut iv_block = [0u8; 16];
+    iv_block.copy_from_slice(iv);
+    iv_block[12..].copy_from_slice(&block.to_be_bytes());
+
+    let mut cipher = Aes256Ctr::new(key.into(), (&iv_block).into());
+    cipher.apply_keystream(data);
+    Ok(())
+}
+
+/// Known hashes of the chunks of a CDN file.
+#[derive(Debug, Clone, Default)]
+pub struct CdnHashes {
+    /// Hashes by chunk offset
+    hashes: BTreeMap<i64, CdnFileHash>,
+}
+
+impl CdnHashes {
+    /// Creates an empty set of hashes.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds hashes received from the main DC.
+    pub fn add(&mut self, hashes: impl IntoIterator<Item = CdnFileHash>) {
+        for hash in hashes {
+            self.hashes.insert(hash.offset, hash);
+        }
+    }
+
+    /// Returns the number of known hashes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Returns `true` if no hashes are known.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Returns the offset of the first chunk of a part whose hash is
+    /// unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the part
+    /// * `size` - The size of the part
+    #[must_use]
+    pub fn find_missing(&self, offset: i64, size: usize) -> Option<i64> {
+        let end = offset + size as i64;
+        let mut pos = offset;
+        while pos < end {
+            match self.hashes.get(&pos) {
+                Some(hash) if hash.limit > 0 => pos += i64::from(hash.limit),
+                _ => return Some(pos),
+            }
+        }
+        None
+    }
+
+    /// Checks the decrypted data of a part against the hashes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CdnHashMismatch`] if a chunk doesn't match its hash
+    /// and [`Error::CdnError`] if a hash is unknown.
+    pub fn check(&self, offset: i64, data: &[u8]) -> Result<()> {
+        let end = offset + data.len() as i64;
+        let mut pos = offset;
+        while pos < end {
+            let hash = self
+                .hashes
+                .get(&pos)
+                .filter(|hash| hash.limit > 0)
+                .ok_or_else(|| Error::CdnError(format!("no hash for offset {pos}")))?;
+            let start = (pos - offset) as usize;
+            let chunk_end = (start + hash.limit as usize).min(data.len());
+            if Sha256::digest(&data[start..chunk_end]).as_slice() != hash.hash.as_slice() {
+                return Err(Error::CdnHashMismatch { offset: pos });
+            }
+            pos += i64::from(hash.limit);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut cipher = Aes256Ctr::new(key.into(), iv.into());
+        let mut encrypted = data.to_vec();
+        cipher.apply_keystream(&mut encrypted);
+        encrypted
+    }
+
+    #[test]
+    fn test_decrypt_cdn_part_at_offset() {
+        let key = [7u8; 32];
+        let iv = [0u8; 16];
+        let data: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let encrypted = encrypt(&key, &iv, &data);
+
+        let mut part = encrypted[1024..2048].to_vec();
+        decrypt_cdn_part(&key, &iv, 1024, &mut part).unwrap();
+        assert_eq!(part, &data[1024..2048]);
+    }
+
+    #[test]
+    fn test_decrypt_cdn_part_invalid() {
+        let mut data = vec![0u8; 16];
+        assert!(decrypt_cdn_part(&[0; 16], &[0; 16], 0, &mut data).is_err());
+        assert!(decrypt_cdn_part(&[0; 32], &[0; 16], 8, &mut data).is_err());
+    }
+
+    #[test]
+    fn test_cdn_hashes() {
+        let data = vec![5u8; 300];
+        let mut hashes = CdnHashes::new();
+        assert_eq!(hashes.find_missing(0, 300), Some(0));
+
+        hashes.add([
+            CdnFileHash::compute(0, &data[..128]),
+            CdnFileHash::compute(128, &data[128..256]),
+        ]);
+        assert_eq!(hashes.find_missing(0, 300), Some(256));
+        assert!(hashes.check(0, &data).is_err());
+
+        hashes.add([CdnFileHash::compute(256, &data[256..])]);
+        assert_eq!(hashes.find_missing(0, 300), None);
+        assert!(hashes.check(0, &data).is_ok());
+        assert!(hashes.check(128, &data[128..]).is_ok());
+
+        let mut corrupted = data.clone();
+        corrupted[200] ^= 1;
+        assert_eq!(
+            hashes.check(0, &corrupted),
+            Err(Error::CdnHashMismatch { offset: 128 })
+        );
+    }
+}
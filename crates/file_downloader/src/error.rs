@@ -57,6 +57,13 @@ pub enum Error {
     #[error("CDN error: {0}")]
     CdnError(String),
 
+    /// A chunk downloaded from a CDN doesn't match its hash
+    #[error("CDN hash mismatch at offset {offset}")]
+    CdnHashMismatch {
+        /// Offset of the chunk
+        offset: i64,
+    },
+
     /// Resource manager error
     #[error("resource manager error: {0}")]
     ResourceError(String),
@@ -91,7 +98,9 @@ mod tests {
             expected: 1000,
             actual: 500,
         };
-        assert!(format!("{}", error).contains("expected"));
-        assert!(format!("{}", error).contains("actual"));
+        assert_eq!(
+            format!("{}", error),
+            "file size mismatch: expected 1000, got 500"
+        );
     }
 }
@@ -33,46 +33,67 @@
 //! ## Usage
 //!
 //! ```rust,no_run
-//! use rustgram_file_downloader::{FileDownloader, FileDownloaderConfig};
-//! use rustgram_file_location::{FullRemoteFileLocation, RemoteFileLocation};
-//! use rustgram_file_encryption_key::FileEncryptionKey;
+//! use rustgram_file_downloader::{
+//!     FileDownloader, FileDownloaderConfig, NetworkPartLoader, PartOutcome,
+//! };
+//! use rustgram_file_location::FullRemoteFileLocation;
+//! use rustgram_net::{DcId, NetQueryDispatcher};
+//! use std::sync::Arc;
 //!
+//! # async fn example(location: &[u8]) -> rustgram_file_downloader::Result<()> {
+//! let loader = NetworkPartLoader::new(Arc::new(NetQueryDispatcher::new()));
 //! let remote = FullRemoteFileLocation::common(123, 456);
 //! let config = FileDownloaderConfig::new(remote, 10_000_000);
 //!
 //! let mut downloader = FileDownloader::new(config)?;
 //! downloader.start()?;
 //!
-//! while !downloader.is_complete() {
-//!     match downloader.download_next_part()? {
-//!         Some(part_data) => {
-//!             // Process downloaded part...
+//! loop {
+//!     let part = downloader.get_next_part()?;
+//!     if part.is_empty() {
+//!         break;
+//!     }
+//!     // `location` is the serialized `InputFileLocation` of the file
+//!     match loader.download_part(&mut downloader, part, DcId::internal(2), location).await? {
+//!         PartOutcome::Ready(data) => {
+//!             // Write the part at `part.offset`...
 //!         }
-//!         None => break,
+//!         PartOutcome::Retry | PartOutcome::Restart => {}
 //!     }
 //! }
-//! # Ok::<(), rustgram_file_downloader::Error>(())
+//! # Ok(())
+//! # }
 //! ```
 
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 
+use rustgram_file_bitmask::FileBitmask;
 use rustgram_file_encryption_key::FileEncryptionKey;
 use rustgram_file_location::{FullRemoteFileLocation, LocalFileLocation};
-use rustgram_parts_manager::{PartsManager, Part};
+use rustgram_file_reference_manager::is_file_reference_error;
+use rustgram_parts_manager::{InitOptions, Part, PartsManager};
 use rustgram_resource_manager::{ResourceManager, ResourceType};
+use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 
 pub use callback::FileDownloaderCallback;
+pub use cdn::{decrypt_cdn_part, CdnFileHash, CdnHashes, CdnRedirect, CDN_HASH_CHUNK_SIZE};
 pub use config::FileDownloaderConfig;
 pub use error::Error;
+pub use network::NetworkPartLoader;
+pub use query::{DownloadAnswer, DownloadQuery, PartOutcome};
 pub use state::DownloadState;
 
 mod callback;
+mod cdn;
 mod config;
 mod error;
+mod network;
+mod query;
 mod state;
+mod tl;
 
 /// Result type for file downloader operations.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -116,6 +137,8 @@ pub struct FileDownloader {
     _path: Option<PathBuf>,
     /// Current state
     state: DownloadState,
+    /// Options the parts manager was initialized with
+    init_options: InitOptions,
     /// Parts manager
     parts_manager: PartsManager,
     /// Query type
@@ -125,11 +148,19 @@ pub struct FileDownloader {
     /// CDN DC ID
     cdn_dc_id: Option<i32>,
     /// CDN encryption key
-    _cdn_encryption_key: Option<Vec<u8>>,
+    cdn_encryption_key: Option<Vec<u8>>,
     /// CDN encryption IV
-    _cdn_encryption_iv: Option<Vec<u8>>,
+    cdn_encryption_iv: Option<Vec<u8>>,
     /// CDN file token
-    _cdn_file_token: Option<String>,
+    cdn_file_token: Option<Vec<u8>>,
+    /// Known hashes of the CDN file
+    cdn_hashes: CdnHashes,
+    /// Reupload request tokens by part ID
+    cdn_reupload_tokens: HashMap<i32, Vec<u8>>,
+    /// Decrypted CDN parts waiting for their hashes, by part ID
+    cdn_unchecked_parts: HashMap<i32, Vec<u8>>,
+    /// Whether the file reference has expired
+    need_file_reference_repair: bool,
     /// Need check flag
     need_check: bool,
     /// Ordered flag
@@ -157,18 +188,16 @@ impl FileDownloader {
             32 * 1024 // Small files use 32KB parts
         };
 
+        let init_options = InitOptions {
+            size: config.size,
+            expected_size: config.expected_size.unwrap_or(config.size),
+            is_size_final: config.is_size_final,
+            part_size,
+            use_part_count_limit: true,
+            is_upload: false,
+        };
         let mut parts_manager = PartsManager::new();
-        parts_manager.init(
-            rustgram_parts_manager::InitOptions {
-                size: config.size,
-                expected_size: config.expected_size.unwrap_or(config.size),
-                is_size_final: config.is_size_final,
-                part_size,
-                use_part_count_limit: true,
-                is_upload: false,
-            },
-            &config.ready_parts,
-        )?;
+        parts_manager.init(init_options, &config.ready_parts)?;
 
         Ok(Self {
             remote: config.remote,
@@ -182,13 +211,18 @@ impl FileDownloader {
             limit: config.limit,
             _path: None,
             state: DownloadState::Idle,
+            init_options,
             parts_manager,
             query_type: QueryType::Default,
             use_cdn: false,
             cdn_dc_id: None,
-            _cdn_encryption_key: None,
-            _cdn_encryption_iv: None,
-            _cdn_file_token: None,
+            cdn_encryption_key: None,
+            cdn_encryption_iv: None,
+            cdn_file_token: None,
+            cdn_hashes: CdnHashes::new(),
+            cdn_reupload_tokens: HashMap::new(),
+            cdn_unchecked_parts: HashMap::new(),
+            need_file_reference_repair: false,
             need_check: false,
             _ordered_flag: false,
             _keep_fd: false,
@@ -334,6 +368,18 @@ impl FileDownloader {
         (ready_size as f64 / self.size as f64) * 100.0
     }
 
+    /// Returns the part size.
+    #[must_use]
+    pub const fn part_size(&self) -> usize {
+        self.parts_manager.get_part_size()
+    }
+
+    /// Returns the bitmask of downloaded parts.
+    #[must_use]
+    pub const fn ready_bitmask(&self) -> &FileBitmask {
+        self.parts_manager.get_ready_bitmask()
+    }
+
     /// Gets the number of downloaded bytes.
     #[must_use]
     pub fn downloaded_bytes(&self) -> i64 {
@@ -398,6 +444,228 @@ impl FileDownloader {
         self.cdn_dc_id
     }
 
+    /// Follows a redirect of the download to a CDN DC.
+    ///
+    /// # Arguments
+    ///
+    /// * `redirect` - The redirect received from the DC of the file
+    pub fn on_cdn_redirect(&mut self, redirect: CdnRedirect) {
+        self.enable_cdn(redirect.dc_id);
+        self.cdn_file_token = Some(redirect.file_token);
+        self.cdn_encryption_key = Some(redirect.encryption_key);
+        self.cdn_encryption_iv = Some(redirect.encryption_iv);
+        self.cdn_hashes = CdnHashes::new();
+        self.cdn_hashes.add(redirect.file_hashes);
+        self.cdn_reupload_tokens.clear();
+        self.cdn_unchecked_parts.clear();
+    }
+
+    /// Stops using the CDN, e.g. after its file token expired.
+    ///
+    /// The following parts are requested from the DC of the file, which
+    /// redirects to the CDN again if needed.
+    pub fn disable_cdn(&mut self) {
+        self.use_cdn = false;
+        self.cdn_dc_id = None;
+        self.cdn_file_token = None;
+        self.cdn_encryption_key = None;
+        self.cdn_encryption_iv = None;
+        self.cdn_hashes = CdnHashes::new();
+        self.cdn_reupload_tokens.clear();
+        self.cdn_unchecked_parts.clear();
+        self.query_type = QueryType::Default;
+    }
+
+    /// Returns the query to send for a part.
+    ///
+    /// The limit of `upload.getFile` and `upload.getCdnFile` is always the
+    /// part size, as the server requires; the last part is just shorter.
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The part to download
+    #[must_use]
+    pub fn query_for_part(&self, part: &Part) -> DownloadQuery {
+        let (Some(dc_id), Some(file_token)) = (self.cdn_dc_id, &self.cdn_file_token) else {
+            return DownloadQuery::GetFile {
+                offset: part.offset,
+                limit: self.part_size() as i32,
+            };
+        };
+
+        if let Some(request_token) = self.cdn_reupload_tokens.get(&part.id) {
+            return DownloadQuery::ReuploadCdnFile {
+                file_token: file_token.clone(),
+                request_token: request_token.clone(),
+            };
+        }
+        if let Some(data) = self.cdn_unchecked_parts.get(&part.id) {
+            if let Some(offset) = self.cdn_hashes.find_missing(part.offset, data.len()) {
+                return DownloadQuery::GetCdnFileHashes {
+                    file_token: file_token.clone(),
+                    offset,
+                };
+            }
+        }
+        DownloadQuery::GetCdnFile {
+            dc_id,
+            file_token: file_token.clone(),
+            offset: part.offset,
+            limit: self.part_size() as i32,
+        }
+    }
+
+    /// Handles the answer to the query for a part.
+    ///
+    /// Parts from a CDN are decrypted and checked against their hashes
+    /// before they are returned. The part is marked as downloaded once it is
+    /// [`PartOutcome::Ready`]. After [`PartOutcome::Restart`] the part is
+    /// dropped and the next parts are requested with
+    /// [`get_next_part`](Self::get_next_part).
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The part the query was sent for
+    /// * `answer` - The answer to the query
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the answer is unexpected or a CDN part doesn't
+    /// match its hash; the part is marked as failed then.
+    pub fn on_part_answer(&mut self, part: Part, answer: DownloadAnswer) -> Result<PartOutcome> {
+        let result = self.process_part_answer(&part, answer);
+        match &result {
+            Ok(PartOutcome::Ready(data)) => self.on_part_ok(part, data.len())?,
+            Ok(PartOutcome::Retry | PartOutcome::Restart) => {}
+            Err(_) => self.on_part_failed(part.id),
+        }
+        result
+    }
+
+    fn process_part_answer(&mut self, part: &Part, answer: DownloadAnswer) -> Result<PartOutcome> {
+        match answer {
+            DownloadAnswer::File(data) => Ok(PartOutcome::Ready(data)),
+            DownloadAnswer::CdnRedirect(redirect) => {
+                self.on_cdn_redirect(redirect);
+                if self.use_cdn_part_size()? {
+                    return Ok(PartOutcome::Restart);
+                }
+                Ok(PartOutcome::Retry)
+            }
+            DownloadAnswer::CdnFileReuploadNeeded(request_token) => {
+                if !self.use_cdn {
+                    return Err(Error::CdnError("unexpected reupload request".to_string()));
+                }
+                self.cdn_reupload_tokens.insert(part.id, request_token);
+                Ok(PartOutcome::Retry)
+            }
+            DownloadAnswer::CdnFileHashes(hashes) => {
+                self.cdn_reupload_tokens.remove(&part.id);
+                let Some(data) = self.cdn_unchecked_parts.remove(&part.id) else {
+                    self.cdn_hashes.add(hashes);
+                    return Ok(PartOutcome::Retry);
+                };
+                // The hashes were requested for the first chunk without one;
+                // asking again for the same chunk would never end
+                let missing = self.cdn_hashes.find_missing(part.offset, data.len());
+                self.cdn_hashes.add(hashes);
+                if missing.is_some()
+                    && self.cdn_hashes.find_missing(part.offset, data.len()) == missing
+                {
+                    return Err(Error::CdnError(format!(
+                        "no hash received for offset {}",
+                        missing.unwrap_or_default()
+                    )));
+                }
+                self.check_cdn_part(part, data)
+            }
+            DownloadAnswer::CdnFile(mut data) => {
+                let (Some(key), Some(iv)) = (&self.cdn_encryption_key, &self.cdn_encryption_iv)
+                else {
+                    return Err(Error::CdnError("unexpected upload.cdnFile".to_string()));
+                };
+                decrypt_cdn_part(key, iv, part.offset, &mut data)?;
+                self.check_cdn_part(part, data)
+            }
+        }
+    }
+
+    /// Splits the file into parts of whole CDN hash chunks, so that parts
+    /// downloaded from a CDN can be checked on their own.
+    ///
+    /// The ready prefix of the file is kept; other ready parts are
+    /// downloaded again. Returns `true` if the parts changed.
+    fn use_cdn_part_size(&mut self) -> Result<bool> {
+        let part_size = self.part_size();
+        let cdn_part_size = CDN_HASH_CHUNK_SIZE as usize;
+        if part_size % cdn_part_size == 0 {
+            return Ok(false);
+        }
+
+        let ready_prefix_size = self.parts_manager.get_ready_prefix_count() as usize * part_size;
+        let ready_parts: Vec<i32> = (0..(ready_prefix_size / cdn_part_size) as i32).collect();
+        self.init_options.part_size = cdn_part_size;
+        let mut parts_manager = PartsManager::new();
+        parts_manager.init(self.init_options, &ready_parts)?;
+        self.parts_manager = parts_manager;
+        Ok(true)
+    }
+
+    /// Checks a decrypted CDN part, or keeps it until its hashes are known.
+    fn check_cdn_part(&mut self, part: &Part, data: Vec<u8>) -> Result<PartOutcome> {
+        if self.cdn_hashes.find_missing(part.offset, data.len()).is_some() {
+            self.cdn_unchecked_parts.insert(part.id, data);
+            return Ok(PartOutcome::Retry);
+        }
+        self.cdn_hashes.check(part.offset, &data)?;
+        Ok(PartOutcome::Ready(data))
+    }
+
+    /// Handles an error returned for the query of a part.
+    ///
+    /// Returns `true` if the part should be requested again with a new
+    /// query; otherwise the part is marked as failed. After a
+    /// `FILE_REFERENCE_*` error the failed parts must be requested again only
+    /// after the file reference is repaired.
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The part the query was sent for
+    /// * `error` - The error message, like `FILE_TOKEN_INVALID`
+    pub fn on_part_error(&mut self, part: &Part, error: &str) -> bool {
+        match error {
+            // The CDN forgot the file; download it from the DC of the file
+            "FILE_TOKEN_INVALID" if self.use_cdn => {
+                self.disable_cdn();
+                true
+            }
+            "REQUEST_TOKEN_INVALID" if self.cdn_reupload_tokens.remove(&part.id).is_some() => true,
+            _ if is_file_reference_error(error) => {
+                self.need_file_reference_repair = true;
+                self.on_part_failed(part.id);
+                false
+            }
+            _ => {
+                self.cdn_unchecked_parts.remove(&part.id);
+                self.on_part_failed(part.id);
+                false
+            }
+        }
+    }
+
+    /// Returns `true` if the file reference must be repaired before
+    /// requesting more parts.
+    #[must_use]
+    pub const fn need_file_reference_repair(&self) -> bool {
+        self.need_file_reference_repair
+    }
+
+    /// Notifies that the file reference was repaired, so the failed parts
+    /// can be requested again.
+    pub fn on_file_reference_repaired(&mut self) {
+        self.need_file_reference_repair = false;
+    }
+
     /// Returns `true` if the download needs checking.
     #[must_use]
     pub const fn need_check(&self) -> bool {
@@ -475,7 +743,7 @@ mod tests {
 
         let downloader = downloader.unwrap();
         assert_eq!(downloader.size(), 10_000_000);
-        assert!(downloader.is_small());
+        assert!(!downloader.is_small());
     }
 
     #[test]
@@ -488,6 +756,26 @@ mod tests {
         assert_eq!(downloader.state(), DownloadState::Active);
     }
 
+    #[test]
+    fn test_file_reference_expired() {
+        let remote = FullRemoteFileLocation::common(123, 456);
+        let config = FileDownloaderConfig::new(remote, 1_000_000);
+
+        let mut downloader = FileDownloader::new(config).unwrap();
+        downloader.start().unwrap();
+        let first = downloader.get_next_part().unwrap();
+        let second = downloader.get_next_part().unwrap();
+
+        assert!(!downloader.on_part_error(&first, "FILE_REFERENCE_EXPIRED"));
+        assert!(!downloader.on_part_error(&second, "FILE_REFERENCE_EXPIRED"));
+        assert!(downloader.need_file_reference_repair());
+
+        downloader.on_file_reference_repaired();
+        assert!(!downloader.need_file_reference_repair());
+        // The failed parts are requested again
+        assert_eq!(downloader.get_next_part().unwrap().id, first.id);
+    }
+
     #[test]
     fn test_start_already_active() {
         let remote = FullRemoteFileLocation::common(123, 456);
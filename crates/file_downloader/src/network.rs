@@ -0,0 +1,236 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sending download queries through a [`NetQueryDispatcher`].
+//!
+//! `upload.getFile`, `upload.getCdnFileHashes` and `upload.reuploadCdnFile`
+//! go to the DC of the file, `upload.getCdnFile` goes to the CDN DC the file
+//! was redirected to. CDN DCs don't know the user, so queries to them are
+//! sent without authorization.
+//!
+//! A part failing with a `FILE_REFERENCE_*` error can be requested again
+//! after the reference is repaired through the `FileReferenceManager`, see
+//! [`NetworkPartLoader::download_part_with_repair`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rustgram_file_id::FileId;
+use rustgram_file_reference_manager::{FileReferenceManager, FileSourceReloader};
+use rustgram_net::{
+    AuthFlag, DcId, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryType,
+    QueryError,
+};
+use rustgram_parts_manager::Part;
+use tokio::sync::oneshot;
+
+use crate::query::{DownloadAnswer, DownloadQuery, PartOutcome};
+use crate::{Error, FileDownloader, Result};
+
+/// Timeout of a single query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Downloads parts of files by sending their queries to the DCs.
+pub struct NetworkPartLoader {
+    /// Dispatcher sending the queries
+    dispatcher: Arc<NetQueryDispatcher>,
+    /// Identifier of the next query
+    next_query_id: AtomicU64,
+}
+
+impl NetworkPartLoader {
+    /// Creates a loader sending the queries through the dispatcher.
+    #[must_use]
+    pub fn new(dispatcher: Arc<NetQueryDispatcher>) -> Self {
+        Self {
+            dispatcher,
+            next_query_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Downloads a part, following CDN redirects and requesting CDN hashes
+    /// and reuploads as needed.
+    ///
+    /// Returns [`PartOutcome::Ready`] with the checked data of the part, or
+    /// [`PartOutcome::Restart`] if the file was split into new parts and the
+    /// next parts must be requested again.
+    ///
+    /// # Arguments
+    ///
+    /// * `downloader` - The downloader the part belongs to
+    /// * `part` - The part to download
+    /// * `dc_id` - The DC of the file
+    /// * `location` - The serialized `InputFileLocation` of the file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails and can't be retried, e.g. after a
+    /// `FILE_REFERENCE_*` error, when
+    /// [`FileDownloader::need_file_reference_repair`] is set.
+    pub async fn download_part(
+        &self,
+        downloader: &mut FileDownloader,
+        part: Part,
+        dc_id: DcId,
+        location: &[u8],
+    ) -> Result<PartOutcome> {
+        loop {
+            let query = downloader.query_for_part(&part);
+            let answer = match self
+                .send(&query, dc_id, location, downloader.is_small())
+                .await
+            {
+                Ok(answer) => answer,
+                Err(error) => {
+                    if downloader.on_part_error(&part, &error) {
+                        continue;
+                    }
+                    return Err(Error::NetworkError(error));
+                }
+            };
+            match downloader.on_part_answer(part, answer)? {
+                PartOutcome::Retry => {}
+                outcome => return Ok(outcome),
+            }
+        }
+    }
+
+    /// Downloads a part like [`NetworkPartLoader::download_part`], repairing
+    /// an expired file reference once.
+    ///
+    /// After a `FILE_REFERENCE_*` error the sources of the file are
+    /// re-fetched and the part is requested again with the fresh reference.
+    ///
+    /// # Arguments
+    ///
+    /// * `downloader` - The downloader the part belongs to
+    /// * `part` - The part to download
+    /// * `dc_id` - The DC of the file
+    /// * `file_id` - The downloaded file
+    /// * `file_references` - The file reference manager knowing the sources
+    ///   of the file
+    /// * `reloader` - Re-fetches the sources of the file
+    /// * `location` - Returns the serialized `InputFileLocation` of the file
+    ///   with the latest reference recorded for it, or with its original
+    ///   reference if none was recorded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails and can't be retried, or the file
+    /// reference can't be repaired.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn download_part_with_repair<L>(
+        &self,
+        downloader: &mut FileDownloader,
+        part: Part,
+        dc_id: DcId,
+        file_id: FileId,
+        file_references: &FileReferenceManager,
+        reloader: &dyn FileSourceReloader,
+        location: L,
+    ) -> Result<PartOutcome>
+    where
+        L: Fn(Option<&[u8]>) -> Vec<u8>,
+    {
+        let current = location(file_references.get_file_reference(file_id).as_deref());
+        let result = self.download_part(downloader, part, dc_id, &current).await;
+        if result.is_ok() || !downloader.need_file_reference_repair() {
+            return result;
+        }
+
+        file_references
+            .repair_file_reference(file_id, reloader)
+            .await
+            .map_err(|e| Error::NetworkError(e.to_string()))?;
+        downloader.on_file_reference_repaired();
+        let repaired = location(file_references.get_file_reference(file_id).as_deref());
+        self.download_part(downloader, part, dc_id, &repaired).await
+    }
+
+    /// Sends a query and waits for its answer.
+    ///
+    /// On failure returns the error message of the server, like
+    /// `FILE_TOKEN_INVALID`, to pass to [`FileDownloader::on_part_error`].
+    async fn send(
+        &self,
+        query: &DownloadQuery,
+        dc_id: DcId,
+        location: &[u8],
+        is_small: bool,
+    ) -> std::result::Result<DownloadAnswer, String> {
+        let (dc_id, auth_flag) = match query {
+            DownloadQuery::GetCdnFile { dc_id, .. } => {
+                if !DcId::is_valid(*dc_id) {
+                    return Err(format!("invalid CDN DC {dc_id}"));
+                }
+                (DcId::external(*dc_id), AuthFlag::Off)
+            }
+            _ => (dc_id, AuthFlag::On),
+        };
+        let query_type = if is_small {
+            NetQueryType::DownloadSmall
+        } else {
+            NetQueryType::Download
+        };
+        let net_query = NetQuery::new(
+            self.next_query_id.fetch_add(1, Ordering::Relaxed),
+            query.encode(location).freeze(),
+            dc_id,
+            query_type,
+            auth_flag,
+            GzipFlag::Off,
+            query.constructor() as i32,
+        );
+
+        let (sender, receiver) = oneshot::channel();
+        net_query.set_callback(Box::new(ResultCallback {
+            sender: parking_lot::Mutex::new(Some(sender)),
+        }));
+        self.dispatcher
+            .dispatch(net_query)
+            .map_err(|e| format!("dispatch error: {e}"))?;
+
+        let answer = match tokio::time::timeout(QUERY_TIMEOUT, receiver).await {
+            Ok(Ok(Ok(answer))) => answer,
+            Ok(Ok(Err(QueryError::WithMessage { message, .. }))) => return Err(message),
+            Ok(Ok(Err(error))) => return Err(error.to_string()),
+            Ok(Err(_)) => return Err("query was dropped".to_string()),
+            Err(_) => return Err("query timed out".to_string()),
+        };
+        query.parse_answer(&answer).map_err(|e| e.to_string())
+    }
+}
+
+/// Callback passing the result of a query to the waiting download
+struct ResultCallback {
+    /// Channel to the download, taken by the first result
+    sender: parking_lot::Mutex<Option<oneshot::Sender<std::result::Result<Bytes, QueryError>>>>,
+}
+
+#[async_trait]
+impl NetQueryCallback for ResultCallback {
+    async fn on_result(&self, query: NetQuery) {
+        let result = if query.is_error() {
+            Err(query.error())
+        } else {
+            Ok(query.ok())
+        };
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(result);
+        }
+    }
+}
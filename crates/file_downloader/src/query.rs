@@ -0,0 +1,93 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Network queries of file downloads.
+//!
+//! The downloader doesn't talk to the network itself. For every part it
+//! tells which query to send, and it is given the answer back.
+
+use crate::cdn::{CdnFileHash, CdnRedirect};
+
+/// A query to send for a part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadQuery {
+    /// `upload.getFile` to the DC of the file
+    GetFile {
+        /// Offset of the part
+        offset: i64,
+        /// Size of the part
+        limit: i32,
+    },
+    /// `upload.getCdnFile` to the CDN DC
+    GetCdnFile {
+        /// CDN DC ID
+        dc_id: i32,
+        /// Token of the file on the CDN
+        file_token: Vec<u8>,
+        /// Offset of the part
+        offset: i64,
+        /// Size of the part
+        limit: i32,
+    },
+    /// `upload.getCdnFileHashes` to the DC of the file
+    GetCdnFileHashes {
+        /// Token of the file on the CDN
+        file_token: Vec<u8>,
+        /// Offset of the first chunk to get hashes for
+        offset: i64,
+    },
+    /// `upload.reuploadCdnFile` to the DC of the file
+    ReuploadCdnFile {
+        /// Token of the file on the CDN
+        file_token: Vec<u8>,
+        /// Token from `upload.cdnFileReuploadNeeded`
+        request_token: Vec<u8>,
+    },
+}
+
+impl DownloadQuery {
+    /// Returns `true` if the query is sent to the CDN DC.
+    #[must_use]
+    pub const fn is_cdn(&self) -> bool {
+        matches!(self, Self::GetCdnFile { .. })
+    }
+}
+
+/// The answer to a [`DownloadQuery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadAnswer {
+    /// `upload.file`
+    File(Vec<u8>),
+    /// `upload.fileCdnRedirect`
+    CdnRedirect(CdnRedirect),
+    /// `upload.cdnFile`, still encrypted
+    CdnFile(Vec<u8>),
+    /// `upload.cdnFileReuploadNeeded` with the request token
+    CdnFileReuploadNeeded(Vec<u8>),
+    /// The hashes returned by `upload.getCdnFileHashes` and
+    /// `upload.reuploadCdnFile`
+    CdnFileHashes(Vec<CdnFileHash>),
+}
+
+/// What to do with a part after an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartOutcome {
+    /// The part is downloaded and checked
+    Ready(Vec<u8>),
+    /// The part needs another query
+    Retry,
+    /// The file was split into new parts, e.g. after a redirect to a CDN;
+    /// the part must be dropped and the next parts requested again
+    Restart,
+}
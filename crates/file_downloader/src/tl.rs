@@ -0,0 +1,318 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TL encoding of download queries and their answers.
+
+use bytes::BytesMut;
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::{TlHelper, TypeError};
+
+use crate::cdn::{CdnFileHash, CdnRedirect};
+use crate::query::{DownloadAnswer, DownloadQuery};
+use crate::{Error, Result};
+
+/// Request constructors
+const TL_UPLOAD_GET_FILE: u32 = 0xbe53_35be;
+const TL_UPLOAD_GET_CDN_FILE: u32 = 0x395f_69da;
+const TL_UPLOAD_REUPLOAD_CDN_FILE: u32 = 0x9b27_54a8;
+const TL_UPLOAD_GET_CDN_FILE_HASHES: u32 = 0x91dc_3f31;
+
+/// Response constructors
+const TL_UPLOAD_FILE: u32 = 0x096a_18d5;
+const TL_UPLOAD_FILE_CDN_REDIRECT: u32 = 0xf18c_da44;
+const TL_UPLOAD_CDN_FILE: u32 = 0xa99f_ca4f;
+const TL_UPLOAD_CDN_FILE_REUPLOAD_NEEDED: u32 = 0xeea8_e46e;
+const TL_FILE_HASH: u32 = 0xf39b_035c;
+const TL_VECTOR: u32 = 0x1cb5_c415;
+
+/// `upload.getFile` flag: the file may be redirected to a CDN
+const GET_FILE_FLAG_CDN_SUPPORTED: i32 = 1 << 1;
+
+impl DownloadQuery {
+    /// Returns the TL constructor of the query.
+    #[must_use]
+    pub const fn constructor(&self) -> u32 {
+        match self {
+            Self::GetFile { .. } => TL_UPLOAD_GET_FILE,
+            Self::GetCdnFile { .. } => TL_UPLOAD_GET_CDN_FILE,
+            Self::GetCdnFileHashes { .. } => TL_UPLOAD_GET_CDN_FILE_HASHES,
+            Self::ReuploadCdnFile { .. } => TL_UPLOAD_REUPLOAD_CDN_FILE,
+        }
+    }
+
+    /// Encodes the query.
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - The serialized `InputFileLocation` of the file, with
+    ///   its current file reference; only used by `upload.getFile`
+    #[must_use]
+    pub fn encode(&self, location: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, self.constructor());
+        match self {
+            Self::GetFile { offset, limit } => {
+                TlHelper::write_i32(&mut buf, GET_FILE_FLAG_CDN_SUPPORTED);
+                buf.extend_from_slice(location);
+                TlHelper::write_i64(&mut buf, *offset);
+                TlHelper::write_i32(&mut buf, *limit);
+            }
+            Self::GetCdnFile {
+                file_token,
+                offset,
+                limit,
+                ..
+            } => {
+                TlHelper::write_bytes(&mut buf, file_token);
+                TlHelper::write_i64(&mut buf, *offset);
+                TlHelper::write_i32(&mut buf, *limit);
+            }
+            Self::GetCdnFileHashes { file_token, offset } => {
+                TlHelper::write_bytes(&mut buf, file_token);
+                TlHelper::write_i64(&mut buf, *offset);
+            }
+            Self::ReuploadCdnFile {
+                file_token,
+                request_token,
+            } => {
+                TlHelper::write_bytes(&mut buf, file_token);
+                TlHelper::write_bytes(&mut buf, request_token);
+            }
+        }
+        buf
+    }
+
+    /// Parses the answer to the query.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NetworkError`] if the answer is malformed or of a
+    /// type the query can't return.
+    pub fn parse_answer(&self, answer: &[u8]) -> Result<DownloadAnswer> {
+        let mut buf = TlBytes::from_vec(answer.to_vec());
+        let answer = match self {
+            Self::GetFile { .. } => parse_file(&mut buf),
+            Self::GetCdnFile { .. } => parse_cdn_file(&mut buf),
+            Self::GetCdnFileHashes { .. } | Self::ReuploadCdnFile { .. } => {
+                read_file_hashes(&mut buf).map(DownloadAnswer::CdnFileHashes)
+            }
+        };
+        answer.map_err(|e| Error::NetworkError(format!("malformed answer: {e}")))
+    }
+}
+
+/// Parses `upload.File`.
+fn parse_file(buf: &mut TlBytes) -> std::result::Result<DownloadAnswer, TypeError> {
+    match TlHelper::read_constructor_id(buf)? {
+        TL_UPLOAD_FILE => {
+            // type:storage.FileType has no fields
+            TlHelper::read_constructor_id(buf)?;
+            // mtime
+            TlHelper::read_i32(buf)?;
+            Ok(DownloadAnswer::File(TlHelper::read_bytes(buf)?))
+        }
+        TL_UPLOAD_FILE_CDN_REDIRECT => Ok(DownloadAnswer::CdnRedirect(CdnRedirect {
+            dc_id: TlHelper::read_i32(buf)?,
+            file_token: TlHelper::read_bytes(buf)?,
+            encryption_key: TlHelper::read_bytes(buf)?,
+            encryption_iv: TlHelper::read_bytes(buf)?,
+            file_hashes: read_file_hashes(buf)?,
+        })),
+        id => Err(unexpected_constructor(id)),
+    }
+}
+
+/// Parses `upload.CdnFile`.
+fn parse_cdn_file(buf: &mut TlBytes) -> std::result::Result<DownloadAnswer, TypeError> {
+    match TlHelper::read_constructor_id(buf)? {
+        TL_UPLOAD_CDN_FILE => Ok(DownloadAnswer::CdnFile(TlHelper::read_bytes(buf)?)),
+        TL_UPLOAD_CDN_FILE_REUPLOAD_NEEDED => Ok(DownloadAnswer::CdnFileReuploadNeeded(
+            TlHelper::read_bytes(buf)?,
+        )),
+        id => Err(unexpected_constructor(id)),
+    }
+}
+
+/// Reads `Vector<FileHash>`.
+fn read_file_hashes(buf: &mut TlBytes) -> std::result::Result<Vec<CdnFileHash>, TypeError> {
+    let id = TlHelper::read_constructor_id(buf)?;
+    if id != TL_VECTOR {
+        return Err(unexpected_constructor(id));
+    }
+    let count = TlHelper::read_i32(buf)?;
+    // Every hash takes at least 20 bytes
+    if count < 0 || count as usize > buf.remaining() / 20 {
+        return Err(TypeError::DeserializationError(format!(
+            "invalid vector length {count}"
+        )));
+    }
+    (0..count)
+        .map(|_| {
+            let id = TlHelper::read_constructor_id(buf)?;
+            if id != TL_FILE_HASH {
+                return Err(unexpected_constructor(id));
+            }
+            Ok(CdnFileHash {
+                offset: TlHelper::read_i64(buf)?,
+                limit: TlHelper::read_i32(buf)?,
+                hash: TlHelper::read_bytes(buf)?,
+            })
+        })
+        .collect()
+}
+
+fn unexpected_constructor(id: u32) -> TypeError {
+    TypeError::DeserializationError(format!("unexpected constructor 0x{id:08x}"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn write_hashes(buf: &mut BytesMut, hashes: &[CdnFileHash]) {
+        TlHelper::write_constructor_id(buf, TL_VECTOR);
+        TlHelper::write_i32(buf, hashes.len() as i32);
+        for hash in hashes {
+            TlHelper::write_constructor_id(buf, TL_FILE_HASH);
+            TlHelper::write_i64(buf, hash.offset);
+            TlHelper::write_i32(buf, hash.limit);
+            TlHelper::write_bytes(buf, &hash.hash);
+        }
+    }
+
+    #[test]
+    fn test_encode_get_file() {
+        let query = DownloadQuery::GetFile {
+            offset: 1 << 20,
+            limit: 512 * 1024,
+        };
+        let location = [0xaa; 12];
+        let buf = query.encode(&location);
+
+        assert_eq!(&buf[..4], &TL_UPLOAD_GET_FILE.to_le_bytes());
+        assert_eq!(&buf[4..8], &GET_FILE_FLAG_CDN_SUPPORTED.to_le_bytes());
+        assert_eq!(&buf[8..20], &location);
+        assert_eq!(&buf[20..28], &(1i64 << 20).to_le_bytes());
+        assert_eq!(&buf[28..], &(512i32 * 1024).to_le_bytes());
+    }
+
+    #[test]
+    fn test_encode_cdn_queries() {
+        let query = DownloadQuery::GetCdnFileHashes {
+            file_token: b"tok".to_vec(),
+            offset: 4096,
+        };
+        let buf = query.encode(&[]);
+        assert_eq!(&buf[..4], &TL_UPLOAD_GET_CDN_FILE_HASHES.to_le_bytes());
+        // Length-prefixed token padded to 4 bytes, then the offset
+        assert_eq!(&buf[4..8], &[3, b't', b'o', b'k']);
+        assert_eq!(&buf[8..], &4096i64.to_le_bytes());
+
+        let query = DownloadQuery::ReuploadCdnFile {
+            file_token: b"tok".to_vec(),
+            request_token: b"req".to_vec(),
+        };
+        assert_eq!(query.encode(&[]).len(), 12);
+    }
+
+    #[test]
+    fn test_parse_file_and_redirect() {
+        let query = DownloadQuery::GetFile {
+            offset: 0,
+            limit: 4096,
+        };
+
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_UPLOAD_FILE);
+        // storage.filePartial
+        TlHelper::write_constructor_id(&mut buf, 0x40bc_6f52);
+        TlHelper::write_i32(&mut buf, 0);
+        TlHelper::write_bytes(&mut buf, b"data");
+        assert_eq!(
+            query.parse_answer(&buf).unwrap(),
+            DownloadAnswer::File(b"data".to_vec())
+        );
+
+        let hash = CdnFileHash::compute(0, b"chunk");
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_UPLOAD_FILE_CDN_REDIRECT);
+        TlHelper::write_i32(&mut buf, 203);
+        TlHelper::write_bytes(&mut buf, b"token");
+        TlHelper::write_bytes(&mut buf, &[1; 32]);
+        TlHelper::write_bytes(&mut buf, &[2; 16]);
+        write_hashes(&mut buf, std::slice::from_ref(&hash));
+        assert_eq!(
+            query.parse_answer(&buf).unwrap(),
+            DownloadAnswer::CdnRedirect(CdnRedirect {
+                dc_id: 203,
+                file_token: b"token".to_vec(),
+                encryption_key: vec![1; 32],
+                encryption_iv: vec![2; 16],
+                file_hashes: vec![hash],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_cdn_answers() {
+        let query = DownloadQuery::GetCdnFile {
+            dc_id: 203,
+            file_token: b"token".to_vec(),
+            offset: 0,
+            limit: 4096,
+        };
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_UPLOAD_CDN_FILE_REUPLOAD_NEEDED);
+        TlHelper::write_bytes(&mut buf, b"request");
+        assert_eq!(
+            query.parse_answer(&buf).unwrap(),
+            DownloadAnswer::CdnFileReuploadNeeded(b"request".to_vec())
+        );
+
+        let query = DownloadQuery::GetCdnFileHashes {
+            file_token: b"token".to_vec(),
+            offset: 0,
+        };
+        let mut buf = BytesMut::new();
+        write_hashes(&mut buf, &[]);
+        assert_eq!(
+            query.parse_answer(&buf).unwrap(),
+            DownloadAnswer::CdnFileHashes(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let query = DownloadQuery::GetCdnFileHashes {
+            file_token: Vec::new(),
+            offset: 0,
+        };
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_VECTOR);
+        TlHelper::write_i32(&mut buf, 1000);
+        assert!(matches!(
+            query.parse_answer(&buf),
+            Err(Error::NetworkError(_))
+        ));
+
+        let query = DownloadQuery::GetFile {
+            offset: 0,
+            limit: 4096,
+        };
+        assert!(query
+            .parse_answer(&TL_UPLOAD_CDN_FILE.to_le_bytes())
+            .is_err());
+    }
+}
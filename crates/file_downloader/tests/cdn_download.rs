@@ -0,0 +1,262 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Downloads through a local fake CDN DC.
+
+#![allow(clippy::unwrap_used)]
+
+use rustgram_file_downloader::{
+    decrypt_cdn_part, CdnFileHash, CdnRedirect, DownloadAnswer, DownloadQuery, Error,
+    FileDownloader, FileDownloaderConfig, PartOutcome, CDN_HASH_CHUNK_SIZE,
+};
+use rustgram_file_location::FullRemoteFileLocation;
+
+const CDN_DC_ID: i32 = 203;
+const FILE_TOKEN: &[u8] = b"file-token";
+const REQUEST_TOKEN: &[u8] = b"request-token";
+
+/// A main DC redirecting to a CDN DC serving an encrypted copy of a file.
+struct FakeCdn {
+    file: Vec<u8>,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    /// Whether the file still has to be reuploaded to the CDN
+    need_reupload: bool,
+    /// Whether the redirect carries the hashes of the first chunks
+    hashes_in_redirect: bool,
+    /// Offset of a byte the CDN corrupts
+    corrupt_at: Option<usize>,
+    /// Whether the main DC answers hash requests with no hashes
+    hashes_lost: bool,
+    queries: Vec<DownloadQuery>,
+}
+
+impl FakeCdn {
+    fn new(size: usize) -> Self {
+        Self {
+            file: (0..size).map(|i| (i * 7 % 256) as u8).collect(),
+            key: (0..32).collect(),
+            iv: (100..116).collect(),
+            need_reupload: false,
+            hashes_in_redirect: true,
+            corrupt_at: None,
+            hashes_lost: false,
+            queries: Vec::new(),
+        }
+    }
+
+    fn hashes(&self, offset: i64) -> Vec<CdnFileHash> {
+        if self.hashes_lost {
+            return Vec::new();
+        }
+        // Like the server, return hashes for 1MB starting at `offset`
+        (offset..(offset + 8 * CDN_HASH_CHUNK_SIZE).min(self.file.len() as i64))
+            .step_by(CDN_HASH_CHUNK_SIZE as usize)
+            .map(|pos| {
+                let end = (pos + CDN_HASH_CHUNK_SIZE).min(self.file.len() as i64);
+                CdnFileHash::compute(pos, &self.file[pos as usize..end as usize])
+            })
+            .collect()
+    }
+
+    fn answer(&mut self, query: DownloadQuery) -> DownloadAnswer {
+        self.queries.push(query.clone());
+        match query {
+            DownloadQuery::GetFile { .. } => DownloadAnswer::CdnRedirect(CdnRedirect {
+                dc_id: CDN_DC_ID,
+                file_token: FILE_TOKEN.to_vec(),
+                encryption_key: self.key.clone(),
+                encryption_iv: self.iv.clone(),
+                file_hashes: if self.hashes_in_redirect {
+                    self.hashes(0)
+                } else {
+                    Vec::new()
+                },
+            }),
+            DownloadQuery::GetCdnFile {
+                dc_id,
+                file_token,
+                offset,
+                limit,
+            } => {
+                assert_eq!(dc_id, CDN_DC_ID);
+                assert_eq!(file_token, FILE_TOKEN);
+                if self.need_reupload {
+                    return DownloadAnswer::CdnFileReuploadNeeded(REQUEST_TOKEN.to_vec());
+                }
+                let start = offset as usize;
+                let end = (start + limit as usize).min(self.file.len());
+                let mut data = self.file[start..end].to_vec();
+                if let Some(pos) = self.corrupt_at.filter(|pos| (start..end).contains(pos)) {
+                    data[pos - start] ^= 0xff;
+                }
+                // Encryption is the same operation as decryption in CTR mode
+                decrypt_cdn_part(&self.key, &self.iv, offset, &mut data).unwrap();
+                DownloadAnswer::CdnFile(data)
+            }
+            DownloadQuery::GetCdnFileHashes { file_token, offset } => {
+                assert_eq!(file_token, FILE_TOKEN);
+                DownloadAnswer::CdnFileHashes(self.hashes(offset))
+            }
+            DownloadQuery::ReuploadCdnFile {
+                file_token,
+                request_token,
+            } => {
+                assert_eq!(file_token, FILE_TOKEN);
+                assert_eq!(request_token, REQUEST_TOKEN);
+                self.need_reupload = false;
+                DownloadAnswer::CdnFileHashes(self.hashes(0))
+            }
+        }
+    }
+}
+
+/// Downloads the whole file, sending each query to the fake DCs.
+fn download(cdn: &mut FakeCdn) -> Result<Vec<u8>, Error> {
+    let config =
+        FileDownloaderConfig::new(FullRemoteFileLocation::common(1, 2), cdn.file.len() as i64)
+            .with_size_final(true);
+    let mut downloader = FileDownloader::new(config)?;
+    downloader.start()?;
+
+    let mut file = vec![0u8; cdn.file.len()];
+    loop {
+        let part = downloader.get_next_part()?;
+        if part.is_empty() {
+            break;
+        }
+        loop {
+            let query = downloader.query_for_part(&part);
+            let answer = cdn.answer(query);
+            match downloader.on_part_answer(part, answer)? {
+                PartOutcome::Ready(data) => {
+                    let start = part.offset as usize;
+                    file[start..start + data.len()].copy_from_slice(&data);
+                    break;
+                }
+                PartOutcome::Retry => {}
+                PartOutcome::Restart => break,
+            }
+        }
+    }
+    assert_eq!(downloader.downloaded_bytes(), cdn.file.len() as i64);
+    Ok(file)
+}
+
+#[test]
+fn test_cdn_download() {
+    let mut cdn = FakeCdn::new(1_500_000);
+    let file = download(&mut cdn).unwrap();
+    assert_eq!(file, cdn.file);
+
+    assert!(matches!(cdn.queries[0], DownloadQuery::GetFile { .. }));
+    assert!(cdn.queries[1..].iter().any(|query| query.is_cdn()));
+    // The redirect carried hashes for the first 1MB only
+    assert!(cdn
+        .queries
+        .iter()
+        .any(|query| matches!(query, DownloadQuery::GetCdnFileHashes { .. })));
+}
+
+#[test]
+fn test_cdn_download_without_hashes() {
+    let mut cdn = FakeCdn::new(300_000);
+    cdn.hashes_in_redirect = false;
+    let file = download(&mut cdn).unwrap();
+    assert_eq!(file, cdn.file);
+}
+
+#[test]
+fn test_cdn_download_reupload() {
+    let mut cdn = FakeCdn::new(300_000);
+    cdn.need_reupload = true;
+    let file = download(&mut cdn).unwrap();
+    assert_eq!(file, cdn.file);
+
+    assert!(cdn
+        .queries
+        .iter()
+        .any(|query| matches!(query, DownloadQuery::ReuploadCdnFile { .. })));
+}
+
+#[test]
+fn test_cdn_download_hash_mismatch() {
+    let mut cdn = FakeCdn::new(300_000);
+    cdn.corrupt_at = Some(200_000);
+    let result = download(&mut cdn);
+    assert_eq!(
+        result,
+        Err(Error::CdnHashMismatch {
+            offset: CDN_HASH_CHUNK_SIZE
+        })
+    );
+}
+
+#[test]
+fn test_cdn_download_without_any_hashes() {
+    let mut cdn = FakeCdn::new(300_000);
+    cdn.hashes_lost = true;
+    let result = download(&mut cdn);
+    assert!(matches!(result, Err(Error::CdnError(_))));
+    let hash_queries = cdn
+        .queries
+        .iter()
+        .filter(|query| matches!(query, DownloadQuery::GetCdnFileHashes { .. }))
+        .count();
+    assert_eq!(hash_queries, 1);
+}
+
+#[test]
+fn test_cdn_redirect_splits_into_hash_chunks() {
+    let mut cdn = FakeCdn::new(300_000);
+    let config = FileDownloaderConfig::new(FullRemoteFileLocation::common(1, 2), 300_000);
+    let mut downloader = FileDownloader::new(config).unwrap();
+    downloader.start().unwrap();
+    assert_eq!(downloader.part_size(), 32 * 1024);
+
+    let part = downloader.get_next_part().unwrap();
+    let answer = cdn.answer(downloader.query_for_part(&part));
+    assert_eq!(
+        downloader.on_part_answer(part, answer).unwrap(),
+        PartOutcome::Restart
+    );
+    assert_eq!(downloader.part_size(), CDN_HASH_CHUNK_SIZE as usize);
+    let part = downloader.get_next_part().unwrap();
+    assert_eq!((part.offset, part.size), (0, CDN_HASH_CHUNK_SIZE as usize));
+    assert!(downloader.query_for_part(&part).is_cdn());
+}
+
+#[test]
+fn test_cdn_file_token_invalid() {
+    let mut cdn = FakeCdn::new(300_000);
+    let config = FileDownloaderConfig::new(FullRemoteFileLocation::common(1, 2), 300_000);
+    let mut downloader = FileDownloader::new(config).unwrap();
+    downloader.start().unwrap();
+
+    let part = downloader.get_next_part().unwrap();
+    let answer = cdn.answer(downloader.query_for_part(&part));
+    assert_eq!(
+        downloader.on_part_answer(part, answer).unwrap(),
+        PartOutcome::Restart
+    );
+    let part = downloader.get_next_part().unwrap();
+    assert!(downloader.query_for_part(&part).is_cdn());
+
+    assert!(downloader.on_part_error(&part, "FILE_TOKEN_INVALID"));
+    assert!(!downloader.use_cdn());
+    assert!(matches!(
+        downloader.query_for_part(&part),
+        DownloadQuery::GetFile { .. }
+    ));
+}
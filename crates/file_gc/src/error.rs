@@ -0,0 +1,23 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Errors for the storage garbage collector.
+
+use file_db::FileDbError;
+use thiserror::Error;
+
+/// Errors that can occur while scanning or cleaning the files directory.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// I/O error while reading the files directory
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Error while reading the file database
+    #[error("file database error: {0}")]
+    FileDb(#[from] FileDbError),
+}
+
+/// Result type for storage garbage collector operations.
+pub type Result<T> = std::result::Result<T, Error>;
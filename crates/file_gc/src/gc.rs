@@ -0,0 +1,300 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Selection and deletion of files.
+
+use std::fs;
+
+use rustgram_file_gc_parameters::FileGcParameters;
+use rustgram_file_stats::{FileStats, FullFileInfo};
+use rustgram_file_type::FileType;
+
+/// Result of a garbage collection.
+///
+/// Corresponds to TDLib `FileGcResult`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileGcResult {
+    /// Statistics of the files left on disk
+    pub kept: FileStats,
+    /// Statistics of the deleted files, i.e. the freed space, listing every
+    /// deleted file
+    pub removed: FileStats,
+}
+
+/// Returns `true` if the parameters allow deleting the file at all.
+///
+/// Without explicit file types, wallpapers, notification sounds and
+/// passport files are never deleted, because they can't be downloaded
+/// again on demand.
+fn is_collectable(parameters: &FileGcParameters, info: &FullFileInfo) -> bool {
+    let main_type = info.file_type.main_type();
+    if parameters.is_all_file_types() {
+        if matches!(
+            main_type,
+            FileType::Background | FileType::Ringtone | FileType::SecureEncrypted
+        ) {
+            return false;
+        }
+    } else if !parameters
+        .file_types()
+        .iter()
+        .any(|file_type| file_type.main_type() == main_type)
+    {
+        return false;
+    }
+
+    if !parameters.is_all_dialogs()
+        && !parameters
+            .owner_dialog_ids()
+            .contains(&info.owner_dialog_id)
+    {
+        return false;
+    }
+    !parameters
+        .exclude_owner_dialog_ids()
+        .contains(&info.owner_dialog_id)
+}
+
+/// Deletes a file, keeping it in the statistics if it can't be deleted.
+///
+/// Returns `true` if the file is gone.
+fn remove_file(info: FullFileInfo, kept: &mut FileStats, removed: &mut FileStats) -> bool {
+    match fs::remove_file(&info.path) {
+        Ok(()) => {
+            removed.add(info);
+            true
+        }
+        // Already deleted by someone else; it freed nothing now
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => {
+            tracing::warn!("Failed to delete {}: {}", info.path, e);
+            kept.add(info);
+            false
+        }
+    }
+}
+
+/// Deletes files according to the parameters.
+///
+/// Files accessed longer than `max_time_from_last_access` ago are deleted
+/// first. Then the least recently accessed files are deleted until all the
+/// remaining files, including those which can't be deleted, fit into
+/// `max_files_size` and `max_file_count`. Files modified within
+/// `immunity_delay` are never deleted.
+///
+/// # Arguments
+///
+/// * `parameters` - The garbage collection parameters
+/// * `files` - All files in the files directory
+/// * `now` - The current Unix time in seconds
+#[must_use]
+pub fn run_gc(parameters: &FileGcParameters, files: Vec<FullFileInfo>, now: u64) -> FileGcResult {
+    let mut kept = FileStats::new(false, true);
+    let mut removed = FileStats::new(true, true);
+
+    let immunity_delay = u64::try_from(parameters.immunity_delay()).unwrap_or(0);
+    let max_time_from_last_access =
+        u64::try_from(parameters.max_time_from_last_access()).unwrap_or(0);
+
+    // As in TDLib, the limits apply to all files, not only the collectable ones
+    let mut total_size: i64 = files.iter().map(|info| info.size).sum();
+    let mut total_count = i64::try_from(files.len()).unwrap_or(i64::MAX);
+
+    let mut candidates = Vec::new();
+    for info in files {
+        if !is_collectable(parameters, &info)
+            || (!parameters.is_immunity_disabled()
+                && info.mtime_sec().saturating_add(immunity_delay) > now)
+        {
+            kept.add(info);
+            continue;
+        }
+        if !parameters.is_time_limit_disabled()
+            && info.atime_sec().saturating_add(max_time_from_last_access) < now
+        {
+            let size = info.size;
+            if remove_file(info, &mut kept, &mut removed) {
+                total_size -= size;
+                total_count -= 1;
+            }
+            continue;
+        }
+        candidates.push(info);
+    }
+
+    candidates.sort_by_key(|info| info.atime_nsec);
+    for info in candidates {
+        let is_too_big =
+            !parameters.is_size_limit_disabled() && total_size > parameters.max_files_size();
+        let is_too_many = !parameters.is_count_limit_disabled()
+            && total_count > i64::from(parameters.max_file_count());
+        if is_too_big || is_too_many {
+            let size = info.size;
+            if remove_file(info, &mut kept, &mut removed) {
+                total_size -= size;
+                total_count -= 1;
+            }
+        } else {
+            kept.add(info);
+        }
+    }
+
+    kept.apply_dialog_limit(parameters.dialog_limit());
+    FileGcResult { kept, removed }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use rustgram_dialog_id::DialogId;
+    use std::path::Path;
+    use tempfile::{tempdir, TempDir};
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 86400;
+
+    /// Creates a fake file last accessed and modified `age` seconds ago.
+    fn fake_file(
+        dir: &TempDir,
+        name: &str,
+        file_type: FileType,
+        owner: i64,
+        size: i64,
+        age: u64,
+    ) -> FullFileInfo {
+        let path = dir.path().join(name);
+        fs::write(&path, vec![0u8; size as usize]).unwrap();
+        let time_nsec = (NOW - age) * 1_000_000_000;
+        FullFileInfo::new(
+            file_type,
+            path.to_string_lossy().into_owned(),
+            DialogId::new(owner),
+            size,
+            time_nsec,
+            time_nsec,
+        )
+    }
+
+    fn removed_paths(files: &[FullFileInfo]) -> Vec<String> {
+        files
+            .iter()
+            .filter(|info| !Path::new(&info.path).exists())
+            .map(|info| info.file_name().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_run_gc_ttl() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "old", FileType::Photo, 1, 10, 10 * DAY),
+            fake_file(&dir, "new", FileType::Photo, 1, 20, DAY),
+        ];
+        let parameters = FileGcParameters::new().with_max_time_from_last_access(2 * DAY as i32);
+
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["old"]);
+        assert_eq!(result.removed.get_total_nontemp_stat().size, 10);
+        assert_eq!(result.kept.get_total_nontemp_stat().size, 20);
+    }
+
+    #[test]
+    fn test_run_gc_max_size_deletes_least_recently_accessed() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "a", FileType::Video, 1, 100, 3 * DAY),
+            fake_file(&dir, "b", FileType::Video, 1, 100, 5 * DAY),
+            fake_file(&dir, "c", FileType::Video, 1, 100, DAY),
+        ];
+        let parameters = FileGcParameters::new().with_max_files_size(150);
+
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["a", "b"]);
+        assert_eq!(result.kept.get_total_nontemp_stat().size, 100);
+    }
+
+    #[test]
+    fn test_run_gc_max_count() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "a", FileType::Document, 1, 1, 2 * DAY),
+            fake_file(&dir, "b", FileType::Document, 1, 1, DAY),
+            fake_file(&dir, "c", FileType::Document, 1, 1, 3 * DAY),
+        ];
+        let parameters = FileGcParameters::new().with_max_file_count(1);
+
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["a", "c"]);
+        assert_eq!(result.removed.get_total_nontemp_stat().cnt, 2);
+    }
+
+    #[test]
+    fn test_run_gc_immunity_delay() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "old", FileType::Photo, 1, 10, 10 * DAY),
+            fake_file(&dir, "fresh", FileType::Photo, 1, 10, 60),
+        ];
+        let parameters = FileGcParameters::new()
+            .with_max_files_size(0)
+            .with_immunity_delay(3600);
+
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["old"]);
+        assert_eq!(result.kept.get_total_nontemp_stat().cnt, 1);
+    }
+
+    #[test]
+    fn test_run_gc_limits_count_immune_files() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "a", FileType::Video, 1, 100, 3 * DAY),
+            fake_file(&dir, "b", FileType::Video, 1, 100, 2 * DAY),
+            fake_file(&dir, "fresh", FileType::Video, 1, 100, 60),
+            fake_file(&dir, "wallpaper", FileType::Background, 1, 100, 5 * DAY),
+        ];
+        let parameters = FileGcParameters::new()
+            .with_max_files_size(250)
+            .with_max_file_count(3)
+            .with_immunity_delay(3600);
+
+        // The fresh file and the wallpaper can't be deleted, but still take
+        // up space and count against the limits
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["a", "b"]);
+        assert_eq!(result.kept.get_total_nontemp_stat().size, 200);
+        assert_eq!(result.removed.get_all_files().len(), 2);
+    }
+
+    #[test]
+    fn test_run_gc_type_and_dialog_filters() {
+        let dir = tempdir().unwrap();
+        let files = vec![
+            fake_file(&dir, "photo1", FileType::Photo, 1, 10, DAY),
+            fake_file(&dir, "photo2", FileType::Photo, 2, 10, DAY),
+            fake_file(&dir, "photo3", FileType::Photo, 3, 10, DAY),
+            fake_file(&dir, "video1", FileType::Video, 1, 10, DAY),
+            fake_file(&dir, "wallpaper", FileType::Background, 1, 10, DAY),
+        ];
+
+        let parameters = FileGcParameters::new()
+            .with_max_files_size(0)
+            .with_file_types(vec![FileType::Photo])
+            .with_owner_dialog_ids(vec![DialogId::new(1), DialogId::new(2)])
+            .with_exclude_owner_dialog_ids(vec![DialogId::new(2)]);
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(removed_paths(&files), vec!["photo1"]);
+        assert_eq!(result.removed.get_total_nontemp_stat().cnt, 1);
+
+        // Wallpapers survive unless asked for explicitly
+        let parameters = FileGcParameters::new().with_max_files_size(0);
+        let result = run_gc(&parameters, files.clone(), NOW);
+        assert_eq!(
+            removed_paths(&files),
+            vec!["photo1", "photo2", "photo3", "video1"]
+        );
+        assert_eq!(result.kept.get_total_nontemp_stat().size, 10);
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! # File GC
+//!
+//! Storage garbage collector for downloaded files.
+//!
+//! ## TDLib Correspondence
+//!
+//! This module implements the TDLib `StorageManager`, `FileStatsWorker` and
+//! `FileGcWorker` classes from `td/telegram/files/`.
+//!
+//! ## Overview
+//!
+//! - [`scan_files`] lists the files directory and joins every file with its
+//!   owner dialog from the file database
+//! - [`scan_files_fast`] only sums sizes and counts, for cheap checks
+//! - [`run_gc`] deletes files according to [`FileGcParameters`]
+//! - [`FileGcService`] ties them together and runs automatic garbage
+//!   collection periodically
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! use rustgram_file_gc::FileGcService;
+//! use rustgram_file_gc_parameters::FileGcParameters;
+//!
+//! # async fn example() {
+//! let service = FileGcService::new("/path/to/files")
+//!     .with_parameters(FileGcParameters::new().with_max_files_size(1 << 30));
+//! tokio::spawn(service.run_periodically());
+//! # }
+//! ```
+
+#![warn(missing_docs)]
+#![warn(clippy::all)]
+
+mod error;
+mod gc;
+mod scan;
+mod service;
+
+pub use error::{Error, Result};
+pub use gc::{run_gc, FileGcResult};
+pub use rustgram_file_gc_parameters::FileGcParameters;
+pub use scan::{local_file_key, scan_files, scan_files_fast, LocalFileRecord};
+pub use service::{FileGcService, DEFAULT_GC_INTERVAL};
@@ -0,0 +1,234 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Scanning of the files directory.
+//!
+//! Files are stored in one subdirectory per file type, named after
+//! [`FileType::unique_dir_name`]. The owner dialog of a file is only known
+//! from the file database, where [`LocalFileRecord`]s are stored under
+//! [`local_file_key`].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use file_db::FileDb;
+use rustgram_dialog_id::DialogId;
+use rustgram_file_stats::{FileStatsFast, FullFileInfo};
+use rustgram_file_type::FileType;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Information about a local file stored in the file database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalFileRecord {
+    /// The type of the file
+    pub file_type: FileType,
+    /// The dialog the file was downloaded for
+    pub owner_dialog_id: DialogId,
+}
+
+impl LocalFileRecord {
+    /// Creates a new record.
+    #[must_use]
+    pub const fn new(file_type: FileType, owner_dialog_id: DialogId) -> Self {
+        Self {
+            file_type,
+            owner_dialog_id,
+        }
+    }
+
+    /// Serializes the record for the file database.
+    #[must_use]
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::from(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Deserializes a record read from the file database.
+    ///
+    /// Returns `None` for data that isn't a local file record.
+    #[must_use]
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+/// Returns the file database key of a local file.
+#[must_use]
+pub fn local_file_key(path: &Path) -> String {
+    format!("local#{}", path.display())
+}
+
+/// Lists the files in the type subdirectories of `files_dir`.
+///
+/// Files outside of the known subdirectories don't belong to the client
+/// and are never returned.
+fn list_files(files_dir: &Path) -> Result<Vec<(FileType, PathBuf, fs::Metadata)>> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(files_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Some(file_type) = entry.file_name().to_str().and_then(FileType::from_dir_name) else {
+            continue;
+        };
+        if entry.file_type()?.is_dir() {
+            walk_dir(file_type, &entry.path(), &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+fn walk_dir(
+    file_type: FileType,
+    dir: &Path,
+    files: &mut Vec<(FileType, PathBuf, fs::Metadata)>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            walk_dir(file_type, &entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push((file_type, entry.path(), metadata));
+        }
+    }
+    Ok(())
+}
+
+fn time_nsec(time: std::io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| {
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Scans the files directory, joining every file with its record in the
+/// file database.
+///
+/// Files without a record are attributed to no dialog.
+///
+/// # Errors
+///
+/// Returns an error if the directory can't be read or the database fails.
+pub async fn scan_files(
+    files_dir: &Path,
+    file_db: Option<&dyn FileDb>,
+) -> Result<Vec<FullFileInfo>> {
+    let mut infos = Vec::new();
+    for (dir_file_type, path, metadata) in list_files(files_dir)? {
+        let mut file_type = dir_file_type;
+        let mut owner_dialog_id = DialogId::default();
+        if let Some(file_db) = file_db {
+            match file_db.get_file_data(&local_file_key(&path)).await {
+                Ok(data) => {
+                    if let Some(record) = LocalFileRecord::from_bytes(&data) {
+                        file_type = record.file_type;
+                        owner_dialog_id = record.owner_dialog_id;
+                    }
+                }
+                Err(e) if e.is_not_found() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        infos.push(FullFileInfo::new(
+            file_type,
+            path.to_string_lossy().into_owned(),
+            owner_dialog_id,
+            i64::try_from(metadata.len()).unwrap_or(i64::MAX),
+            time_nsec(metadata.accessed()),
+            time_nsec(metadata.modified()),
+        ));
+    }
+    Ok(infos)
+}
+
+/// Computes the total size and count of the files without touching the
+/// file database.
+///
+/// # Errors
+///
+/// Returns an error if the directory can't be read.
+pub fn scan_files_fast(files_dir: &Path) -> Result<FileStatsFast> {
+    let mut stats = FileStatsFast::empty();
+    for (_, _, metadata) in list_files(files_dir)? {
+        stats.size = stats
+            .size
+            .saturating_add(i64::try_from(metadata.len()).unwrap_or(i64::MAX));
+        stats.count = stats.count.saturating_add(1);
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use file_db::InMemoryFileDb;
+    use tempfile::tempdir;
+
+    fn write_file(dir: &Path, name: &str, size: usize) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, vec![0u8; size]).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_scan_files_joins_file_db() {
+        let dir = tempdir().unwrap();
+        let photo = write_file(dir.path(), "photos/1.jpg", 100);
+        write_file(dir.path(), "documents/sub/2.pdf", 200);
+        write_file(dir.path(), "unknown/3.bin", 300);
+        write_file(dir.path(), "4.bin", 400);
+
+        let db = InMemoryFileDb::new();
+        let id = db.get_next_file_db_id().await.unwrap();
+        let record = LocalFileRecord::new(FileType::Photo, DialogId::new(42));
+        db.set_file_data(id, &local_file_key(&photo), record.to_bytes())
+            .await
+            .unwrap();
+
+        let mut infos = scan_files(dir.path(), Some(&db)).await.unwrap();
+        infos.sort_by_key(|info| info.size);
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].file_type, FileType::Photo);
+        assert_eq!(infos[0].owner_dialog_id, DialogId::new(42));
+        assert_eq!(infos[1].file_type, FileType::Document);
+        assert_eq!(infos[1].owner_dialog_id, DialogId::default());
+        assert!(infos[1].mtime_nsec > 0);
+    }
+
+    #[test]
+    fn test_scan_files_fast() {
+        let dir = tempdir().unwrap();
+        write_file(dir.path(), "photos/1.jpg", 100);
+        write_file(dir.path(), "videos/2.mp4", 200);
+        write_file(dir.path(), "unknown/3.bin", 300);
+
+        let stats = scan_files_fast(dir.path()).unwrap();
+        assert_eq!(stats.size, 300);
+        assert_eq!(stats.count, 2);
+
+        let missing = scan_files_fast(&dir.path().join("missing")).unwrap();
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_local_file_record_bytes() {
+        let record = LocalFileRecord::new(FileType::Video, DialogId::new(-100));
+        assert_eq!(
+            LocalFileRecord::from_bytes(&record.to_bytes()),
+            Some(record)
+        );
+        assert_eq!(LocalFileRecord::from_bytes(b"garbage"), None);
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Storage garbage collection service.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use file_db::FileDb;
+use rustgram_file_gc_parameters::FileGcParameters;
+use rustgram_file_stats::{FileStats, FileStatsFast};
+
+use crate::gc::{run_gc, FileGcResult};
+use crate::scan::{local_file_key, scan_files, scan_files_fast};
+use crate::Result;
+
+/// Default interval between automatic garbage collections.
+pub const DEFAULT_GC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Storage garbage collector of the files directory.
+///
+/// Corresponds to TDLib `StorageManager`.
+///
+/// # Example
+///
+/// ```no_run
+/// use rustgram_file_gc::FileGcService;
+/// use rustgram_file_gc_parameters::FileGcParameters;
+///
+/// # async fn example() -> Result<(), rustgram_file_gc::Error> {
+/// let service = FileGcService::new("/path/to/files");
+/// let params = FileGcParameters::new().with_max_files_size(1 << 30);
+/// let result = service.optimize_storage(&params).await?;
+/// println!("Freed {} bytes", result.removed.get_total_nontemp_stat().size);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FileGcService {
+    /// Directory with the downloaded files
+    files_dir: PathBuf,
+    /// File database with the owners of the files
+    file_db: Option<Arc<dyn FileDb>>,
+    /// Parameters of automatic garbage collection
+    parameters: FileGcParameters,
+    /// Interval between automatic garbage collections
+    interval: Duration,
+}
+
+impl std::fmt::Debug for FileGcService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileGcService")
+            .field("files_dir", &self.files_dir)
+            .field("has_file_db", &self.file_db.is_some())
+            .field("parameters", &self.parameters)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl FileGcService {
+    /// Creates a service for the given files directory.
+    ///
+    /// Automatic garbage collection deletes nothing until parameters are
+    /// set with [`FileGcService::with_parameters`].
+    #[must_use]
+    pub fn new(files_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            files_dir: files_dir.into(),
+            file_db: None,
+            parameters: FileGcParameters::new(),
+            interval: DEFAULT_GC_INTERVAL,
+        }
+    }
+
+    /// Sets the file database to look up the owners of files in.
+    #[must_use]
+    pub fn with_file_db(mut self, file_db: Arc<dyn FileDb>) -> Self {
+        self.file_db = Some(file_db);
+        self
+    }
+
+    /// Sets the parameters of automatic garbage collection.
+    #[must_use]
+    pub fn with_parameters(mut self, parameters: FileGcParameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Sets the interval between automatic garbage collections.
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns the files directory.
+    #[must_use]
+    pub fn files_dir(&self) -> &Path {
+        &self.files_dir
+    }
+
+    /// Returns the parameters of automatic garbage collection.
+    #[must_use]
+    pub fn parameters(&self) -> &FileGcParameters {
+        &self.parameters
+    }
+
+    /// Returns statistics of all files, split by owner dialog.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files can't be scanned.
+    pub async fn get_storage_statistics(&self, dialog_limit: i32) -> Result<FileStats> {
+        let mut stats = FileStats::new(false, true);
+        for info in scan_files(&self.files_dir, self.file_db.as_deref()).await? {
+            stats.add(info);
+        }
+        stats.apply_dialog_limit(dialog_limit);
+        Ok(stats)
+    }
+
+    /// Returns the total size and count of the files.
+    ///
+    /// Unlike [`FileGcService::get_storage_statistics`], this doesn't read
+    /// the file database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files directory can't be read.
+    pub fn get_storage_statistics_fast(&self) -> Result<FileStatsFast> {
+        scan_files_fast(&self.files_dir)
+    }
+
+    /// Deletes files according to the parameters.
+    ///
+    /// The records of the deleted files are removed from the file database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files can't be scanned.
+    pub async fn optimize_storage(&self, parameters: &FileGcParameters) -> Result<FileGcResult> {
+        let files = scan_files(&self.files_dir, self.file_db.as_deref()).await?;
+        let result = run_gc(parameters, files, unix_time());
+        if let Some(file_db) = &self.file_db {
+            for info in &result.removed.all_files {
+                let key = local_file_key(Path::new(&info.path));
+                if let Err(e) = file_db.clear_file_data(&key).await {
+                    // The file is gone anyway
+                    tracing::warn!("Failed to forget deleted file {}: {}", info.path, e);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns `true` if automatic garbage collection may delete anything,
+    /// judging by the fast statistics.
+    fn need_gc(&self, fast: &FileStatsFast) -> bool {
+        let parameters = &self.parameters;
+        // Access times are only known after a full scan
+        !parameters.is_time_limit_disabled()
+            || (!parameters.is_size_limit_disabled() && fast.size > parameters.max_files_size())
+            || (!parameters.is_count_limit_disabled() && fast.count > parameters.max_file_count())
+    }
+
+    /// Runs automatic garbage collection once.
+    ///
+    /// The full scan is skipped if the fast statistics show that the files
+    /// fit into the limits. Returns `None` if nothing needed to be done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files can't be scanned.
+    pub async fn run_automatic_gc(&self) -> Result<Option<FileGcResult>> {
+        let fast = self.get_storage_statistics_fast()?;
+        if !self.need_gc(&fast) {
+            return Ok(None);
+        }
+        self.optimize_storage(&self.parameters).await.map(Some)
+    }
+
+    /// Runs automatic garbage collection every interval, forever.
+    ///
+    /// Spawn it as a task and abort the task to stop.
+    pub async fn run_periodically(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match self.run_automatic_gc().await {
+                Ok(Some(result)) => {
+                    let removed = result.removed.get_total_nontemp_stat();
+                    tracing::info!(
+                        "Storage GC deleted {} files of {} bytes",
+                        removed.cnt,
+                        removed.size
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Storage GC failed: {}", e),
+            }
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::scan::{local_file_key, LocalFileRecord};
+    use file_db::InMemoryFileDb;
+    use rustgram_dialog_id::DialogId;
+    use rustgram_file_type::FileType;
+    use std::fs::{self, File, FileTimes};
+    use tempfile::tempdir;
+
+    /// Writes a fake file last accessed `age` ago.
+    fn write_file(dir: &Path, name: &str, size: usize, age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, vec![0u8; size]).unwrap();
+        let time = SystemTime::now() - age;
+        let times = FileTimes::new().set_accessed(time).set_modified(time);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+        path
+    }
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    #[tokio::test]
+    async fn test_optimize_storage() {
+        let dir = tempdir().unwrap();
+        let old = write_file(dir.path(), "photos/old.jpg", 100, 10 * DAY);
+        let new = write_file(dir.path(), "photos/new.jpg", 200, DAY);
+        let other = write_file(dir.path(), "videos/other.mp4", 300, 10 * DAY);
+
+        let db = Arc::new(InMemoryFileDb::new());
+        for (path, owner) in [(&old, 1), (&new, 1), (&other, 2)] {
+            let id = db.get_next_file_db_id().await.unwrap();
+            let record = LocalFileRecord::new(FileType::Photo, DialogId::new(owner));
+            db.set_file_data(id, &local_file_key(path), record.to_bytes())
+                .await
+                .unwrap();
+        }
+
+        let service = FileGcService::new(dir.path()).with_file_db(db.clone());
+        let stats = service.get_storage_statistics(0).await.unwrap();
+        assert_eq!(stats.get_total_nontemp_stat().size, 600);
+        assert_eq!(stats.get_dialog_ids().len(), 2);
+
+        let params = FileGcParameters::new()
+            .with_max_time_from_last_access(5 * 86400)
+            .with_owner_dialog_ids(vec![DialogId::new(1)]);
+        let result = service.optimize_storage(&params).await.unwrap();
+        assert_eq!(result.removed.get_total_nontemp_stat().size, 100);
+        assert_eq!(result.kept.get_total_nontemp_stat().size, 500);
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert!(other.exists());
+
+        // The deleted file is forgotten by the file database
+        assert!(db.get_file_data(&local_file_key(&old)).await.is_err());
+        assert!(db.get_file_data(&local_file_key(&new)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_automatic_gc() {
+        let dir = tempdir().unwrap();
+        let old = write_file(dir.path(), "documents/old.pdf", 100, 2 * DAY);
+        let new = write_file(dir.path(), "documents/new.pdf", 100, DAY);
+
+        let service = FileGcService::new(dir.path())
+            .with_parameters(FileGcParameters::new().with_max_files_size(150));
+        let result = service.run_automatic_gc().await.unwrap().unwrap();
+        assert_eq!(result.removed.get_total_nontemp_stat().size, 100);
+        assert!(!old.exists());
+        assert!(new.exists());
+
+        // The files fit now, so the full scan is skipped
+        assert!(service.run_automatic_gc().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_automatic_gc_disabled() {
+        let dir = tempdir().unwrap();
+        let file = write_file(dir.path(), "documents/file.pdf", 100, 100 * DAY);
+
+        let service = FileGcService::new(dir.path());
+        assert!(service.run_automatic_gc().await.unwrap().is_none());
+        assert!(file.exists());
+    }
+}
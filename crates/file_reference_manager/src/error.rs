@@ -0,0 +1,27 @@
+//! Errors of file reference repair.
+
+use rustgram_file_id::FileId;
+use thiserror::Error;
+
+/// Result type for file reference repair.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can occur while repairing a file reference.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Error {
+    /// The file has no known sources to re-fetch.
+    #[error("file {0} has no file sources")]
+    NoFileSources(FileId),
+
+    /// None of the file sources could be re-fetched.
+    #[error("failed to repair file reference of file {0}: {1}")]
+    RepairFailed(FileId, String),
+
+    /// The repair was dropped before it finished.
+    #[error("repair of file reference of file {0} was cancelled")]
+    RepairCancelled(FileId),
+
+    /// The query failed.
+    #[error("query failed: {0}")]
+    Query(String),
+}
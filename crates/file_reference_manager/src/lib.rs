@@ -54,8 +54,22 @@
 //! - Stories and story albums
 //! - Bot media previews
 //! - And more...
-
-pub use types::{StoryAlbumFullId, StoryFullId};
+//!
+//! ## File Reference Repair
+//!
+//! Queries using an expired file reference fail with `FILE_REFERENCE_*`
+//! errors. [`FileReferenceManager::repair_file_reference`] re-fetches the
+//! sources of the file through a [`FileSourceReloader`] to get a fresh
+//! reference, and [`FileReferenceManager::send_with_file_reference_repair`]
+//! sends a query again after the repair. [`NetworkFileSourceReloader`]
+//! re-fetches the sources from the server; the owners of the re-fetched
+//! objects record the fresh references with
+//! [`FileReferenceManager::set_file_reference`].
+
+pub use error::{Error, Result};
+pub use network::{FileSourceResultHandler, NetworkFileSourceReloader};
+pub use repair::{get_file_reference_error_pos, is_file_reference_error, FileSourceReloader};
+pub use types::{FileSource, StoryAlbumFullId, StoryFullId};
 
 use rustgram_background_id::BackgroundId;
 use rustgram_dialog_id::DialogId;
@@ -63,12 +77,19 @@ use rustgram_file_id::FileId;
 use rustgram_file_source_id::FileSourceId;
 use rustgram_message_full_id::MessageFullId;
 use rustgram_quick_reply_message_full_id::QuickReplyMessageFullId;
-use rustgram_types::{ChannelId, ChatId, MessageId, UserId};
+use rustgram_types::{ChannelId, ChatId, UserId};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use tokio::sync::oneshot;
 
+mod error;
+mod network;
+mod repair;
 mod types;
 
+/// Senders of the result of a running repair to the merged repairs.
+type RepairWaiters = Vec<oneshot::Sender<Result<()>>>;
+
 /// File reference manager.
 ///
 /// Tracks file sources and creates source IDs for different file types.
@@ -87,6 +108,15 @@ pub struct FileReferenceManager {
 
     /// Counter for generating unique source IDs.
     source_id_counter: Arc<RwLock<i32>>,
+
+    /// Objects owning files, by their source IDs.
+    file_sources: Arc<RwLock<HashMap<FileSourceId, FileSource>>>,
+
+    /// Waiters for the results of running repairs.
+    repairs: Arc<Mutex<HashMap<FileId, RepairWaiters>>>,
+
+    /// Latest known file references, by file.
+    file_references: Arc<Mutex<HashMap<FileId, Vec<u8>>>>,
 }
 
 impl Default for FileReferenceManager {
@@ -109,6 +139,9 @@ impl FileReferenceManager {
         Self {
             sources: Arc::new(RwLock::new(HashMap::new())),
             source_id_counter: Arc::new(RwLock::new(1i32)),
+            file_sources: Arc::new(RwLock::new(HashMap::new())),
+            repairs: Arc::new(Mutex::new(HashMap::new())),
+            file_references: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -118,8 +151,7 @@ impl FileReferenceManager {
     ///
     /// * `message_full_id` - The full message identifier
     pub fn create_message_file_source(&mut self, message_full_id: MessageFullId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::Message(message_full_id))
     }
 
     /// Create a file source ID for a user photo.
@@ -133,8 +165,7 @@ impl FileReferenceManager {
         user_id: UserId,
         photo_id: i64,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::UserPhoto { user_id, photo_id })
     }
 
     /// Create a file source ID for a web page file.
@@ -143,14 +174,12 @@ impl FileReferenceManager {
     ///
     /// * `url` - The web page URL
     pub fn create_web_page_file_source(&mut self, url: String) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::WebPage(url))
     }
 
     /// Create a file source ID for saved animations.
     pub fn create_saved_animations_file_source(&mut self) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::SavedAnimations)
     }
 
     /// Create a file source ID for recent stickers.
@@ -159,14 +188,12 @@ impl FileReferenceManager {
     ///
     /// * `is_attached` - Whether these are attached stickers
     pub fn create_recent_stickers_file_source(&mut self, is_attached: bool) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::RecentStickers { is_attached })
     }
 
     /// Create a file source ID for favorite stickers.
     pub fn create_favorite_stickers_file_source(&mut self) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::FavoriteStickers)
     }
 
     /// Create a file source ID for a background.
@@ -180,8 +207,10 @@ impl FileReferenceManager {
         background_id: BackgroundId,
         access_hash: i64,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::Background {
+            background_id,
+            access_hash,
+        })
     }
 
     /// Create a file source ID for chat full info.
@@ -190,8 +219,7 @@ impl FileReferenceManager {
     ///
     /// * `chat_id` - The chat identifier
     pub fn create_chat_full_file_source(&mut self, chat_id: ChatId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::ChatFull(chat_id))
     }
 
     /// Create a file source ID for channel full info.
@@ -200,20 +228,17 @@ impl FileReferenceManager {
     ///
     /// * `channel_id` - The channel identifier
     pub fn create_channel_full_file_source(&mut self, channel_id: ChannelId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::ChannelFull(channel_id))
     }
 
     /// Create a file source ID for app config.
     pub fn create_app_config_file_source(&mut self) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::AppConfig)
     }
 
     /// Create a file source ID for saved ringtones.
     pub fn create_saved_ringtones_file_source(&mut self) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::SavedRingtones)
     }
 
     /// Create a file source ID for user full info.
@@ -222,8 +247,7 @@ impl FileReferenceManager {
     ///
     /// * `user_id` - The user identifier
     pub fn create_user_full_file_source(&mut self, user_id: UserId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::UserFull(user_id))
     }
 
     /// Create a file source ID for attach menu bot.
@@ -232,8 +256,7 @@ impl FileReferenceManager {
     ///
     /// * `user_id` - The bot user identifier
     pub fn create_attach_menu_bot_file_source(&mut self, user_id: UserId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::AttachMenuBot(user_id))
     }
 
     /// Create a file source ID for a web app.
@@ -247,8 +270,10 @@ impl FileReferenceManager {
         user_id: UserId,
         short_name: String,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::WebApp {
+            user_id,
+            short_name,
+        })
     }
 
     /// Create a file source ID for a story.
@@ -257,8 +282,7 @@ impl FileReferenceManager {
     ///
     /// * `story_full_id` - The full story identifier
     pub fn create_story_file_source(&mut self, story_full_id: StoryFullId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::Story(story_full_id))
     }
 
     /// Create a file source ID for a quick reply message.
@@ -270,8 +294,7 @@ impl FileReferenceManager {
         &mut self,
         message_full_id: QuickReplyMessageFullId,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::QuickReplyMessage(message_full_id))
     }
 
     /// Create a file source ID for a star transaction.
@@ -287,8 +310,11 @@ impl FileReferenceManager {
         transaction_id: String,
         is_refund: bool,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::StarTransaction {
+            dialog_id,
+            transaction_id,
+            is_refund,
+        })
     }
 
     /// Create a file source ID for bot media preview.
@@ -297,8 +323,7 @@ impl FileReferenceManager {
     ///
     /// * `bot_user_id` - The bot user identifier
     pub fn create_bot_media_preview_file_source(&mut self, bot_user_id: UserId) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::BotMediaPreview(bot_user_id))
     }
 
     /// Create a file source ID for bot media preview info.
@@ -312,8 +337,10 @@ impl FileReferenceManager {
         bot_user_id: UserId,
         language_code: String,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::BotMediaPreviewInfo {
+            bot_user_id,
+            language_code,
+        })
     }
 
     /// Create a file source ID for a story album.
@@ -325,8 +352,7 @@ impl FileReferenceManager {
         &mut self,
         story_album_full_id: StoryAlbumFullId,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::StoryAlbum(story_album_full_id))
     }
 
     /// Create a file source ID for user saved music.
@@ -338,12 +364,15 @@ impl FileReferenceManager {
     /// * `access_hash` - The access hash
     pub fn create_user_saved_music_file_source(
         &mut self,
-        _user_id: UserId,
-        _document_id: i64,
-        _access_hash: i64,
+        user_id: UserId,
+        document_id: i64,
+        access_hash: i64,
     ) -> FileSourceId {
-        let id = self.next_source_id();
-        FileSourceId::new(id)
+        self.add_file_source_object(FileSource::UserSavedMusic {
+            user_id,
+            document_id,
+            access_hash,
+        })
     }
 
     /// Add a file source to tracking.
@@ -397,6 +426,55 @@ impl FileReferenceManager {
         sources.get(&node_id).cloned().unwrap_or_default()
     }
 
+    /// Get the object owning files for a source ID.
+    ///
+    /// Returns `None` if the source ID wasn't created by this manager.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_source_id` - The source identifier
+    pub fn get_file_source(&self, file_source_id: FileSourceId) -> Option<FileSource> {
+        let file_sources = self.file_sources.read().unwrap();
+        file_sources.get(&file_source_id).cloned()
+    }
+
+    /// Records the latest file reference of a file.
+    ///
+    /// Called by the owners of the files when they receive the file, e.g.
+    /// after its source was re-fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - The file
+    /// * `file_reference` - The fresh file reference
+    pub fn set_file_reference(&self, file_id: FileId, file_reference: Vec<u8>) {
+        self.file_references
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(file_id, file_reference);
+    }
+
+    /// Returns the latest recorded file reference of a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - The file
+    pub fn get_file_reference(&self, file_id: FileId) -> Option<Vec<u8>> {
+        self.file_references
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&file_id)
+            .cloned()
+    }
+
+    /// Create a source ID for an object owning files.
+    fn add_file_source_object(&mut self, source: FileSource) -> FileSourceId {
+        let file_source_id = FileSourceId::new(self.next_source_id());
+        let mut file_sources = self.file_sources.write().unwrap();
+        file_sources.insert(file_source_id, source);
+        file_source_id
+    }
+
     /// Generate the next unique source ID.
     fn next_source_id(&self) -> i32 {
         let mut counter = self.source_id_counter.write().unwrap();
@@ -409,6 +487,10 @@ impl FileReferenceManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rustgram_quick_reply_shortcut_id::QuickReplyShortcutId;
+    use rustgram_types::MessageId;
+
+    type DialogIdType = DialogId;
 
     #[test]
     fn test_manager_new() {
@@ -427,7 +509,7 @@ mod tests {
     #[test]
     fn test_create_message_file_source() {
         let mut manager = FileReferenceManager::new();
-        let dialog_id = DialogIdType::new(123);
+        let dialog_id = rustgram_types::DialogId::Chat(ChatId(123));
         let message_id = MessageId(1);
         let message_full_id = MessageFullId::new(dialog_id, message_id);
         let source_id = manager.create_message_file_source(message_full_id);
@@ -547,7 +629,8 @@ mod tests {
     #[test]
     fn test_create_quick_reply_message_file_source() {
         let mut manager = FileReferenceManager::new();
-        let message_full_id = QuickReplyMessageFullId::new(1, 2);
+        let message_full_id =
+            QuickReplyMessageFullId::new(QuickReplyShortcutId::new(1), MessageId(2));
         let source_id = manager.create_quick_reply_message_file_source(message_full_id);
         assert_eq!(source_id, FileSourceId::new(1));
     }
@@ -695,7 +778,7 @@ mod tests {
     #[test]
     fn test_source_id_increments() {
         let mut manager = FileReferenceManager::new();
-        let dialog_id = DialogIdType::new(123);
+        let dialog_id = rustgram_types::DialogId::Chat(ChatId(123));
         let message_id = MessageId(1);
         let message_full_id = MessageFullId::new(dialog_id, message_id);
         let source1 = manager.create_message_file_source(message_full_id);
@@ -743,7 +826,7 @@ mod tests {
         let mut manager = FileReferenceManager::new();
         let file_id = FileId::empty();
         for i in 0..count {
-            manager.add_file_source(file_id, FileSourceId::new(i as u64));
+            manager.add_file_source(file_id, FileSourceId::new(i as i32));
         }
         let sources = manager.get_file_sources(file_id);
         assert_eq!(sources.len(), count);
@@ -757,10 +840,10 @@ mod tests {
         let mut manager = FileReferenceManager::new();
         let file_id = FileId::empty();
         for i in 0..count {
-            manager.add_file_source(file_id, FileSourceId::new(i as u64));
+            manager.add_file_source(file_id, FileSourceId::new(i as i32));
         }
         for i in 0..count {
-            manager.remove_file_source(file_id, FileSourceId::new(i as u64));
+            manager.remove_file_source(file_id, FileSourceId::new(i as i32));
         }
         let sources = manager.get_file_sources(file_id);
         assert!(sources.is_empty());
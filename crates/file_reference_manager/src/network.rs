@@ -0,0 +1,367 @@
+//! Re-fetching file sources from the server.
+//!
+//! [`NetworkFileSourceReloader`] implements [`FileSourceReloader`] by
+//! sending the query re-fetching each kind of object through a
+//! [`NetQueryDispatcher`]. The answers carry the objects with fresh file
+//! references; they are passed to the [`FileSourceResultHandler`] of the
+//! managers owning the objects, which record the new references with
+//! [`FileReferenceManager::set_file_reference`](crate::FileReferenceManager::set_file_reference).
+//!
+//! Users and channels are addressed with their access hashes, which must be
+//! set with [`NetworkFileSourceReloader::set_access_hash`] before their
+//! sources can be re-fetched.
+
+use crate::repair::FileSourceReloader;
+use crate::types::FileSource;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use parking_lot::RwLock;
+use rustgram_net::{
+    AuthFlag, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryType, QueryError,
+};
+use rustgram_types::{ChannelId, DialogId, TlHelper, UserId};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Timeout of a single query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Query constructors.
+const TL_MESSAGES_GET_MESSAGES: u32 = 0x63c6_6506;
+const TL_CHANNELS_GET_MESSAGES: u32 = 0xad8c_9a23;
+const TL_PHOTOS_GET_USER_PHOTOS: u32 = 0x91cd_32a8;
+const TL_MESSAGES_GET_FULL_CHAT: u32 = 0xaeb0_0b34;
+const TL_CHANNELS_GET_FULL_CHANNEL: u32 = 0x0873_6a09;
+const TL_USERS_GET_FULL_USER: u32 = 0xb60f_5918;
+const TL_MESSAGES_GET_SAVED_GIFS: u32 = 0x5cf0_9635;
+const TL_MESSAGES_GET_RECENT_STICKERS: u32 = 0x9da9_403b;
+const TL_MESSAGES_GET_FAVED_STICKERS: u32 = 0x04f1_aaa9;
+const TL_MESSAGES_GET_WEB_PAGE: u32 = 0x8d96_92a3;
+const TL_MESSAGES_GET_ATTACH_MENU_BOT: u32 = 0x7721_6192;
+const TL_HELP_GET_APP_CONFIG: u32 = 0x61e3_f854;
+const TL_ACCOUNT_GET_SAVED_RINGTONES: u32 = 0xe190_2288;
+const TL_ACCOUNT_GET_WALL_PAPER: u32 = 0xfc8d_dbea;
+
+/// Argument constructors.
+const TL_VECTOR: u32 = 0x1cb5_c415;
+const TL_INPUT_MESSAGE_ID: u32 = 0xa676_a322;
+const TL_INPUT_USER: u32 = 0xf211_58c6;
+const TL_INPUT_CHANNEL: u32 = 0xf35a_ec28;
+const TL_INPUT_WALL_PAPER: u32 = 0xe630_b979;
+
+/// Receives the re-fetched objects owning files.
+///
+/// Implemented by the managers of the objects, which take the fresh file
+/// references from the answer.
+pub trait FileSourceResultHandler: Send + Sync {
+    /// Handles the answer of the query re-fetching the source.
+    fn on_file_source_reloaded(&self, source: &FileSource, answer: &Bytes);
+}
+
+/// [`FileSourceReloader`] re-fetching the sources from Telegram.
+pub struct NetworkFileSourceReloader {
+    /// Dispatcher sending the queries.
+    dispatcher: Arc<NetQueryDispatcher>,
+
+    /// Identifier of the next query.
+    next_query_id: AtomicU64,
+
+    /// Known access hashes of users and channels.
+    access_hashes: RwLock<HashMap<DialogId, i64>>,
+
+    /// Receivers of the re-fetched objects.
+    handlers: RwLock<Vec<Arc<dyn FileSourceResultHandler>>>,
+}
+
+impl NetworkFileSourceReloader {
+    /// Creates a reloader sending the queries through the dispatcher.
+    #[must_use]
+    pub fn new(dispatcher: Arc<NetQueryDispatcher>) -> Self {
+        Self {
+            dispatcher,
+            next_query_id: AtomicU64::new(1),
+            access_hashes: RwLock::new(HashMap::new()),
+            handlers: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Remembers the access hash of a user or a channel.
+    pub fn set_access_hash(&self, dialog_id: DialogId, access_hash: i64) {
+        self.access_hashes.write().insert(dialog_id, access_hash);
+    }
+
+    /// Adds a receiver of the re-fetched objects.
+    pub fn add_result_handler(&self, handler: Arc<dyn FileSourceResultHandler>) {
+        self.handlers.write().push(handler);
+    }
+
+    /// Returns the access hash of a user or a channel.
+    fn get_access_hash(&self, dialog_id: DialogId) -> Result<i64, String> {
+        self.access_hashes
+            .read()
+            .get(&dialog_id)
+            .copied()
+            .ok_or_else(|| format!("access hash of {dialog_id:?} is unknown"))
+    }
+
+    /// Writes an `inputUser`.
+    fn write_input_user(&self, buf: &mut BytesMut, user_id: UserId) -> Result<(), String> {
+        let access_hash = self.get_access_hash(DialogId::User(user_id))?;
+        TlHelper::write_constructor_id(buf, TL_INPUT_USER);
+        TlHelper::write_i64(buf, user_id.get());
+        TlHelper::write_i64(buf, access_hash);
+        Ok(())
+    }
+
+    /// Writes an `inputChannel`.
+    fn write_input_channel(&self, buf: &mut BytesMut, channel_id: ChannelId) -> Result<(), String> {
+        let access_hash = self.get_access_hash(DialogId::Channel(channel_id))?;
+        TlHelper::write_constructor_id(buf, TL_INPUT_CHANNEL);
+        TlHelper::write_i64(buf, channel_id.get());
+        TlHelper::write_i64(buf, access_hash);
+        Ok(())
+    }
+
+    /// Encodes the query re-fetching a source.
+    fn encode(&self, source: &FileSource) -> Result<BytesMut, String> {
+        let mut buf = BytesMut::new();
+        match source {
+            FileSource::Message(message_full_id) => {
+                let message_id = message_full_id.message_id();
+                if !message_id.is_server() {
+                    return Err(format!("{message_id:?} isn't a server message"));
+                }
+                match message_full_id.dialog_id() {
+                    DialogId::User(_) | DialogId::Chat(_) => {
+                        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_MESSAGES);
+                    }
+                    DialogId::Channel(channel_id) => {
+                        TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_GET_MESSAGES);
+                        self.write_input_channel(&mut buf, channel_id)?;
+                    }
+                    DialogId::SecretChat(_) => {
+                        return Err("messages of secret chats can't be re-fetched".to_string());
+                    }
+                }
+                TlHelper::write_constructor_id(&mut buf, TL_VECTOR);
+                TlHelper::write_i32(&mut buf, 1);
+                TlHelper::write_constructor_id(&mut buf, TL_INPUT_MESSAGE_ID);
+                TlHelper::write_i32(&mut buf, message_id.get_server_id());
+            }
+            FileSource::UserPhoto { user_id, photo_id } => {
+                TlHelper::write_constructor_id(&mut buf, TL_PHOTOS_GET_USER_PHOTOS);
+                self.write_input_user(&mut buf, *user_id)?;
+                // offset -1 and max_id return the photo itself
+                TlHelper::write_i32(&mut buf, -1);
+                TlHelper::write_i64(&mut buf, *photo_id);
+                TlHelper::write_i32(&mut buf, 1);
+            }
+            FileSource::ChatFull(chat_id) => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_FULL_CHAT);
+                TlHelper::write_i64(&mut buf, chat_id.get());
+            }
+            FileSource::ChannelFull(channel_id) => {
+                TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_GET_FULL_CHANNEL);
+                self.write_input_channel(&mut buf, *channel_id)?;
+            }
+            FileSource::UserFull(user_id) => {
+                TlHelper::write_constructor_id(&mut buf, TL_USERS_GET_FULL_USER);
+                self.write_input_user(&mut buf, *user_id)?;
+            }
+            FileSource::AttachMenuBot(user_id) => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_ATTACH_MENU_BOT);
+                self.write_input_user(&mut buf, *user_id)?;
+            }
+            FileSource::SavedAnimations => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_SAVED_GIFS);
+                // hash 0 always returns the list
+                TlHelper::write_i64(&mut buf, 0);
+            }
+            FileSource::RecentStickers { is_attached } => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_RECENT_STICKERS);
+                // flags.0: attached
+                TlHelper::write_i32(&mut buf, i32::from(*is_attached));
+                TlHelper::write_i64(&mut buf, 0);
+            }
+            FileSource::FavoriteStickers => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_FAVED_STICKERS);
+                TlHelper::write_i64(&mut buf, 0);
+            }
+            FileSource::WebPage(url) => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_WEB_PAGE);
+                TlHelper::write_string(&mut buf, url);
+                TlHelper::write_i32(&mut buf, 0);
+            }
+            FileSource::AppConfig => {
+                TlHelper::write_constructor_id(&mut buf, TL_HELP_GET_APP_CONFIG);
+                TlHelper::write_i32(&mut buf, 0);
+            }
+            FileSource::SavedRingtones => {
+                TlHelper::write_constructor_id(&mut buf, TL_ACCOUNT_GET_SAVED_RINGTONES);
+                TlHelper::write_i64(&mut buf, 0);
+            }
+            FileSource::Background {
+                background_id,
+                access_hash,
+            } => {
+                TlHelper::write_constructor_id(&mut buf, TL_ACCOUNT_GET_WALL_PAPER);
+                TlHelper::write_constructor_id(&mut buf, TL_INPUT_WALL_PAPER);
+                TlHelper::write_i64(&mut buf, background_id.get());
+                TlHelper::write_i64(&mut buf, *access_hash);
+            }
+            other => return Err(format!("re-fetching of {other:?} is not supported")),
+        }
+        Ok(buf)
+    }
+
+    /// Sends a query to the main DC and waits for its answer.
+    ///
+    /// On failure returns the error message of the server.
+    async fn invoke(&self, request: BytesMut) -> Result<Bytes, String> {
+        let request = request.freeze();
+        let constructor = request
+            .get(..4)
+            .map_or(0, |id| i32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+        let query = NetQuery::new(
+            self.next_query_id.fetch_add(1, Ordering::Relaxed),
+            request,
+            self.dispatcher.main_dc_id(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::Off,
+            constructor,
+        );
+
+        let (sender, receiver) = oneshot::channel();
+        query.set_callback(Box::new(ResultCallback {
+            sender: parking_lot::Mutex::new(Some(sender)),
+        }));
+        self.dispatcher
+            .dispatch(query)
+            .map_err(|e| format!("dispatch error: {e}"))?;
+
+        match tokio::time::timeout(QUERY_TIMEOUT, receiver).await {
+            Ok(Ok(Ok(answer))) => Ok(answer),
+            Ok(Ok(Err(QueryError::WithMessage { message, .. }))) => Err(message),
+            Ok(Ok(Err(error))) => Err(error.to_string()),
+            Ok(Err(_)) => Err("query was dropped".to_string()),
+            Err(_) => Err("query timed out".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl FileSourceReloader for NetworkFileSourceReloader {
+    async fn reload(&self, source: &FileSource) -> Result<(), String> {
+        let request = self.encode(source)?;
+        let answer = self.invoke(request).await?;
+        let handlers = self.handlers.read().clone();
+        for handler in handlers {
+            handler.on_file_source_reloaded(source, &answer);
+        }
+        Ok(())
+    }
+}
+
+/// Callback passing the result of a query to the waiting reload.
+struct ResultCallback {
+    /// Channel to the reload, taken by the first result.
+    sender: parking_lot::Mutex<Option<oneshot::Sender<Result<Bytes, QueryError>>>>,
+}
+
+#[async_trait]
+impl NetQueryCallback for ResultCallback {
+    async fn on_result(&self, query: NetQuery) {
+        let result = if query.is_error() {
+            Err(query.error())
+        } else {
+            Ok(query.ok())
+        };
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_background_id::BackgroundId;
+    use rustgram_message_full_id::MessageFullId;
+    use rustgram_types::{ChatId, MessageId};
+
+    fn reloader() -> NetworkFileSourceReloader {
+        NetworkFileSourceReloader::new(Arc::new(NetQueryDispatcher::new()))
+    }
+
+    fn constructor(buf: &BytesMut) -> u32 {
+        u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let reloader = reloader();
+        let source = FileSource::Message(MessageFullId::new(
+            DialogId::Chat(ChatId(5)),
+            MessageId::from_server_id(7),
+        ));
+        let buf = reloader.encode(&source).unwrap();
+        assert_eq!(constructor(&buf), TL_MESSAGES_GET_MESSAGES);
+        // vector of one inputMessageID
+        assert_eq!(buf.len(), 4 + 8 + 8);
+        assert_eq!(&buf[16..], &7i32.to_le_bytes());
+
+        // channels need their access hash
+        let channel_id = ChannelId(9);
+        let source = FileSource::Message(MessageFullId::new(
+            DialogId::Channel(channel_id),
+            MessageId::from_server_id(7),
+        ));
+        assert!(reloader.encode(&source).is_err());
+        reloader.set_access_hash(DialogId::Channel(channel_id), 42);
+        let buf = reloader.encode(&source).unwrap();
+        assert_eq!(constructor(&buf), TL_CHANNELS_GET_MESSAGES);
+        assert_eq!(&buf[16..24], &42i64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_encode_sources() {
+        let reloader = reloader();
+        reloader.set_access_hash(DialogId::User(UserId(3)), 1);
+        let cases = [
+            (
+                FileSource::UserPhoto {
+                    user_id: UserId(3),
+                    photo_id: 10,
+                },
+                TL_PHOTOS_GET_USER_PHOTOS,
+            ),
+            (FileSource::ChatFull(ChatId(5)), TL_MESSAGES_GET_FULL_CHAT),
+            (FileSource::UserFull(UserId(3)), TL_USERS_GET_FULL_USER),
+            (FileSource::SavedAnimations, TL_MESSAGES_GET_SAVED_GIFS),
+            (
+                FileSource::RecentStickers { is_attached: true },
+                TL_MESSAGES_GET_RECENT_STICKERS,
+            ),
+            (FileSource::FavoriteStickers, TL_MESSAGES_GET_FAVED_STICKERS),
+            (FileSource::AppConfig, TL_HELP_GET_APP_CONFIG),
+            (
+                FileSource::Background {
+                    background_id: BackgroundId::new(1),
+                    access_hash: 2,
+                },
+                TL_ACCOUNT_GET_WALL_PAPER,
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(constructor(&reloader.encode(&source).unwrap()), expected);
+        }
+        assert!(reloader.encode(&FileSource::UserFull(UserId(4))).is_err());
+        assert!(reloader
+            .encode(&FileSource::BotMediaPreview(UserId(3)))
+            .is_err());
+    }
+}
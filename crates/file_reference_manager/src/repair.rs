@@ -0,0 +1,335 @@
+//! Repair of expired file references.
+//!
+//! File references embedded in remote file locations expire. A query using
+//! an expired reference fails with a `FILE_REFERENCE_*` error, and the file
+//! must be re-fetched from one of the objects owning it to get a fresh
+//! reference.
+
+use crate::error::{Error, Result};
+use crate::types::FileSource;
+use crate::FileReferenceManager;
+use async_trait::async_trait;
+use rustgram_file_id::FileId;
+use std::future::Future;
+use std::sync::PoisonError;
+use tokio::sync::oneshot;
+
+/// Re-fetches objects owning files.
+///
+/// Implemented by the managers able to load messages, users, stickers and
+/// other file owners from the server.
+#[async_trait]
+pub trait FileSourceReloader: Send + Sync {
+    /// Re-fetches the object, updating the references of its files.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error message if the object couldn't be re-fetched.
+    async fn reload(&self, source: &FileSource) -> std::result::Result<(), String>;
+}
+
+/// Returns `true` if the error is caused by an invalid file reference.
+///
+/// # Example
+///
+/// ```
+/// use rustgram_file_reference_manager::is_file_reference_error;
+///
+/// assert!(is_file_reference_error("FILE_REFERENCE_EXPIRED"));
+/// assert!(is_file_reference_error("FILE_REFERENCE_2_EXPIRED"));
+/// assert!(!is_file_reference_error("FILE_ID_INVALID"));
+/// ```
+#[must_use]
+pub fn is_file_reference_error(error: &str) -> bool {
+    error.starts_with("FILE_REFERENCE_")
+}
+
+/// Returns the index of the media with the invalid file reference from
+/// errors like `FILE_REFERENCE_2_EXPIRED` returned for albums.
+///
+/// Returns 0 if the error doesn't specify the media.
+#[must_use]
+pub fn get_file_reference_error_pos(error: &str) -> usize {
+    error
+        .strip_prefix("FILE_REFERENCE_")
+        .and_then(|rest| rest.split('_').next())
+        .and_then(|pos| pos.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Removes a finished or dropped repair, passing its result to the waiters.
+struct RepairGuard<'a> {
+    manager: &'a FileReferenceManager,
+    file_id: FileId,
+    result: Option<Result<()>>,
+}
+
+impl Drop for RepairGuard<'_> {
+    fn drop(&mut self) {
+        let waiters = self
+            .manager
+            .repairs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.file_id)
+            .unwrap_or_default();
+        // Waiters get `RepairCancelled` if the repair didn't finish
+        if let Some(result) = &self.result {
+            for waiter in waiters {
+                let _ = waiter.send(result.clone());
+            }
+        }
+    }
+}
+
+impl FileReferenceManager {
+    /// Repairs the file reference of a file by re-fetching its sources.
+    ///
+    /// The sources are re-fetched one by one until one of them succeeds.
+    /// Concurrent repairs of the same file are merged, so that many parts
+    /// failing at once re-fetch the sources only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - The file with the expired reference
+    /// * `reloader` - Re-fetches the sources
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file has no sources or none of them could be
+    /// re-fetched.
+    pub async fn repair_file_reference(
+        &self,
+        file_id: FileId,
+        reloader: &dyn FileSourceReloader,
+    ) -> Result<()> {
+        let waiter = {
+            let mut repairs = self.repairs.lock().unwrap_or_else(PoisonError::into_inner);
+            match repairs.get_mut(&file_id) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    repairs.insert(file_id, Vec::new());
+                    None
+                }
+            }
+        };
+        if let Some(receiver) = waiter {
+            return receiver
+                .await
+                .unwrap_or(Err(Error::RepairCancelled(file_id)));
+        }
+
+        let mut guard = RepairGuard {
+            manager: self,
+            file_id,
+            result: None,
+        };
+        let result = self.reload_file_sources(file_id, reloader).await;
+        guard.result = Some(result.clone());
+        result
+    }
+
+    /// Sends a query using a file reference of a file, repairing the
+    /// reference and sending the query again if it has expired.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - The file used by the query
+    /// * `reloader` - Re-fetches the sources of the file
+    /// * `query` - Sends the query with the current file reference, returning
+    ///   the error message on failure
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the reference can't be
+    /// repaired.
+    pub async fn send_with_file_reference_repair<T, F, Fut>(
+        &self,
+        file_id: FileId,
+        reloader: &dyn FileSourceReloader,
+        mut query: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, String>>,
+    {
+        match query().await {
+            Err(error) if is_file_reference_error(&error) => {
+                self.repair_file_reference(file_id, reloader).await?;
+                // The reference is repaired only once
+                query().await.map_err(Error::Query)
+            }
+            result => result.map_err(Error::Query),
+        }
+    }
+
+    async fn reload_file_sources(
+        &self,
+        file_id: FileId,
+        reloader: &dyn FileSourceReloader,
+    ) -> Result<()> {
+        let sources = self.get_file_sources(file_id);
+        if sources.is_empty() {
+            return Err(Error::NoFileSources(file_id));
+        }
+
+        let mut last_error = String::new();
+        for file_source_id in sources {
+            let Some(source) = self.get_file_source(file_source_id) else {
+                continue;
+            };
+            match reloader.reload(&source).await {
+                Ok(()) => return Ok(()),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(Error::RepairFailed(file_id, last_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_file_source_id::FileSourceId;
+    use rustgram_types::{ChatId, UserId};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Reloads sources, failing for the full info of users.
+    #[derive(Default)]
+    struct TestReloader {
+        reloaded: Mutex<Vec<FileSource>>,
+        reload_count: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FileSourceReloader for TestReloader {
+        async fn reload(&self, source: &FileSource) -> std::result::Result<(), String> {
+            self.reload_count.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.reloaded.lock().unwrap().push(source.clone());
+            match source {
+                FileSource::UserFull(_) => Err("USER_ID_INVALID".to_string()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_file_reference_error() {
+        assert!(is_file_reference_error("FILE_REFERENCE_EXPIRED"));
+        assert!(is_file_reference_error("FILE_REFERENCE_INVALID"));
+        assert!(is_file_reference_error("FILE_REFERENCE_3_EXPIRED"));
+        assert!(!is_file_reference_error("FILE_ID_INVALID"));
+    }
+
+    #[test]
+    fn test_get_file_reference_error_pos() {
+        assert_eq!(get_file_reference_error_pos("FILE_REFERENCE_EXPIRED"), 0);
+        assert_eq!(get_file_reference_error_pos("FILE_REFERENCE_3_EXPIRED"), 3);
+        assert_eq!(get_file_reference_error_pos("FILE_ID_INVALID"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_repair_tries_next_source() {
+        let mut manager = FileReferenceManager::new();
+        let file_id = FileId::new(1, 0);
+        let user_full = manager.create_user_full_file_source(UserId(1));
+        let chat_full = manager.create_chat_full_file_source(ChatId(2));
+        manager.add_file_source(file_id, user_full);
+        manager.add_file_source(file_id, chat_full);
+
+        let reloader = TestReloader::default();
+        manager
+            .repair_file_reference(file_id, &reloader)
+            .await
+            .unwrap();
+        assert_eq!(
+            *reloader.reloaded.lock().unwrap(),
+            vec![
+                FileSource::UserFull(UserId(1)),
+                FileSource::ChatFull(ChatId(2))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repair_failed() {
+        let mut manager = FileReferenceManager::new();
+        let file_id = FileId::new(1, 0);
+        let reloader = TestReloader::default();
+        assert_eq!(
+            manager.repair_file_reference(file_id, &reloader).await,
+            Err(Error::NoFileSources(file_id))
+        );
+
+        let user_full = manager.create_user_full_file_source(UserId(1));
+        manager.add_file_source(file_id, user_full);
+        manager.add_file_source(file_id, FileSourceId::new(100));
+        assert_eq!(
+            manager.repair_file_reference(file_id, &reloader).await,
+            Err(Error::RepairFailed(file_id, "USER_ID_INVALID".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_repairs_are_merged() {
+        let mut manager = FileReferenceManager::new();
+        let file_id = FileId::new(1, 0);
+        let saved_animations = manager.create_saved_animations_file_source();
+        manager.add_file_source(file_id, saved_animations);
+
+        let reloader = TestReloader::default();
+        let (first, second, third) = tokio::join!(
+            manager.repair_file_reference(file_id, &reloader),
+            manager.repair_file_reference(file_id, &reloader),
+            manager.repair_file_reference(file_id, &reloader),
+        );
+        assert_eq!((first, second, third), (Ok(()), Ok(()), Ok(())));
+        assert_eq!(reloader.reload_count.load(Ordering::SeqCst), 1);
+
+        // A later failure repairs the reference again
+        manager
+            .repair_file_reference(file_id, &reloader)
+            .await
+            .unwrap();
+        assert_eq!(reloader.reload_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_file_reference_repair() {
+        let mut manager = FileReferenceManager::new();
+        let file_id = FileId::new(1, 0);
+        let saved_animations = manager.create_saved_animations_file_source();
+        manager.add_file_source(file_id, saved_animations);
+
+        let reloader = TestReloader::default();
+        let attempts = AtomicUsize::new(0);
+        let result = manager
+            .send_with_file_reference_repair(file_id, &reloader, || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                // The reference is fresh after the sources are reloaded
+                if reloader.reload_count.load(Ordering::SeqCst) == 0 {
+                    Err("FILE_REFERENCE_EXPIRED".to_string())
+                } else {
+                    Ok("file")
+                }
+            })
+            .await;
+        assert_eq!(result, Ok("file"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let result = manager
+            .send_with_file_reference_repair(file_id, &reloader, || async {
+                Err::<(), _>("FILE_ID_INVALID".to_string())
+            })
+            .await;
+        assert_eq!(result, Err(Error::Query("FILE_ID_INVALID".to_string())));
+        assert_eq!(reloader.reload_count.load(Ordering::SeqCst), 1);
+    }
+}
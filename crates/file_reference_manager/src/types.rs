@@ -1,7 +1,10 @@
 //! Supporting types for file reference manager.
 
+use rustgram_background_id::BackgroundId;
 use rustgram_dialog_id::DialogId;
-use serde::{Deserialize, Serialize};
+use rustgram_message_full_id::MessageFullId;
+use rustgram_quick_reply_message_full_id::QuickReplyMessageFullId;
+use rustgram_types::{ChannelId, ChatId, UserId};
 
 /// Full identifier for a story.
 ///
@@ -26,3 +29,108 @@ pub struct StoryAlbumFullId {
     /// The album identifier.
     pub album_id: i64,
 }
+
+/// An object owning files, which can be re-fetched to get fresh file
+/// references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSource {
+    /// A message.
+    Message(MessageFullId),
+
+    /// A photo of a user.
+    UserPhoto {
+        /// The user identifier.
+        user_id: UserId,
+        /// The photo identifier.
+        photo_id: i64,
+    },
+
+    /// A web page preview.
+    WebPage(String),
+
+    /// The list of saved animations.
+    SavedAnimations,
+
+    /// The list of recent stickers.
+    RecentStickers {
+        /// Whether these are attached stickers.
+        is_attached: bool,
+    },
+
+    /// The list of favorite stickers.
+    FavoriteStickers,
+
+    /// A background.
+    Background {
+        /// The background identifier.
+        background_id: BackgroundId,
+        /// The access hash of the background.
+        access_hash: i64,
+    },
+
+    /// Full info of a basic group.
+    ChatFull(ChatId),
+
+    /// Full info of a channel.
+    ChannelFull(ChannelId),
+
+    /// The app config.
+    AppConfig,
+
+    /// The list of saved ringtones.
+    SavedRingtones,
+
+    /// Full info of a user.
+    UserFull(UserId),
+
+    /// An attachment menu bot.
+    AttachMenuBot(UserId),
+
+    /// A web app.
+    WebApp {
+        /// The bot user identifier.
+        user_id: UserId,
+        /// The web app short name.
+        short_name: String,
+    },
+
+    /// A story.
+    Story(StoryFullId),
+
+    /// A quick reply message.
+    QuickReplyMessage(QuickReplyMessageFullId),
+
+    /// A star transaction.
+    StarTransaction {
+        /// The dialog identifier.
+        dialog_id: DialogId,
+        /// The transaction ID.
+        transaction_id: String,
+        /// Whether this is a refund.
+        is_refund: bool,
+    },
+
+    /// Media previews of a bot.
+    BotMediaPreview(UserId),
+
+    /// Media previews of a bot in a language.
+    BotMediaPreviewInfo {
+        /// The bot user identifier.
+        bot_user_id: UserId,
+        /// The language code.
+        language_code: String,
+    },
+
+    /// A story album.
+    StoryAlbum(StoryAlbumFullId),
+
+    /// A song saved by a user.
+    UserSavedMusic {
+        /// The user identifier.
+        user_id: UserId,
+        /// The document identifier.
+        document_id: i64,
+        /// The access hash of the document.
+        access_hash: i64,
+    },
+}
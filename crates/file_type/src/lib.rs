@@ -187,6 +187,45 @@ impl FileType {
         self.dir_name()
     }
 
+    /// Returns the file type stored in a directory with the given name.
+    ///
+    /// This is the inverse of [`FileType::unique_dir_name`] for main types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustgram_file_type::FileType;
+    ///
+    /// assert_eq!(FileType::from_dir_name("photos"), Some(FileType::Photo));
+    /// assert_eq!(FileType::from_dir_name("video_stories"), Some(FileType::VideoStory));
+    /// assert_eq!(FileType::from_dir_name("unknown"), None);
+    /// ```
+    #[must_use]
+    pub fn from_dir_name(dir_name: &str) -> Option<FileType> {
+        let file_type = match dir_name {
+            "thumbnails" => FileType::Thumbnail,
+            "profile_photos" => FileType::ProfilePhoto,
+            "photos" => FileType::Photo,
+            "voice" => FileType::VoiceNote,
+            "videos" => FileType::Video,
+            "documents" => FileType::Document,
+            "secret" => FileType::Encrypted,
+            "temp" => FileType::Temp,
+            "stickers" => FileType::Sticker,
+            "music" => FileType::Audio,
+            "animations" => FileType::Animation,
+            "secret_thumbnails" => FileType::EncryptedThumbnail,
+            "video_notes" => FileType::VideoNote,
+            "passport" => FileType::SecureEncrypted,
+            "wallpapers" => FileType::Background,
+            "notification_sounds" => FileType::Ringtone,
+            "stories" => FileType::PhotoStory,
+            "video_stories" => FileType::VideoStory,
+            _ => return None,
+        };
+        Some(file_type)
+    }
+
     /// Returns the file type class.
     ///
     /// # Examples
@@ -498,6 +537,25 @@ mod tests {
         assert_eq!(FileType::Photo.unique_dir_name(), "photos");
     }
 
+    #[test]
+    fn test_from_dir_name_roundtrip() {
+        for file_type in [
+            FileType::Thumbnail,
+            FileType::Photo,
+            FileType::Document,
+            FileType::Temp,
+            FileType::Background,
+            FileType::PhotoStory,
+            FileType::VideoStory,
+        ] {
+            assert_eq!(
+                FileType::from_dir_name(file_type.unique_dir_name()),
+                Some(file_type)
+            );
+        }
+        assert_eq!(FileType::from_dir_name("none"), None);
+    }
+
     // === class tests ===
 
     #[rstest]
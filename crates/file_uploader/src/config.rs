@@ -38,6 +38,8 @@ pub struct FileUploaderConfig {
     pub encryption_key: FileEncryptionKey,
     /// Ready parts (for resuming)
     pub ready_parts: Vec<i32>,
+    /// Random ID of the upload on the server
+    pub upload_file_id: i64,
 }
 
 impl FileUploaderConfig {
@@ -59,6 +61,7 @@ impl FileUploaderConfig {
             size_is_final: false,
             encryption_key: FileEncryptionKey::empty(),
             ready_parts: Vec::new(),
+            upload_file_id: 0,
         }
     }
 
@@ -116,6 +119,17 @@ impl FileUploaderConfig {
         self.ready_parts = ready_parts;
         self
     }
+
+    /// Sets the random ID of the upload on the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `upload_file_id` - The ID passed to `upload.saveFilePart`
+    #[must_use]
+    pub const fn with_upload_file_id(mut self, upload_file_id: i64) -> Self {
+        self.upload_file_id = upload_file_id;
+        self
+    }
 }
 
 impl fmt::Display for FileUploaderConfig {
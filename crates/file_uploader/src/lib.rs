@@ -60,6 +60,7 @@
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 
+use file_db::{LocalFileFingerprint, PartialTransfer, PrefixHasher};
 use rustgram_file_encryption_key::FileEncryptionKey;
 use rustgram_file_id::FileId;
 use rustgram_file_upload_id::FileUploadId;
@@ -139,6 +140,12 @@ pub struct FileUploader {
     stop_flag: bool,
     /// Whether to use CDN
     use_cdn: bool,
+    /// Random ID of the upload on the server
+    upload_file_id: i64,
+    /// Running hash of the uploaded prefix of the file
+    hasher: PrefixHasher,
+    /// Whether the upload continues a saved one
+    is_resumed: bool,
 }
 
 impl FileUploader {
@@ -175,7 +182,7 @@ impl FileUploader {
         let upload_id = FileUploadId::new(file_id, 0);
 
         Ok(Self {
-            path: PathBuf::from(config.path),
+            path: PathBuf::from(&config.path),
             file_type: config.file_type,
             file_id,
             upload_id,
@@ -191,6 +198,94 @@ impl FileUploader {
             need_check: false,
             stop_flag: false,
             use_cdn: false,
+            upload_file_id: config.upload_file_id,
+            hasher: PrefixHasher::new(&config.path),
+            is_resumed: false,
+        })
+    }
+
+    /// Creates a file uploader continuing a saved upload.
+    ///
+    /// The saved state is used only if it belongs to the same file and the
+    /// file hasn't changed since; otherwise the upload starts over.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The uploader configuration
+    /// * `partial` - The saved state of the upload
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid.
+    pub fn resume(config: FileUploaderConfig, partial: &PartialTransfer) -> Result<Self> {
+        let hasher = if partial.path == config.path && partial.size == config.size {
+            partial
+                .validate()
+                .map_err(|e| Error::IoError(e.to_string()))?
+        } else {
+            None
+        };
+        let Some(hasher) = hasher else {
+            return Self::new(config);
+        };
+
+        let resumed_config = config
+            .clone()
+            .with_ready_parts(partial.ready_parts())
+            .with_upload_file_id(partial.upload_file_id);
+        match Self::new(resumed_config) {
+            Ok(mut uploader) if uploader.part_size() as i64 == partial.part_size => {
+                uploader.hasher = hasher;
+                uploader.is_resumed = true;
+                Ok(uploader)
+            }
+            _ => Self::new(config),
+        }
+    }
+
+    /// Returns `true` if the upload continues a saved one.
+    #[must_use]
+    pub const fn is_resumed(&self) -> bool {
+        self.is_resumed
+    }
+
+    /// Returns the random ID of the upload on the server.
+    #[must_use]
+    pub const fn upload_file_id(&self) -> i64 {
+        self.upload_file_id
+    }
+
+    /// Returns the part size.
+    #[must_use]
+    pub const fn part_size(&self) -> usize {
+        self.parts_manager.get_part_size()
+    }
+
+    /// Returns the state of the upload to save for resuming it later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read.
+    pub fn partial_transfer(&mut self) -> Result<PartialTransfer> {
+        let part_size = self.part_size() as i64;
+        let ready_bitmask = self.parts_manager.get_ready_bitmask().clone();
+        let ready_prefix_size = ready_bitmask.get_ready_prefix_size(0, part_size, self.size);
+        self.hasher
+            .advance_to(ready_prefix_size)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        let source =
+            LocalFileFingerprint::of(&self.path).map_err(|e| Error::IoError(e.to_string()))?;
+
+        Ok(PartialTransfer {
+            file_id: self.file_id.get(),
+            path: self.path.to_string_lossy().into_owned(),
+            size: self.size,
+            part_size,
+            ready_bitmask,
+            upload_file_id: self.upload_file_id,
+            source: Some(source),
+            hash_size: self.hasher.size(),
+            hash: self.hasher.hash(),
         })
     }
 
@@ -593,6 +688,60 @@ mod tests {
         assert!(s.contains("10000000"));
     }
 
+    /// Uploads the first `count` parts of the file.
+    fn upload_parts(uploader: &mut FileUploader, count: usize) {
+        for _ in 0..count {
+            let part = uploader.get_next_part().unwrap();
+            uploader.on_part_ok(part.id, part.size, part.size).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, vec![3u8; 3_000_000]).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let config = FileUploaderConfig::new(&path, FileType::Video, Some(FileId::new(1, 0)))
+            .with_size(3_000_000)
+            .with_upload_file_id(777);
+
+        let mut uploader = FileUploader::new(config.clone()).unwrap();
+        uploader.start().unwrap();
+        upload_parts(&mut uploader, 3);
+        let partial = uploader.partial_transfer().unwrap();
+        assert_eq!(partial.ready_parts(), vec![0, 1, 2]);
+        assert_eq!(partial.hash_size, 3 * 512 * 1024);
+        drop(uploader);
+
+        let mut uploader =
+            FileUploader::resume(config.clone().with_upload_file_id(0), &partial).unwrap();
+        assert!(uploader.is_resumed());
+        assert_eq!(uploader.upload_file_id(), 777);
+        assert_eq!(uploader.uploaded_bytes(), 3 * 512 * 1024);
+        uploader.start().unwrap();
+        assert_eq!(uploader.get_next_part().unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_resume_changed_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, vec![3u8; 3_000_000]).unwrap();
+        let path_str = path.to_string_lossy().into_owned();
+        let config = FileUploaderConfig::new(&path_str, FileType::Video, None).with_size(3_000_000);
+
+        let mut uploader = FileUploader::new(config.clone()).unwrap();
+        uploader.start().unwrap();
+        upload_parts(&mut uploader, 2);
+        let partial = uploader.partial_transfer().unwrap();
+
+        std::fs::write(&path, vec![4u8; 3_000_000]).unwrap();
+        let uploader = FileUploader::resume(config, &partial).unwrap();
+        assert!(!uploader.is_resumed());
+        assert_eq!(uploader.uploaded_bytes(), 0);
+    }
+
     #[test]
     fn test_upload_priority_value() {
         assert_eq!(UploadPriority::Low.value(), 0);
@@ -0,0 +1,59 @@
+// Copyright 2024 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Entity type names produced by the text parsers.
+//!
+//! The argument of an entity depends on its type: the URL for [`TEXT_URL`],
+//! the decimal user identifier for [`MENTION_NAME`], the decimal custom emoji
+//! identifier for [`CUSTOM_EMOJI`] and the programming language for
+//! [`PRE_CODE`].
+
+/// Bold text.
+pub const BOLD: &str = "bold";
+
+/// Italic text.
+pub const ITALIC: &str = "italic";
+
+/// Underlined text.
+pub const UNDERLINE: &str = "underline";
+
+/// Strikethrough text.
+pub const STRIKETHROUGH: &str = "strikethrough";
+
+/// Spoiler text, hidden until clicked.
+pub const SPOILER: &str = "spoiler";
+
+/// Inline monowidth code.
+pub const CODE: &str = "code";
+
+/// Monowidth code block.
+pub const PRE: &str = "pre";
+
+/// Monowidth code block with a programming language.
+pub const PRE_CODE: &str = "preCode";
+
+/// Text linked to a URL.
+pub const TEXT_URL: &str = "textLink";
+
+/// Mention of a user by identifier.
+pub const MENTION_NAME: &str = "mentionName";
+
+/// Custom emoji replacing the text.
+pub const CUSTOM_EMOJI: &str = "customEmoji";
+
+/// Block quotation.
+pub const BLOCK_QUOTE: &str = "blockQuote";
+
+/// Block quotation collapsed by default.
+pub const EXPANDABLE_BLOCK_QUOTE: &str = "expandableBlockQuote";
@@ -0,0 +1,657 @@
+// Copyright 2024 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTML parsing and rendering.
+//!
+//! ## TDLib Correspondence
+//!
+//! Mirrors `parse_html` in `td/telegram/MessageEntity.cpp`. Supported tags are
+//! `b`, `strong`, `i`, `em`, `u`, `ins`, `s`, `strike`, `del`, `tg-spoiler`,
+//! `span class="tg-spoiler"`, `a href`, `code`, `pre`,
+//! `pre` + `code class="language-*"`, `tg-emoji emoji-id` and `blockquote`
+//! with an optional `expandable` attribute. Unknown attributes are ignored.
+
+use crate::entity_type;
+use crate::parse::{add_link, char_at, user_url, TextBuilder, TextParseError};
+use crate::render::Markup;
+use crate::{FormattedText, MessageEntity};
+
+/// Tags with a meaning in Telegram HTML.
+const SUPPORTED_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "code",
+    "del",
+    "em",
+    "i",
+    "ins",
+    "pre",
+    "s",
+    "span",
+    "strike",
+    "strong",
+    "tg-emoji",
+    "tg-spoiler",
+    "u",
+];
+
+/// A start tag whose end tag hasn't been found yet.
+struct OpenTag {
+    /// Lowercased tag name
+    name: String,
+    /// Start in UTF-16 code units of the plain text
+    offset: i32,
+    /// Start in bytes of the source text
+    begin: usize,
+    /// Start in bytes of the plain text
+    text_begin: usize,
+    /// Link URL, custom emoji identifier or code language
+    argument: Option<String>,
+    /// True for an expandable block quotation
+    expandable: bool,
+}
+
+/// Parses Telegram HTML.
+pub(crate) fn parse_html(text: &str) -> Result<FormattedText, TextParseError> {
+    let bytes = text.as_bytes();
+    let mut out = TextBuilder::new();
+    let mut nested: Vec<OpenTag> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'&' => match decode_entity(text, i) {
+                Some((c, next)) => {
+                    out.push(c);
+                    i = next;
+                }
+                None => {
+                    out.push('&');
+                    i += 1;
+                }
+            },
+            b'<' if bytes.get(i + 1) == Some(&b'/') => {
+                i = parse_end_tag(text, i, &mut nested, &mut out)?;
+            }
+            b'<' => {
+                let tag = parse_start_tag(text, i, &nested, &out)?;
+                i = tag.1;
+                nested.push(tag.0);
+            }
+            _ => {
+                let ch = char_at(text, i);
+                out.push(ch);
+                i += ch.len_utf8();
+            }
+        }
+    }
+
+    if let Some(tag) = nested.last() {
+        return Err(TextParseError::MissingEndTag {
+            tag: tag.name.clone(),
+            offset: tag.begin,
+        });
+    }
+    Ok(out.finish())
+}
+
+/// Parses the start tag at byte `begin`.
+///
+/// Returns the open tag and the offset right after it.
+fn parse_start_tag(
+    text: &str,
+    begin: usize,
+    nested: &[OpenTag],
+    out: &TextBuilder,
+) -> Result<(OpenTag, usize), TextParseError> {
+    let bytes = text.as_bytes();
+    let (name, mut i) = read_name(bytes, begin + 1);
+    if !SUPPORTED_TAGS.contains(&name.as_str()) {
+        return Err(TextParseError::UnsupportedTag {
+            tag: name,
+            offset: begin,
+        });
+    }
+
+    let mut tag = OpenTag {
+        name,
+        offset: out.utf16_offset(),
+        begin,
+        text_begin: out.byte_offset(),
+        argument: None,
+        expandable: false,
+    };
+    let mut is_spoiler_class = false;
+    loop {
+        i = skip_whitespace(bytes, i);
+        match bytes.get(i) {
+            None => return Err(TextParseError::UnclosedTag { offset: begin }),
+            Some(b'>') => break,
+            Some(_) => {}
+        }
+
+        let attribute_begin = i;
+        let (attribute, next) = read_name(bytes, i);
+        if attribute.is_empty() {
+            return Err(TextParseError::InvalidAttribute {
+                tag: tag.name,
+                offset: attribute_begin,
+            });
+        }
+        i = skip_whitespace(bytes, next);
+        let value = if bytes.get(i) == Some(&b'=') {
+            i = skip_whitespace(bytes, i + 1);
+            let (value, next) = parse_attribute_value(text, i, &tag.name)?;
+            i = next;
+            value
+        } else {
+            String::new()
+        };
+
+        match (tag.name.as_str(), attribute.as_str()) {
+            ("a", "href") | ("tg-emoji", "emoji-id") => tag.argument = Some(value),
+            ("code", "class") => {
+                tag.argument = value
+                    .strip_prefix("language-")
+                    .filter(|language| !language.is_empty())
+                    .map(str::to_string);
+            }
+            ("span", "class") => is_spoiler_class = value == "tg-spoiler",
+            ("blockquote", "expandable") => tag.expandable = true,
+            _ => {}
+        }
+    }
+
+    if tag.name == "span" && !is_spoiler_class {
+        return Err(TextParseError::UnsupportedTag {
+            tag: tag.name,
+            offset: begin,
+        });
+    }
+    let in_pre_start = nested
+        .last()
+        .is_some_and(|parent| parent.name == "pre" && parent.offset == tag.offset);
+    if tag.name == "code" && !in_pre_start {
+        // The language is meaningful only for a code block
+        tag.argument = None;
+    }
+    Ok((tag, i + 1))
+}
+
+/// Parses the end tag at byte `begin` and adds the entity it closes.
+///
+/// Returns the offset right after the end tag.
+fn parse_end_tag(
+    text: &str,
+    begin: usize,
+    nested: &mut Vec<OpenTag>,
+    out: &mut TextBuilder,
+) -> Result<usize, TextParseError> {
+    let bytes = text.as_bytes();
+    let (name, i) = read_name(bytes, begin + 2);
+    let i = skip_whitespace(bytes, i);
+    if bytes.get(i) != Some(&b'>') {
+        return Err(TextParseError::UnclosedTag { offset: begin });
+    }
+
+    let Some(tag) = nested.pop() else {
+        return Err(TextParseError::UnexpectedEndTag {
+            tag: name,
+            offset: begin,
+        });
+    };
+    if tag.name != name {
+        return Err(TextParseError::UnmatchedEndTag {
+            expected: tag.name,
+            found: name,
+            offset: begin,
+        });
+    }
+
+    let offset = tag.offset;
+    match tag.name.as_str() {
+        "b" | "strong" => out.add_entity(entity_type::BOLD, offset, None),
+        "i" | "em" => out.add_entity(entity_type::ITALIC, offset, None),
+        "u" | "ins" => out.add_entity(entity_type::UNDERLINE, offset, None),
+        "s" | "strike" | "del" => out.add_entity(entity_type::STRIKETHROUGH, offset, None),
+        "tg-spoiler" | "span" => out.add_entity(entity_type::SPOILER, offset, None),
+        "a" => {
+            let url = match tag.argument {
+                Some(url) => url,
+                None => out.text_from(tag.text_begin).to_string(),
+            };
+            add_link(out, offset, &url);
+        }
+        "code" => match (tag.argument, nested.last_mut()) {
+            // <pre><code class="language-*"> is a single code block
+            (Some(language), Some(parent)) => parent.argument = Some(language),
+            _ => out.add_entity(entity_type::CODE, offset, None),
+        },
+        "pre" => match tag.argument {
+            Some(language) => out.add_entity(entity_type::PRE_CODE, offset, Some(language)),
+            None => out.add_entity(entity_type::PRE, offset, None),
+        },
+        "tg-emoji" => {
+            let emoji_id = tag
+                .argument
+                .as_deref()
+                .and_then(|emoji_id| emoji_id.trim().parse::<i64>().ok())
+                .filter(|&emoji_id| emoji_id != 0)
+                .ok_or(TextParseError::InvalidCustomEmoji { offset: tag.begin })?;
+            out.add_entity(
+                entity_type::CUSTOM_EMOJI,
+                offset,
+                Some(emoji_id.to_string()),
+            );
+        }
+        _ => {
+            let entity_type = if tag.expandable {
+                entity_type::EXPANDABLE_BLOCK_QUOTE
+            } else {
+                entity_type::BLOCK_QUOTE
+            };
+            out.add_entity(entity_type, offset, None);
+        }
+    }
+    Ok(i + 1)
+}
+
+/// Reads a lowercased tag or attribute name starting at byte `i`.
+fn read_name(bytes: &[u8], mut i: usize) -> (String, usize) {
+    let begin = i;
+    while bytes
+        .get(i)
+        .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        i += 1;
+    }
+    let name = String::from_utf8_lossy(&bytes[begin..i]).to_ascii_lowercase();
+    (name, i)
+}
+
+/// Skips ASCII whitespace starting at byte `i`.
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+/// Parses a quoted or unquoted attribute value starting at byte `i`.
+fn parse_attribute_value(
+    text: &str,
+    i: usize,
+    tag: &str,
+) -> Result<(String, usize), TextParseError> {
+    let bytes = text.as_bytes();
+    match bytes.get(i) {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let end = text[i + 1..].find(char::from(quote)).ok_or_else(|| {
+                TextParseError::UnclosedAttributeValue {
+                    tag: tag.to_string(),
+                    offset: i,
+                }
+            })?;
+            let value = decode_entities(&text[i + 1..i + 1 + end]);
+            Ok((value, i + end + 2))
+        }
+        _ => {
+            let mut end = i;
+            while bytes
+                .get(end)
+                .is_some_and(|&b| !b.is_ascii_whitespace() && b != b'>')
+            {
+                end += 1;
+            }
+            Ok((decode_entities(&text[i..end]), end))
+        }
+    }
+}
+
+/// Decodes the character reference at byte `i`.
+///
+/// Returns the character and the offset right after the reference, or `None`
+/// if the `&` doesn't start a known reference.
+fn decode_entity(text: &str, i: usize) -> Option<(char, usize)> {
+    let rest = text.get(i + 1..)?;
+    let end = rest.get(..rest.len().min(10))?.find(';')?;
+    let name = &rest[..end];
+    let c = match name {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        _ => {
+            let code =
+                if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok()?
+                } else {
+                    name.strip_prefix('#')?.parse::<u32>().ok()?
+                };
+            char::from_u32(code).filter(|&c| c != '\0')?
+        }
+    };
+    Some((c, i + end + 2))
+}
+
+/// Decodes all character references in an attribute value.
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let reference = if value.as_bytes()[i] == b'&' {
+            decode_entity(value, i)
+        } else {
+            None
+        };
+        match reference {
+            Some((c, next)) => {
+                decoded.push(c);
+                i = next;
+            }
+            None => {
+                let c = char_at(value, i);
+                decoded.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    decoded
+}
+
+/// Escapes text or an attribute value for HTML.
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        push_escaped(c, out);
+    }
+}
+
+/// Escapes a single character for HTML.
+fn push_escaped(c: char, out: &mut String) {
+    match c {
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '&' => out.push_str("&amp;"),
+        '"' => out.push_str("&quot;"),
+        _ => out.push(c),
+    }
+}
+
+/// Renders entities as Telegram HTML.
+pub(crate) struct Html;
+
+impl Markup for Html {
+    fn is_supported(&self, entity: &MessageEntity) -> bool {
+        matches!(
+            entity.entity_type(),
+            entity_type::BOLD
+                | entity_type::ITALIC
+                | entity_type::UNDERLINE
+                | entity_type::STRIKETHROUGH
+                | entity_type::SPOILER
+                | entity_type::CODE
+                | entity_type::PRE
+                | entity_type::PRE_CODE
+                | entity_type::TEXT_URL
+                | entity_type::MENTION_NAME
+                | entity_type::CUSTOM_EMOJI
+                | entity_type::BLOCK_QUOTE
+                | entity_type::EXPANDABLE_BLOCK_QUOTE
+        )
+    }
+
+    fn open(&mut self, entity: &MessageEntity, out: &mut String) {
+        let argument = entity.argument().unwrap_or_default();
+        let (prefix, attribute) = match entity.entity_type() {
+            entity_type::BOLD => ("<b", None),
+            entity_type::ITALIC => ("<i", None),
+            entity_type::UNDERLINE => ("<u", None),
+            entity_type::STRIKETHROUGH => ("<s", None),
+            entity_type::SPOILER => ("<tg-spoiler", None),
+            entity_type::CODE => ("<code", None),
+            entity_type::PRE => ("<pre", None),
+            entity_type::PRE_CODE => ("<pre><code class=\"language-", Some(argument.to_string())),
+            entity_type::TEXT_URL => ("<a href=\"", Some(argument.to_string())),
+            entity_type::MENTION_NAME => ("<a href=\"", Some(user_url(argument))),
+            entity_type::CUSTOM_EMOJI => ("<tg-emoji emoji-id=\"", Some(argument.to_string())),
+            entity_type::BLOCK_QUOTE => ("<blockquote", None),
+            entity_type::EXPANDABLE_BLOCK_QUOTE => ("<blockquote expandable", None),
+            _ => return,
+        };
+        out.push_str(prefix);
+        if let Some(attribute) = attribute {
+            escape(&attribute, out);
+            out.push('"');
+        }
+        out.push('>');
+    }
+
+    fn close(&mut self, entity: &MessageEntity, out: &mut String) {
+        let tag = match entity.entity_type() {
+            entity_type::BOLD => "</b>",
+            entity_type::ITALIC => "</i>",
+            entity_type::UNDERLINE => "</u>",
+            entity_type::STRIKETHROUGH => "</s>",
+            entity_type::SPOILER => "</tg-spoiler>",
+            entity_type::CODE => "</code>",
+            entity_type::PRE => "</pre>",
+            entity_type::PRE_CODE => "</code></pre>",
+            entity_type::TEXT_URL | entity_type::MENTION_NAME => "</a>",
+            entity_type::CUSTOM_EMOJI => "</tg-emoji>",
+            entity_type::BLOCK_QUOTE | entity_type::EXPANDABLE_BLOCK_QUOTE => "</blockquote>",
+            _ => return,
+        };
+        out.push_str(tag);
+    }
+
+    fn push_char(&mut self, c: char, _open: &[&MessageEntity], out: &mut String) {
+        push_escaped(c, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::render;
+
+    fn entity(entity_type: &str, offset: i32, length: i32) -> MessageEntity {
+        MessageEntity::new(entity_type, offset, length)
+    }
+
+    fn entity_with(entity_type: &str, offset: i32, length: i32, argument: &str) -> MessageEntity {
+        MessageEntity::with_argument(entity_type, offset, length, Some(argument))
+    }
+
+    #[test]
+    fn test_html_simple_tags() {
+        let text = parse_html(
+            "<b>b</b><strong>s</strong><i>i</i><em>e</em><u>u</u><ins>n</ins>\
+             <s>s</s><strike>k</strike><del>d</del><tg-spoiler>p</tg-spoiler>\
+             <span class=\"tg-spoiler\">q</span><code>c</code>",
+        )
+        .unwrap();
+        assert_eq!(text.text(), "bsieunskdpqc");
+        let types: Vec<&str> = text.entities().iter().map(|e| e.entity_type()).collect();
+        assert_eq!(
+            types,
+            [
+                entity_type::BOLD,
+                entity_type::BOLD,
+                entity_type::ITALIC,
+                entity_type::ITALIC,
+                entity_type::UNDERLINE,
+                entity_type::UNDERLINE,
+                entity_type::STRIKETHROUGH,
+                entity_type::STRIKETHROUGH,
+                entity_type::STRIKETHROUGH,
+                entity_type::SPOILER,
+                entity_type::SPOILER,
+                entity_type::CODE,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_links_and_emoji() {
+        let text = parse_html(
+            "<a href=\"https://example.com/?a=1&amp;b=2\">site</a> \
+             <a href='tg://user?id=42'>Bob</a> \
+             <tg-emoji emoji-id=\"5368324170671202286\">👍</tg-emoji>",
+        )
+        .unwrap();
+        assert_eq!(text.text(), "site Bob 👍");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity_with(entity_type::TEXT_URL, 0, 4, "https://example.com/?a=1&b=2"),
+                entity_with(entity_type::MENTION_NAME, 5, 3, "42"),
+                entity_with(entity_type::CUSTOM_EMOJI, 9, 2, "5368324170671202286"),
+            ]
+        );
+
+        assert_eq!(
+            parse_html("<tg-emoji>x</tg-emoji>").unwrap_err(),
+            TextParseError::InvalidCustomEmoji { offset: 0 }
+        );
+    }
+
+    #[test]
+    fn test_html_pre_code() {
+        let text =
+            parse_html("<pre><code class=\"language-rust\">fn main() {}</code></pre><pre>x</pre>")
+                .unwrap();
+        assert_eq!(text.text(), "fn main() {}x");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity_with(entity_type::PRE_CODE, 0, 12, "rust"),
+                entity(entity_type::PRE, 12, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_block_quotes() {
+        let text =
+            parse_html("<blockquote>a</blockquote><blockquote expandable>b</blockquote>").unwrap();
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::BLOCK_QUOTE, 0, 1),
+                entity(entity_type::EXPANDABLE_BLOCK_QUOTE, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_character_references() {
+        let text = parse_html("&lt;b&gt; &amp; &quot; &#65;&#x1F600; &nbsp; & x").unwrap();
+        assert_eq!(text.text(), "<b> & \" A😀 &nbsp; & x");
+        assert!(text.entities().is_empty());
+    }
+
+    #[test]
+    fn test_html_nested_utf16() {
+        let text = parse_html("😀<B>bold <I>both</I></B>").unwrap();
+        assert_eq!(text.text(), "😀bold both");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::BOLD, 2, 9),
+                entity(entity_type::ITALIC, 7, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_errors() {
+        assert_eq!(
+            parse_html("a <x>b</x>").unwrap_err(),
+            TextParseError::UnsupportedTag {
+                tag: "x".to_string(),
+                offset: 2
+            }
+        );
+        assert_eq!(
+            parse_html("<b>a</i>").unwrap_err(),
+            TextParseError::UnmatchedEndTag {
+                expected: "b".to_string(),
+                found: "i".to_string(),
+                offset: 4
+            }
+        );
+        assert_eq!(
+            parse_html("a</b>").unwrap_err(),
+            TextParseError::UnexpectedEndTag {
+                tag: "b".to_string(),
+                offset: 1
+            }
+        );
+        assert_eq!(
+            parse_html("x<i><b>y</b>").unwrap_err(),
+            TextParseError::MissingEndTag {
+                tag: "i".to_string(),
+                offset: 1
+            }
+        );
+        assert_eq!(
+            parse_html("<a href=\"x>y</a>").unwrap_err(),
+            TextParseError::UnclosedAttributeValue {
+                tag: "a".to_string(),
+                offset: 8
+            }
+        );
+        assert_eq!(
+            parse_html("<b").unwrap_err(),
+            TextParseError::UnclosedTag { offset: 0 }
+        );
+        assert_eq!(
+            parse_html("<span>x</span>").unwrap_err().to_string(),
+            "Unsupported start tag \"span\" at byte offset 0"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let text = FormattedText::with_entities(
+            "a<b & \"q\" code",
+            vec![
+                entity(entity_type::BOLD, 0, 1),
+                entity_with(entity_type::TEXT_URL, 2, 1, "https://x.com/?a&b"),
+                entity_with(entity_type::PRE_CODE, 10, 4, "py"),
+                entity(entity_type::MENTION_NAME, 0, 0),
+            ],
+        );
+        assert_eq!(
+            render(&text, &mut Html),
+            "<b>a</b>&lt;<a href=\"https://x.com/?a&amp;b\">b</a> &amp; &quot;q&quot; \
+             <pre><code class=\"language-py\">code</code></pre>"
+        );
+    }
+
+    #[test]
+    fn test_html_roundtrip() {
+        let sources = [
+            "<b>bold <i>both</i></b> <u>u</u> <s>s</s> <tg-spoiler>p</tg-spoiler>",
+            "<a href=\"https://example.com/?a=&quot;1&quot;\">x</a> <a href=\"tg://user?id=3\">u</a>",
+            "<tg-emoji emoji-id=\"12\">👍</tg-emoji> <code>&lt;tag&gt;</code>",
+            "<pre><code class=\"language-c\">int x;</code></pre><pre>raw</pre>",
+            "<blockquote>q</blockquote>\n<blockquote expandable>e</blockquote>",
+        ];
+        for source in sources {
+            let parsed = parse_html(source).unwrap();
+            assert_eq!(render(&parsed, &mut Html), source);
+        }
+    }
+}
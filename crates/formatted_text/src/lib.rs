@@ -18,10 +18,38 @@
 //!
 //! This is a simplified version of TDLib's FormattedText that contains
 //! the text content and associated formatting entities.
+//!
+//! Entity offsets and lengths are measured in UTF-16 code units, like in the
+//! Telegram API.
+//!
+//! ## Parsing
+//!
+//! [`FormattedText::parse`] turns Markdown, MarkdownV2 or HTML markup into a
+//! formatted text, like TDLib's `parseTextEntities`. The [`entity_type`]
+//! module lists the entity types the parsers produce.
+//! [`FormattedText::to_markdown_v2`] and [`FormattedText::to_html`] convert a
+//! formatted text back into markup, for example to edit a draft.
+//!
+//! ```rust
+//! use rustgram_formatted_text::{entity_type, FormattedText, ParseMode};
+//!
+//! let text = FormattedText::parse("*Hello* _world_", ParseMode::MarkdownV2).unwrap();
+//! assert_eq!(text.text(), "Hello world");
+//! assert_eq!(text.entities()[0].entity_type(), entity_type::BOLD);
+//! assert_eq!(text.to_html(), "<b>Hello</b> <i>world</i>");
+//! ```
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+pub mod entity_type;
+mod html;
+mod markdown;
+mod parse;
+mod render;
+
+pub use parse::{ParseMode, TextParseError};
+
 /// A text entity representing formatting (bold, italic, links, etc.)
 ///
 /// This is a simplified version. The full TDLib implementation includes
@@ -258,7 +286,7 @@ impl FormattedText {
         self.text.is_empty()
     }
 
-    /// Returns the length of the text.
+    /// Returns the length of the text in bytes.
     ///
     /// # Example
     ///
@@ -272,8 +300,154 @@ impl FormattedText {
         self.text.len()
     }
 
+    /// Returns the length of the text in UTF-16 code units, the unit of
+    /// entity offsets.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::FormattedText;
+    ///
+    /// let text = FormattedText::new("Hi 😀");
+    /// assert_eq!(text.utf16_len(), 5);
+    /// ```
+    #[must_use]
+    pub fn utf16_len(&self) -> i32 {
+        self.text.encode_utf16().count() as i32
+    }
+
+    /// Parses text with markup into a formatted text.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text with markup
+    /// * `parse_mode` - The markup language
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TextParseError`] with the byte offset of the problem if the
+    /// markup is malformed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::{FormattedText, ParseMode};
+    ///
+    /// let text = FormattedText::parse("<b>Hi</b>", ParseMode::Html).unwrap();
+    /// assert_eq!(text.text(), "Hi");
+    ///
+    /// let error = FormattedText::parse("<b>Hi", ParseMode::Html).unwrap_err();
+    /// assert_eq!(error.offset(), 0);
+    /// ```
+    pub fn parse(text: &str, parse_mode: ParseMode) -> Result<Self, TextParseError> {
+        match parse_mode {
+            ParseMode::Markdown => Self::parse_markdown(text),
+            ParseMode::MarkdownV2 => Self::parse_markdown_v2(text),
+            ParseMode::Html => Self::parse_html(text),
+        }
+    }
+
+    /// Parses legacy Markdown.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TextParseError`] if an entity or link is not closed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::FormattedText;
+    ///
+    /// let text = FormattedText::parse_markdown("*bold* [link](https://example.com)").unwrap();
+    /// assert_eq!(text.text(), "bold link");
+    /// assert_eq!(text.entities().len(), 2);
+    /// ```
+    pub fn parse_markdown(text: &str) -> Result<Self, TextParseError> {
+        markdown::parse_markdown(text)
+    }
+
+    /// Parses MarkdownV2.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TextParseError`] if an entity is not closed or a reserved
+    /// character is not escaped.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::FormattedText;
+    ///
+    /// let text = FormattedText::parse_markdown_v2("*bold _both_*\\!").unwrap();
+    /// assert_eq!(text.text(), "bold both!");
+    /// assert_eq!(text.entities().len(), 2);
+    ///
+    /// assert!(FormattedText::parse_markdown_v2("Hi!").is_err());
+    /// ```
+    pub fn parse_markdown_v2(text: &str) -> Result<Self, TextParseError> {
+        markdown::parse_markdown_v2(text)
+    }
+
+    /// Parses Telegram HTML.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TextParseError`] if a tag is unsupported, unclosed or
+    /// mismatched.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::FormattedText;
+    ///
+    /// let text = FormattedText::parse_html("<a href=\"tg://user?id=1\">me</a> &amp; you").unwrap();
+    /// assert_eq!(text.text(), "me & you");
+    /// assert_eq!(text.entities()[0].argument(), Some("1"));
+    /// ```
+    pub fn parse_html(text: &str) -> Result<Self, TextParseError> {
+        html::parse_html(text)
+    }
+
+    /// Converts the formatted text into MarkdownV2.
+    ///
+    /// Entities without a MarkdownV2 representation, like automatically
+    /// detected URLs, are left out.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::{FormattedText, MessageEntity};
+    ///
+    /// let text = FormattedText::with_entities("Hi, Bob!", vec![MessageEntity::new("bold", 4, 3)]);
+    /// assert_eq!(text.to_markdown_v2(), "Hi, *Bob*\\!");
+    /// ```
+    #[must_use]
+    pub fn to_markdown_v2(&self) -> String {
+        render::render(self, &mut markdown::MarkdownV2::default())
+    }
+
+    /// Converts the formatted text into Telegram HTML.
+    ///
+    /// Entities without an HTML representation, like automatically detected
+    /// URLs, are left out.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::{FormattedText, MessageEntity};
+    ///
+    /// let text = FormattedText::with_entities("a < b", vec![MessageEntity::new("italic", 0, 1)]);
+    /// assert_eq!(text.to_html(), "<i>a</i> &lt; b");
+    /// ```
+    #[must_use]
+    pub fn to_html(&self) -> String {
+        render::render(self, &mut html::Html)
+    }
+
     /// Validates that all entities are within bounds of the text.
     ///
+    /// Bounds are checked in UTF-16 code units.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -287,7 +461,7 @@ impl FormattedText {
     /// assert!(text.validate_entities().is_err());
     /// ```
     pub fn validate_entities(&self) -> Result<(), FormattedTextError> {
-        let text_len = self.text.len() as i32;
+        let text_len = self.utf16_len();
 
         for entity in &self.entities {
             if !entity.is_valid() {
@@ -568,6 +742,44 @@ mod tests {
         assert!(!text.validate_entities().is_ok());
     }
 
+    #[test]
+    fn test_validate_entities_utf16() {
+        let mut text = FormattedText::new("😀😀");
+        text.add_entity(MessageEntity::new("bold", 2, 2));
+        assert!(text.validate_entities().is_ok());
+
+        text.add_entity(MessageEntity::new("bold", 2, 3));
+        assert!(text.validate_entities().is_err());
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        let markdown = FormattedText::parse("*a*", ParseMode::Markdown).unwrap();
+        let markdown_v2 = FormattedText::parse("*a*", ParseMode::MarkdownV2).unwrap();
+        let html = FormattedText::parse("<b>a</b>", ParseMode::Html).unwrap();
+        assert_eq!(markdown, markdown_v2);
+        assert_eq!(markdown, html);
+        assert_eq!(
+            html.entities(),
+            &[MessageEntity::new(entity_type::BOLD, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn test_markdown_html_conversion() {
+        let html = "<b>bold <i>both</i></b> <a href=\"https://example.com\">link</a> \
+                    <pre><code class=\"language-rust\">let x = 1;</code></pre>";
+        let text = FormattedText::parse_html(html).unwrap();
+        let markdown = text.to_markdown_v2();
+        assert_eq!(
+            markdown,
+            "*bold _both_* [link](https://example.com) ```rust\nlet x = 1;```"
+        );
+        let reparsed = FormattedText::parse_markdown_v2(&markdown).unwrap();
+        assert_eq!(reparsed, text);
+        assert_eq!(reparsed.to_html(), html);
+    }
+
     #[test]
     fn test_entity_zero_length_at_valid_position() {
         let mut text = FormattedText::new("Hello");
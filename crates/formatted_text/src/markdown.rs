@@ -0,0 +1,758 @@
+// Copyright 2024 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Markdown parsing and rendering.
+//!
+//! ## TDLib Correspondence
+//!
+//! Mirrors `parse_markdown` and `parse_markdown_v2` in
+//! `td/telegram/MessageEntity.cpp`, including the MarkdownV2 rules that
+//! `__` is always greedily treated as underline and that a `\r` between
+//! underscores is ignored.
+
+use crate::entity_type;
+use crate::parse::{
+    add_link, char_at, emoji_url, parse_emoji_url, parse_pre_language, user_url, TextBuilder,
+    TextParseError,
+};
+use crate::render::Markup;
+use crate::{FormattedText, MessageEntity};
+
+/// Characters that must be escaped in MarkdownV2 text.
+const RESERVED_V2: &[u8] = b"_*[]()~`>#+-=|{}.!";
+
+/// Kind of an entity being parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Code,
+    Pre,
+    TextUrl,
+    CustomEmoji,
+}
+
+impl Kind {
+    /// Returns the name used in error messages.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Bold => "Bold",
+            Self::Italic => "Italic",
+            Self::Underline => "Underline",
+            Self::Strikethrough => "Strikethrough",
+            Self::Spoiler => "Spoiler",
+            Self::Code => "Code",
+            Self::Pre => "Pre",
+            Self::TextUrl => "TextUrl",
+            Self::CustomEmoji => "CustomEmoji",
+        }
+    }
+
+    /// Returns true if the content of the entity is taken literally.
+    fn is_code(self) -> bool {
+        matches!(self, Self::Code | Self::Pre)
+    }
+
+    /// Returns the length of the closing marker.
+    fn end_marker_len(self) -> usize {
+        match self {
+            Self::Underline | Self::Spoiler => 2,
+            Self::Pre => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// An entity whose end hasn't been found yet.
+struct OpenEntity {
+    /// Kind of the entity
+    kind: Kind,
+    /// Start in UTF-16 code units of the plain text
+    offset: i32,
+    /// Start in bytes of the source text
+    begin: usize,
+    /// Start in bytes of the plain text
+    text_begin: usize,
+    /// Language of a code block
+    language: Option<String>,
+}
+
+/// A block quotation whose end hasn't been found yet.
+struct OpenQuote {
+    /// Start in UTF-16 code units of the plain text
+    offset: i32,
+}
+
+/// Parses legacy Markdown.
+pub(crate) fn parse_markdown(text: &str) -> Result<FormattedText, TextParseError> {
+    let bytes = text.as_bytes();
+    let mut out = TextBuilder::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let next = bytes.get(i + 1).copied().unwrap_or(0);
+        if c == b'\\' && matches!(next, b'_' | b'*' | b'`' | b'[') {
+            out.push(char::from(next));
+            i += 2;
+            continue;
+        }
+        if !matches!(c, b'_' | b'*' | b'`' | b'[') {
+            let ch = char_at(text, i);
+            out.push(ch);
+            i += ch.len_utf8();
+            continue;
+        }
+
+        let begin = i;
+        let is_pre = bytes[i..].starts_with(b"```");
+        let kind = match c {
+            b'_' => Kind::Italic,
+            b'*' => Kind::Bold,
+            b'[' => Kind::TextUrl,
+            _ if is_pre => Kind::Pre,
+            _ => Kind::Code,
+        };
+        let end_char = if c == b'[' { b']' } else { c };
+        let mut language = None;
+        i += 1;
+        if is_pre {
+            (language, i) = parse_pre_language(text, i + 2);
+        }
+
+        let offset = out.utf16_offset();
+        let text_begin = out.byte_offset();
+        loop {
+            match bytes.get(i) {
+                None => {
+                    return Err(TextParseError::UnclosedEntity {
+                        entity: kind.name(),
+                        offset: begin,
+                    })
+                }
+                Some(&b) if b == end_char && (!is_pre || bytes[i..].starts_with(b"```")) => {
+                    break;
+                }
+                Some(_) => {
+                    let ch = char_at(text, i);
+                    out.push(ch);
+                    i += ch.len_utf8();
+                }
+            }
+        }
+        i += if is_pre { 3 } else { 1 };
+
+        match kind {
+            Kind::Italic => out.add_entity(entity_type::ITALIC, offset, None),
+            Kind::Bold => out.add_entity(entity_type::BOLD, offset, None),
+            Kind::Code => out.add_entity(entity_type::CODE, offset, None),
+            Kind::Pre => add_pre(&mut out, offset, language),
+            _ => {
+                let url = if bytes.get(i) == Some(&b'(') {
+                    let end = text[i..]
+                        .find(')')
+                        .ok_or(TextParseError::UnclosedUrl { offset: i })?;
+                    let url = text[i + 1..i + end].to_string();
+                    i += end + 1;
+                    url
+                } else {
+                    out.text_from(text_begin).to_string()
+                };
+                add_link(&mut out, offset, &url);
+            }
+        }
+    }
+    Ok(out.finish())
+}
+
+/// Parses MarkdownV2.
+pub(crate) fn parse_markdown_v2(text: &str) -> Result<FormattedText, TextParseError> {
+    let bytes = text.as_bytes();
+    let at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let mut out = TextBuilder::new();
+    let mut nested: Vec<OpenEntity> = Vec::new();
+    let mut quote: Option<OpenQuote> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let in_code = nested.last().is_some_and(|entity| entity.kind.is_code());
+        let is_line_start = i == 0 || bytes[i - 1] == b'\n';
+        if is_line_start && !in_code {
+            if bytes[i] == b'>' || bytes[i..].starts_with(b"**>") {
+                if bytes[i] == b'*' {
+                    // An expandable quotation always starts a new quotation
+                    close_quote(&mut out, quote.take(), entity_type::BLOCK_QUOTE);
+                    i += 2;
+                }
+                if quote.is_none() {
+                    quote = Some(OpenQuote {
+                        offset: out.utf16_offset(),
+                    });
+                }
+                i += 1;
+                continue;
+            }
+            close_quote(&mut out, quote.take(), entity_type::BLOCK_QUOTE);
+        }
+
+        let c = bytes[i];
+        if c == b'\\' && (1..=126).contains(&at(i + 1)) {
+            out.push(char::from(at(i + 1)));
+            i += 2;
+            continue;
+        }
+        if c == b'\r' && i > 0 && bytes[i - 1] == b'_' && at(i + 1) == b'_' {
+            // Separates italic and underline markers
+            i += 1;
+            continue;
+        }
+        if !RESERVED_V2.contains(&c) || (in_code && c != b'`') {
+            let ch = char_at(text, i);
+            out.push(ch);
+            i += ch.len_utf8();
+            continue;
+        }
+
+        // The expandability mark at the end of a quotation
+        let is_top_spoiler = nested.last().is_some_and(|e| e.kind == Kind::Spoiler);
+        if c == b'|'
+            && at(i + 1) == b'|'
+            && quote.is_some()
+            && !is_top_spoiler
+            && matches!(at(i + 2), 0 | b'\n')
+        {
+            close_quote(&mut out, quote.take(), entity_type::EXPANDABLE_BLOCK_QUOTE);
+            i += 2;
+            continue;
+        }
+
+        if let Some(top) = nested.last() {
+            let is_end_of_entity = match top.kind {
+                Kind::Bold => c == b'*',
+                Kind::Italic => c == b'_' && at(i + 1) != b'_',
+                Kind::Underline => c == b'_' && at(i + 1) == b'_',
+                Kind::Strikethrough => c == b'~',
+                Kind::Spoiler => c == b'|' && at(i + 1) == b'|',
+                Kind::Code => c == b'`',
+                Kind::Pre => bytes[i..].starts_with(b"```"),
+                Kind::TextUrl | Kind::CustomEmoji => c == b']',
+            };
+            if is_end_of_entity {
+                if let Some(entity) = nested.pop() {
+                    i += entity.kind.end_marker_len();
+                    i = close_entity_v2(text, i, entity, &mut out)?;
+                }
+                continue;
+            }
+            if top.kind.is_code() {
+                // A lone backtick inside a code block
+                out.push('`');
+                i += 1;
+                continue;
+            }
+        }
+
+        let (kind, marker_len) = match c {
+            b'_' if at(i + 1) == b'_' => (Kind::Underline, 2),
+            b'_' => (Kind::Italic, 1),
+            b'*' => (Kind::Bold, 1),
+            b'~' => (Kind::Strikethrough, 1),
+            b'|' if at(i + 1) == b'|' => (Kind::Spoiler, 2),
+            b'[' => (Kind::TextUrl, 1),
+            b'!' if at(i + 1) == b'[' => (Kind::CustomEmoji, 2),
+            b'`' if bytes[i..].starts_with(b"```") => (Kind::Pre, 3),
+            b'`' => (Kind::Code, 1),
+            _ => {
+                return Err(TextParseError::ReservedCharacter {
+                    character: char::from(c),
+                    offset: i,
+                })
+            }
+        };
+        let begin = i;
+        let mut language = None;
+        i += marker_len;
+        if kind == Kind::Pre {
+            (language, i) = parse_pre_language(text, i);
+        }
+        nested.push(OpenEntity {
+            kind,
+            offset: out.utf16_offset(),
+            begin,
+            text_begin: out.byte_offset(),
+            language,
+        });
+    }
+
+    if let Some(entity) = nested.last() {
+        return Err(TextParseError::UnclosedEntity {
+            entity: entity.kind.name(),
+            offset: entity.begin,
+        });
+    }
+    close_quote(&mut out, quote, entity_type::BLOCK_QUOTE);
+    Ok(out.finish())
+}
+
+/// Finishes a MarkdownV2 entity whose closing marker ends at byte `i`.
+///
+/// Returns the offset right after the entity, including the URL of a link.
+fn close_entity_v2(
+    text: &str,
+    mut i: usize,
+    entity: OpenEntity,
+    out: &mut TextBuilder,
+) -> Result<usize, TextParseError> {
+    let offset = entity.offset;
+    match entity.kind {
+        Kind::Bold => out.add_entity(entity_type::BOLD, offset, None),
+        Kind::Italic => out.add_entity(entity_type::ITALIC, offset, None),
+        Kind::Underline => out.add_entity(entity_type::UNDERLINE, offset, None),
+        Kind::Strikethrough => out.add_entity(entity_type::STRIKETHROUGH, offset, None),
+        Kind::Spoiler => out.add_entity(entity_type::SPOILER, offset, None),
+        Kind::Code => out.add_entity(entity_type::CODE, offset, None),
+        Kind::Pre => add_pre(out, offset, entity.language),
+        Kind::TextUrl | Kind::CustomEmoji => {
+            let url = if text.as_bytes().get(i) == Some(&b'(') {
+                let (url, next) = parse_url_v2(text, i)?;
+                i = next;
+                url
+            } else {
+                out.text_from(entity.text_begin).to_string()
+            };
+            if entity.kind == Kind::TextUrl {
+                add_link(out, offset, &url);
+            } else {
+                let emoji_id = parse_emoji_url(&url).ok_or(TextParseError::InvalidCustomEmoji {
+                    offset: entity.begin,
+                })?;
+                out.add_entity(
+                    entity_type::CUSTOM_EMOJI,
+                    offset,
+                    Some(emoji_id.to_string()),
+                );
+            }
+        }
+    }
+    Ok(i)
+}
+
+/// Parses a parenthesized MarkdownV2 URL starting at byte `i`.
+fn parse_url_v2(text: &str, i: usize) -> Result<(String, usize), TextParseError> {
+    let mut url = String::new();
+    let mut j = i + 1;
+    loop {
+        match text.as_bytes().get(j) {
+            None => return Err(TextParseError::UnclosedUrl { offset: i }),
+            Some(b')') => return Ok((url, j + 1)),
+            Some(b'\\') if j + 1 < text.len() => j += 1,
+            Some(_) => {}
+        }
+        let ch = char_at(text, j);
+        url.push(ch);
+        j += ch.len_utf8();
+    }
+}
+
+/// Adds a code block entity.
+fn add_pre(out: &mut TextBuilder, offset: i32, language: Option<String>) {
+    match language {
+        Some(language) => out.add_entity(entity_type::PRE_CODE, offset, Some(language)),
+        None => out.add_entity(entity_type::PRE, offset, None),
+    }
+}
+
+/// Adds a block quotation ending at the end of the previous line.
+fn close_quote(out: &mut TextBuilder, quote: Option<OpenQuote>, entity_type: &str) {
+    if let Some(quote) = quote {
+        let end = out.utf16_offset() - i32::from(out.ends_with('\n'));
+        out.add_entity_range(entity_type, quote.offset, end, None);
+    }
+}
+
+/// Renders entities as MarkdownV2.
+#[derive(Default)]
+pub(crate) struct MarkdownV2 {
+    /// True if the output ends with an underscore marker
+    after_underscore: bool,
+}
+
+impl MarkdownV2 {
+    /// Writes a marker, separating adjacent underscore markers with `\r`.
+    fn push_marker(&mut self, marker: &str, out: &mut String) {
+        if marker.is_empty() {
+            return;
+        }
+        if self.after_underscore && marker.starts_with('_') {
+            out.push('\r');
+        }
+        out.push_str(marker);
+        self.after_underscore = marker.ends_with('_');
+    }
+}
+
+impl Markup for MarkdownV2 {
+    fn is_supported(&self, entity: &MessageEntity) -> bool {
+        matches!(
+            entity.entity_type(),
+            entity_type::BOLD
+                | entity_type::ITALIC
+                | entity_type::UNDERLINE
+                | entity_type::STRIKETHROUGH
+                | entity_type::SPOILER
+                | entity_type::CODE
+                | entity_type::PRE
+                | entity_type::PRE_CODE
+                | entity_type::TEXT_URL
+                | entity_type::MENTION_NAME
+                | entity_type::CUSTOM_EMOJI
+                | entity_type::BLOCK_QUOTE
+                | entity_type::EXPANDABLE_BLOCK_QUOTE
+        )
+    }
+
+    fn open(&mut self, entity: &MessageEntity, out: &mut String) {
+        let marker = match entity.entity_type() {
+            entity_type::BOLD => "*".to_string(),
+            entity_type::ITALIC => "_".to_string(),
+            entity_type::UNDERLINE => "__".to_string(),
+            entity_type::STRIKETHROUGH => "~".to_string(),
+            entity_type::SPOILER => "||".to_string(),
+            entity_type::CODE => "`".to_string(),
+            entity_type::PRE => "```\n".to_string(),
+            entity_type::PRE_CODE => format!("```{}\n", entity.argument().unwrap_or_default()),
+            entity_type::TEXT_URL | entity_type::MENTION_NAME => "[".to_string(),
+            entity_type::CUSTOM_EMOJI => "![".to_string(),
+            entity_type::BLOCK_QUOTE => ">".to_string(),
+            entity_type::EXPANDABLE_BLOCK_QUOTE => "**>".to_string(),
+            _ => String::new(),
+        };
+        self.push_marker(&marker, out);
+    }
+
+    fn close(&mut self, entity: &MessageEntity, out: &mut String) {
+        let argument = entity.argument().unwrap_or_default();
+        let marker = match entity.entity_type() {
+            entity_type::BOLD => "*".to_string(),
+            entity_type::ITALIC => "_".to_string(),
+            entity_type::UNDERLINE => "__".to_string(),
+            entity_type::STRIKETHROUGH => "~".to_string(),
+            entity_type::SPOILER | entity_type::EXPANDABLE_BLOCK_QUOTE => "||".to_string(),
+            entity_type::CODE => "`".to_string(),
+            entity_type::PRE | entity_type::PRE_CODE => "```".to_string(),
+            entity_type::TEXT_URL => format!("]({})", escape_url_v2(argument)),
+            entity_type::MENTION_NAME => format!("]({})", user_url(argument)),
+            entity_type::CUSTOM_EMOJI => format!("]({})", emoji_url(argument)),
+            _ => String::new(),
+        };
+        self.push_marker(&marker, out);
+    }
+
+    fn push_char(&mut self, c: char, open: &[&MessageEntity], out: &mut String) {
+        let in_code = open.iter().any(|entity| {
+            matches!(
+                entity.entity_type(),
+                entity_type::CODE | entity_type::PRE | entity_type::PRE_CODE
+            )
+        });
+        let needs_escape = if in_code {
+            c == '`' || c == '\\'
+        } else {
+            c == '\\' || (c.is_ascii() && RESERVED_V2.contains(&(c as u8)))
+        };
+        if needs_escape {
+            out.push('\\');
+        }
+        out.push(c);
+        self.after_underscore = false;
+
+        let in_quote = open.iter().any(|entity| {
+            matches!(
+                entity.entity_type(),
+                entity_type::BLOCK_QUOTE | entity_type::EXPANDABLE_BLOCK_QUOTE
+            )
+        });
+        if c == '\n' && in_quote {
+            out.push('>');
+        }
+    }
+}
+
+/// Escapes a URL inside MarkdownV2 parentheses.
+fn escape_url_v2(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        if c == ')' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::render;
+
+    fn entity(entity_type: &str, offset: i32, length: i32) -> MessageEntity {
+        MessageEntity::new(entity_type, offset, length)
+    }
+
+    fn entity_with(entity_type: &str, offset: i32, length: i32, argument: &str) -> MessageEntity {
+        MessageEntity::with_argument(entity_type, offset, length, Some(argument))
+    }
+
+    #[test]
+    fn test_markdown_v2_simple_entities() {
+        let text = parse_markdown_v2("*bold* _italic_ __underline__ ~strike~ ||spoiler||").unwrap();
+        assert_eq!(text.text(), "bold italic underline strike spoiler");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::BOLD, 0, 4),
+                entity(entity_type::ITALIC, 5, 6),
+                entity(entity_type::UNDERLINE, 12, 9),
+                entity(entity_type::STRIKETHROUGH, 22, 6),
+                entity(entity_type::SPOILER, 29, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_nested() {
+        let text = parse_markdown_v2("*bold _italic bold ~strike~ __under__ ||sp||_*").unwrap();
+        assert_eq!(text.text(), "bold italic bold strike under sp");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::BOLD, 0, 32),
+                entity(entity_type::ITALIC, 5, 27),
+                entity(entity_type::STRIKETHROUGH, 17, 6),
+                entity(entity_type::UNDERLINE, 24, 5),
+                entity(entity_type::SPOILER, 30, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_italic_underline_separator() {
+        let text = parse_markdown_v2("___italic underline_\r__").unwrap();
+        assert_eq!(text.text(), "italic underline");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::ITALIC, 0, 16),
+                entity(entity_type::UNDERLINE, 0, 16)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_escapes() {
+        let text = parse_markdown_v2(r"1\. a\_b \*c\* \\ \(\)").unwrap();
+        assert_eq!(text.text(), r"1. a_b *c* \ ()");
+        assert!(text.entities().is_empty());
+    }
+
+    #[test]
+    fn test_markdown_v2_reserved_character() {
+        let error = parse_markdown_v2("Hello, world!").unwrap_err();
+        assert_eq!(
+            error,
+            TextParseError::ReservedCharacter {
+                character: '!',
+                offset: 12
+            }
+        );
+        assert_eq!(parse_markdown_v2("a.b").unwrap_err().offset(), 1);
+    }
+
+    #[test]
+    fn test_markdown_v2_unclosed_entity() {
+        let error = parse_markdown_v2("plain *bold _italic_").unwrap_err();
+        assert_eq!(
+            error,
+            TextParseError::UnclosedEntity {
+                entity: "Bold",
+                offset: 6
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Can't find end of Bold entity at byte offset 6"
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_code() {
+        let text = parse_markdown_v2(
+            r"`a_b*c\`` ```rust
+fn main() {}
+```",
+        )
+        .unwrap();
+        assert_eq!(text.text(), "a_b*c` fn main() {}\n");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::CODE, 0, 6),
+                entity_with(entity_type::PRE_CODE, 7, 13, "rust"),
+            ]
+        );
+
+        let text = parse_markdown_v2("```\nplain```").unwrap();
+        assert_eq!(text.entities(), &[entity(entity_type::PRE, 0, 5)]);
+    }
+
+    #[test]
+    fn test_markdown_v2_links() {
+        let text = parse_markdown_v2(
+            r"[site](https://example.com/a_\(b\)) [John](tg://user?id=123) [https://t\.me]",
+        )
+        .unwrap();
+        assert_eq!(text.text(), "site John https://t.me");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity_with(entity_type::TEXT_URL, 0, 4, "https://example.com/a_(b)"),
+                entity_with(entity_type::MENTION_NAME, 5, 4, "123"),
+                entity_with(entity_type::TEXT_URL, 10, 12, "https://t.me"),
+            ]
+        );
+
+        let error = parse_markdown_v2("[a](https://example.com").unwrap_err();
+        assert_eq!(error, TextParseError::UnclosedUrl { offset: 3 });
+    }
+
+    #[test]
+    fn test_markdown_v2_custom_emoji() {
+        let text = parse_markdown_v2("![👍](tg://emoji?id=5368324170671202286)").unwrap();
+        assert_eq!(text.text(), "👍");
+        assert_eq!(
+            text.entities(),
+            &[entity_with(
+                entity_type::CUSTOM_EMOJI,
+                0,
+                2,
+                "5368324170671202286"
+            )]
+        );
+
+        let error = parse_markdown_v2("x ![👍](https://example.com)").unwrap_err();
+        assert_eq!(error, TextParseError::InvalidCustomEmoji { offset: 2 });
+    }
+
+    #[test]
+    fn test_markdown_v2_block_quotes() {
+        let text = parse_markdown_v2(">first\n>second\nafter").unwrap();
+        assert_eq!(text.text(), "first\nsecond\nafter");
+        assert_eq!(text.entities(), &[entity(entity_type::BLOCK_QUOTE, 0, 12)]);
+
+        let text = parse_markdown_v2("**>hidden\n>more||\n>next").unwrap();
+        assert_eq!(text.text(), "hidden\nmore\nnext");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::EXPANDABLE_BLOCK_QUOTE, 0, 11),
+                entity(entity_type::BLOCK_QUOTE, 12, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_utf16_offsets() {
+        let text = parse_markdown_v2("😀 *жирный*").unwrap();
+        assert_eq!(text.entities(), &[entity(entity_type::BOLD, 3, 6)]);
+
+        // Error offsets are in bytes of the source text
+        assert_eq!(parse_markdown_v2("😀 _x").unwrap_err().offset(), 5);
+    }
+
+    #[test]
+    fn test_markdown_legacy() {
+        let text = parse_markdown(
+            "*bold* _it_ `co_de` ```py\nx=1``` [link](https://example.com) [me](tg://user?id=7) \\*",
+        )
+        .unwrap();
+        assert_eq!(text.text(), "bold it co_de x=1 link me *");
+        assert_eq!(
+            text.entities(),
+            &[
+                entity(entity_type::BOLD, 0, 4),
+                entity(entity_type::ITALIC, 5, 2),
+                entity(entity_type::CODE, 8, 5),
+                entity_with(entity_type::PRE_CODE, 14, 3, "py"),
+                entity_with(entity_type::TEXT_URL, 18, 4, "https://example.com"),
+                entity_with(entity_type::MENTION_NAME, 23, 2, "7"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_legacy_errors() {
+        assert_eq!(
+            parse_markdown("a *b").unwrap_err(),
+            TextParseError::UnclosedEntity {
+                entity: "Bold",
+                offset: 2
+            }
+        );
+        assert_eq!(
+            parse_markdown("[a](b").unwrap_err(),
+            TextParseError::UnclosedUrl { offset: 3 }
+        );
+        // No reserved characters in legacy Markdown
+        assert_eq!(parse_markdown("a.b!").unwrap().text(), "a.b!");
+    }
+
+    #[test]
+    fn test_render_markdown_v2() {
+        let text = FormattedText::with_entities(
+            "bold link 1.5 code_",
+            vec![
+                entity(entity_type::BOLD, 0, 4),
+                entity_with(entity_type::TEXT_URL, 5, 4, "https://example.com/(x)"),
+                entity(entity_type::CODE, 14, 5),
+            ],
+        );
+        assert_eq!(
+            render(&text, &mut MarkdownV2::default()),
+            r"*bold* [link](https://example.com/(x\)) 1\.5 `code_`"
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_roundtrip() {
+        let sources = [
+            "*bold _italic_* __under__\r_it_ ~s~ ||sp||",
+            "___both_\r__",
+            r"[a\.b](https://example.com) [u](tg://user?id=5) ![👍](tg://emoji?id=12)",
+            "```rust\nlet x = `y`;\n```",
+            ">quote *bold*\n>line\nplain",
+            "**>expand\n>more||",
+            r"😀 \* \\ `\`` x",
+        ];
+        for source in sources {
+            let parsed = parse_markdown_v2(source).unwrap();
+            let rendered = render(&parsed, &mut MarkdownV2::default());
+            assert_eq!(
+                parse_markdown_v2(&rendered).unwrap(),
+                parsed,
+                "{rendered:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Copyright 2024 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shared parts of the text parsers.
+
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{entity_type, FormattedText, MessageEntity};
+
+/// Prefix of links mentioning a user by identifier.
+const USER_URL_PREFIX: &str = "tg://user?id=";
+
+/// Prefix of links identifying a custom emoji.
+const EMOJI_URL_PREFIX: &str = "tg://emoji?id=";
+
+/// Markup language of a text to parse.
+///
+/// Corresponds to TDLib's `textParseModeMarkdown` (versions 1 and 2) and
+/// `textParseModeHTML`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ParseMode {
+    /// Legacy Markdown: `*bold*`, `_italic_`, `` `code` ``, ```` ```pre``` ````
+    /// and `[text](url)`, without nesting.
+    Markdown,
+    /// Telegram MarkdownV2 with nesting, escaping and block quotations.
+    MarkdownV2,
+    /// The HTML subset supported by Telegram.
+    Html,
+}
+
+/// Error returned when a text can't be parsed.
+///
+/// Every error carries the byte offset in the source text where the problem
+/// was found, available through [`TextParseError::offset`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TextParseError {
+    /// An entity is started but never finished.
+    #[error("Can't find end of {entity} entity at byte offset {offset}")]
+    UnclosedEntity {
+        /// Name of the unfinished entity
+        entity: &'static str,
+        /// Offset of the entity start
+        offset: usize,
+    },
+
+    /// A MarkdownV2 reserved character is used without escaping.
+    #[error("Character '{character}' is reserved and must be escaped with the preceding '\\' at byte offset {offset}")]
+    ReservedCharacter {
+        /// The reserved character
+        character: char,
+        /// Offset of the character
+        offset: usize,
+    },
+
+    /// The URL of a link has no closing parenthesis.
+    #[error("Can't find end of a URL at byte offset {offset}")]
+    UnclosedUrl {
+        /// Offset of the opening parenthesis
+        offset: usize,
+    },
+
+    /// A custom emoji has no valid `tg://emoji?id=` identifier.
+    #[error("Custom emoji entity must contain a tg://emoji URL at byte offset {offset}")]
+    InvalidCustomEmoji {
+        /// Offset of the entity start
+        offset: usize,
+    },
+
+    /// An HTML tag is not supported.
+    #[error("Unsupported start tag \"{tag}\" at byte offset {offset}")]
+    UnsupportedTag {
+        /// Name of the tag
+        tag: String,
+        /// Offset of the tag
+        offset: usize,
+    },
+
+    /// An HTML tag has no closing `>`.
+    #[error("Unclosed tag at byte offset {offset}")]
+    UnclosedTag {
+        /// Offset of the tag
+        offset: usize,
+    },
+
+    /// An HTML attribute can't be parsed.
+    #[error("Invalid attribute of the tag \"{tag}\" at byte offset {offset}")]
+    InvalidAttribute {
+        /// Name of the tag
+        tag: String,
+        /// Offset of the attribute
+        offset: usize,
+    },
+
+    /// An HTML attribute value has no closing quote.
+    #[error("Unclosed value of an attribute of the tag \"{tag}\" at byte offset {offset}")]
+    UnclosedAttributeValue {
+        /// Name of the tag
+        tag: String,
+        /// Offset of the attribute value
+        offset: usize,
+    },
+
+    /// An HTML end tag has no corresponding start tag.
+    #[error("Unexpected end tag \"{tag}\" at byte offset {offset}")]
+    UnexpectedEndTag {
+        /// Name of the end tag
+        tag: String,
+        /// Offset of the end tag
+        offset: usize,
+    },
+
+    /// An HTML end tag doesn't match the innermost start tag.
+    #[error("Unmatched end tag at byte offset {offset}, expected \"</{expected}>\", found \"</{found}>\"")]
+    UnmatchedEndTag {
+        /// Name of the innermost open tag
+        expected: String,
+        /// Name of the end tag
+        found: String,
+        /// Offset of the end tag
+        offset: usize,
+    },
+
+    /// An HTML start tag is never closed.
+    #[error("Can't find end tag corresponding to start tag \"{tag}\" at byte offset {offset}")]
+    MissingEndTag {
+        /// Name of the start tag
+        tag: String,
+        /// Offset of the start tag
+        offset: usize,
+    },
+}
+
+impl TextParseError {
+    /// Returns the byte offset in the source text where the error was found.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_formatted_text::FormattedText;
+    ///
+    /// let error = FormattedText::parse_markdown_v2("Hello *world").unwrap_err();
+    /// assert_eq!(error.offset(), 6);
+    /// ```
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::UnclosedEntity { offset, .. }
+            | Self::ReservedCharacter { offset, .. }
+            | Self::UnclosedUrl { offset }
+            | Self::InvalidCustomEmoji { offset }
+            | Self::UnsupportedTag { offset, .. }
+            | Self::UnclosedTag { offset }
+            | Self::InvalidAttribute { offset, .. }
+            | Self::UnclosedAttributeValue { offset, .. }
+            | Self::UnexpectedEndTag { offset, .. }
+            | Self::UnmatchedEndTag { offset, .. }
+            | Self::MissingEndTag { offset, .. } => *offset,
+        }
+    }
+}
+
+/// Accumulates the plain text and entities produced by a parser.
+pub(crate) struct TextBuilder {
+    /// Plain text parsed so far
+    text: String,
+    /// Length of `text` in UTF-16 code units
+    utf16_offset: i32,
+    /// Finished entities
+    entities: Vec<MessageEntity>,
+}
+
+impl TextBuilder {
+    /// Creates an empty builder.
+    pub(crate) fn new() -> Self {
+        Self {
+            text: String::new(),
+            utf16_offset: 0,
+            entities: Vec::new(),
+        }
+    }
+
+    /// Appends a character to the plain text.
+    pub(crate) fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.utf16_offset += c.len_utf16() as i32;
+    }
+
+    /// Returns the current position in UTF-16 code units.
+    pub(crate) fn utf16_offset(&self) -> i32 {
+        self.utf16_offset
+    }
+
+    /// Returns the current position in bytes of the plain text.
+    pub(crate) fn byte_offset(&self) -> usize {
+        self.text.len()
+    }
+
+    /// Returns the plain text starting at byte `begin`.
+    pub(crate) fn text_from(&self, begin: usize) -> &str {
+        self.text.get(begin..).unwrap_or_default()
+    }
+
+    /// Returns true if the plain text ends with `c`.
+    pub(crate) fn ends_with(&self, c: char) -> bool {
+        self.text.ends_with(c)
+    }
+
+    /// Adds an entity spanning from `offset` to the current position.
+    pub(crate) fn add_entity(&mut self, entity_type: &str, offset: i32, argument: Option<String>) {
+        self.add_entity_range(entity_type, offset, self.utf16_offset, argument);
+    }
+
+    /// Adds an entity spanning from `offset` to `end`; empty entities are dropped.
+    pub(crate) fn add_entity_range(
+        &mut self,
+        entity_type: &str,
+        offset: i32,
+        end: i32,
+        argument: Option<String>,
+    ) {
+        if end > offset {
+            self.entities.push(MessageEntity::with_argument(
+                entity_type,
+                offset,
+                end - offset,
+                argument.as_deref(),
+            ));
+        }
+    }
+
+    /// Returns the parsed text with entities ordered by offset, outer first.
+    pub(crate) fn finish(mut self) -> FormattedText {
+        self.entities.sort_by(|a, b| {
+            (a.offset(), Reverse(a.length()), a.entity_type()).cmp(&(
+                b.offset(),
+                Reverse(b.length()),
+                b.entity_type(),
+            ))
+        });
+        FormattedText::with_entities(&self.text, self.entities)
+    }
+}
+
+/// Adds a link entity, turning `tg://user` links into user mentions.
+pub(crate) fn add_link(out: &mut TextBuilder, offset: i32, url: &str) {
+    let url = url.trim();
+    if let Some(user_id) = parse_user_url(url) {
+        out.add_entity(entity_type::MENTION_NAME, offset, Some(user_id.to_string()));
+    } else if !url.is_empty() {
+        out.add_entity(entity_type::TEXT_URL, offset, Some(url.to_string()));
+    }
+}
+
+/// Returns the user identifier of a `tg://user?id=` link.
+pub(crate) fn parse_user_url(url: &str) -> Option<i64> {
+    url.strip_prefix(USER_URL_PREFIX)?
+        .parse::<i64>()
+        .ok()
+        .filter(|&user_id| user_id > 0)
+}
+
+/// Returns the custom emoji identifier of a `tg://emoji?id=` link.
+pub(crate) fn parse_emoji_url(url: &str) -> Option<i64> {
+    url.strip_prefix(EMOJI_URL_PREFIX)?
+        .parse::<i64>()
+        .ok()
+        .filter(|&emoji_id| emoji_id != 0)
+}
+
+/// Returns the `tg://user?id=` link for a user.
+pub(crate) fn user_url(user_id: &str) -> String {
+    format!("{USER_URL_PREFIX}{user_id}")
+}
+
+/// Returns the `tg://emoji?id=` link for a custom emoji.
+pub(crate) fn emoji_url(emoji_id: &str) -> String {
+    format!("{EMOJI_URL_PREFIX}{emoji_id}")
+}
+
+/// Returns the character starting at byte `i` of `text`.
+pub(crate) fn char_at(text: &str, i: usize) -> char {
+    text.get(i..)
+        .and_then(|rest| rest.chars().next())
+        .unwrap_or('\0')
+}
+
+/// Parses the optional language line after the opening backticks of a code
+/// block starting at byte `i`.
+///
+/// Returns the language and the offset of the block content. The language
+/// line is consumed only if it is followed by a newline and contains no
+/// backticks or whitespace.
+pub(crate) fn parse_pre_language(text: &str, i: usize) -> (Option<String>, usize) {
+    let rest = text.get(i..).unwrap_or_default();
+    match rest.find('\n') {
+        Some(newline) if !rest[..newline].contains(|c: char| c == '`' || c.is_whitespace()) => {
+            let language = &rest[..newline];
+            let language = (!language.is_empty()).then(|| language.to_string());
+            (language, i + newline + 1)
+        }
+        _ => (None, i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_utf16_offsets() {
+        let mut builder = TextBuilder::new();
+        for c in "a😀b".chars() {
+            builder.push(c);
+        }
+        assert_eq!(builder.utf16_offset(), 4);
+        assert_eq!(builder.byte_offset(), 6);
+
+        builder.add_entity("bold", 1, None);
+        builder.add_entity_range("italic", 2, 2, None);
+        let text = builder.finish();
+        assert_eq!(text.entities(), &[MessageEntity::new("bold", 1, 3)]);
+    }
+
+    #[test]
+    fn test_special_urls() {
+        assert_eq!(parse_user_url("tg://user?id=123"), Some(123));
+        assert_eq!(parse_user_url("tg://user?id=-5"), None);
+        assert_eq!(parse_user_url("https://t.me"), None);
+        assert_eq!(
+            parse_emoji_url("tg://emoji?id=5368324170671202286"),
+            Some(5368324170671202286)
+        );
+        assert_eq!(parse_emoji_url("tg://emoji?id=abc"), None);
+        assert_eq!(user_url("42"), "tg://user?id=42");
+    }
+
+    #[test]
+    fn test_pre_language() {
+        assert_eq!(
+            parse_pre_language("```rust\nfn", 3),
+            (Some("rust".to_string()), 8)
+        );
+        assert_eq!(parse_pre_language("```\nfn", 3), (None, 4));
+        assert_eq!(parse_pre_language("```a b\nfn", 3), (None, 3));
+        assert_eq!(parse_pre_language("```x```\n", 3), (None, 3));
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2024 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of entities back into markup.
+
+use std::cmp::Reverse;
+
+use crate::{FormattedText, MessageEntity};
+
+/// A markup language entities can be rendered into.
+pub(crate) trait Markup {
+    /// Returns true if the entity type has a representation in the markup.
+    fn is_supported(&self, entity: &MessageEntity) -> bool;
+
+    /// Writes the start of an entity.
+    fn open(&mut self, entity: &MessageEntity, out: &mut String);
+
+    /// Writes the end of an entity.
+    fn close(&mut self, entity: &MessageEntity, out: &mut String);
+
+    /// Writes a character of the text inside the `open` entities.
+    fn push_char(&mut self, c: char, open: &[&MessageEntity], out: &mut String);
+}
+
+/// Renders a formatted text into markup.
+///
+/// Entities are nested by offset, outer first. An entity that partially
+/// overlaps an inner one is closed before the inner one ends and reopened
+/// right after it, so the output is always well-nested.
+pub(crate) fn render(text: &FormattedText, markup: &mut impl Markup) -> String {
+    let mut entities: Vec<&MessageEntity> = text
+        .entities()
+        .iter()
+        .filter(|entity| entity.is_valid() && markup.is_supported(entity))
+        .collect();
+    entities.sort_by_key(|entity| (entity.offset(), Reverse(entity.length())));
+
+    let mut out = String::with_capacity(text.len() + 8 * entities.len());
+    let mut stack: Vec<&MessageEntity> = Vec::new();
+    let mut next_entity = 0;
+    let mut position = 0;
+    let mut chars = text.text().chars();
+    loop {
+        close_ended(&mut stack, position, markup, &mut out);
+        while let Some(&entity) = entities.get(next_entity) {
+            if entity.offset() > position {
+                break;
+            }
+            markup.open(entity, &mut out);
+            stack.push(entity);
+            next_entity += 1;
+        }
+
+        let Some(c) = chars.next() else {
+            break;
+        };
+        markup.push_char(c, &stack, &mut out);
+        position += c.len_utf16() as i32;
+    }
+    while let Some(entity) = stack.pop() {
+        markup.close(entity, &mut out);
+    }
+    out
+}
+
+/// Closes the entities ending at `position`, reopening the inner entities
+/// that continue past it.
+fn close_ended(
+    stack: &mut Vec<&MessageEntity>,
+    position: i32,
+    markup: &mut impl Markup,
+    out: &mut String,
+) {
+    let Some(first_ended) = stack
+        .iter()
+        .position(|entity| entity.offset() + entity.length() <= position)
+    else {
+        return;
+    };
+
+    let closed: Vec<&MessageEntity> = stack.drain(first_ended..).collect();
+    for entity in closed.iter().rev() {
+        markup.close(entity, out);
+    }
+    for entity in closed {
+        if entity.offset() + entity.length() > position {
+            markup.open(entity, out);
+            stack.push(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders entities as `<type>`...`</type>`.
+    struct Tags;
+
+    impl Markup for Tags {
+        fn is_supported(&self, _entity: &MessageEntity) -> bool {
+            true
+        }
+
+        fn open(&mut self, entity: &MessageEntity, out: &mut String) {
+            out.push_str(&format!("<{}>", entity.entity_type()));
+        }
+
+        fn close(&mut self, entity: &MessageEntity, out: &mut String) {
+            out.push_str(&format!("</{}>", entity.entity_type()));
+        }
+
+        fn push_char(&mut self, c: char, _open: &[&MessageEntity], out: &mut String) {
+            out.push(c);
+        }
+    }
+
+    #[test]
+    fn test_render_nested() {
+        let text = FormattedText::with_entities(
+            "abcd",
+            vec![MessageEntity::new("i", 1, 2), MessageEntity::new("b", 0, 4)],
+        );
+        assert_eq!(render(&text, &mut Tags), "<b>a<i>bc</i>d</b>");
+    }
+
+    #[test]
+    fn test_render_overlapping() {
+        let text = FormattedText::with_entities(
+            "abcd",
+            vec![MessageEntity::new("b", 0, 2), MessageEntity::new("i", 1, 3)],
+        );
+        assert_eq!(render(&text, &mut Tags), "<b>a<i>b</i></b><i>cd</i>");
+    }
+
+    #[test]
+    fn test_render_utf16_offsets() {
+        let text = FormattedText::with_entities("😀x", vec![MessageEntity::new("b", 2, 1)]);
+        assert_eq!(render(&text, &mut Tags), "😀<b>x</b>");
+    }
+}
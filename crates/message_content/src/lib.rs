@@ -2181,6 +2181,20 @@ impl MessageContent {
             _ => None,
         }
     }
+
+    /// Returns a mutable reference to the caption if this content has one.
+    #[must_use]
+    pub fn caption_mut(&mut self) -> Option<&mut FormattedText> {
+        match self {
+            Self::Photo(p) => Some(&mut p.caption),
+            Self::Video(v) => Some(&mut v.caption),
+            Self::Audio(a) => Some(&mut a.caption),
+            Self::Document(d) => Some(&mut d.caption),
+            Self::Animation(a) => Some(&mut a.caption),
+            Self::VoiceNote(v) => Some(&mut v.caption),
+            _ => None,
+        }
+    }
 }
 
 impl Default for MessageContent {
@@ -2302,6 +2316,17 @@ mod tests {
         assert!(content.caption().is_none());
     }
 
+    #[test]
+    fn test_caption_mut() {
+        let mut content = MessageContent::Photo(Box::default());
+        *content.caption_mut().unwrap() = FormattedText::new("New caption");
+        assert_eq!(content.caption().unwrap().text(), "New caption");
+
+        let text = FormattedText::new("Hello");
+        let mut content = MessageContent::Text(Box::new(MessageText::new(text)));
+        assert!(content.caption_mut().is_none());
+    }
+
     #[test]
     fn test_message_location_new() {
         let location = MessageLocation::new(40.7128, -74.0060);
@@ -2,6 +2,28 @@
 //
 // Licensed under the Apache License, Version 2.0;
 
+//! # Message Copy Options
+//!
+//! Options for forwarding messages as copies.
+//!
+//! ## TDLib Alignment
+//!
+//! This type aligns with TDLib's `MessageCopyOptions` struct from
+//! `td/telegram/MessageCopyOptions.h`. A copy is sent without a link to the
+//! original author; replacing the caption with an empty one removes the
+//! captions of media.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use rustgram_message_copy_options::MessageCopyOptions;
+//!
+//! // Forward without the author and without media captions
+//! let options = MessageCopyOptions::with_flags(true, true);
+//! assert!(options.send_copy());
+//! assert!(options.new_caption().text().is_empty());
+//! ```
+
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 #![deny(clippy::unwrap_used)]
@@ -12,9 +34,11 @@ use rustgram_message_input_reply_to::MessageInputReplyTo;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+/// Placeholder for the reply markup of the copy.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct ReplyMarkup;
 
+/// Options for copying messages.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageCopyOptions {
     send_copy: bool,
@@ -26,11 +50,19 @@ pub struct MessageCopyOptions {
 }
 
 impl MessageCopyOptions {
+    /// Creates options for a plain forward.
     #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Creates options with the given flags.
+    ///
+    /// # Arguments
+    ///
+    /// * `send_copy` - Whether to send a copy without the original author
+    /// * `replace_caption` - Whether to replace the caption of media with an
+    ///   empty one
     #[must_use]
     pub fn with_flags(send_copy: bool, replace_caption: bool) -> Self {
         Self {
@@ -43,16 +75,33 @@ impl MessageCopyOptions {
         }
     }
 
+    /// Sets the caption replacing the caption of media.
+    #[must_use]
+    pub fn with_new_caption(mut self, new_caption: FormattedText) -> Self {
+        self.replace_caption = true;
+        self.new_caption = new_caption;
+        self
+    }
+
+    /// Returns whether a copy is sent without the original author.
     #[must_use]
     pub const fn send_copy(&self) -> bool {
         self.send_copy
     }
 
+    /// Returns whether the caption of media is replaced.
     #[must_use]
     pub const fn replace_caption(&self) -> bool {
         self.replace_caption
     }
 
+    /// Returns the caption replacing the caption of media.
+    #[must_use]
+    pub const fn new_caption(&self) -> &FormattedText {
+        &self.new_caption
+    }
+
+    /// Returns whether media is shown after the caption.
     #[must_use]
     pub const fn new_invert_media(&self) -> bool {
         self.new_invert_media
@@ -97,5 +146,14 @@ mod tests {
         let options = MessageCopyOptions::with_flags(true, true);
         assert!(options.send_copy());
         assert!(options.replace_caption());
+        assert!(options.new_caption().text().is_empty());
+    }
+
+    #[test]
+    fn test_with_new_caption() {
+        let options = MessageCopyOptions::new().with_new_caption(FormattedText::new("new"));
+        assert!(!options.send_copy());
+        assert!(options.replace_caption());
+        assert_eq!(options.new_caption().text(), "new");
     }
 }
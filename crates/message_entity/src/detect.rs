@@ -0,0 +1,670 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0;
+
+//! Automatic detection of entities in a plain text.
+//!
+//! The individual finders return byte ranges of the text and follow the
+//! matching rules of TDLib's `MessageEntity.cpp`, so that the same text is
+//! highlighted identically by every client. [`find_entities`] combines them
+//! and converts the result to UTF-16 offsets.
+
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use crate::url::find_urls;
+use crate::{MessageEntity, MessageEntityType};
+
+/// Short bot usernames that are mentioned despite being shorter than
+/// [`MIN_MENTION_LENGTH`].
+const SHORT_MENTIONS: &[&str] = &["gif", "pic", "vid"];
+
+/// Minimum length of a regular username.
+const MIN_MENTION_LENGTH: usize = 4;
+
+/// Maximum length of a username.
+const MAX_MENTION_LENGTH: usize = 32;
+
+/// Maximum length of a bot command, without the bot username.
+const MAX_BOT_COMMAND_LENGTH: usize = 64;
+
+/// Maximum number of characters of a hashtag, without the `#`.
+const MAX_HASHTAG_LENGTH: usize = 256;
+
+/// Minimum and maximum number of digits of a phone number.
+const PHONE_NUMBER_DIGITS: Range<usize> = 7..16;
+
+/// Minimum and maximum number of digits of a bank card number.
+const BANK_CARD_DIGITS: Range<usize> = 13..20;
+
+/// Characters that can't end a `tg:` URL.
+const BAD_URL_END_CHARS: &[char] = &['.', ':', ';', ',', '(', '\'', '?', '!', '`'];
+
+/// Finds all entities detected automatically in a text.
+///
+/// Overlapping matches are resolved in favour of the one starting first,
+/// then the longest. Offsets and lengths of the result are in UTF-16 code
+/// units, ordered by offset.
+///
+/// # Arguments
+///
+/// * `text` - The plain text to search
+/// * `skip_bot_commands` - Don't look for bot commands, e.g. in chats
+///   without bots
+/// * `skip_media_timestamps` - Don't look for media timestamps, e.g. in
+///   messages without media
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::{find_entities, MessageEntityType};
+///
+/// let entities = find_entities("/start@bot 1:30 $USD", false, false);
+/// let types: Vec<_> = entities.iter().map(|e| e.entity_type()).collect();
+/// assert_eq!(
+///     types,
+///     [
+///         MessageEntityType::BotCommand,
+///         MessageEntityType::MediaTimestamp,
+///         MessageEntityType::Cashtag,
+///     ]
+/// );
+/// assert_eq!(entities[1].get_media_timestamp(), Some(90));
+/// ```
+#[must_use]
+pub fn find_entities(
+    text: &str,
+    skip_bot_commands: bool,
+    skip_media_timestamps: bool,
+) -> Vec<MessageEntity> {
+    let mut found: Vec<(Range<usize>, MessageEntityType, Option<i32>)> = Vec::new();
+    let mut add = |ranges: Vec<Range<usize>>, entity_type| {
+        found.extend(ranges.into_iter().map(|range| (range, entity_type, None)));
+    };
+    add(find_mentions(text), MessageEntityType::Mention);
+    if !skip_bot_commands {
+        add(find_bot_commands(text), MessageEntityType::BotCommand);
+    }
+    add(find_hashtags(text), MessageEntityType::Hashtag);
+    add(find_cashtags(text), MessageEntityType::Cashtag);
+    add(find_phone_numbers(text), MessageEntityType::PhoneNumber);
+    add(
+        find_bank_card_numbers(text),
+        MessageEntityType::BankCardNumber,
+    );
+    add(find_tg_urls(text), MessageEntityType::Url);
+    for (range, is_email) in find_urls(text) {
+        let entity_type = if is_email {
+            MessageEntityType::EmailAddress
+        } else {
+            MessageEntityType::Url
+        };
+        found.push((range, entity_type, None));
+    }
+    if !skip_media_timestamps {
+        for (range, timestamp) in find_media_timestamps(text) {
+            found.push((range, MessageEntityType::MediaTimestamp, Some(timestamp)));
+        }
+    }
+
+    found.sort_by_key(|(range, entity_type, _)| {
+        (range.start, Reverse(range.end), *entity_type as i32)
+    });
+    let mut last_end = 0;
+    found.retain(|(range, _, _)| {
+        let keep = range.start >= last_end;
+        if keep {
+            last_end = range.end;
+        }
+        keep
+    });
+
+    let mut position = Utf16Position::default();
+    found
+        .into_iter()
+        .map(|(range, entity_type, timestamp)| {
+            let offset = position.advance(text, range.start);
+            let length = position.advance(text, range.end) - offset;
+            match timestamp {
+                Some(timestamp) => MessageEntity::with_media_timestamp(offset, length, timestamp),
+                None => MessageEntity::new(entity_type, offset, length),
+            }
+        })
+        .collect()
+}
+
+/// Merges automatically detected entities into the entities supplied by the
+/// user.
+///
+/// All user entities are kept. A detected entity is dropped if it overlaps a
+/// user entity other than a formatting one (see
+/// [`MessageEntityType::is_formatting`]), so that e.g. a URL inside user
+/// supplied code or a text link isn't highlighted. The result is ordered by
+/// offset, outer entities first.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::{find_entities, merge_entities, MessageEntity, MessageEntityType};
+///
+/// let text = "@telegram #tag";
+/// let user = vec![MessageEntity::new(MessageEntityType::Code, 0, 9)];
+/// let merged = merge_entities(user, find_entities(text, false, false));
+/// let types: Vec<_> = merged.iter().map(|e| e.entity_type()).collect();
+/// assert_eq!(types, [MessageEntityType::Code, MessageEntityType::Hashtag]);
+/// ```
+#[must_use]
+pub fn merge_entities(
+    user_entities: Vec<MessageEntity>,
+    found_entities: Vec<MessageEntity>,
+) -> Vec<MessageEntity> {
+    let blocking: Vec<(i32, i32)> = user_entities
+        .iter()
+        .filter(|entity| !entity.entity_type().is_formatting())
+        .map(|entity| (entity.offset(), entity.offset() + entity.length()))
+        .collect();
+
+    let mut result = user_entities;
+    result.extend(found_entities.into_iter().filter(|entity| {
+        let (begin, end) = (entity.offset(), entity.offset() + entity.length());
+        !blocking
+            .iter()
+            .any(|&(user_begin, user_end)| begin < user_end && user_begin < end)
+    }));
+    result.sort_by_key(|entity| {
+        (
+            entity.offset(),
+            Reverse(entity.length()),
+            entity.entity_type() as i32,
+        )
+    });
+    result
+}
+
+/// Finds `@username` mentions.
+///
+/// A username has 4 to 32 ASCII letters, digits and underscores; the bots
+/// `@gif`, `@pic` and `@vid` are the only shorter mentions.
+#[must_use]
+pub fn find_mentions(text: &str) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    for (at, _) in text.match_indices('@') {
+        if prev_char(text, at).is_some_and(is_word_character) {
+            continue;
+        }
+        let name_begin = at + 1;
+        let name_end = skip_ascii(text, name_begin, is_username_byte);
+        let name = &text[name_begin..name_end];
+        if name.len() < 2 || name.len() > MAX_MENTION_LENGTH {
+            continue;
+        }
+        if next_char(text, name_end).is_some_and(is_word_character) {
+            continue;
+        }
+        if name.len() < MIN_MENTION_LENGTH
+            && !SHORT_MENTIONS.contains(&name.to_ascii_lowercase().as_str())
+        {
+            continue;
+        }
+        result.push(at..name_end);
+    }
+    result
+}
+
+/// Finds `/command` and `/command@botname` bot commands.
+#[must_use]
+pub fn find_bot_commands(text: &str) -> Vec<Range<usize>> {
+    let is_separator = |c: char| is_word_character(c) || matches!(c, '/' | '<' | '>');
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    for (slash, _) in text.match_indices('/') {
+        if prev_char(text, slash).is_some_and(is_separator) {
+            continue;
+        }
+        let command_begin = slash + 1;
+        let mut command_end = skip_ascii(text, command_begin, is_username_byte);
+        let command_length = command_end - command_begin;
+        if command_length == 0 || command_length > MAX_BOT_COMMAND_LENGTH {
+            continue;
+        }
+        if bytes.get(command_end) == Some(&b'@') {
+            let bot_begin = command_end + 1;
+            let bot_end = skip_ascii(text, bot_begin, is_username_byte);
+            if !(3..=MAX_MENTION_LENGTH).contains(&(bot_end - bot_begin)) {
+                continue;
+            }
+            command_end = bot_end;
+        }
+        if next_char(text, command_end).is_some_and(is_separator) {
+            continue;
+        }
+        result.push(slash..command_end);
+    }
+    result
+}
+
+/// Finds `#hashtags`.
+///
+/// A hashtag contains at least one letter and is cut after 256 characters.
+#[must_use]
+pub fn find_hashtags(text: &str) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    let mut search_from = 0;
+    while let Some(pos) = text[search_from..].find('#') {
+        let hash = search_from + pos;
+        search_from = hash + 1;
+        if prev_char(text, hash).is_some_and(is_hashtag_letter) {
+            continue;
+        }
+
+        let mut end = hash + 1;
+        let mut cut_end = None;
+        let mut size = 0;
+        let mut was_letter = false;
+        for c in text[hash + 1..].chars() {
+            if !is_hashtag_letter(c) {
+                break;
+            }
+            end += c.len_utf8();
+            if size == MAX_HASHTAG_LENGTH - 1 {
+                cut_end = Some(end);
+            }
+            if size != MAX_HASHTAG_LENGTH {
+                was_letter |= c.is_alphabetic();
+                size += 1;
+            }
+        }
+        search_from = end;
+        if size == 0 || !was_letter || text.as_bytes().get(end) == Some(&b'#') {
+            continue;
+        }
+        result.push(hash..cut_end.unwrap_or(end));
+    }
+    result
+}
+
+/// Finds `$CASHTAGS` of 3 to 8 capital Latin letters, and `$1INCH`.
+#[must_use]
+pub fn find_cashtags(text: &str) -> Vec<Range<usize>> {
+    let is_separator = |c: char| is_hashtag_letter(c) || c == '$';
+    let mut result = Vec::new();
+    for (dollar, _) in text.match_indices('$') {
+        if prev_char(text, dollar).is_some_and(is_separator) {
+            continue;
+        }
+        let begin = dollar + 1;
+        let end = if text[begin..].starts_with("1INCH") {
+            begin + 5
+        } else {
+            let end = skip_ascii(text, begin, |c| c.is_ascii_uppercase());
+            if !(3..=8).contains(&(end - begin)) {
+                continue;
+            }
+            end
+        };
+        if next_char(text, end).is_some_and(is_separator) {
+            continue;
+        }
+        result.push(dollar..end);
+    }
+    result
+}
+
+/// Finds phone numbers in international format, such as
+/// `+1 (555) 123-45-67`.
+///
+/// A number starts with `+` and has 7 to 15 digits, optionally grouped with
+/// single spaces, dashes or brackets.
+#[must_use]
+pub fn find_phone_numbers(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut search_from = 0;
+    while let Some(pos) = text[search_from..].find('+') {
+        let plus = search_from + pos;
+        search_from = plus + 1;
+        if prev_char(text, plus).is_some_and(|c| is_word_character(c) || c == '+') {
+            continue;
+        }
+
+        // `end` is only updated outside of brackets, so that an unclosed
+        // bracket is left out of the number
+        let mut end = plus + 1;
+        let mut in_brackets = false;
+        let mut prev = b'+';
+        for (i, &c) in bytes.iter().enumerate().skip(plus + 1) {
+            match c {
+                b'0'..=b'9' if !in_brackets => end = i + 1,
+                b'0'..=b'9' => {}
+                b' ' | b'-' if prev.is_ascii_digit() || prev == b')' => {}
+                b'(' if !in_brackets && matches!(prev, b' ' | b'-') => in_brackets = true,
+                b')' if in_brackets && prev.is_ascii_digit() => in_brackets = false,
+                _ => break,
+            }
+            prev = c;
+        }
+        // a last group followed by a colon is the start of a time, not a part
+        // of the number
+        while next_char(text, end) == Some(':') {
+            end = match text[plus + 1..end].rfind([' ', '-']) {
+                Some(separator) => text[..plus + 1 + separator]
+                    .trim_end_matches([' ', '-'])
+                    .len(),
+                None => plus,
+            };
+        }
+        search_from = end.max(search_from);
+
+        let digits = text[plus..end].bytes().filter(u8::is_ascii_digit).count();
+        if !PHONE_NUMBER_DIGITS.contains(&digits) {
+            continue;
+        }
+        if next_char(text, end).is_some_and(|c| is_word_character(c) || c == '+') {
+            continue;
+        }
+        result.push(plus..end);
+    }
+    result
+}
+
+/// Finds bank card numbers that pass the Luhn check and match the length
+/// of their payment system.
+///
+/// The digits may be grouped with spaces or dashes.
+#[must_use]
+pub fn find_bank_card_numbers(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let is_card_byte = |c: u8| c.is_ascii_digit() || c == b' ' || c == b'-';
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = bytes[pos..].iter().position(u8::is_ascii_digit) {
+        let begin = pos + offset;
+        pos = begin;
+        if prev_char(text, begin)
+            .is_some_and(|c| matches!(c, '.' | ',' | '+' | '-' | '_') || c.is_alphabetic())
+        {
+            pos = skip_ascii(text, begin, is_card_byte);
+            continue;
+        }
+
+        let mut digits = 0;
+        while let Some(&c) = bytes.get(pos) {
+            if !is_card_byte(c) {
+                break;
+            }
+            if c == b' ' && (16..=19).contains(&digits) && digits == pos - begin {
+                // a continuous number followed by another one
+                break;
+            }
+            digits += usize::from(c.is_ascii_digit());
+            pos += 1;
+        }
+        if !BANK_CARD_DIGITS.contains(&digits) {
+            continue;
+        }
+
+        let mut end = pos;
+        while !bytes[end - 1].is_ascii_digit() {
+            end -= 1;
+        }
+        if end - begin > 2 * digits - 1 {
+            continue;
+        }
+        if next_char(text, end).is_some_and(|c| c == '-' || c == '_' || c.is_alphabetic()) {
+            continue;
+        }
+        if is_valid_bank_card(&text[begin..end]) {
+            result.push(begin..end);
+        }
+    }
+    result
+}
+
+/// Finds `tg://` and `ton://` links.
+#[must_use]
+pub fn find_tg_urls(text: &str) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    for (colon, _) in text.match_indices("://") {
+        let scheme_begin = text[..colon]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| c.is_ascii_alphanumeric())
+            .last()
+            .map_or(colon, |(i, _)| i);
+        let scheme = &text[scheme_begin..colon];
+        if !scheme.eq_ignore_ascii_case("tg") && !scheme.eq_ignore_ascii_case("ton") {
+            continue;
+        }
+        if prev_char(text, scheme_begin).is_some_and(is_word_character) {
+            continue;
+        }
+
+        let path_begin = colon + 3;
+        let mut end = path_begin
+            + text[path_begin..]
+                .find(|c: char| {
+                    c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\u{ab}' | '\u{bb}')
+                })
+                .unwrap_or(text.len() - path_begin);
+        end = text[..end].trim_end_matches(BAD_URL_END_CHARS).len();
+        if end > path_begin {
+            result.push(scheme_begin..end);
+        }
+    }
+    result
+}
+
+/// Finds media timestamps such as `1:30` or `1:02:03`, with the time they
+/// point at in seconds.
+#[must_use]
+pub fn find_media_timestamps(text: &str) -> Vec<(Range<usize>, i32)> {
+    let bytes = text.as_bytes();
+    let is_timestamp_byte = |c: u8| c == b':' || c.is_ascii_digit();
+    let mut result = Vec::new();
+    let mut search_from = 0;
+    while let Some(pos) = text[search_from..].find(':') {
+        let colon = search_from + pos;
+        let mut begin = colon;
+        while begin > 0 && is_timestamp_byte(bytes[begin - 1]) {
+            begin -= 1;
+        }
+        let end = skip_ascii(text, colon + 1, is_timestamp_byte);
+        search_from = end.max(colon + 1);
+        if begin == colon || !bytes.get(colon + 1).is_some_and(u8::is_ascii_digit) {
+            continue;
+        }
+        if prev_char(text, begin).is_some_and(is_word_character)
+            || next_char(text, end).is_some_and(is_word_character)
+        {
+            continue;
+        }
+        if let Some(timestamp) = parse_media_timestamp(&text[begin..end]) {
+            result.push((begin..end, timestamp));
+        }
+    }
+    result
+}
+
+/// Parses `m:ss`, `mm:ss` up to `mmmm:ss`, or `h:mm:ss`, returning seconds.
+fn parse_media_timestamp(text: &str) -> Option<i32> {
+    let parts: Vec<&str> = text.split(':').collect();
+    let parse = |part: &str| part.parse::<i32>().ok();
+    let (seconds_part, rest) = parts.split_last()?;
+    if seconds_part.len() != 2 {
+        return None;
+    }
+    let seconds = parse(seconds_part).filter(|&seconds| seconds < 60)?;
+    match rest {
+        [minutes] if (1..=4).contains(&minutes.len()) => Some(parse(minutes)? * 60 + seconds),
+        [hours, minutes] if (1..=2).contains(&hours.len()) && (1..=2).contains(&minutes.len()) => {
+            let minutes = parse(minutes).filter(|&minutes| minutes < 60)?;
+            Some(parse(hours)? * 3600 + minutes * 60 + seconds)
+        }
+        _ => None,
+    }
+}
+
+/// Returns true if the digits of `number` pass the Luhn check and their
+/// count is valid for the card's payment system.
+fn is_valid_bank_card(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let count = digits.len();
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit) {
+            (0, _) => digit,
+            (_, 0..=4) => 2 * digit,
+            _ => 2 * digit - 9,
+        })
+        .sum();
+    if sum % 10 != 0 {
+        return false;
+    }
+
+    let prefix = |n: usize| {
+        digits
+            .iter()
+            .take(n)
+            .fold(0, |acc, &digit| acc * 10 + digit)
+    };
+    match (prefix(1), prefix(2), prefix(4)) {
+        // Visa
+        (4, _, _) => matches!(count, 13 | 16 | 18 | 19),
+        // Mastercard
+        (_, 51..=55, _) | (_, _, 2221..=2720) => count == 16,
+        // American Express
+        (_, 34 | 37, _) => count == 15,
+        // UnionPay
+        (_, 62 | 81, _) => count >= 16,
+        // MIR
+        (_, _, 2200..=2204) => count == 16,
+        _ => true,
+    }
+}
+
+/// Returns true if `c` is a letter, a digit or an underscore.
+pub(crate) fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns true if `c` may be a part of a hashtag.
+fn is_hashtag_letter(c: char) -> bool {
+    c.is_alphanumeric()
+        || matches!(c, '_' | '\u{200c}' | '\u{b7}')
+        || ('\u{d80}'..='\u{dff}').contains(&c)
+}
+
+/// Returns true if `c` may be a part of a username.
+fn is_username_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Returns the position after the ASCII bytes from `pos` matching `accept`.
+fn skip_ascii(text: &str, pos: usize, accept: impl Fn(u8) -> bool) -> usize {
+    pos + text.as_bytes()[pos..]
+        .iter()
+        .take_while(|&&c| accept(c))
+        .count()
+}
+
+/// Returns the character before byte `pos`.
+pub(crate) fn prev_char(text: &str, pos: usize) -> Option<char> {
+    text.get(..pos)?.chars().next_back()
+}
+
+/// Returns the character at byte `pos`.
+pub(crate) fn next_char(text: &str, pos: usize) -> Option<char> {
+    text.get(pos..)?.chars().next()
+}
+
+/// Converts increasing byte offsets of a text to UTF-16 offsets.
+#[derive(Default)]
+struct Utf16Position {
+    /// Last converted byte offset
+    byte: usize,
+    /// UTF-16 offset of `byte`
+    utf16: i32,
+}
+
+impl Utf16Position {
+    /// Moves to byte offset `byte`, which must not precede the current one,
+    /// and returns its UTF-16 offset.
+    fn advance(&mut self, text: &str, byte: usize) -> i32 {
+        let units: usize = text[self.byte..byte].chars().map(char::len_utf16).sum();
+        self.utf16 += i32::try_from(units).unwrap_or(i32::MAX);
+        self.byte = byte;
+        self.utf16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_entities_utf16_offsets() {
+        let entities = find_entities("😀 @telegram", false, false);
+        assert_eq!(
+            entities,
+            vec![MessageEntity::new(MessageEntityType::Mention, 3, 9)]
+        );
+    }
+
+    #[test]
+    fn test_find_entities_overlaps() {
+        // the mention inside the URL is not reported separately
+        let entities = find_entities("https://t.me/@username", false, false);
+        assert_eq!(
+            entities,
+            vec![MessageEntity::new(MessageEntityType::Url, 0, 22)]
+        );
+    }
+
+    #[test]
+    fn test_find_entities_skip_flags() {
+        assert!(find_entities("/start 1:00", true, true).is_empty());
+        assert_eq!(find_entities("/start 1:00", false, false).len(), 2);
+    }
+
+    #[test]
+    fn test_merge_entities() {
+        let found = find_entities("#tag @username", false, false);
+        let user = vec![
+            MessageEntity::new(MessageEntityType::Bold, 0, 14),
+            MessageEntity::with_argument(
+                MessageEntityType::TextUrl,
+                5,
+                3,
+                "https://telegram.org".to_string(),
+            ),
+        ];
+        let merged = merge_entities(user, found);
+        let types: Vec<_> = merged.iter().map(MessageEntity::entity_type).collect();
+        assert_eq!(
+            types,
+            [
+                MessageEntityType::Bold,
+                MessageEntityType::Hashtag,
+                MessageEntityType::TextUrl
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_media_timestamp() {
+        assert_eq!(parse_media_timestamp("0:00"), Some(0));
+        assert_eq!(parse_media_timestamp("1:02:03"), Some(3723));
+        assert_eq!(parse_media_timestamp("1:60"), None);
+        assert_eq!(parse_media_timestamp("12345:00"), None);
+    }
+
+    #[test]
+    fn test_luhn() {
+        assert!(is_valid_bank_card("4111 1111 1111 1111"));
+        assert!(!is_valid_bank_card("4111 1111 1111 1112"));
+        assert!(!is_valid_bank_card("4111 1111 1111 11"));
+    }
+}
@@ -7,6 +7,39 @@
 #![deny(clippy::unwrap_used)]
 #![deny(clippy::expect_used)]
 
+//! # Message Entity
+//!
+//! Entities marking parts of a message text, and detection of the entities
+//! Telegram clients find automatically.
+//!
+//! [`find_entities`] finds mentions, hashtags, cashtags, bot commands, URLs,
+//! email addresses, phone numbers, bank card numbers and media timestamps in
+//! a plain text. [`merge_entities`] combines the result with the entities the
+//! user supplied. Offsets and lengths are measured in UTF-16 code units, as
+//! everywhere in the Telegram API.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use rustgram_message_entity::{find_entities, MessageEntityType};
+//!
+//! let entities = find_entities("Ask @telegram about #news", false, false);
+//! assert_eq!(entities[0].entity_type(), MessageEntityType::Mention);
+//! assert_eq!((entities[0].offset(), entities[0].length()), (4, 9));
+//! assert_eq!(entities[1].entity_type(), MessageEntityType::Hashtag);
+//! ```
+
+mod detect;
+pub mod punycode;
+mod tld;
+mod url;
+
+pub use detect::{
+    find_bank_card_numbers, find_bot_commands, find_cashtags, find_entities, find_hashtags,
+    find_media_timestamps, find_mentions, find_phone_numbers, find_tg_urls, merge_entities,
+};
+pub use url::{find_urls, is_email_address};
+
 use rustgram_types::UserId;
 use serde::{Deserialize, Serialize};
 
@@ -15,16 +48,19 @@ use serde::{Deserialize, Serialize};
 pub struct CustomEmojiId(i64);
 
 impl CustomEmojiId {
+    /// Creates a custom emoji identifier.
     #[must_use]
     pub const fn new(id: i64) -> Self {
         Self(id)
     }
 
+    /// Returns the raw identifier.
     #[must_use]
     pub const fn get(&self) -> i64 {
         self.0
     }
 
+    /// Returns true if the identifier is non-zero.
     #[must_use]
     pub const fn is_valid(&self) -> bool {
         self.0 != 0
@@ -35,30 +71,100 @@ impl CustomEmojiId {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(i32)]
 pub enum MessageEntityType {
+    /// `@username` mention.
     Mention = 0,
+    /// `#hashtag`.
     Hashtag = 1,
+    /// `/command` or `/command@bot`.
     BotCommand = 2,
+    /// URL shown as is.
     Url = 3,
+    /// Email address.
     EmailAddress = 4,
+    /// Bold text.
     Bold = 5,
+    /// Italic text.
     Italic = 6,
+    /// Inline monowidth code.
     Code = 7,
+    /// Monowidth code block.
     Pre = 8,
+    /// Monowidth code block with a programming language.
     PreCode = 9,
+    /// Text linked to a URL.
     TextUrl = 10,
+    /// Mention of a user by identifier.
     MentionName = 11,
+    /// `$USD` cashtag.
     Cashtag = 12,
+    /// Phone number.
     PhoneNumber = 13,
+    /// Underlined text.
     Underline = 14,
+    /// Strikethrough text.
     Strikethrough = 15,
+    /// Block quotation.
     BlockQuote = 16,
+    /// Bank card number.
     BankCardNumber = 17,
+    /// Timestamp in the media attached to the message.
     MediaTimestamp = 18,
+    /// Spoiler text, hidden until clicked.
     Spoiler = 19,
+    /// Custom emoji replacing the text.
     CustomEmoji = 20,
+    /// Block quotation collapsed by default.
     ExpandableBlockQuote = 21,
 }
 
+impl MessageEntityType {
+    /// Returns the name of the corresponding `td_api` `TextEntityType`
+    /// constructor, e.g. `textEntityTypeMention`.
+    #[must_use]
+    pub const fn td_api_name(self) -> &'static str {
+        match self {
+            Self::Mention => "textEntityTypeMention",
+            Self::Hashtag => "textEntityTypeHashtag",
+            Self::BotCommand => "textEntityTypeBotCommand",
+            Self::Url => "textEntityTypeUrl",
+            Self::EmailAddress => "textEntityTypeEmailAddress",
+            Self::Bold => "textEntityTypeBold",
+            Self::Italic => "textEntityTypeItalic",
+            Self::Code => "textEntityTypeCode",
+            Self::Pre => "textEntityTypePre",
+            Self::PreCode => "textEntityTypePreCode",
+            Self::TextUrl => "textEntityTypeTextUrl",
+            Self::MentionName => "textEntityTypeMentionName",
+            Self::Cashtag => "textEntityTypeCashtag",
+            Self::PhoneNumber => "textEntityTypePhoneNumber",
+            Self::Underline => "textEntityTypeUnderline",
+            Self::Strikethrough => "textEntityTypeStrikethrough",
+            Self::BlockQuote => "textEntityTypeBlockQuote",
+            Self::BankCardNumber => "textEntityTypeBankCardNumber",
+            Self::MediaTimestamp => "textEntityTypeMediaTimestamp",
+            Self::Spoiler => "textEntityTypeSpoiler",
+            Self::CustomEmoji => "textEntityTypeCustomEmoji",
+            Self::ExpandableBlockQuote => "textEntityTypeExpandableBlockQuote",
+        }
+    }
+
+    /// Returns true for formatting entities, which may contain other
+    /// entities and don't prevent autodetection inside them.
+    #[must_use]
+    pub const fn is_formatting(self) -> bool {
+        matches!(
+            self,
+            Self::Bold
+                | Self::Italic
+                | Self::Underline
+                | Self::Strikethrough
+                | Self::Spoiler
+                | Self::BlockQuote
+                | Self::ExpandableBlockQuote
+        )
+    }
+}
+
 /// Message entity.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageEntity {
@@ -76,6 +182,7 @@ pub struct MessageEntity {
 }
 
 impl MessageEntity {
+    /// Creates an entity without an argument.
     #[must_use]
     pub fn new(entity_type: MessageEntityType, offset: i32, length: i32) -> Self {
         Self {
@@ -89,6 +196,9 @@ impl MessageEntity {
         }
     }
 
+    /// Creates an entity with a string argument, such as the URL of a
+    /// [`MessageEntityType::TextUrl`] or the language of a
+    /// [`MessageEntityType::PreCode`].
     #[must_use]
     pub fn with_argument(
         entity_type: MessageEntityType,
@@ -107,6 +217,7 @@ impl MessageEntity {
         }
     }
 
+    /// Creates a mention of a user by identifier.
     #[must_use]
     pub fn mention_name(offset: i32, length: i32, user_id: UserId) -> Self {
         Self {
@@ -120,6 +231,7 @@ impl MessageEntity {
         }
     }
 
+    /// Creates a media timestamp pointing at `ts` seconds.
     #[must_use]
     pub fn with_media_timestamp(offset: i32, length: i32, ts: i32) -> Self {
         Self {
@@ -133,6 +245,7 @@ impl MessageEntity {
         }
     }
 
+    /// Creates a custom emoji entity.
     #[must_use]
     pub fn custom_emoji(offset: i32, length: i32, emoji_id: CustomEmojiId) -> Self {
         Self {
@@ -146,26 +259,31 @@ impl MessageEntity {
         }
     }
 
+    /// Returns the type of the entity.
     #[must_use]
     pub const fn entity_type(&self) -> MessageEntityType {
         self.entity_type
     }
 
+    /// Returns the offset of the entity in UTF-16 code units.
     #[must_use]
     pub const fn offset(&self) -> i32 {
         self.offset
     }
 
+    /// Returns the length of the entity in UTF-16 code units.
     #[must_use]
     pub const fn length(&self) -> i32 {
         self.length
     }
 
+    /// Returns the string argument of the entity.
     #[must_use]
     pub fn argument(&self) -> Option<&str> {
         self.argument.as_deref()
     }
 
+    /// Returns the mentioned user, if valid.
     #[must_use]
     pub const fn user_id(&self) -> Option<UserId> {
         match self.user_id {
@@ -174,11 +292,13 @@ impl MessageEntity {
         }
     }
 
+    /// Returns the media timestamp in seconds.
     #[must_use]
     pub const fn get_media_timestamp(&self) -> Option<i32> {
         self.timestamp
     }
 
+    /// Returns the custom emoji identifier, if valid.
     #[must_use]
     pub const fn custom_emoji_id(&self) -> Option<CustomEmojiId> {
         match self.custom_emoji_id {
@@ -187,6 +307,7 @@ impl MessageEntity {
         }
     }
 
+    /// Returns true if the entity has a non-negative offset and is non-empty.
     #[must_use]
     pub fn is_valid(&self) -> bool {
         self.offset >= 0 && self.length > 0
@@ -0,0 +1,153 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0;
+
+//! Punycode encoding of internationalized domain labels (RFC 3492).
+//!
+//! Used to validate non-ASCII top-level domains against the list of known
+//! domains, which stores them in their `xn--` ASCII form.
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+/// Prefix of the ASCII form of an internationalized domain label.
+pub const ACE_PREFIX: &str = "xn--";
+
+/// Encodes a Unicode string with Punycode, without the `xn--` prefix.
+///
+/// Returns `None` if the string is too long to be encoded.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::punycode;
+///
+/// assert_eq!(punycode::encode("рф").as_deref(), Some("p1ai"));
+/// assert_eq!(punycode::encode("bücher").as_deref(), Some("bcher-kva"));
+/// ```
+#[must_use]
+pub fn encode(input: &str) -> Option<String> {
+    let code_points: Vec<u32> = input.chars().map(u32::from).collect();
+    let mut output: String = input.chars().filter(char::is_ascii).collect();
+    let basic_count = u32::try_from(output.len()).ok()?;
+    if basic_count > 0 {
+        output.push('-');
+    }
+
+    let total = u32::try_from(code_points.len()).ok()?;
+    let mut handled = basic_count;
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    while handled < total {
+        let m = code_points.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic_count);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Returns the ASCII form of a domain label: the label itself if it is ASCII,
+/// or `xn--` followed by its Punycode encoding otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::punycode;
+///
+/// assert_eq!(punycode::to_ascii_label("org").as_deref(), Some("org"));
+/// assert_eq!(punycode::to_ascii_label("рф").as_deref(), Some("xn--p1ai"));
+/// ```
+#[must_use]
+pub fn to_ascii_label(label: &str) -> Option<String> {
+    if label.is_ascii() {
+        return Some(label.to_string());
+    }
+    encode(label).map(|encoded| format!("{ACE_PREFIX}{encoded}"))
+}
+
+/// Returns the bias for the next delta.
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+/// Returns the basic code point representing a digit in base 36.
+fn encode_digit(digit: u32) -> char {
+    let byte = if digit < 26 {
+        b'a' + digit as u8
+    } else {
+        b'0' + (digit - 26) as u8
+    };
+    char::from(byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_rfc_samples() {
+        let samples = [
+            ("", ""),
+            ("abc", "abc-"),
+            ("münchen", "mnchen-3ya"),
+            ("bücher", "bcher-kva"),
+            ("中国", "fiqs8s"),
+            ("рф", "p1ai"),
+            ("укр", "j1amh"),
+            ("ليهمابتكلموشعربي؟", "egbpdaj6bu4bxfgehfvwxn"),
+            ("他们为什么不说中文", "ihqwcrb4cv8a8dqg056pqjye"),
+        ];
+        for (input, expected) in samples {
+            assert_eq!(encode(input).as_deref(), Some(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_to_ascii_label() {
+        assert_eq!(to_ascii_label("com").as_deref(), Some("com"));
+        assert_eq!(to_ascii_label("рус").as_deref(), Some("xn--p1acf"));
+    }
+}
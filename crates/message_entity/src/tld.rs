@@ -0,0 +1,773 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0;
+
+//! Top-level domains accepted in URLs written without a protocol.
+
+use crate::punycode;
+
+/// Known top-level domains in ASCII form, sorted.
+///
+/// All country code domains and the generic domains in common use.
+/// Internationalized domains are stored in their `xn--` form.
+const COMMON_TLDS: &[&str] = &[
+    "ac",
+    "academy",
+    "accountant",
+    "accountants",
+    "actor",
+    "ad",
+    "adult",
+    "ae",
+    "aero",
+    "af",
+    "ag",
+    "agency",
+    "ai",
+    "airforce",
+    "al",
+    "am",
+    "ao",
+    "apartments",
+    "app",
+    "aq",
+    "ar",
+    "archi",
+    "army",
+    "arpa",
+    "art",
+    "as",
+    "asia",
+    "associates",
+    "at",
+    "attorney",
+    "au",
+    "auction",
+    "audio",
+    "auto",
+    "autos",
+    "aw",
+    "ax",
+    "az",
+    "ba",
+    "baby",
+    "band",
+    "bar",
+    "bargains",
+    "bb",
+    "bd",
+    "be",
+    "beer",
+    "berlin",
+    "best",
+    "bet",
+    "bf",
+    "bg",
+    "bh",
+    "bi",
+    "bid",
+    "bike",
+    "bingo",
+    "bio",
+    "biz",
+    "bj",
+    "black",
+    "blackfriday",
+    "blog",
+    "blue",
+    "bm",
+    "bn",
+    "bo",
+    "boutique",
+    "br",
+    "bs",
+    "bt",
+    "build",
+    "builders",
+    "business",
+    "buzz",
+    "bw",
+    "by",
+    "bz",
+    "ca",
+    "cab",
+    "cafe",
+    "cam",
+    "camera",
+    "camp",
+    "capital",
+    "car",
+    "cards",
+    "care",
+    "career",
+    "careers",
+    "cars",
+    "casa",
+    "cash",
+    "casino",
+    "cat",
+    "catering",
+    "cc",
+    "cd",
+    "center",
+    "ceo",
+    "cf",
+    "cg",
+    "ch",
+    "chat",
+    "cheap",
+    "christmas",
+    "church",
+    "ci",
+    "city",
+    "ck",
+    "cl",
+    "claims",
+    "cleaning",
+    "click",
+    "clinic",
+    "clothing",
+    "cloud",
+    "club",
+    "cm",
+    "cn",
+    "co",
+    "codes",
+    "coffee",
+    "college",
+    "com",
+    "community",
+    "company",
+    "computer",
+    "condos",
+    "construction",
+    "consulting",
+    "contractors",
+    "cooking",
+    "cool",
+    "coop",
+    "country",
+    "coupons",
+    "courses",
+    "cr",
+    "credit",
+    "creditcard",
+    "cricket",
+    "cruises",
+    "cu",
+    "cv",
+    "cw",
+    "cx",
+    "cy",
+    "cyou",
+    "cz",
+    "dance",
+    "date",
+    "dating",
+    "de",
+    "deals",
+    "degree",
+    "delivery",
+    "democrat",
+    "dental",
+    "dentist",
+    "design",
+    "dev",
+    "diamonds",
+    "diet",
+    "digital",
+    "direct",
+    "directory",
+    "discount",
+    "dj",
+    "dk",
+    "dm",
+    "do",
+    "doctor",
+    "dog",
+    "domains",
+    "download",
+    "dz",
+    "earth",
+    "ec",
+    "eco",
+    "edu",
+    "education",
+    "ee",
+    "eg",
+    "email",
+    "energy",
+    "engineer",
+    "engineering",
+    "enterprises",
+    "equipment",
+    "er",
+    "es",
+    "estate",
+    "et",
+    "eu",
+    "events",
+    "exchange",
+    "expert",
+    "exposed",
+    "express",
+    "fail",
+    "faith",
+    "family",
+    "fans",
+    "farm",
+    "fashion",
+    "fi",
+    "film",
+    "finance",
+    "financial",
+    "fish",
+    "fishing",
+    "fit",
+    "fitness",
+    "fj",
+    "fk",
+    "flights",
+    "florist",
+    "flowers",
+    "fm",
+    "fo",
+    "football",
+    "forsale",
+    "foundation",
+    "fr",
+    "fun",
+    "fund",
+    "furniture",
+    "futbol",
+    "fyi",
+    "ga",
+    "gallery",
+    "game",
+    "games",
+    "garden",
+    "gay",
+    "gd",
+    "ge",
+    "gf",
+    "gg",
+    "gh",
+    "gi",
+    "gift",
+    "gifts",
+    "gives",
+    "gl",
+    "glass",
+    "global",
+    "gm",
+    "gmbh",
+    "gn",
+    "gold",
+    "golf",
+    "gov",
+    "gp",
+    "gq",
+    "gr",
+    "graphics",
+    "gratis",
+    "green",
+    "gripe",
+    "group",
+    "gs",
+    "gt",
+    "gu",
+    "guide",
+    "guitars",
+    "guru",
+    "gw",
+    "gy",
+    "hair",
+    "haus",
+    "health",
+    "healthcare",
+    "help",
+    "hiphop",
+    "hiv",
+    "hk",
+    "hm",
+    "hn",
+    "hockey",
+    "holdings",
+    "holiday",
+    "homes",
+    "horse",
+    "hospital",
+    "host",
+    "hosting",
+    "house",
+    "how",
+    "hr",
+    "ht",
+    "hu",
+    "icu",
+    "id",
+    "ie",
+    "il",
+    "im",
+    "immo",
+    "immobilien",
+    "in",
+    "inc",
+    "industries",
+    "info",
+    "ink",
+    "institute",
+    "insure",
+    "int",
+    "international",
+    "investments",
+    "io",
+    "iq",
+    "ir",
+    "irish",
+    "is",
+    "it",
+    "je",
+    "jetzt",
+    "jewelry",
+    "jm",
+    "jo",
+    "jobs",
+    "jp",
+    "juegos",
+    "kaufen",
+    "ke",
+    "kg",
+    "kh",
+    "ki",
+    "kim",
+    "kitchen",
+    "kiwi",
+    "km",
+    "kn",
+    "kp",
+    "kr",
+    "kw",
+    "ky",
+    "kz",
+    "la",
+    "land",
+    "lat",
+    "law",
+    "lawyer",
+    "lb",
+    "lc",
+    "lease",
+    "legal",
+    "lgbt",
+    "li",
+    "life",
+    "lighting",
+    "limited",
+    "limo",
+    "link",
+    "live",
+    "lk",
+    "llc",
+    "loan",
+    "loans",
+    "lol",
+    "london",
+    "love",
+    "lr",
+    "ls",
+    "lt",
+    "ltd",
+    "lu",
+    "luxury",
+    "lv",
+    "ly",
+    "ma",
+    "maison",
+    "management",
+    "market",
+    "marketing",
+    "mba",
+    "mc",
+    "md",
+    "me",
+    "media",
+    "memorial",
+    "men",
+    "menu",
+    "mg",
+    "mh",
+    "mil",
+    "mk",
+    "ml",
+    "mm",
+    "mn",
+    "mo",
+    "mobi",
+    "moda",
+    "moe",
+    "mom",
+    "money",
+    "monster",
+    "mortgage",
+    "moscow",
+    "motorcycles",
+    "mov",
+    "movie",
+    "mp",
+    "mq",
+    "mr",
+    "ms",
+    "mt",
+    "mu",
+    "museum",
+    "music",
+    "mv",
+    "mw",
+    "mx",
+    "my",
+    "mz",
+    "na",
+    "name",
+    "navy",
+    "nc",
+    "ne",
+    "net",
+    "network",
+    "new",
+    "news",
+    "nf",
+    "ng",
+    "ngo",
+    "ni",
+    "ninja",
+    "nl",
+    "no",
+    "np",
+    "nr",
+    "nu",
+    "nyc",
+    "nz",
+    "observer",
+    "om",
+    "one",
+    "ong",
+    "onl",
+    "online",
+    "ooo",
+    "org",
+    "pa",
+    "page",
+    "paris",
+    "partners",
+    "parts",
+    "party",
+    "pe",
+    "pet",
+    "pf",
+    "pg",
+    "ph",
+    "phd",
+    "photo",
+    "photography",
+    "photos",
+    "pics",
+    "pictures",
+    "pink",
+    "pizza",
+    "pk",
+    "pl",
+    "place",
+    "plumbing",
+    "plus",
+    "pm",
+    "pn",
+    "poker",
+    "porn",
+    "post",
+    "pr",
+    "press",
+    "pro",
+    "productions",
+    "promo",
+    "properties",
+    "property",
+    "ps",
+    "pt",
+    "pub",
+    "pw",
+    "py",
+    "qa",
+    "qpon",
+    "quest",
+    "racing",
+    "radio",
+    "re",
+    "realestate",
+    "realty",
+    "recipes",
+    "red",
+    "rehab",
+    "reise",
+    "reisen",
+    "rent",
+    "rentals",
+    "repair",
+    "report",
+    "republican",
+    "rest",
+    "restaurant",
+    "review",
+    "reviews",
+    "rich",
+    "rip",
+    "ro",
+    "rocks",
+    "rodeo",
+    "rs",
+    "ru",
+    "run",
+    "rw",
+    "sa",
+    "sale",
+    "salon",
+    "sarl",
+    "sb",
+    "sc",
+    "school",
+    "schule",
+    "science",
+    "sd",
+    "se",
+    "security",
+    "services",
+    "sex",
+    "sexy",
+    "sg",
+    "sh",
+    "shiksha",
+    "shoes",
+    "shop",
+    "shopping",
+    "show",
+    "si",
+    "singles",
+    "site",
+    "sk",
+    "ski",
+    "sl",
+    "sm",
+    "sn",
+    "so",
+    "soccer",
+    "social",
+    "software",
+    "solar",
+    "solutions",
+    "space",
+    "spb",
+    "sport",
+    "sr",
+    "ss",
+    "st",
+    "store",
+    "stream",
+    "studio",
+    "study",
+    "style",
+    "su",
+    "sucks",
+    "supplies",
+    "supply",
+    "support",
+    "surf",
+    "surgery",
+    "sv",
+    "sx",
+    "sy",
+    "systems",
+    "sz",
+    "tattoo",
+    "tax",
+    "taxi",
+    "tc",
+    "td",
+    "team",
+    "tech",
+    "technology",
+    "tel",
+    "tennis",
+    "tf",
+    "tg",
+    "th",
+    "theater",
+    "tickets",
+    "tienda",
+    "tips",
+    "tires",
+    "tj",
+    "tk",
+    "tl",
+    "tm",
+    "tn",
+    "to",
+    "today",
+    "tokyo",
+    "tools",
+    "top",
+    "tours",
+    "town",
+    "toys",
+    "tr",
+    "trade",
+    "trading",
+    "training",
+    "travel",
+    "tt",
+    "tube",
+    "tv",
+    "tw",
+    "tz",
+    "ua",
+    "ug",
+    "uk",
+    "university",
+    "uno",
+    "us",
+    "uy",
+    "uz",
+    "va",
+    "vacations",
+    "vc",
+    "ve",
+    "vegas",
+    "ventures",
+    "vet",
+    "vg",
+    "vi",
+    "viajes",
+    "video",
+    "villas",
+    "vin",
+    "vip",
+    "vision",
+    "vn",
+    "vodka",
+    "vote",
+    "voting",
+    "voto",
+    "voyage",
+    "vu",
+    "wang",
+    "watch",
+    "webcam",
+    "website",
+    "wedding",
+    "wf",
+    "wiki",
+    "win",
+    "wine",
+    "work",
+    "works",
+    "world",
+    "ws",
+    "wtf",
+    "xn--3ds443g",
+    "xn--3e0b707e",
+    "xn--54b7fta0cc",
+    "xn--55qx5d",
+    "xn--6frz82g",
+    "xn--80adxhks",
+    "xn--80ao21a",
+    "xn--80asehdb",
+    "xn--80aswg",
+    "xn--90a3ac",
+    "xn--90ais",
+    "xn--c1avg",
+    "xn--clchc0ea0b2g2a9gcd",
+    "xn--czru2d",
+    "xn--d1acj3b",
+    "xn--d1alf",
+    "xn--e1a4c",
+    "xn--fiq228c5hs",
+    "xn--fiqs8s",
+    "xn--fiqz9s",
+    "xn--fzc2c9e2c",
+    "xn--h2brj9c",
+    "xn--io0a7i",
+    "xn--j1aef",
+    "xn--j1amh",
+    "xn--j6w193g",
+    "xn--kprw13d",
+    "xn--kpry57d",
+    "xn--l1acc",
+    "xn--lgbbat1ad8j",
+    "xn--mgb9awbf",
+    "xn--mgba3a4f16a",
+    "xn--mgbaam7a8h",
+    "xn--mgbai9azgqp6j",
+    "xn--mgbayh7gpa",
+    "xn--mgbc0a9azcg",
+    "xn--mgberp4a5d4ar",
+    "xn--mk1bu44c",
+    "xn--node",
+    "xn--o3cw4h",
+    "xn--ogbpf8fl",
+    "xn--p1acf",
+    "xn--p1ai",
+    "xn--pgbs0dh",
+    "xn--q9jyb4c",
+    "xn--qxam",
+    "xn--ses554g",
+    "xn--tckwe",
+    "xn--unup4y",
+    "xn--wgbh1c",
+    "xn--wgbl6a",
+    "xn--wgv71a",
+    "xn--xkc2dl3a5ee0h",
+    "xn--y9a3aq",
+    "xn--yfro4i67o",
+    "xn--ygbi2ammx",
+    "xxx",
+    "xyz",
+    "yachts",
+    "ye",
+    "yoga",
+    "yt",
+    "za",
+    "zm",
+    "zone",
+    "zw",
+];
+
+/// Returns true if `tld` is a known top-level domain.
+///
+/// The comparison is case-insensitive and accepts internationalized domains
+/// both in Unicode and in `xn--` form.
+pub(crate) fn is_common_tld(tld: &str) -> bool {
+    let lowered = tld.to_lowercase();
+    punycode::to_ascii_label(&lowered)
+        .is_some_and(|ascii| COMMON_TLDS.binary_search(&ascii.as_str()).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tlds_sorted() {
+        assert!(COMMON_TLDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_is_common_tld() {
+        assert!(is_common_tld("org"));
+        assert!(is_common_tld("COM"));
+        assert!(is_common_tld("рф"));
+        assert!(is_common_tld("РФ"));
+        assert!(is_common_tld("xn--p1ai"));
+        assert!(!is_common_tld("abd"));
+        assert!(!is_common_tld("a"));
+        assert!(!is_common_tld(""));
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0;
+
+//! Detection of URLs and email addresses.
+//!
+//! Follows TDLib's `match_urls`, `fix_url` and `is_email_address`: candidate
+//! URLs are found around every dot, then validated by domain structure and
+//! top-level domain.
+
+use std::ops::Range;
+
+use crate::detect::{is_word_character, next_char, prev_char};
+use crate::tld::is_common_tld;
+
+/// Characters that can't end a URL path.
+const BAD_PATH_END_CHARS: &[u8] = b".:;,('?!`";
+
+/// Protocols recognized before a domain.
+const PROTOCOLS: &[&str] = &["http://", "https://", "sftp://", "ftp://", "tonsite://"];
+
+/// Finds URLs and email addresses in a text.
+///
+/// Returns byte ranges with a flag that is true for email addresses. A URL
+/// without a protocol is found only if its top-level domain is known.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::find_urls;
+///
+/// let text = "see telegram.org or mail me@example.com";
+/// let urls = find_urls(text);
+/// assert_eq!(&text[urls[0].0.clone()], "telegram.org");
+/// assert!(!urls[0].1);
+/// assert_eq!(&text[urls[1].0.clone()], "me@example.com");
+/// assert!(urls[1].1);
+/// ```
+#[must_use]
+pub fn find_urls(text: &str) -> Vec<(Range<usize>, bool)> {
+    let mut result = Vec::new();
+    for url in match_urls(text) {
+        let candidate = &text[url.clone()];
+        if is_email_address(candidate) {
+            result.push((url, true));
+        } else if starts_with_ignore_case(candidate, "mailto:") && is_email_address(&candidate[7..])
+        {
+            result.push((url.start + 7..url.end, true));
+        } else if let Some(length) = fix_url(candidate) {
+            result.push((url.start..url.start + length, false));
+        }
+    }
+    result
+}
+
+/// Returns true if the whole string is an email address.
+///
+/// Equivalent to matching
+/// `^([a-z0-9_-]{0,26}[.+:]){0,10}[a-z0-9_-]{1,35}@(([a-z0-9][a-z0-9_-]{0,28})?[a-z0-9][.]){1,6}[a-z]{2,8}$`
+/// case-insensitively.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_message_entity::is_email_address;
+///
+/// assert!(is_email_address("security@telegram.org"));
+/// assert!(!is_email_address("telegram.org"));
+/// ```
+#[must_use]
+pub fn is_email_address(text: &str) -> bool {
+    let Some((user_data, domain)) = text.split_once('@') else {
+        return false;
+    };
+    if domain.is_empty() {
+        return false;
+    }
+
+    let user_data_parts: Vec<&str> = user_data.split(['.', '+', ':']).collect();
+    if user_data_parts.len() >= 12 {
+        return false;
+    }
+    if !user_data_parts
+        .iter()
+        .all(|part| part.bytes().all(is_email_symbol))
+    {
+        return false;
+    }
+    let Some((last, rest)) = user_data_parts.split_last() else {
+        return false;
+    };
+    if last.is_empty() || last.len() >= 36 || rest.iter().any(|part| part.len() >= 27) {
+        return false;
+    }
+
+    let domain_parts: Vec<&str> = domain.split('.').collect();
+    if domain_parts.len() <= 1 || domain_parts.len() > 7 {
+        return false;
+    }
+    let Some((tld, rest)) = domain_parts.split_last() else {
+        return false;
+    };
+    if tld.len() <= 1 || tld.len() >= 9 || !tld.bytes().all(|c| c.is_ascii_alphabetic()) {
+        return false;
+    }
+    rest.iter().all(|part| {
+        let bytes = part.as_bytes();
+        match (bytes.first(), bytes.last()) {
+            (Some(first), Some(last)) => {
+                bytes.len() < 31
+                    && bytes.iter().copied().all(is_email_symbol)
+                    && first.is_ascii_alphanumeric()
+                    && last.is_ascii_alphanumeric()
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Finds URL candidates, which are validated by [`fix_url`] afterwards.
+fn match_urls(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let end = text.len();
+    let mut result = Vec::new();
+    let mut begin = 0;
+    while let Some(dot) = text[begin..].find('.').map(|pos| begin + pos) {
+        if dot + 1 == end {
+            break;
+        }
+        if bytes[dot + 1] == b' ' {
+            begin = dot + 2;
+            continue;
+        }
+
+        let mut last_at = None;
+        for (i, c) in text[dot..].char_indices() {
+            if c == '@' {
+                last_at = Some(dot + i);
+            }
+            if !is_user_data_symbol(c) {
+                break;
+            }
+        }
+        let domain_end = skip_forward(text, last_at.map_or(dot, |at| at + 1), is_domain_symbol);
+        let domain_begin = if last_at.is_none() {
+            skip_backward(text, begin, dot, is_domain_symbol)
+        } else {
+            skip_backward(text, begin, dot, is_user_data_symbol)
+        };
+
+        let mut url_end = domain_end;
+        if bytes.get(url_end) == Some(&b':') {
+            let port_end = skip_forward(text, url_end + 1, |c| c.is_ascii_digit());
+            let port = text[url_end + 1..port_end].trim_start_matches('0');
+            if !port.is_empty() && port.len() <= 5 && port.parse::<u32>().is_ok_and(|p| p <= 65535)
+            {
+                url_end = port_end;
+            }
+        }
+
+        if matches!(bytes.get(url_end), Some(b'/' | b'?' | b'#')) {
+            let mut path_end = skip_forward(text, url_end + 1, is_path_symbol);
+            while path_end > url_end && BAD_PATH_END_CHARS.contains(&bytes[path_end - 1]) {
+                path_end -= 1;
+            }
+            if bytes[url_end] == b'/' || path_end > url_end + 1 {
+                url_end = path_end;
+            }
+        }
+        while url_end > dot + 1 && bytes[url_end - 1] == b'.' {
+            url_end -= 1;
+        }
+
+        let mut is_bad = false;
+        let mut url_begin = domain_begin;
+        if url_begin != begin && bytes[url_begin - 1] == b'@' {
+            let user_data_begin = skip_backward(text, begin, url_begin - 1, is_user_data_symbol);
+            if user_data_begin == url_begin - 1 {
+                is_bad = true;
+            }
+            url_begin = user_data_begin;
+        }
+
+        if url_begin != begin {
+            let prefix = &text[begin..url_begin];
+            if prefix.len() >= 6 && prefix.ends_with("://") {
+                let protocol_end = url_begin - 3;
+                let protocol_begin = skip_backward(text, begin, protocol_end, is_protocol_symbol);
+                let protocol = text[protocol_begin..protocol_end].to_ascii_lowercase();
+                if protocol.ends_with("http") && protocol != "shttp" {
+                    url_begin -= 7;
+                } else if protocol.ends_with("https") {
+                    url_begin -= 8;
+                } else if protocol.ends_with("ftp") && protocol != "tftp" && protocol != "sftp" {
+                    url_begin -= 6;
+                } else if protocol.ends_with("tonsite") {
+                    url_begin -= 10;
+                } else {
+                    is_bad = true;
+                }
+            } else if prefix
+                .chars()
+                .next_back()
+                .is_some_and(|c| is_word_character(c) || matches!(c, '/' | '#' | '@'))
+            {
+                is_bad = true;
+            }
+        }
+
+        if is_bad {
+            while bytes[url_end - 1] != b'.' {
+                url_end -= 1;
+            }
+        } else {
+            if url_end > dot + 1 {
+                result.push(url_begin..url_end);
+            }
+            while bytes.get(url_end) == Some(&b'.') {
+                url_end += 1;
+            }
+        }
+
+        begin = url_end.max(dot + 1);
+    }
+    result
+}
+
+/// Validates a URL candidate and returns its length with unbalanced
+/// brackets and trailing punctuation removed from the path.
+fn fix_url(url: &str) -> Option<usize> {
+    let has_protocol = PROTOCOLS
+        .iter()
+        .any(|protocol| starts_with_ignore_case(url, protocol));
+    let rest = match url.find("://") {
+        Some(pos) if has_protocol => &url[pos + 3..],
+        _ => url,
+    };
+
+    let domain_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (mut domain, path) = rest.split_at(domain_end);
+    if let Some(at) = domain.find('@') {
+        domain = &domain[at + 1..];
+    }
+    if let Some(colon) = domain.rfind(':') {
+        domain = &domain[..colon];
+    }
+    if domain.eq_ignore_ascii_case("teiegram.org") {
+        return None;
+    }
+
+    let mut balance = [0i32; 3];
+    let mut path_end = path.len();
+    for (i, c) in path.bytes().enumerate() {
+        match c {
+            b'(' => balance[0] += 1,
+            b'[' => balance[1] += 1,
+            b'{' => balance[2] += 1,
+            b')' => balance[0] -= 1,
+            b']' => balance[1] -= 1,
+            b'}' => balance[2] -= 1,
+            _ => {}
+        }
+        if balance.iter().any(|&b| b < 0) {
+            path_end = i;
+            break;
+        }
+    }
+    while path_end > 0 && BAD_PATH_END_CHARS.contains(&path.as_bytes()[path_end - 1]) {
+        path_end -= 1;
+    }
+    let length = url.len() - (path.len() - path_end);
+
+    let domain_parts: Vec<&str> = domain.split('.').collect();
+    if domain_parts.len() <= 1 {
+        return None;
+    }
+
+    let mut is_ipv4 = domain_parts.len() == 4;
+    let mut has_non_digit = false;
+    for part in &domain_parts {
+        let bytes = part.as_bytes();
+        if bytes.is_empty() || bytes.len() >= 64 || bytes.last() == Some(&b'-') {
+            return None;
+        }
+        if !has_non_digit {
+            if bytes.len() > 3 {
+                is_ipv4 = false;
+            }
+            if !bytes.iter().all(u8::is_ascii_digit) {
+                is_ipv4 = false;
+                has_non_digit = true;
+            }
+            if bytes.len() == 3
+                && (bytes[0] >= b'3'
+                    || (bytes[0] == b'2'
+                        && (bytes[1] >= b'6' || (bytes[1] == b'5' && bytes[2] >= b'6'))))
+            {
+                is_ipv4 = false;
+            }
+            if bytes[0] == b'0' && bytes.len() >= 2 {
+                is_ipv4 = false;
+            }
+        }
+    }
+    if is_ipv4 {
+        return Some(length);
+    }
+    if !has_non_digit {
+        return None;
+    }
+
+    let (tld, rest) = domain_parts.split_last()?;
+    if tld.chars().count() <= 1 {
+        return None;
+    }
+    if let Some(encoded) = tld.strip_prefix("xn--") {
+        if encoded.len() <= 1 || !encoded.bytes().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+    } else if tld.contains(['_', '-']) || (!has_protocol && !is_common_tld(tld)) {
+        return None;
+    }
+
+    if rest.last().is_some_and(|part| part.contains('_')) {
+        return None;
+    }
+    Some(length)
+}
+
+/// Returns true if `c` may appear in the user name and password of a URL.
+fn is_user_data_symbol(c: char) -> bool {
+    match c {
+        '\n' | '/' | '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '<' | '>' | '"'
+        | '\u{ab}' | '\u{bb}' => false,
+        _ => is_non_punctuation(c),
+    }
+}
+
+/// Returns true if `c` may appear in a domain name.
+fn is_domain_symbol(c: char) -> bool {
+    if u32::from(c) < 0xc0 {
+        return c == '.' || c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '~');
+    }
+    is_non_punctuation(c)
+}
+
+/// Returns true if `c` may appear in a URL path, query or fragment.
+fn is_path_symbol(c: char) -> bool {
+    match c {
+        '\n' | '<' | '>' | '"' | '\u{ab}' | '\u{bb}' => false,
+        _ => is_non_punctuation(c),
+    }
+}
+
+/// Returns true if `c` may appear in a URL protocol name.
+fn is_protocol_symbol(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '+' || c == '-'
+}
+
+/// Returns false for whitespace and General Punctuation characters other
+/// than the zero-width joiners.
+fn is_non_punctuation(c: char) -> bool {
+    if ('\u{2000}'..='\u{206f}').contains(&c) {
+        return c == '\u{200c}' || c == '\u{200d}';
+    }
+    !c.is_whitespace()
+}
+
+/// Returns true if `c` may appear in a part of an email address.
+fn is_email_symbol(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+}
+
+/// Returns the position after the characters from `pos` matching `accept`.
+fn skip_forward(text: &str, mut pos: usize, accept: impl Fn(char) -> bool) -> usize {
+    while let Some(c) = next_char(text, pos) {
+        if !accept(c) {
+            break;
+        }
+        pos += c.len_utf8();
+    }
+    pos
+}
+
+/// Returns the position of the first of the characters before `pos` matching
+/// `accept`, not moving past `limit`.
+fn skip_backward(text: &str, limit: usize, mut pos: usize, accept: impl Fn(char) -> bool) -> usize {
+    while pos > limit {
+        match prev_char(text, pos) {
+            Some(c) if accept(c) => pos -= c.len_utf8(),
+            _ => break,
+        }
+    }
+    pos
+}
+
+/// Returns true if `text` starts with the ASCII `prefix`, ignoring case.
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.as_bytes()
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fix_url_trims_path() {
+        assert_eq!(fix_url("google.com/abc)"), Some(14));
+        assert_eq!(fix_url("google.com/(abc)"), Some(16));
+        assert_eq!(fix_url("google.com/abc?!"), Some(14));
+    }
+
+    #[test]
+    fn test_fix_url_domains() {
+        assert_eq!(fix_url("127.0.0.1"), Some(9));
+        assert_eq!(fix_url("127.0.0.256"), None);
+        assert_eq!(fix_url("test.abd"), None);
+        assert_eq!(fix_url("http://test.abd"), Some(15));
+        assert_eq!(fix_url("test.xn--p1ai"), Some(13));
+        assert_eq!(fix_url("a_b.com"), None);
+        assert_eq!(fix_url("a-.com"), None);
+    }
+}
@@ -0,0 +1,554 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0;
+
+//! Entity detection checked against the test vectors of TDLib's
+//! `test/message_entities.cpp`.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::ops::Range;
+
+use rustgram_message_entity::{
+    find_bank_card_numbers, find_bot_commands, find_cashtags, find_entities, find_hashtags,
+    find_media_timestamps, find_mentions, find_phone_numbers, find_tg_urls, find_urls,
+    is_email_address, MessageEntity, MessageEntityType,
+};
+
+/// Returns the substrings of `text` covered by `ranges`.
+fn slices(text: &str, ranges: Vec<Range<usize>>) -> Vec<&str> {
+    ranges.into_iter().map(|range| &text[range]).collect()
+}
+
+/// Checks a finder against a table of texts and expected matches.
+fn check(finder: fn(&str) -> Vec<Range<usize>>, table: &[(&str, &[&str])]) {
+    for &(text, expected) in table {
+        assert_eq!(slices(text, finder(text)), expected, "text: {text:?}");
+    }
+}
+
+#[test]
+fn test_mentions() {
+    check(
+        find_mentions,
+        &[
+            ("@mention", &["@mention"]),
+            ("@mention ", &["@mention"]),
+            (" @mention", &["@mention"]),
+            (" @mention ", &["@mention"]),
+            ("@abc @xyz @abc @xyz @xxx@yyy @ttt", &[]),
+            (
+                "@abcde @xyzxy @abcde @xyzxy @xxxxx@yyyyy @ttttt",
+                &["@abcde", "@xyzxy", "@abcde", "@xyzxy", "@xxxxx", "@ttttt"],
+            ),
+            ("no@mention", &[]),
+            ("@n", &[]),
+            (
+                "@abcdefghijklmnopqrstuvwxyz123456",
+                &["@abcdefghijklmnopqrstuvwxyz123456"],
+            ),
+            ("@abcdefghijklmnopqrstuvwxyz1234567", &[]),
+            ("нет@mention", &[]),
+            (
+                "@ya @gif @wiki @vid @bing @pic @bold @imdb @coub @like @vote @giff @bingg",
+                &[
+                    "@gif", "@wiki", "@vid", "@bing", "@pic", "@bold", "@imdb", "@coub", "@like",
+                    "@vote", "@giff", "@bingg",
+                ],
+            ),
+        ],
+    );
+    let long = "a".repeat(100_000);
+    assert!(find_mentions(&long).is_empty());
+}
+
+#[test]
+fn test_bot_commands() {
+    check(
+        find_bot_commands,
+        &[
+            ("/abc", &["/abc"]),
+            (" /abc", &["/abc"]),
+            ("/abc ", &["/abc"]),
+            (" /abc ", &["/abc"]),
+            ("/a@abc", &["/a@abc"]),
+            ("/a@b", &[]),
+            ("/@bfdsa", &[]),
+            ("/test/", &[]),
+            ("</abc>", &[]),
+            ("a/abc", &[]),
+        ],
+    );
+}
+
+#[test]
+fn test_hashtags() {
+    check(
+        find_hashtags,
+        &[
+            ("", &[]),
+            ("#", &[]),
+            ("##", &[]),
+            ("###", &[]),
+            ("#a", &["#a"]),
+            (" #a", &["#a"]),
+            ("#a ", &["#a"]),
+            (" #я ", &["#я"]),
+            (" я#a ", &[]),
+            (" #a# ", &[]),
+            (" #123 ", &[]),
+            (" #123a ", &["#123a"]),
+            (" #a123 ", &["#a123"]),
+            (" #123a# ", &[]),
+            ("#a#b #c #d", &["#c", "#d"]),
+            ("#test", &["#test"]),
+            ("#te·st", &["#te·st"]),
+            ("\u{0001}#test\u{0001}#test\u{0001}", &["#test", "#test"]),
+        ],
+    );
+
+    let digits_255 = "1".repeat(255);
+    let digits_256 = "1".repeat(256);
+    let digits_300 = "1".repeat(300);
+    let long_table: Vec<(String, Vec<String>)> = vec![
+        (format!(" #{digits_300}"), vec![]),
+        (format!(" #{digits_256}"), vec![]),
+        (format!(" #{digits_256}a "), vec![]),
+        (format!(" #{digits_255}a"), vec![format!("#{digits_255}a")]),
+        (format!(" #{digits_255}Я"), vec![format!("#{digits_255}Я")]),
+        (format!(" #{digits_255}a{}b# ", "b".repeat(255)), vec![]),
+        (
+            format!(" #{digits_255}a{} ", "b".repeat(255)),
+            vec![format!("#{digits_255}a")],
+        ),
+    ];
+    for (text, expected) in &long_table {
+        assert_eq!(&slices(text, find_hashtags(text)), expected);
+    }
+}
+
+#[test]
+fn test_cashtags() {
+    check(
+        find_cashtags,
+        &[
+            ("", &[]),
+            ("$", &[]),
+            ("$$", &[]),
+            ("$$$", &[]),
+            ("$a", &[]),
+            (" $a", &[]),
+            ("$a ", &[]),
+            (" $я ", &[]),
+            ("$ab", &[]),
+            ("$abc", &[]),
+            ("$A", &[]),
+            ("$AB", &[]),
+            ("$АBC", &[]),
+            ("$АВС", &[]),
+            ("$ABC", &["$ABC"]),
+            ("$ABCD", &["$ABCD"]),
+            ("$ABCDE", &["$ABCDE"]),
+            ("$ABCDEF", &["$ABCDEF"]),
+            ("$ABCDEFG", &["$ABCDEFG"]),
+            ("$ABCDEFGH", &["$ABCDEFGH"]),
+            ("$ABCDEFGHJ", &[]),
+            ("$ABCDEFGH1", &[]),
+            (" $XYZ", &["$XYZ"]),
+            ("$XYZ ", &["$XYZ"]),
+            (" $XYZ ", &["$XYZ"]),
+            (" $$XYZ ", &[]),
+            (" $XYZ$ ", &[]),
+            (" $ABC1 ", &[]),
+            (" $1ABC ", &[]),
+            (" 1$ABC ", &[]),
+            (" А$ABC ", &[]),
+            ("$ABC$DEF $GHI $KLM", &["$GHI", "$KLM"]),
+            ("$TEST", &["$TEST"]),
+            ("$1INC", &[]),
+            ("$1INCH", &["$1INCH"]),
+            ("...$1INCH...", &["$1INCH"]),
+            ("$1inch", &[]),
+            ("$1INCHA", &[]),
+            ("$1INCHА", &[]),
+        ],
+    );
+}
+
+#[test]
+fn test_bank_card_numbers() {
+    check(
+        find_bank_card_numbers,
+        &[
+            ("", &[]),
+            ("123456789012", &[]),
+            ("1234567890123", &[]),
+            ("12345678901234", &[]),
+            ("123456789012345", &[]),
+            ("1234567890123456", &[]),
+            ("12345678901234567", &[]),
+            ("123456789012345678", &[]),
+            ("1234567890123456789", &[]),
+            ("12345678901234567890", &[]),
+            ("4", &[]),
+            ("4111111111111111", &["4111111111111111"]),
+            ("4111 1111 1111 1111", &["4111 1111 1111 1111"]),
+            ("4111-1111-1111-1111", &["4111-1111-1111-1111"]),
+            ("4111111111111111 ", &["4111111111111111"]),
+            (
+                "4111111111111111 4111111111111111",
+                &["4111111111111111", "4111111111111111"],
+            ),
+            ("4111111111111111.", &["4111111111111111"]),
+            (".4111111111111111", &[]),
+            ("4111111111111111a", &[]),
+            ("a4111111111111111", &[]),
+            ("4111111111111111-", &[]),
+            ("-4111111111111111", &[]),
+            ("4111111111111111_", &[]),
+            ("_4111111111111111", &[]),
+            ("4111111111111111,", &["4111111111111111"]),
+            (",4111111111111111", &[]),
+            ("+4111111111111111", &[]),
+            ("4111 1111 1111 1112", &[]),
+            ("378282246310005", &["378282246310005"]),
+            ("3782822463100050", &[]),
+            ("5555555555554444", &["5555555555554444"]),
+            ("2223003122003222", &["2223003122003222"]),
+            ("6200000000000005", &["6200000000000005"]),
+        ],
+    );
+}
+
+#[test]
+fn test_media_timestamps() {
+    let table: &[(&str, &[(&str, i32)])] = &[
+        ("", &[]),
+        (":", &[]),
+        (":1", &[]),
+        ("a:1", &[]),
+        ("01", &[]),
+        ("01:", &[]),
+        ("01::", &[]),
+        ("01::-1", &[]),
+        ("a1:1a", &[]),
+        ("a1::01a", &[]),
+        ("2001:db8::8a2e:f70:13a4", &[]),
+        ("0:00", &[("0:00", 0)]),
+        ("+0:00", &[("0:00", 0)]),
+        ("0:00+", &[("0:00", 0)]),
+        ("a0:00", &[]),
+        ("0:00a", &[]),
+        ("б0:00", &[]),
+        ("0:00б", &[]),
+        ("_0:00", &[]),
+        ("0:00_", &[]),
+        ("00:00:00:00", &[]),
+        ("1:1:01 1:1:1", &[("1:1:01", 3661)]),
+        (
+            "0:0:00 00:00 000:00 0000:00 00000:00 00:00:00 000:00:00 00:000:00 00:00:000",
+            &[
+                ("0:0:00", 0),
+                ("00:00", 0),
+                ("000:00", 0),
+                ("0000:00", 0),
+                ("00:00:00", 0),
+            ],
+        ),
+        (
+            "00:0:00 0:00:00 00::00 :00:00 00:00: 00:00:0 00:00:0a",
+            &[("00:0:00", 0), ("0:00:00", 0)],
+        ),
+        ("1:1:59 1:1:-1 1:1:60", &[("1:1:59", 3719)]),
+        (
+            "1:59:00 1:-1:00 1:60:00",
+            &[("1:59:00", 7140), ("1:00", 60)],
+        ),
+        ("59:59 60:00", &[("59:59", 3599), ("60:00", 3600)]),
+        (
+            "9999:59 99:59:59 99:60:59",
+            &[("9999:59", 599_999), ("99:59:59", 360_000 - 1)],
+        ),
+    ];
+    for &(text, expected) in table {
+        let found: Vec<(&str, i32)> = find_media_timestamps(text)
+            .into_iter()
+            .map(|(range, timestamp)| (&text[range], timestamp))
+            .collect();
+        assert_eq!(found, expected, "text: {text:?}");
+    }
+}
+
+#[test]
+fn test_is_email_address() {
+    let table = [
+        ("telegram.org", false),
+        ("security@telegram.org", true),
+        ("security.telegram.org", false),
+        ("", false),
+        ("@", false),
+        ("A@a.a.a.ab", true),
+        ("A@a.ab", true),
+        ("Test@aa.aa.aa.aa", true),
+        ("Test@test.abd", true),
+        ("a@a.a.a.ab", true),
+        ("test@test.abd", true),
+        ("test@test.com", true),
+        ("test.abd", false),
+        ("a.ab", false),
+        ("a.bc@d.ef", true),
+    ];
+    for (text, expected) in table {
+        assert_eq!(is_email_address(text), expected, "{text}");
+    }
+
+    let bad_user_data = [
+        "",
+        "a.a.a.a.a.a.a.a.a.a.a.a",
+        "+.+.+.+.+.+",
+        "*.a.a",
+        "a.*.a",
+        "a.a.*",
+        "a.a.",
+        "a.a.abcdefghijklmnopqrstuvwxyz0123456789",
+        "a.abcdefghijklmnopqrstuvwxyz0.a",
+        "abcdefghijklmnopqrstuvwxyz0.a.a",
+    ];
+    let good_user_data = [
+        "a.a.a.a.a.a.a.a.a.a.a",
+        "a+a+a+a+a+a+a+a+a+a+a",
+        "+.+.+.+.+._",
+        "aozAQZ0-5-9_+-aozAQZ0-5-9_.aozAQZ0-5-9_.-._.+-",
+        "a.a.a",
+        "a.a.abcdefghijklmnopqrstuvwxyz012345678",
+        "a.abcdefghijklmnopqrstuvwxyz.a",
+        "a..a",
+        "abcdefghijklmnopqrstuvwxyz.a.a",
+        ".a.a",
+    ];
+    let bad_domains = [
+        "",
+        ".",
+        "abc",
+        "localhost",
+        "a.a.a.a.a.a.a.ab",
+        ".......",
+        "a.a.a.a.a.a+ab",
+        "a+a.a.a.a.a.ab",
+        "a.a.a.a.a.a.a",
+        "a.a.a.a.a.a.abcdefghi",
+        "a.a.a.a.a.a.ab0yz",
+        "a.a.a.a.a.a.ab9yz",
+        "a.a.a.a.a.a.ab-yz",
+        "a.a.a.a.a.a.ab_yz",
+        "a.a.a.a.a.a.ab*yz",
+        ".ab",
+        ".a.ab",
+        "a..ab",
+        "a.a.a..a.ab",
+        ".a.a.a.a.ab",
+        "abcdefghijklmnopqrstuvwxyz01234.ab",
+        "ab0cd.abd.aA*sd.0.9.0-9.ABOYZ",
+        "ab*cd.abd.aAasd.0.9.0-9.ABOYZ",
+        "ab0cd.abd.aAasd.0.9.0*9.ABOYZ",
+        "*b0cd.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0c*.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9.0-*.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9.*-9.ABOYZ",
+        "-b0cd.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0c-.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.-.9.0-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9.--9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9.0--.ABOYZ",
+        "_b0cd.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0c_.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd._.9.0-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9._-9.ABOYZ",
+        "ab0cd.ab_d.aA-sd.0.9.0-_.ABOYZ",
+        "-.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0cd.ab_d.-.0.9.0-9.ABOYZ",
+        "_.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "ab0cd.ab_d._.0.9.0-9.ABOYZ",
+    ];
+    let good_domains = [
+        "a.a.a.a.a.a.ab",
+        "a.a.a.a.a.a.abcdefgh",
+        "a.a.a.a.a.a.ABCDEFGH",
+        "a.a.a.a.a.a.ABCDEFG",
+        "ab0cd.ab_d.aA-sd.0.9.0-9.ABOYZ",
+        "abcdefghijklmnopqrstuvwxyz0123.ab",
+    ];
+
+    for user_data in good_user_data {
+        for domain in good_domains {
+            let email = format!("{user_data}@{domain}");
+            assert!(is_email_address(&email), "{email}");
+        }
+        for domain in bad_domains {
+            let email = format!("{user_data}@{domain}");
+            assert!(!is_email_address(&email), "{email}");
+        }
+    }
+    for user_data in bad_user_data {
+        for domain in good_domains.iter().chain(bad_domains.iter()) {
+            let email = format!("{user_data}@{domain}");
+            assert!(!is_email_address(&email), "{email}");
+        }
+    }
+}
+
+#[test]
+fn test_urls() {
+    let table: &[(&str, &[&str])] = &[
+        ("telegram.org", &["telegram.org"]),
+        ("(telegram.org)", &["telegram.org"]),
+        ("\ntelegram.org)", &["telegram.org"]),
+        (" telegram.org)", &["telegram.org"]),
+        (".telegram.org)", &[]),
+        ("()telegram.org/?q=()", &["telegram.org/?q=()"]),
+        ("\"telegram.org\"", &["telegram.org"]),
+        (
+            " telegram. org. www. com... telegram.org... ...google.com...",
+            &["telegram.org"],
+        ),
+        (" telegram.org ", &["telegram.org"]),
+        (
+            "Такой сайт: http://www.google.com или такой telegram.org ",
+            &["http://www.google.com", "telegram.org"],
+        ),
+        (" telegram.org. ", &["telegram.org"]),
+        ("http://google,.com", &[]),
+        (
+            "http://telegram.org/?asd=123#123.",
+            &["http://telegram.org/?asd=123#123"],
+        ),
+        ("[http://google.com](test)", &["http://google.com"]),
+        ("", &[]),
+        (".", &[]),
+        ("http://@google.com", &[]),
+        ("http://a@google.com", &["http://a@google.com"]),
+        ("http://test@google.com", &["http://test@google.com"]),
+        ("google.com:᪉᪉᪉᪉᪉", &["google.com"]),
+        ("https://telegram.org", &["https://telegram.org"]),
+        ("http://telegram.org", &["http://telegram.org"]),
+        ("ftp://telegram.org", &["ftp://telegram.org"]),
+        ("ftps://telegram.org", &[]),
+        ("sftp://telegram.org", &[]),
+        ("hTtPs://telegram.org", &["hTtPs://telegram.org"]),
+        ("HTTP://telegram.org", &["HTTP://telegram.org"]),
+        ("sHTTP://telegram.org", &[]),
+        ("://telegram.org", &[]),
+        ("google.com:᪀᪀", &["google.com"]),
+        ("http://google.com:65535", &["http://google.com:65535"]),
+        ("http://google.com:65536", &["http://google.com"]),
+        ("http://google.com:0", &["http://google.com"]),
+        (
+            "http://google.com:0000000001",
+            &["http://google.com:0000000001"],
+        ),
+        ("http://google.com:-1", &["http://google.com"]),
+        ("http://127.0.0.1", &["http://127.0.0.1"]),
+        ("http://127.0.0.1:80", &["http://127.0.0.1:80"]),
+        ("127.0.0.1", &["127.0.0.1"]),
+        ("127.0.0.256", &[]),
+        ("google.com/abc)", &["google.com/abc"]),
+        ("t.me/abcdef…", &["t.me/abcdef"]),
+        ("t.me…", &["t.me"]),
+        ("t.m…", &[]),
+        ("www.ya.r…", &[]),
+        ("..", &[]),
+        ("www.ya.ru/…", &["www.ya.ru/"]),
+        ("test.abd", &[]),
+        ("http://test.abd", &["http://test.abd"]),
+        ("test.com", &["test.com"]),
+        ("test.xn--p1ai", &["test.xn--p1ai"]),
+        ("test.xn--p", &[]),
+        ("пример.рф", &["пример.рф"]),
+        ("ПРИМЕР.РФ", &["ПРИМЕР.РФ"]),
+        ("http://a.a", &[]),
+        ("teiegram.org", &[]),
+        ("TeiegraM.org", &[]),
+        ("http://teiegram.org", &[]),
+        ("a_b.com", &[]),
+        ("http://a_b.c.com", &["http://a_b.c.com"]),
+    ];
+    for &(text, expected) in table {
+        let urls: Vec<&str> = find_urls(text)
+            .into_iter()
+            .filter(|(_, is_email)| !is_email)
+            .map(|(range, _)| &text[range])
+            .collect();
+        assert_eq!(urls, expected, "text: {text:?}");
+    }
+}
+
+#[test]
+fn test_emails_in_text() {
+    let text = "write to security@telegram.org or mailto:admin@telegram.org.";
+    let emails: Vec<&str> = find_urls(text)
+        .into_iter()
+        .filter(|(_, is_email)| *is_email)
+        .map(|(range, _)| &text[range])
+        .collect();
+    // ':' separates parts of the user name, as in TDLib
+    assert_eq!(emails, ["security@telegram.org", "mailto:admin@telegram.org"]);
+}
+
+#[test]
+fn test_tg_urls() {
+    check(
+        find_tg_urls,
+        &[
+            (
+                "tg://resolve?domain=telegram",
+                &["tg://resolve?domain=telegram"],
+            ),
+            ("open TG://settings.", &["TG://settings"]),
+            ("ton://transfer/addr", &["ton://transfer/addr"]),
+            ("atg://resolve", &[]),
+            ("tg://", &[]),
+        ],
+    );
+}
+
+#[test]
+fn test_phone_numbers() {
+    check(
+        find_phone_numbers,
+        &[
+            ("+79991234567", &["+79991234567"]),
+            ("call +7 999 123-45-67 now", &["+7 999 123-45-67"]),
+            ("+1 (555) 123-4567", &["+1 (555) 123-4567"]),
+            ("+1 (555 123", &[]),
+            ("+123456", &[]),
+            ("+1234567890123456", &[]),
+            ("a+79991234567", &[]),
+            ("+79991234567a", &[]),
+            ("+7  999 123 45 67", &[]),
+            ("+7 999 123 45 67 1:30", &["+7 999 123 45 67"]),
+        ],
+    );
+}
+
+#[test]
+fn test_find_entities_combined() {
+    let text = "/start @username #tag $USD https://t.me 4111 1111 1111 1111 \
+                me@example.com +79991234567 1:02:03";
+    let entities = find_entities(text, false, false);
+    let types: Vec<MessageEntityType> = entities.iter().map(MessageEntity::entity_type).collect();
+    assert_eq!(
+        types,
+        [
+            MessageEntityType::BotCommand,
+            MessageEntityType::Mention,
+            MessageEntityType::Hashtag,
+            MessageEntityType::Cashtag,
+            MessageEntityType::Url,
+            MessageEntityType::BankCardNumber,
+            MessageEntityType::EmailAddress,
+            MessageEntityType::PhoneNumber,
+            MessageEntityType::MediaTimestamp,
+        ]
+    );
+    assert_eq!(entities[8].get_media_timestamp(), Some(3723));
+}
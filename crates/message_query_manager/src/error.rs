@@ -33,6 +33,10 @@ pub enum Error {
     /// Message search operation failed.
     SearchFailed,
 
+    /// Free public post searches are exhausted; contains the number of
+    /// seconds until the next free search.
+    SearchFlood(i32),
+
     /// Invalid state for the requested operation.
     InvalidState,
 
@@ -51,6 +55,12 @@ impl fmt::Display for Error {
             Self::UploadFailed => write!(f, "Message upload failed"),
             Self::DeleteFailed => write!(f, "Message deletion failed"),
             Self::SearchFailed => write!(f, "Message search failed"),
+            Self::SearchFlood(seconds) => {
+                write!(
+                    f,
+                    "Too many public post searches, retry in {seconds} seconds"
+                )
+            }
             Self::InvalidState => write!(f, "Invalid state for operation"),
             Self::IoError(msg) => write!(f, "I/O error: {msg}"),
             Self::Other(msg) => write!(f, "Error: {msg}"),
@@ -102,6 +112,16 @@ mod tests {
         assert_eq!(format!("{err}"), "Message search failed");
     }
 
+    #[test]
+    fn test_error_search_flood() {
+        let err = Error::SearchFlood(30);
+        assert!(matches!(err, Error::SearchFlood(30)));
+        assert_eq!(
+            format!("{err}"),
+            "Too many public post searches, retry in 30 seconds"
+        );
+    }
+
     #[test]
     fn test_error_invalid_state() {
         let err = Error::InvalidState;
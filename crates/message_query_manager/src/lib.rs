@@ -12,7 +12,8 @@
 //! The MessageQueryManager handles various message-related queries and operations
 //! including:
 //!
-//! - **Search**: Search messages, public posts, and hashtag posts
+//! - **Search**: Search messages globally and in a dialog, public posts,
+//!   and hashtag posts
 //! - **Delete**: Delete messages and dialog history
 //! - **Read**: Mark messages as read, track mentions and reactions
 //! - **Upload**: Upload message covers and photos
@@ -30,6 +31,12 @@
 //! - `need_view_counter_increment`: Messages pending view increment
 //! - `being_reloaded_reactions`: Per-dialog reaction reload state
 //! - `pending_read_reactions`: Messages pending reaction read status
+//! - `search_posts_flood`: Last known limits of public post searches
+//!
+//! Search queries are sent through a [`MessageSearchClient`] set with
+//! [`MessageQueryManager::with_search_client`]; [`NetworkSearchClient`]
+//! sends them through a `NetQueryDispatcher`. Results carry a
+//! `next_offset` to request the next page with.
 //!
 //! # TDLib Correspondence
 //!
@@ -52,17 +59,24 @@
 //!
 //! ```rust
 //! use rustgram_message_query_manager::MessageQueryManager;
+//! use rustgram_message_search_filter::MessageSearchFilter;
 //! use rustgram_dialog_list_id::DialogListId;
 //!
 //! # #[tokio::main]
 //! # async fn example() {
 //! let manager = MessageQueryManager::new();
-//! let results = manager.search_messages(
+//! if let Ok(results) = manager.search_messages(
 //!     DialogListId::main(),
 //!     "search query".to_string(),
 //!     String::new(),
 //!     10,
-//! ).await;
+//!     MessageSearchFilter::Empty,
+//!     0,
+//!     0,
+//! ).await {
+//!     // Request the next page with results.next_offset()
+//!     println!("Found {} messages", results.total_count());
+//! }
 //! # }
 //! ```
 //!
@@ -123,26 +137,36 @@
 //!
 //! # Stub Implementation Notice
 //!
-//! This is a stub implementation that matches the TDLib API structure.
-//! The actual TDLib client integration will be implemented in a future update.
-//! Current methods return default/empty values for testing purposes.
+//! Apart from message search, this is a stub implementation that matches
+//! the TDLib API structure. The actual TDLib client integration will be
+//! implemented in a future update. Current methods return default/empty
+//! values for testing purposes.
 
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 #![deny(clippy::unwrap_used)]
 #![deny(clippy::expect_used)]
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
 pub mod error;
 pub mod manager;
+pub mod network;
 pub mod state;
 pub mod tl;
+mod tl_message;
+mod tl_peer;
 
 // Re-exports
 pub use error::{Error, Result};
 pub use manager::{MessageQueryManager, MAX_SEARCH_MESSAGES};
+pub use network::{MessageSearchClient, NetworkSearchClient};
 pub use state::{BeingUploadedCover, MessageReloadState, ReactionsToReload, ReloadType};
 pub use tl::{
-    DiscussionMessage, FactCheck, FoundMessages, InputFile, MessageMedia, SearchPostsFlood,
+    DiscussionMessage, FactCheck, FoundMessages, InputFile, MessageMedia, SearchGlobalRequest,
+    SearchPostsFlood, SearchPostsRequest, SearchRequest, SearchResult,
+};
+pub use tl_message::{
+    read_server_message, ServerDocumentAttribute, ServerMessage, ServerMessageMedia,
 };
 
 /// Version information for the crate.
@@ -203,6 +227,7 @@ mod tests {
             Error::UploadFailed,
             Error::DeleteFailed,
             Error::SearchFailed,
+            Error::SearchFlood(1),
             Error::InvalidState,
             Error::IoError("test".to_string()),
             Error::Other("test".to_string()),
@@ -4,23 +4,31 @@
 
 //! MessageQueryManager implementation.
 //!
-//! This is a stub implementation that matches TDLib's API structure.
-//! Actual TDLib client integration will happen later.
+//! Message search is sent to the server through a [`MessageSearchClient`];
+//! the remaining operations are a stub implementation that matches TDLib's
+//! API structure.
 
 use crate::error::{Error, Result};
+use crate::network::MessageSearchClient;
 use crate::state::{BeingUploadedCover, ReactionsToReload};
-use crate::tl::{FoundMessages, MessageMedia};
+use crate::tl::{
+    FoundMessages, MessageMedia, SearchGlobalRequest, SearchPostsFlood, SearchPostsRequest,
+    SearchRequest, SearchResult,
+};
 use rustgram_affected_history::AffectedHistory;
 use rustgram_business_connection_id::BusinessConnectionId;
 use rustgram_dialog_list_id::DialogListId;
 use rustgram_file_upload_id::FileUploadId;
 use rustgram_message_extended_media::Photo;
 use rustgram_message_full_id::MessageFullId;
+use rustgram_message_search_filter::MessageSearchFilter;
 use rustgram_message_thread_info::MessageThreadInfo;
 use rustgram_message_viewer::MessageViewers;
 use rustgram_types::{ChannelId, DialogId, MessageId};
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
 /// Maximum number of messages to search (server-side limit).
@@ -28,6 +36,33 @@ use tokio::sync::RwLock;
 /// This matches TDLib's MAX_SEARCH_MESSAGES constant.
 pub const MAX_SEARCH_MESSAGES: i32 = 100;
 
+/// Last known limits of public post searches.
+#[derive(Debug, Clone)]
+struct KnownSearchPostsFlood {
+    /// Query the limits were received for.
+    query: String,
+    /// The limits.
+    flood: SearchPostsFlood,
+    /// Time the limits were received.
+    received_at: Instant,
+}
+
+impl KnownSearchPostsFlood {
+    /// Returns the number of seconds until the query can be searched for free.
+    fn get_wait_seconds(&self, query: &str) -> i32 {
+        let flood = if self.query == query {
+            self.flood
+        } else {
+            self.flood.with_query_is_free(false)
+        };
+        if !flood.is_exhausted() {
+            return 0;
+        }
+        let elapsed = i32::try_from(self.received_at.elapsed().as_secs()).unwrap_or(i32::MAX);
+        flood.wait_seconds().saturating_sub(elapsed).max(0)
+    }
+}
+
 /// Message query manager.
 ///
 /// Manages message queries including search, deletion, reading, and view operations.
@@ -41,7 +76,7 @@ pub const MAX_SEARCH_MESSAGES: i32 = 100;
 /// let manager = MessageQueryManager::new();
 /// assert!(!manager.has_pending_operations());
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct MessageQueryManager {
     /// Internal state for cover uploads.
     being_uploaded_covers: Arc<RwLock<HashMap<FileUploadId, BeingUploadedCover>>>,
@@ -57,6 +92,35 @@ pub struct MessageQueryManager {
     being_reloaded_reactions: Arc<RwLock<HashMap<DialogId, ReactionsToReload>>>,
     /// Messages pending read reactions.
     pending_read_reactions: Arc<RwLock<HashMap<MessageFullId, i32>>>,
+    /// Last known limits of public post searches.
+    search_posts_flood: Arc<RwLock<Option<KnownSearchPostsFlood>>>,
+    /// Client sending search queries.
+    search_client: Option<Arc<dyn MessageSearchClient>>,
+}
+
+impl fmt::Debug for MessageQueryManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageQueryManager")
+            .field("being_uploaded_covers", &self.being_uploaded_covers)
+            .field(
+                "being_reloaded_extended_media",
+                &self.being_reloaded_extended_media,
+            )
+            .field(
+                "being_reloaded_fact_checks",
+                &self.being_reloaded_fact_checks,
+            )
+            .field("being_reloaded_views", &self.being_reloaded_views)
+            .field(
+                "need_view_counter_increment",
+                &self.need_view_counter_increment,
+            )
+            .field("being_reloaded_reactions", &self.being_reloaded_reactions)
+            .field("pending_read_reactions", &self.pending_read_reactions)
+            .field("search_posts_flood", &self.search_posts_flood)
+            .field("has_search_client", &self.search_client.is_some())
+            .finish()
+    }
 }
 
 impl Default for MessageQueryManager {
@@ -86,26 +150,50 @@ impl MessageQueryManager {
             need_view_counter_increment: Arc::new(RwLock::new(Vec::new())),
             being_reloaded_reactions: Arc::new(RwLock::new(HashMap::new())),
             pending_read_reactions: Arc::new(RwLock::new(HashMap::new())),
+            search_posts_flood: Arc::new(RwLock::new(None)),
+            search_client: None,
         }
     }
 
+    /// Sets the client sending search queries.
+    ///
+    /// Without a client, searches which need a request fail.
+    #[must_use]
+    pub fn with_search_client(mut self, client: Arc<dyn MessageSearchClient>) -> Self {
+        self.search_client = Some(client);
+        self
+    }
+
     // ========================================================================
     // Search Operations
     // ========================================================================
 
-    /// Searches for messages in a dialog list.
+    /// Searches for messages in all dialogs of a dialog list.
+    ///
+    /// Sends `messages.searchGlobal`. Searching in chat folders isn't
+    /// supported by the server, only the main and archive lists can be
+    /// searched.
     ///
     /// # Arguments
     ///
-    /// * `_dialog_list_id` - Dialog list to search in
-    /// * `_query` - Search query string
-    /// * `_offset` - Search offset string
+    /// * `dialog_list_id` - Dialog list to search in
+    /// * `query` - Search query string
+    /// * `offset` - Offset of the page, empty for the first page
     /// * `limit` - Maximum results (max MAX_SEARCH_MESSAGES)
+    /// * `filter` - Filter of the messages
+    /// * `min_date` - Minimum date of the messages, 0 for no bound
+    /// * `max_date` - Maximum date of the messages, 0 for no bound
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidState`] if the limit is out of range, and
+    /// [`Error::Other`] if the list, filter or offset can't be used.
     ///
     /// # Example
     ///
     /// ```
     /// use rustgram_message_query_manager::MessageQueryManager;
+    /// use rustgram_message_search_filter::MessageSearchFilter;
     /// use rustgram_dialog_list_id::DialogListId;
     ///
     /// # #[tokio::main]
@@ -116,30 +204,177 @@ impl MessageQueryManager {
     ///     "test".to_string(),
     ///     String::new(),
     ///     10,
+    ///     MessageSearchFilter::Empty,
+    ///     0,
+    ///     0,
     /// ).await;
     /// # }
     /// ```
+    #[allow(clippy::too_many_arguments)]
     pub async fn search_messages(
         &self,
-        _dialog_list_id: DialogListId,
-        _query: String,
-        _offset: String,
+        dialog_list_id: DialogListId,
+        query: String,
+        offset: String,
         limit: i32,
+        filter: MessageSearchFilter,
+        min_date: i32,
+        max_date: i32,
     ) -> Result<FoundMessages> {
         if limit <= 0 || limit > MAX_SEARCH_MESSAGES {
             return Err(Error::InvalidState);
         }
-        // Stub: Return empty results
-        Ok(FoundMessages::with_total_count(0))
+        if !dialog_list_id.is_folder() {
+            return Err(Error::Other(
+                "Chat folders can't be searched on the server".to_string(),
+            ));
+        }
+        if !is_global_search_filter(filter) {
+            return Err(Error::Other(format!(
+                "Filter {} isn't supported in global search",
+                filter.as_str()
+            )));
+        }
+        let (offset_rate, offset_dialog_id, offset_id) = parse_global_offset(&offset)?;
+        if query.is_empty() && filter.is_empty() {
+            return Ok(FoundMessages::new());
+        }
+
+        let client = self.get_search_client()?;
+        let result = client
+            .search_global(SearchGlobalRequest {
+                folder_id: Some(dialog_list_id.get_folder_id().get()),
+                query,
+                filter,
+                min_date,
+                max_date,
+                offset_rate,
+                offset_dialog_id,
+                offset_id,
+                limit,
+            })
+            .await?;
+        Ok(found_global_messages(result, limit))
+    }
+
+    /// Searches for messages in a dialog.
+    ///
+    /// Sends `messages.search`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Dialog to search in
+    /// * `query` - Search query string
+    /// * `sender_dialog_id` - Sender of the messages, `None` for any sender
+    /// * `offset` - Offset of the page, empty for the first page
+    /// * `limit` - Maximum results (max MAX_SEARCH_MESSAGES)
+    /// * `filter` - Filter of the messages
+    /// * `min_date` - Minimum date of the messages, 0 for no bound
+    /// * `max_date` - Maximum date of the messages, 0 for no bound
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidDialog`] if the dialog or the sender is
+    /// invalid, [`Error::InvalidState`] if the limit is out of range, and
+    /// [`Error::Other`] if the filter or offset can't be used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rustgram_message_query_manager::MessageQueryManager;
+    /// use rustgram_message_search_filter::MessageSearchFilter;
+    /// use rustgram_types::{ChannelId, DialogId};
+    ///
+    /// # #[tokio::main]
+    /// # async fn test() {
+    /// let manager = MessageQueryManager::new();
+    /// let dialog_id = DialogId::from_channel(ChannelId::new(123).unwrap());
+    /// let results = manager.search_dialog_messages(
+    ///     dialog_id,
+    ///     String::new(),
+    ///     None,
+    ///     String::new(),
+    ///     10,
+    ///     MessageSearchFilter::Photo,
+    ///     0,
+    ///     0,
+    /// ).await;
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub async fn search_dialog_messages(
+        &self,
+        dialog_id: DialogId,
+        query: String,
+        sender_dialog_id: Option<DialogId>,
+        offset: String,
+        limit: i32,
+        filter: MessageSearchFilter,
+        min_date: i32,
+        max_date: i32,
+    ) -> Result<FoundMessages> {
+        if !dialog_id.is_valid() || sender_dialog_id.is_some_and(|sender| !sender.is_valid()) {
+            return Err(Error::InvalidDialog);
+        }
+        if limit <= 0 || limit > MAX_SEARCH_MESSAGES {
+            return Err(Error::InvalidState);
+        }
+        if dialog_id.get_secret_chat_id().is_some() {
+            return Err(Error::Other(
+                "Secret chats can't be searched on the server".to_string(),
+            ));
+        }
+        if filter.is_failed_to_send() || filter.is_unread_mention() || filter.is_unread_reaction() {
+            return Err(Error::Other(format!(
+                "Filter {} isn't supported in dialog search",
+                filter.as_str()
+            )));
+        }
+        let offset_id = parse_dialog_offset(&offset)?;
+
+        let client = self.get_search_client()?;
+        let result = client
+            .search(SearchRequest {
+                dialog_id,
+                query,
+                sender_dialog_id,
+                filter,
+                min_date,
+                max_date,
+                offset_id,
+                limit,
+            })
+            .await?;
+
+        let has_more = has_more_results(&result, limit);
+        let mut found = FoundMessages::with_total_count(result.total_count());
+        for message_full_id in result.messages {
+            if message_full_id.dialog_id() == dialog_id && message_full_id.message_id().is_server()
+            {
+                found.add_message(message_full_id);
+            }
+        }
+        let next_offset = match found.messages().last() {
+            Some(last) if has_more => last.message_id().get_server_id().to_string(),
+            _ => String::new(),
+        };
+        Ok(found.with_next_offset(next_offset))
     }
 
     /// Searches for public posts by hashtag.
     ///
+    /// Sends `channels.searchPosts`. Hashtag searches are free.
+    ///
     /// # Arguments
     ///
-    /// * `_hashtag` - Hashtag to search for (without #)
-    /// * `_offset` - Search offset string
-    /// * `_limit` - Maximum results
+    /// * `hashtag` - Hashtag to search for, with or without the leading `#`
+    /// * `offset` - Offset of the page, empty for the first page
+    /// * `limit` - Maximum results (max MAX_SEARCH_MESSAGES)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidState`] if the limit is out of range, and
+    /// [`Error::Other`] if the offset is invalid.
     ///
     /// # Example
     ///
@@ -154,25 +389,55 @@ impl MessageQueryManager {
     /// ```
     pub async fn search_hashtag_posts(
         &self,
-        _hashtag: String,
-        _offset: String,
+        hashtag: String,
+        offset: String,
         limit: i32,
     ) -> Result<FoundMessages> {
         if limit <= 0 || limit > MAX_SEARCH_MESSAGES {
             return Err(Error::InvalidState);
         }
-        // Stub: Return empty results
-        Ok(FoundMessages::with_total_count(0))
+        let (offset_rate, offset_dialog_id, offset_id) = parse_global_offset(&offset)?;
+        let hashtag = hashtag
+            .strip_prefix(['#', '$'])
+            .unwrap_or(&hashtag)
+            .to_string();
+        if hashtag.is_empty() {
+            return Ok(FoundMessages::new());
+        }
+
+        let client = self.get_search_client()?;
+        let result = client
+            .search_posts(SearchPostsRequest {
+                hashtag: Some(hashtag),
+                query: None,
+                offset_rate,
+                offset_dialog_id,
+                offset_id,
+                limit,
+                allow_paid_stars: None,
+            })
+            .await?;
+        Ok(found_global_messages(result, limit))
     }
 
     /// Searches for public posts by query.
     ///
+    /// Sends `channels.searchPosts`. Only a limited number of new queries is
+    /// free; when they are exhausted, a query costs Telegram Stars until the
+    /// next free query becomes available. Next pages of a query are free.
+    ///
     /// # Arguments
     ///
-    /// * `_query` - Search query
-    /// * `_offset` - Search offset string
-    /// * `_limit` - Maximum results
-    /// * `_star_count` - Minimum star count
+    /// * `query` - Search query
+    /// * `offset` - Offset of the page, empty for the first page
+    /// * `limit` - Maximum results (max MAX_SEARCH_MESSAGES)
+    /// * `star_count` - Number of Telegram Stars allowed to be paid for the
+    ///   query, 0 to search only for free
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SearchFlood`] if free queries are exhausted and
+    /// `star_count` doesn't cover the price of the query.
     ///
     /// # Example
     ///
@@ -187,16 +452,96 @@ impl MessageQueryManager {
     /// ```
     pub async fn search_public_posts(
         &self,
-        _query: String,
-        _offset: String,
+        query: String,
+        offset: String,
         limit: i32,
-        _star_count: i64,
+        star_count: i64,
     ) -> Result<FoundMessages> {
-        if limit <= 0 || limit > MAX_SEARCH_MESSAGES {
+        if limit <= 0 || limit > MAX_SEARCH_MESSAGES || star_count < 0 {
             return Err(Error::InvalidState);
         }
-        // Stub: Return empty results
-        Ok(FoundMessages::with_total_count(0))
+        let (offset_rate, offset_dialog_id, offset_id) = parse_global_offset(&offset)?;
+        if query.is_empty() {
+            return Ok(FoundMessages::new());
+        }
+        if offset.is_empty() {
+            if let Some(known) = self.search_posts_flood.read().await.as_ref() {
+                let wait_seconds = known.get_wait_seconds(&query);
+                if wait_seconds > 0 && star_count < known.flood.star_count() {
+                    return Err(Error::SearchFlood(wait_seconds));
+                }
+            }
+        }
+
+        let client = self.get_search_client()?;
+        let result = client
+            .search_posts(SearchPostsRequest {
+                hashtag: None,
+                query: Some(query.clone()),
+                offset_rate,
+                offset_dialog_id,
+                offset_id,
+                limit,
+                allow_paid_stars: (star_count > 0).then_some(star_count),
+            })
+            .await?;
+        if let Some(flood) = result.search_flood {
+            self.on_get_search_posts_flood(query, flood).await;
+        }
+        Ok(found_global_messages(result, limit))
+    }
+
+    /// Checks the limits of public post searches for a query.
+    ///
+    /// Sends `channels.checkSearchPostsFlood` and remembers the result, so
+    /// that [`search_public_posts`](Self::search_public_posts) fails without
+    /// a request while the limits are exceeded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn check_search_posts_flood(&self, query: String) -> Result<SearchPostsFlood> {
+        let client = self.get_search_client()?;
+        let flood = client.check_search_posts_flood(query.clone()).await?;
+        self.on_get_search_posts_flood(query, flood).await;
+        Ok(flood)
+    }
+
+    /// Returns the last known limits of public post searches.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rustgram_message_query_manager::MessageQueryManager;
+    ///
+    /// # #[tokio::main]
+    /// # async fn test() {
+    /// let manager = MessageQueryManager::new();
+    /// assert!(manager.search_posts_flood().await.is_none());
+    /// # }
+    /// ```
+    pub async fn search_posts_flood(&self) -> Option<SearchPostsFlood> {
+        self.search_posts_flood
+            .read()
+            .await
+            .as_ref()
+            .map(|known| known.flood)
+    }
+
+    /// Remembers the limits of public post searches.
+    async fn on_get_search_posts_flood(&self, query: String, flood: SearchPostsFlood) {
+        *self.search_posts_flood.write().await = Some(KnownSearchPostsFlood {
+            query,
+            flood,
+            received_at: Instant::now(),
+        });
+    }
+
+    /// Returns the client sending search queries.
+    fn get_search_client(&self) -> Result<Arc<dyn MessageSearchClient>> {
+        self.search_client
+            .clone()
+            .ok_or_else(|| Error::Other("Search client is not set".to_string()))
     }
 
     // ========================================================================
@@ -789,14 +1134,157 @@ impl MessageQueryManager {
         self.need_view_counter_increment.write().await.clear();
         self.being_reloaded_reactions.write().await.clear();
         self.pending_read_reactions.write().await.clear();
+        *self.search_posts_flood.write().await = None;
+    }
+}
+
+/// Checks if a filter can be used in `messages.searchGlobal`.
+fn is_global_search_filter(filter: MessageSearchFilter) -> bool {
+    !(filter.is_call_filter()
+        || filter.is_mention()
+        || filter.is_unread_mention()
+        || filter.is_failed_to_send()
+        || filter.is_pinned()
+        || filter.is_unread_reaction())
+}
+
+/// Parses the offset of a global search.
+///
+/// The offset has the form `rate,dialog_id,server_message_id`, as returned
+/// in [`FoundMessages::next_offset`]; an empty offset starts from the
+/// beginning.
+fn parse_global_offset(offset: &str) -> Result<(i32, Option<DialogId>, i32)> {
+    if offset.is_empty() {
+        return Ok((0, None, 0));
+    }
+    let invalid_offset = || Error::Other(format!("Invalid offset specified: {offset}"));
+    let parts: Vec<&str> = offset.split(',').collect();
+    let [rate, dialog_id, message_id] = parts.as_slice() else {
+        return Err(invalid_offset());
+    };
+    let rate = rate.parse::<i32>().map_err(|_| invalid_offset())?;
+    let dialog_id = dialog_id
+        .parse::<i64>()
+        .ok()
+        .and_then(|dialog_id| DialogId::from_encoded(dialog_id).ok())
+        .ok_or_else(invalid_offset)?;
+    let message_id = message_id
+        .parse::<i32>()
+        .ok()
+        .filter(|message_id| *message_id > 0)
+        .ok_or_else(invalid_offset)?;
+    Ok((rate, Some(dialog_id), message_id))
+}
+
+/// Parses the offset of a dialog search, the server ID of the last found
+/// message.
+fn parse_dialog_offset(offset: &str) -> Result<i32> {
+    if offset.is_empty() {
+        return Ok(0);
+    }
+    offset
+        .parse::<i32>()
+        .ok()
+        .filter(|message_id| *message_id > 0)
+        .ok_or_else(|| Error::Other(format!("Invalid offset specified: {offset}")))
+}
+
+/// Checks if more results can be requested after a page of `limit` messages.
+fn has_more_results(result: &SearchResult, limit: i32) -> bool {
+    result.count.is_some() && result.messages.len() >= usize::try_from(limit).unwrap_or(0)
+}
+
+/// Converts the result of a global search.
+fn found_global_messages(result: SearchResult, limit: i32) -> FoundMessages {
+    let has_more = has_more_results(&result, limit);
+    let next_rate = result.next_rate.unwrap_or(0);
+    let mut found = FoundMessages::with_total_count(result.total_count());
+    for message_full_id in result.messages {
+        if message_full_id.dialog_id().is_valid() && message_full_id.message_id().is_server() {
+            found.add_message(message_full_id);
+        }
     }
+    let next_offset = match found.messages().last() {
+        Some(last) if has_more => format!(
+            "{},{},{}",
+            next_rate,
+            last.dialog_id().to_encoded(),
+            last.message_id().get_server_id()
+        ),
+        _ => String::new(),
+    };
+    found.with_next_offset(next_offset)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
     use rustgram_file_id::FileId;
     use rustgram_types::ChatId;
+    use std::sync::Mutex;
+
+    /// Search client returning a prepared result and recording requests.
+    #[derive(Default)]
+    struct FakeSearchClient {
+        result: SearchResult,
+        flood: Option<SearchPostsFlood>,
+        global_requests: Mutex<Vec<SearchGlobalRequest>>,
+        posts_requests: Mutex<Vec<SearchPostsRequest>>,
+        requests: Mutex<Vec<SearchRequest>>,
+    }
+
+    #[async_trait]
+    impl MessageSearchClient for FakeSearchClient {
+        async fn search_global(&self, request: SearchGlobalRequest) -> Result<SearchResult> {
+            self.global_requests.lock().unwrap().push(request);
+            Ok(self.result.clone())
+        }
+
+        async fn search_posts(&self, request: SearchPostsRequest) -> Result<SearchResult> {
+            self.posts_requests.lock().unwrap().push(request);
+            Ok(self.result.clone())
+        }
+
+        async fn search(&self, request: SearchRequest) -> Result<SearchResult> {
+            self.requests.lock().unwrap().push(request);
+            Ok(self.result.clone())
+        }
+
+        async fn check_search_posts_flood(&self, _query: String) -> Result<SearchPostsFlood> {
+            self.flood.ok_or(Error::SearchFailed)
+        }
+    }
+
+    fn channel_message(channel_id: i64, server_id: i32) -> MessageFullId {
+        MessageFullId::new(
+            DialogId::from_channel(ChannelId::new(channel_id).unwrap()),
+            MessageId::from_server_id(server_id),
+        )
+    }
+
+    fn manager_with_client(client: &Arc<FakeSearchClient>) -> MessageQueryManager {
+        MessageQueryManager::new().with_search_client(client.clone())
+    }
+
+    async fn search_main(
+        manager: &MessageQueryManager,
+        query: &str,
+        offset: String,
+        limit: i32,
+    ) -> Result<FoundMessages> {
+        manager
+            .search_messages(
+                DialogListId::main(),
+                query.to_string(),
+                offset,
+                limit,
+                MessageSearchFilter::Empty,
+                0,
+                0,
+            )
+            .await
+    }
 
     // Basic creation tests
     #[test]
@@ -828,43 +1316,260 @@ mod tests {
     // Search operation tests
     #[tokio::test]
     async fn test_search_messages_valid_limit() {
-        let manager = MessageQueryManager::new();
-        let result = manager
-            .search_messages(DialogListId::main(), "test".to_string(), String::new(), 10)
-            .await;
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
+        let result = search_main(&manager, "test", String::new(), 10).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_search_messages_invalid_limit() {
         let manager = MessageQueryManager::new();
-        let result = manager
-            .search_messages(DialogListId::main(), "test".to_string(), String::new(), 0)
-            .await;
+        let result = search_main(&manager, "test", String::new(), 0).await;
         assert!(matches!(result, Err(Error::InvalidState)));
     }
 
     #[tokio::test]
     async fn test_search_messages_exceeds_max() {
         let manager = MessageQueryManager::new();
+        let result = search_main(&manager, "test", String::new(), MAX_SEARCH_MESSAGES + 1).await;
+        assert!(matches!(result, Err(Error::InvalidState)));
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_without_client() {
+        let manager = MessageQueryManager::new();
+        let result = search_main(&manager, "test", String::new(), 10).await;
+        assert!(matches!(result, Err(Error::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_request() {
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
+        manager
+            .search_messages(
+                DialogListId::archive(),
+                "test".to_string(),
+                String::new(),
+                20,
+                MessageSearchFilter::Photo,
+                100,
+                200,
+            )
+            .await
+            .unwrap();
+
+        let requests = client.global_requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].folder_id, Some(1));
+        assert_eq!(requests[0].query, "test");
+        assert_eq!(requests[0].filter, MessageSearchFilter::Photo);
+        assert_eq!((requests[0].min_date, requests[0].max_date), (100, 200));
+        assert_eq!(requests[0].offset_dialog_id, None);
+        assert_eq!(requests[0].limit, 20);
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_next_offset() {
+        let client = Arc::new(FakeSearchClient {
+            result: SearchResult {
+                messages: vec![channel_message(1, 10), channel_message(2, 5)],
+                count: Some(50),
+                next_rate: Some(1_700_000_000),
+                search_flood: None,
+            },
+            ..FakeSearchClient::default()
+        });
+        let manager = manager_with_client(&client);
+
+        let found = search_main(&manager, "test", String::new(), 2)
+            .await
+            .unwrap();
+        assert_eq!(found.total_count(), 50);
+        assert_eq!(
+            found.messages(),
+            &[channel_message(1, 10), channel_message(2, 5)]
+        );
+        let last_dialog_id = channel_message(2, 5).dialog_id();
+        assert_eq!(
+            found.next_offset(),
+            format!("1700000000,{},5", last_dialog_id.to_encoded())
+        );
+
+        search_main(&manager, "test", found.next_offset().to_string(), 2)
+            .await
+            .unwrap();
+        let requests = client.global_requests.lock().unwrap();
+        assert_eq!(requests[1].offset_rate, 1_700_000_000);
+        assert_eq!(requests[1].offset_dialog_id, Some(last_dialog_id));
+        assert_eq!(requests[1].offset_id, 5);
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_last_page() {
+        let client = Arc::new(FakeSearchClient {
+            result: SearchResult {
+                messages: vec![channel_message(1, 10)],
+                count: Some(1),
+                next_rate: Some(1),
+                search_flood: None,
+            },
+            ..FakeSearchClient::default()
+        });
+        let manager = manager_with_client(&client);
+
+        let found = search_main(&manager, "test", String::new(), 10)
+            .await
+            .unwrap();
+        assert_eq!(found.messages().len(), 1);
+        assert!(found.next_offset().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_invalid_offset() {
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
+
+        for offset in ["abc", "1,2", "1,0,5", "1,-1000000000001,0"] {
+            let result = search_main(&manager, "test", offset.to_string(), 10).await;
+            assert!(matches!(result, Err(Error::Other(_))), "{offset}");
+        }
+        assert!(client.global_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_unsupported() {
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
+
         let result = manager
             .search_messages(
                 DialogListId::main(),
                 "test".to_string(),
                 String::new(),
-                MAX_SEARCH_MESSAGES + 1,
+                10,
+                MessageSearchFilter::UnreadMention,
+                0,
+                0,
             )
             .await;
-        assert!(matches!(result, Err(Error::InvalidState)));
+        assert!(matches!(result, Err(Error::Other(_))));
+
+        let result = manager
+            .search_messages(
+                DialogListId(rustgram_dialog_list_id::FILTER_ID_SHIFT + 2),
+                "test".to_string(),
+                String::new(),
+                10,
+                MessageSearchFilter::Empty,
+                0,
+                0,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Other(_))));
+
+        let found = search_main(&manager, "", String::new(), 10).await.unwrap();
+        assert_eq!(found.total_count(), 0);
+        assert!(client.global_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_dialog_messages() {
+        let dialog_id = channel_message(1, 1).dialog_id();
+        let sender_id = DialogId::from_user(rustgram_types::UserId::new(5).unwrap());
+        let client = Arc::new(FakeSearchClient {
+            result: SearchResult {
+                messages: vec![
+                    channel_message(1, 30),
+                    channel_message(2, 25),
+                    channel_message(1, 20),
+                ],
+                count: Some(10),
+                next_rate: None,
+                search_flood: None,
+            },
+            ..FakeSearchClient::default()
+        });
+        let manager = manager_with_client(&client);
+
+        let found = manager
+            .search_dialog_messages(
+                dialog_id,
+                "test".to_string(),
+                Some(sender_id),
+                "40".to_string(),
+                3,
+                MessageSearchFilter::Url,
+                0,
+                500,
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.total_count(), 10);
+        assert_eq!(
+            found.messages(),
+            &[channel_message(1, 30), channel_message(1, 20)]
+        );
+        assert_eq!(found.next_offset(), "20");
+
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests[0].dialog_id, dialog_id);
+        assert_eq!(requests[0].sender_dialog_id, Some(sender_id));
+        assert_eq!(requests[0].filter, MessageSearchFilter::Url);
+        assert_eq!(requests[0].max_date, 500);
+        assert_eq!(requests[0].offset_id, 40);
+    }
+
+    #[tokio::test]
+    async fn test_search_dialog_messages_invalid() {
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
+        let dialog_id = channel_message(1, 1).dialog_id();
+
+        let result = manager
+            .search_dialog_messages(
+                dialog_id,
+                String::new(),
+                None,
+                "-1".to_string(),
+                10,
+                MessageSearchFilter::Empty,
+                0,
+                0,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Other(_))));
+
+        let result = manager
+            .search_dialog_messages(
+                dialog_id,
+                String::new(),
+                None,
+                String::new(),
+                10,
+                MessageSearchFilter::FailedToSend,
+                0,
+                0,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert!(client.requests.lock().unwrap().is_empty());
     }
 
     #[tokio::test]
     async fn test_search_hashtag_posts_valid_limit() {
-        let manager = MessageQueryManager::new();
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
         let result = manager
-            .search_hashtag_posts("rust".to_string(), String::new(), 10)
+            .search_hashtag_posts("#rust".to_string(), String::new(), 10)
             .await;
         assert!(result.is_ok());
+
+        let requests = client.posts_requests.lock().unwrap();
+        assert_eq!(requests[0].hashtag.as_deref(), Some("rust"));
+        assert_eq!(requests[0].query, None);
+        assert_eq!(requests[0].allow_paid_stars, None);
     }
 
     #[tokio::test]
@@ -878,7 +1583,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_search_public_posts_valid_limit() {
-        let manager = MessageQueryManager::new();
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
         let result = manager
             .search_public_posts("test".to_string(), String::new(), 10, 0)
             .await;
@@ -894,6 +1600,82 @@ mod tests {
         assert!(matches!(result, Err(Error::InvalidState)));
     }
 
+    #[tokio::test]
+    async fn test_search_public_posts_flood() {
+        let flood = SearchPostsFlood::new(3600, 10)
+            .with_remains(0)
+            .with_star_count(50);
+        let client = Arc::new(FakeSearchClient {
+            result: SearchResult {
+                search_flood: Some(flood),
+                ..SearchResult::default()
+            },
+            ..FakeSearchClient::default()
+        });
+        let manager = manager_with_client(&client);
+
+        manager
+            .search_public_posts("first".to_string(), String::new(), 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(manager.search_posts_flood().await, Some(flood));
+
+        let result = manager
+            .search_public_posts("second".to_string(), String::new(), 10, 0)
+            .await;
+        assert!(matches!(result, Err(Error::SearchFlood(seconds)) if seconds > 0));
+        assert_eq!(client.posts_requests.lock().unwrap().len(), 1);
+
+        // Next pages and paid queries are sent
+        let offset = format!("0,{},5", channel_message(1, 5).dialog_id().to_encoded());
+        manager
+            .search_public_posts("second".to_string(), offset, 10, 0)
+            .await
+            .unwrap();
+        manager
+            .search_public_posts("second".to_string(), String::new(), 10, 50)
+            .await
+            .unwrap();
+        let requests = client.posts_requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].allow_paid_stars, None);
+        assert_eq!(requests[2].allow_paid_stars, Some(50));
+    }
+
+    #[tokio::test]
+    async fn test_check_search_posts_flood() {
+        let flood = SearchPostsFlood::new(3600, 10)
+            .with_remains(0)
+            .with_star_count(50)
+            .with_query_is_free(true);
+        let client = Arc::new(FakeSearchClient {
+            flood: Some(flood),
+            ..FakeSearchClient::default()
+        });
+        let manager = manager_with_client(&client);
+
+        assert_eq!(
+            manager
+                .check_search_posts_flood("free".to_string())
+                .await
+                .unwrap(),
+            flood
+        );
+        assert!(manager
+            .search_public_posts("free".to_string(), String::new(), 10, 0)
+            .await
+            .is_ok());
+        assert!(matches!(
+            manager
+                .search_public_posts("other".to_string(), String::new(), 10, 0)
+                .await,
+            Err(Error::SearchFlood(_))
+        ));
+
+        manager.clear_all().await;
+        assert!(manager.search_posts_flood().await.is_none());
+    }
+
     // Delete operation tests
     #[tokio::test]
     async fn test_delete_messages_on_server_invalid_dialog() {
@@ -1286,20 +2068,14 @@ mod tests {
 
     #[tokio::test]
     async fn test_multiple_searches() {
-        let manager = MessageQueryManager::new();
+        let client = Arc::new(FakeSearchClient::default());
+        let manager = manager_with_client(&client);
 
         for i in 1..=10 {
-            let _result = manager
-                .search_messages(
-                    DialogListId::main(),
-                    format!("query{}", i),
-                    String::new(),
-                    i32::min(10, i),
-                )
-                .await;
+            let result = search_main(&manager, &format!("query{}", i), String::new(), i).await;
+            assert!(result.is_ok());
         }
-        // All should succeed
-        assert!(true);
+        assert_eq!(client.global_requests.lock().unwrap().len(), 10);
     }
 
     #[tokio::test]
@@ -0,0 +1,857 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Network client for message search queries.
+//!
+//! The manager builds the requests and parses the results, while the client
+//! sends them to the server. Failed requests are reported as
+//! [`Error::IoError`](crate::Error::IoError).
+//!
+//! [`NetworkSearchClient`] implements [`MessageSearchClient`] by sending the
+//! requests through a [`NetQueryDispatcher`]. The requests and responses are
+//! encoded with the TL constructors of the current API layer; only the
+//! identifiers and dates of the found messages are parsed, along with the
+//! access hashes of the chats and users after them.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use parking_lot::RwLock;
+use rustgram_message_search_filter::MessageSearchFilter;
+use rustgram_net::{
+    AuthFlag, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryType, QueryError,
+};
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::{DialogId, TlHelper};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::tl::{
+    SearchGlobalRequest, SearchPostsFlood, SearchPostsRequest, SearchRequest, SearchResult,
+};
+use crate::tl_message::{malformed, read_i32, read_i64, read_message, read_u32, read_vector};
+use crate::tl_peer::read_access_hashes;
+
+/// Timeout of a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request constructors
+const TL_MESSAGES_SEARCH: u32 = 0x29ee_847a;
+const TL_MESSAGES_SEARCH_GLOBAL: u32 = 0x4bc6_589a;
+const TL_CHANNELS_SEARCH_POSTS: u32 = 0xf2c4_f24d;
+const TL_CHANNELS_CHECK_SEARCH_POSTS_FLOOD: u32 = 0x2256_7115;
+
+/// Argument constructors
+const TL_INPUT_PEER_EMPTY: u32 = 0x7f3b_18ea;
+const TL_INPUT_PEER_CHAT: u32 = 0x35a9_5cb9;
+const TL_INPUT_PEER_USER: u32 = 0xdde8_a54c;
+const TL_INPUT_PEER_CHANNEL: u32 = 0x27bc_bbfc;
+const TL_INPUT_MESSAGES_FILTER_EMPTY: u32 = 0x57e2_f66c;
+const TL_INPUT_MESSAGES_FILTER_PHOTOS: u32 = 0x9609_a51c;
+const TL_INPUT_MESSAGES_FILTER_VIDEO: u32 = 0x9fc0_0e65;
+const TL_INPUT_MESSAGES_FILTER_PHOTO_VIDEO: u32 = 0x56e9_f0e4;
+const TL_INPUT_MESSAGES_FILTER_DOCUMENT: u32 = 0x9edd_f188;
+const TL_INPUT_MESSAGES_FILTER_URL: u32 = 0x7ef0_dd87;
+const TL_INPUT_MESSAGES_FILTER_GIF: u32 = 0xffc8_6587;
+const TL_INPUT_MESSAGES_FILTER_VOICE: u32 = 0x50f5_c392;
+const TL_INPUT_MESSAGES_FILTER_MUSIC: u32 = 0x3751_b49e;
+const TL_INPUT_MESSAGES_FILTER_CHAT_PHOTOS: u32 = 0x3a20_ecb8;
+const TL_INPUT_MESSAGES_FILTER_PHONE_CALLS: u32 = 0x80c9_9768;
+const TL_INPUT_MESSAGES_FILTER_ROUND_VOICE: u32 = 0x7a7c_17a4;
+const TL_INPUT_MESSAGES_FILTER_ROUND_VIDEO: u32 = 0xb549_da53;
+const TL_INPUT_MESSAGES_FILTER_MY_MENTIONS: u32 = 0xc1f8_e69a;
+const TL_INPUT_MESSAGES_FILTER_PINNED: u32 = 0x1bb0_0451;
+
+/// Response constructors
+const TL_MESSAGES_MESSAGES: u32 = 0x8c71_8e87;
+const TL_MESSAGES_MESSAGES_SLICE: u32 = 0x3a54_685e;
+const TL_MESSAGES_CHANNEL_MESSAGES: u32 = 0xc776_ba4e;
+const TL_MESSAGES_MESSAGES_NOT_MODIFIED: u32 = 0x7453_5f21;
+const TL_SEARCH_POSTS_FLOOD: u32 = 0x3e0b_5b6a;
+
+/// Client sending message search queries to the server.
+#[async_trait]
+pub trait MessageSearchClient: Send + Sync {
+    /// Sends `messages.searchGlobal`.
+    async fn search_global(&self, request: SearchGlobalRequest) -> Result<SearchResult>;
+
+    /// Sends `channels.searchPosts`.
+    async fn search_posts(&self, request: SearchPostsRequest) -> Result<SearchResult>;
+
+    /// Sends `messages.search`.
+    async fn search(&self, request: SearchRequest) -> Result<SearchResult>;
+
+    /// Sends `channels.checkSearchPostsFlood` for the query.
+    async fn check_search_posts_flood(&self, query: String) -> Result<SearchPostsFlood>;
+}
+
+/// [`MessageSearchClient`] sending the requests to Telegram
+///
+/// Users and channels are addressed with their access hashes, which are
+/// remembered from the chats and users of the search results, or can be set
+/// with [`set_access_hash`](Self::set_access_hash). A dialog without a known
+/// access hash can't be searched in, but is still accepted as a pagination
+/// offset.
+pub struct NetworkSearchClient {
+    /// Dispatcher sending the requests
+    dispatcher: Arc<NetQueryDispatcher>,
+
+    /// Identifier of the next query
+    next_query_id: AtomicU64,
+
+    /// Known access hashes of users and channels
+    access_hashes: RwLock<HashMap<DialogId, i64>>,
+}
+
+impl NetworkSearchClient {
+    /// Creates a client sending the requests through the dispatcher
+    pub fn new(dispatcher: Arc<NetQueryDispatcher>) -> Self {
+        Self {
+            dispatcher,
+            next_query_id: AtomicU64::new(1),
+            access_hashes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Remembers the access hash of a user or a channel
+    pub fn set_access_hash(&self, dialog_id: DialogId, access_hash: i64) {
+        self.access_hashes.write().insert(dialog_id, access_hash);
+    }
+
+    /// Returns the input peer of a dialog, if it can be addressed
+    fn get_input_peer(&self, dialog_id: DialogId) -> Option<InputPeer> {
+        match dialog_id {
+            DialogId::Chat(chat_id) => Some(InputPeer::Chat(chat_id.get())),
+            DialogId::User(user_id) => {
+                let access_hash = *self.access_hashes.read().get(&dialog_id)?;
+                Some(InputPeer::User(user_id.get(), access_hash))
+            }
+            DialogId::Channel(channel_id) => {
+                let access_hash = *self.access_hashes.read().get(&dialog_id)?;
+                Some(InputPeer::Channel(channel_id.get(), access_hash))
+            }
+            DialogId::SecretChat(_) => None,
+        }
+    }
+
+    /// Returns the input peer of the pagination offset
+    fn get_offset_input_peer(&self, dialog_id: Option<DialogId>) -> InputPeer {
+        dialog_id
+            .and_then(|dialog_id| self.get_input_peer(dialog_id))
+            .unwrap_or(InputPeer::Empty)
+    }
+
+    /// Parses `messages.Messages`, remembering the access hashes of the
+    /// chats and users after the messages
+    fn on_get_messages(&self, mut buf: TlBytes) -> Result<SearchResult> {
+        let result = parse_messages(&mut buf, unix_time())?;
+        // messages.messagesNotModified has no chats and users
+        if !buf.is_empty() {
+            match read_access_hashes(&mut buf) {
+                Ok(access_hashes) => self.access_hashes.write().extend(access_hashes),
+                // The found messages are still usable
+                Err(e) => warn!("Failed to read the chats and users of a search: {}", e),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Sends a request to the main DC and waits for its result
+    async fn invoke(&self, request: BytesMut) -> Result<TlBytes> {
+        let request = request.freeze();
+        let constructor = request
+            .get(..4)
+            .map(|id| i32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            .unwrap_or(0);
+        let query = NetQuery::new(
+            self.next_query_id.fetch_add(1, Ordering::Relaxed),
+            request,
+            self.dispatcher.main_dc_id(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::Off,
+            constructor,
+        );
+
+        let (sender, receiver) = oneshot::channel();
+        query.set_callback(Box::new(ResultCallback {
+            sender: parking_lot::Mutex::new(Some(sender)),
+        }));
+        self.dispatcher
+            .dispatch(query)
+            .map_err(|e| Error::IoError(format!("Dispatch error: {}", e)))?;
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, receiver).await {
+            Ok(Ok(result)) => result
+                .map(TlBytes::new)
+                .map_err(|e| Error::IoError(e.to_string())),
+            Ok(Err(_)) => Err(Error::IoError("Request was dropped".to_string())),
+            Err(_) => Err(Error::IoError("Request timed out".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageSearchClient for NetworkSearchClient {
+    async fn search_global(&self, request: SearchGlobalRequest) -> Result<SearchResult> {
+        let offset_peer = self.get_offset_input_peer(request.offset_dialog_id);
+        let buf = encode_search_global(&request, offset_peer)?;
+        self.on_get_messages(self.invoke(buf).await?)
+    }
+
+    async fn search_posts(&self, request: SearchPostsRequest) -> Result<SearchResult> {
+        let offset_peer = self.get_offset_input_peer(request.offset_dialog_id);
+        let buf = encode_search_posts(&request, offset_peer);
+        self.on_get_messages(self.invoke(buf).await?)
+    }
+
+    async fn search(&self, request: SearchRequest) -> Result<SearchResult> {
+        let peer = self
+            .get_input_peer(request.dialog_id)
+            .ok_or(Error::InvalidDialog)?;
+        let from = match request.sender_dialog_id {
+            Some(dialog_id) => Some(self.get_input_peer(dialog_id).ok_or(Error::InvalidDialog)?),
+            None => None,
+        };
+        let buf = encode_search(&request, peer, from)?;
+        self.on_get_messages(self.invoke(buf).await?)
+    }
+
+    async fn check_search_posts_flood(&self, query: String) -> Result<SearchPostsFlood> {
+        let buf = encode_check_search_posts_flood(&query);
+        parse_search_posts_flood(&mut self.invoke(buf).await?, unix_time())
+    }
+}
+
+/// Callback passing the result of a query to the waiting request
+struct ResultCallback {
+    /// Channel to the request, taken by the first result
+    sender: parking_lot::Mutex<Option<oneshot::Sender<std::result::Result<Bytes, QueryError>>>>,
+}
+
+#[async_trait]
+impl NetQueryCallback for ResultCallback {
+    async fn on_result(&self, query: NetQuery) {
+        let result = if query.is_error() {
+            Err(query.error())
+        } else {
+            Ok(query.ok())
+        };
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+/// `InputPeer` of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputPeer {
+    /// `inputPeerEmpty`
+    Empty,
+    /// `inputPeerChat` with the chat identifier
+    Chat(i64),
+    /// `inputPeerUser` with the user identifier and access hash
+    User(i64, i64),
+    /// `inputPeerChannel` with the channel identifier and access hash
+    Channel(i64, i64),
+}
+
+impl InputPeer {
+    fn write(self, buf: &mut BytesMut) {
+        match self {
+            Self::Empty => TlHelper::write_constructor_id(buf, TL_INPUT_PEER_EMPTY),
+            Self::Chat(chat_id) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_CHAT);
+                TlHelper::write_i64(buf, chat_id);
+            }
+            Self::User(user_id, access_hash) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_USER);
+                TlHelper::write_i64(buf, user_id);
+                TlHelper::write_i64(buf, access_hash);
+            }
+            Self::Channel(channel_id, access_hash) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_CHANNEL);
+                TlHelper::write_i64(buf, channel_id);
+                TlHelper::write_i64(buf, access_hash);
+            }
+        }
+    }
+}
+
+/// Writes the `MessagesFilter` of a search filter
+///
+/// Filters which are only applied locally can't be sent to the server.
+fn write_filter(buf: &mut BytesMut, filter: MessageSearchFilter) -> Result<()> {
+    let id = match filter {
+        MessageSearchFilter::Empty => TL_INPUT_MESSAGES_FILTER_EMPTY,
+        MessageSearchFilter::Animation => TL_INPUT_MESSAGES_FILTER_GIF,
+        MessageSearchFilter::Audio => TL_INPUT_MESSAGES_FILTER_MUSIC,
+        MessageSearchFilter::Document => TL_INPUT_MESSAGES_FILTER_DOCUMENT,
+        MessageSearchFilter::Photo => TL_INPUT_MESSAGES_FILTER_PHOTOS,
+        MessageSearchFilter::Video => TL_INPUT_MESSAGES_FILTER_VIDEO,
+        MessageSearchFilter::VoiceNote => TL_INPUT_MESSAGES_FILTER_VOICE,
+        MessageSearchFilter::PhotoAndVideo => TL_INPUT_MESSAGES_FILTER_PHOTO_VIDEO,
+        MessageSearchFilter::Url => TL_INPUT_MESSAGES_FILTER_URL,
+        MessageSearchFilter::ChatPhoto => TL_INPUT_MESSAGES_FILTER_CHAT_PHOTOS,
+        MessageSearchFilter::Call | MessageSearchFilter::MissedCall => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MESSAGES_FILTER_PHONE_CALLS);
+            // flags.0: missed
+            let missed = filter == MessageSearchFilter::MissedCall;
+            TlHelper::write_i32(buf, i32::from(missed));
+            return Ok(());
+        }
+        MessageSearchFilter::VideoNote => TL_INPUT_MESSAGES_FILTER_ROUND_VIDEO,
+        MessageSearchFilter::VoiceAndVideoNote => TL_INPUT_MESSAGES_FILTER_ROUND_VOICE,
+        MessageSearchFilter::Mention => TL_INPUT_MESSAGES_FILTER_MY_MENTIONS,
+        MessageSearchFilter::Pinned => TL_INPUT_MESSAGES_FILTER_PINNED,
+        MessageSearchFilter::UnreadMention
+        | MessageSearchFilter::FailedToSend
+        | MessageSearchFilter::UnreadReaction => return Err(Error::SearchFailed),
+    };
+    TlHelper::write_constructor_id(buf, id);
+    Ok(())
+}
+
+/// Encodes `messages.search`
+fn encode_search(
+    request: &SearchRequest,
+    peer: InputPeer,
+    from: Option<InputPeer>,
+) -> Result<BytesMut> {
+    let mut buf = BytesMut::new();
+    TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_SEARCH);
+    // flags.0: from_id
+    TlHelper::write_i32(&mut buf, i32::from(from.is_some()));
+    peer.write(&mut buf);
+    TlHelper::write_string(&mut buf, &request.query);
+    if let Some(from) = from {
+        from.write(&mut buf);
+    }
+    write_filter(&mut buf, request.filter)?;
+    TlHelper::write_i32(&mut buf, request.min_date);
+    TlHelper::write_i32(&mut buf, request.max_date);
+    TlHelper::write_i32(&mut buf, request.offset_id);
+    // add_offset
+    TlHelper::write_i32(&mut buf, 0);
+    TlHelper::write_i32(&mut buf, request.limit);
+    // max_id, min_id and hash
+    TlHelper::write_i32(&mut buf, 0);
+    TlHelper::write_i32(&mut buf, 0);
+    TlHelper::write_i64(&mut buf, 0);
+    Ok(buf)
+}
+
+/// Encodes `messages.searchGlobal`
+fn encode_search_global(request: &SearchGlobalRequest, offset_peer: InputPeer) -> Result<BytesMut> {
+    let mut buf = BytesMut::new();
+    TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_SEARCH_GLOBAL);
+    // flags.0: folder_id
+    TlHelper::write_i32(&mut buf, i32::from(request.folder_id.is_some()));
+    if let Some(folder_id) = request.folder_id {
+        TlHelper::write_i32(&mut buf, folder_id);
+    }
+    TlHelper::write_string(&mut buf, &request.query);
+    write_filter(&mut buf, request.filter)?;
+    TlHelper::write_i32(&mut buf, request.min_date);
+    TlHelper::write_i32(&mut buf, request.max_date);
+    TlHelper::write_i32(&mut buf, request.offset_rate);
+    offset_peer.write(&mut buf);
+    TlHelper::write_i32(&mut buf, request.offset_id);
+    TlHelper::write_i32(&mut buf, request.limit);
+    Ok(buf)
+}
+
+/// Encodes `channels.searchPosts`
+fn encode_search_posts(request: &SearchPostsRequest, offset_peer: InputPeer) -> BytesMut {
+    let mut buf = BytesMut::new();
+    TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_SEARCH_POSTS);
+    // flags.0: hashtag, flags.1: query, flags.2: allow_paid_stars
+    let mut flags = 0;
+    if request.hashtag.is_some() {
+        flags |= 1 << 0;
+    }
+    if request.query.is_some() {
+        flags |= 1 << 1;
+    }
+    if request.allow_paid_stars.is_some() {
+        flags |= 1 << 2;
+    }
+    TlHelper::write_i32(&mut buf, flags);
+    if let Some(hashtag) = &request.hashtag {
+        TlHelper::write_string(&mut buf, hashtag);
+    }
+    if let Some(query) = &request.query {
+        TlHelper::write_string(&mut buf, query);
+    }
+    TlHelper::write_i32(&mut buf, request.offset_rate);
+    offset_peer.write(&mut buf);
+    TlHelper::write_i32(&mut buf, request.offset_id);
+    TlHelper::write_i32(&mut buf, request.limit);
+    if let Some(allow_paid_stars) = request.allow_paid_stars {
+        TlHelper::write_i64(&mut buf, allow_paid_stars);
+    }
+    buf
+}
+
+/// Encodes `channels.checkSearchPostsFlood`
+fn encode_check_search_posts_flood(query: &str) -> BytesMut {
+    let mut buf = BytesMut::new();
+    TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_CHECK_SEARCH_POSTS_FLOOD);
+    // flags.0: query
+    TlHelper::write_i32(&mut buf, i32::from(!query.is_empty()));
+    if !query.is_empty() {
+        TlHelper::write_string(&mut buf, query);
+    }
+    buf
+}
+
+/// Returns the current Unix time
+fn unix_time() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|elapsed| i32::try_from(elapsed.as_secs()).ok())
+        .unwrap_or(0)
+}
+
+/// Parses `messages.Messages`, converting flood limits relative to `now`
+///
+/// The chats and users after the messages are left for
+/// [`read_access_hashes`].
+fn parse_messages(buf: &mut TlBytes, now: i32) -> Result<SearchResult> {
+    let mut result = SearchResult::default();
+    match read_u32(buf)? {
+        TL_MESSAGES_MESSAGES => {}
+        TL_MESSAGES_MESSAGES_SLICE => {
+            let flags = read_i32(buf)?;
+            result.count = Some(read_i32(buf)?);
+            if flags & (1 << 0) != 0 {
+                result.next_rate = Some(read_i32(buf)?);
+            }
+            if flags & (1 << 2) != 0 {
+                // offset_id_offset
+                read_i32(buf)?;
+            }
+            if flags & (1 << 3) != 0 {
+                result.search_flood = Some(parse_search_posts_flood(buf, now)?);
+            }
+        }
+        TL_MESSAGES_CHANNEL_MESSAGES => {
+            let flags = read_i32(buf)?;
+            // pts
+            read_i32(buf)?;
+            result.count = Some(read_i32(buf)?);
+            if flags & (1 << 2) != 0 {
+                // offset_id_offset
+                read_i32(buf)?;
+            }
+        }
+        TL_MESSAGES_MESSAGES_NOT_MODIFIED => {
+            result.count = Some(read_i32(buf)?);
+            return Ok(result);
+        }
+        id => return Err(malformed(format_args!("messages.Messages {:#010x}", id))),
+    }
+
+    let messages = read_vector(buf, read_message)?;
+    let messages: Vec<_> = messages.into_iter().flatten().collect();
+    if result.next_rate.is_none() {
+        result.next_rate = messages.last().map(|message| message.date);
+    }
+    result.messages = messages
+        .into_iter()
+        .map(|message| message.message_full_id)
+        .collect();
+    Ok(result)
+}
+
+/// Parses `searchPostsFlood`, converting the wait deadline relative to `now`
+fn parse_search_posts_flood(buf: &mut TlBytes, now: i32) -> Result<SearchPostsFlood> {
+    let id = read_u32(buf)?;
+    if id != TL_SEARCH_POSTS_FLOOD {
+        return Err(malformed(format_args!("SearchPostsFlood {:#010x}", id)));
+    }
+    let flags = read_i32(buf)?;
+    let total_daily = read_i32(buf)?;
+    let remains = read_i32(buf)?;
+    let wait_till = if flags & (1 << 1) != 0 {
+        read_i32(buf)?
+    } else {
+        0
+    };
+    let star_count = read_i64(buf)?;
+    let wait_seconds = if wait_till > now { wait_till - now } else { 0 };
+    Ok(SearchPostsFlood::new(wait_seconds, total_daily)
+        .with_remains(remains)
+        .with_star_count(star_count)
+        .with_query_is_free(flags & (1 << 0) != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_message_full_id::MessageFullId;
+    use rustgram_types::{ChannelId, ChatId, MessageId, SecretChatId, UserId};
+
+    fn tl(build: impl FnOnce(&mut BytesMut)) -> TlBytes {
+        let mut buf = BytesMut::new();
+        build(&mut buf);
+        TlBytes::new(buf.freeze())
+    }
+
+    fn channel(id: i64) -> DialogId {
+        DialogId::from_channel(ChannelId::new(id).unwrap())
+    }
+
+    fn write_service_message(buf: &mut BytesMut, id: i32, channel_id: i64, date: i32) {
+        TlHelper::write_constructor_id(buf, 0x2b08_5862);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i32(buf, id);
+        TlHelper::write_constructor_id(buf, 0xa2a5_371e);
+        TlHelper::write_i64(buf, channel_id);
+        TlHelper::write_i32(buf, date);
+        // messageActionPinMessage
+        TlHelper::write_constructor_id(buf, 0x94bd_38ed);
+    }
+
+    fn write_messages(buf: &mut BytesMut, messages: &[(i32, i32)]) {
+        TlHelper::write_constructor_id(buf, 0x1cb5_c415);
+        TlHelper::write_i32(buf, messages.len() as i32);
+        for &(id, date) in messages {
+            write_service_message(buf, id, 100, date);
+        }
+    }
+
+    fn message_full_id(channel_id: i64, id: i32) -> MessageFullId {
+        MessageFullId::new(channel(channel_id), MessageId::from_server_id(id))
+    }
+
+    #[test]
+    fn test_encode_search() {
+        let request = SearchRequest {
+            dialog_id: channel(100),
+            query: String::new(),
+            sender_dialog_id: None,
+            filter: MessageSearchFilter::MissedCall,
+            min_date: 0,
+            max_date: 0,
+            offset_id: 50,
+            limit: 10,
+        };
+        let buf = encode_search(
+            &request,
+            InputPeer::Channel(100, 5),
+            Some(InputPeer::User(7, 9)),
+        )
+        .unwrap();
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0x7a, 0x84, 0xee, 0x29, // messages.search
+            0x01, 0x00, 0x00, 0x00, // flags: from_id
+            0xfc, 0xbb, 0xbc, 0x27, // inputPeerChannel
+            0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, // q
+            0x4c, 0xa5, 0xe8, 0xdd, // inputPeerUser
+            0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x68, 0x97, 0xc9, 0x80, // inputMessagesFilterPhoneCalls
+            0x01, 0x00, 0x00, 0x00, // flags: missed
+            0x00, 0x00, 0x00, 0x00, // min_date
+            0x00, 0x00, 0x00, 0x00, // max_date
+            0x32, 0x00, 0x00, 0x00, // offset_id
+            0x00, 0x00, 0x00, 0x00, // add_offset
+            0x0a, 0x00, 0x00, 0x00, // limit
+            0x00, 0x00, 0x00, 0x00, // max_id
+            0x00, 0x00, 0x00, 0x00, // min_id
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // hash
+        ];
+        assert_eq!(&buf[..], expected);
+    }
+
+    #[test]
+    fn test_encode_search_global() {
+        let request = SearchGlobalRequest {
+            folder_id: Some(1),
+            query: "cat".to_string(),
+            filter: MessageSearchFilter::Photo,
+            min_date: 0,
+            max_date: 0,
+            offset_rate: 0,
+            offset_dialog_id: None,
+            offset_id: 0,
+            limit: 20,
+        };
+        let buf = encode_search_global(&request, InputPeer::Empty).unwrap();
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0x9a, 0x58, 0xc6, 0x4b, // messages.searchGlobal
+            0x01, 0x00, 0x00, 0x00, // flags: folder_id
+            0x01, 0x00, 0x00, 0x00, // folder_id
+            0x03, b'c', b'a', b't', // q
+            0x1c, 0xa5, 0x09, 0x96, // inputMessagesFilterPhotos
+            0x00, 0x00, 0x00, 0x00, // min_date
+            0x00, 0x00, 0x00, 0x00, // max_date
+            0x00, 0x00, 0x00, 0x00, // offset_rate
+            0xea, 0x18, 0x3b, 0x7f, // inputPeerEmpty
+            0x00, 0x00, 0x00, 0x00, // offset_id
+            0x14, 0x00, 0x00, 0x00, // limit
+        ];
+        assert_eq!(&buf[..], expected);
+    }
+
+    #[test]
+    fn test_encode_search_posts() {
+        let request = SearchPostsRequest {
+            hashtag: Some("tag".to_string()),
+            query: None,
+            offset_rate: 5,
+            offset_dialog_id: Some(channel(100)),
+            offset_id: 3,
+            limit: 10,
+            allow_paid_stars: Some(10),
+        };
+        let buf = encode_search_posts(&request, InputPeer::Channel(100, 5));
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0x4d, 0xf2, 0xc4, 0xf2, // channels.searchPosts
+            0x05, 0x00, 0x00, 0x00, // flags: hashtag, allow_paid_stars
+            0x03, b't', b'a', b'g', // hashtag
+            0x05, 0x00, 0x00, 0x00, // offset_rate
+            0xfc, 0xbb, 0xbc, 0x27, // inputPeerChannel
+            0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x03, 0x00, 0x00, 0x00, // offset_id
+            0x0a, 0x00, 0x00, 0x00, // limit
+            0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // allow_paid_stars
+        ];
+        assert_eq!(&buf[..], expected);
+    }
+
+    #[test]
+    fn test_encode_check_search_posts_flood() {
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0x15, 0x71, 0x56, 0x22, // channels.checkSearchPostsFlood
+            0x01, 0x00, 0x00, 0x00, // flags: query
+            0x02, b'a', b'b', 0x00, // query
+        ];
+        assert_eq!(&encode_check_search_posts_flood("ab")[..], expected);
+
+        let buf = encode_check_search_posts_flood("");
+        assert_eq!(&buf[..], &[0x15, 0x71, 0x56, 0x22, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_encode_local_filter() {
+        let request = SearchRequest {
+            dialog_id: channel(100),
+            query: String::new(),
+            sender_dialog_id: None,
+            filter: MessageSearchFilter::UnreadMention,
+            min_date: 0,
+            max_date: 0,
+            offset_id: 0,
+            limit: 10,
+        };
+        let result = encode_search(&request, InputPeer::Channel(100, 5), None);
+        assert!(matches!(result, Err(Error::SearchFailed)));
+    }
+
+    #[test]
+    fn test_get_input_peer() {
+        let client = NetworkSearchClient::new(Arc::new(NetQueryDispatcher::new()));
+        let user = DialogId::from_user(UserId::new(7).unwrap());
+        let chat = DialogId::from_chat(ChatId::new(12).unwrap());
+        let secret_chat = DialogId::from_secret_chat(SecretChatId::new(3).unwrap());
+
+        assert_eq!(client.get_input_peer(chat), Some(InputPeer::Chat(12)));
+        assert_eq!(client.get_input_peer(user), None);
+        assert_eq!(client.get_input_peer(secret_chat), None);
+        assert_eq!(client.get_offset_input_peer(Some(user)), InputPeer::Empty);
+
+        client.set_access_hash(user, 9);
+        client.set_access_hash(channel(100), 5);
+        assert_eq!(client.get_input_peer(user), Some(InputPeer::User(7, 9)));
+        assert_eq!(
+            client.get_offset_input_peer(Some(channel(100))),
+            InputPeer::Channel(100, 5)
+        );
+        assert_eq!(client.get_offset_input_peer(None), InputPeer::Empty);
+    }
+
+    #[tokio::test]
+    async fn test_search_without_access_hash() {
+        let client = NetworkSearchClient::new(Arc::new(NetQueryDispatcher::new()));
+        let result = client
+            .search(SearchRequest {
+                dialog_id: channel(100),
+                query: "cat".to_string(),
+                sender_dialog_id: None,
+                filter: MessageSearchFilter::Empty,
+                min_date: 0,
+                max_date: 0,
+                offset_id: 0,
+                limit: 10,
+            })
+            .await;
+        assert!(matches!(result, Err(Error::InvalidDialog)));
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES);
+            write_messages(buf, &[(5, 1_700_000_500), (4, 1_700_000_400)]);
+        });
+        let result = parse_messages(&mut buf, 0).unwrap();
+        assert_eq!(
+            result.messages,
+            vec![message_full_id(100, 5), message_full_id(100, 4)]
+        );
+        assert_eq!(result.count, None);
+        assert_eq!(result.next_rate, Some(1_700_000_400));
+        assert_eq!(result.search_flood, None);
+    }
+
+    #[test]
+    fn test_parse_messages_slice() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES_SLICE);
+            // next_rate, offset_id_offset and search_flood
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 2) | (1 << 3));
+            TlHelper::write_i32(buf, 42);
+            TlHelper::write_i32(buf, 1_699_999_999);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_constructor_id(buf, TL_SEARCH_POSTS_FLOOD);
+            TlHelper::write_i32(buf, 1 << 1);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 1_000_060);
+            TlHelper::write_i64(buf, 25);
+            write_messages(buf, &[(5, 1_700_000_500)]);
+        });
+        let result = parse_messages(&mut buf, 1_000_000).unwrap();
+        assert_eq!(result.messages, vec![message_full_id(100, 5)]);
+        assert_eq!(result.count, Some(42));
+        assert_eq!(result.next_rate, Some(1_699_999_999));
+        assert_eq!(
+            result.search_flood,
+            Some(
+                SearchPostsFlood::new(60, 10)
+                    .with_remains(0)
+                    .with_star_count(25)
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_channel_messages() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_CHANNEL_MESSAGES);
+            // offset_id_offset
+            TlHelper::write_i32(buf, 1 << 2);
+            TlHelper::write_i32(buf, 1000);
+            TlHelper::write_i32(buf, 7);
+            TlHelper::write_i32(buf, 3);
+            write_messages(buf, &[(9, 1_700_000_900)]);
+        });
+        let result = parse_messages(&mut buf, 0).unwrap();
+        assert_eq!(result.messages, vec![message_full_id(100, 9)]);
+        assert_eq!(result.count, Some(7));
+        assert_eq!(result.next_rate, Some(1_700_000_900));
+    }
+
+    #[test]
+    fn test_remember_access_hashes() {
+        let client = NetworkSearchClient::new(Arc::new(NetQueryDispatcher::new()));
+        let buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES);
+            write_messages(buf, &[(5, 1_700_000_500)]);
+            TlHelper::write_constructor_id(buf, 0x1cb5_c415);
+            TlHelper::write_i32(buf, 1);
+            // channelForbidden
+            TlHelper::write_constructor_id(buf, 0x17d4_93d5);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i64(buf, 100);
+            TlHelper::write_i64(buf, 6);
+            TlHelper::write_string(buf, "Private");
+            TlHelper::write_constructor_id(buf, 0x1cb5_c415);
+            TlHelper::write_i32(buf, 0);
+        });
+        let result = client.on_get_messages(buf).unwrap();
+        assert_eq!(result.messages, vec![message_full_id(100, 5)]);
+        assert_eq!(
+            client.get_input_peer(channel(100)),
+            Some(InputPeer::Channel(100, 6))
+        );
+    }
+
+    #[test]
+    fn test_unreadable_chats_keep_messages() {
+        let client = NetworkSearchClient::new(Arc::new(NetQueryDispatcher::new()));
+        let buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES);
+            write_messages(buf, &[(5, 1_700_000_500)]);
+            TlHelper::write_constructor_id(buf, 0x1cb5_c415);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, 0x1234_5678);
+        });
+        let result = client.on_get_messages(buf).unwrap();
+        assert_eq!(result.messages, vec![message_full_id(100, 5)]);
+        assert_eq!(client.get_input_peer(channel(100)), None);
+    }
+
+    #[test]
+    fn test_parse_messages_not_modified() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES_NOT_MODIFIED);
+            TlHelper::write_i32(buf, 3);
+        });
+        let result = parse_messages(&mut buf, 0).unwrap();
+        assert!(result.messages.is_empty());
+        assert_eq!(result.count, Some(3));
+    }
+
+    #[test]
+    fn test_parse_search_posts_flood() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_SEARCH_POSTS_FLOOD);
+            TlHelper::write_i32(buf, 1 << 0);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_i64(buf, 50);
+        });
+        let flood = parse_search_posts_flood(&mut buf, 1_000_000).unwrap();
+        assert_eq!(
+            flood,
+            SearchPostsFlood::new(0, 10)
+                .with_remains(4)
+                .with_star_count(50)
+                .with_query_is_free(true)
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown_constructor() {
+        let mut buf = tl(|buf| TlHelper::write_constructor_id(buf, 0xdead_beef));
+        assert!(matches!(
+            parse_messages(&mut buf, 0),
+            Err(Error::IoError(_))
+        ));
+        let mut buf = tl(|buf| TlHelper::write_constructor_id(buf, 0xdead_beef));
+        assert!(matches!(
+            parse_search_posts_flood(&mut buf, 0),
+            Err(Error::IoError(_))
+        ));
+    }
+}
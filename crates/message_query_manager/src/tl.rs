@@ -8,6 +8,8 @@
 //! replaced with full TL layer implementations when available.
 
 use rustgram_formatted_text::FormattedText;
+use rustgram_message_full_id::MessageFullId;
+use rustgram_message_search_filter::MessageSearchFilter;
 use rustgram_types::{DialogId, MessageId};
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -183,7 +185,9 @@ impl fmt::Display for MessageMedia {
 
 /// Search posts flood information.
 ///
-/// Stub implementation for TDLib's searchPostsFlood.
+/// Corresponds to `searchPostsFlood`: a number of public post searches is
+/// free every day; when they are exhausted, a search costs `star_count`
+/// Telegram Stars until the next free search in `wait_seconds`.
 ///
 /// # Example
 ///
@@ -199,6 +203,12 @@ pub struct SearchPostsFlood {
     wait_seconds: i32,
     /// Total flood limit.
     total_limit: i32,
+    /// Number of free searches left.
+    remains: i32,
+    /// Number of Telegram Stars a paid search costs.
+    star_count: i64,
+    /// Whether searching for the checked query is free.
+    query_is_free: bool,
 }
 
 impl SearchPostsFlood {
@@ -221,9 +231,33 @@ impl SearchPostsFlood {
         Self {
             wait_seconds,
             total_limit,
+            remains: total_limit,
+            star_count: 0,
+            query_is_free: false,
         }
     }
 
+    /// Sets the number of free searches left.
+    #[must_use]
+    pub const fn with_remains(mut self, remains: i32) -> Self {
+        self.remains = remains;
+        self
+    }
+
+    /// Sets the price of a paid search.
+    #[must_use]
+    pub const fn with_star_count(mut self, star_count: i64) -> Self {
+        self.star_count = star_count;
+        self
+    }
+
+    /// Sets whether searching for the checked query is free.
+    #[must_use]
+    pub const fn with_query_is_free(mut self, query_is_free: bool) -> Self {
+        self.query_is_free = query_is_free;
+        self
+    }
+
     /// Returns the wait time in seconds.
     #[must_use]
     pub const fn wait_seconds(&self) -> i32 {
@@ -235,6 +269,40 @@ impl SearchPostsFlood {
     pub const fn total_limit(&self) -> i32 {
         self.total_limit
     }
+
+    /// Returns the number of free searches left.
+    #[must_use]
+    pub const fn remains(&self) -> i32 {
+        self.remains
+    }
+
+    /// Returns the number of Telegram Stars a paid search costs.
+    #[must_use]
+    pub const fn star_count(&self) -> i64 {
+        self.star_count
+    }
+
+    /// Checks if searching for the checked query is free.
+    #[must_use]
+    pub const fn query_is_free(&self) -> bool {
+        self.query_is_free
+    }
+
+    /// Checks if a new query must be paid for.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rustgram_message_query_manager::tl::SearchPostsFlood;
+    ///
+    /// let flood = SearchPostsFlood::new(3600, 10).with_remains(0);
+    /// assert!(flood.is_exhausted());
+    /// assert!(!flood.with_query_is_free(true).is_exhausted());
+    /// ```
+    #[must_use]
+    pub const fn is_exhausted(&self) -> bool {
+        !self.query_is_free && self.remains <= 0 && self.wait_seconds > 0
+    }
 }
 
 impl fmt::Display for SearchPostsFlood {
@@ -400,7 +468,8 @@ impl fmt::Display for FactCheck {
 
 /// Found messages result.
 ///
-/// Stub implementation for search results.
+/// Corresponds to TDLib's `foundMessages`. The next page of results is
+/// requested with `next_offset`, which is empty if there are no more results.
 ///
 /// # Example
 ///
@@ -409,13 +478,16 @@ impl fmt::Display for FactCheck {
 ///
 /// let found = FoundMessages::with_total_count(10);
 /// assert_eq!(found.total_count(), 10);
+/// assert!(found.next_offset().is_empty());
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct FoundMessages {
     /// Total number of matching messages.
     total_count: i32,
-    /// Found message IDs.
-    messages: Vec<MessageId>,
+    /// Found messages.
+    messages: Vec<MessageFullId>,
+    /// Offset of the next page of results.
+    next_offset: String,
 }
 
 impl FoundMessages {
@@ -434,6 +506,7 @@ impl FoundMessages {
         Self {
             total_count: 0,
             messages: Vec::new(),
+            next_offset: String::new(),
         }
     }
 
@@ -456,9 +529,17 @@ impl FoundMessages {
         Self {
             total_count,
             messages: Vec::new(),
+            next_offset: String::new(),
         }
     }
 
+    /// Sets the offset of the next page of results.
+    #[must_use]
+    pub fn with_next_offset(mut self, next_offset: String) -> Self {
+        self.next_offset = next_offset;
+        self
+    }
+
     /// Returns the total count.
     #[must_use]
     pub const fn total_count(&self) -> i32 {
@@ -467,13 +548,124 @@ impl FoundMessages {
 
     /// Returns the found messages.
     #[must_use]
-    pub fn messages(&self) -> &[MessageId] {
+    pub fn messages(&self) -> &[MessageFullId] {
         &self.messages
     }
 
+    /// Returns the offset of the next page of results.
+    #[must_use]
+    pub fn next_offset(&self) -> &str {
+        &self.next_offset
+    }
+
     /// Adds a message to the results.
-    pub fn add_message(&mut self, message_id: MessageId) {
-        self.messages.push(message_id);
+    pub fn add_message(&mut self, message_full_id: MessageFullId) {
+        self.messages.push(message_full_id);
+    }
+}
+
+/// Request for searching messages in all dialogs.
+///
+/// Corresponds to `messages.searchGlobal`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchGlobalRequest {
+    /// Folder to search in, or `None` to search in all folders.
+    pub folder_id: Option<i32>,
+    /// Search query.
+    pub query: String,
+    /// Filter of the messages.
+    pub filter: MessageSearchFilter,
+    /// Minimum date of the messages, 0 for no bound.
+    pub min_date: i32,
+    /// Maximum date of the messages, 0 for no bound.
+    pub max_date: i32,
+    /// Rate of the last found message, 0 for the first page.
+    pub offset_rate: i32,
+    /// Dialog of the last found message, `None` for the first page.
+    pub offset_dialog_id: Option<DialogId>,
+    /// Server ID of the last found message, 0 for the first page.
+    pub offset_id: i32,
+    /// Maximum number of messages to return.
+    pub limit: i32,
+}
+
+/// Request for searching public channel posts.
+///
+/// Corresponds to `channels.searchPosts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchPostsRequest {
+    /// Hashtag to search for, without the leading `#`.
+    pub hashtag: Option<String>,
+    /// Search query.
+    pub query: Option<String>,
+    /// Rate of the last found message, 0 for the first page.
+    pub offset_rate: i32,
+    /// Dialog of the last found message, `None` for the first page.
+    pub offset_dialog_id: Option<DialogId>,
+    /// Server ID of the last found message, 0 for the first page.
+    pub offset_id: i32,
+    /// Maximum number of messages to return.
+    pub limit: i32,
+    /// Number of Telegram Stars allowed to be paid for the search.
+    pub allow_paid_stars: Option<i64>,
+}
+
+/// Request for searching messages in a dialog.
+///
+/// Corresponds to `messages.search`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchRequest {
+    /// Dialog to search in.
+    pub dialog_id: DialogId,
+    /// Search query.
+    pub query: String,
+    /// Sender of the messages, `None` for any sender.
+    pub sender_dialog_id: Option<DialogId>,
+    /// Filter of the messages.
+    pub filter: MessageSearchFilter,
+    /// Minimum date of the messages, 0 for no bound.
+    pub min_date: i32,
+    /// Maximum date of the messages, 0 for no bound.
+    pub max_date: i32,
+    /// Server ID of the last found message, 0 for the first page.
+    pub offset_id: i32,
+    /// Maximum number of messages to return.
+    pub limit: i32,
+}
+
+/// Result of a search request.
+///
+/// Corresponds to `messages.messages`, `messages.messagesSlice` and
+/// `messages.channelMessages`. Messages are ordered as returned by the
+/// server, the best match or newest first.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchResult {
+    /// Found messages.
+    pub messages: Vec<MessageFullId>,
+    /// Total number of found messages, if only a part of them is returned.
+    pub count: Option<i32>,
+    /// Rate to request the next page with; the date of the last message if
+    /// the server didn't return it.
+    pub next_rate: Option<i32>,
+    /// Public post search limits after the search.
+    pub search_flood: Option<SearchPostsFlood>,
+}
+
+impl SearchResult {
+    /// Creates a result containing all found messages.
+    #[must_use]
+    pub fn new(messages: Vec<MessageFullId>) -> Self {
+        Self {
+            messages,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the total number of found messages.
+    #[must_use]
+    pub fn total_count(&self) -> i32 {
+        self.count
+            .unwrap_or_else(|| i32::try_from(self.messages.len()).unwrap_or(i32::MAX))
     }
 }
 
@@ -554,6 +746,30 @@ mod tests {
         assert!(display.contains("500"));
     }
 
+    #[test]
+    fn test_search_posts_flood_exhausted() {
+        let flood = SearchPostsFlood::new(3600, 10);
+        assert_eq!(flood.remains(), 10);
+        assert!(!flood.is_exhausted());
+
+        let flood = flood.with_remains(0).with_star_count(50);
+        assert!(flood.is_exhausted());
+        assert_eq!(flood.star_count(), 50);
+        assert!(!flood.with_query_is_free(true).is_exhausted());
+    }
+
+    #[test]
+    fn test_search_result_total_count() {
+        let result = SearchResult::new(Vec::new());
+        assert_eq!(result.total_count(), 0);
+
+        let result = SearchResult {
+            count: Some(70),
+            ..SearchResult::default()
+        };
+        assert_eq!(result.total_count(), 70);
+    }
+
     #[test]
     fn test_search_posts_flood_clone() {
         let flood1 = SearchPostsFlood::new(10, 100);
@@ -649,15 +865,27 @@ mod tests {
     #[test]
     fn test_found_messages_add_message() {
         let mut found = FoundMessages::new();
-        found.add_message(MessageId::from_server_id(123));
+        found.add_message(MessageFullId::new(
+            DialogId::from_chat(ChatId::new(1).unwrap()),
+            MessageId::from_server_id(123),
+        ));
 
         assert_eq!(found.messages().len(), 1);
     }
 
+    #[test]
+    fn test_found_messages_next_offset() {
+        let found = FoundMessages::with_total_count(42).with_next_offset("0,1,2".to_string());
+        assert_eq!(found.next_offset(), "0,1,2");
+    }
+
     #[test]
     fn test_found_messages_clone() {
         let mut found1 = FoundMessages::new();
-        found1.add_message(MessageId::from_server_id(123));
+        found1.add_message(MessageFullId::new(
+            DialogId::from_chat(ChatId::new(1).unwrap()),
+            MessageId::from_server_id(123),
+        ));
         let found2 = found1.clone();
         assert_eq!(found1, found2);
     }
@@ -0,0 +1,2319 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Reading of `Message` objects.
+//!
+//! Searches need only the identifier, the dialog and the date of a message,
+//! and `MessagesManager` also the sender, the text and the media describing
+//! its content, see [`ServerMessage`]. The vector of messages can only be
+//! walked by reading every object in it, so the rest of a message is skipped
+//! field by field with the constructors of the current API layer. Media
+//! that isn't described, such as a poll, an invoice or a story, is skipped
+//! and read as [`ServerMessageMedia::Unsupported`]. A constructor this
+//! module doesn't know makes the whole response malformed rather than
+//! silently losing the messages after it.
+
+use rustgram_message_full_id::MessageFullId;
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::{ChannelId, ChatId, DialogId, MessageId, TlHelper, UserId};
+
+use crate::error::{Error, Result};
+
+const TL_VECTOR: u32 = 0x1cb5_c415;
+
+const TL_PEER_USER: u32 = 0x5951_1722;
+const TL_PEER_CHAT: u32 = 0x36c6_019a;
+const TL_PEER_CHANNEL: u32 = 0xa2a5_371e;
+
+const TL_MESSAGE_EMPTY: u32 = 0x90a6_ca84;
+/// `message` with `factcheck`
+const TL_MESSAGE: u32 = 0x9434_5242;
+/// `message` with `report_delivery_until_date`
+const TL_MESSAGE_REPORT_DELIVERY: u32 = 0x96fd_bbe9;
+/// `message` with `paid_message_stars`
+const TL_MESSAGE_PAID_STARS: u32 = 0xeabc_dd4d;
+const TL_MESSAGE_SERVICE: u32 = 0x2b08_5862;
+/// `messageService` with `reactions`
+const TL_MESSAGE_SERVICE_REACTIONS: u32 = 0xd3d2_8540;
+/// `messageService` with `saved_peer_id`
+const TL_MESSAGE_SERVICE_SAVED_PEER: u32 = 0x7a80_0e0a;
+
+const TL_MESSAGE_FWD_HEADER: u32 = 0x4e4d_f4bb;
+const TL_MESSAGE_REPLY_HEADER: u32 = 0xafbc_09db;
+/// `messageReplyHeader` with `todo_item_id`
+const TL_MESSAGE_REPLY_HEADER_TODO: u32 = 0x6917_560b;
+const TL_MESSAGE_REPLY_STORY_HEADER: u32 = 0x0e5a_f939;
+
+const TL_MESSAGE_MEDIA_EMPTY: u32 = 0x3ded_6320;
+const TL_MESSAGE_MEDIA_PHOTO: u32 = 0x6951_50d7;
+const TL_MESSAGE_MEDIA_GEO: u32 = 0x56e0_d474;
+const TL_MESSAGE_MEDIA_CONTACT: u32 = 0x7032_2949;
+const TL_MESSAGE_MEDIA_UNSUPPORTED: u32 = 0x9f84_f49e;
+const TL_MESSAGE_MEDIA_DOCUMENT: u32 = 0x4cf4_d72d;
+/// `messageMediaDocument` with `alt_documents` and `video_cover`
+const TL_MESSAGE_MEDIA_DOCUMENT_COVER: u32 = 0xdd57_0bd5;
+const TL_MESSAGE_MEDIA_WEB_PAGE: u32 = 0xddf1_0c3b;
+const TL_MESSAGE_MEDIA_VENUE: u32 = 0x2ec0_533f;
+const TL_MESSAGE_MEDIA_GEO_LIVE: u32 = 0xb940_c666;
+const TL_MESSAGE_MEDIA_DICE: u32 = 0x3f7e_e58b;
+const TL_MESSAGE_MEDIA_GAME: u32 = 0xfdb1_9008;
+const TL_MESSAGE_MEDIA_INVOICE: u32 = 0xf6a5_48d3;
+const TL_MESSAGE_MEDIA_POLL: u32 = 0x4bd6_e798;
+const TL_MESSAGE_MEDIA_STORY: u32 = 0x68cb_6283;
+const TL_MESSAGE_MEDIA_GIVEAWAY: u32 = 0xaa07_3beb;
+const TL_MESSAGE_MEDIA_GIVEAWAY_RESULTS: u32 = 0xceaa_3ea1;
+const TL_MESSAGE_MEDIA_PAID_MEDIA: u32 = 0xa885_2491;
+
+const TL_GEO_POINT_EMPTY: u32 = 0x1117_dd5f;
+const TL_GEO_POINT: u32 = 0xb2a2_f663;
+const TL_GEO_POINT_ADDRESS: u32 = 0xde4c_5d93;
+
+const TL_GAME: u32 = 0xbdf9_653b;
+const TL_WEB_DOCUMENT: u32 = 0x1c57_0ed1;
+const TL_WEB_DOCUMENT_NO_PROXY: u32 = 0xf9c8_bcc6;
+const TL_MESSAGE_EXTENDED_MEDIA_PREVIEW: u32 = 0xad62_8cc8;
+const TL_MESSAGE_EXTENDED_MEDIA: u32 = 0xee47_9c64;
+
+const TL_POLL: u32 = 0x5874_7131;
+const TL_POLL_ANSWER: u32 = 0xff16_e2ca;
+const TL_POLL_RESULTS: u32 = 0x7adf_2420;
+const TL_POLL_ANSWER_VOTERS: u32 = 0x3b6d_dad2;
+
+const TL_STORY_ITEM_DELETED: u32 = 0x51e6_ee4f;
+const TL_STORY_ITEM_SKIPPED: u32 = 0xffad_c913;
+const TL_STORY_ITEM: u32 = 0x79b2_6a24;
+/// `storyItem` with `albums`
+const TL_STORY_ITEM_ALBUMS: u32 = 0xedf1_64f1;
+const TL_STORY_FWD_HEADER: u32 = 0xb826_e150;
+const TL_STORY_VIEWS: u32 = 0x8d59_5cd6;
+const TL_MEDIA_AREA_COORDINATES: u32 = 0xcfc9_e002;
+const TL_MEDIA_AREA_VENUE: u32 = 0xbe82_db9c;
+const TL_MEDIA_AREA_GEO_POINT: u32 = 0xcad5_452d;
+const TL_MEDIA_AREA_SUGGESTED_REACTION: u32 = 0x1445_5871;
+const TL_MEDIA_AREA_CHANNEL_POST: u32 = 0x7704_16af;
+const TL_MEDIA_AREA_URL: u32 = 0x3738_1085;
+const TL_MEDIA_AREA_WEATHER: u32 = 0x49a6_549c;
+const TL_MEDIA_AREA_STAR_GIFT: u32 = 0x5787_686d;
+const TL_PRIVACY_VALUE_ALLOW_CONTACTS: u32 = 0xfffe_1bac;
+const TL_PRIVACY_VALUE_ALLOW_ALL: u32 = 0x6542_7b82;
+const TL_PRIVACY_VALUE_ALLOW_USERS: u32 = 0xb890_5fb2;
+const TL_PRIVACY_VALUE_DISALLOW_CONTACTS: u32 = 0xf888_fa1a;
+const TL_PRIVACY_VALUE_DISALLOW_ALL: u32 = 0x8b73_e763;
+const TL_PRIVACY_VALUE_DISALLOW_USERS: u32 = 0xe462_1141;
+const TL_PRIVACY_VALUE_ALLOW_CHAT_PARTICIPANTS: u32 = 0x6b13_4e8e;
+const TL_PRIVACY_VALUE_DISALLOW_CHAT_PARTICIPANTS: u32 = 0x41c8_7565;
+const TL_PRIVACY_VALUE_ALLOW_CLOSE_FRIENDS: u32 = 0xf7e8_d89b;
+const TL_PRIVACY_VALUE_ALLOW_PREMIUM: u32 = 0xece9_814b;
+const TL_PRIVACY_VALUE_ALLOW_BOTS: u32 = 0x2146_1b5d;
+const TL_PRIVACY_VALUE_DISALLOW_BOTS: u32 = 0xf6a5_f82f;
+
+const TL_PHOTO_EMPTY: u32 = 0x2331_b22d;
+const TL_PHOTO: u32 = 0xfb19_7a65;
+const TL_PHOTO_SIZE_EMPTY: u32 = 0x0e17_e23c;
+const TL_PHOTO_SIZE: u32 = 0x75c7_8e60;
+const TL_PHOTO_CACHED_SIZE: u32 = 0x021e_1ad6;
+const TL_PHOTO_STRIPPED_SIZE: u32 = 0xe0b0_bc2e;
+const TL_PHOTO_SIZE_PROGRESSIVE: u32 = 0xfa3e_fb95;
+const TL_PHOTO_PATH_SIZE: u32 = 0xd821_4d41;
+const TL_VIDEO_SIZE: u32 = 0xde33_b094;
+const TL_VIDEO_SIZE_EMOJI_MARKUP: u32 = 0xf85c_413c;
+const TL_VIDEO_SIZE_STICKER_MARKUP: u32 = 0x0da0_82fe;
+
+const TL_DOCUMENT_EMPTY: u32 = 0x36f8_c871;
+const TL_DOCUMENT: u32 = 0x8fd4_c4d8;
+const TL_DOCUMENT_ATTRIBUTE_IMAGE_SIZE: u32 = 0x6c37_c15c;
+const TL_DOCUMENT_ATTRIBUTE_ANIMATED: u32 = 0x11b5_8939;
+const TL_DOCUMENT_ATTRIBUTE_STICKER: u32 = 0x6319_d612;
+const TL_DOCUMENT_ATTRIBUTE_VIDEO: u32 = 0xd38f_f1c2;
+/// `documentAttributeVideo` with `video_start_ts`
+const TL_DOCUMENT_ATTRIBUTE_VIDEO_START: u32 = 0x1739_9fad;
+/// `documentAttributeVideo` with `video_codec`
+const TL_DOCUMENT_ATTRIBUTE_VIDEO_CODEC: u32 = 0x43c5_7c48;
+const TL_DOCUMENT_ATTRIBUTE_AUDIO: u32 = 0x9852_f9c6;
+const TL_DOCUMENT_ATTRIBUTE_FILENAME: u32 = 0x1559_0068;
+const TL_DOCUMENT_ATTRIBUTE_HAS_STICKERS: u32 = 0x9801_d2f7;
+const TL_DOCUMENT_ATTRIBUTE_CUSTOM_EMOJI: u32 = 0xfd14_9899;
+const TL_MASK_COORDS: u32 = 0xaed6_dbb2;
+
+const TL_INPUT_STICKER_SET_EMPTY: u32 = 0xffb6_2b95;
+const TL_INPUT_STICKER_SET_ID: u32 = 0x9de7_a269;
+const TL_INPUT_STICKER_SET_SHORT_NAME: u32 = 0x861c_c8a0;
+const TL_INPUT_STICKER_SET_ANIMATED_EMOJI: u32 = 0x0287_03c8;
+const TL_INPUT_STICKER_SET_DICE: u32 = 0xe67f_520e;
+
+const TL_WEB_PAGE_EMPTY: u32 = 0x211a_1788;
+const TL_WEB_PAGE_PENDING: u32 = 0xb0d1_3e47;
+const TL_WEB_PAGE: u32 = 0xe89c_45b2;
+const TL_WEB_PAGE_NOT_MODIFIED: u32 = 0x7311_ca11;
+const TL_WEB_PAGE_ATTRIBUTE_THEME: u32 = 0x54b5_6617;
+const TL_WEB_PAGE_ATTRIBUTE_STORY: u32 = 0x2e94_c3e7;
+const TL_WEB_PAGE_ATTRIBUTE_STICKER_SET: u32 = 0x50cc_03d3;
+
+const TL_REPLY_KEYBOARD_HIDE: u32 = 0xa03e_5b85;
+const TL_REPLY_KEYBOARD_FORCE_REPLY: u32 = 0x86b4_0b08;
+const TL_REPLY_KEYBOARD_MARKUP: u32 = 0x85dd_99d1;
+const TL_REPLY_INLINE_MARKUP: u32 = 0x48a3_0254;
+const TL_KEYBOARD_BUTTON_ROW: u32 = 0x7760_8b83;
+const TL_KEYBOARD_BUTTON: u32 = 0xa2fa_4880;
+const TL_KEYBOARD_BUTTON_URL: u32 = 0x258a_ff05;
+const TL_KEYBOARD_BUTTON_CALLBACK: u32 = 0x35bb_db6b;
+const TL_KEYBOARD_BUTTON_REQUEST_PHONE: u32 = 0xb16a_6c29;
+const TL_KEYBOARD_BUTTON_REQUEST_GEO_LOCATION: u32 = 0xfc79_6b3f;
+const TL_KEYBOARD_BUTTON_SWITCH_INLINE: u32 = 0x93b9_fbb5;
+const TL_KEYBOARD_BUTTON_GAME: u32 = 0x50f4_1ccf;
+const TL_KEYBOARD_BUTTON_BUY: u32 = 0xafd9_3fbb;
+const TL_KEYBOARD_BUTTON_URL_AUTH: u32 = 0x10b7_8d29;
+const TL_KEYBOARD_BUTTON_REQUEST_POLL: u32 = 0xbbc7_515d;
+const TL_KEYBOARD_BUTTON_USER_PROFILE: u32 = 0x3086_60c1;
+const TL_KEYBOARD_BUTTON_WEB_VIEW: u32 = 0x1376_7230;
+const TL_KEYBOARD_BUTTON_SIMPLE_WEB_VIEW: u32 = 0xa0c0_505c;
+const TL_KEYBOARD_BUTTON_COPY: u32 = 0x75d2_698e;
+
+const TL_MESSAGE_ENTITY_UNKNOWN: u32 = 0xbb92_ba95;
+const TL_MESSAGE_ENTITY_MENTION: u32 = 0xfa04_579d;
+const TL_MESSAGE_ENTITY_HASHTAG: u32 = 0x6f63_5b0d;
+const TL_MESSAGE_ENTITY_BOT_COMMAND: u32 = 0x6cef_8ac7;
+const TL_MESSAGE_ENTITY_URL: u32 = 0x6ed0_2538;
+const TL_MESSAGE_ENTITY_EMAIL: u32 = 0x64e4_75c2;
+const TL_MESSAGE_ENTITY_BOLD: u32 = 0xbd61_0bc9;
+const TL_MESSAGE_ENTITY_ITALIC: u32 = 0x826f_8b60;
+const TL_MESSAGE_ENTITY_CODE: u32 = 0x28a2_0571;
+const TL_MESSAGE_ENTITY_PRE: u32 = 0x7392_4be0;
+const TL_MESSAGE_ENTITY_TEXT_URL: u32 = 0x76a6_d327;
+const TL_MESSAGE_ENTITY_MENTION_NAME: u32 = 0xdc7b_1140;
+const TL_MESSAGE_ENTITY_PHONE: u32 = 0x9b69_e34b;
+const TL_MESSAGE_ENTITY_CASHTAG: u32 = 0x4c4e_743f;
+const TL_MESSAGE_ENTITY_UNDERLINE: u32 = 0x9c4e_7e8b;
+const TL_MESSAGE_ENTITY_STRIKE: u32 = 0xbf06_93d4;
+const TL_MESSAGE_ENTITY_BANK_CARD: u32 = 0x761e_6af4;
+const TL_MESSAGE_ENTITY_SPOILER: u32 = 0x32ca_960f;
+const TL_MESSAGE_ENTITY_CUSTOM_EMOJI: u32 = 0xc8cf_05f8;
+const TL_MESSAGE_ENTITY_BLOCKQUOTE: u32 = 0xf1cc_aaac;
+
+const TL_MESSAGE_REPLIES: u32 = 0x83d6_0fc2;
+const TL_MESSAGE_REACTIONS: u32 = 0x4f2b_9479;
+/// `messageReactions` with `top_reactors`
+const TL_MESSAGE_REACTIONS_TOP: u32 = 0x0a33_9f0b;
+const TL_REACTION_COUNT: u32 = 0xa3d1_cb80;
+const TL_MESSAGE_PEER_REACTION: u32 = 0x8c79_b63c;
+const TL_MESSAGE_REACTOR: u32 = 0x4ba3_a95a;
+const TL_REACTION_EMPTY: u32 = 0x79f5_d419;
+const TL_REACTION_EMOJI: u32 = 0x1b22_86b8;
+const TL_REACTION_CUSTOM_EMOJI: u32 = 0x8935_fc73;
+const TL_REACTION_PAID: u32 = 0x523d_a4eb;
+const TL_RESTRICTION_REASON: u32 = 0xd072_acb4;
+const TL_FACT_CHECK: u32 = 0xb89b_fccf;
+const TL_TEXT_WITH_ENTITIES: u32 = 0x751f_3146;
+
+const TL_MESSAGE_ACTION_EMPTY: u32 = 0xb6ae_f7b0;
+const TL_MESSAGE_ACTION_CHAT_CREATE: u32 = 0xbd47_cbad;
+const TL_MESSAGE_ACTION_CHAT_EDIT_TITLE: u32 = 0xb5a1_ce5a;
+const TL_MESSAGE_ACTION_CHAT_EDIT_PHOTO: u32 = 0x7fcb_13a8;
+const TL_MESSAGE_ACTION_CHAT_DELETE_PHOTO: u32 = 0x95e3_fbef;
+const TL_MESSAGE_ACTION_CHAT_ADD_USER: u32 = 0x15ce_fd00;
+const TL_MESSAGE_ACTION_CHAT_DELETE_USER: u32 = 0xa43f_30cc;
+const TL_MESSAGE_ACTION_CHAT_JOINED_BY_LINK: u32 = 0x0312_24c3;
+const TL_MESSAGE_ACTION_CHANNEL_CREATE: u32 = 0x95d2_ac92;
+const TL_MESSAGE_ACTION_CHAT_MIGRATE_TO: u32 = 0xe103_7f92;
+const TL_MESSAGE_ACTION_CHANNEL_MIGRATE_FROM: u32 = 0xea39_48e9;
+const TL_MESSAGE_ACTION_PIN_MESSAGE: u32 = 0x94bd_38ed;
+const TL_MESSAGE_ACTION_HISTORY_CLEAR: u32 = 0x9fba_b604;
+const TL_MESSAGE_ACTION_PHONE_CALL: u32 = 0x80e1_1a7f;
+const TL_MESSAGE_ACTION_SCREENSHOT_TAKEN: u32 = 0x4792_929b;
+const TL_MESSAGE_ACTION_CUSTOM_ACTION: u32 = 0xfae6_9f56;
+const TL_MESSAGE_ACTION_CONTACT_SIGN_UP: u32 = 0xf3f2_5f76;
+const TL_MESSAGE_ACTION_GROUP_CALL: u32 = 0x7a0d_7f42;
+const TL_MESSAGE_ACTION_SET_MESSAGES_TTL: u32 = 0x3c13_4d7b;
+const TL_MESSAGE_ACTION_CHAT_JOINED_BY_REQUEST: u32 = 0xebbc_a3cb;
+const TL_MESSAGE_ACTION_TOPIC_CREATE: u32 = 0x0d99_9256;
+const TL_MESSAGE_ACTION_TOPIC_EDIT: u32 = 0xc094_4820;
+const TL_INPUT_GROUP_CALL: u32 = 0xd8aa_840f;
+const TL_PHONE_CALL_DISCARD_REASON_MISSED: u32 = 0x85e4_2301;
+const TL_PHONE_CALL_DISCARD_REASON_DISCONNECT: u32 = 0xe095_c1a0;
+const TL_PHONE_CALL_DISCARD_REASON_HANGUP: u32 = 0x57ad_c690;
+const TL_PHONE_CALL_DISCARD_REASON_BUSY: u32 = 0xfaf7_e8c9;
+
+/// A message returned by a search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FoundMessage {
+    /// Dialog and identifier of the message
+    pub message_full_id: MessageFullId,
+    /// Date the message was sent
+    pub date: i32,
+}
+
+/// A message read from a server response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerMessage {
+    /// Dialog and identifier of the message
+    pub message_full_id: MessageFullId,
+    /// Sender of the message; `None` for messages sent on behalf of the
+    /// dialog itself, like channel posts
+    pub sender_dialog_id: Option<DialogId>,
+    /// Date the message was sent
+    pub date: i32,
+    /// Date the message was last edited
+    pub edit_date: Option<i32>,
+    /// Whether the message was sent by the current user
+    pub is_outgoing: bool,
+    /// Whether the message is a service message
+    pub is_service: bool,
+    /// Text of the message, or the caption of its media
+    pub text: String,
+    /// Media of the message
+    pub media: Option<ServerMessageMedia>,
+    /// Album the message belongs to
+    pub grouped_id: Option<i64>,
+}
+
+/// Media of a [`ServerMessage`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessageMedia {
+    /// Photo
+    Photo {
+        /// Photo identifier
+        id: i64,
+        /// Whether the photo is hidden under a spoiler
+        spoiler: bool,
+    },
+    /// Document: video, audio, voice note, animation, sticker or file
+    Document {
+        /// Document identifier
+        id: i64,
+        /// MIME type of the file
+        mime_type: String,
+        /// Attributes describing the kind of the document
+        attributes: Vec<ServerDocumentAttribute>,
+        /// Whether the document is hidden under a spoiler
+        spoiler: bool,
+    },
+    /// Media that isn't described, like a location or an expired photo
+    Unsupported,
+}
+
+/// Attribute of a document in a [`ServerMessageMedia::Document`].
+///
+/// Attributes that don't change the kind of the document are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerDocumentAttribute {
+    /// The document is a video
+    Video {
+        /// Whether the video is a round video note
+        round_message: bool,
+        /// Whether the video supports streaming
+        supports_streaming: bool,
+        /// Duration in seconds
+        duration: f64,
+        /// Width
+        w: i32,
+        /// Height
+        h: i32,
+    },
+    /// The document is an audio file or a voice note
+    Audio {
+        /// Whether the audio is a voice note
+        voice: bool,
+        /// Duration in seconds
+        duration: i32,
+    },
+    /// The document is an animation
+    Animated,
+    /// The document is a sticker
+    Sticker {
+        /// Emoji associated with the sticker
+        alt: String,
+    },
+    /// Original name of the file
+    Filename {
+        /// File name
+        file_name: String,
+    },
+}
+
+/// Returns an error for a malformed response.
+pub(crate) fn malformed(what: impl std::fmt::Display) -> Error {
+    Error::IoError(format!("Malformed response: {what}"))
+}
+
+/// Returns an error for a constructor this module can't read.
+pub(crate) fn unsupported(type_name: &str, id: u32) -> Error {
+    malformed(format_args!("unsupported {type_name} {id:#010x}"))
+}
+
+pub(crate) fn read_u32(buf: &mut TlBytes) -> Result<u32> {
+    TlHelper::read_constructor_id(buf).map_err(malformed)
+}
+
+pub(crate) fn read_i32(buf: &mut TlBytes) -> Result<i32> {
+    TlHelper::read_i32(buf).map_err(malformed)
+}
+
+pub(crate) fn read_i64(buf: &mut TlBytes) -> Result<i64> {
+    TlHelper::read_i64(buf).map_err(malformed)
+}
+
+fn read_f64(buf: &mut TlBytes) -> Result<f64> {
+    TlHelper::read_f64(buf).map_err(malformed)
+}
+
+fn read_string(buf: &mut TlBytes) -> Result<String> {
+    TlHelper::read_string(buf).map_err(malformed)
+}
+
+pub(crate) fn skip_bytes(buf: &mut TlBytes) -> Result<()> {
+    TlHelper::read_bytes(buf).map_err(malformed)?;
+    Ok(())
+}
+
+/// Reads a boxed vector, calling `read` for each element.
+pub(crate) fn read_vector<T>(
+    buf: &mut TlBytes,
+    mut read: impl FnMut(&mut TlBytes) -> Result<T>,
+) -> Result<Vec<T>> {
+    let id = read_u32(buf)?;
+    if id != TL_VECTOR {
+        return Err(malformed(format_args!("Vector {id:#010x}")));
+    }
+    let count = read_i32(buf)?;
+    // Every element takes at least 4 bytes
+    let count = usize::try_from(count)
+        .ok()
+        .filter(|count| *count <= buf.remaining() / 4)
+        .ok_or_else(|| malformed(format_args!("vector of {count} elements")))?;
+    (0..count).map(|_| read(buf)).collect()
+}
+
+pub(crate) fn skip_vector(
+    buf: &mut TlBytes,
+    skip: impl FnMut(&mut TlBytes) -> Result<()>,
+) -> Result<()> {
+    read_vector(buf, skip)?;
+    Ok(())
+}
+
+pub(crate) fn skip_i32(buf: &mut TlBytes) -> Result<()> {
+    read_i32(buf).map(drop)
+}
+
+pub(crate) fn skip_i64(buf: &mut TlBytes) -> Result<()> {
+    read_i64(buf).map(drop)
+}
+
+/// Reads the element of `messages.Messages.messages`.
+///
+/// Returns `None` for `messageEmpty`.
+pub(crate) fn read_message(buf: &mut TlBytes) -> Result<Option<FoundMessage>> {
+    Ok(read_server_message(buf)?.map(|message| FoundMessage {
+        message_full_id: message.message_full_id,
+        date: message.date,
+    }))
+}
+
+/// Reads a `Message` object, like an element of `messages.Messages.messages`
+/// or the message of `updateNewMessage`.
+///
+/// Returns `None` for `messageEmpty`.
+///
+/// # Errors
+///
+/// Returns an error if the object is malformed or contains a part this
+/// module doesn't know.
+pub fn read_server_message(buf: &mut TlBytes) -> Result<Option<ServerMessage>> {
+    match read_u32(buf)? {
+        TL_MESSAGE_EMPTY => {
+            let flags = read_i32(buf)?;
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                read_peer(buf)?;
+            }
+            Ok(None)
+        }
+        TL_MESSAGE | TL_MESSAGE_REPORT_DELIVERY | TL_MESSAGE_PAID_STARS => {
+            read_regular_message(buf).map(Some)
+        }
+        TL_MESSAGE_SERVICE | TL_MESSAGE_SERVICE_REACTIONS | TL_MESSAGE_SERVICE_SAVED_PEER => {
+            read_service_message(buf).map(Some)
+        }
+        id => Err(unsupported("Message", id)),
+    }
+}
+
+/// Reads `message`; the later layers only add fields at the end.
+fn read_regular_message(buf: &mut TlBytes) -> Result<ServerMessage> {
+    let flags = read_i32(buf)?;
+    let flags2 = read_i32(buf)?;
+    let id = read_i32(buf)?;
+    let sender_dialog_id = if flags & (1 << 8) != 0 {
+        Some(read_peer(buf)?)
+    } else {
+        None
+    };
+    if flags & (1 << 29) != 0 {
+        // from_boosts_applied
+        read_i32(buf)?;
+    }
+    let dialog_id = read_peer(buf)?;
+    if flags & (1 << 28) != 0 {
+        // saved_peer_id
+        read_peer(buf)?;
+    }
+    if flags & (1 << 2) != 0 {
+        skip_message_fwd_header(buf)?;
+    }
+    if flags & (1 << 11) != 0 {
+        // via_bot_id
+        read_i64(buf)?;
+    }
+    if flags2 & (1 << 0) != 0 {
+        // via_business_bot_id
+        read_i64(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        skip_message_reply_header(buf)?;
+    }
+    let date = read_i32(buf)?;
+    let text = read_string(buf)?;
+    let media = if flags & (1 << 9) != 0 {
+        Some(read_message_media(buf)?)
+    } else {
+        None
+    };
+    if flags & (1 << 6) != 0 {
+        skip_reply_markup(buf)?;
+    }
+    if flags & (1 << 7) != 0 {
+        skip_vector(buf, skip_message_entity)?;
+    }
+    if flags & (1 << 10) != 0 {
+        // views and forwards
+        read_i32(buf)?;
+        read_i32(buf)?;
+    }
+    if flags & (1 << 23) != 0 {
+        skip_message_replies(buf)?;
+    }
+    let edit_date = if flags & (1 << 15) != 0 {
+        Some(read_i32(buf)?)
+    } else {
+        None
+    };
+    if flags & (1 << 16) != 0 {
+        // post_author
+        skip_bytes(buf)?;
+    }
+    let grouped_id = if flags & (1 << 17) != 0 {
+        Some(read_i64(buf)?)
+    } else {
+        None
+    };
+    if flags & (1 << 20) != 0 {
+        skip_message_reactions(buf)?;
+    }
+    if flags & (1 << 22) != 0 {
+        skip_vector(buf, skip_restriction_reason)?;
+    }
+    if flags & (1 << 25) != 0 {
+        // ttl_period
+        read_i32(buf)?;
+    }
+    if flags & (1 << 30) != 0 {
+        // quick_reply_shortcut_id
+        read_i32(buf)?;
+    }
+    if flags2 & (1 << 2) != 0 {
+        // effect
+        read_i64(buf)?;
+    }
+    if flags2 & (1 << 3) != 0 {
+        skip_fact_check(buf)?;
+    }
+    if flags2 & (1 << 5) != 0 {
+        // report_delivery_until_date
+        read_i32(buf)?;
+    }
+    if flags2 & (1 << 6) != 0 {
+        // paid_message_stars
+        read_i64(buf)?;
+    }
+    Ok(ServerMessage {
+        message_full_id: message_full_id(dialog_id, id),
+        sender_dialog_id,
+        date,
+        edit_date,
+        is_outgoing: flags & (1 << 1) != 0,
+        is_service: false,
+        text,
+        media,
+        grouped_id,
+    })
+}
+
+/// Reads `messageService`; the later layers only add optional fields.
+fn read_service_message(buf: &mut TlBytes) -> Result<ServerMessage> {
+    let flags = read_i32(buf)?;
+    let id = read_i32(buf)?;
+    let sender_dialog_id = if flags & (1 << 8) != 0 {
+        Some(read_peer(buf)?)
+    } else {
+        None
+    };
+    let dialog_id = read_peer(buf)?;
+    if flags & (1 << 28) != 0 {
+        // saved_peer_id
+        read_peer(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        skip_message_reply_header(buf)?;
+    }
+    let date = read_i32(buf)?;
+    skip_message_action(buf)?;
+    if flags & (1 << 20) != 0 {
+        skip_message_reactions(buf)?;
+    }
+    if flags & (1 << 25) != 0 {
+        // ttl_period
+        read_i32(buf)?;
+    }
+    Ok(ServerMessage {
+        message_full_id: message_full_id(dialog_id, id),
+        sender_dialog_id,
+        date,
+        edit_date: None,
+        is_outgoing: flags & (1 << 1) != 0,
+        is_service: true,
+        text: String::new(),
+        media: None,
+        grouped_id: None,
+    })
+}
+
+fn message_full_id(dialog_id: DialogId, id: i32) -> MessageFullId {
+    MessageFullId::new(dialog_id, MessageId::from_server_id(id))
+}
+
+/// Reads `Peer` as a dialog.
+pub(crate) fn read_peer(buf: &mut TlBytes) -> Result<DialogId> {
+    let id = read_u32(buf)?;
+    let peer_id = read_i64(buf)?;
+    let dialog_id = match id {
+        TL_PEER_USER => UserId::new(peer_id).map(DialogId::from_user),
+        TL_PEER_CHAT => ChatId::new(peer_id).map(DialogId::from_chat),
+        TL_PEER_CHANNEL => ChannelId::new(peer_id).map(DialogId::from_channel),
+        id => return Err(unsupported("Peer", id)),
+    };
+    dialog_id.map_err(malformed)
+}
+
+fn skip_peer(buf: &mut TlBytes) -> Result<()> {
+    read_peer(buf).map(drop)
+}
+
+fn skip_message_fwd_header(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MESSAGE_FWD_HEADER {
+        return Err(unsupported("MessageFwdHeader", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 0) != 0 {
+        // from_id
+        read_peer(buf)?;
+    }
+    if flags & (1 << 5) != 0 {
+        // from_name
+        skip_bytes(buf)?;
+    }
+    // date
+    read_i32(buf)?;
+    if flags & (1 << 2) != 0 {
+        // channel_post
+        read_i32(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        // post_author
+        skip_bytes(buf)?;
+    }
+    if flags & (1 << 4) != 0 {
+        // saved_from_peer and saved_from_msg_id
+        read_peer(buf)?;
+        read_i32(buf)?;
+    }
+    if flags & (1 << 8) != 0 {
+        // saved_from_id
+        read_peer(buf)?;
+    }
+    if flags & (1 << 9) != 0 {
+        // saved_from_name
+        skip_bytes(buf)?;
+    }
+    if flags & (1 << 10) != 0 {
+        // saved_date
+        read_i32(buf)?;
+    }
+    if flags & (1 << 6) != 0 {
+        // psa_type
+        skip_bytes(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_message_reply_header(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_MESSAGE_REPLY_HEADER | TL_MESSAGE_REPLY_HEADER_TODO => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 4) != 0 {
+                // reply_to_msg_id
+                read_i32(buf)?;
+            }
+            if flags & (1 << 0) != 0 {
+                // reply_to_peer_id
+                read_peer(buf)?;
+            }
+            if flags & (1 << 5) != 0 {
+                skip_message_fwd_header(buf)?;
+            }
+            if flags & (1 << 8) != 0 {
+                skip_message_media(buf)?;
+            }
+            if flags & (1 << 1) != 0 {
+                // reply_to_top_id
+                read_i32(buf)?;
+            }
+            if flags & (1 << 6) != 0 {
+                // quote_text
+                skip_bytes(buf)?;
+            }
+            if flags & (1 << 7) != 0 {
+                skip_vector(buf, skip_message_entity)?;
+            }
+            if flags & (1 << 10) != 0 {
+                // quote_offset
+                read_i32(buf)?;
+            }
+            if flags & (1 << 11) != 0 {
+                // todo_item_id
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_REPLY_STORY_HEADER => {
+            read_peer(buf)?;
+            read_i32(buf).map(drop)
+        }
+        id => Err(unsupported("MessageReplyHeader", id)),
+    }
+}
+
+fn skip_message_media(buf: &mut TlBytes) -> Result<()> {
+    read_message_media(buf).map(drop)
+}
+
+fn read_message_media(buf: &mut TlBytes) -> Result<ServerMessageMedia> {
+    match read_u32(buf)? {
+        TL_MESSAGE_MEDIA_PHOTO => {
+            let flags = read_i32(buf)?;
+            let photo_id = if flags & (1 << 0) != 0 {
+                read_photo(buf)?
+            } else {
+                None
+            };
+            if flags & (1 << 2) != 0 {
+                // ttl_seconds
+                read_i32(buf)?;
+            }
+            Ok(photo_id.map_or(ServerMessageMedia::Unsupported, |id| {
+                ServerMessageMedia::Photo {
+                    id,
+                    spoiler: flags & (1 << 3) != 0,
+                }
+            }))
+        }
+        id @ (TL_MESSAGE_MEDIA_DOCUMENT | TL_MESSAGE_MEDIA_DOCUMENT_COVER) => {
+            let flags = read_i32(buf)?;
+            let document = if flags & (1 << 0) != 0 {
+                read_document(buf)?
+            } else {
+                None
+            };
+            if flags & (1 << 5) != 0 {
+                if id == TL_MESSAGE_MEDIA_DOCUMENT {
+                    skip_document(buf)?;
+                } else {
+                    skip_vector(buf, skip_document)?;
+                }
+            }
+            if flags & (1 << 9) != 0 {
+                // video_cover
+                skip_photo(buf)?;
+            }
+            if flags & (1 << 10) != 0 {
+                // video_timestamp
+                read_i32(buf)?;
+            }
+            if flags & (1 << 2) != 0 {
+                // ttl_seconds
+                read_i32(buf)?;
+            }
+            let Some(document) = document else {
+                return Ok(ServerMessageMedia::Unsupported);
+            };
+            Ok(ServerMessageMedia::Document {
+                id: document.id,
+                mime_type: document.mime_type,
+                attributes: document.attributes,
+                spoiler: flags & (1 << 4) != 0,
+            })
+        }
+        id => {
+            skip_other_message_media(buf, id)?;
+            Ok(ServerMessageMedia::Unsupported)
+        }
+    }
+}
+
+/// Skips the fields of a media that isn't described by
+/// [`ServerMessageMedia`].
+fn skip_other_message_media(buf: &mut TlBytes, id: u32) -> Result<()> {
+    match id {
+        TL_MESSAGE_MEDIA_EMPTY | TL_MESSAGE_MEDIA_UNSUPPORTED => Ok(()),
+        TL_MESSAGE_MEDIA_GEO => skip_geo_point(buf),
+        TL_MESSAGE_MEDIA_CONTACT => {
+            // phone_number, first_name, last_name, vcard and user_id
+            for _ in 0..4 {
+                skip_bytes(buf)?;
+            }
+            read_i64(buf).map(drop)
+        }
+        TL_MESSAGE_MEDIA_WEB_PAGE => {
+            read_i32(buf)?;
+            skip_web_page(buf)
+        }
+        TL_MESSAGE_MEDIA_VENUE => {
+            skip_geo_point(buf)?;
+            // title, address, provider, venue_id and venue_type
+            for _ in 0..5 {
+                skip_bytes(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_MEDIA_GEO_LIVE => {
+            let flags = read_i32(buf)?;
+            skip_geo_point(buf)?;
+            if flags & (1 << 0) != 0 {
+                // heading
+                read_i32(buf)?;
+            }
+            // period
+            read_i32(buf)?;
+            if flags & (1 << 1) != 0 {
+                // proximity_notification_radius
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_MEDIA_DICE => {
+            read_i32(buf)?;
+            skip_bytes(buf)
+        }
+        TL_MESSAGE_MEDIA_GAME => skip_game(buf),
+        TL_MESSAGE_MEDIA_INVOICE => {
+            let flags = read_i32(buf)?;
+            // title and description
+            skip_bytes(buf)?;
+            skip_bytes(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_web_document(buf)?;
+            }
+            if flags & (1 << 2) != 0 {
+                // receipt_msg_id
+                read_i32(buf)?;
+            }
+            // currency, total_amount and start_param
+            skip_bytes(buf)?;
+            read_i64(buf)?;
+            skip_bytes(buf)?;
+            if flags & (1 << 4) != 0 {
+                skip_message_extended_media(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_MEDIA_POLL => {
+            skip_poll(buf)?;
+            skip_poll_results(buf)
+        }
+        TL_MESSAGE_MEDIA_STORY => {
+            let flags = read_i32(buf)?;
+            // peer and id
+            read_peer(buf)?;
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_story_item(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_MEDIA_GIVEAWAY => {
+            let flags = read_i32(buf)?;
+            // channels
+            skip_vector(buf, skip_i64)?;
+            if flags & (1 << 1) != 0 {
+                // countries_iso2
+                skip_vector(buf, skip_bytes)?;
+            }
+            if flags & (1 << 3) != 0 {
+                // prize_description
+                skip_bytes(buf)?;
+            }
+            // quantity
+            read_i32(buf)?;
+            if flags & (1 << 4) != 0 {
+                // months
+                read_i32(buf)?;
+            }
+            if flags & (1 << 5) != 0 {
+                // stars
+                read_i64(buf)?;
+            }
+            // until_date
+            skip_i32(buf)
+        }
+        TL_MESSAGE_MEDIA_GIVEAWAY_RESULTS => {
+            let flags = read_i32(buf)?;
+            // channel_id
+            read_i64(buf)?;
+            if flags & (1 << 3) != 0 {
+                // additional_peers_count
+                read_i32(buf)?;
+            }
+            // launch_msg_id, winners_count and unclaimed_count
+            for _ in 0..3 {
+                read_i32(buf)?;
+            }
+            // winners
+            skip_vector(buf, skip_i64)?;
+            if flags & (1 << 4) != 0 {
+                // months
+                read_i32(buf)?;
+            }
+            if flags & (1 << 5) != 0 {
+                // stars
+                read_i64(buf)?;
+            }
+            if flags & (1 << 1) != 0 {
+                // prize_description
+                skip_bytes(buf)?;
+            }
+            // until_date
+            skip_i32(buf)
+        }
+        TL_MESSAGE_MEDIA_PAID_MEDIA => {
+            // stars_amount
+            read_i64(buf)?;
+            skip_vector(buf, skip_message_extended_media)
+        }
+        id => Err(unsupported("MessageMedia", id)),
+    }
+}
+
+fn skip_game(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_GAME {
+        return Err(unsupported("Game", id));
+    }
+    let flags = read_i32(buf)?;
+    // id and access_hash
+    read_i64(buf)?;
+    read_i64(buf)?;
+    // short_name, title and description
+    for _ in 0..3 {
+        skip_bytes(buf)?;
+    }
+    skip_photo(buf)?;
+    if flags & (1 << 0) != 0 {
+        skip_document(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_web_document(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    // url
+    skip_bytes(buf)?;
+    match id {
+        TL_WEB_DOCUMENT => {
+            // access_hash
+            read_i64(buf)?;
+        }
+        TL_WEB_DOCUMENT_NO_PROXY => {}
+        id => return Err(unsupported("WebDocument", id)),
+    }
+    // size and mime_type
+    read_i32(buf)?;
+    skip_bytes(buf)?;
+    skip_vector(buf, |buf| read_document_attribute(buf).map(drop))
+}
+
+fn skip_message_extended_media(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_MESSAGE_EXTENDED_MEDIA_PREVIEW => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // w and h
+                read_i32(buf)?;
+                read_i32(buf)?;
+            }
+            if flags & (1 << 1) != 0 {
+                // thumb
+                skip_photo_size(buf)?;
+            }
+            if flags & (1 << 2) != 0 {
+                // video_duration
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_EXTENDED_MEDIA => skip_message_media(buf),
+        id => Err(unsupported("MessageExtendedMedia", id)),
+    }
+}
+
+fn skip_poll(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_POLL {
+        return Err(unsupported("Poll", id));
+    }
+    // id
+    read_i64(buf)?;
+    let flags = read_i32(buf)?;
+    // question
+    skip_text_with_entities(buf)?;
+    skip_vector(buf, |buf| {
+        let id = read_u32(buf)?;
+        if id != TL_POLL_ANSWER {
+            return Err(unsupported("PollAnswer", id));
+        }
+        // text and option
+        skip_text_with_entities(buf)?;
+        skip_bytes(buf)
+    })?;
+    if flags & (1 << 4) != 0 {
+        // close_period
+        read_i32(buf)?;
+    }
+    if flags & (1 << 5) != 0 {
+        // close_date
+        read_i32(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_poll_results(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_POLL_RESULTS {
+        return Err(unsupported("PollResults", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 1) != 0 {
+        skip_vector(buf, |buf| {
+            let id = read_u32(buf)?;
+            if id != TL_POLL_ANSWER_VOTERS {
+                return Err(unsupported("PollAnswerVoters", id));
+            }
+            // flags, option and voters
+            read_i32(buf)?;
+            skip_bytes(buf)?;
+            skip_i32(buf)
+        })?;
+    }
+    if flags & (1 << 2) != 0 {
+        // total_voters
+        read_i32(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        // recent_voters
+        skip_vector(buf, skip_peer)?;
+    }
+    if flags & (1 << 4) != 0 {
+        // solution and solution_entities
+        skip_bytes(buf)?;
+        skip_vector(buf, skip_message_entity)?;
+    }
+    Ok(())
+}
+
+fn skip_story_item(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_STORY_ITEM_DELETED => skip_i32(buf),
+        TL_STORY_ITEM_SKIPPED => {
+            // flags, id, date and expire_date
+            for _ in 0..4 {
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_STORY_ITEM | TL_STORY_ITEM_ALBUMS => {
+            let flags = read_i32(buf)?;
+            // id and date
+            read_i32(buf)?;
+            read_i32(buf)?;
+            if flags & (1 << 18) != 0 {
+                // from_id
+                read_peer(buf)?;
+            }
+            if flags & (1 << 17) != 0 {
+                skip_story_fwd_header(buf)?;
+            }
+            // expire_date
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // caption
+                skip_bytes(buf)?;
+            }
+            if flags & (1 << 1) != 0 {
+                skip_vector(buf, skip_message_entity)?;
+            }
+            skip_message_media(buf)?;
+            if flags & (1 << 14) != 0 {
+                skip_vector(buf, skip_media_area)?;
+            }
+            if flags & (1 << 2) != 0 {
+                skip_vector(buf, skip_privacy_rule)?;
+            }
+            if flags & (1 << 3) != 0 {
+                skip_story_views(buf)?;
+            }
+            if flags & (1 << 15) != 0 {
+                // sent_reaction
+                skip_reaction(buf)?;
+            }
+            if flags & (1 << 19) != 0 {
+                // albums
+                skip_vector(buf, skip_i32)?;
+            }
+            Ok(())
+        }
+        id => Err(unsupported("StoryItem", id)),
+    }
+}
+
+fn skip_story_fwd_header(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_STORY_FWD_HEADER {
+        return Err(unsupported("StoryFwdHeader", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 0) != 0 {
+        // from
+        read_peer(buf)?;
+    }
+    if flags & (1 << 1) != 0 {
+        // from_name
+        skip_bytes(buf)?;
+    }
+    if flags & (1 << 2) != 0 {
+        // story_id
+        read_i32(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_story_views(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_STORY_VIEWS {
+        return Err(unsupported("StoryViews", id));
+    }
+    let flags = read_i32(buf)?;
+    // views_count
+    read_i32(buf)?;
+    if flags & (1 << 2) != 0 {
+        // forwards_count
+        read_i32(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        skip_vector(buf, skip_reaction_count)?;
+    }
+    if flags & (1 << 4) != 0 {
+        // reactions_count
+        read_i32(buf)?;
+    }
+    if flags & (1 << 0) != 0 {
+        // recent_viewers
+        skip_vector(buf, skip_i64)?;
+    }
+    Ok(())
+}
+
+fn skip_media_area(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    let flags = match id {
+        TL_MEDIA_AREA_GEO_POINT | TL_MEDIA_AREA_SUGGESTED_REACTION => read_i32(buf)?,
+        _ => 0,
+    };
+    skip_media_area_coordinates(buf)?;
+    match id {
+        TL_MEDIA_AREA_VENUE => {
+            skip_geo_point(buf)?;
+            // title, address, provider, venue_id and venue_type
+            for _ in 0..5 {
+                skip_bytes(buf)?;
+            }
+            Ok(())
+        }
+        TL_MEDIA_AREA_GEO_POINT => {
+            skip_geo_point(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_geo_point_address(buf)?;
+            }
+            Ok(())
+        }
+        TL_MEDIA_AREA_SUGGESTED_REACTION => skip_reaction(buf),
+        TL_MEDIA_AREA_CHANNEL_POST => {
+            read_i64(buf)?;
+            skip_i32(buf)
+        }
+        TL_MEDIA_AREA_URL | TL_MEDIA_AREA_STAR_GIFT => skip_bytes(buf),
+        TL_MEDIA_AREA_WEATHER => {
+            // emoji, temperature_c and color
+            skip_bytes(buf)?;
+            read_f64(buf)?;
+            skip_i32(buf)
+        }
+        id => Err(unsupported("MediaArea", id)),
+    }
+}
+
+fn skip_media_area_coordinates(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MEDIA_AREA_COORDINATES {
+        return Err(unsupported("MediaAreaCoordinates", id));
+    }
+    let flags = read_i32(buf)?;
+    // x, y, w, h and rotation
+    for _ in 0..5 {
+        read_f64(buf)?;
+    }
+    if flags & (1 << 0) != 0 {
+        // radius
+        read_f64(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_geo_point_address(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_GEO_POINT_ADDRESS {
+        return Err(unsupported("GeoPointAddress", id));
+    }
+    let flags = read_i32(buf)?;
+    // country_iso2
+    skip_bytes(buf)?;
+    // state, city and street
+    for bit in 0..3 {
+        if flags & (1 << bit) != 0 {
+            skip_bytes(buf)?;
+        }
+    }
+    Ok(())
+}
+
+fn skip_privacy_rule(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_PRIVACY_VALUE_ALLOW_CONTACTS
+        | TL_PRIVACY_VALUE_ALLOW_ALL
+        | TL_PRIVACY_VALUE_DISALLOW_CONTACTS
+        | TL_PRIVACY_VALUE_DISALLOW_ALL
+        | TL_PRIVACY_VALUE_ALLOW_CLOSE_FRIENDS
+        | TL_PRIVACY_VALUE_ALLOW_PREMIUM
+        | TL_PRIVACY_VALUE_ALLOW_BOTS
+        | TL_PRIVACY_VALUE_DISALLOW_BOTS => Ok(()),
+        TL_PRIVACY_VALUE_ALLOW_USERS
+        | TL_PRIVACY_VALUE_DISALLOW_USERS
+        | TL_PRIVACY_VALUE_ALLOW_CHAT_PARTICIPANTS
+        | TL_PRIVACY_VALUE_DISALLOW_CHAT_PARTICIPANTS => skip_vector(buf, skip_i64),
+        id => Err(unsupported("PrivacyRule", id)),
+    }
+}
+
+fn skip_geo_point(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_GEO_POINT_EMPTY => Ok(()),
+        TL_GEO_POINT => {
+            let flags = read_i32(buf)?;
+            // long and lat
+            read_f64(buf)?;
+            read_f64(buf)?;
+            // access_hash
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                // accuracy_radius
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        id => Err(unsupported("GeoPoint", id)),
+    }
+}
+
+fn skip_photo(buf: &mut TlBytes) -> Result<()> {
+    read_photo(buf).map(drop)
+}
+
+/// Reads `Photo`, returning the identifier of the photo; `None` for
+/// `photoEmpty`.
+fn read_photo(buf: &mut TlBytes) -> Result<Option<i64>> {
+    match read_u32(buf)? {
+        TL_PHOTO_EMPTY => skip_i64(buf).map(|()| None),
+        TL_PHOTO => {
+            let flags = read_i32(buf)?;
+            let id = read_i64(buf)?;
+            // access_hash
+            read_i64(buf)?;
+            // file_reference
+            skip_bytes(buf)?;
+            // date
+            read_i32(buf)?;
+            skip_vector(buf, skip_photo_size)?;
+            if flags & (1 << 1) != 0 {
+                skip_vector(buf, skip_video_size)?;
+            }
+            // dc_id
+            skip_i32(buf)?;
+            Ok(Some(id))
+        }
+        id => Err(unsupported("Photo", id)),
+    }
+}
+
+fn skip_photo_size(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    // type
+    skip_bytes(buf)?;
+    match id {
+        TL_PHOTO_SIZE_EMPTY => Ok(()),
+        TL_PHOTO_SIZE => {
+            // w, h and size
+            for _ in 0..3 {
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_PHOTO_CACHED_SIZE => {
+            read_i32(buf)?;
+            read_i32(buf)?;
+            skip_bytes(buf)
+        }
+        TL_PHOTO_STRIPPED_SIZE | TL_PHOTO_PATH_SIZE => skip_bytes(buf),
+        TL_PHOTO_SIZE_PROGRESSIVE => {
+            read_i32(buf)?;
+            read_i32(buf)?;
+            skip_vector(buf, skip_i32)
+        }
+        id => Err(unsupported("PhotoSize", id)),
+    }
+}
+
+fn skip_video_size(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_VIDEO_SIZE => {
+            let flags = read_i32(buf)?;
+            // type
+            skip_bytes(buf)?;
+            // w, h and size
+            for _ in 0..3 {
+                read_i32(buf)?;
+            }
+            if flags & (1 << 0) != 0 {
+                // video_start_ts
+                read_f64(buf)?;
+            }
+            Ok(())
+        }
+        TL_VIDEO_SIZE_EMOJI_MARKUP => {
+            read_i64(buf)?;
+            skip_vector(buf, skip_i32)
+        }
+        TL_VIDEO_SIZE_STICKER_MARKUP => {
+            skip_input_sticker_set(buf)?;
+            read_i64(buf)?;
+            skip_vector(buf, skip_i32)
+        }
+        id => Err(unsupported("VideoSize", id)),
+    }
+}
+
+fn skip_input_sticker_set(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_INPUT_STICKER_SET_EMPTY | TL_INPUT_STICKER_SET_ANIMATED_EMOJI => Ok(()),
+        TL_INPUT_STICKER_SET_ID => {
+            read_i64(buf)?;
+            skip_i64(buf)
+        }
+        TL_INPUT_STICKER_SET_SHORT_NAME | TL_INPUT_STICKER_SET_DICE => skip_bytes(buf),
+        id => Err(unsupported("InputStickerSet", id)),
+    }
+}
+
+/// The fields of a `document` needed for a [`ServerMessageMedia`].
+struct ServerDocument {
+    id: i64,
+    mime_type: String,
+    attributes: Vec<ServerDocumentAttribute>,
+}
+
+fn skip_document(buf: &mut TlBytes) -> Result<()> {
+    read_document(buf).map(drop)
+}
+
+/// Reads `Document`; `None` for `documentEmpty`.
+fn read_document(buf: &mut TlBytes) -> Result<Option<ServerDocument>> {
+    match read_u32(buf)? {
+        TL_DOCUMENT_EMPTY => skip_i64(buf).map(|()| None),
+        TL_DOCUMENT => {
+            let flags = read_i32(buf)?;
+            let id = read_i64(buf)?;
+            // access_hash
+            read_i64(buf)?;
+            // file_reference
+            skip_bytes(buf)?;
+            // date
+            read_i32(buf)?;
+            let mime_type = read_string(buf)?;
+            // size
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_vector(buf, skip_photo_size)?;
+            }
+            if flags & (1 << 1) != 0 {
+                skip_vector(buf, skip_video_size)?;
+            }
+            // dc_id
+            read_i32(buf)?;
+            let attributes = read_vector(buf, read_document_attribute)?
+                .into_iter()
+                .flatten()
+                .collect();
+            Ok(Some(ServerDocument {
+                id,
+                mime_type,
+                attributes,
+            }))
+        }
+        id => Err(unsupported("Document", id)),
+    }
+}
+
+/// Reads `DocumentAttribute`; `None` for the attributes left out of
+/// [`ServerDocumentAttribute`].
+fn read_document_attribute(buf: &mut TlBytes) -> Result<Option<ServerDocumentAttribute>> {
+    match read_u32(buf)? {
+        TL_DOCUMENT_ATTRIBUTE_IMAGE_SIZE => {
+            read_i32(buf)?;
+            skip_i32(buf).map(|()| None)
+        }
+        TL_DOCUMENT_ATTRIBUTE_ANIMATED => Ok(Some(ServerDocumentAttribute::Animated)),
+        TL_DOCUMENT_ATTRIBUTE_HAS_STICKERS => Ok(None),
+        TL_DOCUMENT_ATTRIBUTE_STICKER => {
+            let flags = read_i32(buf)?;
+            let alt = read_string(buf)?;
+            skip_input_sticker_set(buf)?;
+            if flags & (1 << 0) != 0 {
+                let id = read_u32(buf)?;
+                if id != TL_MASK_COORDS {
+                    return Err(unsupported("MaskCoords", id));
+                }
+                // n, x, y and zoom
+                read_i32(buf)?;
+                for _ in 0..3 {
+                    read_f64(buf)?;
+                }
+            }
+            Ok(Some(ServerDocumentAttribute::Sticker { alt }))
+        }
+        TL_DOCUMENT_ATTRIBUTE_VIDEO
+        | TL_DOCUMENT_ATTRIBUTE_VIDEO_START
+        | TL_DOCUMENT_ATTRIBUTE_VIDEO_CODEC => {
+            let flags = read_i32(buf)?;
+            let duration = read_f64(buf)?;
+            let w = read_i32(buf)?;
+            let h = read_i32(buf)?;
+            if flags & (1 << 2) != 0 {
+                // preload_prefix_size
+                read_i32(buf)?;
+            }
+            if flags & (1 << 4) != 0 {
+                // video_start_ts
+                read_f64(buf)?;
+            }
+            if flags & (1 << 5) != 0 {
+                // video_codec
+                skip_bytes(buf)?;
+            }
+            Ok(Some(ServerDocumentAttribute::Video {
+                round_message: flags & (1 << 0) != 0,
+                supports_streaming: flags & (1 << 1) != 0,
+                duration,
+                w,
+                h,
+            }))
+        }
+        TL_DOCUMENT_ATTRIBUTE_AUDIO => {
+            let flags = read_i32(buf)?;
+            let duration = read_i32(buf)?;
+            // title, performer and waveform
+            for bit in 0..3 {
+                if flags & (1 << bit) != 0 {
+                    skip_bytes(buf)?;
+                }
+            }
+            Ok(Some(ServerDocumentAttribute::Audio {
+                voice: flags & (1 << 10) != 0,
+                duration,
+            }))
+        }
+        TL_DOCUMENT_ATTRIBUTE_FILENAME => Ok(Some(ServerDocumentAttribute::Filename {
+            file_name: read_string(buf)?,
+        })),
+        TL_DOCUMENT_ATTRIBUTE_CUSTOM_EMOJI => {
+            read_i32(buf)?;
+            skip_bytes(buf)?;
+            skip_input_sticker_set(buf).map(|()| None)
+        }
+        id => Err(unsupported("DocumentAttribute", id)),
+    }
+}
+
+fn skip_web_page(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_WEB_PAGE_EMPTY => {
+            let flags = read_i32(buf)?;
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_bytes(buf)?;
+            }
+            Ok(())
+        }
+        TL_WEB_PAGE_PENDING => {
+            let flags = read_i32(buf)?;
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_bytes(buf)?;
+            }
+            skip_i32(buf)
+        }
+        TL_WEB_PAGE_NOT_MODIFIED => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_WEB_PAGE => {
+            let flags = read_i32(buf)?;
+            // id, url, display_url and hash
+            read_i64(buf)?;
+            skip_bytes(buf)?;
+            skip_bytes(buf)?;
+            read_i32(buf)?;
+            // type, site_name, title and description
+            for bit in 0..4 {
+                if flags & (1 << bit) != 0 {
+                    skip_bytes(buf)?;
+                }
+            }
+            if flags & (1 << 4) != 0 {
+                skip_photo(buf)?;
+            }
+            if flags & (1 << 5) != 0 {
+                // embed_url and embed_type
+                skip_bytes(buf)?;
+                skip_bytes(buf)?;
+            }
+            if flags & (1 << 6) != 0 {
+                // embed_width and embed_height
+                read_i32(buf)?;
+                read_i32(buf)?;
+            }
+            if flags & (1 << 7) != 0 {
+                // duration
+                read_i32(buf)?;
+            }
+            if flags & (1 << 8) != 0 {
+                // author
+                skip_bytes(buf)?;
+            }
+            if flags & (1 << 9) != 0 {
+                skip_document(buf)?;
+            }
+            if flags & (1 << 10) != 0 {
+                return Err(malformed("unsupported cached_page of a WebPage"));
+            }
+            if flags & (1 << 12) != 0 {
+                skip_vector(buf, skip_web_page_attribute)?;
+            }
+            Ok(())
+        }
+        id => Err(unsupported("WebPage", id)),
+    }
+}
+
+fn skip_web_page_attribute(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_WEB_PAGE_ATTRIBUTE_THEME => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                skip_vector(buf, skip_document)?;
+            }
+            if flags & (1 << 1) != 0 {
+                return Err(malformed("unsupported settings of a webPageAttributeTheme"));
+            }
+            Ok(())
+        }
+        TL_WEB_PAGE_ATTRIBUTE_STORY => {
+            let flags = read_i32(buf)?;
+            read_peer(buf)?;
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                return Err(malformed("unsupported story of a webPageAttributeStory"));
+            }
+            Ok(())
+        }
+        TL_WEB_PAGE_ATTRIBUTE_STICKER_SET => {
+            read_i32(buf)?;
+            skip_vector(buf, skip_document)
+        }
+        id => Err(unsupported("WebPageAttribute", id)),
+    }
+}
+
+fn skip_reply_markup(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_REPLY_KEYBOARD_HIDE => skip_i32(buf),
+        TL_REPLY_KEYBOARD_FORCE_REPLY => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 3) != 0 {
+                // placeholder
+                skip_bytes(buf)?;
+            }
+            Ok(())
+        }
+        TL_REPLY_KEYBOARD_MARKUP => {
+            let flags = read_i32(buf)?;
+            skip_vector(buf, skip_keyboard_button_row)?;
+            if flags & (1 << 3) != 0 {
+                // placeholder
+                skip_bytes(buf)?;
+            }
+            Ok(())
+        }
+        TL_REPLY_INLINE_MARKUP => skip_vector(buf, skip_keyboard_button_row),
+        id => Err(unsupported("ReplyMarkup", id)),
+    }
+}
+
+fn skip_keyboard_button_row(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_KEYBOARD_BUTTON_ROW {
+        return Err(unsupported("KeyboardButtonRow", id));
+    }
+    skip_vector(buf, skip_keyboard_button)
+}
+
+fn skip_keyboard_button(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_KEYBOARD_BUTTON
+        | TL_KEYBOARD_BUTTON_REQUEST_PHONE
+        | TL_KEYBOARD_BUTTON_REQUEST_GEO_LOCATION
+        | TL_KEYBOARD_BUTTON_GAME
+        | TL_KEYBOARD_BUTTON_BUY => skip_bytes(buf),
+        TL_KEYBOARD_BUTTON_URL
+        | TL_KEYBOARD_BUTTON_WEB_VIEW
+        | TL_KEYBOARD_BUTTON_SIMPLE_WEB_VIEW
+        | TL_KEYBOARD_BUTTON_COPY => {
+            skip_bytes(buf)?;
+            skip_bytes(buf)
+        }
+        TL_KEYBOARD_BUTTON_CALLBACK => {
+            // flags, text and data
+            read_i32(buf)?;
+            skip_bytes(buf)?;
+            skip_bytes(buf)
+        }
+        TL_KEYBOARD_BUTTON_SWITCH_INLINE => {
+            let flags = read_i32(buf)?;
+            // text and query
+            skip_bytes(buf)?;
+            skip_bytes(buf)?;
+            if flags & (1 << 1) != 0 {
+                // peer_types, constructors without fields
+                skip_vector(buf, |buf| read_u32(buf).map(drop))?;
+            }
+            Ok(())
+        }
+        TL_KEYBOARD_BUTTON_URL_AUTH => {
+            let flags = read_i32(buf)?;
+            // text
+            skip_bytes(buf)?;
+            if flags & (1 << 0) != 0 {
+                // fwd_text
+                skip_bytes(buf)?;
+            }
+            // url and button_id
+            skip_bytes(buf)?;
+            skip_i32(buf)
+        }
+        TL_KEYBOARD_BUTTON_REQUEST_POLL => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // quiz
+                read_u32(buf)?;
+            }
+            skip_bytes(buf)
+        }
+        TL_KEYBOARD_BUTTON_USER_PROFILE => {
+            skip_bytes(buf)?;
+            skip_i64(buf)
+        }
+        id => Err(unsupported("KeyboardButton", id)),
+    }
+}
+
+fn skip_message_entity(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id == TL_MESSAGE_ENTITY_BLOCKQUOTE {
+        // flags
+        read_i32(buf)?;
+    }
+    // offset and length
+    read_i32(buf)?;
+    read_i32(buf)?;
+    match id {
+        TL_MESSAGE_ENTITY_UNKNOWN
+        | TL_MESSAGE_ENTITY_MENTION
+        | TL_MESSAGE_ENTITY_HASHTAG
+        | TL_MESSAGE_ENTITY_BOT_COMMAND
+        | TL_MESSAGE_ENTITY_URL
+        | TL_MESSAGE_ENTITY_EMAIL
+        | TL_MESSAGE_ENTITY_BOLD
+        | TL_MESSAGE_ENTITY_ITALIC
+        | TL_MESSAGE_ENTITY_CODE
+        | TL_MESSAGE_ENTITY_PHONE
+        | TL_MESSAGE_ENTITY_CASHTAG
+        | TL_MESSAGE_ENTITY_UNDERLINE
+        | TL_MESSAGE_ENTITY_STRIKE
+        | TL_MESSAGE_ENTITY_BANK_CARD
+        | TL_MESSAGE_ENTITY_SPOILER
+        | TL_MESSAGE_ENTITY_BLOCKQUOTE => Ok(()),
+        // language and url
+        TL_MESSAGE_ENTITY_PRE | TL_MESSAGE_ENTITY_TEXT_URL => skip_bytes(buf),
+        // user_id and document_id
+        TL_MESSAGE_ENTITY_MENTION_NAME | TL_MESSAGE_ENTITY_CUSTOM_EMOJI => skip_i64(buf),
+        id => Err(unsupported("MessageEntity", id)),
+    }
+}
+
+fn skip_message_replies(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MESSAGE_REPLIES {
+        return Err(unsupported("MessageReplies", id));
+    }
+    let flags = read_i32(buf)?;
+    // replies and replies_pts
+    read_i32(buf)?;
+    read_i32(buf)?;
+    if flags & (1 << 1) != 0 {
+        // recent_repliers
+        skip_vector(buf, skip_peer)?;
+    }
+    if flags & (1 << 0) != 0 {
+        // channel_id
+        read_i64(buf)?;
+    }
+    if flags & (1 << 2) != 0 {
+        // max_id
+        read_i32(buf)?;
+    }
+    if flags & (1 << 3) != 0 {
+        // read_max_id
+        read_i32(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_message_reactions(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MESSAGE_REACTIONS && id != TL_MESSAGE_REACTIONS_TOP {
+        return Err(unsupported("MessageReactions", id));
+    }
+    let flags = read_i32(buf)?;
+    skip_vector(buf, skip_reaction_count)?;
+    if flags & (1 << 1) != 0 {
+        skip_vector(buf, skip_message_peer_reaction)?;
+    }
+    if flags & (1 << 4) != 0 {
+        skip_vector(buf, skip_message_reactor)?;
+    }
+    Ok(())
+}
+
+fn skip_reaction_count(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_REACTION_COUNT {
+        return Err(unsupported("ReactionCount", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 0) != 0 {
+        // chosen_order
+        read_i32(buf)?;
+    }
+    skip_reaction(buf)?;
+    // count
+    skip_i32(buf)
+}
+
+fn skip_message_peer_reaction(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MESSAGE_PEER_REACTION {
+        return Err(unsupported("MessagePeerReaction", id));
+    }
+    // flags, peer_id and date
+    read_i32(buf)?;
+    read_peer(buf)?;
+    read_i32(buf)?;
+    skip_reaction(buf)
+}
+
+fn skip_message_reactor(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_MESSAGE_REACTOR {
+        return Err(unsupported("MessageReactor", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 3) != 0 {
+        // peer_id
+        read_peer(buf)?;
+    }
+    // count
+    skip_i32(buf)
+}
+
+fn skip_reaction(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_REACTION_EMPTY | TL_REACTION_PAID => Ok(()),
+        TL_REACTION_EMOJI => skip_bytes(buf),
+        TL_REACTION_CUSTOM_EMOJI => skip_i64(buf),
+        id => Err(unsupported("Reaction", id)),
+    }
+}
+
+pub(crate) fn skip_restriction_reason(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_RESTRICTION_REASON {
+        return Err(unsupported("RestrictionReason", id));
+    }
+    // platform, reason and text
+    for _ in 0..3 {
+        skip_bytes(buf)?;
+    }
+    Ok(())
+}
+
+fn skip_fact_check(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_FACT_CHECK {
+        return Err(unsupported("FactCheck", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 1) != 0 {
+        // country and text
+        skip_bytes(buf)?;
+        skip_text_with_entities(buf)?;
+    }
+    // hash
+    skip_i64(buf)
+}
+
+fn skip_text_with_entities(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_TEXT_WITH_ENTITIES {
+        return Err(unsupported("TextWithEntities", id));
+    }
+    skip_bytes(buf)?;
+    skip_vector(buf, skip_message_entity)
+}
+
+fn skip_message_action(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_MESSAGE_ACTION_EMPTY
+        | TL_MESSAGE_ACTION_CHAT_DELETE_PHOTO
+        | TL_MESSAGE_ACTION_PIN_MESSAGE
+        | TL_MESSAGE_ACTION_HISTORY_CLEAR
+        | TL_MESSAGE_ACTION_SCREENSHOT_TAKEN
+        | TL_MESSAGE_ACTION_CONTACT_SIGN_UP
+        | TL_MESSAGE_ACTION_CHAT_JOINED_BY_REQUEST => Ok(()),
+        TL_MESSAGE_ACTION_CHAT_CREATE => {
+            skip_bytes(buf)?;
+            skip_vector(buf, skip_i64)
+        }
+        TL_MESSAGE_ACTION_CHAT_EDIT_TITLE
+        | TL_MESSAGE_ACTION_CHANNEL_CREATE
+        | TL_MESSAGE_ACTION_CUSTOM_ACTION => skip_bytes(buf),
+        TL_MESSAGE_ACTION_CHAT_EDIT_PHOTO => skip_photo(buf),
+        TL_MESSAGE_ACTION_CHAT_ADD_USER => skip_vector(buf, skip_i64),
+        TL_MESSAGE_ACTION_CHAT_DELETE_USER
+        | TL_MESSAGE_ACTION_CHAT_JOINED_BY_LINK
+        | TL_MESSAGE_ACTION_CHAT_MIGRATE_TO => skip_i64(buf),
+        TL_MESSAGE_ACTION_CHANNEL_MIGRATE_FROM => {
+            skip_bytes(buf)?;
+            skip_i64(buf)
+        }
+        TL_MESSAGE_ACTION_PHONE_CALL => {
+            let flags = read_i32(buf)?;
+            // call_id
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                match read_u32(buf)? {
+                    TL_PHONE_CALL_DISCARD_REASON_MISSED
+                    | TL_PHONE_CALL_DISCARD_REASON_DISCONNECT
+                    | TL_PHONE_CALL_DISCARD_REASON_HANGUP
+                    | TL_PHONE_CALL_DISCARD_REASON_BUSY => {}
+                    id => return Err(unsupported("PhoneCallDiscardReason", id)),
+                }
+            }
+            if flags & (1 << 1) != 0 {
+                // duration
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_ACTION_GROUP_CALL => {
+            let flags = read_i32(buf)?;
+            let id = read_u32(buf)?;
+            if id != TL_INPUT_GROUP_CALL {
+                return Err(unsupported("InputGroupCall", id));
+            }
+            // id and access_hash
+            read_i64(buf)?;
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                // duration
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_ACTION_SET_MESSAGES_TTL => {
+            let flags = read_i32(buf)?;
+            // period
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // auto_setting_from
+                read_i64(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_ACTION_TOPIC_CREATE => {
+            let flags = read_i32(buf)?;
+            // title and icon_color
+            skip_bytes(buf)?;
+            read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // icon_emoji_id
+                read_i64(buf)?;
+            }
+            Ok(())
+        }
+        TL_MESSAGE_ACTION_TOPIC_EDIT => {
+            let flags = read_i32(buf)?;
+            if flags & (1 << 0) != 0 {
+                // title
+                skip_bytes(buf)?;
+            }
+            if flags & (1 << 1) != 0 {
+                // icon_emoji_id
+                read_i64(buf)?;
+            }
+            // closed and hidden
+            for bit in 2..4 {
+                if flags & (1 << bit) != 0 {
+                    read_u32(buf)?;
+                }
+            }
+            Ok(())
+        }
+        id => Err(unsupported("MessageAction", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+
+    fn tl(build: impl FnOnce(&mut BytesMut)) -> TlBytes {
+        let mut buf = BytesMut::new();
+        build(&mut buf);
+        TlBytes::new(buf.freeze())
+    }
+
+    fn write_peer_channel(buf: &mut BytesMut, channel_id: i64) {
+        TlHelper::write_constructor_id(buf, TL_PEER_CHANNEL);
+        TlHelper::write_i64(buf, channel_id);
+    }
+
+    fn write_vector(buf: &mut BytesMut, count: i32) {
+        TlHelper::write_constructor_id(buf, TL_VECTOR);
+        TlHelper::write_i32(buf, count);
+    }
+
+    fn write_photo(buf: &mut BytesMut) {
+        TlHelper::write_constructor_id(buf, TL_PHOTO);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i64(buf, 1);
+        TlHelper::write_i64(buf, 2);
+        TlHelper::write_bytes(buf, b"ref");
+        TlHelper::write_i32(buf, 1_700_000_000);
+        write_vector(buf, 2);
+        TlHelper::write_constructor_id(buf, TL_PHOTO_STRIPPED_SIZE);
+        TlHelper::write_string(buf, "i");
+        TlHelper::write_bytes(buf, &[1, 2, 3]);
+        TlHelper::write_constructor_id(buf, TL_PHOTO_SIZE_PROGRESSIVE);
+        TlHelper::write_string(buf, "y");
+        TlHelper::write_i32(buf, 1280);
+        TlHelper::write_i32(buf, 720);
+        write_vector(buf, 2);
+        TlHelper::write_i32(buf, 1000);
+        TlHelper::write_i32(buf, 5000);
+        TlHelper::write_i32(buf, 2);
+    }
+
+    #[test]
+    fn test_read_message_with_media_and_entities() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGE);
+            // from_id, fwd_from, reply_to, media, entities, views, replies
+            // and reactions
+            let flags = (1 << 8) | (1 << 2) | (1 << 3) | (1 << 9) | (1 << 7) | (1 << 10);
+            TlHelper::write_i32(buf, flags | (1 << 23) | (1 << 20));
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 55);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 7);
+            write_peer_channel(buf, 100);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_FWD_HEADER);
+            TlHelper::write_i32(buf, 1 << 5);
+            TlHelper::write_string(buf, "hidden sender");
+            TlHelper::write_i32(buf, 1_600_000_000);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_REPLY_HEADER);
+            TlHelper::write_i32(buf, 1 << 4);
+            TlHelper::write_i32(buf, 54);
+            TlHelper::write_i32(buf, 1_700_000_100);
+            TlHelper::write_string(buf, "look #cats");
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_PHOTO);
+            TlHelper::write_i32(buf, 1);
+            write_photo(buf);
+            write_vector(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_ENTITY_HASHTAG);
+            TlHelper::write_i32(buf, 5);
+            TlHelper::write_i32(buf, 5);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_ENTITY_TEXT_URL);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_string(buf, "https://example.com");
+            TlHelper::write_i32(buf, 300);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_REPLIES);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_REACTIONS);
+            TlHelper::write_i32(buf, 0);
+            write_vector(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_REACTION_COUNT);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_constructor_id(buf, TL_REACTION_EMOJI);
+            TlHelper::write_string(buf, "👍");
+            TlHelper::write_i32(buf, 3);
+            // The next message must start here
+            TlHelper::write_i32(buf, 0x7777_7777);
+        });
+
+        let message = read_message(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            message.message_full_id,
+            MessageFullId::new(
+                DialogId::from_channel(ChannelId::new(100).unwrap()),
+                MessageId::from_server_id(55)
+            )
+        );
+        assert_eq!(message.date, 1_700_000_100);
+        assert_eq!(read_i32(&mut buf).unwrap(), 0x7777_7777);
+    }
+
+    #[test]
+    fn test_read_message_with_document() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_PAID_STARS);
+            TlHelper::write_i32(buf, 1 << 9);
+            TlHelper::write_i32(buf, 1 << 6);
+            TlHelper::write_i32(buf, 9);
+            TlHelper::write_constructor_id(buf, TL_PEER_CHAT);
+            TlHelper::write_i64(buf, 12);
+            TlHelper::write_i32(buf, 1_700_000_200);
+            TlHelper::write_string(buf, "");
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_DOCUMENT);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i64(buf, 1);
+            TlHelper::write_i64(buf, 2);
+            TlHelper::write_bytes(buf, b"ref");
+            TlHelper::write_i32(buf, 1_700_000_000);
+            TlHelper::write_string(buf, "audio/ogg");
+            TlHelper::write_i64(buf, 4096);
+            TlHelper::write_i32(buf, 2);
+            write_vector(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_AUDIO);
+            TlHelper::write_i32(buf, (1 << 10) | (1 << 2));
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_bytes(buf, &[0; 63]);
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_FILENAME);
+            TlHelper::write_string(buf, "voice.ogg");
+            // paid_message_stars
+            TlHelper::write_i64(buf, 10);
+        });
+
+        let message = read_message(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            message.message_full_id,
+            MessageFullId::new(
+                DialogId::from_chat(ChatId::new(12).unwrap()),
+                MessageId::from_server_id(9)
+            )
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_server_message() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGE);
+            // out, from_id, media, edit_date and grouped_id
+            let flags = (1 << 1) | (1 << 8) | (1 << 9) | (1 << 15) | (1 << 17);
+            TlHelper::write_i32(buf, flags);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 21);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 7);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 8);
+            TlHelper::write_i32(buf, 1_700_000_400);
+            TlHelper::write_string(buf, "clip");
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_DOCUMENT);
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 4));
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i64(buf, 31);
+            TlHelper::write_i64(buf, 32);
+            TlHelper::write_bytes(buf, b"ref");
+            TlHelper::write_i32(buf, 1_700_000_000);
+            TlHelper::write_string(buf, "video/mp4");
+            TlHelper::write_i64(buf, 1 << 20);
+            TlHelper::write_i32(buf, 2);
+            write_vector(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_VIDEO);
+            TlHelper::write_i32(buf, 1 << 1);
+            TlHelper::write_f64(buf, 12.5);
+            TlHelper::write_i32(buf, 640);
+            TlHelper::write_i32(buf, 360);
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_HAS_STICKERS);
+            TlHelper::write_i32(buf, 1_700_000_500);
+            TlHelper::write_i64(buf, 99);
+        });
+
+        let message = read_server_message(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(
+            message.sender_dialog_id,
+            Some(DialogId::from_user(UserId::new(7).unwrap()))
+        );
+        assert_eq!(message.message_full_id.message_id().get_server_id(), 21);
+        assert!(message.is_outgoing);
+        assert!(!message.is_service);
+        assert_eq!(message.text, "clip");
+        assert_eq!(message.edit_date, Some(1_700_000_500));
+        assert_eq!(message.grouped_id, Some(99));
+        assert_eq!(
+            message.media,
+            Some(ServerMessageMedia::Document {
+                id: 31,
+                mime_type: "video/mp4".to_string(),
+                attributes: vec![ServerDocumentAttribute::Video {
+                    round_message: false,
+                    supports_streaming: true,
+                    duration: 12.5,
+                    w: 640,
+                    h: 360,
+                }],
+                spoiler: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_service_message() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_SERVICE);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 3);
+            write_peer_channel(buf, 100);
+            TlHelper::write_i32(buf, 1_700_000_300);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_ACTION_CHAT_EDIT_PHOTO);
+            write_photo(buf);
+        });
+
+        let message = read_message(&mut buf).unwrap().unwrap();
+        assert_eq!(message.message_full_id.message_id().get_server_id(), 3);
+        assert_eq!(message.date, 1_700_000_300);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_message_empty() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_EMPTY);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_i32(buf, 3);
+            write_peer_channel(buf, 100);
+        });
+        assert_eq!(read_message(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+
+    fn write_media_message(buf: &mut BytesMut) {
+        TlHelper::write_constructor_id(buf, TL_MESSAGE);
+        TlHelper::write_i32(buf, 1 << 9);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i32(buf, 1);
+        write_peer_channel(buf, 100);
+        TlHelper::write_i32(buf, 1_700_000_000);
+        TlHelper::write_string(buf, "");
+    }
+
+    fn write_text_with_entities(buf: &mut BytesMut, text: &str) {
+        TlHelper::write_constructor_id(buf, TL_TEXT_WITH_ENTITIES);
+        TlHelper::write_string(buf, text);
+        write_vector(buf, 0);
+    }
+
+    #[test]
+    fn test_read_message_with_poll() {
+        let mut buf = tl(|buf| {
+            write_media_message(buf);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_POLL);
+            TlHelper::write_constructor_id(buf, TL_POLL);
+            TlHelper::write_i64(buf, 5);
+            TlHelper::write_i32(buf, 1 << 5);
+            write_text_with_entities(buf, "Cats or dogs?");
+            write_vector(buf, 2);
+            for (text, option) in [("Cats", b"0"), ("Dogs", b"1")] {
+                TlHelper::write_constructor_id(buf, TL_POLL_ANSWER);
+                write_text_with_entities(buf, text);
+                TlHelper::write_bytes(buf, option);
+            }
+            TlHelper::write_i32(buf, 1_700_000_100);
+            TlHelper::write_constructor_id(buf, TL_POLL_RESULTS);
+            TlHelper::write_i32(buf, (1 << 1) | (1 << 2) | (1 << 3));
+            write_vector(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_POLL_ANSWER_VOTERS);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_bytes(buf, b"0");
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_i32(buf, 3);
+            write_vector(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 7);
+        });
+
+        let message = read_server_message(&mut buf).unwrap().unwrap();
+        assert_eq!(message.media, Some(ServerMessageMedia::Unsupported));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_message_with_story() {
+        let mut buf = tl(|buf| {
+            write_media_message(buf);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_STORY);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 7);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_constructor_id(buf, TL_STORY_ITEM);
+            // caption, privacy, views and media_areas
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 2) | (1 << 3) | (1 << 14));
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_i32(buf, 1_700_000_000);
+            TlHelper::write_i32(buf, 1_700_086_400);
+            TlHelper::write_string(buf, "sunset");
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_PHOTO);
+            TlHelper::write_i32(buf, 1);
+            write_photo(buf);
+            write_vector(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_MEDIA_AREA_URL);
+            TlHelper::write_constructor_id(buf, TL_MEDIA_AREA_COORDINATES);
+            TlHelper::write_i32(buf, 0);
+            for value in [10.0, 20.0, 30.0, 40.0, 0.0] {
+                TlHelper::write_f64(buf, value);
+            }
+            TlHelper::write_string(buf, "https://example.com");
+            write_vector(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_PRIVACY_VALUE_ALLOW_CONTACTS);
+            TlHelper::write_constructor_id(buf, TL_PRIVACY_VALUE_DISALLOW_USERS);
+            write_vector(buf, 1);
+            TlHelper::write_i64(buf, 8);
+            TlHelper::write_constructor_id(buf, TL_STORY_VIEWS);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 12);
+        });
+
+        let message = read_server_message(&mut buf).unwrap().unwrap();
+        assert_eq!(message.media, Some(ServerMessageMedia::Unsupported));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_message_with_paid_media() {
+        let mut buf = tl(|buf| {
+            write_media_message(buf);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_PAID_MEDIA);
+            TlHelper::write_i64(buf, 50);
+            write_vector(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_EXTENDED_MEDIA_PREVIEW);
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 2));
+            TlHelper::write_i32(buf, 1280);
+            TlHelper::write_i32(buf, 720);
+            TlHelper::write_i32(buf, 15);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_EXTENDED_MEDIA);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_MEDIA_PHOTO);
+            TlHelper::write_i32(buf, 1);
+            write_photo(buf);
+        });
+
+        let message = read_server_message(&mut buf).unwrap().unwrap();
+        assert_eq!(message.media, Some(ServerMessageMedia::Unsupported));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_message_unknown_media() {
+        let mut buf = tl(|buf| {
+            write_media_message(buf);
+            TlHelper::write_constructor_id(buf, 0x1234_5678);
+        });
+        assert!(matches!(read_message(&mut buf), Err(Error::IoError(_))));
+    }
+
+    #[test]
+    fn test_read_vector_too_long() {
+        let mut buf = tl(|buf| write_vector(buf, 1000));
+        assert!(read_vector(&mut buf, read_i32).is_err());
+    }
+}
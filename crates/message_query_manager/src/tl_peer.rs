@@ -0,0 +1,516 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Reading of `Chat` and `User` objects.
+//!
+//! Responses with messages end with the chats and users the messages
+//! mention. Only their access hashes are needed, to address them in later
+//! requests; the rest of every object is skipped field by field like in
+//! [`tl_message`](crate::tl_message). Access hashes of `min` objects can't
+//! be used in requests and are left out.
+
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::{ChannelId, DialogId, UserId};
+
+use crate::error::Result;
+use crate::tl_message::{
+    malformed, read_i32, read_i64, read_u32, read_vector, skip_bytes, skip_i32, skip_i64,
+    skip_restriction_reason, skip_vector, unsupported,
+};
+
+const TL_CHAT_EMPTY: u32 = 0x2956_2865;
+const TL_CHAT: u32 = 0x41cb_f256;
+const TL_CHAT_FORBIDDEN: u32 = 0x6592_a1a7;
+/// `channel` with `bot_verification_icon`
+const TL_CHANNEL: u32 = 0xe009_98b7;
+/// `channel` with `send_paid_messages_stars`
+const TL_CHANNEL_PAID_STARS: u32 = 0x7482_147e;
+/// `channel` with `linked_monoforum_id`
+const TL_CHANNEL_MONOFORUM: u32 = 0xfe68_5355;
+const TL_CHANNEL_FORBIDDEN: u32 = 0x17d4_93d5;
+
+const TL_CHAT_PHOTO_EMPTY: u32 = 0x37c1_011c;
+const TL_CHAT_PHOTO: u32 = 0x1c6e_1c11;
+const TL_INPUT_CHANNEL_EMPTY: u32 = 0xee8c_1e86;
+const TL_INPUT_CHANNEL: u32 = 0xf35a_ec28;
+const TL_CHAT_ADMIN_RIGHTS: u32 = 0x5fb2_24d5;
+const TL_CHAT_BANNED_RIGHTS: u32 = 0x9f12_0418;
+
+const TL_USER_EMPTY: u32 = 0xd3bc_4b7a;
+/// `user` with `bot_verification_icon`
+const TL_USER: u32 = 0x4b46_c37e;
+/// `user` with `send_paid_messages_stars`
+const TL_USER_PAID_STARS: u32 = 0x020b_1422;
+
+const TL_USER_PROFILE_PHOTO_EMPTY: u32 = 0x4f11_bae1;
+const TL_USER_PROFILE_PHOTO: u32 = 0x82d1_f706;
+const TL_USER_STATUS_EMPTY: u32 = 0x09d0_5049;
+const TL_USER_STATUS_ONLINE: u32 = 0xedb9_3949;
+const TL_USER_STATUS_OFFLINE: u32 = 0x008c_703f;
+const TL_USER_STATUS_RECENTLY: u32 = 0x7b19_7dc8;
+const TL_USER_STATUS_LAST_WEEK: u32 = 0x541a_1d1a;
+const TL_USER_STATUS_LAST_MONTH: u32 = 0x6589_9777;
+
+const TL_EMOJI_STATUS_EMPTY: u32 = 0x2de1_1aae;
+const TL_EMOJI_STATUS: u32 = 0xe7ff_068a;
+const TL_EMOJI_STATUS_COLLECTIBLE: u32 = 0x7184_603b;
+const TL_USERNAME: u32 = 0xb407_3647;
+const TL_PEER_COLOR: u32 = 0xb54b_5acf;
+
+/// Reads the `chats` and `users` vectors ending a response, returning the
+/// access hashes of the channels and users in them.
+pub(crate) fn read_access_hashes(buf: &mut TlBytes) -> Result<Vec<(DialogId, i64)>> {
+    let chats = read_vector(buf, read_chat)?;
+    let users = read_vector(buf, read_user)?;
+    Ok(chats.into_iter().chain(users).flatten().collect())
+}
+
+/// Reads `Chat`, returning the access hash of a channel.
+fn read_chat(buf: &mut TlBytes) -> Result<Option<(DialogId, i64)>> {
+    match read_u32(buf)? {
+        TL_CHAT_EMPTY => skip_i64(buf).map(|()| None),
+        TL_CHAT => {
+            let flags = read_i32(buf)?;
+            // id and title
+            read_i64(buf)?;
+            skip_bytes(buf)?;
+            skip_chat_photo(buf)?;
+            // participants_count, date and version
+            for _ in 0..3 {
+                read_i32(buf)?;
+            }
+            if flags & (1 << 6) != 0 {
+                // migrated_to
+                skip_input_channel(buf)?;
+            }
+            if flags & (1 << 14) != 0 {
+                skip_chat_admin_rights(buf)?;
+            }
+            if flags & (1 << 18) != 0 {
+                // default_banned_rights
+                skip_chat_banned_rights(buf)?;
+            }
+            Ok(None)
+        }
+        TL_CHAT_FORBIDDEN => {
+            read_i64(buf)?;
+            skip_bytes(buf).map(|()| None)
+        }
+        TL_CHANNEL | TL_CHANNEL_PAID_STARS | TL_CHANNEL_MONOFORUM => {
+            let flags = read_i32(buf)?;
+            let flags2 = read_i32(buf)?;
+            let id = read_i64(buf)?;
+            let access_hash = if flags & (1 << 13) != 0 {
+                Some(read_i64(buf)?)
+            } else {
+                None
+            };
+            // title
+            skip_bytes(buf)?;
+            if flags & (1 << 6) != 0 {
+                // username
+                skip_bytes(buf)?;
+            }
+            skip_chat_photo(buf)?;
+            // date
+            read_i32(buf)?;
+            if flags & (1 << 9) != 0 {
+                skip_vector(buf, skip_restriction_reason)?;
+            }
+            if flags & (1 << 14) != 0 {
+                skip_chat_admin_rights(buf)?;
+            }
+            // banned_rights and default_banned_rights
+            for bit in [15, 18] {
+                if flags & (1 << bit) != 0 {
+                    skip_chat_banned_rights(buf)?;
+                }
+            }
+            if flags & (1 << 17) != 0 {
+                // participants_count
+                read_i32(buf)?;
+            }
+            if flags2 & (1 << 0) != 0 {
+                skip_vector(buf, skip_username)?;
+            }
+            if flags2 & (1 << 4) != 0 {
+                // stories_max_id
+                read_i32(buf)?;
+            }
+            // color and profile_color
+            for bit in [7, 8] {
+                if flags2 & (1 << bit) != 0 {
+                    skip_peer_color(buf)?;
+                }
+            }
+            if flags2 & (1 << 9) != 0 {
+                skip_emoji_status(buf)?;
+            }
+            // level and subscription_until_date
+            for bit in [10, 11] {
+                if flags2 & (1 << bit) != 0 {
+                    read_i32(buf)?;
+                }
+            }
+            // bot_verification_icon, send_paid_messages_stars and
+            // linked_monoforum_id
+            for bit in [13, 14, 18] {
+                if flags2 & (1 << bit) != 0 {
+                    read_i64(buf)?;
+                }
+            }
+            let is_min = flags & (1 << 12) != 0;
+            match access_hash {
+                Some(access_hash) if !is_min => channel_access_hash(id, access_hash),
+                _ => Ok(None),
+            }
+        }
+        TL_CHANNEL_FORBIDDEN => {
+            let flags = read_i32(buf)?;
+            let id = read_i64(buf)?;
+            let access_hash = read_i64(buf)?;
+            // title
+            skip_bytes(buf)?;
+            if flags & (1 << 16) != 0 {
+                // until_date
+                read_i32(buf)?;
+            }
+            channel_access_hash(id, access_hash)
+        }
+        id => Err(unsupported("Chat", id)),
+    }
+}
+
+fn channel_access_hash(id: i64, access_hash: i64) -> Result<Option<(DialogId, i64)>> {
+    let channel_id = ChannelId::new(id).map_err(malformed)?;
+    Ok(Some((DialogId::from_channel(channel_id), access_hash)))
+}
+
+/// Reads `User`, returning its access hash.
+fn read_user(buf: &mut TlBytes) -> Result<Option<(DialogId, i64)>> {
+    match read_u32(buf)? {
+        TL_USER_EMPTY => skip_i64(buf).map(|()| None),
+        TL_USER | TL_USER_PAID_STARS => {
+            let flags = read_i32(buf)?;
+            let flags2 = read_i32(buf)?;
+            let id = read_i64(buf)?;
+            let access_hash = if flags & (1 << 0) != 0 {
+                Some(read_i64(buf)?)
+            } else {
+                None
+            };
+            // first_name, last_name, username and phone
+            for bit in 1..5 {
+                if flags & (1 << bit) != 0 {
+                    skip_bytes(buf)?;
+                }
+            }
+            if flags & (1 << 5) != 0 {
+                skip_user_profile_photo(buf)?;
+            }
+            if flags & (1 << 6) != 0 {
+                skip_user_status(buf)?;
+            }
+            if flags & (1 << 14) != 0 {
+                // bot_info_version
+                read_i32(buf)?;
+            }
+            if flags & (1 << 18) != 0 {
+                skip_vector(buf, skip_restriction_reason)?;
+            }
+            // bot_inline_placeholder and lang_code
+            for bit in [19, 22] {
+                if flags & (1 << bit) != 0 {
+                    skip_bytes(buf)?;
+                }
+            }
+            if flags & (1 << 30) != 0 {
+                skip_emoji_status(buf)?;
+            }
+            if flags2 & (1 << 0) != 0 {
+                skip_vector(buf, skip_username)?;
+            }
+            if flags2 & (1 << 5) != 0 {
+                // stories_max_id
+                read_i32(buf)?;
+            }
+            // color and profile_color
+            for bit in [8, 9] {
+                if flags2 & (1 << bit) != 0 {
+                    skip_peer_color(buf)?;
+                }
+            }
+            if flags2 & (1 << 12) != 0 {
+                // bot_active_users
+                read_i32(buf)?;
+            }
+            // bot_verification_icon and send_paid_messages_stars
+            for bit in [14, 15] {
+                if flags2 & (1 << bit) != 0 {
+                    read_i64(buf)?;
+                }
+            }
+            let is_min = flags & (1 << 20) != 0;
+            match access_hash {
+                Some(access_hash) if !is_min => {
+                    let user_id = UserId::new(id).map_err(malformed)?;
+                    Ok(Some((DialogId::from_user(user_id), access_hash)))
+                }
+                _ => Ok(None),
+            }
+        }
+        id => Err(unsupported("User", id)),
+    }
+}
+
+fn skip_chat_photo(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_CHAT_PHOTO_EMPTY => Ok(()),
+        TL_CHAT_PHOTO => skip_profile_photo(buf),
+        id => Err(unsupported("ChatPhoto", id)),
+    }
+}
+
+fn skip_user_profile_photo(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_USER_PROFILE_PHOTO_EMPTY => Ok(()),
+        TL_USER_PROFILE_PHOTO => skip_profile_photo(buf),
+        id => Err(unsupported("UserProfilePhoto", id)),
+    }
+}
+
+/// Skips the fields shared by `chatPhoto` and `userProfilePhoto`.
+fn skip_profile_photo(buf: &mut TlBytes) -> Result<()> {
+    let flags = read_i32(buf)?;
+    // photo_id
+    read_i64(buf)?;
+    if flags & (1 << 1) != 0 {
+        // stripped_thumb
+        skip_bytes(buf)?;
+    }
+    // dc_id
+    skip_i32(buf)
+}
+
+fn skip_input_channel(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_INPUT_CHANNEL_EMPTY => Ok(()),
+        TL_INPUT_CHANNEL => {
+            read_i64(buf)?;
+            skip_i64(buf)
+        }
+        id => Err(unsupported("InputChannel", id)),
+    }
+}
+
+fn skip_chat_admin_rights(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_CHAT_ADMIN_RIGHTS {
+        return Err(unsupported("ChatAdminRights", id));
+    }
+    skip_i32(buf)
+}
+
+fn skip_chat_banned_rights(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_CHAT_BANNED_RIGHTS {
+        return Err(unsupported("ChatBannedRights", id));
+    }
+    // flags and until_date
+    read_i32(buf)?;
+    skip_i32(buf)
+}
+
+fn skip_user_status(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_USER_STATUS_EMPTY => Ok(()),
+        TL_USER_STATUS_ONLINE
+        | TL_USER_STATUS_OFFLINE
+        | TL_USER_STATUS_RECENTLY
+        | TL_USER_STATUS_LAST_WEEK
+        | TL_USER_STATUS_LAST_MONTH => skip_i32(buf),
+        id => Err(unsupported("UserStatus", id)),
+    }
+}
+
+fn skip_emoji_status(buf: &mut TlBytes) -> Result<()> {
+    match read_u32(buf)? {
+        TL_EMOJI_STATUS_EMPTY => Ok(()),
+        TL_EMOJI_STATUS => {
+            let flags = read_i32(buf)?;
+            // document_id
+            read_i64(buf)?;
+            if flags & (1 << 0) != 0 {
+                // until
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        TL_EMOJI_STATUS_COLLECTIBLE => {
+            let flags = read_i32(buf)?;
+            // collectible_id, document_id, title and slug
+            read_i64(buf)?;
+            read_i64(buf)?;
+            skip_bytes(buf)?;
+            skip_bytes(buf)?;
+            // pattern_document_id and the four colors
+            read_i64(buf)?;
+            for _ in 0..4 {
+                read_i32(buf)?;
+            }
+            if flags & (1 << 0) != 0 {
+                // until
+                read_i32(buf)?;
+            }
+            Ok(())
+        }
+        id => Err(unsupported("EmojiStatus", id)),
+    }
+}
+
+fn skip_username(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_USERNAME {
+        return Err(unsupported("Username", id));
+    }
+    // flags and username
+    read_i32(buf)?;
+    skip_bytes(buf)
+}
+
+fn skip_peer_color(buf: &mut TlBytes) -> Result<()> {
+    let id = read_u32(buf)?;
+    if id != TL_PEER_COLOR {
+        return Err(unsupported("PeerColor", id));
+    }
+    let flags = read_i32(buf)?;
+    if flags & (1 << 0) != 0 {
+        // color
+        read_i32(buf)?;
+    }
+    if flags & (1 << 1) != 0 {
+        // background_emoji_id
+        read_i64(buf)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rustgram_types::TlHelper;
+
+    const TL_VECTOR: u32 = 0x1cb5_c415;
+
+    fn tl(build: impl FnOnce(&mut BytesMut)) -> TlBytes {
+        let mut buf = BytesMut::new();
+        build(&mut buf);
+        TlBytes::new(buf.freeze())
+    }
+
+    fn write_vector(buf: &mut BytesMut, count: i32) {
+        TlHelper::write_constructor_id(buf, TL_VECTOR);
+        TlHelper::write_i32(buf, count);
+    }
+
+    fn write_user(buf: &mut BytesMut, flags: i32, id: i64, access_hash: i64) {
+        TlHelper::write_constructor_id(buf, TL_USER_PAID_STARS);
+        // access_hash, first_name, photo, status and emoji_status
+        TlHelper::write_i32(
+            buf,
+            flags | (1 << 0) | (1 << 1) | (1 << 5) | (1 << 6) | (1 << 30),
+        );
+        // usernames and color
+        TlHelper::write_i32(buf, (1 << 0) | (1 << 8));
+        TlHelper::write_i64(buf, id);
+        TlHelper::write_i64(buf, access_hash);
+        TlHelper::write_string(buf, "Alice");
+        TlHelper::write_constructor_id(buf, TL_USER_PROFILE_PHOTO);
+        TlHelper::write_i32(buf, 1 << 1);
+        TlHelper::write_i64(buf, 77);
+        TlHelper::write_bytes(buf, &[1, 2, 3]);
+        TlHelper::write_i32(buf, 2);
+        TlHelper::write_constructor_id(buf, TL_USER_STATUS_OFFLINE);
+        TlHelper::write_i32(buf, 1_700_000_000);
+        TlHelper::write_constructor_id(buf, TL_EMOJI_STATUS);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i64(buf, 5);
+        write_vector(buf, 1);
+        TlHelper::write_constructor_id(buf, TL_USERNAME);
+        TlHelper::write_i32(buf, 1 << 1);
+        TlHelper::write_string(buf, "alice");
+        TlHelper::write_constructor_id(buf, TL_PEER_COLOR);
+        TlHelper::write_i32(buf, 1 << 0);
+        TlHelper::write_i32(buf, 3);
+    }
+
+    #[test]
+    fn test_read_access_hashes() {
+        let mut buf = tl(|buf| {
+            write_vector(buf, 3);
+            TlHelper::write_constructor_id(buf, TL_CHAT);
+            TlHelper::write_i32(buf, 1 << 6);
+            TlHelper::write_i64(buf, 12);
+            TlHelper::write_string(buf, "Group");
+            TlHelper::write_constructor_id(buf, TL_CHAT_PHOTO_EMPTY);
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_i32(buf, 1_600_000_000);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_INPUT_CHANNEL);
+            TlHelper::write_i64(buf, 100);
+            TlHelper::write_i64(buf, 5);
+            TlHelper::write_constructor_id(buf, TL_CHANNEL_MONOFORUM);
+            // access_hash, username, banned_rights and participants_count
+            TlHelper::write_i32(buf, (1 << 13) | (1 << 6) | (1 << 15) | (1 << 17));
+            // usernames and level
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 10));
+            TlHelper::write_i64(buf, 100);
+            TlHelper::write_i64(buf, 5);
+            TlHelper::write_string(buf, "News");
+            TlHelper::write_string(buf, "news");
+            TlHelper::write_constructor_id(buf, TL_CHAT_PHOTO);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i64(buf, 78);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_i32(buf, 1_600_000_000);
+            TlHelper::write_constructor_id(buf, TL_CHAT_BANNED_RIGHTS);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 1000);
+            write_vector(buf, 0);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_CHANNEL_FORBIDDEN);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i64(buf, 200);
+            TlHelper::write_i64(buf, 6);
+            TlHelper::write_string(buf, "Private");
+            write_vector(buf, 2);
+            write_user(buf, 0, 7, 9);
+            // A min user
+            write_user(buf, 1 << 20, 8, 10);
+        });
+
+        let access_hashes = read_access_hashes(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(
+            access_hashes,
+            vec![
+                (DialogId::from_channel(ChannelId::new(100).unwrap()), 5),
+                (DialogId::from_channel(ChannelId::new(200).unwrap()), 6),
+                (DialogId::from_user(UserId::new(7).unwrap()), 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_access_hashes_unknown_user() {
+        let mut buf = tl(|buf| {
+            write_vector(buf, 0);
+            write_vector(buf, 1);
+            TlHelper::write_constructor_id(buf, 0x1234_5678);
+        });
+        assert!(read_access_hashes(&mut buf).is_err());
+    }
+}
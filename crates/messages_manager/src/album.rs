@@ -0,0 +1,112 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Media albums.
+//!
+//! Messages of an album are separate messages sharing a non-zero
+//! `media_album_id`, which the server assigns from the `grouped_id` of
+//! `messages.sendMultiMedia`. This module groups such messages so that an
+//! album can be shown as a single unit.
+//!
+//! # TDLib Alignment
+//!
+//! Based on the handling of `media_album_id` in
+//! `td/telegram/MessagesManager.cpp`; like TDLib, only adjacent messages are
+//! grouped.
+
+use rustgram_message_types::Message;
+
+/// Messages shown as a single unit: an album or a standalone message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageGroup {
+    /// Album of the messages, 0 for a standalone message
+    pub media_album_id: i64,
+
+    /// Messages of the group in their original order
+    pub messages: Vec<Message>,
+}
+
+impl MessageGroup {
+    /// Returns `true` if the group is an album.
+    pub const fn is_album(&self) -> bool {
+        self.media_album_id != 0
+    }
+
+    /// Returns the message of the album carrying the caption, if any.
+    ///
+    /// An album has a caption only if exactly one of its items has one.
+    pub fn caption_message(&self) -> Option<&Message> {
+        let mut with_caption = self.messages.iter().filter(|message| {
+            message
+                .content
+                .caption()
+                .is_some_and(|caption| !caption.text().is_empty())
+        });
+        let message = with_caption.next()?;
+        with_caption.next().is_none().then_some(message)
+    }
+}
+
+/// Groups adjacent messages of the same album.
+///
+/// Messages not in an album form groups of their own. The order of the
+/// messages is preserved.
+pub fn group_messages_by_album(messages: impl IntoIterator<Item = Message>) -> Vec<MessageGroup> {
+    let mut groups: Vec<MessageGroup> = Vec::new();
+    for message in messages {
+        match groups.last_mut() {
+            Some(group)
+                if message.is_in_album() && group.media_album_id == message.media_album_id =>
+            {
+                group.messages.push(message);
+            }
+            _ => groups.push(MessageGroup {
+                media_album_id: message.media_album_id,
+                messages: vec![message],
+            }),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_formatted_text::FormattedText;
+    use rustgram_types::{DialogId, MessageId, UserId};
+
+    fn message(id: i32, media_album_id: i64) -> Message {
+        let dialog_id = DialogId::from(UserId::new(1).unwrap());
+        let mut message = Message::new_with_date(
+            MessageId::from_server_id(id),
+            dialog_id,
+            dialog_id,
+            1_700_000_000,
+            FormattedText::new("text"),
+        )
+        .unwrap();
+        message.media_album_id = media_album_id;
+        message
+    }
+
+    #[test]
+    fn test_group_messages_by_album() {
+        let groups = group_messages_by_album(vec![
+            message(1, 0),
+            message(2, 7),
+            message(3, 7),
+            message(4, 0),
+            message(5, 0),
+            message(6, 8),
+            message(7, 7),
+        ]);
+
+        let sizes: Vec<_> = groups.iter().map(|group| group.messages.len()).collect();
+        assert_eq!(sizes, vec![1, 2, 1, 1, 1, 1]);
+        assert!(!groups[0].is_album());
+        assert!(groups[1].is_album());
+        assert_eq!(groups[1].media_album_id, 7);
+        assert_eq!(groups[5].media_album_id, 7);
+    }
+}
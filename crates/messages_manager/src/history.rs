@@ -0,0 +1,190 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Dialog history.
+//!
+//! History is served from the local copy of the messages while the asked
+//! range is contiguous. When it runs into a hole, the page is requested with
+//! `messages.getHistory` and merged into the local copy, so that the range
+//! is marked contiguous and never requested again. Reading the history is
+//! sent with `messages.readHistory`.
+//!
+//! # TDLib Alignment
+//!
+//! Based on `MessagesManager::get_history`, `get_history_from_the_end`,
+//! `on_get_history` and `read_history_on_server` from
+//! `td/telegram/MessagesManager.cpp`.
+
+use std::sync::Arc;
+
+use rustgram_message_types::{Message, MessageValidationError};
+use rustgram_types::{DialogId, MessageId};
+use tracing::{debug, info, warn};
+
+use crate::network::MessageHistoryClient;
+use crate::tl_types::{GetHistoryRequest, InputPeer, ReadHistoryRequest};
+use crate::{MessagesManager, MessagesManagerError};
+
+/// Maximum number of messages returned by one history request.
+pub const MAX_HISTORY_LIMIT: i32 = 100;
+
+impl MessagesManager {
+    /// Returns messages of a dialog history.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Dialog of the messages
+    /// * `from_message_id` - Server ID of the message to start from, or 0
+    ///   to start from the last message
+    /// * `offset` - Number of newer messages to return before
+    ///   `from_message_id`, negated; from `-limit + 1` to 0
+    /// * `limit` - Maximum number of messages to return (1-100)
+    /// * `only_local` - Return only messages available without a request
+    ///
+    /// # Returns
+    ///
+    /// The messages from the newest to the oldest. Fewer than `limit`
+    /// messages are returned at the start of the history, and if the
+    /// messages are only local.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the arguments are invalid, or if the history had
+    /// to be requested and the request failed.
+    pub async fn get_chat_history(
+        &self,
+        dialog_id: DialogId,
+        from_message_id: MessageId,
+        offset: i32,
+        limit: i32,
+        only_local: bool,
+    ) -> Result<Vec<Message>, MessagesManagerError> {
+        debug!(
+            "Getting history of {:?} from {:?} with offset {} and limit {}",
+            dialog_id, from_message_id, offset, limit
+        );
+
+        if limit <= 0 || limit > MAX_HISTORY_LIMIT {
+            return Err(MessagesManagerError::Generic(format!(
+                "Parameter limit must be between 1 and {}",
+                MAX_HISTORY_LIMIT
+            )));
+        }
+        if offset > 0 || offset <= -limit {
+            return Err(MessagesManagerError::Generic(
+                "Parameter offset must be non-positive and greater than -limit".to_string(),
+            ));
+        }
+        if from_message_id != MessageId::default() && !from_message_id.is_server() {
+            return Err(MessageValidationError::InvalidMessageId(format!(
+                "{} isn't a server message",
+                from_message_id
+            ))
+            .into());
+        }
+
+        let history = self
+            .local
+            .lock()
+            .get_history(dialog_id, from_message_id, offset, limit);
+        if history.is_complete || only_local {
+            return Ok(history.messages);
+        }
+
+        let client = self.get_history_client()?;
+        let peer = InputPeer::from_dialog_id(dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+        let offset_id = if from_message_id.is_valid() {
+            from_message_id.get_server_id().saturating_add(1)
+        } else {
+            0
+        };
+        info!(
+            "Requesting history of {:?} from {} with offset {} and limit {}",
+            dialog_id, offset_id, offset, limit
+        );
+        let result = client
+            .get_history(GetHistoryRequest::new(peer, offset_id, offset, limit))
+            .await?;
+
+        // Messages that can't be converted are still counted, so that they
+        // are known to be missing
+        let server_message_ids: Vec<MessageId> = result
+            .messages
+            .iter()
+            .map(|data| MessageId::from_server_id(data.id))
+            .collect();
+        let messages = result
+            .messages
+            .into_iter()
+            .filter_map(|data| {
+                let message_id = data.id;
+                Self::convert_message_data(data)
+                    .map_err(|error| {
+                        warn!("Failed to convert message {}: {}", message_id, error);
+                    })
+                    .ok()
+            })
+            .collect();
+        let older_count = usize::try_from(limit + offset).unwrap_or(0);
+
+        let mut local = self.local.lock();
+        local.add_history(
+            dialog_id,
+            from_message_id,
+            older_count,
+            &server_message_ids,
+            messages,
+        );
+        Ok(local
+            .get_history(dialog_id, from_message_id, offset, limit)
+            .messages)
+    }
+
+    /// Marks the incoming messages of a dialog up to `max_message_id` as
+    /// read on the server.
+    ///
+    /// Nothing is sent for a message not yet known to the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn read_history(
+        &self,
+        dialog_id: DialogId,
+        max_message_id: MessageId,
+    ) -> Result<(), MessagesManagerError> {
+        if !max_message_id.is_server() {
+            debug!(
+                "Not reading history of {:?} up to {:?}, which isn't a server message",
+                dialog_id, max_message_id
+            );
+            return Ok(());
+        }
+
+        let client = self.get_history_client()?;
+        let peer = InputPeer::from_dialog_id(dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+        info!(
+            "Reading history of {:?} up to {:?}",
+            dialog_id, max_message_id
+        );
+        let request = ReadHistoryRequest::new(peer, max_message_id.get_server_id());
+        if let Some(affected) = client.read_history(request).await? {
+            debug!(
+                "Read history of {:?}, pts: {}, pts_count: {}",
+                dialog_id, affected.pts, affected.pts_count
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the client requesting dialog history.
+    fn get_history_client(&self) -> Result<Arc<dyn MessageHistoryClient>, MessagesManagerError> {
+        self.history_client
+            .read()
+            .clone()
+            .ok_or_else(|| MessagesManagerError::Generic("History client is not set".to_string()))
+    }
+}
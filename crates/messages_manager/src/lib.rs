@@ -14,6 +14,13 @@
 //!
 //! - **tl_types** - TL schema types for MTProto communication
 //! - **network** - Network client integration
+//! - **tl_client** - Message queries sent to Telegram in the TL encoding
+//! - **media** - Media of outgoing messages and file uploads
+//! - **album** - Grouping of album messages
+//! - **local** - Local copy of the messages, backed by the message database
+//! - **operations** - Editing, forwarding and deleting messages
+//! - **history** - Dialog history, served locally and completed from the
+//!   server
 //! - **send** - Message send operations
 //! - **receive** - Message receive operations
 //!
@@ -24,9 +31,13 @@
 //!
 //! Phase 1 implements ~15% of TDLib's functionality:
 //! - Text message sending
+//! - Media message sending with file uploads
+//! - Album sending and grouping
 //! - Incoming message processing
 //! - Basic reply-to support
-//! - No media, no editing, no forwarding
+//! - Editing, forwarding and deleting messages, applied locally before the
+//!   server confirms them and rolled back if it rejects them
+//! - Dialog history with local caching and gap filling
 //!
 //! # Example
 //!
@@ -61,24 +72,49 @@
 
 pub mod tl_types;
 pub mod network;
-
+pub mod tl_client;
+pub mod media;
+pub mod album;
+mod local;
+mod operations;
+mod history;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use parking_lot::{Mutex, RwLock};
+
+use rustgram_file_reference_manager::{FileReferenceManager, FileSourceReloader};
 use rustgram_types::{DialogId, MessageId};
 use rustgram_message_types::{Message, MessageValidationError};
-use tracing::{debug, error, info, warn};
+use rustgram_storage::MessageDb;
+use tracing::{debug, info, warn};
 
 pub use tl_types::{
-    constructors, InputPeer, MessageData, MessageEntity, MessageFwdHeader, MessageReplyHeader,
-    Peer, SendMessageRequest, SendMessageResult, TlSerializationError, Update, UpdateDeleteMessages,
-    UpdateEditMessage, UpdateNewMessage, UpdateReadHistory, UpdateShortChatMessage,
-    UpdateShortMessage, Updates, User, Chat,
+    constructors, AffectedMessages, DeleteMessagesRequest, DocumentAttribute, EditMessageRequest,
+    ForwardMessagesRequest, GetHistoryRequest, InputFile, InputGeoPoint, InputMedia, InputPeer,
+    InputSingleMedia, MessageData, MessageEntity, MessageFwdHeader, MessageMedia,
+    MessageReplyHeader, Messages, Peer, PollAnswer, PollData, ReadHistoryRequest,
+    SaveFilePartRequest, SendMediaRequest,
+    SendMessageRequest, SendMessageResult, SendMultiMediaRequest, TlSerializationError, Update, UpdateDeleteMessages, UpdateEditMessage, UpdateMessageId,
+    UpdateNewMessage, UpdateReadHistory, UpdateShortChatMessage, UpdateShortMessage, Updates,
+    User, Chat,
 };
 
 pub use network::{
-    MessageNetworkClient, MessageNetworkConfig, MessageUpdateCallback, ProcessUpdateError,
-    SendMessageNetworkError,
+    MediaSendClient, MessageEditClient, MessageHistoryClient, MessageNetworkClient, MessageNetworkConfig, MessageUpdateCallback,
+    ProcessUpdateError, SendMessageNetworkError,
+};
+
+pub use tl_client::NetworkMessagesClient;
+
+pub use album::{group_messages_by_album, MessageGroup};
+pub use operations::MAX_FORWARDED_MESSAGES;
+pub use history::MAX_HISTORY_LIMIT;
+pub use media::{
+    InputMessageMedia, InputPoll, LocalFile, RemoteFile, MAX_ALBUM_SIZE, MIN_ALBUM_SIZE,
 };
 
 // ============================================================================
@@ -112,11 +148,26 @@ pub enum MessagesManagerError {
     #[error("Message not found: {0:?}, {1:?}")]
     MessageNotFound(DialogId, MessageId),
 
+    /// File upload error
+    #[error("Upload error: {0}")]
+    Upload(String),
+
     /// Generic error
     #[error("{0}")]
     Generic(String),
 }
 
+impl MessagesManagerError {
+    /// Returns the delay after which the failed request can be repeated, if
+    /// the server limited the rate of requests.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Network(error) => error.retry_after(),
+            _ => None,
+        }
+    }
+}
+
 // ============================================================================
 // MessagesManager
 // ============================================================================
@@ -131,6 +182,41 @@ pub struct MessagesManager {
 
     /// Configuration
     config: MessagesManagerConfig,
+
+    /// Client sending uploads and media messages
+    media_client: RwLock<Option<Arc<dyn MediaSendClient>>>,
+
+    /// Client editing, forwarding and deleting messages
+    edit_client: RwLock<Option<Arc<dyn MessageEditClient>>>,
+
+    /// Client requesting dialog history
+    history_client: RwLock<Option<Arc<dyn MessageHistoryClient>>>,
+
+    /// File references and the reloader of file sources, used to repair
+    /// the references of media sent again
+    file_reference_repair: RwLock<Option<(FileReferenceManager, Arc<dyn FileSourceReloader>)>>,
+
+    /// Messages known locally
+    local: Mutex<local::LocalMessages>,
+
+    /// Messages being sent, by random ID
+    pending_sends: Mutex<HashMap<i64, PendingSend>>,
+
+    /// Server IDs of sent messages, by dialog and temporary ID
+    sent_message_ids: Mutex<HashMap<(DialogId, MessageId), MessageId>>,
+
+    /// Local part of the last temporary message ID
+    last_yet_unsent_id: AtomicI64,
+}
+
+/// Message sent, but not yet acknowledged by `updateMessageID`.
+#[derive(Debug, Clone, Copy)]
+struct PendingSend {
+    /// Dialog of the message
+    dialog_id: DialogId,
+
+    /// Temporary message ID
+    message_id: MessageId,
 }
 
 /// Configuration for MessagesManager.
@@ -139,6 +225,9 @@ pub struct MessagesManagerConfig {
     /// Maximum message length
     pub max_message_length: usize,
 
+    /// Maximum caption length of media messages
+    pub max_caption_length: usize,
+
     /// Enable automatic deduplication
     pub enable_dedup: bool,
 
@@ -150,6 +239,7 @@ impl Default for MessagesManagerConfig {
     fn default() -> Self {
         Self {
             max_message_length: 4096,
+            max_caption_length: 1024,
             enable_dedup: true,
             cleanup_interval: Duration::from_secs(60),
         }
@@ -170,9 +260,60 @@ impl MessagesManager {
         Self {
             network_client,
             config,
+            media_client: RwLock::new(None),
+            edit_client: RwLock::new(None),
+            history_client: RwLock::new(None),
+            file_reference_repair: RwLock::new(None),
+            local: Mutex::new(local::LocalMessages::default()),
+            pending_sends: Mutex::new(HashMap::new()),
+            sent_message_ids: Mutex::new(HashMap::new()),
+            last_yet_unsent_id: AtomicI64::new(0),
         }
     }
 
+    /// Sets the client used to upload files and send media messages.
+    pub fn set_media_client(&self, client: Arc<dyn MediaSendClient>) {
+        *self.media_client.write() = Some(client);
+    }
+
+    /// Sets the client used to edit, forward and delete messages.
+    pub fn set_edit_client(&self, client: Arc<dyn MessageEditClient>) {
+        *self.edit_client.write() = Some(client);
+    }
+
+    /// Sets the client used to request dialog history.
+    pub fn set_history_client(&self, client: Arc<dyn MessageHistoryClient>) {
+        *self.history_client.write() = Some(client);
+    }
+
+    /// Sets the file references and the reloader used to repair expired
+    /// references of media sent again.
+    ///
+    /// When `messages.sendMedia` fails with a `FILE_REFERENCE_*` error, the
+    /// sources of the file are re-fetched and the media is sent once more
+    /// with the reference recorded in `file_references`.
+    pub fn set_file_reference_repair(
+        &self,
+        file_references: FileReferenceManager,
+        reloader: Arc<dyn FileSourceReloader>,
+    ) {
+        *self.file_reference_repair.write() = Some((file_references, reloader));
+    }
+
+    /// Sets the database the messages are saved to.
+    ///
+    /// The database must be initialized. Messages already in memory are
+    /// not saved until they change.
+    pub fn set_message_db(&self, db: &MessageDb) {
+        self.local.lock().set_db(db);
+    }
+
+    /// Returns a message known locally, loading it from the database if
+    /// needed.
+    pub fn get_message(&self, dialog_id: DialogId, message_id: MessageId) -> Option<Message> {
+        self.local.lock().get_message(dialog_id, message_id)
+    }
+
     /// Sends a text message to a dialog.
     ///
     /// This is the main entry point for sending messages.
@@ -242,6 +383,344 @@ impl MessagesManager {
         Ok(result)
     }
 
+    /// Sends a message with media to a dialog.
+    ///
+    /// The file of the media, if any, is uploaded first. While the message is
+    /// being sent it has a temporary yet-unsent identifier, which is replaced
+    /// by the server identifier on `updateMessageID`; both identifiers are
+    /// reported through [`MessageUpdateCallback::on_message_send_succeeded`].
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Target dialog
+    /// * `media` - The media to send
+    /// * `reply_to` - Optional message ID to reply to
+    ///
+    /// # Returns
+    ///
+    /// The server message ID on success.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The media can't be sent or its caption is too long
+    /// - The file can't be read or uploaded
+    /// - Network operation fails; rate limit errors carry the delay after
+    ///   which the message can be sent again, see
+    ///   [`MessagesManagerError::retry_after`]
+    ///
+    /// The failure is also reported through
+    /// [`MessageUpdateCallback::on_message_send_failed`].
+    pub async fn send_media(
+        &self,
+        dialog_id: DialogId,
+        media: InputMessageMedia,
+        reply_to: Option<MessageId>,
+    ) -> Result<MessageId, MessagesManagerError> {
+        info!("Sending media message to dialog {:?}", dialog_id);
+
+        let client = self
+            .media_client
+            .read()
+            .clone()
+            .ok_or_else(|| MessagesManagerError::Generic("Media client is not set".to_string()))?;
+
+        self.check_caption(&media)?;
+        let input_peer = InputPeer::from_dialog_id(dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+
+        let random_id = media::generate_random_id();
+        let message_id = self.get_next_yet_unsent_message_id();
+        self.pending_sends.lock().insert(
+            random_id,
+            PendingSend {
+                dialog_id,
+                message_id,
+            },
+        );
+
+        let result = self
+            .do_send_media(client.as_ref(), input_peer, &media, random_id, reply_to)
+            .await
+            .and_then(|updates| {
+                // The message is sent even if an update of the response
+                // can't be applied
+                for update in updates.updates {
+                    if let Err(error) = self.process_update(update) {
+                        warn!("Failed to apply an update of sendMedia: {}", error);
+                    }
+                }
+                self.get_sent_message_id(dialog_id, message_id).ok_or_else(|| {
+                    MessagesManagerError::Generic(
+                        "The server didn't assign an ID to the message".to_string(),
+                    )
+                })
+            });
+
+        if let Err(error) = &result {
+            warn!("Failed to send media message {:?}: {}", message_id, error);
+            self.pending_sends.lock().remove(&random_id);
+            self.network_client
+                .notify(|callback| callback.on_message_send_failed(dialog_id, message_id, error));
+        }
+        result
+    }
+
+    /// Uploads the file of the media and sends `messages.sendMedia`.
+    async fn do_send_media(
+        &self,
+        client: &dyn MediaSendClient,
+        input_peer: InputPeer,
+        media: &InputMessageMedia,
+        random_id: i64,
+        reply_to: Option<MessageId>,
+    ) -> Result<Updates, MessagesManagerError> {
+        let input_file = match (media.file(), media.file_type()) {
+            (Some(file), Some(file_type)) => {
+                Some(media::upload_file(client, file, file_type).await?)
+            }
+            _ => None,
+        };
+        let input_media = media.get_input_media(input_file)?;
+
+        let caption = media.caption().cloned().unwrap_or_default();
+        let entities = media::get_input_message_entities(&caption);
+        let mut request =
+            SendMediaRequest::new(input_peer, input_media, caption.text().to_string(), random_id);
+        if !entities.is_empty() {
+            request = request.with_entities(entities);
+        }
+        if let Some(reply_msg_id) = reply_to {
+            request = request.with_reply_to(reply_msg_id.get_server_id());
+        }
+
+        let error = match client.send_media(request.clone()).await {
+            Ok(updates) => return Ok(updates),
+            Err(error) => error,
+        };
+        let remote_file = match media.remote_file() {
+            Some(remote_file) if error.is_file_reference_error() => remote_file,
+            _ => return Err(error.into()),
+        };
+        let Some((file_references, reloader)) = self.file_reference_repair.read().clone() else {
+            return Err(error.into());
+        };
+
+        // The file reference has expired: re-fetch the sources of the file
+        // and send the media once more with the repaired reference
+        info!(
+            "Repairing file reference of {:?}: {}",
+            remote_file.file_id, error
+        );
+        file_references
+            .repair_file_reference(remote_file.file_id, reloader.as_ref())
+            .await
+            .map_err(|repair_error| {
+                warn!("Failed to repair file reference: {}", repair_error);
+                MessagesManagerError::from(error)
+            })?;
+        let file_reference = file_references
+            .get_file_reference(remote_file.file_id)
+            .unwrap_or_else(|| remote_file.file_reference.clone());
+        request.media = media.get_remote_input_media(&file_reference)?;
+        Ok(client.send_media(request).await?)
+    }
+
+    /// Sends an album to a dialog.
+    ///
+    /// The files of all items are uploaded in parallel, then the items are
+    /// sent with a single `messages.sendMultiMedia`, so the server assigns
+    /// them a common `grouped_id`. Each item keeps its own caption with its
+    /// entities. As with [`Self::send_media`], every item has a temporary
+    /// identifier until the server acknowledges it.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Target dialog
+    /// * `items` - Items of the album, 2-10 photos and videos, documents or
+    ///   audio files
+    /// * `reply_to` - Optional message ID to reply to
+    ///
+    /// # Returns
+    ///
+    /// The server message IDs of the items in their order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The items can't be grouped into an album or a caption is too long
+    /// - A file can't be read or uploaded
+    /// - Network operation fails
+    ///
+    /// The failure is reported for every item through
+    /// [`MessageUpdateCallback::on_message_send_failed`].
+    pub async fn send_message_album(
+        &self,
+        dialog_id: DialogId,
+        items: Vec<InputMessageMedia>,
+        reply_to: Option<MessageId>,
+    ) -> Result<Vec<MessageId>, MessagesManagerError> {
+        info!(
+            "Sending album of {} items to dialog {:?}",
+            items.len(),
+            dialog_id
+        );
+
+        let client = self
+            .media_client
+            .read()
+            .clone()
+            .ok_or_else(|| MessagesManagerError::Generic("Media client is not set".to_string()))?;
+
+        media::check_album(&items)?;
+        for item in &items {
+            self.check_caption(item)?;
+        }
+        let input_peer = InputPeer::from_dialog_id(dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+
+        let sends: Vec<_> = items
+            .iter()
+            .map(|_| (media::generate_random_id(), self.get_next_yet_unsent_message_id()))
+            .collect();
+        {
+            let mut pending_sends = self.pending_sends.lock();
+            for &(random_id, message_id) in &sends {
+                pending_sends.insert(
+                    random_id,
+                    PendingSend {
+                        dialog_id,
+                        message_id,
+                    },
+                );
+            }
+        }
+
+        let result = self
+            .do_send_message_album(client, input_peer, &items, &sends, reply_to)
+            .await
+            .and_then(|updates| {
+                for update in updates.updates {
+                    if let Err(error) = self.process_update(update) {
+                        warn!("Failed to apply an update of sendMultiMedia: {}", error);
+                    }
+                }
+                sends
+                    .iter()
+                    .map(|&(_, message_id)| {
+                        self.get_sent_message_id(dialog_id, message_id).ok_or_else(|| {
+                            MessagesManagerError::Generic(
+                                "The server didn't assign an ID to an album item".to_string(),
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            });
+
+        if let Err(error) = &result {
+            warn!("Failed to send album to {:?}: {}", dialog_id, error);
+            let mut pending_sends = self.pending_sends.lock();
+            for (random_id, _) in &sends {
+                pending_sends.remove(random_id);
+            }
+            drop(pending_sends);
+            for &(_, message_id) in &sends {
+                self.network_client.notify(|callback| {
+                    callback.on_message_send_failed(dialog_id, message_id, error)
+                });
+            }
+        }
+        result
+    }
+
+    /// Uploads the files of the album in parallel and sends
+    /// `messages.sendMultiMedia`.
+    async fn do_send_message_album(
+        &self,
+        client: Arc<dyn MediaSendClient>,
+        input_peer: InputPeer,
+        items: &[InputMessageMedia],
+        sends: &[(i64, MessageId)],
+        reply_to: Option<MessageId>,
+    ) -> Result<Updates, MessagesManagerError> {
+        let mut uploads = tokio::task::JoinSet::new();
+        for (index, item) in items.iter().enumerate() {
+            if let (Some(file), Some(file_type)) = (item.file().cloned(), item.file_type()) {
+                let client = Arc::clone(&client);
+                uploads.spawn(async move {
+                    let input_file = media::upload_file(client.as_ref(), &file, file_type).await;
+                    (index, input_file)
+                });
+            }
+        }
+
+        let mut input_files: Vec<Option<InputFile>> = vec![None; items.len()];
+        while let Some(joined) = uploads.join_next().await {
+            let (index, input_file) = joined.map_err(|e| {
+                MessagesManagerError::Upload(format!("Upload task failed: {}", e))
+            })?;
+            // Dropping the set aborts the uploads still in progress
+            input_files[index] = Some(input_file?);
+        }
+
+        let multi_media = items
+            .iter()
+            .zip(input_files)
+            .zip(sends)
+            .map(|((item, input_file), &(random_id, _))| {
+                let caption = item.caption().cloned().unwrap_or_default();
+                let entities = media::get_input_message_entities(&caption);
+                Ok(InputSingleMedia {
+                    media: item.get_input_media(input_file)?,
+                    random_id,
+                    message: caption.text().to_string(),
+                    entities: (!entities.is_empty()).then_some(entities),
+                })
+            })
+            .collect::<Result<Vec<_>, MessagesManagerError>>()?;
+
+        let mut request = SendMultiMediaRequest::new(input_peer, multi_media);
+        if let Some(reply_msg_id) = reply_to {
+            request = request.with_reply_to(reply_msg_id.get_server_id());
+        }
+
+        Ok(client.send_multi_media(request).await?)
+    }
+
+    /// Checks the length of the caption of the media.
+    fn check_caption(&self, media: &InputMessageMedia) -> Result<(), MessagesManagerError> {
+        match media.caption() {
+            Some(caption) if caption.text().len() > self.config.max_caption_length => {
+                Err(MessagesManagerError::Validation(
+                    MessageValidationError::InvalidContent(format!(
+                        "Caption too long: {} > {}",
+                        caption.text().len(),
+                        self.config.max_caption_length
+                    )),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the server ID of a sent message by its temporary ID.
+    pub fn get_sent_message_id(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+    ) -> Option<MessageId> {
+        self.sent_message_ids
+            .lock()
+            .get(&(dialog_id, message_id))
+            .copied()
+    }
+
+    /// Returns a new temporary ID for a message being sent.
+    fn get_next_yet_unsent_message_id(&self) -> MessageId {
+        let local_id = self.last_yet_unsent_id.fetch_add(1, Ordering::Relaxed) + 1;
+        MessageId((local_id << 3) | 1)
+    }
+
     /// Processes an incoming update from the server.
     ///
     /// This method handles:
@@ -271,6 +750,9 @@ impl MessagesManager {
             Update::ReadHistory(read_msg) => {
                 self.process_read_history(read_msg)?;
             }
+            Update::MessageId(message_id) => {
+                self.process_message_id(message_id);
+            }
             _ => {
                 debug!("Ignoring unhandled update type");
             }
@@ -285,22 +767,97 @@ impl MessagesManager {
         // Convert TL MessageData to internal Message type
         let message = Self::convert_message_data(update.message)?;
 
-        // In production, store in database and trigger callbacks
         info!("New message received: id={}", message.id);
+        self.local.lock().add_new_message(message.clone());
+        self.network_client
+            .notify(|callback| callback.on_new_message(message));
 
         Ok(())
     }
 
+    /// Processes a message ID update, assigning the server ID to a sent
+    /// message.
+    fn process_message_id(&self, update: UpdateMessageId) {
+        let Some(pending) = self.pending_sends.lock().remove(&update.random_id) else {
+            debug!("Ignoring updateMessageID for unknown random ID {}", update.random_id);
+            return;
+        };
+
+        let new_message_id = MessageId::from_server_id(update.id);
+        info!(
+            "Message {:?} in {:?} was sent as {:?}",
+            pending.message_id, pending.dialog_id, new_message_id
+        );
+        self.sent_message_ids
+            .lock()
+            .insert((pending.dialog_id, pending.message_id), new_message_id);
+        // The message with the server ID comes with the next update
+        self.local
+            .lock()
+            .delete_message(pending.dialog_id, pending.message_id);
+        self.network_client.notify(|callback| {
+            callback.on_message_send_succeeded(
+                pending.dialog_id,
+                pending.message_id,
+                new_message_id,
+            );
+        });
+    }
+
     /// Processes a message edit update.
     fn process_edit_message(&self, update: UpdateEditMessage) -> Result<(), MessagesManagerError> {
         debug!("Processing message edit {}", update.message.id);
 
-        // In production, update message in database
-        info!("Message {} was edited", update.message.id);
+        let message = Self::convert_message_data(update.message)?;
+        info!("Message {} was edited", message.id);
+        self.local.lock().add_message(message.clone());
+        self.network_client
+            .notify(|callback| callback.on_message_edited(message));
 
         Ok(())
     }
 
+    /// Forgets the local history of a channel whose difference was too long.
+    ///
+    /// The missed updates are lost, so the locally known messages may be
+    /// outdated or no longer contiguous; the history is loaded again from the
+    /// server when it is requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - The dialog of the channel
+    pub fn on_channel_difference_too_long(&self, dialog_id: DialogId) {
+        info!("Dropping local history of {:?}", dialog_id);
+        let deleted_ids = self.local.lock().delete_dialog_history(dialog_id);
+        if !deleted_ids.is_empty() {
+            self.network_client
+                .notify(|callback| callback.on_messages_deleted(dialog_id, deleted_ids));
+        }
+    }
+
+    /// Deletes messages of a channel, after `updateDeleteChannelMessages`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - The dialog of the channel
+    /// * `message_ids` - The deleted messages
+    pub fn on_delete_channel_messages(&self, dialog_id: DialogId, message_ids: &[MessageId]) {
+        let mut deleted_ids = Vec::new();
+        {
+            let mut local = self.local.lock();
+            for &message_id in message_ids {
+                info!("Message {:?} of {:?} was deleted", message_id, dialog_id);
+                if local.delete_message(dialog_id, message_id).is_some() {
+                    deleted_ids.push(message_id);
+                }
+            }
+        }
+        if !deleted_ids.is_empty() {
+            self.network_client
+                .notify(|callback| callback.on_messages_deleted(dialog_id, deleted_ids));
+        }
+    }
+
     /// Processes a delete messages update.
     fn process_delete_messages(
         &self,
@@ -308,11 +865,23 @@ impl MessagesManager {
     ) -> Result<(), MessagesManagerError> {
         debug!("Processing delete of {} messages", update.messages.len());
 
-        // In production, delete from database
-        for msg_id in &update.messages {
-            info!("Message {} was deleted", msg_id);
+        let mut deleted: HashMap<DialogId, Vec<MessageId>> = HashMap::new();
+        {
+            let mut local = self.local.lock();
+            for &server_id in &update.messages {
+                info!("Message {} was deleted", server_id);
+                let message_id = MessageId::from_server_id(server_id);
+                if let Some(dialog_id) = local.find_message_dialog(message_id) {
+                    local.delete_message(dialog_id, message_id);
+                    deleted.entry(dialog_id).or_default().push(message_id);
+                }
+            }
         }
 
+        for (dialog_id, message_ids) in deleted {
+            self.network_client
+                .notify(|callback| callback.on_messages_deleted(dialog_id, message_ids));
+        }
         Ok(())
     }
 
@@ -330,8 +899,12 @@ impl MessagesManager {
     pub fn convert_message_data(data: MessageData) -> Result<Message, MessagesManagerError> {
         use rustgram_formatted_text::FormattedText;
 
-        // Create FormattedText
+        // Create FormattedText; for media messages it is the caption
         let formatted = FormattedText::new(data.message.as_str());
+        let content = data
+            .media
+            .as_ref()
+            .and_then(|media| media::get_message_content(media, formatted.clone()));
 
         // Create DialogIds from i64 values
         let dialog_id = DialogId::from_encoded(data.dialog_id)
@@ -344,13 +917,16 @@ impl MessagesManager {
         let message_id = MessageId::from_server_id(data.id);
 
         // Create Message
-        let message = Message::new_with_date(
-            message_id,
-            dialog_id,
-            sender_id,
-            data.date,
-            formatted,
-        ).map_err(MessagesManagerError::Validation)?;
+        let mut message = match content {
+            Some(content) => {
+                Message::new_with_content(message_id, dialog_id, sender_id, data.date, content)
+            }
+            None => Message::new_with_date(message_id, dialog_id, sender_id, data.date, formatted),
+        }
+        .map_err(MessagesManagerError::Validation)?;
+        message.media_album_id = data.grouped_id.unwrap_or(0);
+        message.edit_date = data.edit_date;
+        message.reply_markup = data.reply_markup;
 
         Ok(message)
     }
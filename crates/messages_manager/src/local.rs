@@ -0,0 +1,570 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Local copy of the messages.
+//!
+//! Messages known to the manager are kept in memory, ordered per dialog with
+//! [`OrderedMessages`], and saved to the message database if one is set.
+//! Every change returns the previous version of the message, so that a
+//! change applied before the server confirmed it can be rolled back.
+//!
+//! Server messages known to be contiguous are attached to their previous
+//! message, and the flag is saved with them. History is served locally as
+//! long as the walk over the attached messages doesn't hit a hole.
+//!
+//! # TDLib Alignment
+//!
+//! Based on the handling of `Dialog::ordered_messages` and `MessageDb` in
+//! `td/telegram/MessagesManager.cpp`. Only server messages are saved to the
+//! database and ordered; messages being sent live in memory until they get
+//! their server identifier.
+//!
+//! Like TDLib, messages loaded from the database one by one aren't trusted
+//! to be attached: their previous message may not be in memory yet.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bytes::Bytes;
+use rustgram_message_content::MessageContent;
+use rustgram_message_types::Message;
+use rustgram_ordered_messages::OrderedMessages;
+use rustgram_storage::message::sync::AddMessageParams;
+use rustgram_storage::message::MessageDbSync;
+use rustgram_storage::{MessageDb, StorageError};
+use rustgram_types::{DialogId, MessageId};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Message as saved to the database.
+#[derive(Deserialize)]
+struct StoredMessage {
+    /// The message
+    message: Message,
+
+    /// Whether the previous message of the dialog is known
+    #[serde(default)]
+    have_previous: bool,
+}
+
+/// Message being saved to the database.
+#[derive(Serialize)]
+struct StoredMessageRef<'a> {
+    /// The message
+    message: &'a Message,
+
+    /// Whether the previous message of the dialog is known
+    have_previous: bool,
+}
+
+/// Messages of a dialog history found locally.
+#[derive(Debug, Default)]
+pub(crate) struct LocalHistory {
+    /// Found messages, from the newest to the oldest
+    pub(crate) messages: Vec<Message>,
+
+    /// Whether no message of the asked range is missing
+    pub(crate) is_complete: bool,
+}
+
+/// Messages of a dialog loaded into memory.
+#[derive(Debug, Default)]
+struct LocalDialog {
+    /// Identifiers of the messages in their order
+    ordered_messages: OrderedMessages,
+
+    /// Messages by identifier
+    messages: HashMap<MessageId, Message>,
+
+    /// Last message of the dialog on the server, if known
+    last_message_id: Option<MessageId>,
+}
+
+impl LocalDialog {
+    /// Returns the newest message not newer than `from_message_id`, if no
+    /// message between them is missing.
+    fn find_history_anchor(&self, from_message_id: MessageId) -> Option<MessageId> {
+        if let Some(last_message_id) = self.last_message_id {
+            if from_message_id >= last_message_id {
+                return Some(last_message_id);
+            }
+        }
+        if self.ordered_messages.contains(from_message_id) {
+            return Some(from_message_id);
+        }
+
+        let next = self.ordered_messages.get_next(from_message_id)?;
+        if !self.ordered_messages.have_previous(next) {
+            return None;
+        }
+        self.ordered_messages.get_previous(from_message_id)
+    }
+}
+
+/// Messages of all dialogs, backed by the message database.
+#[derive(Default)]
+pub(crate) struct LocalMessages {
+    /// Dialogs with messages in memory
+    dialogs: HashMap<DialogId, LocalDialog>,
+
+    /// Message database, if messages are persisted
+    db: Option<MessageDbSync>,
+}
+
+impl LocalMessages {
+    /// Sets the database the messages are saved to.
+    pub(crate) fn set_db(&mut self, db: &MessageDb) {
+        self.db = Some(db.sync());
+    }
+
+    /// Returns a message, loading it from the database if needed.
+    pub(crate) fn get_message(
+        &mut self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+    ) -> Option<Message> {
+        if let Some(message) = self
+            .dialogs
+            .get(&dialog_id)
+            .and_then(|dialog| dialog.messages.get(&message_id))
+        {
+            return Some(message.clone());
+        }
+
+        let message = self.load_message(dialog_id, message_id)?;
+        self.insert(message.clone());
+        Some(message)
+    }
+
+    /// Adds a message or replaces its previous version.
+    ///
+    /// Returns the previous version of the message, if any.
+    pub(crate) fn add_message(&mut self, message: Message) -> Option<Message> {
+        let old_message = self.get_message(message.dialog_id, message.id);
+        self.insert(message.clone());
+        self.save_message(&message);
+        old_message
+    }
+
+    /// Adds a new message of a dialog, which directly follows the last
+    /// message of the dialog.
+    ///
+    /// Returns the previous version of the message, if any.
+    pub(crate) fn add_new_message(&mut self, message: Message) -> Option<Message> {
+        let old_message = self.get_message(message.dialog_id, message.id);
+        let dialog = self.dialogs.entry(message.dialog_id).or_default();
+        if message.id.is_server() {
+            let old_last_message_id = dialog.last_message_id.unwrap_or_default();
+            dialog.ordered_messages.insert(
+                message.id,
+                true,
+                old_last_message_id,
+                "add_new_message",
+            );
+            if dialog.last_message_id.map_or(true, |last| last < message.id) {
+                dialog.last_message_id = Some(message.id);
+            }
+        }
+        dialog.messages.insert(message.id, message.clone());
+        self.save_message(&message);
+        old_message
+    }
+
+    /// Adds a page of a dialog history received from the server.
+    ///
+    /// `server_message_ids` are the identifiers of all messages of the
+    /// page, and `messages` those of them that could be added. The page
+    /// holds all messages between the oldest and the newest of
+    /// `server_message_ids`, but a message that wasn't added leaves a hole
+    /// in it. If the page holds fewer than `older_count` messages not newer
+    /// than `from_message_id`, the oldest of them is the first message of the
+    /// dialog; if `from_message_id` is invalid, the page was counted from
+    /// the last message.
+    pub(crate) fn add_history(
+        &mut self,
+        dialog_id: DialogId,
+        from_message_id: MessageId,
+        older_count: usize,
+        server_message_ids: &[MessageId],
+        messages: Vec<Message>,
+    ) {
+        let message_ids: BTreeSet<MessageId> = messages
+            .iter()
+            .filter(|message| message.dialog_id == dialog_id && message.id.is_server())
+            .map(|message| message.id)
+            .collect();
+        let (Some(&first), Some(&last)) = (message_ids.first(), message_ids.last()) else {
+            return;
+        };
+        let server_message_ids: BTreeSet<MessageId> = server_message_ids
+            .iter()
+            .copied()
+            .filter(|message_id| message_id.is_server())
+            .chain(message_ids.iter().copied())
+            .collect();
+
+        // Known messages of the page keep their saved attachment
+        self.load_history(
+            dialog_id,
+            last.get_server_id().saturating_add(1),
+            message_ids.len() + 1,
+        );
+        for message in messages {
+            if message_ids.contains(&message.id) {
+                self.insert(message);
+            } else {
+                warn!("Ignoring {:?} in history of {:?}", message.id, dialog_id);
+            }
+        }
+
+        // Only the runs of added messages are contiguous
+        let dialog = self.dialogs.entry(dialog_id).or_default();
+        let mut changed_ids = message_ids.clone();
+        let mut run: Option<(MessageId, MessageId)> = None;
+        for &message_id in &server_message_ids {
+            if message_ids.contains(&message_id) {
+                let run_first = run.map_or(message_id, |(run_first, _)| run_first);
+                run = Some((run_first, message_id));
+            } else if let Some((run_first, run_last)) = run.take() {
+                changed_ids.extend(dialog.ordered_messages.attach(run_first, run_last));
+            }
+        }
+        if let Some((run_first, run_last)) = run {
+            changed_ids.extend(dialog.ordered_messages.attach(run_first, run_last));
+        }
+
+        let from_end = !from_message_id.is_valid();
+        let found_older_count = server_message_ids
+            .iter()
+            .filter(|&&message_id| from_end || message_id <= from_message_id)
+            .count();
+        if found_older_count < older_count && server_message_ids.first() == Some(&first) {
+            dialog.ordered_messages.set_have_previous(first, true);
+        }
+        if from_end && dialog.last_message_id.map_or(true, |old_last| old_last < last) {
+            dialog.last_message_id = Some(last);
+        }
+
+        let changed_messages: Vec<Message> = changed_ids
+            .iter()
+            .filter_map(|message_id| dialog.messages.get(message_id).cloned())
+            .collect();
+        for message in &changed_messages {
+            self.save_message(message);
+        }
+    }
+
+    /// Returns messages of a dialog history known locally.
+    ///
+    /// Returns `limit` messages, starting `-offset` messages after
+    /// `from_message_id`, or after the last message if `from_message_id` is
+    /// invalid, from the newest to the oldest. The messages end where the
+    /// first missing message would be.
+    pub(crate) fn get_history(
+        &mut self,
+        dialog_id: DialogId,
+        from_message_id: MessageId,
+        offset: i32,
+        limit: i32,
+    ) -> LocalHistory {
+        let newer_count = usize::try_from(-offset).unwrap_or(0);
+        let older_count = usize::try_from(limit.saturating_add(offset)).unwrap_or(0);
+        let from_server_id = if from_message_id.is_valid() {
+            from_message_id.get_server_id().saturating_add(1)
+        } else {
+            0
+        };
+        self.load_history(dialog_id, from_server_id, older_count + 1);
+
+        let Some(dialog) = self.dialogs.get(&dialog_id) else {
+            return LocalHistory::default();
+        };
+        let anchor = if from_message_id.is_valid() {
+            dialog.find_history_anchor(from_message_id)
+        } else {
+            dialog.last_message_id
+        };
+        let Some(anchor) = anchor else {
+            return LocalHistory::default();
+        };
+
+        let ordered_messages = &dialog.ordered_messages;
+        let mut is_complete = true;
+
+        // Newer messages are walked in memory only: the database can't
+        // return them in ascending order
+        let mut message_ids = Vec::new();
+        let mut current = anchor;
+        while message_ids.len() < newer_count && Some(current) != dialog.last_message_id {
+            match ordered_messages.get_next(current) {
+                Some(next) if ordered_messages.have_previous(next) => {
+                    message_ids.push(next);
+                    current = next;
+                }
+                _ => {
+                    is_complete = false;
+                    break;
+                }
+            }
+        }
+        message_ids.reverse();
+
+        let mut found_older_count = 1;
+        message_ids.push(anchor);
+        let mut current = anchor;
+        while found_older_count < older_count {
+            if !ordered_messages.have_previous(current) {
+                is_complete = false;
+                break;
+            }
+            // The first message of the dialog has no previous message
+            let Some(previous) = ordered_messages.get_previous(current) else {
+                break;
+            };
+            message_ids.push(previous);
+            found_older_count += 1;
+            current = previous;
+        }
+
+        LocalHistory {
+            messages: message_ids
+                .iter()
+                .filter_map(|message_id| dialog.messages.get(message_id).cloned())
+                .collect(),
+            is_complete,
+        }
+    }
+
+    /// Deletes a message.
+    ///
+    /// Returns the deleted message, if it was known.
+    pub(crate) fn delete_message(
+        &mut self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+    ) -> Option<Message> {
+        let old_message = self.get_message(dialog_id, message_id);
+        let mut changed_message = None;
+        if let Some(dialog) = self.dialogs.get_mut(&dialog_id) {
+            let ordered_messages = &mut dialog.ordered_messages;
+            if dialog.last_message_id == Some(message_id) {
+                dialog.last_message_id = if ordered_messages.have_previous(message_id) {
+                    ordered_messages.get_previous(message_id)
+                } else {
+                    None
+                };
+            }
+
+            let next = ordered_messages.get_next(message_id);
+            let had_previous = next.map(|next| ordered_messages.have_previous(next));
+            ordered_messages.erase(message_id, false, "delete_message");
+            if let Some(next) = next {
+                if had_previous != Some(ordered_messages.have_previous(next)) {
+                    changed_message = dialog.messages.get(&next).cloned();
+                }
+            }
+            dialog.messages.remove(&message_id);
+        }
+        if let Some(message) = changed_message {
+            self.save_message(&message);
+        }
+
+        if let (Some(db), true) = (self.db.as_mut(), message_id.is_server()) {
+            if let Err(error) =
+                db.delete_message(dialog_id.to_encoded(), message_id.get_server_id())
+            {
+                warn!(
+                    "Failed to delete {:?} from the database: {}",
+                    message_id, error
+                );
+            }
+        }
+        old_message
+    }
+
+    /// Forgets the server messages of a dialog, in memory and in the
+    /// database.
+    ///
+    /// Messages being sent are kept. Returns the forgotten messages that
+    /// were in memory.
+    pub(crate) fn delete_dialog_history(&mut self, dialog_id: DialogId) -> Vec<MessageId> {
+        let mut deleted_ids = Vec::new();
+        if let Some(dialog) = self.dialogs.get_mut(&dialog_id) {
+            deleted_ids = dialog
+                .messages
+                .keys()
+                .copied()
+                .filter(|message_id| message_id.is_server())
+                .collect();
+            deleted_ids.sort();
+            dialog
+                .messages
+                .retain(|message_id, _| !message_id.is_server());
+            dialog.ordered_messages = OrderedMessages::default();
+            dialog.last_message_id = None;
+        }
+
+        if let Some(db) = self.db.as_mut() {
+            if let Err(error) = db.delete_all_dialog_messages(dialog_id.to_encoded()) {
+                warn!(
+                    "Failed to delete history of {:?} from the database: {}",
+                    dialog_id, error
+                );
+            }
+        }
+        deleted_ids
+    }
+
+    /// Returns the dialog of a message loaded into memory.
+    ///
+    /// Identifiers of messages outside of channels are unique, so
+    /// `updateDeleteMessages` carries no dialog; messages of channels are
+    /// never matched.
+    pub(crate) fn find_message_dialog(&self, message_id: MessageId) -> Option<DialogId> {
+        self.dialogs
+            .iter()
+            .find(|(dialog_id, dialog)| {
+                !matches!(dialog_id, DialogId::Channel(_))
+                    && dialog.ordered_messages.contains(message_id)
+            })
+            .map(|(dialog_id, _)| *dialog_id)
+    }
+
+    /// Puts a message into memory.
+    fn insert(&mut self, message: Message) {
+        let dialog = self.dialogs.entry(message.dialog_id).or_default();
+        if message.id.is_server() {
+            let last_message_id = dialog.ordered_messages.get_last_message_id();
+            dialog
+                .ordered_messages
+                .insert(message.id, false, last_message_id, "add_message");
+        }
+        dialog.messages.insert(message.id, message);
+    }
+
+    /// Loads up to `limit` messages older than `from_server_id` from the
+    /// database, or the last messages if `from_server_id` is 0.
+    ///
+    /// The oldest message of a full batch isn't attached: its previous
+    /// message may be in the database, but not in memory.
+    fn load_history(&mut self, dialog_id: DialogId, from_server_id: i32, limit: usize) {
+        let Some(db) = self.db.as_mut() else {
+            return;
+        };
+
+        let rows = match db.get_dialog_messages(
+            dialog_id.to_encoded(),
+            from_server_id,
+            0,
+            i32::try_from(limit).unwrap_or(i32::MAX),
+            None,
+        ) {
+            Ok(rows) => rows,
+            Err(error) => {
+                warn!(
+                    "Failed to load history of {:?} from the database: {}",
+                    dialog_id, error
+                );
+                return;
+            }
+        };
+
+        let is_full = rows.len() == limit;
+        let row_count = rows.len();
+        let dialog = self.dialogs.entry(dialog_id).or_default();
+        for (i, row) in rows.into_iter().enumerate() {
+            let Some(stored) = parse_stored_message(&row.content) else {
+                continue;
+            };
+            let message_id = stored.message.id;
+            if !dialog.messages.contains_key(&message_id) {
+                let last_message_id = dialog.ordered_messages.get_last_message_id();
+                dialog
+                    .ordered_messages
+                    .insert(message_id, false, last_message_id, "load_history");
+                dialog.messages.insert(message_id, stored.message);
+            }
+            if stored.have_previous && !(is_full && i + 1 == row_count) {
+                dialog.ordered_messages.set_have_previous(message_id, true);
+            }
+        }
+    }
+
+    /// Saves a server message to the database.
+    fn save_message(&mut self, message: &Message) {
+        let Some(db) = self.db.as_mut() else {
+            return;
+        };
+        if !message.id.is_server() {
+            return;
+        }
+
+        let have_previous = self
+            .dialogs
+            .get(&message.dialog_id)
+            .is_some_and(|dialog| dialog.ordered_messages.have_previous(message.id));
+        let stored = StoredMessageRef {
+            message,
+            have_previous,
+        };
+        let content = match serde_json::to_vec(&stored) {
+            Ok(content) => content,
+            Err(error) => {
+                warn!("Failed to serialize {:?}: {}", message.id, error);
+                return;
+            }
+        };
+        let mut params = AddMessageParams::new(
+            message.dialog_id.to_encoded(),
+            message.id.get_server_id(),
+            message.sender_id.to_encoded(),
+            message.date,
+            Bytes::from(content),
+        );
+        if let Some(text) = get_message_text(&message.content) {
+            params = params.with_text(text.to_string());
+        }
+
+        if let Err(error) = db.add_message(params) {
+            warn!("Failed to save {:?} to the database: {}", message.id, error);
+        }
+    }
+
+    /// Loads a server message from the database.
+    fn load_message(&mut self, dialog_id: DialogId, message_id: MessageId) -> Option<Message> {
+        let db = self.db.as_mut()?;
+        if !message_id.is_server() {
+            return None;
+        }
+
+        let row = match db.get_message(dialog_id.to_encoded(), message_id.get_server_id()) {
+            Ok(row) => row,
+            Err(StorageError::NotFound(_)) => return None,
+            Err(error) => {
+                warn!(
+                    "Failed to load {:?} from the database: {}",
+                    message_id, error
+                );
+                return None;
+            }
+        };
+        parse_stored_message(&row.content).map(|stored| stored.message)
+    }
+}
+
+/// Parses a message saved to the database.
+fn parse_stored_message(content: &[u8]) -> Option<StoredMessage> {
+    serde_json::from_slice(content)
+        .map_err(|error| warn!("Failed to parse a message from the database: {}", error))
+        .ok()
+}
+
+/// Returns the searchable text of a message: its text or caption.
+fn get_message_text(content: &MessageContent) -> Option<&str> {
+    content
+        .as_text()
+        .map(|text| &text.text)
+        .or_else(|| content.caption())
+        .map(|text| text.text())
+        .filter(|text| !text.is_empty())
+}
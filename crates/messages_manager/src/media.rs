@@ -0,0 +1,1030 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Media messages.
+//!
+//! This module describes the media of an outgoing message, builds the
+//! `inputMedia*` objects sent with `messages.sendMedia` from its
+//! [`MessageContent`] and uploads the files the media references. It also
+//! checks which media can be grouped into an album and converts the media
+//! of incoming messages back to [`MessageContent`].
+//!
+//! # TDLib Alignment
+//!
+//! Based on `get_input_media`, `is_allowed_media_group_content` and
+//! `is_homogenous_media_group_content` from `td/telegram/MessageContent.cpp`
+//! and on `td/telegram/files/FileUploader.cpp`.
+
+#![warn(missing_docs)]
+#![warn(clippy::all)]
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use rustgram_file_type::FileType;
+use rustgram_file_uploader::{FileUploader, FileUploaderConfig};
+use rustgram_formatted_text::{entity_type, FormattedText};
+use rustgram_message_content::{
+    FileId, MessageAnimation, MessageAudio, MessageContact, MessageContent, MessageDocument,
+    MessageLocation, MessagePhoto, MessagePoll, MessageSticker, MessageVenue, MessageVideo,
+    MessageVoiceNote, PhotoData, PollId,
+};
+use rustgram_message_types::MessageValidationError;
+use tracing::debug;
+
+use crate::network::MediaSendClient;
+use crate::tl_types::{
+    DocumentAttribute, InputFile, InputGeoPoint, InputMedia, MessageEntity, MessageMedia,
+    PollAnswer, PollData, SaveFilePartRequest,
+};
+use crate::MessagesManagerError;
+
+/// Minimum number of items in an album.
+pub const MIN_ALBUM_SIZE: usize = 2;
+
+/// Maximum number of items in an album.
+pub const MAX_ALBUM_SIZE: usize = 10;
+
+/// Local file attached to an outgoing message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFile {
+    /// Path to the file
+    pub path: String,
+
+    /// File name shown to the recipients
+    pub name: String,
+
+    /// MIME type of the file
+    pub mime_type: String,
+
+    /// File size in bytes
+    pub size: i64,
+}
+
+impl LocalFile {
+    /// Creates a local file, named after the last component of its path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file
+    /// * `size` - File size in bytes
+    pub fn new(path: impl Into<String>, size: i64) -> Self {
+        let path = path.into();
+        let name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            path,
+            name,
+            mime_type: "application/octet-stream".to_string(),
+            size,
+        }
+    }
+
+    /// Sets the file name shown to the recipients.
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the MIME type of the file.
+    #[must_use]
+    pub fn with_mime_type(mut self, mime_type: impl Into<String>) -> Self {
+        self.mime_type = mime_type.into();
+        self
+    }
+}
+
+/// File already stored on the server, sent again without uploading it.
+///
+/// Its file reference expires; a send failing with a `FILE_REFERENCE_*`
+/// error is repeated with the reference recorded for `file_id` after the
+/// sources of the file are re-fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    /// The file, whose sources are re-fetched to repair the reference
+    pub file_id: rustgram_file_id::FileId,
+
+    /// Identifier of the photo or document on the server
+    pub id: i64,
+
+    /// Access hash of the photo or document
+    pub access_hash: i64,
+
+    /// File reference known when the media was created
+    pub file_reference: Vec<u8>,
+}
+
+/// Poll attached to an outgoing message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPoll {
+    /// Question
+    pub question: String,
+
+    /// Answer options, 2-10 items
+    pub options: Vec<String>,
+
+    /// Whether the voters are hidden
+    pub is_anonymous: bool,
+
+    /// Whether several options can be chosen
+    pub allows_multiple_answers: bool,
+
+    /// Index of the correct option if the poll is a quiz
+    pub correct_option_id: Option<usize>,
+
+    /// Time in seconds after which the poll is closed, 0 if never
+    pub open_period: i32,
+}
+
+impl InputPoll {
+    /// Minimum number of answer options.
+    pub const MIN_OPTION_COUNT: usize = 2;
+
+    /// Maximum number of answer options.
+    pub const MAX_OPTION_COUNT: usize = 10;
+
+    /// Creates an anonymous poll with a single answer.
+    pub fn new(question: impl Into<String>, options: Vec<String>) -> Self {
+        Self {
+            question: question.into(),
+            options,
+            is_anonymous: true,
+            allows_multiple_answers: false,
+            correct_option_id: None,
+            open_period: 0,
+        }
+    }
+
+    /// Makes the poll a quiz with the given correct option.
+    #[must_use]
+    pub fn quiz(mut self, correct_option_id: usize) -> Self {
+        self.correct_option_id = Some(correct_option_id);
+        self
+    }
+
+    /// Converts the poll to its TL representation.
+    fn to_input_media(&self) -> Result<InputMedia, MessagesManagerError> {
+        if self.question.is_empty() {
+            return Err(invalid_content("Poll question cannot be empty"));
+        }
+        if !(Self::MIN_OPTION_COUNT..=Self::MAX_OPTION_COUNT).contains(&self.options.len()) {
+            return Err(invalid_content(format!(
+                "Poll must have {}-{} options, got {}",
+                Self::MIN_OPTION_COUNT,
+                Self::MAX_OPTION_COUNT,
+                self.options.len()
+            )));
+        }
+        if self
+            .correct_option_id
+            .is_some_and(|option_id| option_id >= self.options.len())
+        {
+            return Err(invalid_content("Invalid correct option of the quiz"));
+        }
+
+        let answers = self
+            .options
+            .iter()
+            .enumerate()
+            .map(|(i, text)| PollAnswer {
+                text: text.clone(),
+                option: vec![i as u8],
+            })
+            .collect();
+        let poll = PollData {
+            question: self.question.clone(),
+            answers,
+            public_voters: !self.is_anonymous,
+            multiple_choice: self.allows_multiple_answers,
+            quiz: self.correct_option_id.is_some(),
+            close_period: (self.open_period > 0).then_some(self.open_period),
+        };
+        Ok(InputMedia::Poll {
+            poll,
+            correct_answers: self
+                .correct_option_id
+                .map(|option_id| vec![vec![option_id as u8]]),
+        })
+    }
+}
+
+/// Media of an outgoing message.
+///
+/// Holds the content of the message as it is shown locally, together with
+/// what must be uploaded or created on the server to send it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputMessageMedia {
+    /// Content of the message
+    content: MessageContent,
+
+    /// File to upload
+    file: Option<LocalFile>,
+
+    /// File already stored on the server
+    remote_file: Option<RemoteFile>,
+
+    /// Poll to create
+    poll: Option<InputPoll>,
+}
+
+impl InputMessageMedia {
+    /// Creates a photo.
+    pub fn photo(file: LocalFile, caption: FormattedText) -> Self {
+        let mut photo = MessagePhoto::new();
+        photo.caption = caption;
+        Self::with_file(MessageContent::Photo(Box::new(photo)), file)
+    }
+
+    /// Creates a video.
+    pub fn video(file: LocalFile, caption: FormattedText) -> Self {
+        let mut video = MessageVideo::new(FileId::new(0));
+        video.caption = caption;
+        Self::with_file(MessageContent::Video(Box::new(video)), file)
+    }
+
+    /// Creates a document.
+    pub fn document(file: LocalFile, caption: FormattedText) -> Self {
+        let mut document = MessageDocument::new(FileId::new(0));
+        document.caption = caption;
+        Self::with_file(MessageContent::Document(Box::new(document)), file)
+    }
+
+    /// Creates an audio file.
+    pub fn audio(file: LocalFile, caption: FormattedText) -> Self {
+        let mut audio = MessageAudio::new(FileId::new(0));
+        audio.caption = caption;
+        Self::with_file(MessageContent::Audio(Box::new(audio)), file)
+    }
+
+    /// Creates a voice note.
+    pub fn voice_note(file: LocalFile, caption: FormattedText) -> Self {
+        let mut voice_note = MessageVoiceNote::new(FileId::new(0));
+        voice_note.caption = caption;
+        Self::with_file(MessageContent::VoiceNote(Box::new(voice_note)), file)
+    }
+
+    /// Creates an animation.
+    pub fn animation(file: LocalFile, caption: FormattedText) -> Self {
+        let mut animation = MessageAnimation::new(FileId::new(0));
+        animation.caption = caption;
+        Self::with_file(MessageContent::Animation(Box::new(animation)), file)
+    }
+
+    /// Creates a sticker.
+    pub fn sticker(file: LocalFile) -> Self {
+        let sticker = MessageSticker::new(FileId::new(0));
+        Self::with_file(MessageContent::Sticker(Box::new(sticker)), file)
+    }
+
+    /// Creates a poll.
+    pub fn poll(poll: InputPoll) -> Self {
+        Self {
+            content: MessageContent::Poll(Box::new(MessagePoll::new(PollId::new(0)))),
+            file: None,
+            remote_file: None,
+            poll: Some(poll),
+        }
+    }
+
+    /// Creates a location.
+    pub fn location(location: MessageLocation) -> Self {
+        Self::without_file(MessageContent::Location(Box::new(location)))
+    }
+
+    /// Creates a venue.
+    pub fn venue(venue: MessageVenue) -> Self {
+        Self::without_file(MessageContent::Venue(Box::new(venue)))
+    }
+
+    /// Creates a contact.
+    pub fn contact(contact: MessageContact) -> Self {
+        Self::without_file(MessageContent::Contact(Box::new(contact)))
+    }
+
+    /// Creates a photo already stored on the server.
+    pub fn remote_photo(remote_file: RemoteFile, caption: FormattedText) -> Self {
+        let mut photo = MessagePhoto::new();
+        photo.photo.id = remote_file.id;
+        photo.caption = caption;
+        Self::with_remote_file(MessageContent::Photo(Box::new(photo)), remote_file)
+    }
+
+    /// Creates a document already stored on the server.
+    pub fn remote_document(remote_file: RemoteFile, caption: FormattedText) -> Self {
+        let mut document = MessageDocument::new(FileId::new(0));
+        document.caption = caption;
+        Self::with_remote_file(MessageContent::Document(Box::new(document)), remote_file)
+    }
+
+    fn with_file(content: MessageContent, file: LocalFile) -> Self {
+        Self {
+            content,
+            file: Some(file),
+            remote_file: None,
+            poll: None,
+        }
+    }
+
+    fn with_remote_file(content: MessageContent, remote_file: RemoteFile) -> Self {
+        Self {
+            content,
+            file: None,
+            remote_file: Some(remote_file),
+            poll: None,
+        }
+    }
+
+    fn without_file(content: MessageContent) -> Self {
+        Self {
+            content,
+            file: None,
+            remote_file: None,
+            poll: None,
+        }
+    }
+
+    /// Returns the content of the message.
+    pub fn content(&self) -> &MessageContent {
+        &self.content
+    }
+
+    /// Returns the file to upload, if any.
+    pub fn file(&self) -> Option<&LocalFile> {
+        self.file.as_ref()
+    }
+
+    /// Returns the file already stored on the server, if any.
+    pub fn remote_file(&self) -> Option<&RemoteFile> {
+        self.remote_file.as_ref()
+    }
+
+    /// Returns the caption of the media, if the media can have one.
+    pub fn caption(&self) -> Option<&FormattedText> {
+        match &self.content {
+            MessageContent::Photo(photo) => Some(&photo.caption),
+            MessageContent::Video(video) => Some(&video.caption),
+            MessageContent::Document(document) => Some(&document.caption),
+            MessageContent::Audio(audio) => Some(&audio.caption),
+            MessageContent::VoiceNote(voice_note) => Some(&voice_note.caption),
+            MessageContent::Animation(animation) => Some(&animation.caption),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of the file to upload, if any.
+    pub fn file_type(&self) -> Option<FileType> {
+        self.file.as_ref()?;
+        match &self.content {
+            MessageContent::Photo(_) => Some(FileType::Photo),
+            MessageContent::Video(_) => Some(FileType::Video),
+            MessageContent::Document(_) => Some(FileType::Document),
+            MessageContent::Audio(_) => Some(FileType::Audio),
+            MessageContent::VoiceNote(_) => Some(FileType::VoiceNote),
+            MessageContent::Animation(_) => Some(FileType::Animation),
+            MessageContent::Sticker(_) => Some(FileType::Sticker),
+            _ => None,
+        }
+    }
+
+    /// Builds the `inputMedia*` object for the content.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_file` - The uploaded file, required if the media has one
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the content can't be sent as media or
+    /// its file isn't uploaded.
+    pub fn get_input_media(
+        &self,
+        input_file: Option<InputFile>,
+    ) -> Result<InputMedia, MessagesManagerError> {
+        if let Some(remote_file) = &self.remote_file {
+            return self.get_remote_input_media(&remote_file.file_reference);
+        }
+
+        let document = |input_file: Option<InputFile>, attributes: Vec<DocumentAttribute>| {
+            let file = input_file.ok_or_else(|| invalid_content("The file isn't uploaded"))?;
+            let local_file = self
+                .file
+                .as_ref()
+                .ok_or_else(|| invalid_content("The media has no file"))?;
+            let mut attributes = attributes;
+            attributes.push(DocumentAttribute::Filename {
+                file_name: local_file.name.clone(),
+            });
+            Ok(InputMedia::UploadedDocument {
+                file,
+                mime_type: local_file.mime_type.clone(),
+                attributes,
+                force_file: false,
+                spoiler: false,
+            })
+        };
+
+        match &self.content {
+            MessageContent::Photo(photo) => Ok(InputMedia::UploadedPhoto {
+                file: input_file.ok_or_else(|| invalid_content("The photo isn't uploaded"))?,
+                spoiler: photo.has_spoiler,
+                ttl_seconds: None,
+            }),
+            MessageContent::Video(video) => {
+                let media = document(
+                    input_file,
+                    vec![DocumentAttribute::Video {
+                        round_message: false,
+                        supports_streaming: true,
+                        duration: 0,
+                        w: 0,
+                        h: 0,
+                    }],
+                )?;
+                Ok(with_spoiler(media, video.has_spoiler))
+            }
+            MessageContent::Document(_) => document(input_file, Vec::new()),
+            MessageContent::Audio(_) => document(
+                input_file,
+                vec![DocumentAttribute::Audio {
+                    voice: false,
+                    duration: 0,
+                }],
+            ),
+            MessageContent::VoiceNote(_) => document(
+                input_file,
+                vec![DocumentAttribute::Audio {
+                    voice: true,
+                    duration: 0,
+                }],
+            ),
+            MessageContent::Animation(animation) => {
+                let media = document(input_file, vec![DocumentAttribute::Animated])?;
+                Ok(with_spoiler(media, animation.has_spoiler))
+            }
+            MessageContent::Sticker(_) => document(
+                input_file,
+                vec![DocumentAttribute::Sticker { alt: String::new() }],
+            ),
+            MessageContent::Location(location) => Ok(InputMedia::GeoPoint {
+                geo_point: input_geo_point(location),
+            }),
+            MessageContent::Venue(venue) => Ok(InputMedia::Venue {
+                geo_point: input_geo_point(&venue.location),
+                title: venue.title.clone(),
+                address: venue.address.clone(),
+                provider: venue.provider.clone(),
+                venue_id: venue.venue_id.clone(),
+                venue_type: venue.venue_type.clone(),
+            }),
+            MessageContent::Contact(contact) => Ok(InputMedia::Contact {
+                phone_number: contact.phone_number.clone(),
+                first_name: contact.first_name.clone(),
+                last_name: contact.last_name.clone(),
+                vcard: contact.vcard.clone(),
+            }),
+            MessageContent::Poll(_) => self
+                .poll
+                .as_ref()
+                .ok_or_else(|| invalid_content("The poll has no question"))?
+                .to_input_media(),
+            other => Err(invalid_content(format!(
+                "Content {} can't be sent as media",
+                other.content_type()
+            ))),
+        }
+    }
+}
+
+impl InputMessageMedia {
+    /// Builds the `inputMediaPhoto` or `inputMediaDocument` object for a
+    /// file already stored on the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_reference` - The file reference to send
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the media has no file stored on the
+    /// server.
+    pub fn get_remote_input_media(
+        &self,
+        file_reference: &[u8],
+    ) -> Result<InputMedia, MessagesManagerError> {
+        let remote_file = self
+            .remote_file
+            .as_ref()
+            .ok_or_else(|| invalid_content("The media has no remote file"))?;
+        let id = remote_file.id;
+        let access_hash = remote_file.access_hash;
+        let file_reference = file_reference.to_vec();
+        match &self.content {
+            MessageContent::Photo(photo) => Ok(InputMedia::Photo {
+                id,
+                access_hash,
+                file_reference,
+                spoiler: photo.has_spoiler,
+            }),
+            _ => Ok(InputMedia::Document {
+                id,
+                access_hash,
+                file_reference,
+                spoiler: false,
+            }),
+        }
+    }
+}
+
+/// Checks that the media can be sent together as an album.
+///
+/// Photos and videos can be mixed, while documents and audio files can only
+/// be grouped with media of the same kind.
+///
+/// # Errors
+///
+/// Returns a validation error if the number of items is out of range or an
+/// item can't be part of the album.
+pub fn check_album(items: &[InputMessageMedia]) -> Result<(), MessagesManagerError> {
+    if !(MIN_ALBUM_SIZE..=MAX_ALBUM_SIZE).contains(&items.len()) {
+        return Err(invalid_content(format!(
+            "Album must have {}-{} items, got {}",
+            MIN_ALBUM_SIZE,
+            MAX_ALBUM_SIZE,
+            items.len()
+        )));
+    }
+
+    let mut first_kind = None;
+    for item in items {
+        let kind = album_kind(item.content()).ok_or_else(|| {
+            invalid_content(format!(
+                "Content {} can't be sent in an album",
+                item.content().content_type()
+            ))
+        })?;
+        let first_kind = *first_kind.get_or_insert(kind);
+        if kind != first_kind {
+            return Err(invalid_content(
+                "Documents and audio files can only be grouped with media of the same kind",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Kind of album an item can be part of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AlbumKind {
+    PhotoOrVideo,
+    Document,
+    Audio,
+}
+
+fn album_kind(content: &MessageContent) -> Option<AlbumKind> {
+    match content {
+        MessageContent::Photo(_) | MessageContent::Video(_) => Some(AlbumKind::PhotoOrVideo),
+        MessageContent::Document(_) => Some(AlbumKind::Document),
+        MessageContent::Audio(_) => Some(AlbumKind::Audio),
+        _ => None,
+    }
+}
+
+/// Converts the media of an incoming message to its content.
+///
+/// # Arguments
+///
+/// * `media` - Media of the message
+/// * `caption` - Caption of the media
+///
+/// Returns `None` if the media isn't supported.
+pub fn get_message_content(media: &MessageMedia, caption: FormattedText) -> Option<MessageContent> {
+    match media {
+        MessageMedia::Photo { id, spoiler } => {
+            let mut photo = MessagePhoto::new();
+            photo.photo = PhotoData { id: *id };
+            photo.caption = caption;
+            photo.has_spoiler = *spoiler;
+            Some(MessageContent::Photo(Box::new(photo)))
+        }
+        MessageMedia::Document {
+            id,
+            attributes,
+            spoiler,
+            ..
+        } => {
+            let file_id = FileId::new(*id);
+            let is_animated = attributes.contains(&DocumentAttribute::Animated);
+            for attribute in attributes {
+                match attribute {
+                    DocumentAttribute::Sticker { .. } => {
+                        return Some(MessageContent::Sticker(Box::new(MessageSticker::new(
+                            file_id,
+                        ))));
+                    }
+                    DocumentAttribute::Video { .. } if !is_animated => {
+                        let mut video = MessageVideo::new(file_id);
+                        video.caption = caption;
+                        video.has_spoiler = *spoiler;
+                        return Some(MessageContent::Video(Box::new(video)));
+                    }
+                    DocumentAttribute::Audio { voice: true, .. } => {
+                        let mut voice_note = MessageVoiceNote::new(file_id);
+                        voice_note.caption = caption;
+                        return Some(MessageContent::VoiceNote(Box::new(voice_note)));
+                    }
+                    DocumentAttribute::Audio { voice: false, .. } => {
+                        let mut audio = MessageAudio::new(file_id);
+                        audio.caption = caption;
+                        return Some(MessageContent::Audio(Box::new(audio)));
+                    }
+                    _ => {}
+                }
+            }
+            if is_animated {
+                let mut animation = MessageAnimation::new(file_id);
+                animation.caption = caption;
+                animation.has_spoiler = *spoiler;
+                return Some(MessageContent::Animation(Box::new(animation)));
+            }
+            let mut document = MessageDocument::new(file_id);
+            document.caption = caption;
+            Some(MessageContent::Document(Box::new(document)))
+        }
+        MessageMedia::Unsupported => None,
+    }
+}
+
+/// Converts the entities of a formatted text to their TL representation.
+///
+/// Entities that can't be sent are skipped.
+pub fn get_input_message_entities(text: &FormattedText) -> Vec<MessageEntity> {
+    text.entities()
+        .iter()
+        .filter_map(|entity| {
+            let offset = entity.offset();
+            let length = entity.length();
+            let argument = entity.argument().unwrap_or_default();
+            let entity = match entity.entity_type() {
+                entity_type::BOLD => MessageEntity::Bold { offset, length },
+                entity_type::ITALIC => MessageEntity::Italic { offset, length },
+                entity_type::UNDERLINE => MessageEntity::Underline { offset, length },
+                entity_type::STRIKETHROUGH => MessageEntity::Strike { offset, length },
+                entity_type::CODE => MessageEntity::Code { offset, length },
+                entity_type::PRE | entity_type::PRE_CODE => MessageEntity::Pre {
+                    offset,
+                    length,
+                    language: entity.argument().map(str::to_string),
+                },
+                entity_type::TEXT_URL => MessageEntity::TextUrl {
+                    offset,
+                    length,
+                    url: argument.to_string(),
+                },
+                entity_type::MENTION_NAME => MessageEntity::Mention {
+                    offset,
+                    length,
+                    user_id: argument.parse().ok()?,
+                },
+                entity_type::CUSTOM_EMOJI => MessageEntity::CustomEmoji {
+                    offset,
+                    length,
+                    document_id: argument.parse().ok()?,
+                },
+                _ => return None,
+            };
+            Some(entity)
+        })
+        .collect()
+}
+
+/// Uploads a file part by part with `upload.saveFilePart` or
+/// `upload.saveBigFilePart`.
+///
+/// # Errors
+///
+/// Returns [`MessagesManagerError::Upload`] if the file can't be read and
+/// [`MessagesManagerError::Network`] if a part can't be uploaded.
+pub async fn upload_file(
+    client: &dyn MediaSendClient,
+    file: &LocalFile,
+    file_type: FileType,
+) -> Result<InputFile, MessagesManagerError> {
+    if file.size <= 0 {
+        return Err(invalid_content("The file is empty"));
+    }
+
+    let config = FileUploaderConfig::new(&file.path, file_type, None)
+        .with_size(file.size)
+        .with_expected_size(file.size)
+        .with_size_final(true)
+        .with_upload_file_id(generate_random_id());
+    let mut uploader = FileUploader::new(config).map_err(upload_error)?;
+    uploader.start().map_err(upload_error)?;
+
+    let part_size = uploader.part_size() as i64;
+    let total_parts = i32::try_from((file.size + part_size - 1) / part_size)
+        .map_err(|_| MessagesManagerError::Upload("The file is too big".to_string()))?;
+    let is_big = !uploader.is_small();
+    let mut source = File::open(&file.path).map_err(upload_error)?;
+
+    loop {
+        let part = uploader.get_next_part().map_err(upload_error)?;
+        if part.is_empty() {
+            break;
+        }
+
+        let bytes = read_part(&mut source, part.offset, part.size)?;
+        let actual_size = bytes.len();
+        let request = SaveFilePartRequest {
+            file_id: uploader.upload_file_id(),
+            file_part: part.id,
+            file_total_parts: is_big.then_some(total_parts),
+            bytes,
+        };
+        match client.save_file_part(request).await {
+            Ok(true) => {}
+            Ok(false) => {
+                uploader.on_part_failed(part.id);
+                return Err(MessagesManagerError::Upload(format!(
+                    "The server didn't save part {}",
+                    part.id
+                )));
+            }
+            Err(error) => {
+                uploader.on_part_failed(part.id);
+                return Err(error.into());
+            }
+        }
+        uploader
+            .on_part_ok(part.id, part.size, actual_size)
+            .map_err(upload_error)?;
+        if uploader.is_complete() {
+            break;
+        }
+    }
+    debug!(
+        "Uploaded {} in {} parts as {}",
+        file.path,
+        total_parts,
+        uploader.upload_file_id()
+    );
+
+    Ok(if is_big {
+        InputFile::Big {
+            id: uploader.upload_file_id(),
+            parts: total_parts,
+            name: file.name.clone(),
+        }
+    } else {
+        InputFile::Small {
+            id: uploader.upload_file_id(),
+            parts: total_parts,
+            name: file.name.clone(),
+            md5_checksum: String::new(),
+        }
+    })
+}
+
+/// Generates a non-zero random ID for a message or an upload.
+pub(crate) fn generate_random_id() -> i64 {
+    loop {
+        let id = rand::random::<i64>();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+/// Reads a part of a file; the last part may be shorter.
+fn read_part(source: &mut File, offset: i64, size: usize) -> Result<Vec<u8>, MessagesManagerError> {
+    let offset = u64::try_from(offset)
+        .map_err(|_| MessagesManagerError::Upload(format!("Invalid part offset {offset}")))?;
+    source.seek(SeekFrom::Start(offset)).map_err(upload_error)?;
+    let mut bytes = Vec::with_capacity(size);
+    source
+        .take(size as u64)
+        .read_to_end(&mut bytes)
+        .map_err(upload_error)?;
+    if bytes.is_empty() {
+        return Err(MessagesManagerError::Upload(format!(
+            "The file is shorter than expected at offset {offset}"
+        )));
+    }
+    Ok(bytes)
+}
+
+fn input_geo_point(location: &MessageLocation) -> InputGeoPoint {
+    InputGeoPoint {
+        lat: location.latitude,
+        long: location.longitude,
+        accuracy_radius: (location.horizontal_accuracy > 0.0)
+            .then_some(location.horizontal_accuracy.round() as i32),
+    }
+}
+
+fn with_spoiler(media: InputMedia, has_spoiler: bool) -> InputMedia {
+    match media {
+        InputMedia::UploadedDocument {
+            file,
+            mime_type,
+            attributes,
+            force_file,
+            ..
+        } => InputMedia::UploadedDocument {
+            file,
+            mime_type,
+            attributes,
+            force_file,
+            spoiler: has_spoiler,
+        },
+        other => other,
+    }
+}
+
+fn invalid_content(message: impl Into<String>) -> MessagesManagerError {
+    MessagesManagerError::Validation(MessageValidationError::InvalidContent(message.into()))
+}
+
+fn upload_error(error: impl std::fmt::Display) -> MessagesManagerError {
+    MessagesManagerError::Upload(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_formatted_text::MessageEntity as TextEntity;
+
+    #[test]
+    fn test_location_media() {
+        let media = InputMessageMedia::location(MessageLocation::new(55.75, 37.61));
+        assert!(media.file_type().is_none());
+        match media.get_input_media(None).unwrap() {
+            InputMedia::GeoPoint { geo_point } => {
+                assert_eq!(geo_point.lat, 55.75);
+                assert_eq!(geo_point.long, 37.61);
+                assert!(geo_point.accuracy_radius.is_none());
+            }
+            other => panic!("Expected geo point, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_voice_note_media() {
+        let file = LocalFile::new("/tmp/voice.ogg", 1000).with_mime_type("audio/ogg");
+        let media = InputMessageMedia::voice_note(file, FormattedText::new("listen"));
+        assert_eq!(media.file_type(), Some(FileType::VoiceNote));
+        assert_eq!(media.caption().map(FormattedText::text), Some("listen"));
+
+        let input_file = InputFile::Small {
+            id: 1,
+            parts: 1,
+            name: "voice.ogg".to_string(),
+            md5_checksum: String::new(),
+        };
+        match media.get_input_media(Some(input_file)).unwrap() {
+            InputMedia::UploadedDocument {
+                mime_type,
+                attributes,
+                ..
+            } => {
+                assert_eq!(mime_type, "audio/ogg");
+                assert!(attributes.contains(&DocumentAttribute::Audio {
+                    voice: true,
+                    duration: 0
+                }));
+                assert!(attributes.contains(&DocumentAttribute::Filename {
+                    file_name: "voice.ogg".to_string()
+                }));
+            }
+            other => panic!("Expected uploaded document, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_media_requires_uploaded_file() {
+        let media =
+            InputMessageMedia::photo(LocalFile::new("/tmp/a.jpg", 10), FormattedText::new(""));
+        assert!(matches!(
+            media.get_input_media(None),
+            Err(MessagesManagerError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_quiz_media() {
+        let poll = InputPoll::new("2 + 2?", vec!["3".to_string(), "4".to_string()]).quiz(1);
+        match InputMessageMedia::poll(poll).get_input_media(None).unwrap() {
+            InputMedia::Poll {
+                poll,
+                correct_answers,
+            } => {
+                assert!(poll.quiz);
+                assert_eq!(poll.answers.len(), 2);
+                assert_eq!(correct_answers, Some(vec![vec![1]]));
+            }
+            other => panic!("Expected poll, got {:?}", other),
+        }
+
+        let poll = InputPoll::new("?", vec!["only".to_string()]);
+        assert!(InputMessageMedia::poll(poll).get_input_media(None).is_err());
+    }
+
+    #[test]
+    fn test_check_album() {
+        let photo =
+            || InputMessageMedia::photo(LocalFile::new("/tmp/a.jpg", 10), FormattedText::new(""));
+        let video =
+            || InputMessageMedia::video(LocalFile::new("/tmp/a.mp4", 10), FormattedText::new(""));
+        let document = || {
+            InputMessageMedia::document(LocalFile::new("/tmp/a.pdf", 10), FormattedText::new(""))
+        };
+
+        assert!(check_album(&[photo(), video(), photo()]).is_ok());
+        assert!(check_album(&[document(), document()]).is_ok());
+        assert!(check_album(&[photo()]).is_err());
+        assert!(check_album(&vec![photo(); MAX_ALBUM_SIZE + 1]).is_err());
+        assert!(check_album(&[photo(), document()]).is_err());
+        let location = InputMessageMedia::location(MessageLocation::new(0.0, 0.0));
+        assert!(check_album(&[photo(), location]).is_err());
+    }
+
+    #[test]
+    fn test_get_message_content() {
+        let photo = MessageMedia::Photo {
+            id: 5,
+            spoiler: true,
+        };
+        match get_message_content(&photo, FormattedText::new("sunset")) {
+            Some(MessageContent::Photo(photo)) => {
+                assert_eq!(photo.photo.id, 5);
+                assert_eq!(photo.caption.text(), "sunset");
+                assert!(photo.has_spoiler);
+            }
+            other => panic!("Expected photo, got {:?}", other),
+        }
+
+        let document = |attributes| MessageMedia::Document {
+            id: 7,
+            mime_type: "video/mp4".to_string(),
+            attributes,
+            spoiler: false,
+        };
+        let video = DocumentAttribute::Video {
+            round_message: false,
+            supports_streaming: true,
+            duration: 3,
+            w: 640,
+            h: 480,
+        };
+        assert!(matches!(
+            get_message_content(&document(vec![video.clone()]), FormattedText::new("")),
+            Some(MessageContent::Video(_))
+        ));
+        assert!(matches!(
+            get_message_content(
+                &document(vec![video, DocumentAttribute::Animated]),
+                FormattedText::new("")
+            ),
+            Some(MessageContent::Animation(_))
+        ));
+        assert!(matches!(
+            get_message_content(&document(Vec::new()), FormattedText::new("")),
+            Some(MessageContent::Document(_))
+        ));
+        assert!(get_message_content(&MessageMedia::Unsupported, FormattedText::new("")).is_none());
+    }
+
+    #[test]
+    fn test_input_message_entities() {
+        let text = FormattedText::with_entities(
+            "bold link",
+            vec![
+                TextEntity::new(entity_type::BOLD, 0, 4),
+                TextEntity::with_argument(entity_type::TEXT_URL, 5, 4, Some("https://t.me")),
+                TextEntity::new(entity_type::SPOILER, 0, 9),
+            ],
+        );
+        assert_eq!(
+            get_input_message_entities(&text),
+            vec![
+                MessageEntity::Bold {
+                    offset: 0,
+                    length: 4
+                },
+                MessageEntity::TextUrl {
+                    offset: 5,
+                    length: 4,
+                    url: "https://t.me".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -25,7 +25,7 @@ use bytes::Bytes;
 use parking_lot::RwLock;
 use thiserror::Error;
 use tokio::sync::oneshot;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, info, trace, warn};
 
 use rustgram_net::{
     AuthFlag, DcId, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryId,
@@ -33,8 +33,12 @@ use rustgram_net::{
 };
 
 use super::tl_types::{
-    InputPeer, SendMessageRequest, SendMessageResult, TlSerializationError,
+    AffectedMessages, DeleteMessagesRequest, EditMessageRequest, ForwardMessagesRequest,
+    GetHistoryRequest, InputPeer, Messages, ReadHistoryRequest, SaveFilePartRequest,
+    SendMediaRequest, SendMessageRequest, SendMessageResult, SendMultiMediaRequest,
+    TlSerializationError, Updates,
 };
+use crate::MessagesManagerError;
 use rustgram_types::{DialogId, MessageId};
 use rustgram_message_types::Message;
 
@@ -106,11 +110,148 @@ pub trait MessageUpdateCallback: Send + Sync {
     /// Called when a new message is received.
     fn on_new_message(&self, message: Message);
 
+    /// Called when a message is edited.
+    ///
+    /// Also called when a local edit is rolled back, with the message as it
+    /// was before the edit.
+    fn on_message_edited(&self, _message: Message) {}
+
     /// Called when messages are deleted.
     fn on_messages_deleted(&self, dialog_id: DialogId, message_ids: Vec<MessageId>);
 
     /// Called when messages are read.
     fn on_messages_read(&self, dialog_id: DialogId, max_id: MessageId);
+
+    /// Called when a sent message got its identifier from the server.
+    ///
+    /// `old_message_id` is the temporary identifier assigned when sending
+    /// started.
+    fn on_message_send_succeeded(
+        &self,
+        _dialog_id: DialogId,
+        _old_message_id: MessageId,
+        _message_id: MessageId,
+    ) {
+    }
+
+    /// Called when sending a message failed.
+    ///
+    /// The message can be sent again after
+    /// [`MessagesManagerError::retry_after`].
+    fn on_message_send_failed(
+        &self,
+        _dialog_id: DialogId,
+        _message_id: MessageId,
+        _error: &MessagesManagerError,
+    ) {
+    }
+}
+
+/// Client sending the queries of media messages.
+///
+/// Based on TDLib's FileUploader queries and `SendMediaQuery` from
+/// `td/telegram/MessagesManager.cpp`.
+#[async_trait::async_trait]
+pub trait MediaSendClient: Send + Sync {
+    /// Uploads a part of a file with `upload.saveFilePart` or
+    /// `upload.saveBigFilePart`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn save_file_part(
+        &self,
+        request: SaveFilePartRequest,
+    ) -> Result<bool, SendMessageNetworkError>;
+
+    /// Sends a message with media with `messages.sendMedia`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn send_media(
+        &self,
+        request: SendMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError>;
+
+    /// Sends an album with `messages.sendMultiMedia`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn send_multi_media(
+        &self,
+        request: SendMultiMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError>;
+}
+
+/// Client sending the queries changing existing messages.
+///
+/// Based on `EditMessageQuery`, `ForwardMessagesQuery` and
+/// `DeleteMessagesQuery` from `td/telegram/MessagesManager.cpp`.
+#[async_trait::async_trait]
+pub trait MessageEditClient: Send + Sync {
+    /// Edits a message with `messages.editMessage`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn edit_message(
+        &self,
+        request: EditMessageRequest,
+    ) -> Result<Updates, SendMessageNetworkError>;
+
+    /// Forwards messages with `messages.forwardMessages`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn forward_messages(
+        &self,
+        request: ForwardMessagesRequest,
+    ) -> Result<Updates, SendMessageNetworkError>;
+
+    /// Deletes messages with `messages.deleteMessages` or
+    /// `channels.deleteMessages`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn delete_messages(
+        &self,
+        request: DeleteMessagesRequest,
+    ) -> Result<AffectedMessages, SendMessageNetworkError>;
+}
+
+/// Client requesting the history of dialogs.
+///
+/// Based on `GetHistoryQuery`, `ReadHistoryQuery` and
+/// `ReadChannelHistoryQuery` from `td/telegram/MessagesManager.cpp`.
+#[async_trait::async_trait]
+pub trait MessageHistoryClient: Send + Sync {
+    /// Requests a page of history with `messages.getHistory`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn get_history(
+        &self,
+        request: GetHistoryRequest,
+    ) -> Result<Messages, SendMessageNetworkError>;
+
+    /// Marks the history as read with `messages.readHistory` or
+    /// `channels.readHistory`.
+    ///
+    /// Returns the affected messages; `None` for channels, whose query
+    /// returns only a `Bool`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    async fn read_history(
+        &self,
+        request: ReadHistoryRequest,
+    ) -> Result<Option<AffectedMessages>, SendMessageNetworkError>;
 }
 
 /// Network error types for message operations.
@@ -154,6 +295,50 @@ pub enum SendMessageNetworkError {
     Generic(String),
 }
 
+impl SendMessageNetworkError {
+    /// Creates an error from an RPC error returned by the server.
+    ///
+    /// `FLOOD_WAIT_X` errors become [`Self::RateLimited`] with the number of
+    /// seconds to wait.
+    pub fn from_rpc_error(code: i32, message: &str) -> Self {
+        if code == 420 {
+            if let Some(seconds) = message
+                .strip_prefix("FLOOD_WAIT_")
+                .and_then(|seconds| seconds.parse().ok())
+            {
+                return Self::RateLimited(seconds);
+            }
+        }
+        Self::ServerError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns `true` if the server rejected an expired or invalid file
+    /// reference with a `FILE_REFERENCE_*` error.
+    pub fn is_file_reference_error(&self) -> bool {
+        match self {
+            Self::ServerError { message, .. }
+            | Self::NetworkError(QueryError::WithMessage { message, .. }) => {
+                rustgram_file_reference_manager::is_file_reference_error(message)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the delay after which the failed request can be repeated, if
+    /// the server limited the rate of requests.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(seconds) => {
+                Some(Duration::from_secs(u64::try_from(*seconds).unwrap_or(0)))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl MessageNetworkClient {
     /// Creates a new message network client.
     ///
@@ -178,6 +363,13 @@ impl MessageNetworkClient {
         *self.update_callback.write() = Some(callback);
     }
 
+    /// Calls the update callback, if one is set.
+    pub(crate) fn notify(&self, f: impl FnOnce(&dyn MessageUpdateCallback)) {
+        if let Some(callback) = self.update_callback.read().as_deref() {
+            f(callback);
+        }
+    }
+
     /// Sends a text message to the specified dialog.
     ///
     /// # Arguments
@@ -329,9 +521,8 @@ impl NetQueryCallback for MessageSendCallback {
 
                     if error.is_canceled() {
                         Err(SendMessageNetworkError::Canceled)
-                    } else if error.code() == 420 {
-                        // FLOOD_WAIT
-                        Err(SendMessageNetworkError::RateLimited(error.code()))
+                    } else if let QueryError::WithMessage { code, message } = &error {
+                        Err(SendMessageNetworkError::from_rpc_error(*code, message))
                     } else {
                         Err(SendMessageNetworkError::ServerError {
                             code: error.code(),
@@ -431,6 +622,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_rpc_error() {
+        let error = SendMessageNetworkError::from_rpc_error(420, "FLOOD_WAIT_30");
+        assert!(matches!(error, SendMessageNetworkError::RateLimited(30)));
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(30)));
+
+        let error = SendMessageNetworkError::from_rpc_error(400, "PEER_ID_INVALID");
+        assert!(matches!(error, SendMessageNetworkError::ServerError { code: 400, .. }));
+        assert_eq!(error.retry_after(), None);
+    }
+
     fn create_test_client() -> MessageNetworkClient {
         let dispatcher = Arc::new(NetQueryDispatcher::new());
         let config = MessageNetworkConfig::default();
@@ -0,0 +1,583 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Editing, forwarding and deleting messages.
+//!
+//! Every operation is applied to the local copy of the messages first, so
+//! that it is visible at once, and then sent to the server. If the server
+//! rejects the change, the previous state of the messages is restored and
+//! reported through the update callback.
+//!
+//! # TDLib Alignment
+//!
+//! Based on `MessagesManager::edit_message_text`, `forward_messages` and
+//! `delete_messages` from `td/telegram/MessagesManager.cpp`. Like TDLib,
+//! `MESSAGE_NOT_MODIFIED` is not an error.
+
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustgram_formatted_text::FormattedText;
+use rustgram_message_content::MessageContent;
+use rustgram_message_copy_options::MessageCopyOptions;
+use rustgram_message_types::{Message, MessageValidationError};
+use rustgram_reply_markup::ReplyMarkup;
+use rustgram_types::{DialogId, MessageId};
+use tracing::{debug, info, warn};
+
+use crate::media::{self, InputMessageMedia};
+use crate::network::{MessageEditClient, SendMessageNetworkError};
+use crate::tl_types::{
+    DeleteMessagesRequest, EditMessageRequest, ForwardMessagesRequest, InputPeer, Updates,
+};
+use crate::{MessagesManager, MessagesManagerError, PendingSend};
+
+/// Maximum number of messages forwarded at once.
+pub const MAX_FORWARDED_MESSAGES: usize = 100;
+
+impl MessagesManager {
+    /// Edits the text of a text message.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Dialog of the message
+    /// * `message_id` - Server ID of the message
+    /// * `text` - New text of the message
+    ///
+    /// # Returns
+    ///
+    /// The edited message as returned by the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is unknown, isn't a text message or
+    /// the text is invalid, or if the server rejects the change; the
+    /// previous version of the message is restored then.
+    pub async fn edit_message_text(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+        text: FormattedText,
+    ) -> Result<Message, MessagesManagerError> {
+        info!("Editing text of {:?} in {:?}", message_id, dialog_id);
+
+        if text.text().is_empty() {
+            return Err(invalid_content("Message text cannot be empty"));
+        }
+        if text.text().len() > self.config.max_message_length {
+            return Err(invalid_content(&format!(
+                "Message text too long: {} > {}",
+                text.text().len(),
+                self.config.max_message_length
+            )));
+        }
+
+        let client = self.get_edit_client()?;
+        let request = get_edit_message_request(dialog_id, message_id)?;
+        let request = with_text(request, &text);
+
+        self.edit_message(
+            dialog_id,
+            message_id,
+            |message| match &mut message.content {
+                MessageContent::Text(content) => {
+                    content.text = text.clone();
+                    Ok(())
+                }
+                _ => Err(invalid_content("Message has no text")),
+            },
+            async move { Ok(client.edit_message(request).await?) },
+        )
+        .await
+    }
+
+    /// Edits the caption of a media message.
+    ///
+    /// An empty caption removes the caption.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is unknown, can't have a caption or
+    /// the caption is too long, or if the server rejects the change; the
+    /// previous version of the message is restored then.
+    pub async fn edit_message_caption(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+        caption: FormattedText,
+    ) -> Result<Message, MessagesManagerError> {
+        info!("Editing caption of {:?} in {:?}", message_id, dialog_id);
+
+        if caption.text().len() > self.config.max_caption_length {
+            return Err(invalid_content(&format!(
+                "Caption too long: {} > {}",
+                caption.text().len(),
+                self.config.max_caption_length
+            )));
+        }
+
+        let client = self.get_edit_client()?;
+        let request = get_edit_message_request(dialog_id, message_id)?;
+        let request = with_text(request, &caption);
+
+        self.edit_message(
+            dialog_id,
+            message_id,
+            |message| match message.content.caption_mut() {
+                Some(old_caption) => {
+                    *old_caption = caption.clone();
+                    Ok(())
+                }
+                None => Err(invalid_content("Message can't have a caption")),
+            },
+            async move { Ok(client.edit_message(request).await?) },
+        )
+        .await
+    }
+
+    /// Replaces the media of a media message.
+    ///
+    /// The file of the new media is uploaded with the media client. The
+    /// caption of the new media replaces the caption of the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is unknown or has no media, if the
+    /// new media is invalid or can't be uploaded, or if the server rejects
+    /// the change; the previous version of the message is restored then.
+    pub async fn edit_message_media(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+        media: InputMessageMedia,
+    ) -> Result<Message, MessagesManagerError> {
+        info!("Editing media of {:?} in {:?}", message_id, dialog_id);
+
+        let media_client =
+            self.media_client.read().clone().ok_or_else(|| {
+                MessagesManagerError::Generic("Media client is not set".to_string())
+            })?;
+        self.check_caption(&media)?;
+
+        let client = self.get_edit_client()?;
+        let request = get_edit_message_request(dialog_id, message_id)?;
+        let query = async {
+            let input_file = match (media.file(), media.file_type()) {
+                (Some(file), Some(file_type)) => {
+                    Some(media::upload_file(media_client.as_ref(), file, file_type).await?)
+                }
+                _ => None,
+            };
+            let caption = media.caption().cloned().unwrap_or_default();
+            let request = with_text(
+                request.with_media(media.get_input_media(input_file)?),
+                &caption,
+            );
+            Ok(client.edit_message(request).await?)
+        };
+
+        self.edit_message(
+            dialog_id,
+            message_id,
+            |message| {
+                if !message.content.is_media() {
+                    return Err(invalid_content("Message has no media"));
+                }
+                message.content = media.content().clone();
+                Ok(())
+            },
+            query,
+        )
+        .await
+    }
+
+    /// Replaces or removes the reply markup of a message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is unknown or if the server rejects
+    /// the change; the previous version of the message is restored then.
+    pub async fn edit_message_reply_markup(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<Message, MessagesManagerError> {
+        info!(
+            "Editing reply markup of {:?} in {:?}",
+            message_id, dialog_id
+        );
+
+        let client = self.get_edit_client()?;
+        let mut request = get_edit_message_request(dialog_id, message_id)?;
+        if let Some(reply_markup) = &reply_markup {
+            request = request.with_reply_markup(reply_markup.clone());
+        }
+
+        self.edit_message(
+            dialog_id,
+            message_id,
+            |message| {
+                message.reply_markup = reply_markup.clone();
+                Ok(())
+            },
+            async move { Ok(client.edit_message(request).await?) },
+        )
+        .await
+    }
+
+    /// Forwards messages to a dialog.
+    ///
+    /// With [`MessageCopyOptions::send_copy`] the messages are sent without
+    /// the original author; with [`MessageCopyOptions::replace_caption`] the
+    /// captions of media are removed. Replacing captions with another
+    /// caption isn't supported by `messages.forwardMessages`.
+    ///
+    /// Copies of the messages known locally are added to the target dialog
+    /// with temporary identifiers until the server acknowledges them, as
+    /// with [`Self::send_media`].
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Target dialog
+    /// * `from_dialog_id` - Dialog of the messages
+    /// * `message_ids` - Server IDs of the messages, up to 100
+    /// * `options` - How the messages are copied
+    ///
+    /// # Returns
+    ///
+    /// The server message IDs of the new messages in their order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the messages can't be forwarded with the options
+    /// or if the server rejects them; the copies are removed then and the
+    /// failure is reported for every copy through
+    /// [`crate::MessageUpdateCallback::on_message_send_failed`].
+    pub async fn forward_messages(
+        &self,
+        dialog_id: DialogId,
+        from_dialog_id: DialogId,
+        message_ids: &[MessageId],
+        options: MessageCopyOptions,
+    ) -> Result<Vec<MessageId>, MessagesManagerError> {
+        info!(
+            "Forwarding {} messages from {:?} to {:?}",
+            message_ids.len(),
+            from_dialog_id,
+            dialog_id
+        );
+
+        if message_ids.is_empty() || message_ids.len() > MAX_FORWARDED_MESSAGES {
+            return Err(MessagesManagerError::Generic(format!(
+                "Can forward 1-{} messages, got {}",
+                MAX_FORWARDED_MESSAGES,
+                message_ids.len()
+            )));
+        }
+        if let Some(&message_id) = message_ids
+            .iter()
+            .find(|message_id| !message_id.is_server())
+        {
+            return Err(MessagesManagerError::MessageNotFound(
+                from_dialog_id,
+                message_id,
+            ));
+        }
+        if options.replace_caption() && !options.new_caption().text().is_empty() {
+            return Err(invalid_content(
+                "Forwarded messages can only have their captions removed",
+            ));
+        }
+
+        let client = self.get_edit_client()?;
+        let from_peer = InputPeer::from_dialog_id(from_dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(from_dialog_id))?;
+        let to_peer = InputPeer::from_dialog_id(dialog_id)
+            .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+
+        let sends: Vec<_> = message_ids
+            .iter()
+            .map(|_| {
+                (
+                    media::generate_random_id(),
+                    self.get_next_yet_unsent_message_id(),
+                )
+            })
+            .collect();
+        {
+            let mut pending_sends = self.pending_sends.lock();
+            for &(random_id, message_id) in &sends {
+                pending_sends.insert(
+                    random_id,
+                    PendingSend {
+                        dialog_id,
+                        message_id,
+                    },
+                );
+            }
+        }
+        self.add_forwarded_copies(dialog_id, from_dialog_id, message_ids, &sends, &options);
+
+        let mut request = ForwardMessagesRequest::new(
+            from_peer,
+            message_ids
+                .iter()
+                .map(|message_id| message_id.get_server_id())
+                .collect(),
+            sends.iter().map(|&(random_id, _)| random_id).collect(),
+            to_peer,
+        );
+        if options.send_copy() {
+            request = request.drop_author();
+        }
+        if options.replace_caption() {
+            request = request.drop_media_captions();
+        }
+
+        let result = client
+            .forward_messages(request)
+            .await
+            .map_err(MessagesManagerError::from)
+            .and_then(|updates| {
+                self.process_query_updates(updates, "forwardMessages");
+                sends
+                    .iter()
+                    .map(|&(_, message_id)| {
+                        self.get_sent_message_id(dialog_id, message_id)
+                            .ok_or_else(|| {
+                                MessagesManagerError::Generic(
+                                    "The server didn't assign an ID to a forwarded message"
+                                        .to_string(),
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            });
+
+        if let Err(error) = &result {
+            warn!("Failed to forward messages to {:?}: {}", dialog_id, error);
+            {
+                let mut pending_sends = self.pending_sends.lock();
+                for (random_id, _) in &sends {
+                    pending_sends.remove(random_id);
+                }
+            }
+            for &(_, message_id) in &sends {
+                self.local.lock().delete_message(dialog_id, message_id);
+                self.network_client.notify(|callback| {
+                    callback.on_message_send_failed(dialog_id, message_id, error)
+                });
+            }
+        }
+        result
+    }
+
+    /// Deletes messages of a dialog.
+    ///
+    /// Messages not yet sent are deleted only locally. With `revoke` the
+    /// messages are deleted for all participants of the dialog; messages of
+    /// channels are always deleted for everyone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the deletion; the deleted
+    /// messages are restored then and reported through
+    /// [`crate::MessageUpdateCallback::on_new_message`].
+    pub async fn delete_messages(
+        &self,
+        dialog_id: DialogId,
+        message_ids: &[MessageId],
+        revoke: bool,
+    ) -> Result<(), MessagesManagerError> {
+        info!(
+            "Deleting {} messages in {:?}, revoke: {}",
+            message_ids.len(),
+            dialog_id,
+            revoke
+        );
+
+        let server_ids: Vec<i32> = message_ids
+            .iter()
+            .filter(|message_id| message_id.is_server())
+            .map(|message_id| message_id.get_server_id())
+            .collect();
+        let request = if server_ids.is_empty() {
+            None
+        } else {
+            let client = self.get_edit_client()?;
+            let peer = InputPeer::from_dialog_id(dialog_id)
+                .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+            let mut request = DeleteMessagesRequest::new(peer, server_ids);
+            if revoke && !request.is_channel() {
+                request = request.revoke();
+            }
+            Some((client, request))
+        };
+
+        let deleted: Vec<Message> = {
+            let mut local = self.local.lock();
+            message_ids
+                .iter()
+                .filter_map(|&message_id| local.delete_message(dialog_id, message_id))
+                .collect()
+        };
+        let deleted_ids = deleted.iter().map(|message| message.id).collect::<Vec<_>>();
+        self.network_client
+            .notify(|callback| callback.on_messages_deleted(dialog_id, deleted_ids));
+
+        let Some((client, request)) = request else {
+            return Ok(());
+        };
+        match client.delete_messages(request).await {
+            Ok(affected) => {
+                debug!(
+                    "Deleted messages in {:?}, pts: {}, pts_count: {}",
+                    dialog_id, affected.pts, affected.pts_count
+                );
+                Ok(())
+            }
+            Err(error) => {
+                warn!("Failed to delete messages in {:?}: {}", dialog_id, error);
+                for message in deleted {
+                    self.local.lock().add_message(message.clone());
+                    self.network_client
+                        .notify(|callback| callback.on_new_message(message));
+                }
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Returns the client editing, forwarding and deleting messages.
+    fn get_edit_client(
+        &self,
+    ) -> Result<std::sync::Arc<dyn MessageEditClient>, MessagesManagerError> {
+        self.edit_client
+            .read()
+            .clone()
+            .ok_or_else(|| MessagesManagerError::Generic("Edit client is not set".to_string()))
+    }
+
+    /// Applies an edit locally, sends it to the server and rolls it back if
+    /// the server rejects it.
+    async fn edit_message(
+        &self,
+        dialog_id: DialogId,
+        message_id: MessageId,
+        edit: impl FnOnce(&mut Message) -> Result<(), MessagesManagerError>,
+        query: impl Future<Output = Result<Updates, MessagesManagerError>>,
+    ) -> Result<Message, MessagesManagerError> {
+        let old_message = self
+            .get_message(dialog_id, message_id)
+            .ok_or(MessagesManagerError::MessageNotFound(dialog_id, message_id))?;
+        let mut message = old_message.clone();
+        edit(&mut message)?;
+
+        self.local.lock().add_message(message.clone());
+        self.network_client
+            .notify(|callback| callback.on_message_edited(message.clone()));
+
+        match query.await {
+            Ok(updates) => {
+                self.process_query_updates(updates, "editMessage");
+            }
+            Err(MessagesManagerError::Network(SendMessageNetworkError::ServerError {
+                message: error_message,
+                ..
+            })) if error_message == "MESSAGE_NOT_MODIFIED" => {
+                debug!("{:?} in {:?} wasn't modified", message_id, dialog_id);
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to edit {:?} in {:?}: {}",
+                    message_id, dialog_id, error
+                );
+                self.local.lock().add_message(old_message.clone());
+                self.network_client
+                    .notify(|callback| callback.on_message_edited(old_message));
+                return Err(error);
+            }
+        }
+
+        Ok(self.get_message(dialog_id, message_id).unwrap_or(message))
+    }
+
+    /// Adds copies of the forwarded messages known locally to the target
+    /// dialog.
+    fn add_forwarded_copies(
+        &self,
+        dialog_id: DialogId,
+        from_dialog_id: DialogId,
+        message_ids: &[MessageId],
+        sends: &[(i64, MessageId)],
+        options: &MessageCopyOptions,
+    ) {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| {
+                i32::try_from(duration.as_secs()).unwrap_or(i32::MAX)
+            });
+
+        let mut local = self.local.lock();
+        for (&message_id, &(_, copy_id)) in message_ids.iter().zip(sends) {
+            let Some(mut copy) = local.get_message(from_dialog_id, message_id) else {
+                continue;
+            };
+            copy.id = copy_id;
+            copy.dialog_id = dialog_id;
+            copy.date = date;
+            copy.edit_date = None;
+            copy.views = None;
+            copy.reply_to = None;
+            copy.reply_markup = None;
+            if options.replace_caption() {
+                if let Some(caption) = copy.content.caption_mut() {
+                    *caption = FormattedText::default();
+                }
+            }
+            local.add_message(copy);
+        }
+    }
+
+    /// Applies the updates returned by a query.
+    ///
+    /// The query succeeded even if one of its updates can't be applied.
+    fn process_query_updates(&self, updates: Updates, query: &str) {
+        for update in updates.updates {
+            if let Err(error) = self.process_update(update) {
+                warn!("Failed to apply an update of {}: {}", query, error);
+            }
+        }
+    }
+}
+
+/// Creates a request for editing a server message.
+fn get_edit_message_request(
+    dialog_id: DialogId,
+    message_id: MessageId,
+) -> Result<EditMessageRequest, MessagesManagerError> {
+    if !message_id.is_server() {
+        return Err(MessagesManagerError::MessageNotFound(dialog_id, message_id));
+    }
+    let peer = InputPeer::from_dialog_id(dialog_id)
+        .map_err(|_| MessagesManagerError::DialogNotAccessible(dialog_id))?;
+    Ok(EditMessageRequest::new(peer, message_id.get_server_id()))
+}
+
+/// Sets the new text of an edit request with its entities.
+fn with_text(request: EditMessageRequest, text: &FormattedText) -> EditMessageRequest {
+    let entities = media::get_input_message_entities(text);
+    let request = request.with_message(text.text().to_string());
+    if entities.is_empty() {
+        request
+    } else {
+        request.with_entities(entities)
+    }
+}
+
+/// Returns a validation error for content that can't be changed as asked.
+fn invalid_content(reason: &str) -> MessagesManagerError {
+    MessageValidationError::InvalidContent(reason.to_string()).into()
+}
@@ -0,0 +1,2122 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! TL client sending the message queries to Telegram.
+//!
+//! [`NetworkMessagesClient`] implements [`MediaSendClient`],
+//! [`MessageEditClient`] and [`MessageHistoryClient`] by encoding the
+//! requests with the constructors of the current API layer and sending them
+//! through a [`NetQueryDispatcher`]. The returned `Updates` and
+//! `messages.Messages` are parsed into [`Updates`] and [`Messages`], with
+//! the messages read by
+//! [`read_server_message`](rustgram_message_query_manager::read_server_message).
+//!
+//! Only the updates describing messages are parsed. An update of another
+//! kind can't be skipped, so the updates after it are dropped, as are the
+//! users and chats of the response; `date` and `seq` of the parsed
+//! [`Updates`] are left zero.
+//!
+//! # TDLib Alignment
+//!
+//! Based on `SendMediaQuery`, `SendMultiMediaQuery`, `EditMessageQuery`,
+//! `ForwardMessagesQuery`, `DeleteMessagesQuery`,
+//! `DeleteChannelMessagesQuery`, `GetHistoryQuery`, `ReadHistoryQuery`,
+//! `ReadChannelHistoryQuery` and the part uploads of `FileUploader` from
+//! TDLib.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use parking_lot::RwLock;
+use rustgram_message_query_manager::{
+    read_server_message, ServerDocumentAttribute, ServerMessage, ServerMessageMedia,
+};
+use rustgram_net::{
+    AuthFlag, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryType, QueryError,
+};
+use rustgram_types::tl::Bytes as TlBytes;
+use rustgram_types::{ChannelId, ChatId, DialogId, TlHelper, UserId};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use rustgram_reply_markup::{InlineKeyboardButton, KeyboardButton, ReplyMarkup};
+
+use crate::network::{
+    MediaSendClient, MessageEditClient, MessageHistoryClient, SendMessageNetworkError,
+};
+use crate::tl_types::{
+    AffectedMessages, DeleteMessagesRequest, DocumentAttribute, EditMessageRequest,
+    ForwardMessagesRequest, GetHistoryRequest, InputFile, InputGeoPoint, InputMedia, InputPeer,
+    InputSingleMedia, MessageData, MessageEntity, MessageMedia, Messages, OtherUpdate, PollData,
+    ReadHistoryRequest, SaveFilePartRequest, SendMediaRequest, SendMultiMediaRequest,
+    TlSerializationError, Update, UpdateDeleteMessages, UpdateEditMessage, UpdateMessageId,
+    UpdateNewMessage, Updates,
+};
+
+/// Timeout of a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request constructors
+const TL_UPLOAD_SAVE_FILE_PART: u32 = 0xb304_a621;
+const TL_UPLOAD_SAVE_BIG_FILE_PART: u32 = 0xde7b_673d;
+/// `messages.sendMedia` with `suggested_post`
+const TL_MESSAGES_SEND_MEDIA: u32 = 0xac55_d9c1;
+/// `messages.sendMultiMedia` with `allow_paid_stars`
+const TL_MESSAGES_SEND_MULTI_MEDIA: u32 = 0x1bf8_9d74;
+/// `messages.editMessage` with `schedule_repeat_period`
+const TL_MESSAGES_EDIT_MESSAGE: u32 = 0xdfd1_4005;
+const TL_MESSAGES_FORWARD_MESSAGES: u32 = 0xd503_9208;
+const TL_MESSAGES_DELETE_MESSAGES: u32 = 0xe58e_95d2;
+const TL_CHANNELS_DELETE_MESSAGES: u32 = 0x84c1_fd4e;
+const TL_MESSAGES_GET_HISTORY: u32 = 0x4423_e6c5;
+const TL_MESSAGES_READ_HISTORY: u32 = 0x0e30_6d3a;
+const TL_CHANNELS_READ_HISTORY: u32 = 0xcc10_4937;
+
+/// Argument constructors
+const TL_VECTOR: u32 = 0x1cb5_c415;
+const TL_INPUT_PEER_EMPTY: u32 = 0x7f3b_18ea;
+const TL_INPUT_PEER_SELF: u32 = 0x7da0_7ec9;
+const TL_INPUT_PEER_CHAT: u32 = 0x35a9_5cb9;
+const TL_INPUT_PEER_USER: u32 = 0xdde8_a54c;
+const TL_INPUT_PEER_CHANNEL: u32 = 0x27bc_bbfc;
+const TL_INPUT_USER_SELF: u32 = 0xf7c1_b13f;
+const TL_INPUT_USER: u32 = 0xf211_58c6;
+const TL_INPUT_CHANNEL: u32 = 0xf35a_ec28;
+/// `inputReplyToMessage` with `todo_item_id`
+const TL_INPUT_REPLY_TO_MESSAGE: u32 = 0x869f_be10;
+const TL_INPUT_FILE: u32 = 0xf52f_f27f;
+const TL_INPUT_FILE_BIG: u32 = 0xfa4f_0bb5;
+const TL_INPUT_MEDIA_UPLOADED_PHOTO: u32 = 0x1e28_7d04;
+/// `inputMediaUploadedDocument` with `video_cover`
+const TL_INPUT_MEDIA_UPLOADED_DOCUMENT: u32 = 0x037c_9330;
+const TL_INPUT_MEDIA_PHOTO: u32 = 0xb3ba_0635;
+/// `inputMediaDocument` with `video_cover`
+const TL_INPUT_MEDIA_DOCUMENT: u32 = 0xa876_3ab5;
+const TL_INPUT_MEDIA_GEO_POINT: u32 = 0xf9c4_4144;
+const TL_INPUT_MEDIA_VENUE: u32 = 0xc13d_1c11;
+const TL_INPUT_MEDIA_CONTACT: u32 = 0xf8ab_7dfb;
+const TL_INPUT_MEDIA_POLL: u32 = 0x0f94_e5f1;
+const TL_INPUT_PHOTO: u32 = 0x3bb3_b94a;
+const TL_INPUT_DOCUMENT: u32 = 0x1abf_b575;
+const TL_INPUT_GEO_POINT: u32 = 0x4822_2faf;
+const TL_INPUT_SINGLE_MEDIA: u32 = 0x1cc6_e91f;
+const TL_INPUT_STICKER_SET_EMPTY: u32 = 0xffb6_2b95;
+const TL_POLL: u32 = 0x5874_7131;
+const TL_POLL_ANSWER: u32 = 0xff16_e2ca;
+const TL_TEXT_WITH_ENTITIES: u32 = 0x751f_3146;
+const TL_DOCUMENT_ATTRIBUTE_VIDEO: u32 = 0x43c5_7c48;
+const TL_DOCUMENT_ATTRIBUTE_AUDIO: u32 = 0x9852_f9c6;
+const TL_DOCUMENT_ATTRIBUTE_ANIMATED: u32 = 0x11b5_8939;
+const TL_DOCUMENT_ATTRIBUTE_STICKER: u32 = 0x6319_d612;
+const TL_DOCUMENT_ATTRIBUTE_FILENAME: u32 = 0x1559_0068;
+const TL_MESSAGE_ENTITY_BOLD: u32 = 0xbd61_0bc9;
+const TL_MESSAGE_ENTITY_ITALIC: u32 = 0x826f_8b60;
+const TL_MESSAGE_ENTITY_UNDERLINE: u32 = 0x9c4e_7e8b;
+const TL_MESSAGE_ENTITY_STRIKE: u32 = 0xbf06_93d4;
+const TL_MESSAGE_ENTITY_CODE: u32 = 0x28a2_0571;
+const TL_MESSAGE_ENTITY_PRE: u32 = 0x7392_4be0;
+const TL_MESSAGE_ENTITY_TEXT_URL: u32 = 0x76a6_d327;
+const TL_INPUT_MESSAGE_ENTITY_MENTION_NAME: u32 = 0x208e_68c9;
+const TL_MESSAGE_ENTITY_HASHTAG: u32 = 0x6f63_5b0d;
+const TL_MESSAGE_ENTITY_CASHTAG: u32 = 0x4c4e_743f;
+const TL_MESSAGE_ENTITY_BOT_COMMAND: u32 = 0x6cef_8ac7;
+const TL_MESSAGE_ENTITY_CUSTOM_EMOJI: u32 = 0xc8cf_05f8;
+const TL_REPLY_KEYBOARD_HIDE: u32 = 0xa03e_5b85;
+const TL_REPLY_KEYBOARD_FORCE_REPLY: u32 = 0x86b4_0b08;
+const TL_REPLY_KEYBOARD_MARKUP: u32 = 0x85dd_99d1;
+const TL_REPLY_INLINE_MARKUP: u32 = 0x48a3_0254;
+const TL_KEYBOARD_BUTTON_ROW: u32 = 0x7760_8b83;
+const TL_KEYBOARD_BUTTON: u32 = 0xa2fa_4880;
+const TL_KEYBOARD_BUTTON_URL: u32 = 0x258a_ff05;
+const TL_KEYBOARD_BUTTON_CALLBACK: u32 = 0x35bb_db6b;
+const TL_KEYBOARD_BUTTON_REQUEST_PHONE: u32 = 0xb16a_6c29;
+const TL_KEYBOARD_BUTTON_REQUEST_GEO_LOCATION: u32 = 0xfc79_6b3f;
+const TL_KEYBOARD_BUTTON_SWITCH_INLINE: u32 = 0x93b9_fbb5;
+const TL_KEYBOARD_BUTTON_REQUEST_POLL: u32 = 0xbbc7_515d;
+const TL_INPUT_KEYBOARD_BUTTON_USER_PROFILE: u32 = 0xe988_037b;
+const TL_KEYBOARD_BUTTON_WEB_VIEW: u32 = 0x1376_7230;
+const TL_KEYBOARD_BUTTON_SIMPLE_WEB_VIEW: u32 = 0xa0c0_505c;
+
+/// Response constructors
+const TL_BOOL_TRUE: u32 = 0x9972_75b5;
+const TL_BOOL_FALSE: u32 = 0xbc79_9737;
+const TL_MESSAGES_AFFECTED_MESSAGES: u32 = 0x84d1_9185;
+const TL_MESSAGES_MESSAGES: u32 = 0x8c71_8e87;
+const TL_MESSAGES_MESSAGES_SLICE: u32 = 0x3a54_685e;
+const TL_MESSAGES_CHANNEL_MESSAGES: u32 = 0xc776_ba4e;
+const TL_MESSAGES_MESSAGES_NOT_MODIFIED: u32 = 0x7453_5f21;
+const TL_UPDATES_TOO_LONG: u32 = 0xe317_af7e;
+const TL_UPDATE_SHORT_MESSAGE: u32 = 0x313b_c7f8;
+const TL_UPDATE_SHORT_CHAT_MESSAGE: u32 = 0x4d6d_eea5;
+const TL_UPDATE_SHORT: u32 = 0x78d4_dec1;
+const TL_UPDATES_COMBINED: u32 = 0x725b_04c3;
+const TL_UPDATES: u32 = 0x74ae_4240;
+const TL_UPDATE_SHORT_SENT_MESSAGE: u32 = 0x9015_e101;
+const TL_UPDATE_NEW_MESSAGE: u32 = 0x1f2b_0afd;
+const TL_UPDATE_MESSAGE_ID: u32 = 0x4e90_bfd6;
+const TL_UPDATE_DELETE_MESSAGES: u32 = 0xa20d_b0e5;
+const TL_UPDATE_NEW_CHANNEL_MESSAGE: u32 = 0x62ba_04d9;
+const TL_UPDATE_EDIT_MESSAGE: u32 = 0xe403_70a3;
+const TL_UPDATE_EDIT_CHANNEL_MESSAGE: u32 = 0x1b3f_4df7;
+const TL_UPDATE_DELETE_CHANNEL_MESSAGES: u32 = 0xc32d_5b12;
+const TL_UPDATE_READ_HISTORY_INBOX: u32 = 0x9e84_bc99;
+const TL_UPDATE_READ_HISTORY_OUTBOX: u32 = 0x2f2f_21bf;
+const TL_UPDATE_READ_CHANNEL_INBOX: u32 = 0x922e_6e10;
+const TL_UPDATE_READ_CHANNEL_OUTBOX: u32 = 0xb75f_99a9;
+const TL_PEER_USER: u32 = 0x5951_1722;
+const TL_PEER_CHAT: u32 = 0x36c6_019a;
+const TL_PEER_CHANNEL: u32 = 0xa2a5_371e;
+
+/// Client sending the message queries to Telegram.
+///
+/// The requests address users and channels by their access hashes, which
+/// must be set with [`set_access_hash`](Self::set_access_hash) before sending
+/// to them. Requests to a dialog without a known access hash fail with
+/// [`SendMessageNetworkError::DialogNotAccessible`]; the current user is
+/// always addressed as `inputPeerSelf`.
+pub struct NetworkMessagesClient {
+    /// Dispatcher sending the requests
+    dispatcher: Arc<NetQueryDispatcher>,
+
+    /// Identifier of the current user
+    my_user_id: UserId,
+
+    /// Identifier of the next query
+    next_query_id: AtomicU64,
+
+    /// Known access hashes of users and channels
+    access_hashes: RwLock<HashMap<DialogId, i64>>,
+}
+
+impl NetworkMessagesClient {
+    /// Creates a client sending the requests of `my_user_id` through the
+    /// dispatcher.
+    pub fn new(dispatcher: Arc<NetQueryDispatcher>, my_user_id: UserId) -> Self {
+        Self {
+            dispatcher,
+            my_user_id,
+            next_query_id: AtomicU64::new(1),
+            access_hashes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Remembers the access hash of a user or a channel.
+    pub fn set_access_hash(&self, dialog_id: DialogId, access_hash: i64) {
+        self.access_hashes.write().insert(dialog_id, access_hash);
+    }
+
+    /// Returns the access hash of a user or a channel, if it is known.
+    fn get_access_hash(&self, dialog_id: DialogId) -> Option<i64> {
+        self.access_hashes.read().get(&dialog_id).copied()
+    }
+
+    /// Returns the `InputPeer` of a request with the access hash filled in.
+    fn get_input_peer(&self, peer: &InputPeer) -> Result<TlInputPeer, SendMessageNetworkError> {
+        match *peer {
+            InputPeer::Empty => Ok(TlInputPeer::Empty),
+            InputPeer::SelfUser => Ok(TlInputPeer::SelfUser),
+            InputPeer::Chat { chat_id } => Ok(TlInputPeer::Chat(chat_id)),
+            InputPeer::User { user_id } if user_id == self.my_user_id.get() => {
+                Ok(TlInputPeer::SelfUser)
+            }
+            InputPeer::User { user_id } => {
+                let dialog_id = DialogId::from_user(UserId::new(user_id).map_err(invalid_peer)?);
+                let access_hash = self
+                    .get_access_hash(dialog_id)
+                    .ok_or(SendMessageNetworkError::DialogNotAccessible(dialog_id))?;
+                Ok(TlInputPeer::User(user_id, access_hash))
+            }
+            InputPeer::UserFrom {
+                user_id,
+                access_hash,
+            } => Ok(TlInputPeer::User(user_id, access_hash)),
+            InputPeer::Channel {
+                channel_id,
+                access_hash,
+            }
+            | InputPeer::ChannelFrom {
+                channel_id,
+                access_hash,
+            } => {
+                if access_hash != 0 {
+                    return Ok(TlInputPeer::Channel(channel_id, access_hash));
+                }
+                let channel_id = ChannelId::new(channel_id).map_err(invalid_peer)?;
+                let dialog_id = DialogId::from_channel(channel_id);
+                let access_hash = self
+                    .get_access_hash(dialog_id)
+                    .ok_or(SendMessageNetworkError::DialogNotAccessible(dialog_id))?;
+                Ok(TlInputPeer::Channel(channel_id.get(), access_hash))
+            }
+        }
+    }
+
+    /// Writes `Vector<MessageEntity>` of a text.
+    ///
+    /// Mentions of users without a known access hash can't be sent and are
+    /// left out.
+    fn write_entities(&self, buf: &mut BytesMut, entities: &[MessageEntity]) {
+        let entities: Vec<_> = entities
+            .iter()
+            .filter_map(|entity| match *entity {
+                MessageEntity::Mention { user_id, .. } => match self.get_input_user(user_id) {
+                    Some(input_user) => Some((entity, Some(input_user))),
+                    None => {
+                        debug!("Dropping the mention of inaccessible user {}", user_id);
+                        None
+                    }
+                },
+                _ => Some((entity, None)),
+            })
+            .collect();
+
+        TlHelper::write_constructor_id(buf, TL_VECTOR);
+        TlHelper::write_i32(buf, entities.len() as i32);
+        for (entity, input_user) in entities {
+            write_entity(buf, entity, input_user);
+        }
+    }
+
+    /// Returns the `InputUser` of a mentioned user, if it can be addressed.
+    fn get_input_user(&self, user_id: i64) -> Option<TlInputUser> {
+        if user_id == self.my_user_id.get() {
+            return Some(TlInputUser::SelfUser);
+        }
+        let dialog_id = DialogId::from_user(UserId::new(user_id).ok()?);
+        let access_hash = self.get_access_hash(dialog_id)?;
+        Some(TlInputUser::User(user_id, access_hash))
+    }
+
+    /// Encodes `messages.sendMedia`
+    fn encode_send_media(
+        &self,
+        request: &SendMediaRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let peer = self.get_input_peer(&request.peer)?;
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_SEND_MEDIA);
+        // flags.0: reply_to, flags.3: entities, flags.5: silent,
+        // flags.10: schedule_date
+        let mut flags = 0;
+        if request.reply_to_msg_id.is_some() {
+            flags |= 1 << 0;
+        }
+        if request.entities.is_some() {
+            flags |= 1 << 3;
+        }
+        if request.silent == Some(true) {
+            flags |= 1 << 5;
+        }
+        if request.schedule_date.is_some() {
+            flags |= 1 << 10;
+        }
+        TlHelper::write_i32(&mut buf, flags);
+        peer.write(&mut buf);
+        if let Some(reply_to_msg_id) = request.reply_to_msg_id {
+            write_reply_to(&mut buf, reply_to_msg_id);
+        }
+        write_input_media(&mut buf, &request.media);
+        TlHelper::write_string(&mut buf, &request.message);
+        TlHelper::write_i64(&mut buf, request.random_id);
+        if let Some(entities) = &request.entities {
+            self.write_entities(&mut buf, entities);
+        }
+        if let Some(schedule_date) = request.schedule_date {
+            TlHelper::write_i32(&mut buf, schedule_date);
+        }
+        Ok(buf)
+    }
+
+    /// Encodes `messages.sendMultiMedia`
+    fn encode_send_multi_media(
+        &self,
+        request: &SendMultiMediaRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let peer = self.get_input_peer(&request.peer)?;
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_SEND_MULTI_MEDIA);
+        // flags.0: reply_to, flags.5: silent, flags.10: schedule_date
+        let mut flags = 0;
+        if request.reply_to_msg_id.is_some() {
+            flags |= 1 << 0;
+        }
+        if request.silent == Some(true) {
+            flags |= 1 << 5;
+        }
+        if request.schedule_date.is_some() {
+            flags |= 1 << 10;
+        }
+        TlHelper::write_i32(&mut buf, flags);
+        peer.write(&mut buf);
+        if let Some(reply_to_msg_id) = request.reply_to_msg_id {
+            write_reply_to(&mut buf, reply_to_msg_id);
+        }
+        TlHelper::write_constructor_id(&mut buf, TL_VECTOR);
+        TlHelper::write_i32(&mut buf, request.multi_media.len() as i32);
+        for item in &request.multi_media {
+            self.write_single_media(&mut buf, item);
+        }
+        if let Some(schedule_date) = request.schedule_date {
+            TlHelper::write_i32(&mut buf, schedule_date);
+        }
+        Ok(buf)
+    }
+
+    /// Writes `inputSingleMedia`
+    fn write_single_media(&self, buf: &mut BytesMut, item: &InputSingleMedia) {
+        TlHelper::write_constructor_id(buf, TL_INPUT_SINGLE_MEDIA);
+        // flags.0: entities
+        TlHelper::write_i32(buf, i32::from(item.entities.is_some()));
+        write_input_media(buf, &item.media);
+        TlHelper::write_i64(buf, item.random_id);
+        TlHelper::write_string(buf, &item.message);
+        if let Some(entities) = &item.entities {
+            self.write_entities(buf, entities);
+        }
+    }
+
+    /// Encodes `messages.editMessage`
+    fn encode_edit_message(
+        &self,
+        request: &EditMessageRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let peer = self.get_input_peer(&request.peer)?;
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_EDIT_MESSAGE);
+        // flags.2: reply_markup, flags.3: entities, flags.11: message,
+        // flags.14: media
+        let mut flags = 0;
+        if request.reply_markup.is_some() {
+            flags |= 1 << 2;
+        }
+        if request.entities.is_some() {
+            flags |= 1 << 3;
+        }
+        if request.message.is_some() {
+            flags |= 1 << 11;
+        }
+        if request.media.is_some() {
+            flags |= 1 << 14;
+        }
+        TlHelper::write_i32(&mut buf, flags);
+        peer.write(&mut buf);
+        TlHelper::write_i32(&mut buf, request.id);
+        if let Some(message) = &request.message {
+            TlHelper::write_string(&mut buf, message);
+        }
+        if let Some(media) = &request.media {
+            write_input_media(&mut buf, media);
+        }
+        if let Some(reply_markup) = &request.reply_markup {
+            self.write_reply_markup(&mut buf, reply_markup)?;
+        }
+        if let Some(entities) = &request.entities {
+            self.write_entities(&mut buf, entities);
+        }
+        Ok(buf)
+    }
+
+    /// Encodes `messages.forwardMessages`
+    fn encode_forward_messages(
+        &self,
+        request: &ForwardMessagesRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let from_peer = self.get_input_peer(&request.from_peer)?;
+        let to_peer = self.get_input_peer(&request.to_peer)?;
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_FORWARD_MESSAGES);
+        // flags.5: silent, flags.11: drop_author, flags.12: drop_media_captions
+        let mut flags = 0;
+        if request.silent == Some(true) {
+            flags |= 1 << 5;
+        }
+        if request.drop_author == Some(true) {
+            flags |= 1 << 11;
+        }
+        if request.drop_media_captions == Some(true) {
+            flags |= 1 << 12;
+        }
+        TlHelper::write_i32(&mut buf, flags);
+        from_peer.write(&mut buf);
+        write_int_vector(&mut buf, &request.id);
+        TlHelper::write_constructor_id(&mut buf, TL_VECTOR);
+        TlHelper::write_i32(&mut buf, request.random_id.len() as i32);
+        for &random_id in &request.random_id {
+            TlHelper::write_i64(&mut buf, random_id);
+        }
+        to_peer.write(&mut buf);
+        Ok(buf)
+    }
+
+    /// Encodes `channels.deleteMessages` for messages of a channel and
+    /// `messages.deleteMessages` for the others
+    fn encode_delete_messages(
+        &self,
+        request: &DeleteMessagesRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let mut buf = BytesMut::new();
+        if request.is_channel() {
+            let TlInputPeer::Channel(channel_id, access_hash) =
+                self.get_input_peer(&request.peer)?
+            else {
+                return Err(invalid_peer("not a channel"));
+            };
+            TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_DELETE_MESSAGES);
+            TlHelper::write_constructor_id(&mut buf, TL_INPUT_CHANNEL);
+            TlHelper::write_i64(&mut buf, channel_id);
+            TlHelper::write_i64(&mut buf, access_hash);
+        } else {
+            TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_DELETE_MESSAGES);
+            // flags.0: revoke
+            TlHelper::write_i32(&mut buf, i32::from(request.revoke == Some(true)));
+        }
+        write_int_vector(&mut buf, &request.id);
+        Ok(buf)
+    }
+
+    /// Encodes `messages.getHistory`
+    fn encode_get_history(
+        &self,
+        request: &GetHistoryRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let mut buf = BytesMut::new();
+        TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_GET_HISTORY);
+        self.get_input_peer(&request.peer)?.write(&mut buf);
+        TlHelper::write_i32(&mut buf, request.offset_id);
+        TlHelper::write_i32(&mut buf, request.offset_date);
+        TlHelper::write_i32(&mut buf, request.add_offset);
+        TlHelper::write_i32(&mut buf, request.limit);
+        TlHelper::write_i32(&mut buf, request.max_id);
+        TlHelper::write_i32(&mut buf, request.min_id);
+        TlHelper::write_i64(&mut buf, request.hash);
+        Ok(buf)
+    }
+
+    /// Encodes `channels.readHistory` for a channel and
+    /// `messages.readHistory` for the other dialogs
+    fn encode_read_history(
+        &self,
+        request: &ReadHistoryRequest,
+    ) -> Result<BytesMut, SendMessageNetworkError> {
+        let mut buf = BytesMut::new();
+        match self.get_input_peer(&request.peer)? {
+            TlInputPeer::Channel(channel_id, access_hash) => {
+                TlHelper::write_constructor_id(&mut buf, TL_CHANNELS_READ_HISTORY);
+                TlHelper::write_constructor_id(&mut buf, TL_INPUT_CHANNEL);
+                TlHelper::write_i64(&mut buf, channel_id);
+                TlHelper::write_i64(&mut buf, access_hash);
+            }
+            peer => {
+                TlHelper::write_constructor_id(&mut buf, TL_MESSAGES_READ_HISTORY);
+                peer.write(&mut buf);
+            }
+        }
+        TlHelper::write_i32(&mut buf, request.max_id);
+        Ok(buf)
+    }
+
+    /// Writes `ReplyMarkup`
+    ///
+    /// Buttons that can't be described by the server, like login URLs
+    /// without the bot, make the request fail.
+    fn write_reply_markup(
+        &self,
+        buf: &mut BytesMut,
+        reply_markup: &ReplyMarkup,
+    ) -> Result<(), SendMessageNetworkError> {
+        match reply_markup {
+            ReplyMarkup::RemoveKeyboard { is_personal } => {
+                TlHelper::write_constructor_id(buf, TL_REPLY_KEYBOARD_HIDE);
+                // flags.2: selective
+                TlHelper::write_i32(buf, i32::from(*is_personal) << 2);
+            }
+            ReplyMarkup::ForceReply {
+                input_field_placeholder,
+                ..
+            } => {
+                TlHelper::write_constructor_id(buf, TL_REPLY_KEYBOARD_FORCE_REPLY);
+                // flags.3: placeholder
+                let has_placeholder = !input_field_placeholder.is_empty();
+                TlHelper::write_i32(buf, i32::from(has_placeholder) << 3);
+                if has_placeholder {
+                    TlHelper::write_string(buf, input_field_placeholder);
+                }
+            }
+            ReplyMarkup::ShowKeyboard {
+                rows,
+                resize,
+                one_time,
+                is_personal,
+                input_field_placeholder,
+            } => write_keyboard(
+                buf,
+                rows,
+                *resize,
+                *one_time,
+                *is_personal,
+                input_field_placeholder,
+            )?,
+            ReplyMarkup::ReplyKeyboard(keyboard) => write_keyboard(
+                buf,
+                keyboard.rows(),
+                keyboard.resize(),
+                keyboard.one_time(),
+                keyboard.is_personal(),
+                "",
+            )?,
+            ReplyMarkup::InlineKeyboard(keyboard) => {
+                TlHelper::write_constructor_id(buf, TL_REPLY_INLINE_MARKUP);
+                TlHelper::write_constructor_id(buf, TL_VECTOR);
+                TlHelper::write_i32(buf, keyboard.rows().len() as i32);
+                for row in keyboard.rows() {
+                    TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_ROW);
+                    TlHelper::write_constructor_id(buf, TL_VECTOR);
+                    TlHelper::write_i32(buf, row.len() as i32);
+                    for button in row {
+                        self.write_inline_keyboard_button(buf, button)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the `KeyboardButton` of an inline keyboard
+    fn write_inline_keyboard_button(
+        &self,
+        buf: &mut BytesMut,
+        button: &InlineKeyboardButton,
+    ) -> Result<(), SendMessageNetworkError> {
+        match button {
+            InlineKeyboardButton::Callback { text, data }
+            | InlineKeyboardButton::CallbackWithPassword { text, data } => {
+                TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_CALLBACK);
+                // flags.0: requires_password
+                let requires_password =
+                    matches!(button, InlineKeyboardButton::CallbackWithPassword { .. });
+                TlHelper::write_i32(buf, i32::from(requires_password));
+                TlHelper::write_string(buf, text);
+                TlHelper::write_bytes(buf, data);
+            }
+            InlineKeyboardButton::SwitchInline {
+                text,
+                query_in_current_chat,
+                query,
+            } => {
+                TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_SWITCH_INLINE);
+                // flags.0: same_peer
+                TlHelper::write_i32(buf, i32::from(*query_in_current_chat));
+                TlHelper::write_string(buf, text);
+                TlHelper::write_string(buf, query);
+            }
+            InlineKeyboardButton::Url { text, url, .. } => {
+                TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_URL);
+                TlHelper::write_string(buf, text);
+                TlHelper::write_string(buf, url);
+            }
+            InlineKeyboardButton::UserProfile { text, user_id } => {
+                let input_user = self.get_input_user(*user_id).ok_or_else(|| {
+                    unsupported_button(format_args!("profile of inaccessible user {user_id}"))
+                })?;
+                TlHelper::write_constructor_id(buf, TL_INPUT_KEYBOARD_BUTTON_USER_PROFILE);
+                TlHelper::write_string(buf, text);
+                input_user.write(buf);
+            }
+            InlineKeyboardButton::WebApp { text, url } => {
+                TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_WEB_VIEW);
+                TlHelper::write_string(buf, text);
+                TlHelper::write_string(buf, url);
+            }
+            InlineKeyboardButton::Buy
+            | InlineKeyboardButton::CallbackGame
+            | InlineKeyboardButton::LoginUrl { .. } => {
+                return Err(unsupported_button(format_args!("{:?}", button)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a request to the main DC and waits for its result
+    async fn invoke(&self, request: BytesMut) -> Result<TlBytes, SendMessageNetworkError> {
+        let request = request.freeze();
+        let constructor = request
+            .get(..4)
+            .map(|id| i32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            .unwrap_or(0);
+        let query = NetQuery::new(
+            self.next_query_id.fetch_add(1, Ordering::Relaxed),
+            request,
+            self.dispatcher.main_dc_id(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::Off,
+            constructor,
+        );
+
+        let (sender, receiver) = oneshot::channel();
+        query.set_callback(Box::new(ResultCallback {
+            sender: parking_lot::Mutex::new(Some(sender)),
+        }));
+        self.dispatcher.dispatch(query)?;
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, receiver).await {
+            Ok(Ok(Ok(result))) => Ok(TlBytes::new(result)),
+            Ok(Ok(Err(QueryError::WithMessage { code, message }))) => {
+                Err(SendMessageNetworkError::from_rpc_error(code, &message))
+            }
+            Ok(Ok(Err(error))) => Err(error.into()),
+            Ok(Err(_)) => Err(SendMessageNetworkError::Canceled),
+            Err(_) => Err(SendMessageNetworkError::Timeout(REQUEST_TIMEOUT)),
+        }
+    }
+
+    /// Parses `Updates`
+    ///
+    /// `sent` describes the message of the request, which is needed for
+    /// `updateShortSentMessage`.
+    fn parse_updates(
+        &self,
+        buf: &mut TlBytes,
+        sent: Option<SentMessage<'_>>,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        let updates = match read_u32(buf)? {
+            TL_UPDATES_TOO_LONG => Vec::new(),
+            TL_UPDATE_SHORT_MESSAGE => {
+                let flags = read_i32(buf)?;
+                let id = read_i32(buf)?;
+                let user_id = read_i64(buf)?;
+                let dialog_id = user_dialog_id(user_id)?;
+                let sender_id = if flags & (1 << 1) != 0 {
+                    DialogId::from_user(self.my_user_id)
+                } else {
+                    dialog_id
+                };
+                vec![read_short_message(buf, id, dialog_id, sender_id, flags)?]
+            }
+            TL_UPDATE_SHORT_CHAT_MESSAGE => {
+                let flags = read_i32(buf)?;
+                let id = read_i32(buf)?;
+                let sender_id = user_dialog_id(read_i64(buf)?)?;
+                let chat_id = ChatId::new(read_i64(buf)?).map_err(malformed)?;
+                let dialog_id = DialogId::from_chat(chat_id);
+                vec![read_short_message(buf, id, dialog_id, sender_id, flags)?]
+            }
+            TL_UPDATE_SHORT => self.read_update(buf)?.into_iter().collect(),
+            TL_UPDATES_COMBINED | TL_UPDATES => self.read_updates(buf)?,
+            TL_UPDATE_SHORT_SENT_MESSAGE => {
+                let sent = sent.ok_or_else(|| malformed("unexpected updateShortSentMessage"))?;
+                let _flags = read_i32(buf)?;
+                let id = read_i32(buf)?;
+                let pts = read_i32(buf)?;
+                let pts_count = read_i32(buf)?;
+                let date = read_i32(buf)?;
+                let dialog_id = sent.peer.to_encoded();
+                let sender_id = DialogId::from_user(self.my_user_id).to_encoded();
+                let mut message =
+                    MessageData::new(id, dialog_id, sender_id, date, sent.text.to_string());
+                message.flags = 1 << 1;
+                vec![
+                    Update::MessageId(UpdateMessageId {
+                        id,
+                        random_id: sent.random_id,
+                    }),
+                    Update::NewMessage(UpdateNewMessage::new(message, pts, pts_count)),
+                ]
+            }
+            id => return Err(malformed(format_args!("Updates {:#010x}", id))),
+        };
+        Ok(Updates::new(updates, 0, 0))
+    }
+
+    /// Parses `messages.Messages`
+    ///
+    /// `messageEmpty` is left out. Service messages are kept with
+    /// unsupported content, so that they are known to exist.
+    fn parse_messages(&self, buf: &mut TlBytes) -> Result<Messages, SendMessageNetworkError> {
+        let count = match read_u32(buf)? {
+            TL_MESSAGES_MESSAGES => None,
+            TL_MESSAGES_MESSAGES_SLICE => {
+                let flags = read_i32(buf)?;
+                let count = read_i32(buf)?;
+                if flags & (1 << 0) != 0 {
+                    // next_rate
+                    read_i32(buf)?;
+                }
+                if flags & (1 << 2) != 0 {
+                    // offset_id_offset
+                    read_i32(buf)?;
+                }
+                if flags & (1 << 3) != 0 {
+                    return Err(malformed("messages.messagesSlice with search_flood"));
+                }
+                Some(count)
+            }
+            TL_MESSAGES_CHANNEL_MESSAGES => {
+                let flags = read_i32(buf)?;
+                // pts
+                read_i32(buf)?;
+                let count = read_i32(buf)?;
+                if flags & (1 << 2) != 0 {
+                    // offset_id_offset
+                    read_i32(buf)?;
+                }
+                Some(count)
+            }
+            TL_MESSAGES_MESSAGES_NOT_MODIFIED => {
+                let count = read_i32(buf)?;
+                return Ok(Messages {
+                    messages: Vec::new(),
+                    count: Some(count),
+                });
+            }
+            id => return Err(malformed(format_args!("messages.Messages {:#010x}", id))),
+        };
+
+        let id = read_u32(buf)?;
+        if id != TL_VECTOR {
+            return Err(malformed(format_args!("Vector {:#010x}", id)));
+        }
+        let length = read_i32(buf)?;
+        let mut messages = Vec::new();
+        for _ in 0..length {
+            if let Some(message) = read_server_message(buf).map_err(malformed)? {
+                messages.push(self.get_message_data(message));
+            }
+        }
+        // The chats and users of the response are left unread
+        Ok(Messages { messages, count })
+    }
+
+    /// Reads `Vector<Update>`, stopping at the first unknown update
+    fn read_updates(&self, buf: &mut TlBytes) -> Result<Vec<Update>, SendMessageNetworkError> {
+        let id = read_u32(buf)?;
+        if id != TL_VECTOR {
+            return Err(malformed(format_args!("Vector {:#010x}", id)));
+        }
+        let count = read_i32(buf)?;
+        let mut updates = Vec::new();
+        for _ in 0..count {
+            match self.read_update(buf)? {
+                Some(update) => updates.push(update),
+                None => break,
+            }
+        }
+        Ok(updates)
+    }
+
+    /// Reads an `Update`
+    ///
+    /// Returns `None` for an update of an unknown kind, which can't be
+    /// skipped.
+    fn read_update(&self, buf: &mut TlBytes) -> Result<Option<Update>, SendMessageNetworkError> {
+        let update = match read_u32(buf)? {
+            TL_UPDATE_NEW_MESSAGE | TL_UPDATE_NEW_CHANNEL_MESSAGE => {
+                let message = read_server_message(buf).map_err(malformed)?;
+                let pts = read_i32(buf)?;
+                let pts_count = read_i32(buf)?;
+                match message.filter(|message| !message.is_service) {
+                    Some(message) => Update::NewMessage(UpdateNewMessage::new(
+                        self.get_message_data(message),
+                        pts,
+                        pts_count,
+                    )),
+                    None => other_update("updateNewMessage"),
+                }
+            }
+            TL_UPDATE_EDIT_MESSAGE | TL_UPDATE_EDIT_CHANNEL_MESSAGE => {
+                let message = read_server_message(buf).map_err(malformed)?;
+                let pts = read_i32(buf)?;
+                let pts_count = read_i32(buf)?;
+                match message.filter(|message| !message.is_service) {
+                    Some(message) => Update::EditMessage(UpdateEditMessage {
+                        message: self.get_message_data(message),
+                        pts,
+                        pts_count,
+                    }),
+                    None => other_update("updateEditMessage"),
+                }
+            }
+            TL_UPDATE_MESSAGE_ID => Update::MessageId(UpdateMessageId {
+                id: read_i32(buf)?,
+                random_id: read_i64(buf)?,
+            }),
+            TL_UPDATE_DELETE_MESSAGES => Update::DeleteMessages(UpdateDeleteMessages {
+                flags: 0,
+                messages: read_int_vector(buf)?,
+                pts: read_i32(buf)?,
+                pts_count: read_i32(buf)?,
+            }),
+            TL_UPDATE_DELETE_CHANNEL_MESSAGES => {
+                // The messages are identified by the channel, which
+                // UpdateDeleteMessages can't describe
+                read_i64(buf)?;
+                read_int_vector(buf)?;
+                read_i32(buf)?;
+                read_i32(buf)?;
+                other_update("updateDeleteChannelMessages")
+            }
+            TL_UPDATE_READ_HISTORY_INBOX => {
+                let flags = read_i32(buf)?;
+                if flags & (1 << 0) != 0 {
+                    // folder_id
+                    read_i32(buf)?;
+                }
+                skip_peer(buf)?;
+                if flags & (1 << 1) != 0 {
+                    // top_msg_id
+                    read_i32(buf)?;
+                }
+                // max_id, still_unread_count, pts and pts_count
+                for _ in 0..4 {
+                    read_i32(buf)?;
+                }
+                other_update("updateReadHistoryInbox")
+            }
+            TL_UPDATE_READ_HISTORY_OUTBOX => {
+                skip_peer(buf)?;
+                // max_id, pts and pts_count
+                for _ in 0..3 {
+                    read_i32(buf)?;
+                }
+                other_update("updateReadHistoryOutbox")
+            }
+            TL_UPDATE_READ_CHANNEL_INBOX => {
+                let flags = read_i32(buf)?;
+                if flags & (1 << 0) != 0 {
+                    // folder_id
+                    read_i32(buf)?;
+                }
+                read_i64(buf)?;
+                // max_id, still_unread_count and pts
+                for _ in 0..3 {
+                    read_i32(buf)?;
+                }
+                other_update("updateReadChannelInbox")
+            }
+            TL_UPDATE_READ_CHANNEL_OUTBOX => {
+                read_i64(buf)?;
+                read_i32(buf)?;
+                other_update("updateReadChannelOutbox")
+            }
+            id => {
+                debug!("Ignoring the updates from unknown update {:#010x}", id);
+                return Ok(None);
+            }
+        };
+        Ok(Some(update))
+    }
+
+    /// Converts a message read from the server
+    ///
+    /// Service messages get no text and unsupported media.
+    fn get_message_data(&self, message: ServerMessage) -> MessageData {
+        let dialog_id = message.message_full_id.dialog_id();
+        let sender_id = match message.sender_dialog_id {
+            Some(sender_id) => sender_id,
+            None if message.is_outgoing => DialogId::from_user(self.my_user_id),
+            None => dialog_id,
+        };
+
+        let mut data = MessageData::new(
+            message.message_full_id.message_id().get_server_id(),
+            dialog_id.to_encoded(),
+            sender_id.to_encoded(),
+            message.date,
+            message.text,
+        );
+        if message.is_outgoing {
+            data.flags |= 1 << 1;
+        }
+        data.edit_date = message.edit_date;
+        data.media = if message.is_service {
+            data.message.clear();
+            Some(MessageMedia::Unsupported)
+        } else {
+            message.media.map(get_message_media)
+        };
+        data.grouped_id = message.grouped_id;
+        data
+    }
+}
+
+#[async_trait]
+impl MediaSendClient for NetworkMessagesClient {
+    async fn save_file_part(
+        &self,
+        request: SaveFilePartRequest,
+    ) -> Result<bool, SendMessageNetworkError> {
+        let buf = encode_save_file_part(&request);
+        parse_bool(&mut self.invoke(buf).await?)
+    }
+
+    async fn send_media(
+        &self,
+        request: SendMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        let buf = self.encode_send_media(&request)?;
+        let sent = SentMessage::new(&request.peer, request.random_id, &request.message);
+        self.parse_updates(&mut self.invoke(buf).await?, sent)
+    }
+
+    async fn send_multi_media(
+        &self,
+        request: SendMultiMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        let buf = self.encode_send_multi_media(&request)?;
+        self.parse_updates(&mut self.invoke(buf).await?, None)
+    }
+}
+
+#[async_trait]
+impl MessageEditClient for NetworkMessagesClient {
+    async fn edit_message(
+        &self,
+        request: EditMessageRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        let buf = self.encode_edit_message(&request)?;
+        self.parse_updates(&mut self.invoke(buf).await?, None)
+    }
+
+    async fn forward_messages(
+        &self,
+        request: ForwardMessagesRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        let buf = self.encode_forward_messages(&request)?;
+        self.parse_updates(&mut self.invoke(buf).await?, None)
+    }
+
+    async fn delete_messages(
+        &self,
+        request: DeleteMessagesRequest,
+    ) -> Result<AffectedMessages, SendMessageNetworkError> {
+        let buf = self.encode_delete_messages(&request)?;
+        parse_affected_messages(&mut self.invoke(buf).await?)
+    }
+}
+
+#[async_trait]
+impl MessageHistoryClient for NetworkMessagesClient {
+    async fn get_history(
+        &self,
+        request: GetHistoryRequest,
+    ) -> Result<Messages, SendMessageNetworkError> {
+        let buf = self.encode_get_history(&request)?;
+        self.parse_messages(&mut self.invoke(buf).await?)
+    }
+
+    async fn read_history(
+        &self,
+        request: ReadHistoryRequest,
+    ) -> Result<Option<AffectedMessages>, SendMessageNetworkError> {
+        let buf = self.encode_read_history(&request)?;
+        let mut result = self.invoke(buf).await?;
+        if request.is_channel() {
+            parse_bool(&mut result).map(|_| None)
+        } else {
+            parse_affected_messages(&mut result).map(Some)
+        }
+    }
+}
+
+/// Callback passing the result of a query to the waiting request
+struct ResultCallback {
+    /// Channel to the request, taken by the first result
+    sender: parking_lot::Mutex<Option<oneshot::Sender<Result<Bytes, QueryError>>>>,
+}
+
+#[async_trait]
+impl NetQueryCallback for ResultCallback {
+    async fn on_result(&self, query: NetQuery) {
+        let result = if query.is_error() {
+            Err(query.error())
+        } else {
+            Ok(query.ok())
+        };
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+/// Message sent by a request, as far as `updateShortSentMessage` needs it
+#[derive(Debug, Clone, Copy)]
+struct SentMessage<'a> {
+    /// Dialog of the message
+    peer: DialogId,
+    /// Random ID of the message
+    random_id: i64,
+    /// Text or caption of the message
+    text: &'a str,
+}
+
+impl<'a> SentMessage<'a> {
+    /// Describes the message of a request, if its dialog is valid
+    fn new(peer: &InputPeer, random_id: i64, text: &'a str) -> Option<Self> {
+        let peer = match *peer {
+            InputPeer::User { user_id } | InputPeer::UserFrom { user_id, .. } => {
+                DialogId::from_user(UserId::new(user_id).ok()?)
+            }
+            InputPeer::Chat { chat_id } => DialogId::from_chat(ChatId::new(chat_id).ok()?),
+            InputPeer::Channel { channel_id, .. } | InputPeer::ChannelFrom { channel_id, .. } => {
+                DialogId::from_channel(ChannelId::new(channel_id).ok()?)
+            }
+            InputPeer::Empty | InputPeer::SelfUser => return None,
+        };
+        Some(Self {
+            peer,
+            random_id,
+            text,
+        })
+    }
+}
+
+/// `InputPeer` with the access hash filled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TlInputPeer {
+    /// `inputPeerEmpty`
+    Empty,
+    /// `inputPeerSelf`
+    SelfUser,
+    /// `inputPeerChat` with the chat identifier
+    Chat(i64),
+    /// `inputPeerUser` with the user identifier and access hash
+    User(i64, i64),
+    /// `inputPeerChannel` with the channel identifier and access hash
+    Channel(i64, i64),
+}
+
+impl TlInputPeer {
+    fn write(self, buf: &mut BytesMut) {
+        match self {
+            Self::Empty => TlHelper::write_constructor_id(buf, TL_INPUT_PEER_EMPTY),
+            Self::SelfUser => TlHelper::write_constructor_id(buf, TL_INPUT_PEER_SELF),
+            Self::Chat(chat_id) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_CHAT);
+                TlHelper::write_i64(buf, chat_id);
+            }
+            Self::User(user_id, access_hash) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_USER);
+                TlHelper::write_i64(buf, user_id);
+                TlHelper::write_i64(buf, access_hash);
+            }
+            Self::Channel(channel_id, access_hash) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_PEER_CHANNEL);
+                TlHelper::write_i64(buf, channel_id);
+                TlHelper::write_i64(buf, access_hash);
+            }
+        }
+    }
+}
+
+/// `InputUser` of a mentioned user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TlInputUser {
+    /// `inputUserSelf`
+    SelfUser,
+    /// `inputUser` with the user identifier and access hash
+    User(i64, i64),
+}
+
+impl TlInputUser {
+    fn write(self, buf: &mut BytesMut) {
+        match self {
+            Self::SelfUser => TlHelper::write_constructor_id(buf, TL_INPUT_USER_SELF),
+            Self::User(user_id, access_hash) => {
+                TlHelper::write_constructor_id(buf, TL_INPUT_USER);
+                TlHelper::write_i64(buf, user_id);
+                TlHelper::write_i64(buf, access_hash);
+            }
+        }
+    }
+}
+
+/// Returns an error for a peer with an invalid identifier
+fn invalid_peer(error: impl std::fmt::Display) -> SendMessageNetworkError {
+    SendMessageNetworkError::Generic(format!("Invalid peer: {error}"))
+}
+
+/// Returns an error for a keyboard button that can't be sent
+fn unsupported_button(what: impl std::fmt::Display) -> SendMessageNetworkError {
+    TlSerializationError::SerializationError(format!("Unsupported keyboard button: {what}")).into()
+}
+
+/// Returns an error for a malformed response
+fn malformed(what: impl std::fmt::Display) -> SendMessageNetworkError {
+    TlSerializationError::DeserializationError(format!("Malformed response: {what}")).into()
+}
+
+fn read_u32(buf: &mut TlBytes) -> Result<u32, SendMessageNetworkError> {
+    TlHelper::read_constructor_id(buf).map_err(malformed)
+}
+
+fn read_i32(buf: &mut TlBytes) -> Result<i32, SendMessageNetworkError> {
+    TlHelper::read_i32(buf).map_err(malformed)
+}
+
+fn read_i64(buf: &mut TlBytes) -> Result<i64, SendMessageNetworkError> {
+    TlHelper::read_i64(buf).map_err(malformed)
+}
+
+fn read_string(buf: &mut TlBytes) -> Result<String, SendMessageNetworkError> {
+    TlHelper::read_string(buf).map_err(malformed)
+}
+
+/// Reads `Vector<int>`
+fn read_int_vector(buf: &mut TlBytes) -> Result<Vec<i32>, SendMessageNetworkError> {
+    let id = read_u32(buf)?;
+    if id != TL_VECTOR {
+        return Err(malformed(format_args!("Vector {:#010x}", id)));
+    }
+    let count = read_i32(buf)?;
+    (0..count).map(|_| read_i32(buf)).collect()
+}
+
+/// Writes `Vector<int>`
+fn write_int_vector(buf: &mut BytesMut, values: &[i32]) {
+    TlHelper::write_constructor_id(buf, TL_VECTOR);
+    TlHelper::write_i32(buf, values.len() as i32);
+    for &value in values {
+        TlHelper::write_i32(buf, value);
+    }
+}
+
+/// Skips a `Peer`
+fn skip_peer(buf: &mut TlBytes) -> Result<(), SendMessageNetworkError> {
+    match read_u32(buf)? {
+        TL_PEER_USER | TL_PEER_CHAT | TL_PEER_CHANNEL => read_i64(buf).map(|_| ()),
+        id => Err(malformed(format_args!("Peer {:#010x}", id))),
+    }
+}
+
+/// Returns the dialog of a user in a response
+fn user_dialog_id(user_id: i64) -> Result<DialogId, SendMessageNetworkError> {
+    UserId::new(user_id)
+        .map(DialogId::from_user)
+        .map_err(malformed)
+}
+
+/// Reads the rest of `updateShortMessage` or `updateShortChatMessage` after
+/// the sender.
+///
+/// The optional fields after the date aren't needed and are left unread.
+fn read_short_message(
+    buf: &mut TlBytes,
+    id: i32,
+    dialog_id: DialogId,
+    sender_id: DialogId,
+    flags: i32,
+) -> Result<Update, SendMessageNetworkError> {
+    let text = read_string(buf)?;
+    let pts = read_i32(buf)?;
+    let pts_count = read_i32(buf)?;
+    let date = read_i32(buf)?;
+    let mut message = MessageData::new(
+        id,
+        dialog_id.to_encoded(),
+        sender_id.to_encoded(),
+        date,
+        text,
+    );
+    message.flags = flags & (1 << 1);
+    Ok(Update::NewMessage(UpdateNewMessage::new(
+        message, pts, pts_count,
+    )))
+}
+
+/// Returns an update that is parsed, but not passed on
+fn other_update(update_type: &str) -> Update {
+    Update::Other(OtherUpdate {
+        update_type: update_type.to_string(),
+        data: Vec::new(),
+    })
+}
+
+/// Parses `Bool`
+fn parse_bool(buf: &mut TlBytes) -> Result<bool, SendMessageNetworkError> {
+    match read_u32(buf)? {
+        TL_BOOL_TRUE => Ok(true),
+        TL_BOOL_FALSE => Ok(false),
+        id => Err(malformed(format_args!("Bool {:#010x}", id))),
+    }
+}
+
+/// Parses `messages.affectedMessages`
+fn parse_affected_messages(buf: &mut TlBytes) -> Result<AffectedMessages, SendMessageNetworkError> {
+    match read_u32(buf)? {
+        TL_MESSAGES_AFFECTED_MESSAGES => Ok(AffectedMessages {
+            pts: read_i32(buf)?,
+            pts_count: read_i32(buf)?,
+        }),
+        id => Err(malformed(format_args!(
+            "messages.AffectedMessages {:#010x}",
+            id
+        ))),
+    }
+}
+
+/// Converts the media of a message read from the server
+fn get_message_media(media: ServerMessageMedia) -> MessageMedia {
+    match media {
+        ServerMessageMedia::Photo { id, spoiler } => MessageMedia::Photo { id, spoiler },
+        ServerMessageMedia::Document {
+            id,
+            mime_type,
+            attributes,
+            spoiler,
+        } => MessageMedia::Document {
+            id,
+            mime_type,
+            attributes: attributes.into_iter().map(get_document_attribute).collect(),
+            spoiler,
+        },
+        ServerMessageMedia::Unsupported => MessageMedia::Unsupported,
+    }
+}
+
+/// Converts an attribute of a document read from the server
+fn get_document_attribute(attribute: ServerDocumentAttribute) -> DocumentAttribute {
+    match attribute {
+        ServerDocumentAttribute::Video {
+            round_message,
+            supports_streaming,
+            duration,
+            w,
+            h,
+        } => DocumentAttribute::Video {
+            round_message,
+            supports_streaming,
+            duration: duration.round() as i32,
+            w,
+            h,
+        },
+        ServerDocumentAttribute::Audio { voice, duration } => {
+            DocumentAttribute::Audio { voice, duration }
+        }
+        ServerDocumentAttribute::Animated => DocumentAttribute::Animated,
+        ServerDocumentAttribute::Sticker { alt } => DocumentAttribute::Sticker { alt },
+        ServerDocumentAttribute::Filename { file_name } => {
+            DocumentAttribute::Filename { file_name }
+        }
+    }
+}
+
+/// Encodes `upload.saveFilePart` or `upload.saveBigFilePart`
+fn encode_save_file_part(request: &SaveFilePartRequest) -> BytesMut {
+    let mut buf = BytesMut::new();
+    match request.file_total_parts {
+        Some(file_total_parts) => {
+            TlHelper::write_constructor_id(&mut buf, TL_UPLOAD_SAVE_BIG_FILE_PART);
+            TlHelper::write_i64(&mut buf, request.file_id);
+            TlHelper::write_i32(&mut buf, request.file_part);
+            TlHelper::write_i32(&mut buf, file_total_parts);
+        }
+        None => {
+            TlHelper::write_constructor_id(&mut buf, TL_UPLOAD_SAVE_FILE_PART);
+            TlHelper::write_i64(&mut buf, request.file_id);
+            TlHelper::write_i32(&mut buf, request.file_part);
+        }
+    }
+    TlHelper::write_bytes(&mut buf, &request.bytes);
+    buf
+}
+
+/// Writes `replyKeyboardMarkup`
+fn write_keyboard(
+    buf: &mut BytesMut,
+    rows: &[Vec<KeyboardButton>],
+    resize: bool,
+    one_time: bool,
+    is_personal: bool,
+    placeholder: &str,
+) -> Result<(), SendMessageNetworkError> {
+    TlHelper::write_constructor_id(buf, TL_REPLY_KEYBOARD_MARKUP);
+    // flags.0: resize, flags.1: single_use, flags.2: selective,
+    // flags.3: placeholder
+    let mut flags = 0;
+    if resize {
+        flags |= 1 << 0;
+    }
+    if one_time {
+        flags |= 1 << 1;
+    }
+    if is_personal {
+        flags |= 1 << 2;
+    }
+    if !placeholder.is_empty() {
+        flags |= 1 << 3;
+    }
+    TlHelper::write_i32(buf, flags);
+    TlHelper::write_constructor_id(buf, TL_VECTOR);
+    TlHelper::write_i32(buf, rows.len() as i32);
+    for row in rows {
+        TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_ROW);
+        TlHelper::write_constructor_id(buf, TL_VECTOR);
+        TlHelper::write_i32(buf, row.len() as i32);
+        for button in row {
+            write_keyboard_button(buf, button)?;
+        }
+    }
+    if !placeholder.is_empty() {
+        TlHelper::write_string(buf, placeholder);
+    }
+    Ok(())
+}
+
+/// Writes the `KeyboardButton` of a reply keyboard
+fn write_keyboard_button(
+    buf: &mut BytesMut,
+    button: &KeyboardButton,
+) -> Result<(), SendMessageNetworkError> {
+    match button {
+        KeyboardButton::Text { text } => {
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON);
+            TlHelper::write_string(buf, text);
+        }
+        KeyboardButton::RequestContact { text } => {
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_REQUEST_PHONE);
+            TlHelper::write_string(buf, text);
+        }
+        KeyboardButton::RequestGeoLocation { text } => {
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_REQUEST_GEO_LOCATION);
+            TlHelper::write_string(buf, text);
+        }
+        KeyboardButton::RequestPoll {
+            text,
+            force_regular,
+            force_quiz,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_REQUEST_POLL);
+            // flags.0: quiz, set if the kind of the poll is forced
+            let has_quiz = *force_regular || *force_quiz;
+            TlHelper::write_i32(buf, i32::from(has_quiz));
+            if has_quiz {
+                let id = if *force_quiz {
+                    TL_BOOL_TRUE
+                } else {
+                    TL_BOOL_FALSE
+                };
+                TlHelper::write_constructor_id(buf, id);
+            }
+            TlHelper::write_string(buf, text);
+        }
+        KeyboardButton::WebApp { text, url } => {
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_SIMPLE_WEB_VIEW);
+            TlHelper::write_string(buf, text);
+            TlHelper::write_string(buf, url);
+        }
+        KeyboardButton::RequestPeer { .. } => {
+            return Err(unsupported_button(format_args!("{:?}", button)));
+        }
+    }
+    Ok(())
+}
+
+/// Writes `inputReplyToMessage`
+fn write_reply_to(buf: &mut BytesMut, reply_to_msg_id: i32) {
+    TlHelper::write_constructor_id(buf, TL_INPUT_REPLY_TO_MESSAGE);
+    TlHelper::write_i32(buf, 0);
+    TlHelper::write_i32(buf, reply_to_msg_id);
+}
+
+/// Writes `InputFile`
+fn write_input_file(buf: &mut BytesMut, file: &InputFile) {
+    match file {
+        InputFile::Small {
+            id,
+            parts,
+            name,
+            md5_checksum,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_FILE);
+            TlHelper::write_i64(buf, *id);
+            TlHelper::write_i32(buf, *parts);
+            TlHelper::write_string(buf, name);
+            TlHelper::write_string(buf, md5_checksum);
+        }
+        InputFile::Big { id, parts, name } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_FILE_BIG);
+            TlHelper::write_i64(buf, *id);
+            TlHelper::write_i32(buf, *parts);
+            TlHelper::write_string(buf, name);
+        }
+    }
+}
+
+/// Writes `InputMedia`
+fn write_input_media(buf: &mut BytesMut, media: &InputMedia) {
+    match media {
+        InputMedia::UploadedPhoto {
+            file,
+            spoiler,
+            ttl_seconds,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_UPLOADED_PHOTO);
+            // flags.1: ttl_seconds, flags.2: spoiler
+            let mut flags = 0;
+            if ttl_seconds.is_some() {
+                flags |= 1 << 1;
+            }
+            if *spoiler {
+                flags |= 1 << 2;
+            }
+            TlHelper::write_i32(buf, flags);
+            write_input_file(buf, file);
+            if let Some(ttl_seconds) = ttl_seconds {
+                TlHelper::write_i32(buf, *ttl_seconds);
+            }
+        }
+        InputMedia::UploadedDocument {
+            file,
+            mime_type,
+            attributes,
+            force_file,
+            spoiler,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_UPLOADED_DOCUMENT);
+            // flags.4: force_file, flags.5: spoiler
+            let mut flags = 0;
+            if *force_file {
+                flags |= 1 << 4;
+            }
+            if *spoiler {
+                flags |= 1 << 5;
+            }
+            TlHelper::write_i32(buf, flags);
+            write_input_file(buf, file);
+            TlHelper::write_string(buf, mime_type);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, attributes.len() as i32);
+            for attribute in attributes {
+                write_document_attribute(buf, attribute);
+            }
+        }
+        InputMedia::Photo {
+            id,
+            access_hash,
+            file_reference,
+            spoiler,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_PHOTO);
+            // flags.1: spoiler
+            TlHelper::write_i32(buf, i32::from(*spoiler) << 1);
+            TlHelper::write_constructor_id(buf, TL_INPUT_PHOTO);
+            TlHelper::write_i64(buf, *id);
+            TlHelper::write_i64(buf, *access_hash);
+            TlHelper::write_bytes(buf, file_reference);
+        }
+        InputMedia::Document {
+            id,
+            access_hash,
+            file_reference,
+            spoiler,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_DOCUMENT);
+            // flags.2: spoiler
+            TlHelper::write_i32(buf, i32::from(*spoiler) << 2);
+            TlHelper::write_constructor_id(buf, TL_INPUT_DOCUMENT);
+            TlHelper::write_i64(buf, *id);
+            TlHelper::write_i64(buf, *access_hash);
+            TlHelper::write_bytes(buf, file_reference);
+        }
+        InputMedia::GeoPoint { geo_point } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_GEO_POINT);
+            write_geo_point(buf, geo_point);
+        }
+        InputMedia::Venue {
+            geo_point,
+            title,
+            address,
+            provider,
+            venue_id,
+            venue_type,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_VENUE);
+            write_geo_point(buf, geo_point);
+            TlHelper::write_string(buf, title);
+            TlHelper::write_string(buf, address);
+            TlHelper::write_string(buf, provider);
+            TlHelper::write_string(buf, venue_id);
+            TlHelper::write_string(buf, venue_type);
+        }
+        InputMedia::Contact {
+            phone_number,
+            first_name,
+            last_name,
+            vcard,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_CONTACT);
+            TlHelper::write_string(buf, phone_number);
+            TlHelper::write_string(buf, first_name);
+            TlHelper::write_string(buf, last_name);
+            TlHelper::write_string(buf, vcard);
+        }
+        InputMedia::Poll {
+            poll,
+            correct_answers,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_POLL);
+            // flags.0: correct_answers
+            TlHelper::write_i32(buf, i32::from(correct_answers.is_some()));
+            write_poll(buf, poll);
+            if let Some(correct_answers) = correct_answers {
+                TlHelper::write_constructor_id(buf, TL_VECTOR);
+                TlHelper::write_i32(buf, correct_answers.len() as i32);
+                for option in correct_answers {
+                    TlHelper::write_bytes(buf, option);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `inputGeoPoint`
+fn write_geo_point(buf: &mut BytesMut, geo_point: &InputGeoPoint) {
+    TlHelper::write_constructor_id(buf, TL_INPUT_GEO_POINT);
+    // flags.0: accuracy_radius
+    TlHelper::write_i32(buf, i32::from(geo_point.accuracy_radius.is_some()));
+    TlHelper::write_f64(buf, geo_point.lat);
+    TlHelper::write_f64(buf, geo_point.long);
+    if let Some(accuracy_radius) = geo_point.accuracy_radius {
+        TlHelper::write_i32(buf, accuracy_radius);
+    }
+}
+
+/// Writes `poll`; the server assigns the identifier of a new poll
+fn write_poll(buf: &mut BytesMut, poll: &PollData) {
+    TlHelper::write_constructor_id(buf, TL_POLL);
+    TlHelper::write_i64(buf, 0);
+    // flags.1: public_voters, flags.2: multiple_choice, flags.3: quiz,
+    // flags.4: close_period
+    let mut flags = 0;
+    if poll.public_voters {
+        flags |= 1 << 1;
+    }
+    if poll.multiple_choice {
+        flags |= 1 << 2;
+    }
+    if poll.quiz {
+        flags |= 1 << 3;
+    }
+    if poll.close_period.is_some() {
+        flags |= 1 << 4;
+    }
+    TlHelper::write_i32(buf, flags);
+    write_plain_text(buf, &poll.question);
+    TlHelper::write_constructor_id(buf, TL_VECTOR);
+    TlHelper::write_i32(buf, poll.answers.len() as i32);
+    for answer in &poll.answers {
+        TlHelper::write_constructor_id(buf, TL_POLL_ANSWER);
+        write_plain_text(buf, &answer.text);
+        TlHelper::write_bytes(buf, &answer.option);
+    }
+    if let Some(close_period) = poll.close_period {
+        TlHelper::write_i32(buf, close_period);
+    }
+}
+
+/// Writes `textWithEntities` without entities
+fn write_plain_text(buf: &mut BytesMut, text: &str) {
+    TlHelper::write_constructor_id(buf, TL_TEXT_WITH_ENTITIES);
+    TlHelper::write_string(buf, text);
+    TlHelper::write_constructor_id(buf, TL_VECTOR);
+    TlHelper::write_i32(buf, 0);
+}
+
+/// Writes `DocumentAttribute`
+fn write_document_attribute(buf: &mut BytesMut, attribute: &DocumentAttribute) {
+    match attribute {
+        DocumentAttribute::Video {
+            round_message,
+            supports_streaming,
+            duration,
+            w,
+            h,
+        } => {
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_VIDEO);
+            // flags.0: round_message, flags.1: supports_streaming
+            TlHelper::write_i32(
+                buf,
+                i32::from(*round_message) | (i32::from(*supports_streaming) << 1),
+            );
+            TlHelper::write_f64(buf, f64::from(*duration));
+            TlHelper::write_i32(buf, *w);
+            TlHelper::write_i32(buf, *h);
+        }
+        DocumentAttribute::Audio { voice, duration } => {
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_AUDIO);
+            // flags.10: voice
+            TlHelper::write_i32(buf, i32::from(*voice) << 10);
+            TlHelper::write_i32(buf, *duration);
+        }
+        DocumentAttribute::Animated => {
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_ANIMATED);
+        }
+        DocumentAttribute::Sticker { alt } => {
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_STICKER);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_string(buf, alt);
+            TlHelper::write_constructor_id(buf, TL_INPUT_STICKER_SET_EMPTY);
+        }
+        DocumentAttribute::Filename { file_name } => {
+            TlHelper::write_constructor_id(buf, TL_DOCUMENT_ATTRIBUTE_FILENAME);
+            TlHelper::write_string(buf, file_name);
+        }
+    }
+}
+
+/// Writes a `MessageEntity`; mentions are written as
+/// `inputMessageEntityMentionName` with the `InputUser` of the user
+fn write_entity(buf: &mut BytesMut, entity: &MessageEntity, input_user: Option<TlInputUser>) {
+    let (id, offset, length) = match *entity {
+        MessageEntity::Bold { offset, length } => (TL_MESSAGE_ENTITY_BOLD, offset, length),
+        MessageEntity::Italic { offset, length } => (TL_MESSAGE_ENTITY_ITALIC, offset, length),
+        MessageEntity::Underline { offset, length } => {
+            (TL_MESSAGE_ENTITY_UNDERLINE, offset, length)
+        }
+        MessageEntity::Strike { offset, length } => (TL_MESSAGE_ENTITY_STRIKE, offset, length),
+        MessageEntity::Code { offset, length } => (TL_MESSAGE_ENTITY_CODE, offset, length),
+        MessageEntity::Pre { offset, length, .. } => (TL_MESSAGE_ENTITY_PRE, offset, length),
+        MessageEntity::TextUrl { offset, length, .. } => {
+            (TL_MESSAGE_ENTITY_TEXT_URL, offset, length)
+        }
+        MessageEntity::Mention { offset, length, .. } => {
+            (TL_INPUT_MESSAGE_ENTITY_MENTION_NAME, offset, length)
+        }
+        MessageEntity::Hashtag { offset, length } => (TL_MESSAGE_ENTITY_HASHTAG, offset, length),
+        MessageEntity::Cashtag { offset, length } => (TL_MESSAGE_ENTITY_CASHTAG, offset, length),
+        MessageEntity::BotCommand { offset, length, .. } => {
+            (TL_MESSAGE_ENTITY_BOT_COMMAND, offset, length)
+        }
+        MessageEntity::CustomEmoji { offset, length, .. } => {
+            (TL_MESSAGE_ENTITY_CUSTOM_EMOJI, offset, length)
+        }
+    };
+    TlHelper::write_constructor_id(buf, id);
+    TlHelper::write_i32(buf, offset);
+    TlHelper::write_i32(buf, length);
+    match entity {
+        MessageEntity::Pre { language, .. } => {
+            TlHelper::write_string(buf, language.as_deref().unwrap_or(""));
+        }
+        MessageEntity::TextUrl { url, .. } => TlHelper::write_string(buf, url),
+        MessageEntity::Mention { .. } => {
+            if let Some(input_user) = input_user {
+                input_user.write(buf);
+            }
+        }
+        MessageEntity::CustomEmoji { document_id, .. } => {
+            TlHelper::write_i64(buf, *document_id);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_reply_markup::InlineKeyboardMarkup;
+
+    const MY_USER_ID: i64 = 777;
+
+    fn client() -> NetworkMessagesClient {
+        NetworkMessagesClient::new(
+            Arc::new(NetQueryDispatcher::new()),
+            UserId::new(MY_USER_ID).unwrap(),
+        )
+    }
+
+    fn user(user_id: i64) -> DialogId {
+        DialogId::from_user(UserId::new(user_id).unwrap())
+    }
+
+    fn encoded(build: impl FnOnce(&mut BytesMut)) -> BytesMut {
+        let mut buf = BytesMut::new();
+        build(&mut buf);
+        buf
+    }
+
+    fn tl(build: impl FnOnce(&mut BytesMut)) -> TlBytes {
+        TlBytes::new(encoded(build).freeze())
+    }
+
+    /// Writes an outgoing text `message` to a user
+    fn write_message(buf: &mut BytesMut, id: i32, user_id: i64, text: &str) {
+        TlHelper::write_constructor_id(buf, 0x9434_5242);
+        // out
+        TlHelper::write_i32(buf, 1 << 1);
+        TlHelper::write_i32(buf, 0);
+        TlHelper::write_i32(buf, id);
+        TlHelper::write_constructor_id(buf, TL_PEER_USER);
+        TlHelper::write_i64(buf, user_id);
+        TlHelper::write_i32(buf, 1_700_000_000);
+        TlHelper::write_string(buf, text);
+    }
+
+    #[test]
+    fn test_input_peer_access_hashes() {
+        let client = client();
+        let peer = InputPeer::User { user_id: 123 };
+        assert!(matches!(
+            client.get_input_peer(&peer),
+            Err(SendMessageNetworkError::DialogNotAccessible(dialog_id)) if dialog_id == user(123)
+        ));
+
+        client.set_access_hash(user(123), 55);
+        assert_eq!(
+            client.get_input_peer(&peer).unwrap(),
+            TlInputPeer::User(123, 55)
+        );
+        assert_eq!(
+            client
+                .get_input_peer(&InputPeer::User {
+                    user_id: MY_USER_ID
+                })
+                .unwrap(),
+            TlInputPeer::SelfUser
+        );
+
+        let channel_id = ChannelId::new(100).unwrap();
+        client.set_access_hash(DialogId::from_channel(channel_id), 66);
+        let peer = InputPeer::from_dialog_id(DialogId::from_channel(channel_id)).unwrap();
+        assert_eq!(
+            client.get_input_peer(&peer).unwrap(),
+            TlInputPeer::Channel(100, 66)
+        );
+    }
+
+    #[test]
+    fn test_encode_send_media() {
+        let client = client();
+        client.set_access_hash(user(123), 55);
+        let media = InputMedia::UploadedPhoto {
+            file: InputFile::Small {
+                id: 9,
+                parts: 1,
+                name: "photo.jpg".to_string(),
+                md5_checksum: String::new(),
+            },
+            spoiler: true,
+            ttl_seconds: None,
+        };
+        let request = SendMediaRequest::new(
+            InputPeer::User { user_id: 123 },
+            media,
+            "Look".to_string(),
+            42,
+        )
+        .with_reply_to(5)
+        .with_entities(vec![
+            MessageEntity::Bold {
+                offset: 0,
+                length: 4,
+            },
+            // The user can't be addressed
+            MessageEntity::Mention {
+                offset: 0,
+                length: 4,
+                user_id: 124,
+            },
+        ]);
+
+        let buf = client.encode_send_media(&request).unwrap();
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_SEND_MEDIA);
+            TlHelper::write_i32(buf, (1 << 0) | (1 << 3));
+            TlInputPeer::User(123, 55).write(buf);
+            write_reply_to(buf, 5);
+            TlHelper::write_constructor_id(buf, TL_INPUT_MEDIA_UPLOADED_PHOTO);
+            TlHelper::write_i32(buf, 1 << 2);
+            TlHelper::write_constructor_id(buf, TL_INPUT_FILE);
+            TlHelper::write_i64(buf, 9);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_string(buf, "photo.jpg");
+            TlHelper::write_string(buf, "");
+            TlHelper::write_string(buf, "Look");
+            TlHelper::write_i64(buf, 42);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_MESSAGE_ENTITY_BOLD);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 4);
+        });
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_encode_save_file_part() {
+        let mut request = SaveFilePartRequest {
+            file_id: 9,
+            file_part: 2,
+            file_total_parts: None,
+            bytes: vec![1, 2, 3],
+        };
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_UPLOAD_SAVE_FILE_PART);
+            TlHelper::write_i64(buf, 9);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_bytes(buf, &[1, 2, 3]);
+        });
+        assert_eq!(encode_save_file_part(&request), expected);
+
+        request.file_total_parts = Some(7);
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_UPLOAD_SAVE_BIG_FILE_PART);
+            TlHelper::write_i64(buf, 9);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_i32(buf, 7);
+            TlHelper::write_bytes(buf, &[1, 2, 3]);
+        });
+        assert_eq!(encode_save_file_part(&request), expected);
+
+        let mut buf = tl(|buf| TlHelper::write_constructor_id(buf, TL_BOOL_TRUE));
+        assert!(parse_bool(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_encode_edit_message() {
+        let client = client();
+        let mut keyboard = InlineKeyboardMarkup::new();
+        keyboard.add_row(vec![
+            InlineKeyboardButton::Url {
+                text: "Open".to_string(),
+                url: "https://example.com".to_string(),
+                id: 0,
+            },
+            InlineKeyboardButton::Callback {
+                text: "Vote".to_string(),
+                data: vec![1],
+            },
+        ]);
+        let request = EditMessageRequest::new(InputPeer::Chat { chat_id: 5 }, 10)
+            .with_message("Hello".to_string())
+            .with_reply_markup(ReplyMarkup::InlineKeyboard(keyboard));
+
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_EDIT_MESSAGE);
+            TlHelper::write_i32(buf, (1 << 2) | (1 << 11));
+            TlInputPeer::Chat(5).write(buf);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_string(buf, "Hello");
+            TlHelper::write_constructor_id(buf, TL_REPLY_INLINE_MARKUP);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_ROW);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 2);
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_URL);
+            TlHelper::write_string(buf, "Open");
+            TlHelper::write_string(buf, "https://example.com");
+            TlHelper::write_constructor_id(buf, TL_KEYBOARD_BUTTON_CALLBACK);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_string(buf, "Vote");
+            TlHelper::write_bytes(buf, &[1]);
+        });
+        assert_eq!(client.encode_edit_message(&request).unwrap(), expected);
+
+        let mut keyboard = InlineKeyboardMarkup::new();
+        keyboard.add_row(vec![InlineKeyboardButton::Buy]);
+        let request = EditMessageRequest::new(InputPeer::Chat { chat_id: 5 }, 10)
+            .with_reply_markup(ReplyMarkup::InlineKeyboard(keyboard));
+        assert!(matches!(
+            client.encode_edit_message(&request),
+            Err(SendMessageNetworkError::TlError(_))
+        ));
+    }
+
+    #[test]
+    fn test_encode_delete_messages() {
+        let client = client();
+        let request =
+            DeleteMessagesRequest::new(InputPeer::User { user_id: 123 }, vec![1, 2]).revoke();
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_DELETE_MESSAGES);
+            TlHelper::write_i32(buf, 1);
+            write_int_vector(buf, &[1, 2]);
+        });
+        assert_eq!(client.encode_delete_messages(&request).unwrap(), expected);
+
+        let channel = DialogId::from_channel(ChannelId::new(100).unwrap());
+        let request =
+            DeleteMessagesRequest::new(InputPeer::from_dialog_id(channel).unwrap(), vec![3]);
+        assert!(client.encode_delete_messages(&request).is_err());
+        client.set_access_hash(channel, 66);
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_CHANNELS_DELETE_MESSAGES);
+            TlHelper::write_constructor_id(buf, TL_INPUT_CHANNEL);
+            TlHelper::write_i64(buf, 100);
+            TlHelper::write_i64(buf, 66);
+            write_int_vector(buf, &[3]);
+        });
+        assert_eq!(client.encode_delete_messages(&request).unwrap(), expected);
+
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_AFFECTED_MESSAGES);
+            TlHelper::write_i32(buf, 8);
+            TlHelper::write_i32(buf, 2);
+        });
+        assert_eq!(
+            parse_affected_messages(&mut buf).unwrap(),
+            AffectedMessages {
+                pts: 8,
+                pts_count: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_history() {
+        let client = client();
+        let request = GetHistoryRequest {
+            peer: InputPeer::User { user_id: 123 },
+            offset_id: 10,
+            offset_date: 0,
+            add_offset: -2,
+            limit: 5,
+            max_id: 0,
+            min_id: 0,
+            hash: 0,
+        };
+        assert!(client.encode_get_history(&request).is_err());
+        client.set_access_hash(user(123), 55);
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_GET_HISTORY);
+            TlHelper::write_constructor_id(buf, TL_INPUT_PEER_USER);
+            TlHelper::write_i64(buf, 123);
+            TlHelper::write_i64(buf, 55);
+            for value in [10, 0, -2, 5, 0, 0] {
+                TlHelper::write_i32(buf, value);
+            }
+            TlHelper::write_i64(buf, 0);
+        });
+        assert_eq!(client.encode_get_history(&request).unwrap(), expected);
+
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_MESSAGES_SLICE);
+            // offset_id_offset
+            TlHelper::write_i32(buf, 1 << 2);
+            TlHelper::write_i32(buf, 40);
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 2);
+            write_message(buf, 9, 123, "Hi");
+            // messageService with messageActionEmpty
+            TlHelper::write_constructor_id(buf, 0x2b08_5862);
+            TlHelper::write_i32(buf, 0);
+            TlHelper::write_i32(buf, 8);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 123);
+            TlHelper::write_i32(buf, 1_700_000_000);
+            TlHelper::write_constructor_id(buf, 0xb6ae_f7b0);
+        });
+        let messages = client.parse_messages(&mut buf).unwrap();
+        assert_eq!(messages.count, Some(40));
+        assert_eq!(messages.messages.len(), 2);
+        assert_eq!(messages.messages[0].id, 9);
+        assert_eq!(messages.messages[0].message, "Hi");
+        assert_eq!(messages.messages[1].id, 8);
+        assert!(messages.messages[1].message.is_empty());
+        assert_eq!(messages.messages[1].media, Some(MessageMedia::Unsupported));
+    }
+
+    #[test]
+    fn test_encode_read_history() {
+        let client = client();
+        let request = ReadHistoryRequest::new(InputPeer::User { user_id: 123 }, 9);
+        client.set_access_hash(user(123), 55);
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_MESSAGES_READ_HISTORY);
+            TlHelper::write_constructor_id(buf, TL_INPUT_PEER_USER);
+            TlHelper::write_i64(buf, 123);
+            TlHelper::write_i64(buf, 55);
+            TlHelper::write_i32(buf, 9);
+        });
+        assert_eq!(client.encode_read_history(&request).unwrap(), expected);
+
+        let channel = DialogId::from_channel(ChannelId::new(100).unwrap());
+        client.set_access_hash(channel, 66);
+        let request = ReadHistoryRequest::new(InputPeer::from_dialog_id(channel).unwrap(), 4);
+        let expected = encoded(|buf| {
+            TlHelper::write_constructor_id(buf, TL_CHANNELS_READ_HISTORY);
+            TlHelper::write_constructor_id(buf, TL_INPUT_CHANNEL);
+            TlHelper::write_i64(buf, 100);
+            TlHelper::write_i64(buf, 66);
+            TlHelper::write_i32(buf, 4);
+        });
+        assert_eq!(client.encode_read_history(&request).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_updates() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_UPDATES);
+            TlHelper::write_constructor_id(buf, TL_VECTOR);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_constructor_id(buf, TL_UPDATE_MESSAGE_ID);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_i64(buf, 42);
+            TlHelper::write_constructor_id(buf, TL_UPDATE_READ_HISTORY_OUTBOX);
+            TlHelper::write_constructor_id(buf, TL_PEER_USER);
+            TlHelper::write_i64(buf, 123);
+            TlHelper::write_i32(buf, 9);
+            TlHelper::write_i32(buf, 3);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_constructor_id(buf, TL_UPDATE_NEW_MESSAGE);
+            write_message(buf, 10, 123, "Look");
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_i32(buf, 1);
+            // An unknown update ends the parsed updates
+            TlHelper::write_constructor_id(buf, 0x0123_4567);
+        });
+
+        let updates = client().parse_updates(&mut buf, None).unwrap();
+        assert_eq!(updates.updates.len(), 3);
+        assert_eq!(
+            updates.updates[0],
+            Update::MessageId(UpdateMessageId {
+                id: 10,
+                random_id: 42
+            })
+        );
+        assert!(matches!(&updates.updates[1], Update::Other(_)));
+        let Update::NewMessage(update) = &updates.updates[2] else {
+            panic!("Expected a new message, got {:?}", updates.updates[2]);
+        };
+        assert_eq!(update.pts, 4);
+        assert_eq!(update.message.id, 10);
+        assert_eq!(update.message.dialog_id, user(123).to_encoded());
+        assert_eq!(update.message.sender_id, user(MY_USER_ID).to_encoded());
+        assert_eq!(update.message.message, "Look");
+    }
+
+    #[test]
+    fn test_parse_short_sent_message() {
+        let mut buf = tl(|buf| {
+            TlHelper::write_constructor_id(buf, TL_UPDATE_SHORT_SENT_MESSAGE);
+            TlHelper::write_i32(buf, 1 << 1);
+            TlHelper::write_i32(buf, 10);
+            TlHelper::write_i32(buf, 4);
+            TlHelper::write_i32(buf, 1);
+            TlHelper::write_i32(buf, 1_700_000_000);
+        });
+        let peer = InputPeer::User { user_id: 123 };
+        let sent = SentMessage::new(&peer, 42, "Hi");
+
+        let updates = client().parse_updates(&mut buf, sent).unwrap();
+        let [Update::MessageId(message_id), Update::NewMessage(update)] =
+            updates.updates.as_slice()
+        else {
+            panic!("Unexpected updates {:?}", updates.updates);
+        };
+        assert_eq!((message_id.id, message_id.random_id), (10, 42));
+        assert_eq!(update.message.dialog_id, user(123).to_encoded());
+        assert_eq!(update.message.message, "Hi");
+
+        let mut buf = tl(|buf| TlHelper::write_constructor_id(buf, TL_UPDATE_SHORT_SENT_MESSAGE));
+        assert!(client().parse_updates(&mut buf, None).is_err());
+    }
+}
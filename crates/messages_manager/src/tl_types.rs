@@ -23,6 +23,7 @@ use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use rustgram_reply_markup::ReplyMarkup;
 use rustgram_types::DialogId;
 
 /// TL constructor numbers for message types.
@@ -33,6 +34,39 @@ pub mod constructors {
     /// messages.sendMedia
     pub const MESSAGES_SEND_MEDIA: i32 = 1116092968; // 0x50ddb2b8 as i32
 
+    /// messages.sendMultiMedia
+    pub const MESSAGES_SEND_MULTI_MEDIA: i32 = 934757205; // 0x37b74355 as i32
+
+    /// messages.editMessage
+    pub const MESSAGES_EDIT_MESSAGE: i32 = -539934715; // 0xdfd14005 as i32
+
+    /// messages.forwardMessages
+    pub const MESSAGES_FORWARD_MESSAGES: i32 = -721186296; // 0xd5039208 as i32
+
+    /// messages.deleteMessages
+    pub const MESSAGES_DELETE_MESSAGES: i32 = -443640366; // 0xe58e95d2 as i32
+
+    /// channels.deleteMessages
+    pub const CHANNELS_DELETE_MESSAGES: i32 = -2067661490; // 0x84c1fd4e as i32
+
+    /// messages.getHistory
+    pub const MESSAGES_GET_HISTORY: i32 = 1143203525; // 0x4423e6c5 as i32
+
+    /// messages.readHistory
+    pub const MESSAGES_READ_HISTORY: i32 = 238054714; // 0x0e306d3a as i32
+
+    /// channels.readHistory
+    pub const CHANNELS_READ_HISTORY: i32 = -871347913; // 0xcc104937 as i32
+
+    /// upload.saveFilePart
+    pub const UPLOAD_SAVE_FILE_PART: i32 = -1291540959; // 0xb304a621 as i32
+
+    /// upload.saveBigFilePart
+    pub const UPLOAD_SAVE_BIG_FILE_PART: i32 = -562337987; // 0xde7b673d as i32
+
+    /// UpdateMessageID
+    pub const UPDATE_MESSAGE_ID: i32 = 1318109142; // 0x4e90bfd6 as i32
+
     /// UpdateNewMessage
     pub const UPDATE_NEW_MESSAGE: i32 = 1273992; // 0x001373c8 as i32
 
@@ -45,132 +79,972 @@ pub mod constructors {
     /// UpdateShortSentMessage
     pub const UPDATE_SHORT_SENT_MESSAGE: i32 = 298377884; // 0x11f1331c as i32
 
-    /// Updates
-    pub const UPDATES: i32 = 481402225; // 0x1ca93831 as i32
+    /// messages.messages
+    pub const MESSAGES_MESSAGES: i32 = -1938715001; // 0x8c718e87 as i32
+
+    /// messages.messagesSlice
+    pub const MESSAGES_MESSAGES_SLICE: i32 = 978610270; // 0x3a54685e as i32
+
+    /// messages.channelMessages
+    pub const MESSAGES_CHANNEL_MESSAGES: i32 = -948520370; // 0xc776ba4e as i32
+
+    /// messages.affectedMessages
+    pub const MESSAGES_AFFECTED_MESSAGES: i32 = -2066640507; // 0x84d19185 as i32
+
+    /// Updates
+    pub const UPDATES: i32 = 481402225; // 0x1ca93831 as i32
+
+    /// UpdatesTooLong
+    pub const UPDATES_TOO_LONG: i32 = -484987010; // 0xe317af7e as i32
+}
+
+// ============================================================================
+// Request Types
+// ============================================================================
+
+/// Request for sending a text message.
+///
+/// Corresponds to `messages.sendMessage` in MTProto.
+/// Based on TDLib's SendMessageRequest from `td/telegram/tl/tl_message.hpp`.
+///
+/// # Fields
+///
+/// * `peer` - Target dialog (user, chat, or channel)
+/// * `message` - Message text
+/// * `random_id` - Unique request identifier for deduplication
+/// * `reply_to_msg_id` - Optional message ID being replied to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendMessageRequest {
+    /// Request flags (bitmask for optional fields)
+    pub flags: i32,
+
+    /// Target dialog (InputPeer)
+    pub peer: InputPeer,
+
+    /// Message text (or caption for media)
+    pub message: String,
+
+    /// Random ID for deduplication (must be unique per message)
+    pub random_id: i64,
+
+    /// Optional reply-to message ID (present if flags & 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_msg_id: Option<i32>,
+
+    /// Optional entities for formatted text (present if flags & 4)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<MessageEntity>>,
+
+    /// Optional: send as silent message (no notification)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent: Option<bool>,
+
+    /// Optional: background message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<bool>,
+
+    /// Optional: clear draft
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clear_draft: Option<bool>,
+
+    /// Optional: schedule date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule_date: Option<i32>,
+
+    /// Optional: send as (different peer)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_as: Option<InputPeer>,
+
+    /// Optional: quick reply shortcut ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quick_reply_shortcut_id: Option<i32>,
+
+    /// Optional: effect ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<i64>,
+}
+
+impl SendMessageRequest {
+    /// Creates a new send message request.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Target dialog
+    /// * `message` - Message text
+    /// * `random_id` - Unique random ID
+    pub fn new(peer: InputPeer, message: String, random_id: i64) -> Self {
+        Self {
+            flags: 0,
+            peer,
+            message,
+            random_id,
+            reply_to_msg_id: None,
+            entities: None,
+            silent: None,
+            background: None,
+            clear_draft: None,
+            schedule_date: None,
+            send_as: None,
+            quick_reply_shortcut_id: None,
+            effect: None,
+        }
+    }
+
+    /// Sets the reply-to message ID.
+    #[must_use]
+    pub fn with_reply_to(mut self, reply_to_msg_id: i32) -> Self {
+        self.reply_to_msg_id = Some(reply_to_msg_id);
+        self.flags |= 0x01;
+        self
+    }
+
+    /// Sets message entities for formatted text.
+    #[must_use]
+    pub fn with_entities(mut self, entities: Vec<MessageEntity>) -> Self {
+        self.entities = Some(entities);
+        self.flags |= 0x04;
+        self
+    }
+
+    /// Sets the message to be sent silently (no notification).
+    #[must_use]
+    pub fn silent(mut self) -> Self {
+        self.silent = Some(true);
+        self.flags |= 0x10;
+        self
+    }
+
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_SEND_MESSAGE
+    }
+
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
+
+    /// Deserializes a request from bytes.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, TlSerializationError> {
+        bincode::deserialize(bytes)
+            .map_err(|e| TlSerializationError::DeserializationError(e.to_string()))
+    }
+}
+
+/// Uploaded file, referenced by the media of a message.
+///
+/// Corresponds to `inputFile` and `inputFileBig` in MTProto.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputFile {
+    /// File uploaded with `upload.saveFilePart`
+    #[serde(rename = "input_file")]
+    Small {
+        /// Random ID of the upload
+        id: i64,
+        /// Number of uploaded parts
+        parts: i32,
+        /// File name
+        name: String,
+        /// MD5 checksum of the file, may be empty
+        md5_checksum: String,
+    },
+
+    /// File uploaded with `upload.saveBigFilePart`
+    #[serde(rename = "input_file_big")]
+    Big {
+        /// Random ID of the upload
+        id: i64,
+        /// Number of uploaded parts
+        parts: i32,
+        /// File name
+        name: String,
+    },
+}
+
+impl InputFile {
+    /// Returns the random ID of the upload.
+    pub fn id(&self) -> i64 {
+        match self {
+            Self::Small { id, .. } | Self::Big { id, .. } => *id,
+        }
+    }
+
+    /// Returns the number of uploaded parts.
+    pub fn parts(&self) -> i32 {
+        match self {
+            Self::Small { parts, .. } | Self::Big { parts, .. } => *parts,
+        }
+    }
+}
+
+/// Attribute of an uploaded document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DocumentAttribute {
+    /// The document is a video
+    #[serde(rename = "video")]
+    Video {
+        /// Whether the video is a round video note
+        round_message: bool,
+        /// Whether the video supports streaming
+        supports_streaming: bool,
+        /// Duration in seconds
+        duration: i32,
+        /// Width
+        w: i32,
+        /// Height
+        h: i32,
+    },
+
+    /// The document is an audio file or a voice note
+    #[serde(rename = "audio")]
+    Audio {
+        /// Whether the audio is a voice note
+        voice: bool,
+        /// Duration in seconds
+        duration: i32,
+    },
+
+    /// The document is an animation
+    #[serde(rename = "animated")]
+    Animated,
+
+    /// The document is a sticker
+    #[serde(rename = "sticker")]
+    Sticker {
+        /// Emoji associated with the sticker
+        alt: String,
+    },
+
+    /// Original name of the file
+    #[serde(rename = "filename")]
+    Filename {
+        /// File name
+        file_name: String,
+    },
+}
+
+/// Geographic point.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputGeoPoint {
+    /// Latitude
+    pub lat: f64,
+
+    /// Longitude
+    pub long: f64,
+
+    /// Optional: accuracy radius in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accuracy_radius: Option<i32>,
+}
+
+/// Answer option of a poll.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PollAnswer {
+    /// Answer text
+    pub text: String,
+
+    /// Identifier of the option, unique within the poll
+    #[serde(with = "serde_bytes")]
+    pub option: Vec<u8>,
+}
+
+/// Poll to create.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PollData {
+    /// Question
+    pub question: String,
+
+    /// Answer options
+    pub answers: Vec<PollAnswer>,
+
+    /// Whether the voters are visible
+    pub public_voters: bool,
+
+    /// Whether several answers can be chosen
+    pub multiple_choice: bool,
+
+    /// Whether the poll is a quiz
+    pub quiz: bool,
+
+    /// Optional: time in seconds after which the poll is closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_period: Option<i32>,
+}
+
+/// Media attached to a message.
+///
+/// Corresponds to the `inputMedia*` constructors in MTProto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputMedia {
+    /// Newly uploaded photo
+    #[serde(rename = "uploaded_photo")]
+    UploadedPhoto {
+        /// The uploaded file
+        file: InputFile,
+        /// Whether the photo is hidden under a spoiler
+        spoiler: bool,
+        /// Optional: self-destruct timer in seconds
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl_seconds: Option<i32>,
+    },
+
+    /// Newly uploaded document: video, voice note, animation, sticker or file
+    #[serde(rename = "uploaded_document")]
+    UploadedDocument {
+        /// The uploaded file
+        file: InputFile,
+        /// MIME type of the file
+        mime_type: String,
+        /// Document attributes
+        attributes: Vec<DocumentAttribute>,
+        /// Whether the document must be sent as a file
+        force_file: bool,
+        /// Whether the document is hidden under a spoiler
+        spoiler: bool,
+    },
+
+    /// Photo already stored on the server
+    #[serde(rename = "photo")]
+    Photo {
+        /// Photo identifier
+        id: i64,
+        /// Access hash of the photo
+        access_hash: i64,
+        /// File reference of the photo
+        #[serde(with = "serde_bytes")]
+        file_reference: Vec<u8>,
+        /// Whether the photo is hidden under a spoiler
+        spoiler: bool,
+    },
+
+    /// Document already stored on the server
+    #[serde(rename = "document")]
+    Document {
+        /// Document identifier
+        id: i64,
+        /// Access hash of the document
+        access_hash: i64,
+        /// File reference of the document
+        #[serde(with = "serde_bytes")]
+        file_reference: Vec<u8>,
+        /// Whether the document is hidden under a spoiler
+        spoiler: bool,
+    },
+
+    /// Location
+    #[serde(rename = "geo_point")]
+    GeoPoint {
+        /// The location
+        geo_point: InputGeoPoint,
+    },
+
+    /// Venue
+    #[serde(rename = "venue")]
+    Venue {
+        /// Location of the venue
+        geo_point: InputGeoPoint,
+        /// Venue name
+        title: String,
+        /// Address
+        address: String,
+        /// Venue provider
+        provider: String,
+        /// Venue ID in the provider's database
+        venue_id: String,
+        /// Venue type in the provider's database
+        venue_type: String,
+    },
+
+    /// Contact
+    #[serde(rename = "contact")]
+    Contact {
+        /// Phone number
+        phone_number: String,
+        /// First name
+        first_name: String,
+        /// Last name
+        last_name: String,
+        /// Contact vCard
+        vcard: String,
+    },
+
+    /// Poll
+    #[serde(rename = "poll")]
+    Poll {
+        /// The poll
+        poll: PollData,
+        /// Optional: correct answers of a quiz
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correct_answers: Option<Vec<Vec<u8>>>,
+    },
+}
+
+impl InputMedia {
+    /// Returns the uploaded file of the media, if any.
+    pub fn file(&self) -> Option<&InputFile> {
+        match self {
+            Self::UploadedPhoto { file, .. } | Self::UploadedDocument { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+}
+
+/// Request for sending a message with media.
+///
+/// Corresponds to `messages.sendMedia` in MTProto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendMediaRequest {
+    /// Request flags (bitmask for optional fields)
+    pub flags: i32,
+
+    /// Target dialog (InputPeer)
+    pub peer: InputPeer,
+
+    /// Media to send
+    pub media: InputMedia,
+
+    /// Caption
+    pub message: String,
+
+    /// Random ID for deduplication (must be unique per message)
+    pub random_id: i64,
+
+    /// Optional reply-to message ID (present if flags & 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_msg_id: Option<i32>,
+
+    /// Optional entities of the caption (present if flags & 8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<MessageEntity>>,
+
+    /// Optional: send as silent message (no notification)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent: Option<bool>,
+
+    /// Optional: schedule date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule_date: Option<i32>,
+}
+
+impl SendMediaRequest {
+    /// Creates a new send media request.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Target dialog
+    /// * `media` - Media to send
+    /// * `message` - Caption, may be empty
+    /// * `random_id` - Unique random ID
+    pub fn new(peer: InputPeer, media: InputMedia, message: String, random_id: i64) -> Self {
+        Self {
+            flags: 0,
+            peer,
+            media,
+            message,
+            random_id,
+            reply_to_msg_id: None,
+            entities: None,
+            silent: None,
+            schedule_date: None,
+        }
+    }
+
+    /// Sets the reply-to message ID.
+    #[must_use]
+    pub fn with_reply_to(mut self, reply_to_msg_id: i32) -> Self {
+        self.reply_to_msg_id = Some(reply_to_msg_id);
+        self.flags |= 0x01;
+        self
+    }
+
+    /// Sets the entities of the caption.
+    #[must_use]
+    pub fn with_entities(mut self, entities: Vec<MessageEntity>) -> Self {
+        self.entities = Some(entities);
+        self.flags |= 0x08;
+        self
+    }
+
+    /// Sets the message to be sent silently (no notification).
+    #[must_use]
+    pub fn silent(mut self) -> Self {
+        self.silent = Some(true);
+        self.flags |= 0x20;
+        self
+    }
+
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_SEND_MEDIA
+    }
+
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
+}
+
+/// Item of an album sent with `messages.sendMultiMedia`.
+///
+/// Corresponds to `inputSingleMedia` in MTProto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputSingleMedia {
+    /// Media of the item
+    pub media: InputMedia,
+
+    /// Random ID of the item's message
+    pub random_id: i64,
+
+    /// Caption of the item
+    pub message: String,
+
+    /// Optional entities of the caption
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<MessageEntity>>,
+}
+
+/// Request for sending an album.
+///
+/// Corresponds to `messages.sendMultiMedia` in MTProto. The server assigns
+/// the same `grouped_id` to all messages of the album.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendMultiMediaRequest {
+    /// Request flags (bitmask for optional fields)
+    pub flags: i32,
+
+    /// Target dialog (InputPeer)
+    pub peer: InputPeer,
+
+    /// Optional reply-to message ID (present if flags & 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_msg_id: Option<i32>,
+
+    /// Items of the album
+    pub multi_media: Vec<InputSingleMedia>,
+
+    /// Optional: send as silent message (no notification)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent: Option<bool>,
+
+    /// Optional: schedule date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule_date: Option<i32>,
+}
+
+impl SendMultiMediaRequest {
+    /// Creates a new send album request.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Target dialog
+    /// * `multi_media` - Items of the album
+    pub fn new(peer: InputPeer, multi_media: Vec<InputSingleMedia>) -> Self {
+        Self {
+            flags: 0,
+            peer,
+            reply_to_msg_id: None,
+            multi_media,
+            silent: None,
+            schedule_date: None,
+        }
+    }
+
+    /// Sets the reply-to message ID.
+    #[must_use]
+    pub fn with_reply_to(mut self, reply_to_msg_id: i32) -> Self {
+        self.reply_to_msg_id = Some(reply_to_msg_id);
+        self.flags |= 0x01;
+        self
+    }
+
+    /// Sets the album to be sent silently (no notification).
+    #[must_use]
+    pub fn silent(mut self) -> Self {
+        self.silent = Some(true);
+        self.flags |= 0x20;
+        self
+    }
+
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_SEND_MULTI_MEDIA
+    }
+
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
+}
+
+/// Request for editing a message.
+///
+/// Corresponds to `messages.editMessage` in MTProto. Only the fields whose
+/// flags are set are changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EditMessageRequest {
+    /// Request flags (bitmask for optional fields)
+    pub flags: i32,
+
+    /// Dialog of the message (InputPeer)
+    pub peer: InputPeer,
+
+    /// Server ID of the message
+    pub id: i32,
+
+    /// Optional: new text or caption (present if flags & 0x800)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// Optional: new media (present if flags & 0x4000)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<InputMedia>,
+
+    /// Optional: new reply markup (present if flags & 0x4)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+
+    /// Optional: entities of the new text (present if flags & 0x8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<MessageEntity>>,
+}
+
+impl EditMessageRequest {
+    /// Creates a new edit message request changing nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Dialog of the message
+    /// * `id` - Server ID of the message
+    pub fn new(peer: InputPeer, id: i32) -> Self {
+        Self {
+            flags: 0,
+            peer,
+            id,
+            message: None,
+            media: None,
+            reply_markup: None,
+            entities: None,
+        }
+    }
+
+    /// Sets the new text or caption.
+    #[must_use]
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = Some(message);
+        self.flags |= 0x800;
+        self
+    }
+
+    /// Sets the new media.
+    #[must_use]
+    pub fn with_media(mut self, media: InputMedia) -> Self {
+        self.media = Some(media);
+        self.flags |= 0x4000;
+        self
+    }
+
+    /// Sets the new reply markup.
+    #[must_use]
+    pub fn with_reply_markup(mut self, reply_markup: ReplyMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self.flags |= 0x04;
+        self
+    }
+
+    /// Sets the entities of the new text.
+    #[must_use]
+    pub fn with_entities(mut self, entities: Vec<MessageEntity>) -> Self {
+        self.entities = Some(entities);
+        self.flags |= 0x08;
+        self
+    }
+
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_EDIT_MESSAGE
+    }
+
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
+}
+
+/// Request for forwarding messages.
+///
+/// Corresponds to `messages.forwardMessages` in MTProto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForwardMessagesRequest {
+    /// Request flags (bitmask for optional fields)
+    pub flags: i32,
+
+    /// Dialog the messages are forwarded from (InputPeer)
+    pub from_peer: InputPeer,
+
+    /// Server IDs of the messages
+    pub id: Vec<i32>,
+
+    /// Random IDs of the new messages, one per message
+    pub random_id: Vec<i64>,
+
+    /// Dialog the messages are forwarded to (InputPeer)
+    pub to_peer: InputPeer,
+
+    /// Optional: send as silent messages (no notification)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent: Option<bool>,
+
+    /// Optional: hide the original author (flags & 0x800)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drop_author: Option<bool>,
+
+    /// Optional: remove the captions of media (flags & 0x1000)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drop_media_captions: Option<bool>,
+}
+
+impl ForwardMessagesRequest {
+    /// Creates a new forward messages request.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_peer` - Dialog the messages are forwarded from
+    /// * `id` - Server IDs of the messages
+    /// * `random_id` - Random IDs of the new messages
+    /// * `to_peer` - Dialog the messages are forwarded to
+    pub fn new(from_peer: InputPeer, id: Vec<i32>, random_id: Vec<i64>, to_peer: InputPeer) -> Self {
+        Self {
+            flags: 0,
+            from_peer,
+            id,
+            random_id,
+            to_peer,
+            silent: None,
+            drop_author: None,
+            drop_media_captions: None,
+        }
+    }
+
+    /// Sets the messages to be sent silently (no notification).
+    #[must_use]
+    pub fn silent(mut self) -> Self {
+        self.silent = Some(true);
+        self.flags |= 0x20;
+        self
+    }
+
+    /// Sends copies of the messages without the original author.
+    #[must_use]
+    pub fn drop_author(mut self) -> Self {
+        self.drop_author = Some(true);
+        self.flags |= 0x800;
+        self
+    }
+
+    /// Removes the captions of media from the copies.
+    #[must_use]
+    pub fn drop_media_captions(mut self) -> Self {
+        self.drop_media_captions = Some(true);
+        self.flags |= 0x1000;
+        self
+    }
+
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_FORWARD_MESSAGES
+    }
 
-    /// UpdatesTooLong
-    pub const UPDATES_TOO_LONG: i32 = -484987010; // 0xe317af7e as i32
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
 }
 
-// ============================================================================
-// Request Types
-// ============================================================================
-
-/// Request for sending a text message.
-///
-/// Corresponds to `messages.sendMessage` in MTProto.
-/// Based on TDLib's SendMessageRequest from `td/telegram/tl/tl_message.hpp`.
-///
-/// # Fields
+/// Request for deleting messages.
 ///
-/// * `peer` - Target dialog (user, chat, or channel)
-/// * `message` - Message text
-/// * `random_id` - Unique request identifier for deduplication
-/// * `reply_to_msg_id` - Optional message ID being replied to
+/// Corresponds to `messages.deleteMessages`, or to `channels.deleteMessages`
+/// for messages of a channel. Messages of channels are always deleted for
+/// everyone.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct SendMessageRequest {
+pub struct DeleteMessagesRequest {
     /// Request flags (bitmask for optional fields)
     pub flags: i32,
 
-    /// Target dialog (InputPeer)
+    /// Dialog of the messages (InputPeer)
     pub peer: InputPeer,
 
-    /// Message text (or caption for media)
-    pub message: String,
-
-    /// Random ID for deduplication (must be unique per message)
-    pub random_id: i64,
+    /// Server IDs of the messages
+    pub id: Vec<i32>,
 
-    /// Optional reply-to message ID (present if flags & 1)
+    /// Optional: delete the messages for everyone (flags & 0x1)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub reply_to_msg_id: Option<i32>,
+    pub revoke: Option<bool>,
+}
 
-    /// Optional entities for formatted text (present if flags & 4)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub entities: Option<Vec<MessageEntity>>,
+impl DeleteMessagesRequest {
+    /// Creates a new delete messages request.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Dialog of the messages
+    /// * `id` - Server IDs of the messages
+    pub fn new(peer: InputPeer, id: Vec<i32>) -> Self {
+        Self {
+            flags: 0,
+            peer,
+            id,
+            revoke: None,
+        }
+    }
 
-    /// Optional: send as silent message (no notification)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub silent: Option<bool>,
+    /// Deletes the messages for all participants of the dialog.
+    #[must_use]
+    pub fn revoke(mut self) -> Self {
+        self.revoke = Some(true);
+        self.flags |= 0x01;
+        self
+    }
 
-    /// Optional: background message
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub background: Option<bool>,
+    /// Returns `true` if the messages belong to a channel.
+    pub fn is_channel(&self) -> bool {
+        matches!(self.peer, InputPeer::Channel { .. })
+    }
 
-    /// Optional: clear draft
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub clear_draft: Option<bool>,
+    /// Returns the TL constructor number.
+    pub fn tl_constructor(&self) -> i32 {
+        if self.is_channel() {
+            constructors::CHANNELS_DELETE_MESSAGES
+        } else {
+            constructors::MESSAGES_DELETE_MESSAGES
+        }
+    }
 
-    /// Optional: schedule date
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub schedule_date: Option<i32>,
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
+    }
+}
 
-    /// Optional: send as (different peer)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub send_as: Option<InputPeer>,
+/// Request for a page of the history of a dialog.
+///
+/// Corresponds to `messages.getHistory` in MTProto. The page starts
+/// `add_offset` messages after the last message older than `offset_id`,
+/// or after the last message of the dialog if `offset_id` is 0; a negative
+/// `add_offset` includes newer messages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetHistoryRequest {
+    /// Dialog (InputPeer)
+    pub peer: InputPeer,
 
-    /// Optional: quick reply shortcut ID
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub quick_reply_shortcut_id: Option<i32>,
+    /// Server ID of the message the page is counted from, exclusive
+    pub offset_id: i32,
 
-    /// Optional: effect ID
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub effect: Option<i64>,
+    /// Date the page is counted from, 0 to use `offset_id`
+    pub offset_date: i32,
+
+    /// Number of messages to skip from the offset
+    pub add_offset: i32,
+
+    /// Maximum number of messages to return
+    pub limit: i32,
+
+    /// Return only messages with smaller IDs, 0 for no bound
+    pub max_id: i32,
+
+    /// Return only messages with bigger IDs, 0 for no bound
+    pub min_id: i32,
+
+    /// Hash of the known messages, 0 to always receive them
+    pub hash: i64,
 }
 
-impl SendMessageRequest {
-    /// Creates a new send message request.
+impl GetHistoryRequest {
+    /// Creates a new get history request.
     ///
     /// # Arguments
     ///
-    /// * `peer` - Target dialog
-    /// * `message` - Message text
-    /// * `random_id` - Unique random ID
-    pub fn new(peer: InputPeer, message: String, random_id: i64) -> Self {
+    /// * `peer` - Dialog
+    /// * `offset_id` - Server ID of the message the page is counted from,
+    ///   exclusive; 0 for the last message
+    /// * `add_offset` - Number of messages to skip from the offset
+    /// * `limit` - Maximum number of messages to return
+    pub fn new(peer: InputPeer, offset_id: i32, add_offset: i32, limit: i32) -> Self {
         Self {
-            flags: 0,
             peer,
-            message,
-            random_id,
-            reply_to_msg_id: None,
-            entities: None,
-            silent: None,
-            background: None,
-            clear_draft: None,
-            schedule_date: None,
-            send_as: None,
-            quick_reply_shortcut_id: None,
-            effect: None,
+            offset_id,
+            offset_date: 0,
+            add_offset,
+            limit,
+            max_id: 0,
+            min_id: 0,
+            hash: 0,
         }
     }
 
-    /// Sets the reply-to message ID.
-    #[must_use]
-    pub fn with_reply_to(mut self, reply_to_msg_id: i32) -> Self {
-        self.reply_to_msg_id = Some(reply_to_msg_id);
-        self.flags |= 0x01;
-        self
+    /// Returns the TL constructor number.
+    pub const fn tl_constructor(&self) -> i32 {
+        constructors::MESSAGES_GET_HISTORY
     }
 
-    /// Sets message entities for formatted text.
-    #[must_use]
-    pub fn with_entities(mut self, entities: Vec<MessageEntity>) -> Self {
-        self.entities = Some(entities);
-        self.flags |= 0x04;
-        self
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
     }
+}
 
-    /// Sets the message to be sent silently (no notification).
-    #[must_use]
-    pub fn silent(mut self) -> Self {
-        self.silent = Some(true);
-        self.flags |= 0x10;
-        self
+/// Request marking the incoming messages of a dialog as read.
+///
+/// Corresponds to `messages.readHistory`, or to `channels.readHistory` for
+/// channels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadHistoryRequest {
+    /// Dialog (InputPeer)
+    pub peer: InputPeer,
+
+    /// Server ID of the last read message
+    pub max_id: i32,
+}
+
+impl ReadHistoryRequest {
+    /// Creates a new read history request.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Dialog
+    /// * `max_id` - Server ID of the last read message
+    pub fn new(peer: InputPeer, max_id: i32) -> Self {
+        Self { peer, max_id }
+    }
+
+    /// Returns `true` if the dialog is a channel.
+    pub fn is_channel(&self) -> bool {
+        matches!(self.peer, InputPeer::Channel { .. })
     }
 
     /// Returns the TL constructor number.
-    pub const fn tl_constructor(&self) -> i32 {
-        constructors::MESSAGES_SEND_MESSAGE
+    pub fn tl_constructor(&self) -> i32 {
+        if self.is_channel() {
+            constructors::CHANNELS_READ_HISTORY
+        } else {
+            constructors::MESSAGES_READ_HISTORY
+        }
     }
 
     /// Serializes this request to bytes for MTProto transport.
@@ -179,11 +1053,49 @@ impl SendMessageRequest {
             .map(Bytes::from)
             .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
     }
+}
 
-    /// Deserializes a request from bytes.
-    pub fn deserialize(bytes: &[u8]) -> Result<Self, TlSerializationError> {
-        bincode::deserialize(bytes)
-            .map_err(|e| TlSerializationError::DeserializationError(e.to_string()))
+/// Request for uploading a part of a file.
+///
+/// Corresponds to `upload.saveFilePart`, or to `upload.saveBigFilePart` if
+/// `file_total_parts` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveFilePartRequest {
+    /// Random ID of the upload
+    pub file_id: i64,
+
+    /// Part number, starting from 0
+    pub file_part: i32,
+
+    /// Optional: total number of parts of a big file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_total_parts: Option<i32>,
+
+    /// Part data
+    #[serde(with = "serde_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+impl SaveFilePartRequest {
+    /// Returns `true` if the part belongs to a big file.
+    pub fn is_big(&self) -> bool {
+        self.file_total_parts.is_some()
+    }
+
+    /// Returns the TL constructor number.
+    pub fn tl_constructor(&self) -> i32 {
+        if self.is_big() {
+            constructors::UPLOAD_SAVE_BIG_FILE_PART
+        } else {
+            constructors::UPLOAD_SAVE_FILE_PART
+        }
+    }
+
+    /// Serializes this request to bytes for MTProto transport.
+    pub fn serialize(&self) -> Result<Bytes, TlSerializationError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|e| TlSerializationError::SerializationError(e.to_string()))
     }
 }
 
@@ -227,6 +1139,50 @@ impl SendMessageResult {
     }
 }
 
+/// List of messages.
+///
+/// Corresponds to `messages.messages`, `messages.messagesSlice` and
+/// `messages.channelMessages` in MTProto. Messages are ordered from the
+/// newest to the oldest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Messages {
+    /// The messages
+    pub messages: Vec<MessageData>,
+
+    /// Optional: total number of messages, if only a part of them is
+    /// returned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i32>,
+}
+
+impl Messages {
+    /// Creates a list containing all messages.
+    pub fn new(messages: Vec<MessageData>) -> Self {
+        Self {
+            messages,
+            count: None,
+        }
+    }
+
+    /// Returns the total number of messages.
+    pub fn total_count(&self) -> i32 {
+        self.count
+            .unwrap_or_else(|| i32::try_from(self.messages.len()).unwrap_or(i32::MAX))
+    }
+}
+
+/// Result of deleting messages.
+///
+/// Corresponds to `messages.affectedMessages` in MTProto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AffectedMessages {
+    /// Permanent timestamp after the deletion
+    pub pts: i32,
+
+    /// PTS change count
+    pub pts_count: i32,
+}
+
 /// Updates wrapper response.
 ///
 /// Telegram wraps most responses in an Updates object.
@@ -306,6 +1262,10 @@ pub enum Update {
     #[serde(rename = "read_history")]
     ReadHistory(UpdateReadHistory),
 
+    /// Server identifier assigned to a sent message
+    #[serde(rename = "message_id")]
+    MessageId(UpdateMessageId),
+
     /// Other update (unhandled)
     #[serde(rename = "other")]
     Other(OtherUpdate),
@@ -463,6 +1423,19 @@ pub struct UpdateReadHistory {
     pub pts_count: i32,
 }
 
+/// Message identifier update.
+///
+/// Sent before the update with the message itself; maps the `random_id` of a
+/// sent message to the identifier assigned by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateMessageId {
+    /// Server message ID
+    pub id: i32,
+
+    /// Random ID passed when the message was sent
+    pub random_id: i64,
+}
+
 /// Placeholder for unhandled update types.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OtherUpdate {
@@ -478,6 +1451,40 @@ pub struct OtherUpdate {
 // Message Data Types
 // ============================================================================
 
+/// Media of a message received from the server.
+///
+/// Simplified version of the `messageMedia*` constructors; only the media
+/// that can be grouped into albums is described.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum MessageMedia {
+    /// Photo
+    #[serde(rename = "photo")]
+    Photo {
+        /// Photo ID
+        id: i64,
+        /// Whether the photo is hidden under a spoiler
+        spoiler: bool,
+    },
+
+    /// Document: video, audio, voice note, animation, sticker or file
+    #[serde(rename = "document")]
+    Document {
+        /// Document ID
+        id: i64,
+        /// MIME type of the file
+        mime_type: String,
+        /// Document attributes
+        attributes: Vec<DocumentAttribute>,
+        /// Whether the document is hidden under a spoiler
+        spoiler: bool,
+    },
+
+    /// Media this client doesn't describe
+    #[serde(rename = "unsupported")]
+    Unsupported,
+}
+
 /// Message data from server.
 ///
 /// Simplified version of TDLib's message object.
@@ -514,6 +1521,18 @@ pub struct MessageData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reply_to: Option<MessageReplyHeader>,
 
+    /// Optional: attached media; `message` is its caption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MessageMedia>,
+
+    /// Optional: album the message belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grouped_id: Option<i64>,
+
+    /// Optional: keyboard attached to the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+
     /// Message flags
     pub flags: i32,
 }
@@ -537,10 +1556,37 @@ impl MessageData {
             entities: None,
             fwd_from: None,
             reply_to: None,
+            media: None,
+            grouped_id: None,
+            reply_markup: None,
             flags: 0,
         }
     }
 
+    /// Sets the attached media.
+    #[must_use]
+    pub fn with_media(mut self, media: MessageMedia) -> Self {
+        self.media = Some(media);
+        self.flags |= 0x200;
+        self
+    }
+
+    /// Sets the album the message belongs to.
+    #[must_use]
+    pub fn with_grouped_id(mut self, grouped_id: i64) -> Self {
+        self.grouped_id = Some(grouped_id);
+        self.flags |= 0x20000;
+        self
+    }
+
+    /// Sets the keyboard attached to the message.
+    #[must_use]
+    pub fn with_reply_markup(mut self, reply_markup: ReplyMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self.flags |= 0x40;
+        self
+    }
+
     /// Returns `true` if this is an outgoing message.
     pub fn is_outgoing(&self) -> bool {
         self.flags & 0x02 != 0
@@ -983,6 +2029,36 @@ mod tests {
         assert!(request.entities.is_none());
     }
 
+    #[test]
+    fn test_send_media_request() {
+        let peer = InputPeer::User { user_id: 123456 };
+        let media = InputMedia::GeoPoint {
+            geo_point: InputGeoPoint {
+                lat: 1.5,
+                long: 2.5,
+                accuracy_radius: None,
+            },
+        };
+        let request = SendMediaRequest::new(peer, media, String::new(), 42).with_reply_to(7);
+        assert_eq!(request.flags, 0x01);
+        assert_eq!(request.tl_constructor(), constructors::MESSAGES_SEND_MEDIA);
+
+        assert!(!request.serialize().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_file_part_request_constructor() {
+        let mut request = SaveFilePartRequest {
+            file_id: 1,
+            file_part: 0,
+            file_total_parts: None,
+            bytes: vec![1, 2, 3],
+        };
+        assert_eq!(request.tl_constructor(), constructors::UPLOAD_SAVE_FILE_PART);
+        request.file_total_parts = Some(5);
+        assert_eq!(request.tl_constructor(), constructors::UPLOAD_SAVE_BIG_FILE_PART);
+    }
+
     #[test]
     fn test_send_message_request_with_reply() {
         let peer = InputPeer::Chat { chat_id: 123456 };
@@ -1086,4 +2162,64 @@ mod tests {
         assert_eq!(new_msgs[0].message_id(), 1);
         assert_eq!(new_msgs[1].message_id(), 2);
     }
+
+    #[test]
+    fn test_edit_message_request_flags() {
+        let peer = InputPeer::User { user_id: 123456 };
+        let request = EditMessageRequest::new(peer.clone(), 42);
+        assert_eq!(request.flags, 0);
+        assert_eq!(request.tl_constructor(), constructors::MESSAGES_EDIT_MESSAGE);
+
+        let request = EditMessageRequest::new(peer, 42)
+            .with_message("Edited".to_string())
+            .with_reply_markup(ReplyMarkup::RemoveKeyboard { is_personal: false });
+        assert_eq!(request.flags, 0x800 | 0x04);
+        assert!(request.media.is_none());
+        assert!(request.serialize().is_ok());
+    }
+
+    #[test]
+    fn test_forward_messages_request_flags() {
+        let from_peer = InputPeer::User { user_id: 1 };
+        let to_peer = InputPeer::Chat { chat_id: 2 };
+        let request = ForwardMessagesRequest::new(from_peer, vec![10, 11], vec![7, 8], to_peer)
+            .drop_author()
+            .drop_media_captions();
+
+        assert_eq!(request.flags, 0x800 | 0x1000);
+        assert_eq!(request.drop_author, Some(true));
+        assert_eq!(request.tl_constructor(), constructors::MESSAGES_FORWARD_MESSAGES);
+    }
+
+    #[test]
+    fn test_delete_messages_request_constructor() {
+        let request = DeleteMessagesRequest::new(InputPeer::User { user_id: 1 }, vec![5]).revoke();
+        assert_eq!(request.flags, 0x01);
+        assert_eq!(request.tl_constructor(), constructors::MESSAGES_DELETE_MESSAGES);
+
+        let channel = InputPeer::Channel {
+            channel_id: 3,
+            access_hash: 0,
+        };
+        let request = DeleteMessagesRequest::new(channel, vec![5]);
+        assert!(request.is_channel());
+        assert_eq!(request.tl_constructor(), constructors::CHANNELS_DELETE_MESSAGES);
+    }
+
+    #[test]
+    fn test_get_history_request() {
+        let request = GetHistoryRequest::new(InputPeer::User { user_id: 1 }, 101, -5, 20);
+        assert_eq!(request.offset_id, 101);
+        assert_eq!(request.add_offset, -5);
+        assert_eq!(request.hash, 0);
+        assert_eq!(request.tl_constructor(), constructors::MESSAGES_GET_HISTORY);
+        assert!(request.serialize().is_ok());
+
+        let messages = Messages {
+            messages: Vec::new(),
+            count: Some(250),
+        };
+        assert_eq!(messages.total_count(), 250);
+        assert_eq!(Messages::new(Vec::new()).total_count(), 0);
+    }
 }
@@ -0,0 +1,367 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Dialog history served from the local copy of the messages, with the
+//! missing parts requested from a fake DC.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{peer, setup, FakeDc, DATE, PEER_USER_ID};
+use rustgram_message_types::Message;
+use rustgram_messages_manager::{
+    MessageData, MessageNetworkClient, MessageNetworkConfig, MessagesManager,
+    MessagesManagerConfig, MessagesManagerError, Update, UpdateDeleteMessages,
+};
+use rustgram_net::NetQueryDispatcher;
+use rustgram_storage::{DbConnection, MessageDb};
+use rustgram_types::MessageId;
+
+fn history_message(id: i32) -> MessageData {
+    MessageData::new(
+        id,
+        PEER_USER_ID,
+        PEER_USER_ID,
+        DATE + id,
+        format!("Message {id}"),
+    )
+}
+
+/// Creates a manager and a DC with the messages from 1 to `count`.
+fn setup_with_history(count: i32) -> (Arc<MessagesManager>, Arc<FakeDc>) {
+    let (manager, dc, _recorder) = setup();
+    for id in 1..=count {
+        dc.add_message(history_message(id));
+    }
+    (manager, dc)
+}
+
+fn id(server_id: i32) -> MessageId {
+    MessageId::from_server_id(server_id)
+}
+
+/// Returns the server IDs of messages.
+fn ids(messages: &[Message]) -> Vec<i32> {
+    messages
+        .iter()
+        .map(|message| message.id.get_server_id())
+        .collect()
+}
+
+async fn get_history(
+    manager: &MessagesManager,
+    from_server_id: i32,
+    offset: i32,
+    limit: i32,
+) -> Vec<i32> {
+    let messages = manager
+        .get_chat_history(peer(), id(from_server_id), offset, limit, false)
+        .await
+        .unwrap();
+    ids(&messages)
+}
+
+#[tokio::test]
+async fn scrolling_back_requests_each_page_once() {
+    let (manager, dc) = setup_with_history(50);
+
+    assert_eq!(
+        get_history(&manager, 0, 0, 20).await,
+        (31..=50).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 1);
+    assert_eq!(dc.history_requests.lock()[0].offset_id, 0);
+
+    // The page starts with the oldest shown message
+    assert_eq!(
+        get_history(&manager, 31, 0, 21).await,
+        (11..=31).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+    assert_eq!(dc.history_requests.lock()[1].offset_id, 32);
+
+    // Scrolling over loaded messages again needs no requests
+    assert_eq!(
+        get_history(&manager, 0, 0, 40).await,
+        (11..=50).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        get_history(&manager, 35, 0, 10).await,
+        (26..=35).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+}
+
+#[tokio::test]
+async fn holes_are_filled_from_the_server() {
+    let (manager, dc) = setup_with_history(50);
+
+    assert_eq!(
+        get_history(&manager, 20, 0, 5).await,
+        vec![20, 19, 18, 17, 16]
+    );
+    assert_eq!(
+        get_history(&manager, 30, 0, 10).await,
+        (21..=30).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+
+    // 21 isn't known to follow 20, and 31 to 35 are missing
+    assert_eq!(
+        get_history(&manager, 35, 0, 20).await,
+        (16..=35).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 3);
+
+    assert_eq!(
+        get_history(&manager, 33, 0, 18).await,
+        (16..=33).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 3);
+}
+
+#[tokio::test]
+async fn new_messages_follow_the_loaded_history() {
+    let (manager, dc) = setup_with_history(10);
+
+    assert_eq!(get_history(&manager, 0, 0, 5).await, vec![10, 9, 8, 7, 6]);
+    manager
+        .process_update(dc.add_message(history_message(11)))
+        .unwrap();
+    manager
+        .process_update(dc.add_message(history_message(12)))
+        .unwrap();
+
+    assert_eq!(
+        get_history(&manager, 0, 0, 7).await,
+        vec![12, 11, 10, 9, 8, 7, 6]
+    );
+    assert_eq!(dc.history_request_count(), 1);
+}
+
+#[tokio::test]
+async fn messages_received_before_loading_are_not_trusted_to_be_contiguous() {
+    let (manager, dc) = setup_with_history(20);
+    manager
+        .process_update(dc.add_message(history_message(21)))
+        .unwrap();
+    manager
+        .process_update(dc.add_message(history_message(22)))
+        .unwrap();
+
+    // Only the received messages are known locally
+    let local = manager
+        .get_chat_history(peer(), MessageId::default(), 0, 5, true)
+        .await
+        .unwrap();
+    assert_eq!(ids(&local), vec![22, 21]);
+    assert_eq!(dc.history_request_count(), 0);
+
+    assert_eq!(
+        get_history(&manager, 0, 0, 5).await,
+        vec![22, 21, 20, 19, 18]
+    );
+    assert_eq!(dc.history_request_count(), 1);
+}
+
+#[tokio::test]
+async fn start_of_history_is_remembered() {
+    let (manager, dc) = setup_with_history(5);
+
+    assert_eq!(get_history(&manager, 0, 0, 10).await, vec![5, 4, 3, 2, 1]);
+    assert_eq!(get_history(&manager, 0, 0, 10).await, vec![5, 4, 3, 2, 1]);
+    assert_eq!(get_history(&manager, 2, 0, 10).await, vec![2, 1]);
+    assert_eq!(dc.history_request_count(), 1);
+}
+
+#[tokio::test]
+async fn messages_that_cant_be_converted_leave_holes() {
+    let (manager, dc, _recorder) = setup();
+    for id in 1..=20 {
+        let mut data = history_message(id);
+        if id == 18 {
+            data.sender_id = 0;
+        }
+        dc.add_message(data);
+    }
+
+    assert_eq!(get_history(&manager, 0, 0, 5).await, vec![20, 19]);
+    assert_eq!(dc.history_request_count(), 1);
+
+    // The page held five messages, so 16 isn't the first message
+    assert_eq!(
+        get_history(&manager, 17, 0, 5).await,
+        (13..=17).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+}
+
+#[tokio::test]
+async fn negative_offset_returns_newer_messages() {
+    let (manager, dc) = setup_with_history(30);
+
+    assert_eq!(
+        get_history(&manager, 10, -5, 10).await,
+        (6..=15).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 1);
+    assert_eq!(dc.history_requests.lock()[0].add_offset, -5);
+
+    assert_eq!(
+        get_history(&manager, 12, -3, 8).await,
+        (8..=15).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 1);
+
+    // The newest messages aren't known yet
+    assert_eq!(
+        get_history(&manager, 14, -4, 6).await,
+        (13..=18).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+}
+
+#[tokio::test]
+async fn deleted_messages_keep_the_history_contiguous() {
+    let (manager, dc) = setup_with_history(10);
+    assert_eq!(get_history(&manager, 0, 0, 20).await.len(), 10);
+
+    manager
+        .process_update(Update::DeleteMessages(UpdateDeleteMessages {
+            flags: 0,
+            messages: vec![5, 10],
+            pts: 2,
+            pts_count: 2,
+        }))
+        .unwrap();
+
+    assert_eq!(
+        get_history(&manager, 0, 0, 10).await,
+        vec![9, 8, 7, 6, 4, 3, 2, 1]
+    );
+    assert_eq!(dc.history_request_count(), 1);
+}
+
+#[tokio::test]
+async fn history_is_kept_in_the_message_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = MessageDb::new(DbConnection::new(dir.path().join("messages.db")).unwrap());
+    db.init().unwrap();
+
+    let (manager, dc) = setup_with_history(30);
+    manager.set_message_db(&db);
+    assert_eq!(get_history(&manager, 0, 0, 20).await.len(), 20);
+    assert_eq!(
+        get_history(&manager, 11, 0, 20).await,
+        (1..=11).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+
+    // A new manager reads the loaded history from the database
+    let (restarted, restarted_dc) = setup_with_history(30);
+    restarted.set_message_db(&db);
+    assert_eq!(
+        get_history(&restarted, 15, 0, 20).await,
+        (1..=15).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(restarted_dc.history_request_count(), 0);
+
+    // The last message of the dialog may have changed while offline
+    assert_eq!(
+        get_history(&restarted, 0, 0, 5).await,
+        (26..=30).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(restarted_dc.history_request_count(), 1);
+    assert_eq!(
+        get_history(&restarted, 0, 0, 30).await,
+        (1..=30).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(restarted_dc.history_request_count(), 1);
+}
+
+#[tokio::test]
+async fn dropped_history_is_requested_again() {
+    let (manager, dc) = setup_with_history(30);
+
+    get_history(&manager, 0, 0, 10).await;
+    get_history(&manager, 0, 0, 10).await;
+    assert_eq!(dc.history_request_count(), 1);
+
+    manager.on_channel_difference_too_long(peer());
+    assert!(manager.get_message(peer(), id(30)).is_none());
+    assert_eq!(
+        get_history(&manager, 0, 0, 10).await,
+        (21..=30).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(dc.history_request_count(), 2);
+}
+
+#[tokio::test]
+async fn failed_request_is_reported_and_can_be_repeated() {
+    let (manager, dc) = setup_with_history(10);
+
+    dc.fail_next(420, "FLOOD_WAIT_3");
+    let error = manager
+        .get_chat_history(peer(), MessageId::default(), 0, 5, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, MessagesManagerError::Network(_)));
+
+    assert_eq!(get_history(&manager, 0, 0, 5).await, vec![10, 9, 8, 7, 6]);
+    assert_eq!(dc.history_request_count(), 2);
+}
+
+#[tokio::test]
+async fn reading_history_is_sent_for_server_messages() {
+    let (manager, dc) = setup_with_history(10);
+
+    manager.read_history(peer(), id(7)).await.unwrap();
+    let yet_unsent = MessageId((1 << 3) | 1);
+    manager.read_history(peer(), yet_unsent).await.unwrap();
+
+    let requests = dc.read_requests.lock();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].max_id, 7);
+    assert!(!requests[0].is_channel());
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_rejected() {
+    let (manager, dc) = setup_with_history(10);
+
+    for (offset, limit) in [(0, 0), (0, 101), (1, 10), (-10, 10)] {
+        assert!(manager
+            .get_chat_history(peer(), MessageId::default(), offset, limit, false)
+            .await
+            .is_err());
+    }
+    let yet_unsent = MessageId((1 << 3) | 1);
+    assert!(manager
+        .get_chat_history(peer(), yet_unsent, 0, 10, false)
+        .await
+        .is_err());
+    assert_eq!(dc.history_request_count(), 0);
+}
+
+#[tokio::test]
+async fn history_client_is_required_for_missing_messages() {
+    let network_client = Arc::new(MessageNetworkClient::new(
+        Arc::new(NetQueryDispatcher::new()),
+        MessageNetworkConfig::default(),
+    ));
+    let manager = MessagesManager::new(network_client, MessagesManagerConfig::default());
+
+    let local = manager
+        .get_chat_history(peer(), MessageId::default(), 0, 10, true)
+        .await
+        .unwrap();
+    assert!(local.is_empty());
+    assert!(manager
+        .get_chat_history(peer(), MessageId::default(), 0, 10, false)
+        .await
+        .is_err());
+}
@@ -0,0 +1,477 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Fake DC and update recorder shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rustgram_message_content::MessageContent;
+use rustgram_message_types::Message;
+use rustgram_messages_manager::{
+    AffectedMessages, DeleteMessagesRequest, EditMessageRequest, ForwardMessagesRequest,
+    GetHistoryRequest, InputFile, InputMedia, MediaSendClient, MessageData, MessageEditClient,
+    MessageHistoryClient, MessageMedia, MessageNetworkClient, MessageNetworkConfig,
+    MessageUpdateCallback, Messages, MessagesManager, MessagesManagerConfig, MessagesManagerError,
+    ReadHistoryRequest, SaveFilePartRequest, SendMediaRequest, SendMessageNetworkError,
+    SendMultiMediaRequest, Update, UpdateEditMessage, UpdateMessageId, UpdateNewMessage, Updates,
+};
+use rustgram_net::NetQueryDispatcher;
+use rustgram_types::{DialogId, MessageId, UserId};
+use tokio::sync::Notify;
+
+pub const MY_USER_ID: i64 = 777;
+pub const PEER_USER_ID: i64 = 123456;
+pub const DATE: i32 = 1_700_000_000;
+
+/// Fake DC keeping the messages of the account and the uploaded files.
+#[derive(Default)]
+pub struct FakeDc {
+    /// Messages by server ID
+    pub messages: Mutex<BTreeMap<i32, MessageData>>,
+    /// Uploaded parts by upload ID and part number
+    pub uploads: Mutex<HashMap<i64, BTreeMap<i32, Vec<u8>>>>,
+    /// Total part counts declared for big files
+    pub big_file_parts: Mutex<HashMap<i64, i32>>,
+    /// Received sendMedia requests
+    pub sent: Mutex<Vec<SendMediaRequest>>,
+    /// Received sendMultiMedia requests
+    pub sent_albums: Mutex<Vec<SendMultiMediaRequest>>,
+    /// Received editMessage requests
+    pub edits: Mutex<Vec<EditMessageRequest>>,
+    /// Received forwardMessages requests
+    pub forwards: Mutex<Vec<ForwardMessagesRequest>>,
+    /// Received deleteMessages requests
+    pub deletes: Mutex<Vec<DeleteMessagesRequest>>,
+    /// Received getHistory requests
+    pub history_requests: Mutex<Vec<GetHistoryRequest>>,
+    /// Received readHistory requests
+    pub read_requests: Mutex<Vec<ReadHistoryRequest>>,
+    /// Error returned by the next request
+    next_error: Mutex<Option<(i32, String)>>,
+    /// Released to answer the next request; requests are answered at once
+    /// if not set
+    gate: Mutex<Option<Arc<Notify>>>,
+    /// Last assigned message ID
+    last_message_id: Mutex<i32>,
+}
+
+impl FakeDc {
+    /// Makes the next request fail with an RPC error.
+    pub fn fail_next(&self, code: i32, message: &str) {
+        *self.next_error.lock() = Some((code, message.to_string()));
+    }
+
+    /// Holds the answer to the next request until the returned gate is
+    /// notified.
+    pub fn hold_next(&self) -> Arc<Notify> {
+        let gate = Arc::new(Notify::new());
+        *self.gate.lock() = Some(Arc::clone(&gate));
+        gate
+    }
+
+    async fn answer(&self) -> Result<(), SendMessageNetworkError> {
+        let gate = self.gate.lock().take();
+        if let Some(gate) = gate {
+            gate.notified().await;
+        }
+        match self.next_error.lock().take() {
+            Some((code, message)) => Err(SendMessageNetworkError::from_rpc_error(code, &message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a message to the DC and returns the update announcing it.
+    pub fn add_message(&self, data: MessageData) -> Update {
+        {
+            let mut last_message_id = self.last_message_id.lock();
+            *last_message_id = (*last_message_id).max(data.id);
+        }
+        self.messages.lock().insert(data.id, data.clone());
+        Update::NewMessage(UpdateNewMessage::new(data, 1, 1))
+    }
+
+    fn next_message_id(&self) -> i32 {
+        let mut last_message_id = self.last_message_id.lock();
+        *last_message_id += 1;
+        *last_message_id
+    }
+
+    /// Returns the contents of an uploaded file, checking that all parts
+    /// were uploaded.
+    pub fn uploaded_file(&self, file: &InputFile) -> Vec<u8> {
+        let uploads = self.uploads.lock();
+        let parts = uploads.get(&file.id()).expect("file not uploaded");
+        assert_eq!(parts.len(), file.parts() as usize, "missing parts");
+        assert_eq!(*parts.keys().last().unwrap(), file.parts() - 1);
+        parts.values().flatten().copied().collect()
+    }
+
+    /// Returns the number of uploaded file parts.
+    pub fn uploaded_part_count(&self) -> usize {
+        self.uploads.lock().values().map(BTreeMap::len).sum()
+    }
+
+    /// Returns the number of received getHistory requests.
+    pub fn history_request_count(&self) -> usize {
+        self.history_requests.lock().len()
+    }
+
+    /// Creates the message for a sent item and returns the updates
+    /// announcing it.
+    fn send_item(
+        &self,
+        peer_id: i64,
+        media: &InputMedia,
+        text: &str,
+        random_id: i64,
+        grouped_id: Option<i64>,
+    ) -> [Update; 2] {
+        if let Some(file) = media.file() {
+            if let InputFile::Big { id, parts, .. } = file {
+                assert_eq!(self.big_file_parts.lock().get(id), Some(parts));
+            }
+            self.uploaded_file(file);
+        }
+        let id = self.next_message_id();
+        let data = MessageData::new(id, peer_id, MY_USER_ID, DATE, text.to_string())
+            .with_media(message_media(media));
+        let data = match grouped_id {
+            Some(grouped_id) => data.with_grouped_id(grouped_id),
+            None => data,
+        };
+        [
+            Update::MessageId(UpdateMessageId { id, random_id }),
+            self.add_message(data),
+        ]
+    }
+}
+
+/// Media of the message the server creates from the sent media.
+pub fn message_media(media: &InputMedia) -> MessageMedia {
+    match media {
+        InputMedia::UploadedPhoto { file, spoiler, .. } => MessageMedia::Photo {
+            id: file.id(),
+            spoiler: *spoiler,
+        },
+        InputMedia::UploadedDocument {
+            file,
+            mime_type,
+            attributes,
+            spoiler,
+            ..
+        } => MessageMedia::Document {
+            id: file.id(),
+            mime_type: mime_type.clone(),
+            attributes: attributes.clone(),
+            spoiler: *spoiler,
+        },
+        InputMedia::Photo { id, spoiler, .. } => MessageMedia::Photo {
+            id: *id,
+            spoiler: *spoiler,
+        },
+        _ => MessageMedia::Unsupported,
+    }
+}
+
+#[async_trait::async_trait]
+impl MediaSendClient for FakeDc {
+    async fn save_file_part(
+        &self,
+        request: SaveFilePartRequest,
+    ) -> Result<bool, SendMessageNetworkError> {
+        if let Some(total_parts) = request.file_total_parts {
+            self.big_file_parts
+                .lock()
+                .insert(request.file_id, total_parts);
+        }
+        self.uploads
+            .lock()
+            .entry(request.file_id)
+            .or_default()
+            .insert(request.file_part, request.bytes);
+        Ok(true)
+    }
+
+    async fn send_media(
+        &self,
+        request: SendMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        self.answer().await?;
+        let updates = self.send_item(
+            request.peer.peer_id(),
+            &request.media,
+            &request.message,
+            request.random_id,
+            None,
+        );
+        self.sent.lock().push(request);
+        Ok(Updates::new(updates.to_vec(), DATE, 0))
+    }
+
+    async fn send_multi_media(
+        &self,
+        request: SendMultiMediaRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        self.answer().await?;
+        let grouped_id = 1000 + i64::from(*self.last_message_id.lock());
+        let mut updates = Vec::new();
+        for item in &request.multi_media {
+            assert!(item.media.file().is_some(), "album item without file");
+            updates.extend(self.send_item(
+                request.peer.peer_id(),
+                &item.media,
+                &item.message,
+                item.random_id,
+                Some(grouped_id),
+            ));
+        }
+        self.sent_albums.lock().push(request);
+        Ok(Updates::new(updates, DATE, 0))
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageEditClient for FakeDc {
+    async fn edit_message(
+        &self,
+        request: EditMessageRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        self.edits.lock().push(request.clone());
+        self.answer().await?;
+
+        let mut messages = self.messages.lock();
+        let data = messages.get_mut(&request.id).expect("unknown message");
+        if let Some(text) = request.message {
+            data.message = text;
+        }
+        if let Some(media) = &request.media {
+            data.media = Some(message_media(media));
+        }
+        data.reply_markup = request.reply_markup;
+        data.edit_date = Some(DATE + 60);
+
+        Ok(Updates::new(
+            vec![Update::EditMessage(UpdateEditMessage {
+                message: data.clone(),
+                pts: 2,
+                pts_count: 1,
+            })],
+            DATE + 60,
+            0,
+        ))
+    }
+
+    async fn forward_messages(
+        &self,
+        request: ForwardMessagesRequest,
+    ) -> Result<Updates, SendMessageNetworkError> {
+        self.forwards.lock().push(request.clone());
+        self.answer().await?;
+
+        let mut updates = Vec::new();
+        for (id, &random_id) in request.id.iter().zip(&request.random_id) {
+            let original = self
+                .messages
+                .lock()
+                .get(id)
+                .cloned()
+                .expect("unknown message");
+            let new_id = self.next_message_id();
+            let mut data = MessageData::new(
+                new_id,
+                request.to_peer.peer_id(),
+                MY_USER_ID,
+                DATE + 60,
+                original.message.clone(),
+            );
+            data.media = original.media.clone();
+            if request.drop_media_captions == Some(true) && data.media.is_some() {
+                data.message.clear();
+            }
+            updates.push(Update::MessageId(UpdateMessageId {
+                id: new_id,
+                random_id,
+            }));
+            updates.push(self.add_message(data));
+        }
+        Ok(Updates::new(updates, DATE + 60, 0))
+    }
+
+    async fn delete_messages(
+        &self,
+        request: DeleteMessagesRequest,
+    ) -> Result<AffectedMessages, SendMessageNetworkError> {
+        self.deletes.lock().push(request.clone());
+        self.answer().await?;
+
+        let mut messages = self.messages.lock();
+        for id in &request.id {
+            messages.remove(id);
+        }
+        Ok(AffectedMessages {
+            pts: 3,
+            pts_count: request.id.len() as i32,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageHistoryClient for FakeDc {
+    async fn get_history(
+        &self,
+        request: GetHistoryRequest,
+    ) -> Result<Messages, SendMessageNetworkError> {
+        self.history_requests.lock().push(request.clone());
+        self.answer().await?;
+
+        let newest_first: Vec<MessageData> = self.messages.lock().values().rev().cloned().collect();
+        let position = newest_first
+            .iter()
+            .position(|data| request.offset_id == 0 || data.id < request.offset_id)
+            .unwrap_or(newest_first.len()) as i32;
+        let start = (position + request.add_offset).max(0) as usize;
+        let end = (position + request.add_offset + request.limit).max(0) as usize;
+        let page = newest_first
+            .iter()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .cloned()
+            .collect();
+        Ok(Messages::new(page))
+    }
+
+    async fn read_history(
+        &self,
+        request: ReadHistoryRequest,
+    ) -> Result<Option<AffectedMessages>, SendMessageNetworkError> {
+        self.read_requests.lock().push(request);
+        self.answer().await?;
+        Ok(Some(AffectedMessages {
+            pts: 4,
+            pts_count: 1,
+        }))
+    }
+}
+
+/// Events reported through the update callback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    New(MessageId),
+    Edited(MessageId, String),
+    Deleted(Vec<MessageId>),
+    SendSucceeded(MessageId, MessageId),
+    SendFailed(MessageId, Option<Duration>),
+}
+
+impl Event {
+    /// Returns `true` for the result of sending a message.
+    pub fn is_send_result(&self) -> bool {
+        matches!(self, Self::SendSucceeded(..) | Self::SendFailed(..))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Recorder {
+    pub events: Arc<Mutex<Vec<Event>>>,
+    pub messages: Arc<Mutex<Vec<Message>>>,
+}
+
+impl Recorder {
+    /// Takes the recorded events.
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock())
+    }
+
+    /// Returns the recorded results of sending messages.
+    pub fn send_results(&self) -> Vec<Event> {
+        let events = self.events.lock();
+        events
+            .iter()
+            .filter(|event| event.is_send_result())
+            .cloned()
+            .collect()
+    }
+}
+
+impl MessageUpdateCallback for Recorder {
+    fn on_new_message(&self, message: Message) {
+        self.events.lock().push(Event::New(message.id));
+        self.messages.lock().push(message);
+    }
+
+    fn on_message_edited(&self, message: Message) {
+        self.events
+            .lock()
+            .push(Event::Edited(message.id, text(&message)));
+    }
+
+    fn on_messages_deleted(&self, _dialog_id: DialogId, message_ids: Vec<MessageId>) {
+        self.events.lock().push(Event::Deleted(message_ids));
+    }
+
+    fn on_messages_read(&self, _dialog_id: DialogId, _max_id: MessageId) {}
+
+    fn on_message_send_succeeded(
+        &self,
+        _dialog_id: DialogId,
+        old_message_id: MessageId,
+        message_id: MessageId,
+    ) {
+        self.events
+            .lock()
+            .push(Event::SendSucceeded(old_message_id, message_id));
+    }
+
+    fn on_message_send_failed(
+        &self,
+        _dialog_id: DialogId,
+        message_id: MessageId,
+        error: &MessagesManagerError,
+    ) {
+        self.events
+            .lock()
+            .push(Event::SendFailed(message_id, error.retry_after()));
+    }
+}
+
+/// Returns the text or caption of a message.
+pub fn text(message: &Message) -> String {
+    match &message.content {
+        MessageContent::Text(text) => text.text.text().to_string(),
+        content => content
+            .caption()
+            .map(|caption| caption.text().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Creates a manager using a new fake DC for all queries.
+pub fn setup() -> (Arc<MessagesManager>, Arc<FakeDc>, Recorder) {
+    let network_client = Arc::new(MessageNetworkClient::new(
+        Arc::new(NetQueryDispatcher::new()),
+        MessageNetworkConfig::default(),
+    ));
+    let manager = MessagesManager::new(network_client, MessagesManagerConfig::default());
+    let dc = Arc::new(FakeDc::default());
+    manager.set_media_client(dc.clone());
+    manager.set_edit_client(dc.clone());
+    manager.set_history_client(dc.clone());
+    let recorder = Recorder::default();
+    manager.set_update_callback(Box::new(recorder.clone()));
+    (Arc::new(manager), dc, recorder)
+}
+
+pub fn peer() -> DialogId {
+    DialogId::from(UserId::new(PEER_USER_ID).unwrap())
+}
+
+/// Returns a text message received from the peer.
+pub fn text_message(id: i32, text: &str) -> MessageData {
+    MessageData::new(id, PEER_USER_ID, PEER_USER_ID, DATE, text.to_string())
+}
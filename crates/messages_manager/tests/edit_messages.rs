@@ -0,0 +1,414 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Editing, forwarding and deleting messages against a fake DC, with the
+//! local copy of the messages updated before the server answers.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::sync::Arc;
+
+use common::{peer, setup, text, text_message, Event, FakeDc, DATE};
+use rustgram_formatted_text::FormattedText;
+use rustgram_message_content::MessageContent;
+use rustgram_message_copy_options::MessageCopyOptions;
+use rustgram_messages_manager::{
+    InputMessageMedia, LocalFile, MessageData, MessageMedia, MessagesManager, MessagesManagerError,
+    Update, UpdateDeleteMessages, UpdateEditMessage,
+};
+use rustgram_reply_markup::{InlineKeyboardButton, InlineKeyboardMarkup, ReplyMarkup};
+use rustgram_storage::{DbConnection, MessageDb};
+use rustgram_types::{ChannelId, DialogId, MessageId, UserId};
+
+const OTHER_USER_ID: i64 = 654321;
+
+fn other_peer() -> DialogId {
+    DialogId::from(UserId::new(OTHER_USER_ID).unwrap())
+}
+
+/// Puts a message into the DC and delivers it to the manager.
+fn receive(manager: &MessagesManager, dc: &FakeDc, data: MessageData) -> MessageId {
+    let id = MessageId::from_server_id(data.id);
+    manager.process_update(dc.add_message(data)).unwrap();
+    id
+}
+
+fn photo_message(id: i32, caption: &str) -> MessageData {
+    text_message(id, caption).with_media(MessageMedia::Photo {
+        id: 5,
+        spoiler: false,
+    })
+}
+
+#[tokio::test]
+async fn edited_text_is_visible_before_the_server_answers() {
+    let (manager, dc, recorder) = setup();
+    let id = receive(&manager, &dc, text_message(10, "Hello"));
+    recorder.take();
+
+    let gate = dc.hold_next();
+    let edit = tokio::spawn({
+        let manager = Arc::clone(&manager);
+        async move {
+            manager
+                .edit_message_text(peer(), id, FormattedText::new("Hello, world"))
+                .await
+        }
+    });
+    tokio::task::yield_now().await;
+    while dc.edits.lock().is_empty() {
+        tokio::task::yield_now().await;
+    }
+
+    assert_eq!(
+        text(&manager.get_message(peer(), id).unwrap()),
+        "Hello, world"
+    );
+    assert!(manager.get_message(peer(), id).unwrap().edit_date.is_none());
+
+    gate.notify_one();
+    let message = edit.await.unwrap().unwrap();
+    assert_eq!(text(&message), "Hello, world");
+    assert_eq!(message.edit_date, Some(DATE + 60));
+
+    let request = dc.edits.lock()[0].clone();
+    assert_eq!(request.id, 10);
+    assert_eq!(request.message.as_deref(), Some("Hello, world"));
+    assert_eq!(request.flags & 0x800, 0x800);
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Edited(id, "Hello, world".to_string()),
+            Event::Edited(id, "Hello, world".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rejected_edit_is_rolled_back() {
+    let (manager, dc, recorder) = setup();
+    let id = receive(&manager, &dc, text_message(10, "Hello"));
+    recorder.take();
+
+    dc.fail_next(400, "MESSAGE_AUTHOR_REQUIRED");
+    let error = manager
+        .edit_message_text(peer(), id, FormattedText::new("Hijacked"))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, MessagesManagerError::Network(_)));
+    assert_eq!(text(&manager.get_message(peer(), id).unwrap()), "Hello");
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Edited(id, "Hijacked".to_string()),
+            Event::Edited(id, "Hello".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn not_modified_edit_is_not_an_error() {
+    let (manager, dc, _recorder) = setup();
+    let id = receive(&manager, &dc, text_message(10, "Hello"));
+
+    dc.fail_next(400, "MESSAGE_NOT_MODIFIED");
+    let message = manager
+        .edit_message_text(peer(), id, FormattedText::new("Hello"))
+        .await
+        .unwrap();
+    assert_eq!(text(&message), "Hello");
+}
+
+#[tokio::test]
+async fn edits_are_checked_against_the_content() {
+    let (manager, dc, _recorder) = setup();
+    let text_id = receive(&manager, &dc, text_message(10, "Hello"));
+    let photo_id = receive(&manager, &dc, photo_message(11, "Sunset"));
+
+    let error = manager
+        .edit_message_caption(peer(), text_id, FormattedText::new("Caption"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, MessagesManagerError::Validation(_)));
+
+    let error = manager
+        .edit_message_text(peer(), photo_id, FormattedText::new("Text"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, MessagesManagerError::Validation(_)));
+
+    let error = manager
+        .edit_message_text(
+            peer(),
+            MessageId::from_server_id(99),
+            FormattedText::new("Text"),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, MessagesManagerError::MessageNotFound(_, _)));
+    assert!(dc.edits.lock().is_empty());
+}
+
+#[tokio::test]
+async fn caption_and_reply_markup_are_edited() {
+    let (manager, dc, _recorder) = setup();
+    let id = receive(&manager, &dc, photo_message(11, "Sunset"));
+
+    let message = manager
+        .edit_message_caption(peer(), id, FormattedText::new("Sunrise"))
+        .await
+        .unwrap();
+    assert_eq!(text(&message), "Sunrise");
+    assert!(matches!(message.content, MessageContent::Photo(_)));
+
+    let mut keyboard = InlineKeyboardMarkup::new();
+    keyboard.add_row(vec![InlineKeyboardButton::Url {
+        text: "Open".to_string(),
+        url: "https://example.com".to_string(),
+        id: 0,
+    }]);
+    let reply_markup = ReplyMarkup::InlineKeyboard(keyboard);
+    let message = manager
+        .edit_message_reply_markup(peer(), id, Some(reply_markup.clone()))
+        .await
+        .unwrap();
+    assert_eq!(message.reply_markup, Some(reply_markup));
+    assert_eq!(text(&message), "Sunrise");
+
+    let edits = dc.edits.lock().clone();
+    assert_eq!(edits[1].flags, 0x04);
+    assert!(edits[1].message.is_none());
+}
+
+#[tokio::test]
+async fn media_is_uploaded_and_replaced() {
+    let (manager, dc, _recorder) = setup();
+    let id = receive(&manager, &dc, photo_message(11, "Sunset"));
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&[7; 1000]).unwrap();
+    file.flush().unwrap();
+    let media = InputMessageMedia::document(
+        LocalFile::new(file.path().to_string_lossy(), 1000).with_name("notes.txt"),
+        FormattedText::new("Notes"),
+    );
+
+    let message = manager.edit_message_media(peer(), id, media).await.unwrap();
+    assert!(matches!(message.content, MessageContent::Document(_)));
+    assert_eq!(text(&message), "Notes");
+    assert_eq!(dc.uploaded_part_count(), 1);
+
+    let request = dc.edits.lock()[0].clone();
+    assert_eq!(request.flags & 0x4800, 0x4800);
+    assert_eq!(request.message.as_deref(), Some("Notes"));
+}
+
+#[tokio::test]
+async fn forwarded_copies_drop_author_and_captions() {
+    let (manager, dc, recorder) = setup();
+    let photo_id = receive(&manager, &dc, photo_message(11, "Sunset"));
+    let text_id = receive(&manager, &dc, text_message(12, "Look"));
+    recorder.take();
+
+    let options = MessageCopyOptions::with_flags(true, true);
+    let ids = manager
+        .forward_messages(other_peer(), peer(), &[photo_id, text_id], options)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ids,
+        vec![MessageId::from_server_id(13), MessageId::from_server_id(14)]
+    );
+    let request = dc.forwards.lock()[0].clone();
+    assert_eq!(request.id, vec![11, 12]);
+    assert_eq!(request.flags, 0x800 | 0x1000);
+    assert_eq!(request.random_id.len(), 2);
+    assert_ne!(request.random_id[0], request.random_id[1]);
+
+    let photo_copy = manager.get_message(other_peer(), ids[0]).unwrap();
+    assert_eq!(text(&photo_copy), "");
+    assert_eq!(
+        text(&manager.get_message(other_peer(), ids[1]).unwrap()),
+        "Look"
+    );
+    // The originals keep their captions
+    assert_eq!(
+        text(&manager.get_message(peer(), photo_id).unwrap()),
+        "Sunset"
+    );
+}
+
+#[tokio::test]
+async fn rejected_forward_removes_the_copies() {
+    let (manager, dc, recorder) = setup();
+    let id = receive(&manager, &dc, text_message(12, "Look"));
+    recorder.take();
+
+    let gate = dc.hold_next();
+    dc.fail_next(400, "CHAT_WRITE_FORBIDDEN");
+    let forward = tokio::spawn({
+        let manager = Arc::clone(&manager);
+        async move {
+            manager
+                .forward_messages(other_peer(), peer(), &[id], MessageCopyOptions::new())
+                .await
+        }
+    });
+    while dc.forwards.lock().is_empty() {
+        tokio::task::yield_now().await;
+    }
+
+    let copy_id = MessageId((1 << 3) | 1);
+    assert_eq!(
+        text(&manager.get_message(other_peer(), copy_id).unwrap()),
+        "Look"
+    );
+
+    gate.notify_one();
+    assert!(forward.await.unwrap().is_err());
+    assert!(manager.get_message(other_peer(), copy_id).is_none());
+    assert_eq!(recorder.take(), vec![Event::SendFailed(copy_id, None)]);
+}
+
+#[tokio::test]
+async fn forward_with_new_caption_is_rejected() {
+    let (manager, dc, _recorder) = setup();
+    let id = receive(&manager, &dc, photo_message(11, "Sunset"));
+
+    let options = MessageCopyOptions::new().with_new_caption(FormattedText::new("Mine"));
+    let error = manager
+        .forward_messages(other_peer(), peer(), &[id], options)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, MessagesManagerError::Validation(_)));
+    assert!(dc.forwards.lock().is_empty());
+}
+
+#[tokio::test]
+async fn deleted_messages_are_restored_if_the_server_rejects_the_deletion() {
+    let (manager, dc, recorder) = setup();
+    let first = receive(&manager, &dc, text_message(10, "One"));
+    let second = receive(&manager, &dc, text_message(11, "Two"));
+    recorder.take();
+
+    dc.fail_next(403, "MESSAGE_DELETE_FORBIDDEN");
+    assert!(manager
+        .delete_messages(peer(), &[first, second], true)
+        .await
+        .is_err());
+
+    assert_eq!(text(&manager.get_message(peer(), first).unwrap()), "One");
+    assert_eq!(text(&manager.get_message(peer(), second).unwrap()), "Two");
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Deleted(vec![first, second]),
+            Event::New(first),
+            Event::New(second),
+        ]
+    );
+
+    manager
+        .delete_messages(peer(), &[first], true)
+        .await
+        .unwrap();
+    assert!(manager.get_message(peer(), first).is_none());
+    assert!(manager.get_message(peer(), second).is_some());
+
+    let deletes = dc.deletes.lock().clone();
+    assert_eq!(deletes[1].id, vec![10]);
+    assert_eq!(deletes[1].revoke, Some(true));
+}
+
+#[tokio::test]
+async fn channel_messages_are_always_revoked() {
+    let (manager, dc, _recorder) = setup();
+    let channel = DialogId::from(ChannelId::new(123).unwrap());
+    let mut data = text_message(10, "News");
+    data.dialog_id = channel.to_encoded();
+    let id = receive(&manager, &dc, data);
+
+    manager
+        .delete_messages(channel, &[id], false)
+        .await
+        .unwrap();
+
+    let request = dc.deletes.lock()[0].clone();
+    assert!(request.is_channel());
+    assert!(request.revoke.is_none());
+    assert!(manager.get_message(channel, id).is_none());
+}
+
+#[tokio::test]
+async fn server_deletions_and_edits_update_the_local_messages() {
+    let (manager, dc, recorder) = setup();
+    let id = receive(&manager, &dc, text_message(10, "Hello"));
+    recorder.take();
+
+    let mut edited = text_message(10, "Hello again");
+    edited.edit_date = Some(DATE + 5);
+    manager
+        .process_update(Update::EditMessage(UpdateEditMessage {
+            message: edited,
+            pts: 2,
+            pts_count: 1,
+        }))
+        .unwrap();
+    assert_eq!(
+        text(&manager.get_message(peer(), id).unwrap()),
+        "Hello again"
+    );
+
+    manager
+        .process_update(Update::DeleteMessages(UpdateDeleteMessages {
+            flags: 0,
+            messages: vec![10, 42],
+            pts: 3,
+            pts_count: 2,
+        }))
+        .unwrap();
+    assert!(manager.get_message(peer(), id).is_none());
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Edited(id, "Hello again".to_string()),
+            Event::Deleted(vec![id]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn changes_are_saved_to_the_message_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = MessageDb::new(DbConnection::new(dir.path().join("messages.db")).unwrap());
+    db.init().unwrap();
+
+    let (manager, dc, _recorder) = setup();
+    manager.set_message_db(&db);
+    let first = receive(&manager, &dc, text_message(10, "One"));
+    let second = receive(&manager, &dc, text_message(11, "Two"));
+    manager
+        .edit_message_text(peer(), first, FormattedText::new("One, edited"))
+        .await
+        .unwrap();
+    manager
+        .delete_messages(peer(), &[second], false)
+        .await
+        .unwrap();
+
+    // A new manager reads the messages from the database
+    let (restarted, _dc, _recorder) = setup();
+    restarted.set_message_db(&db);
+    let message = restarted.get_message(peer(), first).unwrap();
+    assert_eq!(text(&message), "One, edited");
+    assert_eq!(message.edit_date, Some(DATE + 60));
+    assert!(restarted.get_message(peer(), second).is_none());
+    assert_eq!(db.sync().get_message_count(peer().to_encoded()).unwrap(), 1);
+}
@@ -0,0 +1,479 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Sending media messages and albums end to end against a fake DC.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{peer, setup, Event};
+use parking_lot::Mutex;
+use rustgram_file_id::FileId;
+use rustgram_file_reference_manager::{FileReferenceManager, FileSource, FileSourceReloader};
+use rustgram_formatted_text::{entity_type, FormattedText, MessageEntity as TextEntity};
+use rustgram_message_content::{MessageContact, MessageLocation, MessageVenue};
+use rustgram_messages_manager::{
+    group_messages_by_album, InputFile, InputMedia, InputMessageMedia, InputPoll, LocalFile,
+    MessageEntity, MessagesManagerError, RemoteFile, SendMessageNetworkError, Update,
+    UpdateMessageId,
+};
+use rustgram_types::MessageId;
+use tempfile::NamedTempFile;
+
+fn temp_file(size: usize) -> (NamedTempFile, Vec<u8>) {
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(&data).unwrap();
+    file.flush().unwrap();
+    (file, data)
+}
+
+fn local_file(file: &NamedTempFile, size: usize) -> LocalFile {
+    LocalFile::new(file.path().to_string_lossy(), size as i64)
+}
+
+#[tokio::test]
+async fn photo_is_uploaded_and_sent_with_caption() {
+    let (manager, dc, recorder) = setup();
+    let (file, data) = temp_file(100_000);
+    let caption = FormattedText::with_entities(
+        "Look at this",
+        vec![TextEntity::new(entity_type::BOLD, 0, 4)],
+    );
+    let media = InputMessageMedia::photo(local_file(&file, data.len()), caption);
+
+    let message_id = manager.send_media(peer(), media, None).await.unwrap();
+    assert_eq!(message_id, MessageId::from_server_id(1));
+
+    let sent = dc.sent.lock();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].message, "Look at this");
+    assert_eq!(
+        sent[0].entities,
+        Some(vec![MessageEntity::Bold {
+            offset: 0,
+            length: 4
+        }])
+    );
+    let InputMedia::UploadedPhoto {
+        file: input_file, ..
+    } = &sent[0].media
+    else {
+        panic!("Expected uploaded photo, got {:?}", sent[0].media);
+    };
+    assert!(matches!(input_file, InputFile::Small { parts: 4, .. }));
+    assert_eq!(dc.uploaded_file(input_file), data);
+
+    let events = recorder.send_results();
+    let [Event::SendSucceeded(old_message_id, new_message_id)] = events.as_slice() else {
+        panic!("Unexpected events {:?}", events);
+    };
+    assert!(old_message_id.is_yet_unsent());
+    assert_eq!(*new_message_id, message_id);
+    assert_eq!(
+        manager.get_sent_message_id(peer(), *old_message_id),
+        Some(message_id)
+    );
+}
+
+#[tokio::test]
+async fn big_video_is_uploaded_with_big_file_parts() {
+    let (manager, dc, _recorder) = setup();
+    let (file, data) = temp_file(2_500_000);
+    let media = InputMessageMedia::video(
+        local_file(&file, data.len()).with_mime_type("video/mp4"),
+        FormattedText::new(""),
+    );
+
+    manager.send_media(peer(), media, None).await.unwrap();
+
+    let sent = dc.sent.lock();
+    let InputMedia::UploadedDocument {
+        file: input_file,
+        mime_type,
+        ..
+    } = &sent[0].media
+    else {
+        panic!("Expected uploaded document, got {:?}", sent[0].media);
+    };
+    assert_eq!(mime_type, "video/mp4");
+    assert!(matches!(input_file, InputFile::Big { parts: 5, .. }));
+    assert_eq!(dc.uploaded_file(input_file), data);
+    assert!(sent[0].entities.is_none());
+}
+
+#[tokio::test]
+async fn file_media_kinds_are_sent_as_documents() {
+    let (manager, dc, _recorder) = setup();
+    let (file, data) = temp_file(1000);
+    let caption = || FormattedText::new("caption");
+    let medias = vec![
+        InputMessageMedia::document(local_file(&file, data.len()), caption()),
+        InputMessageMedia::voice_note(local_file(&file, data.len()), caption()),
+        InputMessageMedia::animation(local_file(&file, data.len()), caption()),
+        InputMessageMedia::sticker(local_file(&file, data.len())),
+    ];
+
+    for media in medias {
+        manager.send_media(peer(), media, None).await.unwrap();
+    }
+
+    let sent = dc.sent.lock();
+    assert_eq!(sent.len(), 4);
+    for request in sent.iter() {
+        let file = request.media.file().expect("document without file");
+        assert_eq!(dc.uploaded_file(file), data);
+    }
+    assert_eq!(sent[3].message, "");
+}
+
+#[tokio::test]
+async fn media_without_files_is_sent_directly() {
+    let (manager, dc, _recorder) = setup();
+    let location = MessageLocation::new(59.93, 30.31);
+    let medias = vec![
+        InputMessageMedia::location(location.clone()),
+        InputMessageMedia::venue(MessageVenue::new(
+            location,
+            "Hermitage".to_string(),
+            "Palace Square 2".to_string(),
+        )),
+        InputMessageMedia::contact(MessageContact::new(
+            "+10000000000".to_string(),
+            "Alice".to_string(),
+        )),
+        InputMessageMedia::poll(InputPoll::new(
+            "Tea or coffee?",
+            vec!["Tea".to_string(), "Coffee".to_string()],
+        )),
+    ];
+
+    let reply_to = MessageId::from_server_id(42);
+    for media in medias {
+        manager
+            .send_media(peer(), media, Some(reply_to))
+            .await
+            .unwrap();
+    }
+
+    let sent = dc.sent.lock();
+    assert!(matches!(sent[0].media, InputMedia::GeoPoint { .. }));
+    assert!(matches!(sent[1].media, InputMedia::Venue { ref title, .. } if title == "Hermitage"));
+    assert!(
+        matches!(sent[2].media, InputMedia::Contact { ref first_name, .. } if first_name == "Alice")
+    );
+    assert!(matches!(sent[3].media, InputMedia::Poll { .. }));
+    assert!(sent
+        .iter()
+        .all(|request| request.reply_to_msg_id == Some(42)));
+    assert!(dc.uploads.lock().is_empty());
+}
+
+#[tokio::test]
+async fn flood_wait_is_reported_with_retry_after() {
+    let (manager, dc, recorder) = setup();
+    dc.fail_next(420, "FLOOD_WAIT_17");
+    let media = InputMessageMedia::location(MessageLocation::new(1.0, 2.0));
+
+    let error = manager
+        .send_media(peer(), media.clone(), None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        MessagesManagerError::Network(SendMessageNetworkError::RateLimited(17))
+    ));
+    assert_eq!(error.retry_after(), Some(Duration::from_secs(17)));
+
+    let failed_message_id = {
+        let events = recorder.send_results();
+        let [Event::SendFailed(message_id, retry_after)] = events.as_slice() else {
+            panic!("Unexpected events {:?}", events);
+        };
+        assert_eq!(*retry_after, Some(Duration::from_secs(17)));
+        *message_id
+    };
+    assert_eq!(manager.get_sent_message_id(peer(), failed_message_id), None);
+
+    // The retry gets a new temporary ID and succeeds
+    let message_id = manager.send_media(peer(), media, None).await.unwrap();
+    assert_eq!(message_id, MessageId::from_server_id(1));
+}
+
+#[tokio::test]
+async fn server_errors_are_not_retryable() {
+    let (manager, dc, _recorder) = setup();
+    dc.fail_next(400, "MEDIA_EMPTY");
+    let media = InputMessageMedia::contact(MessageContact::new(
+        "+10000000000".to_string(),
+        "Bob".to_string(),
+    ));
+
+    let error = manager.send_media(peer(), media, None).await.unwrap_err();
+    assert!(matches!(
+        error,
+        MessagesManagerError::Network(SendMessageNetworkError::ServerError { code: 400, .. })
+    ));
+    assert_eq!(error.retry_after(), None);
+}
+
+#[tokio::test]
+async fn missing_file_fails_before_sending() {
+    let (manager, dc, _recorder) = setup();
+    let media = InputMessageMedia::photo(
+        LocalFile::new("/nonexistent/photo.jpg", 1000),
+        FormattedText::new(""),
+    );
+
+    let error = manager.send_media(peer(), media, None).await.unwrap_err();
+    assert!(matches!(error, MessagesManagerError::Upload(_)));
+    assert!(dc.sent.lock().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_message_id_update_is_ignored() {
+    let (manager, _dc, _recorder) = setup();
+
+    // An updateMessageID for a message this client didn't send is ignored
+    manager
+        .process_update(Update::MessageId(UpdateMessageId {
+            id: 5,
+            random_id: 99,
+        }))
+        .unwrap();
+    assert_eq!(
+        manager.get_sent_message_id(peer(), MessageId::from_server_id(5)),
+        None
+    );
+}
+
+#[tokio::test]
+async fn album_is_uploaded_in_parallel_and_sent_together() {
+    let (manager, dc, recorder) = setup();
+    let (photo, photo_data) = temp_file(50_000);
+    let (video, video_data) = temp_file(2_100_000);
+    let items = vec![
+        InputMessageMedia::photo(
+            local_file(&photo, photo_data.len()),
+            FormattedText::with_entities(
+                "First day",
+                vec![TextEntity::new(entity_type::ITALIC, 0, 5)],
+            ),
+        ),
+        InputMessageMedia::video(
+            local_file(&video, video_data.len()).with_mime_type("video/mp4"),
+            FormattedText::new("Second day"),
+        ),
+        InputMessageMedia::photo(local_file(&photo, photo_data.len()), FormattedText::new("")),
+    ];
+
+    let message_ids = manager
+        .send_message_album(peer(), items, Some(MessageId::from_server_id(9)))
+        .await
+        .unwrap();
+    assert_eq!(
+        message_ids,
+        (1..=3).map(MessageId::from_server_id).collect::<Vec<_>>()
+    );
+
+    let albums = dc.sent_albums.lock();
+    assert_eq!(albums.len(), 1);
+    let album = &albums[0];
+    assert_eq!(album.reply_to_msg_id, Some(9));
+    assert_eq!(album.multi_media.len(), 3);
+    assert_eq!(album.multi_media[0].message, "First day");
+    assert_eq!(
+        album.multi_media[0].entities,
+        Some(vec![MessageEntity::Italic {
+            offset: 0,
+            length: 5
+        }])
+    );
+    assert_eq!(album.multi_media[1].message, "Second day");
+    assert!(album.multi_media[1].entities.is_none());
+    let video_file = album.multi_media[1].media.file().unwrap();
+    assert!(matches!(video_file, InputFile::Big { .. }));
+    assert_eq!(dc.uploaded_file(video_file), video_data);
+    assert_eq!(
+        dc.uploaded_file(album.multi_media[2].media.file().unwrap()),
+        photo_data
+    );
+    let mut random_ids: Vec<_> = album
+        .multi_media
+        .iter()
+        .map(|item| item.random_id)
+        .collect();
+    random_ids.sort_unstable();
+    random_ids.dedup();
+    assert_eq!(random_ids.len(), 3);
+    assert!(dc.sent.lock().is_empty());
+
+    let events = recorder.send_results();
+    assert_eq!(events.len(), 3);
+    assert!(events
+        .iter()
+        .zip(&message_ids)
+        .all(|(event, message_id)| matches!(
+            event,
+            Event::SendSucceeded(old, new) if old.is_yet_unsent() && new == message_id
+        )));
+
+    // The received messages form a single album
+    let groups = group_messages_by_album(recorder.messages.lock().clone());
+    assert_eq!(groups.len(), 1);
+    assert!(groups[0].is_album());
+    let contents: Vec<_> = groups[0]
+        .messages
+        .iter()
+        .map(|message| message.content.content_type().to_string())
+        .collect();
+    assert_eq!(contents, vec!["Photo", "Video", "Photo"]);
+    assert!(groups[0].caption_message().is_none());
+}
+
+#[tokio::test]
+async fn album_with_incompatible_media_is_rejected() {
+    let (manager, dc, recorder) = setup();
+    let (file, data) = temp_file(1000);
+    let items = vec![
+        InputMessageMedia::photo(local_file(&file, data.len()), FormattedText::new("")),
+        InputMessageMedia::document(local_file(&file, data.len()), FormattedText::new("")),
+    ];
+
+    let error = manager
+        .send_message_album(peer(), items, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, MessagesManagerError::Validation(_)));
+
+    let single = vec![InputMessageMedia::audio(
+        local_file(&file, data.len()),
+        FormattedText::new(""),
+    )];
+    assert!(manager
+        .send_message_album(peer(), single, None)
+        .await
+        .is_err());
+
+    assert!(dc.uploads.lock().is_empty());
+    assert!(dc.sent_albums.lock().is_empty());
+    assert!(recorder.events.lock().is_empty());
+}
+
+#[tokio::test]
+async fn failed_album_is_reported_for_every_item() {
+    let (manager, dc, recorder) = setup();
+    dc.fail_next(420, "FLOOD_WAIT_3");
+    let (file, data) = temp_file(1000);
+    let items: Vec<_> = (0..4)
+        .map(|_| InputMessageMedia::document(local_file(&file, data.len()), FormattedText::new("")))
+        .collect();
+
+    let error = manager
+        .send_message_album(peer(), items, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.retry_after(), Some(Duration::from_secs(3)));
+
+    let events = recorder.send_results();
+    assert_eq!(events.len(), 4);
+    assert!(events.iter().all(|event| matches!(
+        event,
+        Event::SendFailed(message_id, Some(_)) if message_id.is_yet_unsent()
+    )));
+    assert!(recorder.messages.lock().is_empty());
+}
+
+/// Reloader recording a fresh file reference for every re-fetched source.
+struct TestReloader {
+    file_references: FileReferenceManager,
+    file_id: FileId,
+    reloaded: Mutex<Vec<FileSource>>,
+}
+
+#[async_trait::async_trait]
+impl FileSourceReloader for TestReloader {
+    async fn reload(&self, source: &FileSource) -> Result<(), String> {
+        self.reloaded.lock().push(source.clone());
+        self.file_references
+            .set_file_reference(self.file_id, b"fresh".to_vec());
+        Ok(())
+    }
+}
+
+fn remote_photo(file_id: FileId) -> InputMessageMedia {
+    InputMessageMedia::remote_photo(
+        RemoteFile {
+            file_id,
+            id: 42,
+            access_hash: 4242,
+            file_reference: b"expired".to_vec(),
+        },
+        FormattedText::new("Again"),
+    )
+}
+
+#[tokio::test]
+async fn expired_file_reference_is_repaired_and_media_sent_again() {
+    let (manager, dc, recorder) = setup();
+    let file_id = FileId::new(5, 0);
+    let mut file_references = FileReferenceManager::new();
+    let source_id = file_references.create_app_config_file_source();
+    file_references.add_file_source(file_id, source_id);
+    let reloader = Arc::new(TestReloader {
+        file_references: file_references.clone(),
+        file_id,
+        reloaded: Mutex::new(Vec::new()),
+    });
+    manager.set_file_reference_repair(file_references, reloader.clone());
+
+    dc.fail_next(400, "FILE_REFERENCE_EXPIRED");
+    let message_id = manager
+        .send_media(peer(), remote_photo(file_id), None)
+        .await
+        .unwrap();
+
+    assert_eq!(message_id, MessageId::from_server_id(1));
+    assert_eq!(*reloader.reloaded.lock(), vec![FileSource::AppConfig]);
+    let sent = dc.sent.lock();
+    assert_eq!(sent.len(), 1);
+    match &sent[0].media {
+        InputMedia::Photo {
+            id,
+            access_hash,
+            file_reference,
+            ..
+        } => {
+            assert_eq!((*id, *access_hash), (42, 4242));
+            assert_eq!(file_reference, b"fresh");
+        }
+        other => panic!("Expected a photo, got {:?}", other),
+    }
+    assert!(matches!(
+        recorder.send_results().as_slice(),
+        [Event::SendSucceeded(..)]
+    ));
+}
+
+#[tokio::test]
+async fn file_reference_error_without_repair_is_reported() {
+    let (manager, dc, recorder) = setup();
+    dc.fail_next(400, "FILE_REFERENCE_EXPIRED");
+
+    let result = manager
+        .send_media(peer(), remote_photo(FileId::new(5, 0)), None)
+        .await;
+
+    assert!(matches!(result, Err(MessagesManagerError::Network(_))));
+    assert!(dc.sent.lock().is_empty());
+    assert!(matches!(
+        recorder.send_results().as_slice(),
+        [Event::SendFailed(_, None)]
+    ));
+}
@@ -9,11 +9,12 @@
 
 use parking_lot::Mutex;
 use std::fmt;
-use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crate::dc::{DcError, DcId};
+use crate::packet::{MessageId, MessageIdGenerator};
 
 /// Authentication key state.
 ///
@@ -147,6 +148,12 @@ pub struct AuthDataShared {
     /// Server time difference
     server_time_difference: Arc<Mutex<f64>>,
 
+    /// Whether the server time difference was received from the server
+    server_time_difference_was_updated: Arc<AtomicBool>,
+
+    /// Generator of outgoing message IDs
+    message_ids: Arc<Mutex<MessageIdGenerator>>,
+
     /// Auth key state
     auth_key_state: Arc<Mutex<AuthKeyState>>,
 
@@ -183,6 +190,8 @@ impl AuthDataShared {
             auth_key: Arc::new(Mutex::new(None)),
             future_salts: Arc::new(Mutex::new(Vec::new())),
             server_time_difference: Arc::new(Mutex::new(0.0)),
+            server_time_difference_was_updated: Arc::new(AtomicBool::new(false)),
+            message_ids: Arc::new(Mutex::new(MessageIdGenerator::new())),
             auth_key_state: Arc::new(Mutex::new(AuthKeyState::Empty)),
             listeners: Arc::new(Mutex::new(Vec::new())),
             session_id: AtomicU64::new(session_id),
@@ -238,8 +247,35 @@ impl AuthDataShared {
     }
 
     /// Updates the server time difference.
-    pub fn update_server_time_difference(&self, diff: f64, _force: bool) {
-        *self.server_time_difference.lock() = diff;
+    ///
+    /// Until the first update any difference is accepted; after that the
+    /// difference only grows, since delays make the server time look
+    /// smaller than it is. With `force` the difference is always replaced,
+    /// e.g. after the server rejected a message ID as too low or too high.
+    pub fn update_server_time_difference(&self, diff: f64, force: bool) {
+        let mut current = self.server_time_difference.lock();
+        if force
+            || !self
+                .server_time_difference_was_updated
+                .swap(true, Ordering::Relaxed)
+            || diff > *current
+        {
+            *current = diff;
+        }
+    }
+
+    /// Returns the current server time in seconds since Unix epoch.
+    pub fn server_time(&self) -> f64 {
+        local_time() + self.server_time_difference()
+    }
+
+    /// Generates the ID of the next outgoing message.
+    ///
+    /// The ID is based on the server time, so a corrected time difference
+    /// applies to all following messages.
+    pub fn next_message_id(&self) -> MessageId {
+        let server_time = self.server_time();
+        self.message_ids.lock().next(server_time)
     }
 
     /// Adds a listener for auth key changes.
@@ -254,30 +290,39 @@ impl AuthDataShared {
     }
 
     /// Sets the server salt.
+    ///
+    /// The salt replaces the current one; salts that become valid later
+    /// are kept.
     pub fn set_server_salt(&self, salt: u64) {
-        // Convert u64 to ServerSalt with current timestamp
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as i64;
-        let server_salt = ServerSalt::new(salt as i64, now);
-        self.future_salts.lock().push(server_salt);
+        let now = self.server_time() as i64;
+        let mut salts = self.future_salts.lock();
+        salts.retain(|s| s.valid_since > now);
+        salts.insert(0, ServerSalt::new(salt as i64, now));
     }
 
     /// Gets the current server salt.
+    ///
+    /// This is the valid salt that became valid last.
     pub fn get_server_salt(&self) -> Option<u64> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as i64;
+        let now = self.server_time() as i64;
 
         self.future_salts
             .lock()
             .iter()
-            .find(|s| s.is_valid(now))
+            .filter(|s| s.is_valid(now))
+            .max_by_key(|s| s.valid_since)
             .map(|s| s.salt as u64)
     }
 
+    /// Returns `true` if no salt becoming valid later is known.
+    ///
+    /// The current salt stays valid for a while after the next one becomes
+    /// valid, so requesting future salts now avoids `bad_server_salt`.
+    pub fn need_future_salts(&self) -> bool {
+        let now = self.server_time() as i64;
+        !self.future_salts.lock().iter().any(|s| s.valid_since > now)
+    }
+
     /// Returns the server salt for packet serialization.
     ///
     /// Returns a default salt if none is set.
@@ -290,6 +335,16 @@ impl AuthDataShared {
         self.session_id.load(Ordering::Relaxed)
     }
 
+    /// Starts a new session with a random ID and sequence numbers from zero.
+    ///
+    /// This is how the server's complaints about wrong sequence numbers are
+    /// resolved.
+    pub fn reset_session(&self) {
+        self.session_id
+            .store(rand::random::<u64>(), Ordering::Relaxed);
+        self.seq_no.store(0, Ordering::Relaxed);
+    }
+
     /// Generates and returns the next sequence number.
     ///
     /// # Arguments
@@ -324,6 +379,8 @@ impl Clone for AuthDataShared {
             auth_key: self.auth_key.clone(),
             future_salts: self.future_salts.clone(),
             server_time_difference: self.server_time_difference.clone(),
+            server_time_difference_was_updated: self.server_time_difference_was_updated.clone(),
+            message_ids: self.message_ids.clone(),
             auth_key_state: self.auth_key_state.clone(),
             listeners: Arc::new(Mutex::new(Vec::new())),
             session_id: AtomicU64::new(self.session_id.load(Ordering::Relaxed)),
@@ -332,6 +389,14 @@ impl Clone for AuthDataShared {
     }
 }
 
+/// Returns the local time in seconds since Unix epoch.
+pub(crate) fn local_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
 /// Factory for creating AuthDataShared instances.
 pub struct AuthDataSharedFactory;
 
@@ -461,6 +526,72 @@ mod tests {
         assert_eq!(auth_data.server_time_difference(), 10.5);
     }
 
+    #[test]
+    fn test_auth_data_shared_time_difference_only_grows() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+
+        auth_data.update_server_time_difference(10.0, false);
+        auth_data.update_server_time_difference(5.0, false);
+        assert_eq!(auth_data.server_time_difference(), 10.0);
+
+        auth_data.update_server_time_difference(15.0, false);
+        assert_eq!(auth_data.server_time_difference(), 15.0);
+
+        auth_data.update_server_time_difference(-3.0, true);
+        assert_eq!(auth_data.server_time_difference(), -3.0);
+    }
+
+    #[test]
+    fn test_auth_data_shared_next_message_id() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+        auth_data.update_server_time_difference(3600.0, true);
+
+        let first = auth_data.next_message_id();
+        let second = auth_data.next_message_id();
+        assert!(second > first);
+        assert!((first.time() - auth_data.server_time()).abs() < 5.0);
+    }
+
+    #[test]
+    fn test_auth_data_shared_set_server_salt() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+
+        auth_data.set_server_salt(1);
+        assert_eq!(auth_data.get_server_salt(), Some(1));
+
+        auth_data.set_server_salt(2);
+        assert_eq!(auth_data.get_server_salt(), Some(2));
+        assert_eq!(auth_data.get_future_salts().len(), 1);
+    }
+
+    #[test]
+    fn test_auth_data_shared_need_future_salts() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+        assert!(auth_data.need_future_salts());
+
+        auth_data.set_server_salt(1);
+        assert!(auth_data.need_future_salts());
+
+        let now = auth_data.server_time() as i64;
+        auth_data.set_future_salts(vec![
+            ServerSalt::new(1, now - 10),
+            ServerSalt::new(2, now + 1800),
+        ]);
+        assert!(!auth_data.need_future_salts());
+        assert_eq!(auth_data.get_server_salt(), Some(1));
+    }
+
+    #[test]
+    fn test_auth_data_shared_reset_session() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+        let session_id = auth_data.session_id();
+        auth_data.next_seq_no(true);
+
+        auth_data.reset_session();
+        assert_ne!(auth_data.session_id(), session_id);
+        assert_eq!(auth_data.next_seq_no(false), 0);
+    }
+
     #[test]
     fn test_auth_data_shared_clear() {
         let auth_data = AuthDataShared::new(DcId::internal(2));
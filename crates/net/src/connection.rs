@@ -14,7 +14,7 @@ use thiserror::Error;
 use crate::dc::{DcId, DcOption, DcOptionsSet};
 use crate::proxy::Proxy;
 use crate::query::NetQuery;
-use crate::stats::NetType;
+use crate::stats::{NetStatsManager, NetType};
 
 /// Connection mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -196,6 +196,9 @@ pub struct ConnectionCreator {
 
     /// Connection statistics
     stats: Arc<parking_lot::Mutex<ConnectionStats>>,
+
+    /// Network usage statistics, which are counted by network type
+    net_stats: Option<Arc<parking_lot::Mutex<NetStatsManager>>>,
 }
 
 impl ConnectionCreator {
@@ -208,9 +211,19 @@ impl ConnectionCreator {
             network_flag: Arc::new(AtomicBool::new(true)),
             network_generation: Arc::new(AtomicU32::new(0)),
             stats: Arc::new(parking_lot::Mutex::new(ConnectionStats::default())),
+            net_stats: None,
         }
     }
 
+    /// Sets the network usage statistics to pass the network type to.
+    ///
+    /// Bytes are counted for the current network type from now on.
+    pub fn with_net_stats(mut self, net_stats: Arc<parking_lot::Mutex<NetStatsManager>>) -> Self {
+        net_stats.lock().set_net_type(self.net_type());
+        self.net_stats = Some(net_stats);
+        self
+    }
+
     /// Updates DC options.
     pub fn on_dc_options(&self, options: DcOptionsSet) {
         *self.dc_options.lock() = options;
@@ -232,8 +245,13 @@ impl ConnectionCreator {
     }
 
     /// Sets the network type.
+    ///
+    /// The network usage statistics count the following bytes for it.
     pub fn set_net_type(&self, net_type: NetType) {
         *self.net_type.lock() = net_type;
+        if let Some(net_stats) = &self.net_stats {
+            net_stats.lock().set_net_type(net_type);
+        }
     }
 
     /// Returns the network type.
@@ -579,6 +597,16 @@ mod tests {
         assert_eq!(creator.network_generation(), 1);
     }
 
+    #[test]
+    fn test_connection_creator_sets_net_stats_type() {
+        let net_stats = Arc::new(parking_lot::Mutex::new(NetStatsManager::new()));
+        let creator = ConnectionCreator::new().with_net_stats(net_stats.clone());
+        assert_eq!(net_stats.lock().net_type(), NetType::Other);
+
+        creator.set_net_type(NetType::Mobile);
+        assert_eq!(net_stats.lock().net_type(), NetType::Mobile);
+    }
+
     #[test]
     fn test_session() {
         let session = Session::new(2, DcId::internal(2), true, false, true, false);
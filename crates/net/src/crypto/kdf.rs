@@ -33,16 +33,19 @@ impl KdfOutput {
 
 /// MTProto 1.0 key derivation function (SHA1-based).
 ///
-/// This function is used for MTProto v1.0 compatibility.
+/// This function is used for MTProto v1.0 compatibility. The only message
+/// still encrypted this way is the inner message of `auth.bindTempAuthKey`.
 ///
 /// # Algorithm
 ///
 /// ```text
-/// sha256_a = SHA256(msg_key + substr(auth_key, 0, 36))
-/// sha256_b = SHA256(substr(auth_key, 40, 36) + msg_key)
+/// sha1_a = SHA1(msg_key + substr(auth_key, x, 32))
+/// sha1_b = SHA1(substr(auth_key, 32+x, 16) + msg_key + substr(auth_key, 48+x, 16))
+/// sha1_c = SHA1(substr(auth_key, 64+x, 32) + msg_key)
+/// sha1_d = SHA1(msg_key + substr(auth_key, 96+x, 32))
 ///
-/// aes_key = substr(sha256_a, 0, 8) + substr(sha256_b, 8, 24)
-/// aes_iv  = substr(sha256_b, 0, 8) + substr(sha256_a, 24, 24)
+/// aes_key = substr(sha1_a, 0, 8) + substr(sha1_b, 8, 12) + substr(sha1_c, 4, 12)
+/// aes_iv  = substr(sha1_a, 8, 12) + substr(sha1_b, 0, 8) + substr(sha1_c, 16, 4) + substr(sha1_d, 0, 8)
 /// ```
 ///
 /// # Arguments
@@ -66,30 +69,43 @@ impl KdfOutput {
 pub fn kdf(auth_key: &[u8], msg_key: &[u8; 16], x: usize) -> KdfOutput {
     assert_eq!(auth_key.len(), 256, "auth_key must be 256 bytes");
 
-    // Based on TDLib KDF::KDF (MTProto v1 with SHA256 - note: TDLib uses SHA256 for both)
-    // sha256_a = SHA256 (msg_key + substr(auth_key, x, 36));
-    let mut buf_a = [0u8; 16 + 36];
+    // sha1_a = SHA1 (msg_key + substr(auth_key, x, 32));
+    let mut buf_a = [0u8; 16 + 32];
     buf_a[..16].copy_from_slice(msg_key);
-    buf_a[16..].copy_from_slice(&auth_key[x..x + 36]);
-    let sha256_a = sha256(&buf_a);
-
-    // sha256_b = SHA256 (substr(auth_key, x + 36, 36) + msg_key);
-    let mut buf_b = [0u8; 36 + 16];
-    buf_b[..36].copy_from_slice(&auth_key[x + 36..x + 36 + 36]);
-    buf_b[36..].copy_from_slice(msg_key);
-    let sha256_b = sha256(&buf_b);
-
-    // aes_key = substr(sha256_a, 0, 8) + substr(sha256_b, 8, 16) + substr(sha256_a, 24, 8);
+    buf_a[16..].copy_from_slice(&auth_key[x..x + 32]);
+    let sha1_a = sha1(&buf_a);
+
+    // sha1_b = SHA1 (substr(auth_key, 32+x, 16) + msg_key + substr(auth_key, 48+x, 16));
+    let mut buf_b = [0u8; 16 + 16 + 16];
+    buf_b[..16].copy_from_slice(&auth_key[32 + x..48 + x]);
+    buf_b[16..32].copy_from_slice(msg_key);
+    buf_b[32..].copy_from_slice(&auth_key[48 + x..64 + x]);
+    let sha1_b = sha1(&buf_b);
+
+    // sha1_c = SHA1 (substr(auth_key, 64+x, 32) + msg_key);
+    let mut buf_c = [0u8; 32 + 16];
+    buf_c[..32].copy_from_slice(&auth_key[64 + x..96 + x]);
+    buf_c[32..].copy_from_slice(msg_key);
+    let sha1_c = sha1(&buf_c);
+
+    // sha1_d = SHA1 (msg_key + substr(auth_key, 96+x, 32));
+    let mut buf_d = [0u8; 16 + 32];
+    buf_d[..16].copy_from_slice(msg_key);
+    buf_d[16..].copy_from_slice(&auth_key[96 + x..128 + x]);
+    let sha1_d = sha1(&buf_d);
+
+    // aes_key = substr(sha1_a, 0, 8) + substr(sha1_b, 8, 12) + substr(sha1_c, 4, 12);
     let mut aes_key = [0u8; 32];
-    aes_key[0..8].copy_from_slice(&sha256_a[0..8]);
-    aes_key[8..24].copy_from_slice(&sha256_b[8..24]);
-    aes_key[24..32].copy_from_slice(&sha256_a[24..32]);
+    aes_key[0..8].copy_from_slice(&sha1_a[0..8]);
+    aes_key[8..20].copy_from_slice(&sha1_b[8..20]);
+    aes_key[20..32].copy_from_slice(&sha1_c[4..16]);
 
-    // aes_iv = substr(sha256_b, 0, 8) + substr(sha256_a, 8, 16) + substr(sha256_b, 24, 8);
+    // aes_iv = substr(sha1_a, 8, 12) + substr(sha1_b, 0, 8) + substr(sha1_c, 16, 4) + substr(sha1_d, 0, 8);
     let mut aes_iv = [0u8; 32];
-    aes_iv[0..8].copy_from_slice(&sha256_b[0..8]);
-    aes_iv[8..24].copy_from_slice(&sha256_a[8..24]);
-    aes_iv[24..32].copy_from_slice(&sha256_b[24..32]);
+    aes_iv[0..12].copy_from_slice(&sha1_a[8..20]);
+    aes_iv[12..20].copy_from_slice(&sha1_b[0..8]);
+    aes_iv[20..24].copy_from_slice(&sha1_c[16..20]);
+    aes_iv[24..32].copy_from_slice(&sha1_d[0..8]);
 
     KdfOutput { aes_key, aes_iv }
 }
@@ -252,6 +268,37 @@ mod tests {
         let _ = kdf2(&auth_key, &msg_key, 0);
     }
 
+    #[test]
+    fn test_kdf_known_vector() {
+        let mut auth_key = [0u8; 256];
+        for (i, byte) in auth_key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut msg_key = [0u8; 16];
+        for (i, byte) in msg_key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let output = kdf(&auth_key, &msg_key, 0);
+
+        assert_eq!(
+            output.aes_key,
+            [
+                0x17, 0xd7, 0x29, 0x5c, 0xa9, 0x21, 0x3d, 0x1a, 0xb6, 0x56, 0xac, 0xdb, 0x1a,
+                0xd4, 0x8b, 0x2e, 0xa7, 0xf3, 0xa8, 0xf7, 0x09, 0x50, 0x98, 0xd5, 0x50, 0x8b,
+                0x90, 0x0b, 0xbd, 0x5f, 0xcc, 0xfc,
+            ]
+        );
+        assert_eq!(
+            output.aes_iv,
+            [
+                0x2d, 0x7d, 0x16, 0xa6, 0x5a, 0x84, 0x10, 0x8e, 0x98, 0x05, 0x65, 0x6c, 0xaa,
+                0x47, 0x45, 0x01, 0xcc, 0x58, 0x0a, 0xa2, 0xed, 0xc3, 0x3a, 0xbf, 0xd0, 0xbf,
+                0xad, 0x78, 0x54, 0x64, 0xd1, 0xc6,
+            ]
+        );
+    }
+
     #[test]
     fn test_kdf_deterministic() {
         let auth_key = [42u8; 256];
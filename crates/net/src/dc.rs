@@ -8,6 +8,7 @@
 
 use std::fmt;
 use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
@@ -485,7 +486,9 @@ pub struct DcOptionStats {
     /// Average round-trip time in seconds
     pub avg_rtt: f64,
     /// Last successful connection timestamp
-    pub last_success: Option<std::time::Instant>,
+    pub last_success: Option<Instant>,
+    /// Last failed connection timestamp
+    pub last_failure: Option<Instant>,
 }
 
 impl DcOptionStats {
@@ -494,12 +497,24 @@ impl DcOptionStats {
         self.success_count += 1;
         self.avg_rtt =
             (self.avg_rtt * (self.success_count - 1) as f64 + rtt) / self.success_count as f64;
-        self.last_success = Some(std::time::Instant::now());
+        self.last_success = Some(Instant::now());
     }
 
     /// Records a failed connection.
     pub fn record_failure(&mut self) {
         self.failure_count += 1;
+        self.last_failure = Some(Instant::now());
+    }
+
+    /// Returns `true` unless the last connection attempt failed.
+    ///
+    /// Like TDLib's `DcOptionsSet::OptionStat::is_ok`.
+    pub fn is_ok(&self) -> bool {
+        match (self.last_success, self.last_failure) {
+            (_, None) => true,
+            (Some(success), Some(failure)) => success >= failure,
+            (None, Some(_)) => false,
+        }
     }
 
     /// Returns the success rate (0.0 to 1.0).
@@ -585,6 +600,102 @@ impl DcOptionsSet {
         options.first().map(|(_, opt)| (*opt).clone())
     }
 
+    /// Returns the options to try when connecting to a DC, best first.
+    ///
+    /// Options whose last connection failed are demoted behind the working
+    /// ones. Within both groups media-only options come first if they are
+    /// allowed, static options come last, and IPv6 and IPv4 options
+    /// alternate, starting with the preferred family, so that connection
+    /// attempts can be raced across both families.
+    ///
+    /// Returns pairs of the option index, for recording statistics, and the
+    /// option.
+    pub fn find_connection_candidates(
+        &self,
+        dc_id: DcId,
+        allow_media_only: bool,
+        prefer_ipv6: bool,
+    ) -> Vec<(usize, DcOption)> {
+        let mut options: Vec<(usize, &DcOption)> = self
+            .options
+            .dc_options
+            .iter()
+            .enumerate()
+            .filter(|(_, opt)| {
+                opt.dc_id == dc_id && (!opt.is_media_only() || allow_media_only) && opt.is_valid()
+            })
+            .collect();
+
+        // Stable sort, so that options keep their order otherwise
+        options.sort_by_key(|(idx, opt)| {
+            let is_ok = self.stats.get(idx).map_or(true, DcOptionStats::is_ok);
+            (
+                !is_ok,
+                allow_media_only && !opt.is_media_only(),
+                opt.is_static(),
+            )
+        });
+
+        let (working, demoted): (Vec<_>, Vec<_>) = options
+            .into_iter()
+            .partition(|(idx, _)| self.stats.get(idx).map_or(true, DcOptionStats::is_ok));
+
+        let mut candidates = interleave_families(working, prefer_ipv6);
+        candidates.extend(interleave_families(demoted, prefer_ipv6));
+        candidates
+            .into_iter()
+            .map(|(idx, opt)| (idx, opt.clone()))
+            .collect()
+    }
+
+    /// Returns the statistics of all options with statistics for saving.
+    pub fn saved_stats(&self) -> Vec<SavedDcOptionStats> {
+        let mut saved: Vec<_> = self
+            .stats
+            .iter()
+            .filter_map(|(idx, stats)| {
+                let option = self.options.dc_options.get(*idx)?;
+                Some(SavedDcOptionStats {
+                    dc_id: option.dc_id.get_raw_id(),
+                    ip_address: option.ip_address,
+                    port: option.port,
+                    success_count: stats.success_count,
+                    failure_count: stats.failure_count,
+                    avg_rtt: stats.avg_rtt,
+                    is_ok: stats.is_ok(),
+                })
+            })
+            .collect();
+        saved.sort_by_key(|stats| (stats.dc_id, stats.ip_address, stats.port));
+        saved
+    }
+
+    /// Restores statistics saved by [`saved_stats`](Self::saved_stats).
+    ///
+    /// Statistics of options that are no longer present are ignored.
+    pub fn restore_stats(&mut self, saved: &[SavedDcOptionStats]) {
+        let now = Instant::now();
+        for entry in saved {
+            let Some(idx) = self.options.dc_options.iter().position(|opt| {
+                opt.dc_id.get_raw_id() == entry.dc_id
+                    && opt.ip_address == entry.ip_address
+                    && opt.port == entry.port
+            }) else {
+                continue;
+            };
+            self.stats.insert(
+                idx,
+                DcOptionStats {
+                    success_count: entry.success_count,
+                    failure_count: entry.failure_count,
+                    avg_rtt: entry.avg_rtt,
+                    last_success: (entry.success_count > 0 && entry.is_ok).then_some(now),
+                    last_failure: (!entry.is_ok).then_some(now),
+                },
+            );
+        }
+    }
+
     /// Records connection statistics for an option.
     pub fn record_success(&mut self, option_idx: usize, rtt: f64) {
         self.stats
@@ -604,7 +715,51 @@ impl DcOptionsSet {
     }
 }
 
+/// Saved connection statistics of a DC option.
+///
+/// Options are identified by their address rather than their index, so the
+/// statistics survive changes of the option list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedDcOptionStats {
+    /// Raw DC ID
+    pub dc_id: i32,
+    /// IP address
+    pub ip_address: IpAddr,
+    /// Port number
+    pub port: u16,
+    /// Number of successful connections
+    pub success_count: u32,
+    /// Number of failed connections
+    pub failure_count: u32,
+    /// Average round-trip time in seconds
+    pub avg_rtt: f64,
+    /// Whether the last connection attempt succeeded
+    pub is_ok: bool,
+}
+
+/// Alternates options of both IP families, starting with the preferred one.
+fn interleave_families(
+    options: Vec<(usize, &DcOption)>,
+    prefer_ipv6: bool,
+) -> Vec<(usize, &DcOption)> {
+    let (preferred, other): (Vec<_>, Vec<_>) = options
+        .into_iter()
+        .partition(|(_, opt)| opt.ip_address.is_ipv6() == prefer_ipv6);
+
+    let mut result = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (first, second) => result.extend(first.into_iter().chain(second)),
+        }
+    }
+    result
+}
+
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     use std::net::Ipv4Addr;
@@ -715,4 +870,108 @@ mod tests {
         assert!((stats.success_rate() - 0.666).abs() < 0.01);
         assert!((stats.avg_rtt - 0.75).abs() < 0.01);
     }
+
+    fn race_options() -> DcOptionsSet {
+        let dc_id = DcId::internal(2);
+        let mut options = DcOptions::new();
+        options.add(DcOption::new(dc_id, "149.154.167.51".parse().unwrap(), 443));
+        options.add(
+            DcOption::new(dc_id, "2001:67c:4e8:f002::a".parse().unwrap(), 443)
+                .with_flag(DcOptionFlag::IPv6),
+        );
+        options.add(
+            DcOption::new(dc_id, "149.154.167.222".parse().unwrap(), 443)
+                .with_flag(DcOptionFlag::MediaOnly),
+        );
+        options.add(
+            DcOption::new(dc_id, "149.154.167.50".parse().unwrap(), 443)
+                .with_flag(DcOptionFlag::Static),
+        );
+        options.add(
+            DcOption::new(dc_id, "2001:67c:4e8:f002::b".parse().unwrap(), 443)
+                .with_flag(DcOptionFlag::IPv6),
+        );
+        options.add(DcOption::new(
+            DcId::internal(4),
+            "149.154.167.91".parse().unwrap(),
+            443,
+        ));
+
+        let mut set = DcOptionsSet::new();
+        set.add_options(options);
+        set
+    }
+
+    fn candidate_indices(
+        set: &DcOptionsSet,
+        allow_media_only: bool,
+        prefer_ipv6: bool,
+    ) -> Vec<usize> {
+        set.find_connection_candidates(DcId::internal(2), allow_media_only, prefer_ipv6)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    #[test]
+    fn test_connection_candidates_interleave_families() {
+        let set = race_options();
+
+        assert_eq!(candidate_indices(&set, false, true), vec![1, 0, 4, 3]);
+        assert_eq!(candidate_indices(&set, false, false), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_connection_candidates_media_only() {
+        let set = race_options();
+
+        assert_eq!(candidate_indices(&set, true, false), vec![2, 1, 0, 4, 3]);
+    }
+
+    #[test]
+    fn test_connection_candidates_demote_failed() {
+        let mut set = race_options();
+
+        // Broken IPv6
+        set.record_failure(1);
+        set.record_failure(4);
+        assert_eq!(candidate_indices(&set, false, true), vec![0, 3, 1, 4]);
+
+        // A later success restores the option
+        set.record_success(4, 0.1);
+        assert_eq!(candidate_indices(&set, false, true), vec![4, 0, 3, 1]);
+    }
+
+    #[test]
+    fn test_saved_stats_roundtrip() {
+        let mut set = race_options();
+        set.record_success(0, 0.2);
+        set.record_failure(1);
+
+        let saved = set.saved_stats();
+        assert_eq!(saved.len(), 2);
+
+        // The restored list has the options in a different order
+        let mut options = race_options().get_options().clone();
+        options.dc_options.reverse();
+        let mut restored = DcOptionsSet::new();
+        restored.add_options(options);
+        restored.restore_stats(&saved);
+
+        assert_eq!(restored.saved_stats(), set.saved_stats());
+        let ipv6_idx = restored.get_options().dc_options.len() - 2;
+        assert!(!restored.get_stats(ipv6_idx).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_dc_option_stats_is_ok() {
+        let mut stats = DcOptionStats::default();
+        assert!(stats.is_ok());
+
+        stats.record_failure();
+        assert!(!stats.is_ok());
+
+        stats.record_success(0.1);
+        assert!(stats.is_ok());
+    }
 }
@@ -0,0 +1,253 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Gzip-packed MTProto objects.
+//!
+//! Any TL object may be sent as `gzip_packed#3072cfa1 packed_data:bytes`,
+//! where `packed_data` is the gzip-compressed serialized object. Servers do
+//! this for large results like `messages.getHistory` or
+//! `updates.getDifference`; clients may do it for large requests.
+//!
+//! Based on TDLib's `td/utils/Gzip.h` and the use of `gzip_packed` in
+//! `NetQueryCreator` and `SessionConnection`.
+
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use thiserror::Error;
+
+/// TL constructor of `gzip_packed`.
+pub const GZIP_PACKED_CONSTRUCTOR: u32 = 0x3072cfa1;
+
+/// Requests smaller than this are never compressed.
+pub const MIN_GZIP_REQUEST_SIZE: usize = 1024;
+
+/// Largest size of an unpacked object.
+///
+/// Protects against decompression bombs; real responses are far smaller.
+pub const MAX_UNPACKED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Compressed requests are used only if they are at most this fraction
+/// of the original size.
+const MAX_COMPRESSION_RATIO: f64 = 0.9;
+
+/// Error unpacking a `gzip_packed` object.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum GzipError {
+    /// The TL object is truncated or malformed
+    #[error("Invalid gzip_packed object: {0}")]
+    InvalidFormat(&'static str),
+
+    /// The compressed data is corrupted
+    #[error("Invalid gzip data: {0}")]
+    InvalidData(String),
+
+    /// The unpacked object exceeds the limit
+    #[error("Unpacked object exceeds {limit} bytes")]
+    TooLarge {
+        /// The exceeded limit
+        limit: usize,
+    },
+}
+
+/// Compresses data with gzip.
+pub fn gzencode(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec can't fail
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Decompresses gzip data of at most `max_size` bytes.
+pub fn gzdecode(data: &[u8], max_size: usize) -> Result<Vec<u8>, GzipError> {
+    let mut decoder = GzDecoder::new(data).take(max_size as u64 + 1);
+    let mut unpacked = Vec::new();
+    decoder
+        .read_to_end(&mut unpacked)
+        .map_err(|e| GzipError::InvalidData(e.to_string()))?;
+    if unpacked.len() > max_size {
+        return Err(GzipError::TooLarge { limit: max_size });
+    }
+    Ok(unpacked)
+}
+
+/// Returns `true` if the serialized object is `gzip_packed`.
+pub fn is_gzip_packed(data: &[u8]) -> bool {
+    read_u32(data, 0) == Some(GZIP_PACKED_CONSTRUCTOR)
+}
+
+/// Packs a serialized request into `gzip_packed`.
+///
+/// Returns `None` if the request is too small or doesn't compress well
+/// enough to be worth it.
+pub fn pack(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < MIN_GZIP_REQUEST_SIZE {
+        return None;
+    }
+
+    let compressed = gzencode(data);
+    if compressed.len() as f64 > data.len() as f64 * MAX_COMPRESSION_RATIO {
+        return None;
+    }
+
+    let mut packed = Vec::with_capacity(compressed.len() + 8);
+    packed.extend_from_slice(&GZIP_PACKED_CONSTRUCTOR.to_le_bytes());
+    write_tl_bytes(&mut packed, &compressed);
+    Some(packed)
+}
+
+/// Unpacks a `gzip_packed` object.
+///
+/// Other objects are returned unchanged.
+pub fn unpack(data: Bytes) -> Result<Bytes, GzipError> {
+    unpack_with_limit(data, MAX_UNPACKED_SIZE)
+}
+
+/// Unpacks a `gzip_packed` object of at most `max_size` bytes.
+///
+/// Other objects are returned unchanged.
+pub fn unpack_with_limit(data: Bytes, max_size: usize) -> Result<Bytes, GzipError> {
+    if !is_gzip_packed(&data) {
+        return Ok(data);
+    }
+
+    let mut offset = 4;
+    let compressed = read_tl_bytes(&data, &mut offset)?;
+    gzdecode(compressed, max_size).map(Bytes::from)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_tl_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    let header = if data.len() < 254 {
+        buf.push(data.len() as u8);
+        1
+    } else {
+        buf.push(254);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+        4
+    };
+    buf.extend_from_slice(data);
+    let padding = (4 - (header + data.len()) % 4) % 4;
+    buf.extend(std::iter::repeat(0).take(padding));
+}
+
+fn read_tl_bytes<'a>(data: &'a [u8], offset: &mut usize) -> Result<&'a [u8], GzipError> {
+    let first = *data
+        .get(*offset)
+        .ok_or(GzipError::InvalidFormat("missing packed_data"))?;
+    let (len, header) = match first {
+        254 => {
+            let bytes = data
+                .get(*offset + 1..*offset + 4)
+                .ok_or(GzipError::InvalidFormat("truncated length"))?;
+            let len = bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16;
+            (len, 4)
+        }
+        255 => return Err(GzipError::InvalidFormat("invalid length")),
+        len => (len as usize, 1),
+    };
+    let start = *offset + header;
+    let bytes = data
+        .get(start..start + len)
+        .ok_or(GzipError::InvalidFormat("truncated packed_data"))?;
+    *offset = start + len + (4 - (header + len) % 4) % 4;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn compressible(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 16) as u8).collect()
+    }
+
+    #[test]
+    fn test_gzip_roundtrip() {
+        let data = compressible(10_000);
+        assert_eq!(gzdecode(&gzencode(&data), MAX_UNPACKED_SIZE).unwrap(), data);
+    }
+
+    #[test]
+    fn test_pack_unpack_roundtrip() {
+        let data = compressible(10_000);
+
+        let packed = pack(&data).unwrap();
+        assert!(is_gzip_packed(&packed));
+        assert!(packed.len() < data.len());
+        assert_eq!(packed.len() % 4, 0);
+
+        assert_eq!(unpack(Bytes::from(packed)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_pack_small_request() {
+        assert!(pack(&compressible(MIN_GZIP_REQUEST_SIZE - 1)).is_none());
+    }
+
+    #[test]
+    fn test_pack_incompressible_request() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        assert!(pack(&data).is_none());
+    }
+
+    #[test]
+    fn test_unpack_plain_object() {
+        let data = Bytes::from_static(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(unpack(data.clone()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_unpack_bomb() {
+        // 64 MiB of zeros compress to a few dozen kilobytes
+        let compressed = gzencode(&vec![0u8; 64 * 1024 * 1024]);
+        let mut packed = GZIP_PACKED_CONSTRUCTOR.to_le_bytes().to_vec();
+        write_tl_bytes(&mut packed, &compressed);
+
+        assert_eq!(
+            unpack(Bytes::from(packed)),
+            Err(GzipError::TooLarge {
+                limit: MAX_UNPACKED_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn test_unpack_with_limit() {
+        let packed = Bytes::from(pack(&compressible(2000)).unwrap());
+        assert!(unpack_with_limit(packed.clone(), 2000).is_ok());
+        assert_eq!(
+            unpack_with_limit(packed, 1999),
+            Err(GzipError::TooLarge { limit: 1999 })
+        );
+    }
+
+    #[test]
+    fn test_unpack_truncated() {
+        let mut packed = pack(&compressible(2000)).unwrap();
+        packed.truncate(packed.len() / 2);
+        assert!(matches!(
+            unpack(Bytes::from(packed)),
+            Err(GzipError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_unpack_corrupted() {
+        let mut packed = GZIP_PACKED_CONSTRUCTOR.to_le_bytes().to_vec();
+        write_tl_bytes(&mut packed, &[0x1f, 0x8b, 1, 2, 3, 4, 5, 6]);
+        assert!(matches!(
+            unpack(Bytes::from(packed)),
+            Err(GzipError::InvalidData(_))
+        ));
+    }
+}
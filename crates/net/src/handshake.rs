@@ -38,6 +38,7 @@ use crate::crypto::{
     RsaPublicKeyWrapper,
 };
 use crate::dc::DcId;
+use crate::pfs::DEFAULT_TEMP_KEY_EXPIRES_IN;
 use crate::rsa_key_shared::RsaKey;
 use crate::test_config::is_test_dc;
 use bytes::BytesMut;
@@ -264,7 +265,7 @@ impl MtprotoHandshake {
             auth_key: None,
             server_salt: None,
             expires_in: if matches!(mode, HandshakeMode::Temp) {
-                Some(86400) // 24 hours default for temp keys
+                Some(DEFAULT_TEMP_KEY_EXPIRES_IN)
             } else {
                 None
             },
@@ -473,7 +474,7 @@ impl MtprotoHandshake {
                 if is_test_dc() {
                     dc_id += 10000;
                 }
-                let expires_in = self.expires_in.unwrap_or(86400);
+                let expires_in = self.expires_in.unwrap_or(DEFAULT_TEMP_KEY_EXPIRES_IN);
                 let inner = PQInnerDataTempDc::new(rustgram_types::mtproto_auth::PQInnerDataTempDcOptions {
                     pq: res_pq.pq.clone(),
                     p: p.clone(),
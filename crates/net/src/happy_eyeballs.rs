@@ -0,0 +1,233 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Racing connection attempts across DC options.
+//!
+//! A DC is reachable through several options, usually over both IPv4 and
+//! IPv6. Trying them one after another means a broken IPv6 network stalls
+//! every connection until the connect timeout. Instead the attempts are
+//! staggered as in "Happy Eyeballs" (RFC 8305): the next option is tried
+//! when the previous attempt fails or hasn't succeeded within a short
+//! delay, and the first connection to succeed wins.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use crate::connection::ConnectionError;
+use crate::transport::TcpTransport;
+
+/// Delay before the next connection attempt is started.
+///
+/// The value recommended by RFC 8305.
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Result of a connection race.
+#[derive(Debug)]
+pub struct RaceOutcome<T, E> {
+    /// Index of the winning candidate and its connection
+    pub winner: Option<(usize, T)>,
+
+    /// Indices of the candidates that failed and their errors
+    ///
+    /// Attempts still running when another one won are not included.
+    pub failures: Vec<(usize, E)>,
+}
+
+/// Races connection attempts to `count` candidates.
+///
+/// `connect` is called with the index of the candidate to connect to.
+/// Candidates are tried in order; the next one is started `attempt_delay`
+/// after the previous one, or immediately when an attempt fails.
+pub async fn race<T, E, F, Fut>(
+    count: usize,
+    attempt_delay: Duration,
+    mut connect: F,
+) -> RaceOutcome<T, E>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempts = FuturesUnordered::new();
+    let mut failures = Vec::new();
+    let mut next = 0;
+
+    loop {
+        if attempts.is_empty() {
+            if next == count {
+                return RaceOutcome {
+                    winner: None,
+                    failures,
+                };
+            }
+            attempts.push(tagged(next, connect(next)));
+            next += 1;
+        }
+
+        let delay = tokio::time::sleep(attempt_delay);
+        tokio::select! {
+            Some((idx, result)) = attempts.next() => match result {
+                Ok(connection) => {
+                    return RaceOutcome {
+                        winner: Some((idx, connection)),
+                        failures,
+                    };
+                }
+                Err(error) => {
+                    failures.push((idx, error));
+                    if next < count {
+                        attempts.push(tagged(next, connect(next)));
+                        next += 1;
+                    }
+                }
+            },
+            _ = delay, if next < count => {
+                attempts.push(tagged(next, connect(next)));
+                next += 1;
+            }
+        }
+    }
+}
+
+/// Tags the result of an attempt with the index of its candidate.
+async fn tagged<Fut: Future>(idx: usize, attempt: Fut) -> (usize, Fut::Output) {
+    (idx, attempt.await)
+}
+
+/// Connects to the first reachable address.
+///
+/// The addresses are raced with [`race`].
+pub async fn connect_tcp(
+    addrs: &[SocketAddr],
+    attempt_delay: Duration,
+) -> RaceOutcome<TcpTransport, ConnectionError> {
+    race(addrs.len(), attempt_delay, |idx| {
+        let addr = addrs[idx];
+        async move {
+            let mut transport = TcpTransport::new(addr);
+            transport.connect().await?;
+            Ok(transport)
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use tokio::net::TcpListener;
+
+    const DELAY: Duration = Duration::from_millis(50);
+
+    /// Connects after `delay`, or fails if `ok` is false.
+    async fn fake_connect(idx: usize, delay: Duration, ok: bool) -> Result<usize, String> {
+        tokio::time::sleep(delay).await;
+        if ok {
+            Ok(idx)
+        } else {
+            Err(format!("attempt {} failed", idx))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_race_first_wins() {
+        let started = AtomicUsize::new(0);
+        let outcome = race(3, DELAY, |idx| {
+            started.fetch_add(1, Ordering::Relaxed);
+            fake_connect(idx, Duration::from_millis(5), true)
+        })
+        .await;
+
+        assert!(matches!(outcome.winner, Some((0, 0))));
+        assert!(outcome.failures.is_empty());
+        assert_eq!(started.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_race_staggers_hanging_attempt() {
+        let start = Instant::now();
+        let outcome = race(2, DELAY, |idx| {
+            // The first candidate hangs like a blackholed IPv6 address
+            let delay = if idx == 0 {
+                Duration::from_secs(30)
+            } else {
+                Duration::from_millis(5)
+            };
+            fake_connect(idx, delay, true)
+        })
+        .await;
+
+        assert!(matches!(outcome.winner, Some((1, 1))));
+        assert!(outcome.failures.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_race_failure_starts_next_attempt() {
+        let start = Instant::now();
+        let outcome = race(3, Duration::from_secs(30), |idx| {
+            fake_connect(idx, Duration::from_millis(5), idx == 2)
+        })
+        .await;
+
+        assert!(matches!(outcome.winner, Some((2, 2))));
+        let failed: Vec<usize> = outcome.failures.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(failed, vec![0, 1]);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_race_all_fail() {
+        let outcome = race(3, DELAY, |idx| {
+            fake_connect(idx, Duration::from_millis(5), false)
+        })
+        .await;
+
+        assert!(outcome.winner.is_none());
+        assert_eq!(outcome.failures.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_race_no_candidates() {
+        let outcome = race(0, DELAY, |idx| fake_connect(idx, DELAY, true)).await;
+
+        assert!(outcome.winner.is_none());
+        assert!(outcome.failures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_tcp_skips_refused_address() {
+        let listener = match TcpListener::bind("127.0.0.1:0").await {
+            Ok(listener) => listener,
+            Err(e) => panic!("Failed to bind: {}", e),
+        };
+        let open = match listener.local_addr() {
+            Ok(addr) => addr,
+            Err(e) => panic!("Failed to get address: {}", e),
+        };
+
+        // Find a port nobody listens on
+        let closed = {
+            let closed_listener = match TcpListener::bind("127.0.0.1:0").await {
+                Ok(listener) => listener,
+                Err(e) => panic!("Failed to bind: {}", e),
+            };
+            match closed_listener.local_addr() {
+                Ok(addr) => addr,
+                Err(e) => panic!("Failed to get address: {}", e),
+            }
+        };
+
+        let outcome = connect_tcp(&[closed, open], Duration::from_secs(30)).await;
+
+        assert!(matches!(&outcome.winner, Some((1, transport)) if transport.addr() == open));
+        assert_eq!(outcome.failures.len(), 1);
+        assert_eq!(outcome.failures[0].0, 0);
+    }
+}
@@ -12,8 +12,11 @@
 //! - [`crypto`] - Cryptography primitives (AES-IGE, KDF, SHA1/SHA256, RSA, HMAC)
 //! - [`packet`] - MTProto packet types (MessageId, PacketInfo, MtprotoQuery)
 //! - [`auth`] - Authentication data handling
+//! - [`pfs`] - Temporary auth keys for Perfect Forward Secrecy
+//! - [`gzip`] - Gzip-packed requests and responses
 //! - [`connection`] - Connection management
 //! - [`dc`] - Data Center types and options
+//! - [`happy_eyeballs`] - Racing connection attempts across DC options
 //! - [`proxy`] - Proxy types (SOCKS5, HTTP, MTProto)
 //! - [`query`] - Query dispatching and lifecycle
 //! - [`pool`] - Connection pooling for multiple DCs
@@ -33,11 +36,14 @@ pub mod dc;
 pub mod dc_auth;
 pub mod dispatch;
 pub mod failover;
+pub mod gzip;
 pub mod handshake;
+pub mod happy_eyeballs;
 pub mod health_check;
 pub mod mtproto_header;
 pub mod net_actor;
 pub mod packet;
+pub mod pfs;
 pub mod pool;
 pub mod proxy;
 pub mod query;
@@ -51,15 +57,15 @@ pub mod test_config;
 pub mod transport;
 
 // Re-export existing types
-pub use auth::{AuthDataShared, AuthKeyState};
+pub use auth::{AuthDataShared, AuthKey, AuthKeyState};
 pub use connection::{ConnectionCreator, ConnectionMode, ConnectionState, Session, SessionProxy};
-pub use dc::{DcId, DcOption, DcOptions, DcOptionsSet};
+pub use dc::{DcId, DcOption, DcOptions, DcOptionsSet, SavedDcOptionStats};
 pub use proxy::{Proxy, ProxyType};
 pub use query::{
     AuthFlag, GzipFlag, NetQuery, NetQueryCallback, NetQueryDispatcher, NetQueryId, NetQueryState,
     NetQueryType, QueryError,
 };
-pub use stats::{NetStatsManager, NetType, NetworkStats, NetworkStatsEntry};
+pub use stats::{NetStatsCounter, NetStatsManager, NetType, NetworkStats, NetworkStatsEntry};
 
 // Re-export crypto types
 pub use crypto::compute_auth_key_id;
@@ -70,7 +76,7 @@ pub use crypto::{kdf, kdf2, pq_factorize, pq_factorize_big, sha1, sha256, tmp_kd
 pub use crypto::{AuthKeyError, AuthKeyHelper, CryptoAuthKey, DefaultAuthKeyHelper};
 
 // Re-export packet types
-pub use packet::{MessageId, MtprotoQuery, PacketInfo, PacketType};
+pub use packet::{MessageId, MessageIdGenerator, MtprotoQuery, PacketInfo, PacketType};
 
 // Re-export transport types
 pub use transport::{
@@ -112,6 +118,9 @@ pub use dc_auth::{
     StoredAuthKey, TempAuthKeyWatchdog,
 };
 
+// Re-export PFS types
+pub use pfs::{BindAuthKeyInner, BindTempAuthKey, PfsError, TempAuthKeys};
+
 // Re-export handshake types
 pub use handshake::{
     HandshakeAction, HandshakeError, HandshakeMode, HandshakeState, MtprotoHandshake,
@@ -270,6 +270,62 @@ impl MessageId {
     }
 }
 
+/// Generator of outgoing message IDs.
+///
+/// Outgoing message IDs must grow monotonically. The generator returns IDs
+/// based on the given server time, but never one that is not greater than
+/// the previous ID, e.g. when the server time moved back after a correction.
+///
+/// Based on TDLib's `AuthData::next_message_id`.
+///
+/// # Examples
+///
+/// ```
+/// use rustgram_net::packet::MessageIdGenerator;
+///
+/// let mut generator = MessageIdGenerator::new();
+/// let first = generator.next(1704067200.0);
+/// let second = generator.next(1704067100.0);
+/// assert!(second > first);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct MessageIdGenerator {
+    /// The last generated message ID
+    last: MessageId,
+}
+
+impl MessageIdGenerator {
+    /// Creates a new generator.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            last: MessageId::EMPTY,
+        }
+    }
+
+    /// Returns the next outgoing message ID for the given server time.
+    pub fn next(&mut self, server_time: f64) -> MessageId {
+        let mut msg_id = MessageId::generate(server_time, true, 0);
+        if msg_id <= self.last {
+            msg_id = MessageId::from_u64(self.last.as_u64() + 4);
+        }
+        self.last = msg_id;
+        msg_id
+    }
+
+    /// Returns the last generated message ID.
+    #[must_use]
+    pub const fn last(&self) -> MessageId {
+        self.last
+    }
+}
+
+impl Default for MessageIdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -450,4 +506,31 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_message_id_generator_monotonic() {
+        let mut generator = MessageIdGenerator::new();
+        let first = generator.next(1704067200.0);
+        let second = generator.next(1704067200.0);
+        assert!(second > first);
+        assert_eq!(second.as_u64() % 4, 0);
+        assert_eq!(generator.last(), second);
+    }
+
+    #[test]
+    fn test_message_id_generator_time_moved_back() {
+        let mut generator = MessageIdGenerator::new();
+        let first = generator.next(1704067200.0);
+        let second = generator.next(1704060000.0);
+        assert_eq!(second.as_u64(), first.as_u64() + 4);
+    }
+
+    #[test]
+    fn test_message_id_generator_follows_server_time() {
+        let mut generator = MessageIdGenerator::new();
+        let first = generator.next(1704067200.0);
+        let second = generator.next(1704070800.0);
+        assert!((second.time() - 1704070800.0).abs() < 1.0);
+        assert!(second > first);
+    }
 }
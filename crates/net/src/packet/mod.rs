@@ -24,10 +24,10 @@ mod message_id;
 mod query;
 
 pub use info::{PacketInfo, PacketType};
-pub use message_id::MessageId;
+pub use message_id::{MessageId, MessageIdGenerator};
 pub use query::MtprotoQuery;
 
 /// Prelude for packet module imports.
 pub mod prelude {
-    pub use super::{MessageId, MtprotoQuery, PacketInfo, PacketType};
+    pub use super::{MessageId, MessageIdGenerator, MtprotoQuery, PacketInfo, PacketType};
 }
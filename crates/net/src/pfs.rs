@@ -0,0 +1,874 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Perfect Forward Secrecy (PFS).
+//!
+//! With PFS the traffic of a session is encrypted with a temporary auth key
+//! that the server forgets after it expires. The temporary key is bound to
+//! the permanent auth key with `auth.bindTempAuthKey`, whose inner message
+//! is encrypted with the permanent key using MTProto 1.0.
+//!
+//! Based on TDLib's `td/mtproto/AuthData.h` and `td/telegram/net/Session.cpp`.
+//!
+//! # References
+//!
+//! - <https://core.telegram.org/api/pfs>
+
+use rand::Rng;
+use thiserror::Error;
+
+use crate::auth::AuthKey;
+use crate::crypto::{aes_ige_decrypt, aes_ige_encrypt, kdf, sha1};
+use crate::session::ContainerDecoder;
+
+/// Constructor of `auth.bindTempAuthKey`.
+pub const BIND_TEMP_AUTH_KEY_CONSTRUCTOR: u32 = 0xcdd42a05;
+
+/// Constructor of `bind_auth_key_inner`.
+pub const BIND_AUTH_KEY_INNER_CONSTRUCTOR: u32 = 0x75a3f765;
+
+/// Default lifetime of a temporary auth key in seconds.
+pub const DEFAULT_TEMP_KEY_EXPIRES_IN: i32 = 86400;
+
+/// Default time in seconds before the expiry of a temporary key when a new
+/// one is created.
+pub const DEFAULT_ROTATE_MARGIN: i32 = 3600;
+
+const RPC_RESULT_CONSTRUCTOR: u32 = 0xf35c6d01;
+const RPC_ERROR_CONSTRUCTOR: u32 = 0x2144ca19;
+const MSG_CONTAINER_CONSTRUCTOR: u32 = 0x73f1f8dc;
+const BOOL_TRUE_CONSTRUCTOR: u32 = 0x997275b5;
+const BOOL_FALSE_CONSTRUCTOR: u32 = 0xbc799737;
+
+/// Size of the serialized `bind_auth_key_inner`.
+const BIND_AUTH_KEY_INNER_SIZE: usize = 40;
+
+/// Size of salt, session_id, message_id, seq_no and length of an MTProto 1.0
+/// message.
+const MESSAGE_HEADER_SIZE: usize = 32;
+
+/// Errors of binding temporary auth keys.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum PfsError {
+    /// The server doesn't know the permanent auth key
+    #[error("Permanent auth key is unknown to the server")]
+    PermKeyEmpty,
+
+    /// The server rejected the binding
+    #[error("Binding rejected: {code} {message}")]
+    Rejected {
+        /// Error code
+        code: i32,
+        /// Error message
+        message: String,
+    },
+
+    /// A bind message or response is malformed
+    #[error("Invalid bind message: {0}")]
+    InvalidMessage(String),
+
+    /// The keys needed for the operation are missing
+    #[error("Invalid state: {0}")]
+    InvalidState(&'static str),
+}
+
+/// `bind_auth_key_inner`, the message proving ownership of both keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindAuthKeyInner {
+    /// Random nonce, repeated in the outer query
+    pub nonce: u64,
+    /// Identifier of the temporary auth key
+    pub temp_auth_key_id: u64,
+    /// Identifier of the permanent auth key
+    pub perm_auth_key_id: u64,
+    /// Session in which `auth.bindTempAuthKey` is sent
+    pub temp_session_id: u64,
+    /// Unix time when the temporary key expires
+    pub expires_at: i32,
+}
+
+impl BindAuthKeyInner {
+    /// Serializes the message.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(BIND_AUTH_KEY_INNER_SIZE);
+        buf.extend_from_slice(&BIND_AUTH_KEY_INNER_CONSTRUCTOR.to_le_bytes());
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        buf.extend_from_slice(&self.temp_auth_key_id.to_le_bytes());
+        buf.extend_from_slice(&self.perm_auth_key_id.to_le_bytes());
+        buf.extend_from_slice(&self.temp_session_id.to_le_bytes());
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf
+    }
+
+    /// Parses the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not a `bind_auth_key_inner`.
+    pub fn parse(data: &[u8]) -> Result<Self, PfsError> {
+        if data.len() != BIND_AUTH_KEY_INNER_SIZE {
+            return Err(PfsError::InvalidMessage(format!(
+                "bind_auth_key_inner has {} bytes",
+                data.len()
+            )));
+        }
+        let mut offset = 0;
+        if read_u32(data, &mut offset)? != BIND_AUTH_KEY_INNER_CONSTRUCTOR {
+            return Err(PfsError::InvalidMessage(
+                "Wrong bind_auth_key_inner constructor".into(),
+            ));
+        }
+        Ok(Self {
+            nonce: read_u64(data, &mut offset)?,
+            temp_auth_key_id: read_u64(data, &mut offset)?,
+            perm_auth_key_id: read_u64(data, &mut offset)?,
+            temp_session_id: read_u64(data, &mut offset)?,
+            expires_at: read_u32(data, &mut offset)? as i32,
+        })
+    }
+}
+
+/// `auth.bindTempAuthKey` query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindTempAuthKey {
+    /// Identifier of the permanent auth key
+    pub perm_auth_key_id: u64,
+    /// Nonce of the inner message
+    pub nonce: u64,
+    /// Unix time when the temporary key expires
+    pub expires_at: i32,
+    /// `bind_auth_key_inner` encrypted with the permanent key
+    pub encrypted_message: Vec<u8>,
+}
+
+impl BindTempAuthKey {
+    /// Serializes the query.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(28 + self.encrypted_message.len());
+        buf.extend_from_slice(&BIND_TEMP_AUTH_KEY_CONSTRUCTOR.to_le_bytes());
+        buf.extend_from_slice(&self.perm_auth_key_id.to_le_bytes());
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        write_tl_bytes(&mut buf, &self.encrypted_message);
+        buf
+    }
+
+    /// Parses the query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not an `auth.bindTempAuthKey` query.
+    pub fn parse(data: &[u8]) -> Result<Self, PfsError> {
+        let mut offset = 0;
+        if read_u32(data, &mut offset)? != BIND_TEMP_AUTH_KEY_CONSTRUCTOR {
+            return Err(PfsError::InvalidMessage(
+                "Wrong auth.bindTempAuthKey constructor".into(),
+            ));
+        }
+        Ok(Self {
+            perm_auth_key_id: read_u64(data, &mut offset)?,
+            nonce: read_u64(data, &mut offset)?,
+            expires_at: read_u32(data, &mut offset)? as i32,
+            encrypted_message: read_tl_bytes(data, &mut offset)?,
+        })
+    }
+}
+
+/// Encrypts the inner message of `auth.bindTempAuthKey` with the permanent key.
+///
+/// The message is encrypted with MTProto 1.0 and a random salt and session,
+/// and must have the same message_id as the query carrying it.
+///
+/// # Errors
+///
+/// Returns an error if the permanent key is not 256 bytes long.
+pub fn encrypt_bind_message(
+    perm_key: &AuthKey,
+    message_id: u64,
+    inner: &BindAuthKeyInner,
+) -> Result<Vec<u8>, PfsError> {
+    if perm_key.len() != 256 {
+        return Err(PfsError::InvalidState(
+            "Permanent auth key must be 256 bytes",
+        ));
+    }
+    let mut rng = rand::thread_rng();
+    let body = inner.serialize();
+
+    let mut plaintext = Vec::with_capacity(MESSAGE_HEADER_SIZE + body.len() + 15);
+    plaintext.extend_from_slice(&rng.gen::<u64>().to_le_bytes());
+    plaintext.extend_from_slice(&rng.gen::<u64>().to_le_bytes());
+    plaintext.extend_from_slice(&message_id.to_le_bytes());
+    plaintext.extend_from_slice(&0i32.to_le_bytes());
+    plaintext.extend_from_slice(&(body.len() as i32).to_le_bytes());
+    plaintext.extend_from_slice(&body);
+
+    // In MTProto 1.0 msg_key doesn't cover the padding
+    let msg_key = v1_msg_key(&plaintext);
+    while plaintext.len() % 16 != 0 {
+        plaintext.push(rng.gen());
+    }
+
+    let output = kdf(perm_key.as_bytes(), &msg_key, 0);
+    let mut iv = output.aes_iv;
+    aes_ige_encrypt(&output.aes_key, &mut iv, &mut plaintext)
+        .map_err(|e| PfsError::InvalidMessage(e.to_string()))?;
+
+    let mut message = Vec::with_capacity(24 + plaintext.len());
+    message.extend_from_slice(&perm_key.id.to_le_bytes());
+    message.extend_from_slice(&msg_key);
+    message.extend_from_slice(&plaintext);
+    Ok(message)
+}
+
+/// Decrypts the inner message of `auth.bindTempAuthKey` and checks its msg_key.
+///
+/// Returns the message_id of the message and the message itself. This is
+/// what the server does with the query; the client only needs it in tests.
+///
+/// # Errors
+///
+/// Returns an error if the message isn't encrypted with the key or was
+/// tampered with.
+pub fn decrypt_bind_message(
+    perm_key: &AuthKey,
+    data: &[u8],
+) -> Result<(u64, BindAuthKeyInner), PfsError> {
+    if perm_key.len() != 256 {
+        return Err(PfsError::InvalidState(
+            "Permanent auth key must be 256 bytes",
+        ));
+    }
+    if data.len() < 24 + MESSAGE_HEADER_SIZE || (data.len() - 24) % 16 != 0 {
+        return Err(PfsError::InvalidMessage(format!(
+            "Encrypted message has {} bytes",
+            data.len()
+        )));
+    }
+    let mut offset = 0;
+    if read_u64(data, &mut offset)? != perm_key.id {
+        return Err(PfsError::InvalidMessage("Wrong auth_key_id".into()));
+    }
+    let mut msg_key = [0u8; 16];
+    msg_key.copy_from_slice(&data[8..24]);
+
+    let output = kdf(perm_key.as_bytes(), &msg_key, 0);
+    let mut iv = output.aes_iv;
+    let mut plaintext = data[24..].to_vec();
+    aes_ige_decrypt(&output.aes_key, &mut iv, &mut plaintext)
+        .map_err(|e| PfsError::InvalidMessage(e.to_string()))?;
+
+    let mut offset = 16;
+    let message_id = read_u64(&plaintext, &mut offset)?;
+    let _seq_no = read_u32(&plaintext, &mut offset)?;
+    let length = read_u32(&plaintext, &mut offset)? as usize;
+    if length > plaintext.len() - MESSAGE_HEADER_SIZE {
+        return Err(PfsError::InvalidMessage(format!(
+            "Message length {} is too big",
+            length
+        )));
+    }
+    let end = MESSAGE_HEADER_SIZE + length;
+    if v1_msg_key(&plaintext[..end]) != msg_key {
+        return Err(PfsError::InvalidMessage("msg_key mismatch".into()));
+    }
+
+    let inner = BindAuthKeyInner::parse(&plaintext[MESSAGE_HEADER_SIZE..end])?;
+    Ok((message_id, inner))
+}
+
+/// Finds the answer to the `auth.bindTempAuthKey` query with the given
+/// message_id in a received message.
+///
+/// Returns `None` if the message is not the answer, e.g. `new_session_created`.
+pub fn parse_bind_result(body: &[u8], message_id: u64) -> Option<Result<(), PfsError>> {
+    let mut offset = 0;
+    match read_u32(body, &mut offset).ok()? {
+        MSG_CONTAINER_CONSTRUCTOR => ContainerDecoder::decode(body)
+            .ok()?
+            .iter()
+            .find_map(|message| parse_bind_result(&message.body, message_id)),
+        RPC_RESULT_CONSTRUCTOR => {
+            if read_u64(body, &mut offset).ok()? != message_id {
+                return None;
+            }
+            Some(parse_bind_answer(&body[offset..]))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the `Bool` or `rpc_error` answer to `auth.bindTempAuthKey`.
+fn parse_bind_answer(data: &[u8]) -> Result<(), PfsError> {
+    let mut offset = 0;
+    match read_u32(data, &mut offset)? {
+        BOOL_TRUE_CONSTRUCTOR => Ok(()),
+        BOOL_FALSE_CONSTRUCTOR => Err(PfsError::Rejected {
+            code: 0,
+            message: "boolFalse".into(),
+        }),
+        RPC_ERROR_CONSTRUCTOR => {
+            let code = read_u32(data, &mut offset)? as i32;
+            let message = String::from_utf8_lossy(&read_tl_bytes(data, &mut offset)?).into_owned();
+            if message == "AUTH_KEY_PERM_EMPTY" {
+                Err(PfsError::PermKeyEmpty)
+            } else {
+                Err(PfsError::Rejected { code, message })
+            }
+        }
+        constructor => Err(PfsError::InvalidMessage(format!(
+            "Unexpected answer {:#010x}",
+            constructor
+        ))),
+    }
+}
+
+/// Pending `auth.bindTempAuthKey` query.
+#[derive(Debug, Clone, Copy)]
+struct PendingBind {
+    message_id: u64,
+}
+
+/// Auth keys of a PFS session.
+///
+/// Tracks the permanent key, the temporary key encrypting the session and
+/// whether the latter is bound. The caller performs the handshakes and sends
+/// the queries built here; the keys only change in response to their
+/// results.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_net::pfs::TempAuthKeys;
+///
+/// let keys = TempAuthKeys::new();
+/// assert!(keys.need_perm_key());
+/// assert!(keys.need_temp_key(0));
+/// ```
+#[derive(Debug)]
+pub struct TempAuthKeys {
+    /// Permanent auth key
+    perm_key: Option<AuthKey>,
+
+    /// Temporary auth key
+    temp_key: Option<AuthKey>,
+
+    /// Unix time when the temporary key expires
+    expires_at: i32,
+
+    /// Whether the temporary key is bound to the permanent one
+    is_bound: bool,
+
+    /// Bind query waiting for its result
+    pending_bind: Option<PendingBind>,
+
+    /// Seconds before expiry when the temporary key is replaced
+    rotate_margin: i32,
+}
+
+impl Default for TempAuthKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TempAuthKeys {
+    /// Creates an empty key set with the default rotate margin.
+    pub fn new() -> Self {
+        Self::with_rotate_margin(DEFAULT_ROTATE_MARGIN)
+    }
+
+    /// Creates an empty key set that replaces temporary keys the given
+    /// number of seconds before they expire.
+    pub fn with_rotate_margin(rotate_margin: i32) -> Self {
+        Self {
+            perm_key: None,
+            temp_key: None,
+            expires_at: 0,
+            is_bound: false,
+            pending_bind: None,
+            rotate_margin,
+        }
+    }
+
+    /// Returns `true` if a permanent key must be created.
+    pub fn need_perm_key(&self) -> bool {
+        self.perm_key.is_none()
+    }
+
+    /// Returns the permanent key.
+    pub fn perm_key(&self) -> Option<&AuthKey> {
+        self.perm_key.as_ref()
+    }
+
+    /// Sets the permanent key.
+    ///
+    /// A temporary key bound to another permanent key is dropped.
+    pub fn set_perm_key(&mut self, key: AuthKey) {
+        if self.perm_key.as_ref().map(|perm_key| perm_key.id) != Some(key.id) {
+            self.drop_temp_key();
+        }
+        self.perm_key = Some(key);
+    }
+
+    /// Returns `true` if a new temporary key must be created, because there
+    /// is none or the current one expires within the rotate margin.
+    pub fn need_temp_key(&self, now: i32) -> bool {
+        self.temp_key.is_none() || now >= self.expires_at.saturating_sub(self.rotate_margin)
+    }
+
+    /// Returns the temporary key.
+    pub fn temp_key(&self) -> Option<&AuthKey> {
+        self.temp_key.as_ref()
+    }
+
+    /// Returns the unix time when the temporary key expires.
+    pub fn expires_at(&self) -> i32 {
+        self.expires_at
+    }
+
+    /// Returns `true` if the temporary key is bound and can be used for
+    /// queries.
+    pub fn is_bound(&self) -> bool {
+        self.is_bound
+    }
+
+    /// Sets a new unbound temporary key created by a Temp handshake.
+    pub fn set_temp_key(&mut self, key: AuthKey, expires_at: i32) {
+        self.temp_key = Some(key);
+        self.expires_at = expires_at;
+        self.is_bound = false;
+        self.pending_bind = None;
+    }
+
+    /// Builds the `auth.bindTempAuthKey` query for the temporary key.
+    ///
+    /// The query must be sent encrypted with the temporary key in the session
+    /// `temp_session_id` as the message `message_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either key is missing.
+    pub fn bind_query(
+        &mut self,
+        message_id: u64,
+        temp_session_id: u64,
+    ) -> Result<Vec<u8>, PfsError> {
+        let perm_key = self
+            .perm_key
+            .as_ref()
+            .ok_or(PfsError::InvalidState("No permanent auth key"))?;
+        let temp_key = self
+            .temp_key
+            .as_ref()
+            .ok_or(PfsError::InvalidState("No temporary auth key"))?;
+
+        let inner = BindAuthKeyInner {
+            nonce: rand::thread_rng().gen(),
+            temp_auth_key_id: temp_key.id,
+            perm_auth_key_id: perm_key.id,
+            temp_session_id,
+            expires_at: self.expires_at,
+        };
+        let query = BindTempAuthKey {
+            perm_auth_key_id: perm_key.id,
+            nonce: inner.nonce,
+            expires_at: inner.expires_at,
+            encrypted_message: encrypt_bind_message(perm_key, message_id, &inner)?,
+        };
+
+        self.pending_bind = Some(PendingBind { message_id });
+        Ok(query.serialize())
+    }
+
+    /// Returns the message_id of the bind query waiting for its result.
+    pub fn pending_bind_message_id(&self) -> Option<u64> {
+        self.pending_bind.map(|bind| bind.message_id)
+    }
+
+    /// Handles the result of the bind query.
+    ///
+    /// On `AUTH_KEY_PERM_EMPTY` the permanent key is dropped, so that a new
+    /// one is created; on other errors only the temporary key is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the query, or an error if no query was sent.
+    pub fn on_bind_result(&mut self, result: Result<(), PfsError>) -> Result<(), PfsError> {
+        if self.pending_bind.take().is_none() {
+            return Err(PfsError::InvalidState("No bind query was sent"));
+        }
+        match result {
+            Ok(()) => {
+                self.is_bound = true;
+                Ok(())
+            }
+            Err(PfsError::PermKeyEmpty) => {
+                self.perm_key = None;
+                self.drop_temp_key();
+                Err(PfsError::PermKeyEmpty)
+            }
+            Err(error) => {
+                self.drop_temp_key();
+                Err(error)
+            }
+        }
+    }
+
+    /// Forgets the temporary key.
+    fn drop_temp_key(&mut self) {
+        self.temp_key = None;
+        self.expires_at = 0;
+        self.is_bound = false;
+        self.pending_bind = None;
+    }
+}
+
+/// MTProto 1.0 msg_key: the lower 128 bits of SHA1 of the plaintext.
+fn v1_msg_key(plaintext: &[u8]) -> [u8; 16] {
+    let mut msg_key = [0u8; 16];
+    msg_key.copy_from_slice(&sha1(plaintext)[4..20]);
+    msg_key
+}
+
+fn read_u32(data: &[u8], offset: &mut usize) -> Result<u32, PfsError> {
+    let bytes = data
+        .get(*offset..*offset + 4)
+        .ok_or_else(|| PfsError::InvalidMessage("Unexpected end of data".into()))?;
+    *offset += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: &mut usize) -> Result<u64, PfsError> {
+    let low = read_u32(data, offset)?;
+    let high = read_u32(data, offset)?;
+    Ok(u64::from(low) | (u64::from(high) << 32))
+}
+
+fn write_tl_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    let prefix_len = if data.len() < 254 {
+        buf.push(data.len() as u8);
+        1
+    } else {
+        buf.push(254);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+        4
+    };
+    buf.extend_from_slice(data);
+    let padding = (4 - (prefix_len + data.len()) % 4) % 4;
+    buf.resize(buf.len() + padding, 0);
+}
+
+fn read_tl_bytes(data: &[u8], offset: &mut usize) -> Result<Vec<u8>, PfsError> {
+    let eof = || PfsError::InvalidMessage("Unexpected end of data".into());
+    let first = *data.get(*offset).ok_or_else(eof)?;
+    let (len, prefix_len) = if first < 254 {
+        (first as usize, 1)
+    } else {
+        let bytes = data.get(*offset + 1..*offset + 4).ok_or_else(eof)?;
+        (
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) as usize,
+            4,
+        )
+    };
+    let start = *offset + prefix_len;
+    let out = data.get(start..start + len).ok_or_else(eof)?.to_vec();
+    *offset = start + len + (4 - (prefix_len + len) % 4) % 4;
+    Ok(out)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::crypto::compute_auth_key_id;
+    use std::collections::HashMap;
+
+    const NOW: i32 = 1_700_000_000;
+
+    fn random_key() -> AuthKey {
+        let mut key = [0u8; 256];
+        rand::thread_rng().fill(&mut key[..]);
+        AuthKey::new(compute_auth_key_id(&key), key.to_vec())
+    }
+
+    /// Fake DC checking bind queries the way the server does.
+    #[derive(Default)]
+    struct FakeDc {
+        /// Known permanent keys
+        perm_keys: HashMap<u64, AuthKey>,
+        /// Bound temporary keys: temp_auth_key_id -> perm_auth_key_id
+        bindings: HashMap<u64, u64>,
+    }
+
+    impl FakeDc {
+        /// Answers a bind query sent in the given session with the given key.
+        fn on_bind_query(
+            &mut self,
+            query: &[u8],
+            message_id: u64,
+            temp_key: &AuthKey,
+            session_id: u64,
+        ) -> Vec<u8> {
+            let answer = self.check_bind_query(query, message_id, temp_key, session_id);
+            let mut body = Vec::new();
+            body.extend_from_slice(&RPC_RESULT_CONSTRUCTOR.to_le_bytes());
+            body.extend_from_slice(&message_id.to_le_bytes());
+            match answer {
+                Ok(()) => body.extend_from_slice(&BOOL_TRUE_CONSTRUCTOR.to_le_bytes()),
+                Err(message) => {
+                    body.extend_from_slice(&RPC_ERROR_CONSTRUCTOR.to_le_bytes());
+                    body.extend_from_slice(&400i32.to_le_bytes());
+                    write_tl_bytes(&mut body, message.as_bytes());
+                }
+            }
+            body
+        }
+
+        fn check_bind_query(
+            &mut self,
+            query: &[u8],
+            message_id: u64,
+            temp_key: &AuthKey,
+            session_id: u64,
+        ) -> Result<(), &'static str> {
+            let query = BindTempAuthKey::parse(query).map_err(|_| "INPUT_REQUEST_INVALID")?;
+            let perm_key = self
+                .perm_keys
+                .get(&query.perm_auth_key_id)
+                .ok_or("AUTH_KEY_PERM_EMPTY")?;
+            // The msg_key of the inner message signs it with the permanent key
+            let (inner_message_id, inner) =
+                decrypt_bind_message(perm_key, &query.encrypted_message)
+                    .map_err(|_| "ENCRYPTED_MESSAGE_INVALID")?;
+            if inner_message_id != message_id
+                || inner.nonce != query.nonce
+                || inner.expires_at != query.expires_at
+                || inner.perm_auth_key_id != query.perm_auth_key_id
+                || inner.temp_auth_key_id != temp_key.id
+                || inner.temp_session_id != session_id
+            {
+                return Err("ENCRYPTED_MESSAGE_INVALID");
+            }
+            if inner.expires_at <= NOW {
+                return Err("EXPIRES_AT_INVALID");
+            }
+            self.bindings.insert(temp_key.id, perm_key.id);
+            Ok(())
+        }
+    }
+
+    /// Creates a temporary key, binds it through the fake DC and returns the
+    /// result.
+    fn bind(keys: &mut TempAuthKeys, dc: &mut FakeDc) -> Result<AuthKey, PfsError> {
+        let temp_key = random_key();
+        keys.set_temp_key(temp_key.clone(), NOW + DEFAULT_TEMP_KEY_EXPIRES_IN);
+        let message_id = 0x6000_0000_0000_0004;
+        let session_id = 0x1234;
+        let query = keys.bind_query(message_id, session_id).unwrap();
+        let answer = dc.on_bind_query(&query, message_id, &temp_key, session_id);
+        keys.on_bind_result(parse_bind_result(&answer, message_id).unwrap())?;
+        Ok(temp_key)
+    }
+
+    #[test]
+    fn test_bind_auth_key_inner_roundtrip() {
+        let inner = BindAuthKeyInner {
+            nonce: 1,
+            temp_auth_key_id: 2,
+            perm_auth_key_id: 3,
+            temp_session_id: 4,
+            expires_at: NOW,
+        };
+        let data = inner.serialize();
+        assert_eq!(data.len(), BIND_AUTH_KEY_INNER_SIZE);
+        assert_eq!(BindAuthKeyInner::parse(&data).unwrap(), inner);
+    }
+
+    #[test]
+    fn test_bind_temp_auth_key_roundtrip() {
+        for len in [0, 3, 100, 300] {
+            let query = BindTempAuthKey {
+                perm_auth_key_id: 5,
+                nonce: 6,
+                expires_at: NOW,
+                encrypted_message: vec![7; len],
+            };
+            let data = query.serialize();
+            assert_eq!(data.len() % 4, 0);
+            assert_eq!(BindTempAuthKey::parse(&data).unwrap(), query);
+        }
+    }
+
+    #[test]
+    fn test_bind_message_roundtrip() {
+        let perm_key = random_key();
+        let inner = BindAuthKeyInner {
+            nonce: 10,
+            temp_auth_key_id: 11,
+            perm_auth_key_id: perm_key.id,
+            temp_session_id: 12,
+            expires_at: NOW,
+        };
+        let message = encrypt_bind_message(&perm_key, 42, &inner).unwrap();
+        assert_eq!(&message[..8], &perm_key.id.to_le_bytes());
+        assert_eq!((message.len() - 24) % 16, 0);
+        assert_eq!(
+            decrypt_bind_message(&perm_key, &message).unwrap(),
+            (42, inner)
+        );
+    }
+
+    #[test]
+    fn test_bind_message_signature() {
+        let perm_key = random_key();
+        let inner = BindAuthKeyInner {
+            nonce: 10,
+            temp_auth_key_id: 11,
+            perm_auth_key_id: perm_key.id,
+            temp_session_id: 12,
+            expires_at: NOW,
+        };
+        let message = encrypt_bind_message(&perm_key, 42, &inner).unwrap();
+
+        for index in [8, 30, message.len() - 1] {
+            let mut tampered = message.clone();
+            tampered[index] ^= 1;
+            assert!(decrypt_bind_message(&perm_key, &tampered).is_err());
+        }
+
+        let other_key = AuthKey::new(perm_key.id, random_key().key);
+        assert!(matches!(
+            decrypt_bind_message(&other_key, &message),
+            Err(PfsError::InvalidMessage(_))
+        ));
+    }
+
+    #[test]
+    fn test_bind_accepted_by_dc() {
+        let perm_key = random_key();
+        let mut dc = FakeDc::default();
+        dc.perm_keys.insert(perm_key.id, perm_key.clone());
+
+        let mut keys = TempAuthKeys::new();
+        keys.set_perm_key(perm_key.clone());
+        let temp_key = bind(&mut keys, &mut dc).unwrap();
+
+        assert!(keys.is_bound());
+        assert_eq!(keys.temp_key(), Some(&temp_key));
+        assert_eq!(keys.pending_bind_message_id(), None);
+        assert_eq!(dc.bindings.get(&temp_key.id), Some(&perm_key.id));
+    }
+
+    #[test]
+    fn test_bind_with_wrong_perm_key_rejected() {
+        let perm_key = random_key();
+        let mut dc = FakeDc::default();
+        dc.perm_keys.insert(perm_key.id, perm_key.clone());
+
+        // Same identifier, different key: the signature doesn't verify
+        let mut keys = TempAuthKeys::new();
+        keys.set_perm_key(AuthKey::new(perm_key.id, random_key().key));
+        let error = bind(&mut keys, &mut dc).unwrap_err();
+
+        assert_eq!(
+            error,
+            PfsError::Rejected {
+                code: 400,
+                message: "ENCRYPTED_MESSAGE_INVALID".into()
+            }
+        );
+        assert!(!keys.need_perm_key());
+        assert!(keys.need_temp_key(NOW));
+        assert!(dc.bindings.is_empty());
+    }
+
+    #[test]
+    fn test_perm_key_empty_fallback() {
+        let mut dc = FakeDc::default();
+        let mut keys = TempAuthKeys::new();
+        keys.set_perm_key(random_key());
+
+        // The DC forgot the permanent key
+        assert_eq!(
+            bind(&mut keys, &mut dc).unwrap_err(),
+            PfsError::PermKeyEmpty
+        );
+        assert!(keys.need_perm_key());
+        assert!(keys.temp_key().is_none());
+        assert!(keys.bind_query(1, 1).is_err());
+
+        // A new permanent key is created and the binding succeeds
+        let perm_key = random_key();
+        dc.perm_keys.insert(perm_key.id, perm_key.clone());
+        keys.set_perm_key(perm_key);
+        bind(&mut keys, &mut dc).unwrap();
+        assert!(keys.is_bound());
+    }
+
+    #[test]
+    fn test_temp_key_rotation() {
+        let perm_key = random_key();
+        let mut dc = FakeDc::default();
+        dc.perm_keys.insert(perm_key.id, perm_key.clone());
+
+        let mut keys = TempAuthKeys::with_rotate_margin(600);
+        assert!(keys.need_temp_key(NOW));
+        keys.set_perm_key(perm_key.clone());
+        let first = bind(&mut keys, &mut dc).unwrap();
+
+        let expires_at = keys.expires_at();
+        assert!(!keys.need_temp_key(NOW));
+        assert!(!keys.need_temp_key(expires_at - 601));
+        assert!(keys.need_temp_key(expires_at - 600));
+
+        let second = bind(&mut keys, &mut dc).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(dc.bindings.len(), 2);
+
+        // Setting the same permanent key again keeps the bound key
+        keys.set_perm_key(perm_key);
+        assert!(keys.is_bound());
+        keys.set_perm_key(random_key());
+        assert!(!keys.is_bound());
+    }
+
+    #[test]
+    fn test_on_bind_result_without_query() {
+        let mut keys = TempAuthKeys::new();
+        assert!(matches!(
+            keys.on_bind_result(Ok(())),
+            Err(PfsError::InvalidState(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_bind_result() {
+        let message_id: u64 = 0x6000_0000_0000_0008;
+        let mut answer = Vec::new();
+        answer.extend_from_slice(&RPC_RESULT_CONSTRUCTOR.to_le_bytes());
+        answer.extend_from_slice(&message_id.to_le_bytes());
+        answer.extend_from_slice(&BOOL_TRUE_CONSTRUCTOR.to_le_bytes());
+
+        // new_session_created is not the answer
+        let new_session = 0x9ec20908u32.to_le_bytes();
+        assert_eq!(parse_bind_result(&new_session, message_id), None);
+        assert_eq!(parse_bind_result(&answer, message_id + 4), None);
+        assert_eq!(parse_bind_result(&answer, message_id), Some(Ok(())));
+
+        // The answer may come in a container
+        let mut container = Vec::new();
+        container.extend_from_slice(&MSG_CONTAINER_CONSTRUCTOR.to_le_bytes());
+        container.extend_from_slice(&2u32.to_le_bytes());
+        for body in [&new_session[..], &answer[..]] {
+            container.extend_from_slice(&1u64.to_le_bytes());
+            container.extend_from_slice(&1i32.to_le_bytes());
+            container.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            container.extend_from_slice(body);
+        }
+        assert_eq!(parse_bind_result(&container, message_id), Some(Ok(())));
+    }
+}
@@ -31,11 +31,14 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
+use crate::auth::AuthKey;
 use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
-use crate::dc::{DcId, DcOptionsSet};
+use crate::dc::{DcId, DcOptionsSet, SavedDcOptionStats};
+use crate::dc_auth::TempAuthKeyWatchdog;
 use crate::health_check::{HealthChecker, HealthCheckConfig, HealthStatus};
 use crate::rsa_key_shared::RsaKey;
 use crate::session::{SessionConnection, SessionConnectionConfig};
+use crate::stats::{FileType, NetStatsManager};
 
 /// Connection purpose determines which sub-pool to use.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -213,8 +216,7 @@ impl PooledConnection {
     }
 
     /// Explicitly returns the connection to the pool early.
-    pub fn release(mut self) {
-        self.valid = false;
+    pub fn release(self) {
         // Drop will handle the rest
     }
 }
@@ -265,15 +267,24 @@ struct ConnectionPoolInner {
     /// Number of pending acquisitions
     pending_count: Mutex<usize>,
 
-    /// DC options for all data centers
-    dc_options: Mutex<DcOptionsSet>,
+    /// DC options for all data centers, shared with the connections
+    dc_options: Arc<Mutex<DcOptionsSet>>,
     rsa_keys: Mutex<Vec<RsaKey>>,
 
+    /// Permanent auth keys of the DCs, shared by all their connections
+    auth_keys: Mutex<HashMap<DcId, AuthKey>>,
+
+    /// Watchdog of the temporary auth keys of PFS connections
+    temp_auth_key_watchdog: TempAuthKeyWatchdog,
+
     /// Circuit breakers for each DC
     circuit_breakers: Mutex<HashMap<DcId, Arc<CircuitBreaker>>>,
 
     /// Health checker for connection validation
     health_checker: HealthChecker,
+
+    /// Network statistics of all connections
+    net_stats: Arc<Mutex<NetStatsManager>>,
 }
 
 impl ConnectionPoolInner {
@@ -297,6 +308,15 @@ impl ConnectionPoolInner {
 
         let pool = pools.entry(dc_id).or_default();
 
+        // New queries must use a connection with a new temporary key; the
+        // queries already sent through this one may still wait for answers
+        if connection.needs_temp_key_rotation() {
+            tracing::debug!("Closing connection to DC {:?} with an expiring temporary key", dc_id);
+            pool.retain(|e| !Arc::ptr_eq(&e.connection, &connection));
+            stop_connection(connection);
+            return;
+        }
+
         // Find existing entry or create new one
         if let Some(entry) = pool.iter_mut().find(|e| Arc::ptr_eq(&e.connection, &connection)) {
             entry.in_use = false;
@@ -321,6 +341,7 @@ impl ConnectionPoolInner {
         if let Some(pool) = pools.get_mut(&dc_id) {
             pool.retain(|e| !Arc::ptr_eq(&e.connection, connection));
         }
+        stop_connection(connection.clone());
     }
 
     /// Cleans up idle connections in a pool (must hold lock).
@@ -333,24 +354,29 @@ impl ConnectionPoolInner {
         // 3. In excess of max_idle_connections
 
         // First, separate idle and in-use connections
-        let mut idle: Vec<PoolEntry> = pool
-            .drain(..)
-            .filter(|e| !e.in_use)
-            .collect();
+        let (in_use, mut idle): (Vec<PoolEntry>, Vec<PoolEntry>) =
+            pool.drain(..).partition(|e| e.in_use);
 
         // Sort by last_used (oldest first)
         idle.sort_by_key(|e| e.last_used);
 
-        // Remove old connections
-        idle.retain(|e| now.duration_since(e.last_used) < self.config.connection_ttl);
+        // Remove old connections and connections with expiring temporary keys
+        let (mut kept, mut removed): (Vec<PoolEntry>, Vec<PoolEntry>) = idle.into_iter().partition(|e| {
+            now.duration_since(e.last_used) < self.config.connection_ttl
+                && !e.connection.needs_temp_key_rotation()
+        });
 
         // Keep only max_idle_connections
-        if idle.len() > self.config.max_idle_connections {
-            idle.truncate(self.config.max_idle_connections);
+        if kept.len() > self.config.max_idle_connections {
+            removed.extend(kept.drain(self.config.max_idle_connections..));
+        }
+        for entry in removed {
+            stop_connection(entry.connection);
         }
 
         // Recreate pool with in-use connections + remaining idle
-        *pool = idle;
+        *pool = in_use;
+        pool.extend(kept);
     }
 
     /// Cleans up all idle connections.
@@ -372,7 +398,32 @@ impl ConnectionPoolInner {
         for purpose in [ConnectionPurpose::Main, ConnectionPurpose::Download, ConnectionPurpose::Upload] {
             let pools = self.get_pool(purpose);
             let mut pools = pools.lock();
-            pools.clear();
+            for (_, pool) in pools.drain() {
+                for entry in pool {
+                    stop_connection(entry.connection);
+                }
+            }
+        }
+    }
+}
+
+/// Stops a connection which left the pool.
+///
+/// The queries sent through the connection still get their answers, so it
+/// stops once they are done. Outside of a runtime it stops at once.
+fn stop_connection(connection: Arc<SessionConnection>) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(async move {
+                if let Err(e) = connection.stop_gracefully().await {
+                    tracing::warn!("Failed to stop connection: {}", e);
+                }
+            });
+        }
+        Err(_) => {
+            if let Err(e) = futures::executor::block_on(connection.stop()) {
+                tracing::warn!("Failed to stop connection: {}", e);
+            }
         }
     }
 }
@@ -406,10 +457,13 @@ impl ConnectionPool {
                 config,
                 closed: Mutex::new(false),
                 pending_count: Mutex::new(0),
-                dc_options: Mutex::new(DcOptionsSet::new()),
+                dc_options: Arc::new(Mutex::new(DcOptionsSet::new())),
                 rsa_keys: Mutex::new(Vec::new()),
+                auth_keys: Mutex::new(HashMap::new()),
+                temp_auth_key_watchdog: TempAuthKeyWatchdog::new(),
                 circuit_breakers: Mutex::new(HashMap::new()),
                 health_checker,
+                net_stats: Arc::new(Mutex::new(NetStatsManager::new())),
             }),
         }
     }
@@ -428,6 +482,24 @@ impl ConnectionPool {
         *self.inner.dc_options.lock() = options;
     }
 
+    /// Returns the connection statistics of the DC options, for saving.
+    pub fn dc_option_stats(&self) -> Vec<SavedDcOptionStats> {
+        self.inner.dc_options.lock().saved_stats()
+    }
+
+    /// Returns the network statistics of the pool's connections.
+    pub fn net_stats(&self) -> Arc<Mutex<NetStatsManager>> {
+        self.inner.net_stats.clone()
+    }
+
+    /// Restores saved connection statistics of the DC options.
+    ///
+    /// Must be called after [`set_dc_options`](Self::set_dc_options), which
+    /// resets the statistics.
+    pub fn restore_dc_option_stats(&self, stats: &[SavedDcOptionStats]) {
+        self.inner.dc_options.lock().restore_stats(stats);
+    }
+
     /// Sets RSA keys for handshake encryption.
     ///
     /// Keys are applied to new connections and updated on existing connections.
@@ -445,6 +517,26 @@ impl ConnectionPool {
         }
     }
 
+    /// Sets the auth key of a DC, e.g. one restored from a saved session.
+    ///
+    /// New connections to the DC use the key instead of performing a
+    /// handshake. Existing connections keep their keys.
+    pub fn set_auth_key(&self, dc_id: DcId, key: AuthKey) {
+        self.inner.auth_keys.lock().insert(dc_id, key);
+    }
+
+    /// Returns the auth key of a DC, if one was set or created by a
+    /// handshake.
+    pub fn auth_key(&self, dc_id: DcId) -> Option<AuthKey> {
+        self.inner.auth_keys.lock().get(&dc_id).cloned()
+    }
+
+    /// Returns the watchdog of the temporary auth keys used by PFS
+    /// connections.
+    pub fn temp_auth_key_watchdog(&self) -> &TempAuthKeyWatchdog {
+        &self.inner.temp_auth_key_watchdog
+    }
+
     /// Acquires a connection for the given DC and purpose.
     ///
     /// If an idle connection is available, it will be reused.
@@ -535,7 +627,9 @@ impl ConnectionPool {
 
         if let Some(pool) = pools.get_mut(&dc_id) {
             // Find an idle, ready connection
-            if let Some(entry) = pool.iter_mut().find(|e| !e.in_use && e.connection.is_ready()) {
+            if let Some(entry) = pool.iter_mut().find(|e| {
+                !e.in_use && e.connection.is_ready() && !e.connection.needs_temp_key_rotation()
+            }) {
                 entry.in_use = true;
                 entry.last_used = Instant::now();
 
@@ -574,15 +668,23 @@ impl ConnectionPool {
             }
         }
 
-        // Create new session connection
+        // Create new session connection, reusing the auth key of the DC
         let auth_data = Arc::new(AuthDataShared::new(dc_id));
-        let connection = Arc::new(SessionConnection::new(config, auth_data));
+        if let Some(key) = self.auth_key(dc_id) {
+            auth_data.set_auth_key(key);
+        }
+        // Only the main connections stay away from media-only options
+        let config = config.with_media_only(purpose != ConnectionPurpose::Main);
+        let stats_counter = self.inner.net_stats.lock().counter(FileType::None);
+        let connection = Arc::new(
+            SessionConnection::new(config, auth_data)
+                .with_shared_dc_options(self.inner.dc_options.clone())
+                .with_stats_counter(stats_counter),
+        );
 
-        // Set DC options on the connection
-        let dc_options = self.inner.dc_options.lock().clone();
-        connection.set_dc_options(dc_options);
         let rsa_keys = self.inner.rsa_keys.lock().clone();
         connection.set_rsa_keys(rsa_keys);
+        connection.set_temp_auth_key_watchdog(self.inner.temp_auth_key_watchdog.clone());
 
         // Start the connection
         connection
@@ -590,6 +692,18 @@ impl ConnectionPool {
             .await
             .map_err(|e| PoolError::ConnectionFailed(e.to_string()))?;
 
+        // Remember the permanent key, which the connection may have created
+        // or replaced after the server forgot it
+        let perm_key = connection.perm_auth_key().or_else(|| {
+            connection
+                .auth_data()
+                .get_auth_key()
+                .filter(|key| !key.is_temporary())
+        });
+        if let Some(key) = perm_key {
+            self.inner.auth_keys.lock().insert(dc_id, key);
+        }
+
         // Add to pool
         {
             let pools = self.inner.get_pool(purpose);
@@ -648,7 +762,9 @@ impl ConnectionPool {
             let unhealthy_idx = pool.iter().position(|e| {
                 !e.in_use && {
                     let health = HealthChecker::check_connection_quick(&e.connection);
-                    health != HealthStatus::Healthy || !e.connection.is_ready()
+                    health != HealthStatus::Healthy
+                        || !e.connection.is_ready()
+                        || e.connection.needs_temp_key_rotation()
                 }
             });
 
@@ -658,11 +774,14 @@ impl ConnectionPool {
                     dc_id,
                     purpose
                 );
-                pool.remove(idx);
+                stop_connection(pool.remove(idx).connection);
             }
 
             // Now find a healthy idle connection
-            if let Some(entry) = pool.iter_mut().find(|e| !e.in_use) {
+            if let Some(entry) = pool
+                .iter_mut()
+                .find(|e| !e.in_use && !e.connection.needs_temp_key_rotation())
+            {
                 let health = HealthChecker::check_connection_quick(&entry.connection);
 
                 if health == HealthStatus::Healthy && entry.connection.is_ready() {
@@ -753,7 +872,7 @@ impl ConnectionPool {
                     // Don't sleep after the last attempt
                     if attempt < max_attempts - 1 {
                         // Calculate delay with exponential backoff
-                        let delay_ms = base_delay.as_millis() * 2u128.pow(attempt as u32);
+                        let delay_ms = base_delay.as_millis() * 2u128.pow(attempt);
                         let delay = Duration::from_millis(
                             delay_ms.min(max_delay.as_millis()) as u64,
                         );
@@ -832,6 +951,7 @@ impl Default for ConnectionPool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::SessionState;
 
     #[test]
     fn test_pool_config_default() {
@@ -907,9 +1027,13 @@ mod tests {
             config: PoolConfig::default(),
             closed: Mutex::new(false),
             pending_count: Mutex::new(0),
-            dc_options: Mutex::new(DcOptionsSet::new()),
+            dc_options: Arc::new(Mutex::new(DcOptionsSet::new())),
             circuit_breakers: Mutex::new(HashMap::new()),
             health_checker: HealthChecker::default(),
+            rsa_keys: Mutex::new(Vec::new()),
+            auth_keys: Mutex::new(HashMap::new()),
+            temp_auth_key_watchdog: TempAuthKeyWatchdog::new(),
+            net_stats: Arc::new(Mutex::new(NetStatsManager::new())),
         });
 
         let mut pooled = PooledConnection::new(connection, dc_id, purpose, pool);
@@ -978,9 +1102,13 @@ mod tests {
             config: PoolConfig::default(),
             closed: Mutex::new(false),
             pending_count: Mutex::new(0),
-            dc_options: Mutex::new(DcOptionsSet::new()),
+            dc_options: Arc::new(Mutex::new(DcOptionsSet::new())),
             circuit_breakers: Mutex::new(HashMap::new()),
             health_checker: HealthChecker::default(),
+            rsa_keys: Mutex::new(Vec::new()),
+            auth_keys: Mutex::new(HashMap::new()),
+            temp_auth_key_watchdog: TempAuthKeyWatchdog::new(),
+            net_stats: Arc::new(Mutex::new(NetStatsManager::new())),
         });
 
         let pooled = PooledConnection::new(connection.clone(), dc_id, purpose, pool);
@@ -1057,4 +1185,62 @@ mod tests {
             DcId::internal(3)
         );
     }
+
+    #[test]
+    fn test_cleanup_stops_removed_connections() {
+        let dc_id = DcId::internal(2);
+        let connection = || {
+            let auth_data = Arc::new(crate::auth::AuthDataShared::new(dc_id));
+            Arc::new(SessionConnection::new(SessionConnectionConfig::new(dc_id), auth_data))
+        };
+        let entry = |connection: &Arc<SessionConnection>, age: u64, in_use: bool| PoolEntry {
+            connection: connection.clone(),
+            last_used: Instant::now() - Duration::from_secs(age),
+            in_use,
+        };
+
+        let pool = ConnectionPool::with_config(PoolConfig {
+            max_idle_connections: 1,
+            ..PoolConfig::default()
+        });
+        let (busy, idle, excess, expired) = (connection(), connection(), connection(), connection());
+        pool.inner.main_pools.lock().insert(
+            dc_id,
+            vec![
+                entry(&busy, 1000, true),
+                entry(&idle, 1, false),
+                entry(&excess, 2, false),
+                entry(&expired, 1000, false),
+            ],
+        );
+
+        pool.inner.cleanup_idle();
+
+        let pools = pool.inner.main_pools.lock();
+        let kept: Vec<_> = pools[&dc_id].iter().map(|e| e.connection.clone()).collect();
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().any(|c| Arc::ptr_eq(c, &busy)));
+        assert_eq!(busy.state(), SessionState::Empty);
+        assert_eq!(expired.state(), SessionState::Closed);
+        // One of the two fresh idle connections is kept
+        let closed = [&idle, &excess]
+            .iter()
+            .filter(|c| c.state() == SessionState::Closed)
+            .count();
+        assert_eq!(closed, 1);
+    }
+
+    #[test]
+    fn test_set_auth_key() {
+        let pool = ConnectionPool::new();
+        assert!(pool.auth_key(DcId::internal(2)).is_none());
+
+        let key = AuthKey::new(42, vec![7; 256]);
+        pool.set_auth_key(DcId::internal(2), key.clone());
+
+        assert_eq!(pool.auth_key(DcId::internal(2)), Some(key));
+        assert!(pool.auth_key(DcId::internal(4)).is_none());
+        // Clones share the keys
+        assert!(pool.clone().auth_key(DcId::internal(2)).is_some());
+    }
 }
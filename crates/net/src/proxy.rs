@@ -47,6 +47,10 @@ pub enum ProxyError {
     #[error("Invalid proxy address: {0}")]
     InvalidAddress(String),
 
+    /// Malformed MTProto proxy secret
+    #[error("Invalid proxy secret: {0}")]
+    InvalidSecret(String),
+
     /// Connection failed
     #[error("Proxy connection failed: {0}")]
     ConnectionFailed(String),
@@ -286,6 +290,11 @@ impl fmt::Display for Proxy {
 /// Secret for MTProto proxy obfuscation.
 ///
 /// Based on TDLib's ProxySecret from `td/mtproto/ProxySecret.h`.
+///
+/// The raw secret has one of three layouts:
+/// - 16 bytes: plain obfuscated2 secret
+/// - `0xdd` + 16 bytes: obfuscated2 with padded intermediate framing
+/// - `0xee` + 16 bytes + domain: fake-TLS emulation for the given domain
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProxySecret {
     /// Secret data
@@ -293,11 +302,75 @@ pub struct ProxySecret {
 }
 
 impl ProxySecret {
+    /// Length of the key part of the secret.
+    pub const KEY_SIZE: usize = 16;
+
+    /// Maximum length of the fake-TLS domain.
+    pub const MAX_DOMAIN_LENGTH: usize = 182;
+
+    /// Prefix byte of secrets that use padded intermediate framing.
+    pub const RANDOM_PADDING_PREFIX: u8 = 0xdd;
+
+    /// Prefix byte of secrets that use fake-TLS emulation.
+    pub const EMULATE_TLS_PREFIX: u8 = 0xee;
+
     /// Creates a new proxy secret.
     pub fn new(secret: Vec<u8>) -> Self {
         Self { secret }
     }
 
+    /// Creates a proxy secret from its binary form, validating the layout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret has an unknown prefix or wrong length.
+    pub fn from_binary(raw: &[u8]) -> Result<Self, ProxyError> {
+        if raw.len() == Self::KEY_SIZE {
+            return Ok(Self::new(raw.to_vec()));
+        }
+
+        if raw.len() < Self::KEY_SIZE + 1 {
+            return Err(ProxyError::InvalidSecret(format!(
+                "Wrong proxy secret length: {}",
+                raw.len()
+            )));
+        }
+
+        match raw[0] {
+            Self::RANDOM_PADDING_PREFIX if raw.len() == Self::KEY_SIZE + 1 => {
+                Ok(Self::new(raw.to_vec()))
+            }
+            Self::EMULATE_TLS_PREFIX => {
+                let domain_len = raw.len() - Self::KEY_SIZE - 1;
+                if domain_len == 0 || domain_len > Self::MAX_DOMAIN_LENGTH {
+                    return Err(ProxyError::InvalidSecret(format!(
+                        "Wrong fake-TLS domain length: {}",
+                        domain_len
+                    )));
+                }
+                Ok(Self::new(raw.to_vec()))
+            }
+            prefix => Err(ProxyError::InvalidSecret(format!(
+                "Unsupported proxy secret prefix: {:#04x}",
+                prefix
+            ))),
+        }
+    }
+
+    /// Parses a proxy secret as it appears in `tg://proxy` links.
+    ///
+    /// Both the hex form and the URL-safe base64 form are accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret cannot be decoded or has a wrong layout.
+    pub fn from_link(link: &str) -> Result<Self, ProxyError> {
+        let raw = decode_hex(link)
+            .or_else(|| decode_base64url(link))
+            .ok_or_else(|| ProxyError::InvalidSecret("Malformed proxy secret".into()))?;
+        Self::from_binary(&raw)
+    }
+
     /// Returns the raw secret bytes.
     pub fn as_bytes(&self) -> &[u8] {
         &self.secret
@@ -312,6 +385,72 @@ impl ProxySecret {
     pub fn is_empty(&self) -> bool {
         self.secret.is_empty()
     }
+
+    /// Returns the 16-byte key used for obfuscation key derivation.
+    ///
+    /// The `0xdd`/`0xee` prefix and the fake-TLS domain are stripped.
+    pub fn get_proxy_secret(&self) -> &[u8] {
+        if self.secret.len() > Self::KEY_SIZE {
+            &self.secret[1..=Self::KEY_SIZE]
+        } else {
+            &self.secret
+        }
+    }
+
+    /// Returns `true` if packets must be sent with random padding.
+    pub fn use_random_padding(&self) -> bool {
+        self.secret.len() > Self::KEY_SIZE
+    }
+
+    /// Returns `true` if the connection must be wrapped in fake TLS.
+    pub fn emulate_tls(&self) -> bool {
+        self.secret.len() > Self::KEY_SIZE + 1 && self.secret[0] == Self::EMULATE_TLS_PREFIX
+    }
+
+    /// Returns the fake-TLS domain (empty if TLS is not emulated).
+    pub fn get_domain(&self) -> &[u8] {
+        if self.emulate_tls() {
+            &self.secret[Self::KEY_SIZE + 1..]
+        } else {
+            &[]
+        }
+    }
+}
+
+/// Decodes a hex string, returning `None` on any malformed input.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decodes URL-safe (or standard) base64 with optional padding.
+fn decode_base64url(s: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    for c in s.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(result)
 }
 
 #[cfg(test)]
@@ -389,4 +528,53 @@ mod tests {
         assert!(!secret.is_empty());
         assert_eq!(secret.as_bytes(), &[1, 2, 3, 4, 5]);
     }
+
+    #[test]
+    fn test_proxy_secret_layouts() {
+        let key = [0x11u8; 16];
+
+        let plain = ProxySecret::from_binary(&key).expect("valid secret");
+        assert!(!plain.use_random_padding());
+        assert!(!plain.emulate_tls());
+        assert_eq!(plain.get_proxy_secret(), &key);
+
+        let mut raw = vec![0xdd];
+        raw.extend_from_slice(&key);
+        let padded = ProxySecret::from_binary(&raw).expect("valid secret");
+        assert!(padded.use_random_padding());
+        assert!(!padded.emulate_tls());
+        assert_eq!(padded.get_proxy_secret(), &key);
+
+        let mut raw = vec![0xee];
+        raw.extend_from_slice(&key);
+        raw.extend_from_slice(b"example.com");
+        let tls = ProxySecret::from_binary(&raw).expect("valid secret");
+        assert!(tls.use_random_padding());
+        assert!(tls.emulate_tls());
+        assert_eq!(tls.get_proxy_secret(), &key);
+        assert_eq!(tls.get_domain(), b"example.com");
+
+        assert!(ProxySecret::from_binary(&[0u8; 15]).is_err());
+        assert!(ProxySecret::from_binary(&[0xab; 17]).is_err());
+        assert!(ProxySecret::from_binary(&raw[..17]).is_err());
+    }
+
+    #[test]
+    fn test_proxy_secret_from_link() {
+        let hex =
+            ProxySecret::from_link("dd00112233445566778899aabbccddeeff").expect("valid secret");
+        assert!(hex.use_random_padding());
+        assert_eq!(hex.get_proxy_secret()[0], 0x00);
+        assert_eq!(hex.get_proxy_secret()[15], 0xff);
+
+        // 0xee + 16 zero bytes + "a.io", URL-safe base64
+        let b64 = ProxySecret::from_link("7gAAAAAAAAAAAAAAAAAAAABhLmlv").expect("valid secret");
+        assert!(b64.emulate_tls());
+        assert_eq!(b64.get_domain(), b"a.io");
+
+        assert!(matches!(
+            ProxySecret::from_link("not a secret"),
+            Err(ProxyError::InvalidSecret(_))
+        ));
+    }
 }
@@ -14,6 +14,7 @@ use std::sync::Arc;
 use bytes::Bytes;
 
 use crate::dc::DcId;
+use crate::gzip;
 use crate::query::{AuthFlag, GzipFlag, NetQuery, NetQueryId, NetQueryType};
 
 /// Unique ID generator for queries.
@@ -92,6 +93,8 @@ impl NetQueryCreator {
     /// * `gzip_flag` - Whether to use gzip compression
     /// * `data` - Query data/bytes
     ///
+    /// With `GzipFlag::On` large queries are sent as `gzip_packed`.
+    ///
     /// # Returns
     ///
     /// A new NetQuery instance with a unique ID.
@@ -108,7 +111,7 @@ impl NetQueryCreator {
 
         NetQuery::new(
             id,
-            Bytes::from(data),
+            Self::pack_query(gzip_flag, data),
             dc_id,
             query_type,
             auth_flag,
@@ -163,7 +166,7 @@ impl NetQueryCreator {
         self.stats.on_query_created();
         NetQuery::new(
             id,
-            Bytes::from(data),
+            Self::pack_query(gzip_flag, data),
             dc_id,
             query_type,
             auth_flag,
@@ -233,6 +236,14 @@ impl NetQueryCreator {
         self.create(dc_id, query_type, AuthFlag::On, GzipFlag::Off, data)
     }
 
+    /// Packs large queries into `gzip_packed` if the flag is on.
+    fn pack_query(gzip_flag: GzipFlag, data: Vec<u8>) -> Bytes {
+        match gzip_flag {
+            GzipFlag::On => gzip::pack(&data).map_or_else(|| Bytes::from(data), Bytes::from),
+            GzipFlag::Off => Bytes::from(data),
+        }
+    }
+
     /// Gets reference to the query statistics.
     pub fn stats(&self) -> &Arc<NetQueryStats> {
         &self.stats
@@ -291,6 +302,45 @@ mod tests {
         assert_ne!(query1.id(), query2.id());
     }
 
+    #[test]
+    fn test_create_gzip_packed() {
+        let creator = NetQueryCreator::default();
+        let data: Vec<u8> = (0..4096).map(|i| (i % 16) as u8).collect();
+
+        let query = creator.create(
+            DcId::main(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::On,
+            data.clone(),
+        );
+        assert!(gzip::is_gzip_packed(query.query()));
+        assert!(matches!(gzip::unpack(query.query().clone()), Ok(unpacked) if unpacked == data));
+
+        let query = creator.create(
+            DcId::main(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::Off,
+            data.clone(),
+        );
+        assert_eq!(query.query().as_ref(), &data[..]);
+    }
+
+    #[test]
+    fn test_create_gzip_small_query() {
+        let creator = NetQueryCreator::default();
+
+        let query = creator.create(
+            DcId::main(),
+            NetQueryType::Common,
+            AuthFlag::On,
+            GzipFlag::On,
+            vec![1, 2, 3, 4],
+        );
+        assert_eq!(query.query().as_ref(), &[1, 2, 3, 4]);
+    }
+
     #[test]
     fn test_create_common() {
         let creator = NetQueryCreator::default();
@@ -6,23 +6,28 @@
 //!
 //! This module implements TDLib's SessionConnection from `td/telegram/net/Session.h`.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bytes::{Buf, Bytes};
 use parking_lot::Mutex;
 use tokio::sync::mpsc;
 
-use crate::auth::{AuthDataShared, AuthKeyState};
+use crate::auth::{AuthDataShared, AuthKey, AuthKeyState};
 use crate::connection::ConnectionError;
 use crate::crypto::{aes_ige_decrypt, aes_ige_encrypt, sha256};
 use crate::dc::{DcId, DcOption, DcOptionsSet};
+use crate::dc_auth::{RegisteredAuthKey, TempAuthKeyWatchdog};
+use crate::gzip;
 use crate::handshake::{HandshakeAction, HandshakeError, HandshakeMode, MtprotoHandshake};
+use crate::happy_eyeballs;
 use crate::packet::{MessageId, PacketInfo, PacketType};
-use crate::query::NetQuery;
+use crate::pfs::{parse_bind_result, PfsError, TempAuthKeys, DEFAULT_TEMP_KEY_EXPIRES_IN};
+use crate::query::{NetQuery, QueryError};
 use crate::rsa_key_shared::RsaKey;
+use crate::stats::{FileType, NetStatsCounter};
 use crate::transport::{ReadResult, TcpTransport, WriteOptions};
 
 /// Convert HandshakeError to ConnectionError
@@ -32,7 +37,11 @@ impl From<HandshakeError> for ConnectionError {
     }
 }
 
-use super::packets::{ContainerMessage, ServicePacket};
+use super::handlers::{PacketHandler, PacketHandlerResult, ServicePacketHandler};
+use super::packets::{
+    encode_destroy_session, encode_get_future_salts, encode_msgs_state_req, ContainerMessage,
+    RpcResult, ServicePacket,
+};
 use super::ping::{PingConfig, PingManager};
 use super::query::QueryLifecycle;
 use super::{SessionState, SessionStatistics};
@@ -40,6 +49,29 @@ use super::{SessionState, SessionStatistics};
 /// Default MTProto timeout.
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// Maximum number of packets read while waiting for the answer to
+/// `auth.bindTempAuthKey`.
+const MAX_BIND_RESULT_READS: usize = 8;
+
+/// Maximum number of times `auth.bindTempAuthKey` is sent when the server
+/// rejects it.
+const MAX_BIND_QUERY_SENDS: usize = 3;
+
+/// Interval of the service checks of the network loop.
+const SERVICE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of future salts requested at once.
+const FUTURE_SALT_COUNT: i32 = 64;
+
+/// Delay before future salts are requested again if no answer arrived.
+const FUTURE_SALTS_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Interval of the checks for pending queries of a stopping session.
+const PENDING_QUERIES_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Time without an answer after which the state of a query is requested.
+const STATE_REQUEST_DELAY: Duration = Duration::from_secs(10);
+
 /// Session connection configuration.
 #[derive(Debug, Clone)]
 pub struct SessionConnectionConfig {
@@ -63,6 +95,12 @@ pub struct SessionConnectionConfig {
 
     /// Query timeout
     pub query_timeout: Duration,
+
+    /// Whether media-only DC options may be used
+    pub allow_media_only: bool,
+
+    /// Whether IPv6 options are tried before IPv4 ones
+    pub prefer_ipv6: bool,
 }
 
 impl Default for SessionConnectionConfig {
@@ -75,6 +113,8 @@ impl Default for SessionConnectionConfig {
             write_options: WriteOptions::default(),
             ping_config: PingConfig::default(),
             query_timeout: DEFAULT_TIMEOUT,
+            allow_media_only: false,
+            prefer_ipv6: true,
         }
     }
 }
@@ -105,6 +145,18 @@ impl SessionConnectionConfig {
         self.is_cdn = is_cdn;
         self
     }
+
+    /// Sets whether media-only DC options may be used.
+    pub fn with_media_only(mut self, allow_media_only: bool) -> Self {
+        self.allow_media_only = allow_media_only;
+        self
+    }
+
+    /// Sets whether IPv6 options are tried before IPv4 ones.
+    pub fn with_prefer_ipv6(mut self, prefer_ipv6: bool) -> Self {
+        self.prefer_ipv6 = prefer_ipv6;
+        self
+    }
 }
 
 /// Session connection events.
@@ -171,6 +223,18 @@ pub struct SessionConnection {
 
     /// RSA keys for handshake encryption
     rsa_keys: Arc<Mutex<Vec<RsaKey>>>,
+
+    /// Permanent and temporary auth keys when PFS is used
+    temp_auth_keys: Arc<Mutex<TempAuthKeys>>,
+
+    /// Watchdog the bound temporary key is registered with
+    temp_auth_key_watchdog: Arc<Mutex<Option<TempAuthKeyWatchdog>>>,
+
+    /// Registration of the bound temporary key
+    registered_temp_auth_key: Arc<Mutex<Option<RegisteredAuthKey>>>,
+
+    /// Counter of the bytes sent and received
+    stats_counter: Option<NetStatsCounter>,
 }
 
 impl std::fmt::Debug for SessionConnection {
@@ -208,6 +272,10 @@ impl SessionConnection {
             stop_flag: Arc::new(AtomicBool::new(false)),
             dc_options: Arc::new(Mutex::new(DcOptionsSet::new())),
             rsa_keys: Arc::new(Mutex::new(Vec::new())),
+            temp_auth_keys: Arc::new(Mutex::new(TempAuthKeys::new())),
+            temp_auth_key_watchdog: Arc::new(Mutex::new(None)),
+            registered_temp_auth_key: Arc::new(Mutex::new(None)),
+            stats_counter: None,
         }
     }
 
@@ -287,20 +355,95 @@ impl SessionConnection {
         *self.dc_options.lock() = options;
     }
 
+    /// Uses DC options shared with other connections.
+    ///
+    /// Connection statistics of the options are recorded in the shared set,
+    /// so all connections learn which options work.
+    pub fn with_shared_dc_options(mut self, options: Arc<Mutex<DcOptionsSet>>) -> Self {
+        self.dc_options = options;
+        self
+    }
+
+    /// Counts the bytes sent and received by the connection.
+    ///
+    /// Bytes of queries for file parts are attributed to the file type of
+    /// the query.
+    pub fn with_stats_counter(mut self, counter: NetStatsCounter) -> Self {
+        self.stats_counter = Some(counter);
+        self
+    }
+
     /// Gets a DC option for connecting to the configured DC.
     pub fn get_dc_option(&self) -> Result<DcOption, ConnectionError> {
-        let options = self.dc_options.lock();
-        let dc_options = options.get_options_for_dc(self.config.dc_id);
+        self.connection_candidates()?
+            .into_iter()
+            .next()
+            .map(|(_, option)| option)
+            .ok_or_else(|| self.no_dc_options_error())
+    }
 
-        if dc_options.is_empty() {
-            return Err(ConnectionError::Failed(format!(
-                "No DC options found for DC {}",
-                self.config.dc_id.get_raw_id()
-            )));
+    /// Returns the DC options to race when connecting, best first.
+    fn connection_candidates(&self) -> Result<Vec<(usize, DcOption)>, ConnectionError> {
+        let candidates = self.dc_options.lock().find_connection_candidates(
+            self.config.dc_id,
+            self.config.allow_media_only,
+            self.config.prefer_ipv6,
+        );
+        if candidates.is_empty() {
+            return Err(self.no_dc_options_error());
         }
+        Ok(candidates)
+    }
+
+    fn no_dc_options_error(&self) -> ConnectionError {
+        ConnectionError::Failed(format!(
+            "No DC options found for DC {}",
+            self.config.dc_id.get_raw_id()
+        ))
+    }
+
+    /// Connects to the configured DC.
+    ///
+    /// Connection attempts are raced across the DC options; the result of
+    /// every finished attempt is recorded in the options' statistics.
+    async fn connect_transport(&self) -> Result<TcpTransport, ConnectionError> {
+        let candidates = self.connection_candidates()?;
+        let addrs: Vec<std::net::SocketAddr> = candidates
+            .iter()
+            .map(|(_, option)| std::net::SocketAddr::new(option.ip_address, option.port))
+            .collect();
 
-        // Return the first available option
-        Ok(dc_options[0].clone())
+        tracing::info!(
+            "Connecting to DC {} at {:?}",
+            self.config.dc_id.get_raw_id(),
+            addrs
+        );
+
+        let started = Instant::now();
+        let outcome =
+            happy_eyeballs::connect_tcp(&addrs, happy_eyeballs::DEFAULT_CONNECTION_ATTEMPT_DELAY)
+                .await;
+
+        let mut options = self.dc_options.lock();
+        for (idx, error) in &outcome.failures {
+            tracing::debug!("Failed to connect to {}: {}", addrs[*idx], error);
+            options.record_failure(candidates[*idx].0);
+        }
+        match outcome.winner {
+            Some((idx, mut transport)) => {
+                options.record_success(candidates[idx].0, started.elapsed().as_secs_f64());
+                if let Some(counter) = &self.stats_counter {
+                    transport.set_stats_counter(counter.clone());
+                }
+                Ok(transport)
+            }
+            None => Err(outcome
+                .failures
+                .into_iter()
+                .last()
+                .map(|(_, error)| error)
+                .unwrap_or_else(|| self.no_dc_options_error())),
+        }
     }
 
     /// Sets the RSA keys for handshake encryption.
@@ -317,6 +460,26 @@ impl SessionConnection {
             .cloned()
     }
 
+    /// Sets the watchdog the bound temporary auth key is registered with.
+    pub fn set_temp_auth_key_watchdog(&self, watchdog: TempAuthKeyWatchdog) {
+        *self.temp_auth_key_watchdog.lock() = Some(watchdog);
+    }
+
+    /// Returns the permanent auth key the temporary key is bound to, if PFS
+    /// is used.
+    pub fn perm_auth_key(&self) -> Option<AuthKey> {
+        self.temp_auth_keys.lock().perm_key().cloned()
+    }
+
+    /// Returns `true` if the temporary auth key expires soon.
+    ///
+    /// The session keeps working with the key, so queries already sent get
+    /// their answers, but new queries should use a connection with a new key.
+    pub fn needs_temp_key_rotation(&self) -> bool {
+        let keys = self.temp_auth_keys.lock();
+        keys.temp_key().is_some() && keys.need_temp_key(unix_time())
+    }
+
     /// Sends a query through this session.
     pub fn send_query(&self, query: NetQuery) -> Result<(), ConnectionError> {
         if !self.is_ready() {
@@ -360,8 +523,16 @@ impl SessionConnection {
             payload.len()
         );
 
+        let payload = match Self::unpack_message(Bytes::copy_from_slice(payload)) {
+            Ok(payload) => payload,
+            Err(error) => {
+                self.fail_content_message(info.message_id, error);
+                return Ok(());
+            }
+        };
+
         // Handle based on packet type
-        if let Ok(service_packet) = ServicePacket::decode(payload) {
+        if let Ok(service_packet) = ServicePacket::decode(&payload) {
             match service_packet {
                 ServicePacket::MessageContainer { messages } => {
                     // Recursively process container messages
@@ -375,7 +546,7 @@ impl SessionConnection {
             }
         } else {
             // Content message - try to match to active query
-            self.handle_content_message(info.message_id, Bytes::copy_from_slice(payload))?;
+            self.handle_content_message(info.message_id, payload)?;
         }
 
         Ok(())
@@ -582,6 +753,19 @@ impl SessionConnection {
         Ok(())
     }
 
+    /// Fails the query answered by a message which can't be unpacked.
+    fn fail_content_message(&self, message_id: MessageId, error: QueryError) {
+        let msg_id = message_id.as_u64();
+        tracing::warn!("Failed to unpack message {}: {}", msg_id, error);
+        if let Some(query_id) = self.query_lifecycle.find_by_message_id(msg_id) {
+            let _ = self.query_lifecycle.mark_failed(query_id, error.to_string());
+        }
+        if let Some(query) = self.active_queries.lock().remove(&msg_id) {
+            self.statistics.lock().failed_queries += 1;
+            query.set_error(error);
+        }
+    }
+
     /// Handles a service packet.
     fn handle_service_packet(&self, packet: ServicePacket) -> Result<(), ConnectionError> {
         match packet {
@@ -607,23 +791,27 @@ impl SessionConnection {
     ///
     /// This method performs the complete connection flow:
     /// 1. Establishes TCP connection to the DC
-    /// 2. Performs MTProto handshake if no auth key exists
+    /// 2. With PFS, creates a temporary auth key and binds it to the
+    ///    permanent one; otherwise performs MTProto handshake if no auth key
+    ///    exists
     /// 3. Starts the network loop for read/write operations
     pub async fn start(&self) -> Result<(), ConnectionError> {
         self.set_state(SessionState::Connecting);
 
         // 1. Create TCP transport
-        let dc_option = self.get_dc_option()?;
-        let addr = std::net::SocketAddr::new(dc_option.ip_address, dc_option.port);
-
-        tracing::info!("Connecting to DC {} at {}", self.config.dc_id.get_raw_id(), addr);
-
-        let mut transport = TcpTransport::new(addr);
-        transport.connect().await?;
+        let transport = self.connect_transport().await?;
 
         tracing::info!("TCP connected to DC {}", self.config.dc_id.get_raw_id());
 
-        // 2. Check auth key
+        // 2. With PFS the session is encrypted with a bound temporary key
+        if self.config.use_pfs && !self.config.is_cdn {
+            let transport = self.bind_temp_auth_key(transport).await?;
+            self.run_network_loop_with_transport(transport).await?;
+            self.start_ping_loop().await;
+            return Ok(());
+        }
+
+        // 3. Check auth key
         if self.auth_data.auth_key_state() == AuthKeyState::Ready {
             // Auth key already exists, skip handshake
             tracing::info!("Auth key already ready for DC {}", self.config.dc_id.get_raw_id());
@@ -633,13 +821,31 @@ impl SessionConnection {
             return Ok(());
         }
 
-        // 3. Start handshake
-        let mode = if self.config.use_pfs {
-            HandshakeMode::Temp
-        } else {
-            HandshakeMode::Main
-        };
+        // 4. Create a permanent auth key
+        let (transport, auth_key) = self
+            .perform_handshake(transport, HandshakeMode::Main)
+            .await?;
+        self.auth_data.set_auth_key(auth_key);
 
+        tracing::info!("Handshake complete, auth key stored for DC {}", self.config.dc_id.get_raw_id());
+
+        // 5. Start main network loop with new auth key
+        self.run_network_loop_with_transport(transport).await?;
+
+        self.set_state(SessionState::Ready);
+
+        // Start ping manager
+        self.start_ping_loop().await;
+
+        Ok(())
+    }
+
+    /// Performs an MTProto handshake and returns the created auth key.
+    async fn perform_handshake(
+        &self,
+        mut transport: TcpTransport,
+        mode: HandshakeMode,
+    ) -> Result<(TcpTransport, AuthKey), ConnectionError> {
         let rsa_keys = self.rsa_keys.lock().clone();
         let mut handshake = MtprotoHandshake::new(self.config.dc_id, mode, rsa_keys);
 
@@ -649,15 +855,15 @@ impl SessionConnection {
         };
 
         // Send initial packet (unencrypted)
-        transport.write(&packet, None).await?;
+        transport
+            .write_plain(&packet, self.auth_data.next_message_id())
+            .await?;
         tracing::info!("Sent req_pq_multi to DC {}", self.config.dc_id.get_raw_id());
 
-        // 4. Run handshake loop
         let (transport, auth_key) = self
             .run_handshake_loop(transport, &mut handshake)
             .await?;
 
-        // Store the auth key
         let auth_key_id = crate::crypto::compute_auth_key_id(
             &auth_key
                 .clone()
@@ -665,20 +871,155 @@ impl SessionConnection {
                 .map_err(|_| ConnectionError::Failed("Invalid auth key length".into()))?,
         );
 
-        self.auth_data
-            .set_auth_key(crate::auth::AuthKey::new(auth_key_id, auth_key));
-
-        tracing::info!("Handshake complete, auth key stored for DC {}", self.config.dc_id.get_raw_id());
+        let auth_key = match mode {
+            HandshakeMode::Main => AuthKey::new(auth_key_id, auth_key),
+            HandshakeMode::Temp => AuthKey::temporary(
+                auth_key_id,
+                auth_key,
+                Instant::now() + Duration::from_secs(DEFAULT_TEMP_KEY_EXPIRES_IN as u64),
+            ),
+        };
+        Ok((transport, auth_key))
+    }
 
-        // 5. Start main network loop with new auth key
-        self.run_network_loop_with_transport(transport).await?;
+    /// Creates a temporary auth key and binds it to the permanent one.
+    ///
+    /// The permanent key is the one the connection was created with, or a
+    /// new one if there is none or the server doesn't know it anymore. On
+    /// success the session is encrypted with the temporary key.
+    async fn bind_temp_auth_key(
+        &self,
+        mut transport: TcpTransport,
+    ) -> Result<TcpTransport, ConnectionError> {
+        if let Some(key) = self.auth_data.get_auth_key().filter(|key| !key.is_temporary()) {
+            self.temp_auth_keys.lock().set_perm_key(key);
+        }
 
-        self.set_state(SessionState::Ready);
+        let mut perm_key_recreated = false;
+        loop {
+            if self.temp_auth_keys.lock().need_perm_key() {
+                let (new_transport, perm_key) = self
+                    .perform_handshake(transport, HandshakeMode::Main)
+                    .await?;
+                transport = new_transport;
+                tracing::info!("Permanent auth key created for DC {}", self.config.dc_id.get_raw_id());
+                self.temp_auth_keys.lock().set_perm_key(perm_key);
+            }
 
-        // Start ping manager
-        self.start_ping_loop().await;
+            let (new_transport, temp_key) = self
+                .perform_handshake(transport, HandshakeMode::Temp)
+                .await?;
+            transport = new_transport;
+            let temp_key_bytes: [u8; 256] = temp_key
+                .as_bytes()
+                .try_into()
+                .map_err(|_| ConnectionError::Failed("Invalid auth key length".into()))?;
+
+            let now = unix_time();
+            self.temp_auth_keys
+                .lock()
+                .set_temp_key(temp_key.clone(), now + DEFAULT_TEMP_KEY_EXPIRES_IN);
+            self.auth_data.set_auth_key(temp_key.clone());
+
+            // The bind query is sent encrypted with the temporary key and
+            // sent again if the server corrects the salt, time or session
+            let mut result = None;
+            for _ in 0..MAX_BIND_QUERY_SENDS {
+                let message_id = self.auth_data.next_message_id();
+                let query = self
+                    .temp_auth_keys
+                    .lock()
+                    .bind_query(message_id.as_u64(), self.auth_data.session_id())
+                    .map_err(|e| ConnectionError::Failed(e.to_string()))?;
+                let mut packet_info = PacketInfo::new()
+                    .with_packet_type(PacketType::Common)
+                    .with_salt(self.auth_data.server_salt())
+                    .with_session_id(self.auth_data.session_id())
+                    .with_message_id(message_id)
+                    .with_seq_no(self.auth_data.next_seq_no(true))
+                    .with_version(2);
+                transport
+                    .write_with_info(&query, Some(&temp_key_bytes), &mut packet_info)
+                    .await?;
+
+                result = self
+                    .read_bind_result(&mut transport, &temp_key_bytes, message_id.as_u64())
+                    .await?;
+                if result.is_some() {
+                    break;
+                }
+            }
+            let Some(result) = result else {
+                return Err(ConnectionError::Failed("Bind query was rejected too many times".into()));
+            };
+            let result = self.temp_auth_keys.lock().on_bind_result(result);
+            match result {
+                Ok(()) => {
+                    if let Some(watchdog) = self.temp_auth_key_watchdog.lock().as_ref() {
+                        *self.registered_temp_auth_key.lock() =
+                            Some(watchdog.register_auth_key_id(temp_key.id as i64));
+                    }
+                    tracing::info!("Temporary auth key bound for DC {}", self.config.dc_id.get_raw_id());
+                    return Ok(transport);
+                }
+                Err(PfsError::PermKeyEmpty) if !perm_key_recreated => {
+                    tracing::warn!(
+                        "Permanent auth key of DC {} is unknown to the server, creating a new one",
+                        self.config.dc_id.get_raw_id()
+                    );
+                    perm_key_recreated = true;
+                }
+                Err(e) => {
+                    return Err(ConnectionError::Failed(format!(
+                        "Failed to bind temporary auth key: {}",
+                        e
+                    )));
+                }
+            }
+        }
+    }
 
-        Ok(())
+    /// Reads packets until the answer to `auth.bindTempAuthKey` arrives.
+    ///
+    /// Returns `None` if the server rejected the query with a
+    /// `bad_server_salt` or `bad_msg_notification` and it must be sent
+    /// again; the salt, time difference or session are corrected by then.
+    async fn read_bind_result(
+        &self,
+        transport: &mut TcpTransport,
+        temp_key: &[u8; 256],
+        message_id: u64,
+    ) -> Result<Option<Result<(), PfsError>>, ConnectionError> {
+        let service_handler = ServicePacketHandler::with_auth_data(self.auth_data.clone());
+        for _ in 0..MAX_BIND_RESULT_READS {
+            let (body, packet_info) = match transport.read_with_info(Some(temp_key)).await? {
+                (ReadResult::Packet(body), packet_info) => (body, packet_info),
+                (ReadResult::Nop | ReadResult::QuickAck(_), _) => continue,
+                (ReadResult::Error(code), _) => {
+                    return Err(ConnectionError::Failed(format!("Transport error: {}", code)));
+                }
+            };
+            if let Some(result) = parse_bind_result(&body, message_id) {
+                return Ok(Some(result));
+            }
+            if !matches!(
+                ServicePacket::decode(&body),
+                Ok(ServicePacket::BadServerSalt { .. } | ServicePacket::BadMsgNotification { .. })
+            ) {
+                continue;
+            }
+            match service_handler.handle(&body, &packet_info) {
+                PacketHandlerResult::Resend(msg_ids) if msg_ids.contains(&message_id) => {
+                    tracing::debug!("Sending the bind query again");
+                    return Ok(None);
+                }
+                PacketHandlerResult::Error(e) => {
+                    return Err(ConnectionError::Failed(format!("Bind query rejected: {}", e)));
+                }
+                _ => {}
+            }
+        }
+        Err(ConnectionError::Failed("No answer to the bind query".into()))
     }
 
     /// Runs the MTProto handshake loop.
@@ -707,7 +1048,9 @@ impl SessionConnection {
             match handshake.on_message(&packet_data) {
                 Ok(HandshakeAction::Send(packet)) => {
                     // Send packet to server (still unencrypted)
-                    transport.write(&packet, None).await?;
+                    transport
+                        .write_plain(&packet, self.auth_data.next_message_id())
+                        .await?;
                 }
                 Ok(HandshakeAction::Wait) => {
                     // Wait for next response
@@ -739,6 +1082,27 @@ impl SessionConnection {
         Ok(())
     }
 
+    /// Stops the session connection once the sent queries are answered.
+    ///
+    /// Queries still waiting for an answer after the query timeout fail.
+    pub async fn stop_gracefully(&self) -> Result<(), ConnectionError> {
+        let deadline = Instant::now() + self.config.query_timeout;
+        while self.has_pending_queries() && Instant::now() < deadline {
+            tokio::time::sleep(PENDING_QUERIES_CHECK_INTERVAL).await;
+        }
+
+        let pending: Vec<NetQuery> = self.active_queries.lock().drain().map(|(_, query)| query).collect();
+        for query in pending {
+            self.on_query_timeout(query);
+        }
+        self.stop().await
+    }
+
+    /// Returns `true` if queries sent through this session wait for answers.
+    pub fn has_pending_queries(&self) -> bool {
+        !self.active_queries.lock().is_empty()
+    }
+
     /// Starts the ping loop.
     async fn start_ping_loop(&self) {
         let ping_manager = self.ping_manager.clone();
@@ -791,16 +1155,7 @@ impl SessionConnection {
 
     /// Serializes a NetQuery into an MTProto packet.
     fn serialize_query_packet(&self, query: &NetQuery) -> Result<Vec<u8>, ConnectionError> {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let message_id = MessageId::generate(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs_f64(),
-            true,
-            0,
-        );
+        let message_id = self.auth_data.next_message_id();
 
         let seq_no = self.auth_data.next_seq_no(true);
 
@@ -879,10 +1234,7 @@ impl SessionConnection {
     /// separate tasks for handling incoming packets and outgoing queries.
     async fn run_network_loop(&self) -> Result<(), ConnectionError> {
         // 1. Create TCP transport
-        let dc_option = self.get_dc_option()?;
-        let addr = std::net::SocketAddr::new(dc_option.ip_address, dc_option.port);
-        let mut transport = TcpTransport::new(addr);
-        transport.connect().await?;
+        let mut transport = self.connect_transport().await?;
         transport.send_magic_if_needed().await?;
 
         // 2. Split transport into read/write halves
@@ -1133,15 +1485,25 @@ impl SessionConnection {
         let mut query_receiver = self.query_receiver.lock().take().ok_or_else(|| {
             ConnectionError::Failed("Query receiver already taken".into())
         })?;
+        let (service_sender, mut service_receiver) = mpsc::unbounded_channel::<ServiceMessage>();
+        let check_sender = service_sender.clone();
+        let service_handler = Arc::new(ServicePacketHandler::with_auth_data(self.auth_data.clone()));
+        let writer_service_handler = service_handler.clone();
         let auth_data = self.auth_data.clone();
         let stop_flag = self.stop_flag.clone();
         let event_sender = self.event_sender.clone();
+        let query_lifecycle = self.query_lifecycle.clone();
+        let active_queries = self.active_queries.clone();
+        let stats_counter = self.stats_counter.clone();
 
         tokio::spawn(async move {
+            let mut check_timer = tokio::time::interval(SERVICE_CHECK_INTERVAL);
+            let mut service_checks = ServiceChecks::default();
             while !stop_flag.load(Ordering::Relaxed) {
-                match query_receiver.recv().await {
-                    Some(query) => {
-                        let (packet, mut packet_info) = match Self::serialize_query_packet_impl(&query, &auth_data) {
+                let (packet, mut packet_info, file_type) = tokio::select! {
+                    query = query_receiver.recv() => {
+                        let Some(query) = query else { break };
+                        let (packet, packet_info) = match Self::serialize_query_packet_impl(&query, &auth_data) {
                             Ok(p) => p,
                             Err(e) => {
                                 tracing::error!("Failed to serialize query: {}", e);
@@ -1150,16 +1512,52 @@ impl SessionConnection {
                             }
                         };
 
-                        if let Err(e) = write_half
-                            .write_packet_with_info(&packet, Some(&auth_key_bytes_write), &mut packet_info)
-                            .await
-                        {
-                            tracing::error!("Failed to write packet: {}", e);
-                            let _ = event_sender.send(SessionEvent::Error(e.to_string()));
-                            break;
+                        // Register query by message_id for response matching and resending
+                        let msg_id = packet_info.message_id.as_u64();
+                        if let Err(err) = query_lifecycle.mark_sent(query.id(), msg_id) {
+                            tracing::trace!(
+                                "Query {} is not tracked (message_id={}): {}",
+                                query.id(),
+                                msg_id,
+                                err
+                            );
                         }
+                        let file_type = FileType::from_i32(query.file_type());
+                        active_queries.lock().insert(msg_id, query);
+                        (packet, packet_info, file_type)
                     }
-                    None => break,
+                    service = service_receiver.recv() => {
+                        let Some(message) = service else { break };
+                        let packet_info = Self::service_packet_info_impl(&auth_data, message.needs_ack);
+                        if let Some(msg_ids) = message.state_request {
+                            writer_service_handler
+                                .register_state_request(packet_info.message_id.as_u64(), msg_ids);
+                        }
+                        (message.packet, packet_info, FileType::None)
+                    }
+                    _ = check_timer.tick() => {
+                        let messages = service_checks.check(
+                            &auth_data,
+                            &active_queries.lock(),
+                            &writer_service_handler,
+                        );
+                        for message in messages {
+                            let _ = check_sender.send(message);
+                        }
+                        continue;
+                    }
+                };
+
+                if let Err(e) = write_half
+                    .write_packet_with_info(&packet, Some(&auth_key_bytes_write), &mut packet_info)
+                    .await
+                {
+                    tracing::error!("Failed to write packet: {}", e);
+                    let _ = event_sender.send(SessionEvent::Error(e.to_string()));
+                    break;
+                }
+                if let Some(counter) = &stats_counter {
+                    counter.move_to_file_type(file_type, 0, packet.len() as i64);
                 }
             }
             Ok::<(), ConnectionError>(())
@@ -1171,7 +1569,12 @@ impl SessionConnection {
         let query_lifecycle = self.query_lifecycle.clone();
         let active_queries = self.active_queries.clone();
         let statistics = self.statistics.clone();
+        let query_sender = self.query_sender.clone();
+        let stats_counter = self.stats_counter.clone();
         let ping_manager = self.ping_manager.clone();
+        service_handler.set_pong_callback(move |ping_id| {
+            ping_manager.lock().on_pong(ping_id);
+        });
 
         tokio::spawn(async move {
             while !stop_flag.load(Ordering::Relaxed) {
@@ -1186,30 +1589,14 @@ impl SessionConnection {
                         statistics.lock().packets_received += 1;
                         statistics.lock().bytes_received += data.len() as u64;
 
-                        let handle_service = |packet: ServicePacket| {
-                            match packet {
-                                ServicePacket::Pong(ping_id) => {
-                                    ping_manager.lock().on_pong(ping_id);
-                                }
-                                ServicePacket::NewSessionCreated { .. } => {
-                                    tracing::debug!("New session created");
-                                }
-                                ServicePacket::Ack { msg_ids } => {
-                                    tracing::debug!(
-                                        "Received ack for {} messages",
-                                        msg_ids.len()
-                                    );
-                                }
-                                _ => {
-                                    tracing::debug!(
-                                        "Unhandled service packet: {:?}",
-                                        packet
-                                    );
+                        let handle_content = |message_id: u64, payload: Bytes| {
+                            if let Some(counter) = &stats_counter {
+                                if let Some(query) = active_queries.lock().get(&message_id) {
+                                    let file_type = FileType::from_i32(query.file_type());
+                                    counter.move_to_file_type(file_type, payload.len() as i64, 0);
                                 }
                             }
-                        };
 
-                        let handle_content = |message_id: u64, payload: Bytes| {
                             if let Some(query_id) =
                                 query_lifecycle.find_by_message_id(message_id)
                             {
@@ -1246,26 +1633,80 @@ impl SessionConnection {
                             );
                         };
 
+                        let fail_content = |message_id: u64, error: QueryError| {
+                            tracing::warn!("Failed to unpack message {}: {}", message_id, error);
+                            if let Some(query_id) = query_lifecycle.find_by_message_id(message_id)
+                            {
+                                let _ = query_lifecycle.mark_failed(query_id, error.to_string());
+                            }
+                            if let Some(query) = active_queries.lock().remove(&message_id) {
+                                statistics.lock().failed_queries += 1;
+                                query.set_error(error);
+                                let _ = event_sender.send(SessionEvent::QueryCompleted(query.id()));
+                            }
+                        };
+
+                        let handle_message = |message_info: &PacketInfo, payload: Bytes| {
+                            match service_handler.handle(&payload, message_info) {
+                                PacketHandlerResult::Pass => {
+                                    handle_content(message_info.message_id.as_u64(), payload)
+                                }
+                                PacketHandlerResult::Handled => {}
+                                PacketHandlerResult::Respond(response) => {
+                                    let _ = service_sender.send(ServiceMessage::answer(response));
+                                }
+                                PacketHandlerResult::Resend(msg_ids) => {
+                                    for msg_id in msg_ids {
+                                        // The query is sent again with a new message ID
+                                        if let Some(query) = active_queries.lock().remove(&msg_id) {
+                                            tracing::debug!(
+                                                "Resending query {} sent as message {}",
+                                                query.id(),
+                                                msg_id
+                                            );
+                                            let _ = query_sender.send(query);
+                                        }
+                                    }
+                                }
+                                PacketHandlerResult::Error(e) => {
+                                    tracing::warn!("Failed to handle service message: {}", e);
+                                }
+                            }
+                        };
+
+                        let data = match Self::unpack_message(data) {
+                            Ok(data) => data,
+                            Err(error) => {
+                                fail_content(packet_info.message_id.as_u64(), error);
+                                continue;
+                            }
+                        };
+
                         match ServicePacket::decode(&data) {
                             Ok(ServicePacket::MessageContainer { messages }) => {
                                 let mut stack = messages;
                                 while let Some(msg) = stack.pop() {
-                                    match ServicePacket::decode(&msg.body) {
+                                    let body = match Self::unpack_message(msg.body) {
+                                        Ok(body) => body,
+                                        Err(error) => {
+                                            fail_content(msg.msg_id, error);
+                                            continue;
+                                        }
+                                    };
+                                    match ServicePacket::decode(&body) {
                                         Ok(ServicePacket::MessageContainer { messages }) => {
                                             stack.extend(messages);
                                         }
-                                        Ok(packet) => handle_service(packet),
-                                        Err(_) => handle_content(msg.msg_id, msg.body),
+                                        _ => {
+                                            let mut message_info = packet_info.clone();
+                                            message_info.message_id = MessageId::from_u64(msg.msg_id);
+                                            message_info.seq_no = msg.seqno;
+                                            handle_message(&message_info, body);
+                                        }
                                     }
                                 }
                             }
-                            Ok(packet) => handle_service(packet),
-                            Err(_) => {
-                                handle_content(
-                                    packet_info.message_id.as_u64(),
-                                    data,
-                                );
-                            }
+                            _ => handle_message(&packet_info, data),
                         }
                     }
                     Ok((ReadResult::Nop, _)) => continue,
@@ -1294,16 +1735,7 @@ impl SessionConnection {
         query: &NetQuery,
         auth_data: &AuthDataShared,
     ) -> Result<(Vec<u8>, PacketInfo), ConnectionError> {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let message_id = MessageId::generate(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs_f64(),
-            true,
-            0,
-        );
+        let message_id = auth_data.next_message_id();
 
         let seq_no = auth_data.next_seq_no(true);
 
@@ -1320,6 +1752,41 @@ impl SessionConnection {
         Ok((query.query().to_vec(), packet_info))
     }
 
+    /// Static helper for unpacking a received message.
+    ///
+    /// A `gzip_packed` message is unpacked, as is the `gzip_packed` result
+    /// of an `rpc_result`, which keeps its envelope.
+    fn unpack_message(payload: Bytes) -> Result<Bytes, QueryError> {
+        let unpacked = match RpcResult::decode(&payload) {
+            Some(rpc_result) if gzip::is_gzip_packed(&rpc_result.result) => {
+                gzip::unpack(rpc_result.result).map(|result| {
+                    RpcResult {
+                        req_msg_id: rpc_result.req_msg_id,
+                        result,
+                    }
+                    .encode()
+                })
+            }
+            Some(_) => Ok(payload),
+            None => gzip::unpack(payload),
+        };
+        unpacked.map_err(|e| QueryError::Generic(format!("Failed to unpack message: {}", e)))
+    }
+
+    /// Static helper for building the packet info of a service message.
+    ///
+    /// Only messages which need an acknowledgement advance the sequence
+    /// number.
+    fn service_packet_info_impl(auth_data: &AuthDataShared, needs_ack: bool) -> PacketInfo {
+        PacketInfo::new()
+            .with_packet_type(PacketType::Common)
+            .with_salt(auth_data.server_salt())
+            .with_session_id(auth_data.session_id())
+            .with_message_id(auth_data.next_message_id())
+            .with_seq_no(auth_data.next_seq_no(needs_ack))
+            .with_version(2)
+    }
+
     /// Static helper for encrypting a packet.
     fn encrypt_packet_impl(
         packet: &[u8],
@@ -1372,6 +1839,114 @@ impl SessionConnection {
     }
 }
 
+/// Service message waiting to be sent by the network loop.
+#[derive(Debug)]
+struct ServiceMessage {
+    /// Serialized message
+    packet: Vec<u8>,
+
+    /// Whether the message is content-related and must be acknowledged
+    needs_ack: bool,
+
+    /// IDs of the messages a msgs_state_req asks about
+    state_request: Option<Vec<u64>>,
+}
+
+impl ServiceMessage {
+    /// Creates an answer to a service message of the server.
+    fn answer(packet: Vec<u8>) -> Self {
+        Self {
+            packet,
+            needs_ack: false,
+            state_request: None,
+        }
+    }
+
+    /// Creates a query to the server.
+    fn query(packet: Vec<u8>) -> Self {
+        Self {
+            packet,
+            needs_ack: true,
+            state_request: None,
+        }
+    }
+
+    /// Creates a msgs_state_req asking about the sent messages.
+    fn state_request(msg_ids: Vec<u64>) -> Self {
+        Self {
+            packet: encode_msgs_state_req(&msg_ids),
+            needs_ack: true,
+            state_request: Some(msg_ids),
+        }
+    }
+}
+
+/// Periodic checks of the network loop.
+///
+/// Based on TDLib's `SessionConnection::flush`, which asks for future salts
+/// before the current one expires and for the state of the queries which
+/// wait for an answer for too long.
+#[derive(Debug, Default)]
+struct ServiceChecks {
+    /// When future salts were requested last
+    future_salts_requested_at: Option<Instant>,
+
+    /// Sent messages whose state was already requested
+    asked_msg_ids: HashSet<u64>,
+}
+
+impl ServiceChecks {
+    /// Returns the service messages to send now.
+    fn check(
+        &mut self,
+        auth_data: &AuthDataShared,
+        active_queries: &HashMap<u64, NetQuery>,
+        service_handler: &ServicePacketHandler,
+    ) -> Vec<ServiceMessage> {
+        let mut messages = Vec::new();
+
+        let salts_requested_recently = self
+            .future_salts_requested_at
+            .is_some_and(|at| at.elapsed() < FUTURE_SALTS_RETRY_DELAY);
+        if auth_data.need_future_salts() && !salts_requested_recently {
+            self.future_salts_requested_at = Some(Instant::now());
+            messages.push(ServiceMessage::query(encode_get_future_salts(FUTURE_SALT_COUNT)));
+        }
+
+        // Message IDs contain the time they were sent at
+        self.asked_msg_ids
+            .retain(|msg_id| active_queries.contains_key(msg_id));
+        let sent_before = auth_data.server_time() - STATE_REQUEST_DELAY.as_secs_f64();
+        let mut msg_ids: Vec<u64> = active_queries
+            .keys()
+            .copied()
+            .filter(|&msg_id| {
+                MessageId::from_u64(msg_id).time() < sent_before
+                    && !self.asked_msg_ids.contains(&msg_id)
+            })
+            .collect();
+        if !msg_ids.is_empty() {
+            msg_ids.sort_unstable();
+            self.asked_msg_ids.extend(&msg_ids);
+            messages.push(ServiceMessage::state_request(msg_ids));
+        }
+
+        for session_id in service_handler.take_sessions_to_destroy() {
+            messages.push(ServiceMessage::query(encode_destroy_session(session_id)));
+        }
+
+        messages
+    }
+}
+
+/// Returns the current unix time in seconds.
+fn unix_time() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i32)
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1422,6 +1997,105 @@ mod tests {
         assert!(!conn.is_ready());
     }
 
+    #[test]
+    fn test_needs_temp_key_rotation() {
+        let config = SessionConnectionConfig::new(DcId::internal(2));
+        let auth_data = Arc::new(AuthDataShared::new(DcId::internal(2)));
+        let conn = SessionConnection::new(config, auth_data);
+        assert!(!conn.needs_temp_key_rotation());
+        assert!(conn.perm_auth_key().is_none());
+
+        let key = AuthKey::temporary(1, vec![0; 256], Instant::now());
+        conn.temp_auth_keys
+            .lock()
+            .set_temp_key(key.clone(), unix_time() + DEFAULT_TEMP_KEY_EXPIRES_IN);
+        assert!(!conn.needs_temp_key_rotation());
+
+        conn.temp_auth_keys.lock().set_temp_key(key, unix_time() + 60);
+        assert!(conn.needs_temp_key_rotation());
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_unpack_message() {
+        let object = vec![7u8; 4096];
+        let packed = Bytes::from(gzip::pack(&object).unwrap());
+        assert_eq!(
+            SessionConnection::unpack_message(packed.clone()).unwrap(),
+            Bytes::from(object.clone())
+        );
+
+        let rpc_result = RpcResult {
+            req_msg_id: 5,
+            result: packed,
+        };
+        let unpacked = SessionConnection::unpack_message(rpc_result.encode()).unwrap();
+        let unpacked = RpcResult::decode(&unpacked).unwrap();
+        assert_eq!(unpacked.req_msg_id, 5);
+        assert_eq!(unpacked.result, Bytes::from(object));
+
+        let plain = Bytes::from_static(&[1, 2, 3, 4]);
+        assert_eq!(SessionConnection::unpack_message(plain.clone()).unwrap(), plain);
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_corrupt_gzip_fails_query() {
+        let config = SessionConnectionConfig::new(DcId::internal(2));
+        let auth_data = Arc::new(AuthDataShared::new(DcId::internal(2)));
+        let conn = SessionConnection::new(config, auth_data);
+        let query = NetQuery::new(
+            1,
+            Bytes::new(),
+            DcId::internal(2),
+            crate::query::NetQueryType::Common,
+            crate::query::AuthFlag::On,
+            crate::query::GzipFlag::Off,
+            0,
+        );
+        conn.register_query(5, query.clone());
+
+        let mut corrupt = gzip::GZIP_PACKED_CONSTRUCTOR.to_le_bytes().to_vec();
+        corrupt.extend_from_slice(&[4, 1, 2, 3, 4, 0, 0, 0]);
+        let error = SessionConnection::unpack_message(Bytes::from(corrupt)).unwrap_err();
+        conn.fail_content_message(MessageId::from_u64(5), error);
+
+        assert!(query.is_error());
+        assert_eq!(conn.statistics().failed_queries, 1);
+    }
+
+    #[test]
+    fn test_service_checks() {
+        let auth_data = AuthDataShared::new(DcId::internal(2));
+        let handler = ServicePacketHandler::new();
+        let mut checks = ServiceChecks::default();
+
+        let query = NetQuery::new(
+            1,
+            Bytes::new(),
+            DcId::internal(2),
+            crate::query::NetQueryType::Common,
+            crate::query::AuthFlag::On,
+            crate::query::GzipFlag::Off,
+            0,
+        );
+        let old_msg_id = MessageId::generate(auth_data.server_time() - 30.0, true, 0).as_u64();
+        let new_msg_id = auth_data.next_message_id().as_u64();
+        let mut active_queries = HashMap::new();
+        active_queries.insert(old_msg_id, query.clone());
+        active_queries.insert(new_msg_id, query);
+
+        // Future salts are requested and the old query is checked
+        let messages = checks.check(&auth_data, &active_queries, &handler);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].packet, encode_get_future_salts(FUTURE_SALT_COUNT));
+        assert!(messages[0].needs_ack);
+        assert_eq!(messages[1].state_request, Some(vec![old_msg_id]));
+
+        // Nothing is requested twice
+        assert!(checks.check(&auth_data, &active_queries, &handler).is_empty());
+    }
+
     #[test]
     fn test_session_state_transitions() {
         let config = SessionConnectionConfig::new(DcId::internal(2));
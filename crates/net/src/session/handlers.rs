@@ -6,9 +6,32 @@
 //!
 //! This module implements handlers for processing different packet types.
 
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::auth::{local_time, AuthDataShared, ServerSalt};
 use crate::packet::PacketInfo;
 
-use super::packets::{PacketDecodeError, ServicePacket};
+use super::packets::{
+    encode_msg_resend_req, encode_msgs_ack, encode_msgs_state_info, PacketDecodeError,
+    ServicePacket,
+};
+
+/// Number of received message IDs remembered for msgs_state_req answers
+const MAX_RECEIVED_MESSAGE_IDS: usize = 1024;
+
+/// bad_msg_notification error codes, see <https://core.telegram.org/mtproto/service_messages_about_messages>
+const MSG_ID_TOO_LOW: i32 = 16;
+const MSG_ID_TOO_HIGH: i32 = 17;
+const MSG_TOO_OLD: i32 = 20;
+const MSG_SEQNO_TOO_LOW: i32 = 32;
+const MSG_SEQNO_TOO_HIGH: i32 = 33;
+const INCORRECT_SERVER_SALT: i32 = 48;
+
+/// Message states of msgs_state_info
+const MSG_STATE_NOT_RECEIVED: u8 = 2;
+const MSG_STATE_NOT_RECEIVED_TOO_OLD: u8 = 3;
+const MSG_STATE_RECEIVED: u8 = 4;
 
 /// Result of packet handling.
 #[derive(Debug, Clone)]
@@ -22,6 +45,10 @@ pub enum PacketHandlerResult {
     /// Packet was handled and a response should be sent
     Respond(Vec<u8>),
 
+    /// Packet was handled and the sent messages with these IDs should be
+    /// sent again with new message IDs
+    Resend(Vec<u64>),
+
     /// Packet handling failed
     Error(String),
 }
@@ -42,9 +69,26 @@ pub trait PacketHandler: Send + Sync {
 /// Service packet handler.
 ///
 /// Handles MTProto service packets like ping/pong, ack, etc.
+///
+/// With [`with_auth_data`](Self::with_auth_data) the handler also keeps the
+/// session in sync with the server: salts, the server time difference used
+/// for message IDs and the session itself are corrected from the service
+/// messages.
 pub struct ServicePacketHandler {
     /// Callback for pong packets
     pong_callback: parking_lot::Mutex<Box<dyn Fn(u64) + Send>>,
+
+    /// Auth data of the session
+    auth_data: Option<Arc<AuthDataShared>>,
+
+    /// IDs of the recently received messages
+    received_msg_ids: parking_lot::Mutex<ReceivedMessageIds>,
+
+    /// Sent msgs_state_req queries and the message IDs they ask about
+    state_requests: parking_lot::Mutex<HashMap<u64, Vec<u64>>>,
+
+    /// IDs of the replaced sessions the server may forget
+    sessions_to_destroy: parking_lot::Mutex<Vec<u64>>,
 }
 
 impl ServicePacketHandler {
@@ -52,9 +96,36 @@ impl ServicePacketHandler {
     pub fn new() -> Self {
         Self {
             pong_callback: parking_lot::Mutex::new(Box::new(|_| {})),
+            auth_data: None,
+            received_msg_ids: parking_lot::Mutex::new(ReceivedMessageIds::default()),
+            state_requests: parking_lot::Mutex::new(HashMap::new()),
+            sessions_to_destroy: parking_lot::Mutex::new(Vec::new()),
         }
     }
 
+    /// Creates a service packet handler updating the given auth data.
+    pub fn with_auth_data(auth_data: Arc<AuthDataShared>) -> Self {
+        Self {
+            auth_data: Some(auth_data),
+            ..Self::new()
+        }
+    }
+
+    /// Registers a sent msgs_state_req query.
+    ///
+    /// The messages the server doesn't know about are resent when the
+    /// msgs_state_info answer arrives.
+    pub fn register_state_request(&self, req_msg_id: u64, msg_ids: Vec<u64>) {
+        self.state_requests.lock().insert(req_msg_id, msg_ids);
+    }
+
+    /// Returns the IDs of the sessions replaced since the last call.
+    ///
+    /// They should be destroyed with `destroy_session` from the new session.
+    pub fn take_sessions_to_destroy(&self) -> Vec<u64> {
+        std::mem::take(&mut *self.sessions_to_destroy.lock())
+    }
+
     /// Sets the pong callback.
     pub fn set_pong_callback<F>(&self, callback: F)
     where
@@ -71,14 +142,20 @@ impl Default for ServicePacketHandler {
 }
 
 impl PacketHandler for ServicePacketHandler {
-    fn handle(&self, packet: &[u8], _packet_info: &PacketInfo) -> PacketHandlerResult {
+    fn handle(&self, packet: &[u8], packet_info: &PacketInfo) -> PacketHandlerResult {
+        if !packet_info.message_id.is_empty() {
+            self.received_msg_ids
+                .lock()
+                .insert(packet_info.message_id.as_u64());
+        }
+
         match ServicePacket::decode(packet) {
             Ok(ServicePacket::Unknown(constructor)) => {
                 // Unknown service packet, pass to next handler
                 tracing::warn!("Unknown service packet constructor: 0x{:08x}", constructor);
                 PacketHandlerResult::Pass
             }
-            Ok(service_packet) => self.handle_service_packet(service_packet),
+            Ok(service_packet) => self.handle_service_packet(service_packet, packet_info),
             Err(PacketDecodeError::UnknownConstructor(_)) => {
                 // Not a service packet, pass to next handler
                 PacketHandlerResult::Pass
@@ -93,7 +170,11 @@ impl PacketHandler for ServicePacketHandler {
 }
 
 impl ServicePacketHandler {
-    fn handle_service_packet(&self, packet: ServicePacket) -> PacketHandlerResult {
+    fn handle_service_packet(
+        &self,
+        packet: ServicePacket,
+        packet_info: &PacketInfo,
+    ) -> PacketHandlerResult {
         match packet {
             ServicePacket::Pong(ping_id) => {
                 (self.pong_callback.lock())(ping_id);
@@ -115,19 +196,18 @@ impl ServicePacketHandler {
                     server_salt,
                     session_id
                 );
+                if let Some(auth_data) = &self.auth_data {
+                    auth_data.set_server_salt(server_salt);
+                }
                 PacketHandlerResult::Handled
             }
             ServicePacket::BadMsgNotification {
                 bad_msg_id,
+                bad_msg_seqno,
                 error_code,
                 ..
             } => {
-                tracing::warn!(
-                    "Bad message notification: msg_id={}, error_code={}",
-                    bad_msg_id,
-                    error_code
-                );
-                PacketHandlerResult::Handled
+                self.handle_bad_msg_notification(bad_msg_id, bad_msg_seqno, error_code, packet_info)
             }
             ServicePacket::BadServerSalt {
                 bad_msg_id,
@@ -139,17 +219,67 @@ impl ServicePacketHandler {
                     bad_msg_id,
                     new_server_salt
                 );
-                // Should trigger salt update
-                PacketHandlerResult::Handled
+                if let Some(auth_data) = &self.auth_data {
+                    auth_data.set_server_salt(new_server_salt);
+                }
+                PacketHandlerResult::Resend(vec![bad_msg_id])
             }
             ServicePacket::MsgResendReq { msg_ids } => {
                 tracing::debug!("Message resend request: {:?} messages", msg_ids.len());
-                PacketHandlerResult::Handled
+                PacketHandlerResult::Resend(msg_ids)
             }
             ServicePacket::MsgsAck { .. } => {
                 // We should process these
                 PacketHandlerResult::Handled
             }
+            ServicePacket::FutureSalts { now, salts, .. } => {
+                tracing::debug!("Received {} future salts", salts.len());
+                if let Some(auth_data) = &self.auth_data {
+                    auth_data.update_server_time_difference(f64::from(now) - local_time(), false);
+                    auth_data.set_future_salts(
+                        salts
+                            .iter()
+                            .map(|s| ServerSalt::new(s.salt as i64, i64::from(s.valid_since)))
+                            .collect(),
+                    );
+                }
+                PacketHandlerResult::Handled
+            }
+            ServicePacket::DestroySessionOk { session_id } => {
+                tracing::debug!("Session {} was destroyed", session_id);
+                PacketHandlerResult::Handled
+            }
+            ServicePacket::DestroySessionNone { session_id } => {
+                tracing::debug!("Session {} to destroy doesn't exist", session_id);
+                PacketHandlerResult::Handled
+            }
+            ServicePacket::MsgsStateReq { msg_ids } => {
+                let received = self.received_msg_ids.lock();
+                let info: Vec<u8> = msg_ids.iter().map(|&id| received.state(id)).collect();
+                PacketHandlerResult::Respond(encode_msgs_state_info(
+                    packet_info.message_id.as_u64(),
+                    &info,
+                ))
+            }
+            ServicePacket::MsgsStateInfo { req_msg_id, info } => {
+                let Some(msg_ids) = self.state_requests.lock().remove(&req_msg_id) else {
+                    tracing::debug!("Unexpected msgs_state_info for {}", req_msg_id);
+                    return PacketHandlerResult::Handled;
+                };
+                Self::resend_unknown_messages(&msg_ids, &info)
+            }
+            ServicePacket::MsgsAllInfo { msg_ids, info } => {
+                Self::resend_unknown_messages(&msg_ids, &info)
+            }
+            ServicePacket::MsgDetailedInfo { answer_msg_id, .. }
+            | ServicePacket::MsgNewDetailedInfo { answer_msg_id, .. } => {
+                // The answer is acknowledged if it arrived, or requested again
+                if self.received_msg_ids.lock().contains(answer_msg_id) {
+                    PacketHandlerResult::Respond(encode_msgs_ack(&[answer_msg_id]))
+                } else {
+                    PacketHandlerResult::Respond(encode_msg_resend_req(&[answer_msg_id]))
+                }
+            }
             ServicePacket::MessageContainer { messages } => {
                 tracing::trace!(
                     "Received message container with {} messages",
@@ -164,6 +294,108 @@ impl ServicePacketHandler {
             }
         }
     }
+
+    /// Handles a bad_msg_notification.
+    ///
+    /// Based on TDLib's `SessionConnection::on_packet(bad_msg_notification)`.
+    fn handle_bad_msg_notification(
+        &self,
+        bad_msg_id: u64,
+        bad_msg_seqno: i32,
+        error_code: i32,
+        packet_info: &PacketInfo,
+    ) -> PacketHandlerResult {
+        tracing::warn!(
+            "Bad message notification: msg_id={}, seqno={}, error_code={}",
+            bad_msg_id,
+            bad_msg_seqno,
+            error_code
+        );
+
+        match error_code {
+            MSG_ID_TOO_LOW | MSG_ID_TOO_HIGH => {
+                // Our clock is off; the ID of the notification carries the server time
+                if let Some(auth_data) = &self.auth_data {
+                    if !packet_info.message_id.is_empty() {
+                        let server_time = packet_info.message_id.time();
+                        auth_data.update_server_time_difference(server_time - local_time(), true);
+                    }
+                }
+                PacketHandlerResult::Resend(vec![bad_msg_id])
+            }
+            MSG_SEQNO_TOO_LOW | MSG_SEQNO_TOO_HIGH => {
+                // Sequence numbers can't be fixed within the session
+                if let Some(auth_data) = &self.auth_data {
+                    let old_session_id = auth_data.session_id();
+                    auth_data.reset_session();
+                    self.sessions_to_destroy.lock().push(old_session_id);
+                }
+                PacketHandlerResult::Resend(vec![bad_msg_id])
+            }
+            MSG_TOO_OLD | INCORRECT_SERVER_SALT => PacketHandlerResult::Resend(vec![bad_msg_id]),
+            _ => PacketHandlerResult::Error(format!(
+                "Message {} was rejected with error code {}",
+                bad_msg_id, error_code
+            )),
+        }
+    }
+
+    /// Resends the messages which the server didn't receive.
+    fn resend_unknown_messages(msg_ids: &[u64], info: &[u8]) -> PacketHandlerResult {
+        let msg_ids: Vec<u64> = msg_ids
+            .iter()
+            .zip(info)
+            .filter(|(_, &state)| state & 7 != MSG_STATE_RECEIVED)
+            .map(|(&msg_id, _)| msg_id)
+            .collect();
+        if msg_ids.is_empty() {
+            PacketHandlerResult::Handled
+        } else {
+            PacketHandlerResult::Resend(msg_ids)
+        }
+    }
+}
+
+/// Bounded set of the recently received message IDs.
+#[derive(Debug, Default)]
+struct ReceivedMessageIds {
+    /// IDs in the order of arrival
+    order: VecDeque<u64>,
+
+    /// The same IDs for lookup
+    ids: HashSet<u64>,
+}
+
+impl ReceivedMessageIds {
+    fn insert(&mut self, msg_id: u64) {
+        if !self.ids.insert(msg_id) {
+            return;
+        }
+        self.order.push_back(msg_id);
+        if self.order.len() > MAX_RECEIVED_MESSAGE_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, msg_id: u64) -> bool {
+        self.ids.contains(&msg_id)
+    }
+
+    /// Returns the msgs_state_info state of the message.
+    fn state(&self, msg_id: u64) -> u8 {
+        if self.contains(msg_id) {
+            MSG_STATE_RECEIVED
+        } else if self.ids.iter().min().is_some_and(|&oldest| msg_id < oldest)
+            && self.order.len() == MAX_RECEIVED_MESSAGE_IDS
+        {
+            // Older than anything remembered
+            MSG_STATE_NOT_RECEIVED_TOO_OLD
+        } else {
+            MSG_STATE_NOT_RECEIVED
+        }
+    }
 }
 
 /// Chain of packet handlers.
@@ -207,7 +439,9 @@ impl PacketHandlerChain {
                     }
                 }
                 PacketHandlerResult::Pass => continue,
-                PacketHandlerResult::Respond(_) | PacketHandlerResult::Error(_) => return result,
+                PacketHandlerResult::Respond(_)
+                | PacketHandlerResult::Resend(_)
+                | PacketHandlerResult::Error(_) => return result,
             }
         }
 
@@ -229,6 +463,32 @@ impl Default for PacketHandlerChain {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dc::DcId;
+    use crate::packet::MessageId;
+    use crate::session::packets::encode_msgs_state_req;
+
+    fn bad_msg_notification(bad_msg_id: u64, error_code: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0xa7eff811u32.to_le_bytes());
+        data.extend_from_slice(&bad_msg_id.to_le_bytes());
+        data.extend_from_slice(&2i32.to_le_bytes());
+        data.extend_from_slice(&error_code.to_le_bytes());
+        data
+    }
+
+    fn packet_info(server_time: f64) -> PacketInfo {
+        let mut packet_info = PacketInfo::new();
+        packet_info.message_id = MessageId::generate(server_time, false, 0);
+        packet_info
+    }
+
+    fn handler_with_auth_data() -> (ServicePacketHandler, Arc<AuthDataShared>) {
+        let auth_data = Arc::new(AuthDataShared::new(DcId::internal(2)));
+        (
+            ServicePacketHandler::with_auth_data(auth_data.clone()),
+            auth_data,
+        )
+    }
 
     #[test]
     fn test_packet_handler_result_variants() {
@@ -317,4 +577,244 @@ mod tests {
 
         assert!(matches!(result, PacketHandlerResult::Handled));
     }
+
+    #[test]
+    fn test_bad_msg_id_corrects_server_time() {
+        for error_code in [MSG_ID_TOO_LOW, MSG_ID_TOO_HIGH] {
+            let (handler, auth_data) = handler_with_auth_data();
+            let server_time = local_time() + 1000.0;
+
+            let result = handler.handle(
+                &bad_msg_notification(100, error_code),
+                &packet_info(server_time),
+            );
+
+            assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![100]));
+            assert!((auth_data.server_time_difference() - 1000.0).abs() < 5.0);
+            assert!((auth_data.next_message_id().time() - server_time).abs() < 5.0);
+        }
+    }
+
+    #[test]
+    fn test_bad_msg_seqno_resets_session() {
+        for error_code in [MSG_SEQNO_TOO_LOW, MSG_SEQNO_TOO_HIGH] {
+            let (handler, auth_data) = handler_with_auth_data();
+            let session_id = auth_data.session_id();
+            auth_data.next_seq_no(true);
+
+            let result = handler.handle(&bad_msg_notification(100, error_code), &PacketInfo::new());
+
+            assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![100]));
+            assert_ne!(auth_data.session_id(), session_id);
+            assert_eq!(auth_data.next_seq_no(false), 0);
+
+            // The old session is destroyed once
+            assert_eq!(handler.take_sessions_to_destroy(), vec![session_id]);
+            assert!(handler.take_sessions_to_destroy().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_bad_msg_incorrect_salt_resends() {
+        let (handler, _) = handler_with_auth_data();
+
+        let result = handler.handle(
+            &bad_msg_notification(100, INCORRECT_SERVER_SALT),
+            &PacketInfo::new(),
+        );
+
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![100]));
+    }
+
+    #[test]
+    fn test_bad_msg_other_code_fails() {
+        let (handler, _) = handler_with_auth_data();
+
+        let result = handler.handle(&bad_msg_notification(100, 64), &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Error(_)));
+    }
+
+    #[test]
+    fn test_bad_server_salt_updates_salt() {
+        let (handler, auth_data) = handler_with_auth_data();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0xedab447bu32.to_le_bytes());
+        data.extend_from_slice(&100u64.to_le_bytes());
+        data.extend_from_slice(&2i32.to_le_bytes());
+        data.extend_from_slice(&48i32.to_le_bytes());
+        data.extend_from_slice(&777u64.to_le_bytes());
+
+        let result = handler.handle(&data, &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![100]));
+        assert_eq!(auth_data.get_server_salt(), Some(777));
+    }
+
+    #[test]
+    fn test_new_session_created_updates_salt() {
+        let (handler, auth_data) = handler_with_auth_data();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x9ec20908u32.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&555u64.to_le_bytes());
+        data.extend_from_slice(&3u64.to_le_bytes());
+
+        let result = handler.handle(&data, &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Handled));
+        assert_eq!(auth_data.get_server_salt(), Some(555));
+    }
+
+    #[test]
+    fn test_msg_resend_req_resends() {
+        let handler = ServicePacketHandler::new();
+
+        let result = handler.handle(&encode_msg_resend_req(&[4, 8]), &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![4, 8]));
+    }
+
+    #[test]
+    fn test_future_salts_updates_auth_data() {
+        let (handler, auth_data) = handler_with_auth_data();
+        let now = local_time() as i32 + 100;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0xae500895u32.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&now.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        for (since, salt) in [(now - 10, 11u64), (now + 1800, 12)] {
+            data.extend_from_slice(&since.to_le_bytes());
+            data.extend_from_slice(&(since + 1800).to_le_bytes());
+            data.extend_from_slice(&salt.to_le_bytes());
+        }
+
+        let result = handler.handle(&data, &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Handled));
+        assert_eq!(auth_data.get_future_salts().len(), 2);
+        assert_eq!(auth_data.get_server_salt(), Some(11));
+        assert!((auth_data.server_time_difference() - 100.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn test_destroy_session_handled() {
+        let handler = ServicePacketHandler::new();
+
+        for constructor in [0xe22045fcu32, 0x62d350c9] {
+            let mut data = Vec::new();
+            data.extend_from_slice(&constructor.to_le_bytes());
+            data.extend_from_slice(&42u64.to_le_bytes());
+
+            let result = handler.handle(&data, &PacketInfo::new());
+            assert!(matches!(result, PacketHandlerResult::Handled));
+        }
+    }
+
+    #[test]
+    fn test_msgs_state_req_answers_states() {
+        let handler = ServicePacketHandler::new();
+
+        let received = packet_info(1704067200.0);
+        handler.handle(&[0xFF, 0xFF, 0xFF, 0xFF], &received);
+        let received_id = received.message_id.as_u64();
+
+        let request = packet_info(1704067201.0);
+        let result = handler.handle(&encode_msgs_state_req(&[received_id, 12345]), &request);
+
+        match result {
+            PacketHandlerResult::Respond(data) => match ServicePacket::decode(&data) {
+                Ok(ServicePacket::MsgsStateInfo { req_msg_id, info }) => {
+                    assert_eq!(req_msg_id, request.message_id.as_u64());
+                    assert_eq!(info, vec![MSG_STATE_RECEIVED, MSG_STATE_NOT_RECEIVED]);
+                }
+                _ => panic!("Expected msgs_state_info"),
+            },
+            _ => panic!("Expected Respond"),
+        }
+    }
+
+    #[test]
+    fn test_msgs_state_info_resends_unknown() {
+        let handler = ServicePacketHandler::new();
+        handler.register_state_request(50, vec![4, 8, 12]);
+
+        let data = encode_msgs_state_info(50, &[4, 2, 1 | 8]);
+
+        let result = handler.handle(&data, &PacketInfo::new());
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![8, 12]));
+
+        // The request is answered only once
+        let result = handler.handle(&data, &PacketInfo::new());
+        assert!(matches!(result, PacketHandlerResult::Handled));
+    }
+
+    #[test]
+    fn test_msgs_all_info_resends_unknown() {
+        let handler = ServicePacketHandler::new();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x8cc0d131u32.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(&8u64.to_le_bytes());
+        data.extend_from_slice(&[2, 4, 2, 0]); // TL string [4, 2]
+
+        let result = handler.handle(&data, &PacketInfo::new());
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![8]));
+    }
+
+    #[test]
+    fn test_msg_detailed_info_acks_received_answer() {
+        let handler = ServicePacketHandler::new();
+        let answer = packet_info(1704067200.0);
+        handler.handle(&[0xFF, 0xFF, 0xFF, 0xFF], &answer);
+        let answer_msg_id = answer.message_id.as_u64();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x276d3ec6u32.to_le_bytes());
+        data.extend_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(&answer_msg_id.to_le_bytes());
+        data.extend_from_slice(&100i32.to_le_bytes());
+        data.extend_from_slice(&0i32.to_le_bytes());
+
+        let result = handler.handle(&data, &PacketInfo::new());
+        match result {
+            PacketHandlerResult::Respond(data) => {
+                assert_eq!(data, encode_msgs_ack(&[answer_msg_id]))
+            }
+            _ => panic!("Expected Respond"),
+        }
+    }
+
+    #[test]
+    fn test_msg_new_detailed_info_requests_missing_answer() {
+        let handler = ServicePacketHandler::new();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x809db6dfu32.to_le_bytes());
+        data.extend_from_slice(&9u64.to_le_bytes());
+        data.extend_from_slice(&100i32.to_le_bytes());
+        data.extend_from_slice(&0i32.to_le_bytes());
+
+        let result = handler.handle(&data, &PacketInfo::new());
+        match result {
+            PacketHandlerResult::Respond(data) => assert_eq!(data, encode_msg_resend_req(&[9])),
+            _ => panic!("Expected Respond"),
+        }
+    }
+
+    #[test]
+    fn test_packet_handler_chain_returns_resend() {
+        let chain = PacketHandlerChain::new();
+        chain.add_handler(Box::new(ServicePacketHandler::new()));
+
+        let result = chain.process(&encode_msg_resend_req(&[4]), &PacketInfo::new());
+
+        assert!(matches!(result, PacketHandlerResult::Resend(ids) if ids == vec![4]));
+    }
 }
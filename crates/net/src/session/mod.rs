@@ -14,7 +14,7 @@ mod query;
 
 pub use connection::{SessionConnection, SessionConnectionConfig, SessionEvent};
 pub use handlers::{PacketHandler, PacketHandlerResult, ServicePacketHandler};
-pub use packets::{ContainerDecoder, MessageContainer, ServicePacket};
+pub use packets::{ContainerDecoder, FutureSalt, MessageContainer, RpcResult, ServicePacket};
 pub use ping::{PingConfig, PingManager};
 pub use query::{QueryLifecycle, QueryState};
 
@@ -68,6 +68,72 @@ pub enum ServicePacket {
         session_id: u64,
     },
 
+    /// Salts for the next time periods
+    FutureSalts {
+        /// ID of the get_future_salts query
+        req_msg_id: u64,
+        /// Server time
+        now: i32,
+        /// The salts, ordered by validity
+        salts: Vec<FutureSalt>,
+    },
+
+    /// Session was destroyed by destroy_session
+    DestroySessionOk {
+        /// ID of the destroyed session
+        session_id: u64,
+    },
+
+    /// Session to destroy didn't exist
+    DestroySessionNone {
+        /// ID of the session
+        session_id: u64,
+    },
+
+    /// Request for the state of messages
+    MsgsStateReq {
+        /// IDs of the messages
+        msg_ids: Vec<u64>,
+    },
+
+    /// State of the messages from a msgs_state_req
+    MsgsStateInfo {
+        /// ID of the msgs_state_req
+        req_msg_id: u64,
+        /// One state byte per requested message
+        info: Vec<u8>,
+    },
+
+    /// Unsolicited state of messages
+    MsgsAllInfo {
+        /// IDs of the messages
+        msg_ids: Vec<u64>,
+        /// One state byte per message
+        info: Vec<u8>,
+    },
+
+    /// Answer to a message was already sent
+    MsgDetailedInfo {
+        /// ID of the message
+        msg_id: u64,
+        /// ID of the answer
+        answer_msg_id: u64,
+        /// Size of the answer
+        bytes: i32,
+        /// Status of the answer
+        status: i32,
+    },
+
+    /// Answer to a message that was already answered
+    MsgNewDetailedInfo {
+        /// ID of the answer
+        answer_msg_id: u64,
+        /// Size of the answer
+        bytes: i32,
+        /// Status of the answer
+        status: i32,
+    },
+
     /// Container with messages
     MessageContainer {
         /// Messages in the container
@@ -78,6 +144,19 @@ pub enum ServicePacket {
     Unknown(u32),
 }
 
+/// Server salt for a time period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FutureSalt {
+    /// Start of the period
+    pub valid_since: i32,
+
+    /// End of the period
+    pub valid_until: i32,
+
+    /// The salt
+    pub salt: u64,
+}
+
 /// Message in a container.
 #[derive(Debug, Clone)]
 pub struct ContainerMessage {
@@ -120,6 +199,7 @@ impl fmt::Display for PacketDecodeError {
 impl std::error::Error for PacketDecodeError {}
 
 // TL constructors
+const RPC_RESULT_CONSTRUCTOR: u32 = 0xf35c6d01;
 const RPC_ERROR_CONSTRUCTOR: u32 = 0x2144ca19;
 const BAD_MSG_NOTIFICATION_CONSTRUCTOR: u32 = 0xa7eff811;
 const BAD_SERVER_SALT_CONSTRUCTOR: u32 = 0xedab447b;
@@ -130,6 +210,17 @@ const PONG_CONSTRUCTOR: u32 = 0x2b0f7de3;
 const PING_DELAY_DISCONNECT_CONSTRUCTOR: u32 = 0x34a27b63;
 const NEW_SESSION_CREATED_CONSTRUCTOR: u32 = 0x9ec20908;
 const MSG_CONTAINER_CONSTRUCTOR: u32 = 0x73f1f8dc;
+const FUTURE_SALTS_CONSTRUCTOR: u32 = 0xae500895;
+const GET_FUTURE_SALTS_CONSTRUCTOR: u32 = 0xb921bd04;
+const DESTROY_SESSION_CONSTRUCTOR: u32 = 0xe7512126;
+const DESTROY_SESSION_OK_CONSTRUCTOR: u32 = 0xe22045fc;
+const DESTROY_SESSION_NONE_CONSTRUCTOR: u32 = 0x62d350c9;
+const MSGS_STATE_REQ_CONSTRUCTOR: u32 = 0xda69fb52;
+const MSGS_STATE_INFO_CONSTRUCTOR: u32 = 0x04deb57d;
+const MSGS_ALL_INFO_CONSTRUCTOR: u32 = 0x8cc0d131;
+const MSG_DETAILED_INFO_CONSTRUCTOR: u32 = 0x276d3ec6;
+const MSG_NEW_DETAILED_INFO_CONSTRUCTOR: u32 = 0x809db6df;
+const VECTOR_CONSTRUCTOR: u32 = 0x1cb5c415;
 
 impl ServicePacket {
     /// Decodes a service packet from bytes.
@@ -150,6 +241,35 @@ impl ServicePacket {
             PONG_CONSTRUCTOR => Self::decode_pong(&mut cursor),
             NEW_SESSION_CREATED_CONSTRUCTOR => Self::decode_new_session_created(&mut cursor),
             MSG_CONTAINER_CONSTRUCTOR => Self::decode_msg_container(&mut cursor),
+            FUTURE_SALTS_CONSTRUCTOR => Self::decode_future_salts(&mut cursor),
+            DESTROY_SESSION_OK_CONSTRUCTOR => Ok(ServicePacket::DestroySessionOk {
+                session_id: read_u64(&mut cursor)?,
+            }),
+            DESTROY_SESSION_NONE_CONSTRUCTOR => Ok(ServicePacket::DestroySessionNone {
+                session_id: read_u64(&mut cursor)?,
+            }),
+            MSGS_STATE_REQ_CONSTRUCTOR => Ok(ServicePacket::MsgsStateReq {
+                msg_ids: read_msg_ids(&mut cursor)?,
+            }),
+            MSGS_STATE_INFO_CONSTRUCTOR => Ok(ServicePacket::MsgsStateInfo {
+                req_msg_id: read_u64(&mut cursor)?,
+                info: read_tl_bytes(&mut cursor)?,
+            }),
+            MSGS_ALL_INFO_CONSTRUCTOR => Ok(ServicePacket::MsgsAllInfo {
+                msg_ids: read_msg_ids(&mut cursor)?,
+                info: read_tl_bytes(&mut cursor)?,
+            }),
+            MSG_DETAILED_INFO_CONSTRUCTOR => Ok(ServicePacket::MsgDetailedInfo {
+                msg_id: read_u64(&mut cursor)?,
+                answer_msg_id: read_u64(&mut cursor)?,
+                bytes: read_i32(&mut cursor)?,
+                status: read_i32(&mut cursor)?,
+            }),
+            MSG_NEW_DETAILED_INFO_CONSTRUCTOR => Ok(ServicePacket::MsgNewDetailedInfo {
+                answer_msg_id: read_u64(&mut cursor)?,
+                bytes: read_i32(&mut cursor)?,
+                status: read_i32(&mut cursor)?,
+            }),
             _ => Ok(ServicePacket::Unknown(constructor)),
         }
     }
@@ -173,7 +293,7 @@ impl ServicePacket {
     }
 
     fn decode_bad_server_salt(cursor: &mut Bytes) -> Result<Self, PacketDecodeError> {
-        if cursor.remaining() < 28 {
+        if cursor.remaining() < 24 {
             return Err(PacketDecodeError::BufferTooSmall);
         }
 
@@ -191,39 +311,37 @@ impl ServicePacket {
     }
 
     fn decode_msgs_ack(cursor: &mut Bytes) -> Result<Self, PacketDecodeError> {
-        if cursor.remaining() < 4 {
-            return Err(PacketDecodeError::BufferTooSmall);
-        }
-
-        let count = cursor.get_u32_le() as usize;
-        let mut msg_ids = Vec::with_capacity(count);
-
-        for _ in 0..count {
-            if cursor.remaining() < 8 {
-                return Err(PacketDecodeError::BufferTooSmall);
-            }
-            msg_ids.push(cursor.get_u64_le());
-        }
-
+        let msg_ids = read_msg_ids(cursor)?;
         Ok(ServicePacket::Ack { msg_ids })
     }
 
     fn decode_msg_resend_req(cursor: &mut Bytes) -> Result<Self, PacketDecodeError> {
-        if cursor.remaining() < 4 {
-            return Err(PacketDecodeError::BufferTooSmall);
-        }
+        let msg_ids = read_msg_ids(cursor)?;
+        Ok(ServicePacket::MsgResendReq { msg_ids })
+    }
 
-        let count = cursor.get_u32_le() as usize;
-        let mut msg_ids = Vec::with_capacity(count);
+    fn decode_future_salts(cursor: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let req_msg_id = read_u64(cursor)?;
+        let now = read_i32(cursor)?;
 
-        for _ in 0..count {
-            if cursor.remaining() < 8 {
-                return Err(PacketDecodeError::BufferTooSmall);
-            }
-            msg_ids.push(cursor.get_u64_le());
+        // The salts are a bare vector of future_salt
+        let count = read_u32(cursor)? as usize;
+        if cursor.remaining() < count.saturating_mul(16) {
+            return Err(PacketDecodeError::BufferTooSmall);
         }
-
-        Ok(ServicePacket::MsgResendReq { msg_ids })
+        let salts = (0..count)
+            .map(|_| FutureSalt {
+                valid_since: cursor.get_i32_le(),
+                valid_until: cursor.get_i32_le(),
+                salt: cursor.get_u64_le(),
+            })
+            .collect();
+
+        Ok(ServicePacket::FutureSalts {
+            req_msg_id,
+            now,
+            salts,
+        })
     }
 
     fn decode_pong(cursor: &mut Bytes) -> Result<Self, PacketDecodeError> {
@@ -286,6 +404,162 @@ impl ServicePacket {
     }
 }
 
+/// Answer to a query.
+#[derive(Debug, Clone)]
+pub struct RpcResult {
+    /// ID of the query message
+    pub req_msg_id: u64,
+
+    /// The serialized result, possibly gzip_packed or rpc_error
+    pub result: Bytes,
+}
+
+impl RpcResult {
+    /// Decodes an rpc_result, returning `None` for other messages.
+    pub fn decode(data: &Bytes) -> Option<Self> {
+        if data.len() < 12 || data[..4] != RPC_RESULT_CONSTRUCTOR.to_le_bytes() {
+            return None;
+        }
+        let mut cursor = data.slice(4..);
+        let req_msg_id = cursor.get_u64_le();
+        Some(Self {
+            req_msg_id,
+            result: cursor,
+        })
+    }
+
+    /// Encodes the rpc_result.
+    pub fn encode(&self) -> Bytes {
+        let mut data = Vec::with_capacity(12 + self.result.len());
+        data.extend_from_slice(&RPC_RESULT_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&self.req_msg_id.to_le_bytes());
+        data.extend_from_slice(&self.result);
+        Bytes::from(data)
+    }
+}
+
+/// Encodes a msgs_ack service message.
+pub fn encode_msgs_ack(msg_ids: &[u64]) -> Vec<u8> {
+    encode_msg_ids(MSGS_ACK_CONSTRUCTOR, msg_ids)
+}
+
+/// Encodes a msg_resend_req service message.
+pub fn encode_msg_resend_req(msg_ids: &[u64]) -> Vec<u8> {
+    encode_msg_ids(MSG_RESEND_REQ_CONSTRUCTOR, msg_ids)
+}
+
+/// Encodes a msgs_state_req service message.
+pub fn encode_msgs_state_req(msg_ids: &[u64]) -> Vec<u8> {
+    encode_msg_ids(MSGS_STATE_REQ_CONSTRUCTOR, msg_ids)
+}
+
+/// Encodes a msgs_state_info answer to the msgs_state_req `req_msg_id`.
+pub fn encode_msgs_state_info(req_msg_id: u64, info: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 + info.len());
+    data.extend_from_slice(&MSGS_STATE_INFO_CONSTRUCTOR.to_le_bytes());
+    data.extend_from_slice(&req_msg_id.to_le_bytes());
+    write_tl_bytes(&mut data, info);
+    data
+}
+
+/// Encodes a get_future_salts query for `num` salts.
+pub fn encode_get_future_salts(num: i32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8);
+    data.extend_from_slice(&GET_FUTURE_SALTS_CONSTRUCTOR.to_le_bytes());
+    data.extend_from_slice(&num.to_le_bytes());
+    data
+}
+
+/// Encodes a destroy_session query.
+pub fn encode_destroy_session(session_id: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12);
+    data.extend_from_slice(&DESTROY_SESSION_CONSTRUCTOR.to_le_bytes());
+    data.extend_from_slice(&session_id.to_le_bytes());
+    data
+}
+
+fn encode_msg_ids(constructor: u32, msg_ids: &[u64]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12 + msg_ids.len() * 8);
+    data.extend_from_slice(&constructor.to_le_bytes());
+    data.extend_from_slice(&VECTOR_CONSTRUCTOR.to_le_bytes());
+    data.extend_from_slice(&(msg_ids.len() as u32).to_le_bytes());
+    for msg_id in msg_ids {
+        data.extend_from_slice(&msg_id.to_le_bytes());
+    }
+    data
+}
+
+fn read_u32(cursor: &mut Bytes) -> Result<u32, PacketDecodeError> {
+    if cursor.remaining() < 4 {
+        return Err(PacketDecodeError::BufferTooSmall);
+    }
+    Ok(cursor.get_u32_le())
+}
+
+fn read_i32(cursor: &mut Bytes) -> Result<i32, PacketDecodeError> {
+    read_u32(cursor).map(|value| value as i32)
+}
+
+fn read_u64(cursor: &mut Bytes) -> Result<u64, PacketDecodeError> {
+    if cursor.remaining() < 8 {
+        return Err(PacketDecodeError::BufferTooSmall);
+    }
+    Ok(cursor.get_u64_le())
+}
+
+/// Reads a vector of message IDs, boxed or bare.
+fn read_msg_ids(cursor: &mut Bytes) -> Result<Vec<u64>, PacketDecodeError> {
+    let mut count = read_u32(cursor)?;
+    if count == VECTOR_CONSTRUCTOR {
+        count = read_u32(cursor)?;
+    }
+    let count = count as usize;
+    if cursor.remaining() < count.saturating_mul(8) {
+        return Err(PacketDecodeError::BufferTooSmall);
+    }
+    Ok((0..count).map(|_| cursor.get_u64_le()).collect())
+}
+
+/// Reads a TL string.
+fn read_tl_bytes(cursor: &mut Bytes) -> Result<Vec<u8>, PacketDecodeError> {
+    if !cursor.has_remaining() {
+        return Err(PacketDecodeError::BufferTooSmall);
+    }
+    let (len, header) = match cursor.get_u8() {
+        254 => {
+            if cursor.remaining() < 3 {
+                return Err(PacketDecodeError::BufferTooSmall);
+            }
+            let len = cursor.get_uint_le(3) as usize;
+            (len, 4)
+        }
+        255 => return Err(PacketDecodeError::InvalidFormat),
+        len => (len as usize, 1),
+    };
+    let padding = (4 - (header + len) % 4) % 4;
+    if cursor.remaining() < len + padding {
+        return Err(PacketDecodeError::BufferTooSmall);
+    }
+    let data = cursor.copy_to_bytes(len).to_vec();
+    cursor.advance(padding);
+    Ok(data)
+}
+
+/// Writes a TL string.
+fn write_tl_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    let header = if bytes.len() < 254 {
+        data.push(bytes.len() as u8);
+        1
+    } else {
+        data.push(254);
+        data.extend_from_slice(&(bytes.len() as u32).to_le_bytes()[..3]);
+        4
+    };
+    data.extend_from_slice(bytes);
+    let padding = (4 - (header + bytes.len()) % 4) % 4;
+    data.extend(std::iter::repeat(0).take(padding));
+}
+
 /// Message container decoder.
 ///
 /// Decodes msg_container TL type.
@@ -435,4 +709,191 @@ mod tests {
             Err(_) => panic!("Expected Ok packet"),
         }
     }
+
+    #[test]
+    fn test_decode_ack_boxed_vector() {
+        let data = encode_msgs_ack(&[5, 6]);
+
+        match ServicePacket::decode(&data) {
+            Ok(ServicePacket::Ack { msg_ids }) => assert_eq!(msg_ids, vec![5, 6]),
+            _ => panic!("Expected Ack packet"),
+        }
+    }
+
+    #[test]
+    fn test_decode_future_salts() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&FUTURE_SALTS_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes()); // req_msg_id
+        data.extend_from_slice(&1000i32.to_le_bytes()); // now
+        data.extend_from_slice(&2u32.to_le_bytes()); // count
+        for (since, salt) in [(900i32, 11u64), (2700, 12)] {
+            data.extend_from_slice(&since.to_le_bytes());
+            data.extend_from_slice(&(since + 1800).to_le_bytes());
+            data.extend_from_slice(&salt.to_le_bytes());
+        }
+
+        match ServicePacket::decode(&data) {
+            Ok(ServicePacket::FutureSalts {
+                req_msg_id,
+                now,
+                salts,
+            }) => {
+                assert_eq!(req_msg_id, 7);
+                assert_eq!(now, 1000);
+                assert_eq!(
+                    salts,
+                    vec![
+                        FutureSalt {
+                            valid_since: 900,
+                            valid_until: 2700,
+                            salt: 11,
+                        },
+                        FutureSalt {
+                            valid_since: 2700,
+                            valid_until: 4500,
+                            salt: 12,
+                        },
+                    ]
+                );
+            }
+            _ => panic!("Expected FutureSalts packet"),
+        }
+    }
+
+    #[test]
+    fn test_decode_destroy_session() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&DESTROY_SESSION_OK_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&42u64.to_le_bytes());
+        assert!(matches!(
+            ServicePacket::decode(&data),
+            Ok(ServicePacket::DestroySessionOk { session_id: 42 })
+        ));
+
+        data[..4].copy_from_slice(&DESTROY_SESSION_NONE_CONSTRUCTOR.to_le_bytes());
+        assert!(matches!(
+            ServicePacket::decode(&data),
+            Ok(ServicePacket::DestroySessionNone { session_id: 42 })
+        ));
+    }
+
+    #[test]
+    fn test_decode_msgs_state_req() {
+        let data = encode_msgs_state_req(&[1, 2, 3]);
+
+        match ServicePacket::decode(&data) {
+            Ok(ServicePacket::MsgsStateReq { msg_ids }) => assert_eq!(msg_ids, vec![1, 2, 3]),
+            _ => panic!("Expected MsgsStateReq packet"),
+        }
+    }
+
+    #[test]
+    fn test_msgs_state_info_roundtrip() {
+        let data = encode_msgs_state_info(9, &[1, 2, 4]);
+        assert_eq!(data.len() % 4, 0);
+
+        match ServicePacket::decode(&data) {
+            Ok(ServicePacket::MsgsStateInfo { req_msg_id, info }) => {
+                assert_eq!(req_msg_id, 9);
+                assert_eq!(info, vec![1, 2, 4]);
+            }
+            _ => panic!("Expected MsgsStateInfo packet"),
+        }
+    }
+
+    #[test]
+    fn test_decode_msgs_all_info() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MSGS_ALL_INFO_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&VECTOR_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&10u64.to_le_bytes());
+        data.extend_from_slice(&20u64.to_le_bytes());
+        write_tl_bytes(&mut data, &[4, 1]);
+
+        match ServicePacket::decode(&data) {
+            Ok(ServicePacket::MsgsAllInfo { msg_ids, info }) => {
+                assert_eq!(msg_ids, vec![10, 20]);
+                assert_eq!(info, vec![4, 1]);
+            }
+            _ => panic!("Expected MsgsAllInfo packet"),
+        }
+    }
+
+    #[test]
+    fn test_decode_msg_detailed_info() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MSG_DETAILED_INFO_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes()); // msg_id
+        data.extend_from_slice(&2u64.to_le_bytes()); // answer_msg_id
+        data.extend_from_slice(&100i32.to_le_bytes()); // bytes
+        data.extend_from_slice(&0i32.to_le_bytes()); // status
+
+        assert!(matches!(
+            ServicePacket::decode(&data),
+            Ok(ServicePacket::MsgDetailedInfo {
+                msg_id: 1,
+                answer_msg_id: 2,
+                bytes: 100,
+                status: 0,
+            })
+        ));
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&MSG_NEW_DETAILED_INFO_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&2u64.to_le_bytes()); // answer_msg_id
+        data.extend_from_slice(&100i32.to_le_bytes()); // bytes
+        data.extend_from_slice(&0i32.to_le_bytes()); // status
+
+        assert!(matches!(
+            ServicePacket::decode(&data),
+            Ok(ServicePacket::MsgNewDetailedInfo {
+                answer_msg_id: 2,
+                bytes: 100,
+                status: 0,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_tl_bytes_long() {
+        let bytes = vec![7u8; 300];
+        let mut data = Vec::new();
+        write_tl_bytes(&mut data, &bytes);
+        assert_eq!(data.len() % 4, 0);
+
+        let mut cursor = Bytes::from(data);
+        assert_eq!(read_tl_bytes(&mut cursor), Ok(bytes));
+        assert!(!cursor.has_remaining());
+    }
+
+    #[test]
+    fn test_encode_queries() {
+        let data = encode_get_future_salts(32);
+        assert_eq!(&data[..4], &GET_FUTURE_SALTS_CONSTRUCTOR.to_le_bytes());
+        assert_eq!(&data[4..], &32i32.to_le_bytes());
+
+        let data = encode_destroy_session(5);
+        assert_eq!(&data[..4], &DESTROY_SESSION_CONSTRUCTOR.to_le_bytes());
+        assert_eq!(&data[4..], &5u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_decode_rpc_result() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&RPC_RESULT_CONSTRUCTOR.to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3, 4]);
+
+        let rpc_result = match RpcResult::decode(&Bytes::from(data.clone())) {
+            Some(rpc_result) => rpc_result,
+            None => panic!("Expected rpc_result"),
+        };
+        assert_eq!(rpc_result.req_msg_id, 7);
+        assert_eq!(&rpc_result.result[..], &[1, 2, 3, 4]);
+        assert_eq!(rpc_result.encode(), Bytes::from(data));
+
+        assert!(RpcResult::decode(&Bytes::from_static(&[1, 2, 3, 4])).is_none());
+    }
 }
@@ -7,8 +7,12 @@
 //! This module implements TDLib's network statistics tracking from
 //! `td/telegram/net/NetType.h` and `td/telegram/net/NetStatsManager.h`.
 
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Network type.
 ///
@@ -129,6 +133,29 @@ pub enum FileType {
 }
 
 impl FileType {
+    /// Returns the file type with the given numeric value.
+    ///
+    /// Unknown values, like the `-1` of queries not related to files, map
+    /// to [`FileType::None`].
+    pub fn from_i32(value: i32) -> Self {
+        match value {
+            0 => Self::Photo,
+            1 => Self::ProfilePhoto,
+            2 => Self::Video,
+            3 => Self::Audio,
+            4 => Self::Voice,
+            5 => Self::VideoNote,
+            6 => Self::Document,
+            7 => Self::Sticker,
+            8 => Self::AnimatedSticker,
+            9 => Self::CustomEmoji,
+            10 => Self::Background,
+            20 => Self::Secure,
+            21 => Self::SecureDecrypted,
+            _ => Self::None,
+        }
+    }
+
     /// Returns the unique name for this file type.
     pub fn unique_name(&self) -> &'static str {
         match self {
@@ -331,29 +358,136 @@ impl NetworkStats {
     }
 }
 
+/// Byte counter of a connection.
+///
+/// Transports count the bytes they read and write here, and
+/// [`NetStatsManager::update`] collects the counts. Bytes are attributed to
+/// the file type of the counter unless they are moved to the file type of
+/// the query they belong to with [`move_to_file_type`](Self::move_to_file_type).
+///
+/// Based on TDLib's NetStatsCallback from `td/net/NetStats.h`.
+#[derive(Debug, Clone)]
+pub struct NetStatsCounter {
+    inner: Arc<Mutex<CounterBytes>>,
+}
+
+#[derive(Debug)]
+struct CounterBytes {
+    /// File type of the bytes not attributed to a query
+    file_type: FileType,
+
+    /// Bytes received and sent by file type, since the last collection
+    bytes: HashMap<FileType, (i64, i64)>,
+}
+
+impl NetStatsCounter {
+    /// Creates a counter attributing bytes to the given file type.
+    pub fn new(file_type: FileType) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CounterBytes {
+                file_type,
+                bytes: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns the file type bytes are attributed to by default.
+    pub fn file_type(&self) -> FileType {
+        self.inner.lock().file_type
+    }
+
+    /// Counts bytes read from the network.
+    pub fn on_read(&self, size: usize) {
+        let mut inner = self.inner.lock();
+        let file_type = inner.file_type;
+        let bytes = inner.bytes.entry(file_type).or_default();
+        bytes.0 = bytes.0.saturating_add(size as i64);
+    }
+
+    /// Counts bytes written to the network.
+    pub fn on_write(&self, size: usize) {
+        let mut inner = self.inner.lock();
+        let file_type = inner.file_type;
+        let bytes = inner.bytes.entry(file_type).or_default();
+        bytes.1 = bytes.1.saturating_add(size as i64);
+    }
+
+    /// Attributes bytes counted by the transport to another file type.
+    ///
+    /// Used for the queries of file parts, whose sizes are known only once
+    /// they are serialized or their answers are parsed.
+    pub fn move_to_file_type(&self, file_type: FileType, rx: i64, tx: i64) {
+        let mut inner = self.inner.lock();
+        let default_type = inner.file_type;
+        if file_type == default_type {
+            return;
+        }
+        let from = inner.bytes.entry(default_type).or_default();
+        from.0 = from.0.saturating_sub(rx);
+        from.1 = from.1.saturating_sub(tx);
+        let to = inner.bytes.entry(file_type).or_default();
+        to.0 = to.0.saturating_add(rx);
+        to.1 = to.1.saturating_add(tx);
+    }
+
+    /// Returns and resets the counted bytes as `(file_type, rx, tx)`.
+    pub fn take(&self) -> Vec<(FileType, i64, i64)> {
+        self.inner
+            .lock()
+            .bytes
+            .drain()
+            .map(|(file_type, (rx, tx))| (file_type, rx, tx))
+            .filter(|(_, rx, tx)| *rx != 0 || *tx != 0)
+            .collect()
+    }
+
+    /// Returns `true` if no connection holds the counter anymore.
+    fn is_orphaned(&self) -> bool {
+        Arc::strong_count(&self.inner) == 1
+    }
+}
+
 /// Network statistics manager.
 ///
+/// Keeps the bytes sent and received by file type and network type, since
+/// the time the statistics were last reset. Bytes counted by the
+/// [`NetStatsCounter`]s of the connections are added to the current network
+/// type when the manager is [updated](Self::update).
+///
+/// The statistics are persisted by saving [`take_changed`](Self::take_changed)
+/// snapshots and restoring them with [`restore`](Self::restore).
+///
 /// Based on TDLib's NetStatsManager from `td/telegram/net/NetStatsManager.h`.
 #[derive(Debug, Clone)]
 pub struct NetStatsManager {
     /// Current network type
     net_type: NetType,
 
-    /// Statistics by category
-    common_stats: NetworkStatsEntry,
-    media_stats: NetworkStatsEntry,
-    file_stats: Vec<NetworkStatsEntry>,
-    call_stats: NetworkStatsEntry,
+    /// Unix time the statistics were last reset
+    since: i32,
+
+    /// Bytes by file type and network type
+    file_stats: NetworkStats,
+
+    /// Call statistics by network type
+    call_stats: Vec<NetworkStatsEntry>,
+
+    /// Counters of the connections
+    counters: Vec<NetStatsCounter>,
+
+    /// Whether the statistics changed since the last snapshot
+    changed: bool,
 }
 
 impl Default for NetStatsManager {
     fn default() -> Self {
         Self {
             net_type: NetType::Other,
-            common_stats: NetworkStatsEntry::new(FileType::None, NetType::Other),
-            media_stats: NetworkStatsEntry::new(FileType::None, NetType::Other),
-            file_stats: Vec::new(),
-            call_stats: NetworkStatsEntry::new(FileType::None, NetType::Other),
+            since: unix_time(),
+            file_stats: NetworkStats::new(),
+            call_stats: Vec::new(),
+            counters: Vec::new(),
+            changed: false,
         }
     }
 }
@@ -370,103 +504,193 @@ impl NetStatsManager {
     }
 
     /// Sets the current network type.
+    ///
+    /// Bytes counted so far are attributed to the previous network type.
     pub fn set_net_type(&mut self, net_type: NetType) {
+        self.update();
         self.net_type = net_type;
     }
 
-    /// Returns the common statistics callback data.
-    pub fn common_stats(&self) -> NetworkStatsEntry {
-        self.common_stats.clone()
+    /// Returns the Unix time the statistics were last reset.
+    pub fn since(&self) -> i32 {
+        self.since
     }
 
-    /// Returns the media statistics callback data.
-    pub fn media_stats(&self) -> NetworkStatsEntry {
-        self.media_stats.clone()
+    /// Creates a counter for a connection.
+    ///
+    /// Bytes not attributed to a query are counted for `file_type`.
+    pub fn counter(&mut self, file_type: FileType) -> NetStatsCounter {
+        let counter = NetStatsCounter::new(file_type);
+        self.counters.push(counter.clone());
+        counter
     }
 
-    /// Returns statistics for a specific file type.
-    pub fn file_stats(&self, file_type: FileType) -> Option<NetworkStatsEntry> {
+    /// Collects the bytes counted by the connections.
+    pub fn update(&mut self) {
+        let net_type = self.net_type;
+        for counter in &self.counters {
+            for (file_type, rx, tx) in counter.take() {
+                self.file_stats.add_bytes(file_type, net_type, rx, tx);
+                self.changed = true;
+            }
+        }
+        self.counters.retain(|counter| !counter.is_orphaned());
+    }
+
+    /// Returns the statistics for a file type on a network type.
+    pub fn stats(&self, file_type: FileType, net_type: NetType) -> NetworkStatsEntry {
         self.file_stats
+            .entries
             .iter()
-            .find(|e| e.file_type == file_type)
+            .find(|e| e.file_type == file_type && e.net_type == net_type)
             .cloned()
+            .unwrap_or_else(|| NetworkStatsEntry::new(file_type, net_type))
+    }
+
+    /// Returns the statistics not related to files, summed over network types.
+    pub fn common_stats(&self) -> NetworkStatsEntry {
+        self.sum_file_stats(|file_type| file_type == FileType::None)
+    }
+
+    /// Returns the statistics of media files, summed over network types.
+    pub fn media_stats(&self) -> NetworkStatsEntry {
+        self.sum_file_stats(|file_type| {
+            matches!(
+                file_type,
+                FileType::Photo
+                    | FileType::ProfilePhoto
+                    | FileType::Video
+                    | FileType::Voice
+                    | FileType::VideoNote
+            )
+        })
+    }
+
+    /// Returns statistics for a specific file type, summed over network types.
+    pub fn file_stats(&self, file_type: FileType) -> Option<NetworkStatsEntry> {
+        if !self
+            .file_stats
+            .entries
+            .iter()
+            .any(|e| e.file_type == file_type)
+        {
+            return None;
+        }
+        let mut stats = self.sum_file_stats(|other| other == file_type);
+        stats.file_type = file_type;
+        Some(stats)
     }
 
-    /// Returns the call statistics.
+    /// Returns the call statistics, summed over network types.
     pub fn call_stats(&self) -> NetworkStatsEntry {
-        self.call_stats.clone()
+        let mut stats = NetworkStatsEntry::call(NetType::Other, 0, 0, 0, 0.0);
+        for entry in &self.call_stats {
+            stats.add_bytes(entry.rx, entry.tx);
+            stats.count += entry.count;
+            stats.duration += entry.duration;
+        }
+        stats
+    }
+
+    fn sum_file_stats(&self, filter: impl Fn(FileType) -> bool) -> NetworkStatsEntry {
+        let mut stats = NetworkStatsEntry::new(FileType::None, NetType::Other);
+        for entry in &self.file_stats.entries {
+            if filter(entry.file_type) {
+                stats.add_bytes(entry.rx, entry.tx);
+            }
+        }
+        stats
     }
 
     /// Adds network statistics.
     pub fn add_stats(&mut self, entry: &NetworkStatsEntry) {
-        match entry.file_type {
-            FileType::None => {
-                if entry.is_call {
-                    self.call_stats.add_bytes(entry.rx, entry.tx);
-                } else {
-                    self.common_stats.add_bytes(entry.rx, entry.tx);
-                }
-            }
-            FileType::Photo
-            | FileType::ProfilePhoto
-            | FileType::Video
-            | FileType::Voice
-            | FileType::VideoNote => {
-                self.media_stats.add_bytes(entry.rx, entry.tx);
-            }
-            _ => {
-                // Find or create file stats entry
-                if let Some(stats) = self
-                    .file_stats
-                    .iter_mut()
-                    .find(|e| e.file_type == entry.file_type)
-                {
-                    stats.add_bytes(entry.rx, entry.tx);
-                } else {
-                    let mut stats = entry.clone();
-                    stats.rx = 0;
-                    stats.tx = 0;
-                    stats.add_bytes(entry.rx, entry.tx);
-                    self.file_stats.push(stats);
+        if entry.is_call {
+            let stats = match self
+                .call_stats
+                .iter_mut()
+                .position(|e| e.net_type == entry.net_type)
+            {
+                Some(idx) => &mut self.call_stats[idx],
+                None => {
+                    self.call_stats
+                        .push(NetworkStatsEntry::call(entry.net_type, 0, 0, 0, 0.0));
+                    self.call_stats
+                        .last_mut()
+                        .expect("entry was just pushed, so it exists")
                 }
-            }
+            };
+            stats.add_bytes(entry.rx, entry.tx);
+            stats.count = stats.count.saturating_add(entry.count);
+            stats.duration += entry.duration;
+        } else {
+            self.file_stats
+                .add_bytes(entry.file_type, entry.net_type, entry.rx, entry.tx);
         }
+        self.changed = true;
     }
 
     /// Generates network statistics snapshot.
+    ///
+    /// Contains an entry for every file type and network type with data.
     pub fn get_network_stats(&self) -> NetworkStats {
         let mut stats = NetworkStats::new();
+        stats.since = self.since;
+        stats.entries = self.file_stats.filter_active();
+        stats
+            .entries
+            .extend(self.call_stats.iter().filter(|e| e.has_data()).cloned());
+        stats
+    }
 
-        if self.common_stats.has_data() {
-            stats.entries.push(self.common_stats.clone());
-        }
-
-        if self.media_stats.has_data() {
-            stats.entries.push(self.media_stats.clone());
+    /// Returns a snapshot for saving if the statistics changed since the
+    /// last one.
+    ///
+    /// Bytes counted by the connections are collected first.
+    pub fn take_changed(&mut self) -> Option<NetworkStats> {
+        self.update();
+        if !self.changed {
+            return None;
         }
+        self.changed = false;
+        Some(self.get_network_stats())
+    }
 
-        for file_stats in &self.file_stats {
-            if file_stats.has_data() {
-                stats.entries.push(file_stats.clone());
-            }
+    /// Restores saved statistics, replacing the current ones.
+    pub fn restore(&mut self, saved: &NetworkStats) {
+        self.file_stats = NetworkStats::new();
+        self.call_stats.clear();
+        for entry in &saved.entries {
+            self.add_stats(entry);
         }
-
-        if self.call_stats.has_data() {
-            stats.entries.push(self.call_stats.clone());
+        if saved.since > 0 {
+            self.since = saved.since;
         }
-
-        stats
+        self.changed = false;
     }
 
     /// Resets all statistics.
+    ///
+    /// Bytes counted by the connections but not collected yet are dropped
+    /// and the statistics are collected anew from now on.
     pub fn reset(&mut self) {
-        self.common_stats.reset();
-        self.media_stats.reset();
-        self.file_stats.clear();
-        self.call_stats.reset();
+        for counter in &self.counters {
+            counter.take();
+        }
+        self.file_stats = NetworkStats::new();
+        self.call_stats.clear();
+        self.since = unix_time();
+        self.changed = true;
     }
 }
 
+/// Returns the current Unix time.
+fn unix_time() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i32)
+        .unwrap_or(0)
+}
+
 /// Callback for tracking network statistics.
 ///
 /// Based on TDLib's NetStatsCallback from `td/net/NetStats.h`.
@@ -510,6 +734,7 @@ impl NetStatsCallback {
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
 
@@ -630,4 +855,93 @@ mod tests {
         assert_eq!(entry.rx, 2000);
         assert_eq!(entry.tx, 1000);
     }
+
+    #[test]
+    fn test_file_type_from_i32() {
+        assert_eq!(FileType::from_i32(2), FileType::Video);
+        assert_eq!(FileType::from_i32(21), FileType::SecureDecrypted);
+        assert_eq!(FileType::from_i32(-1), FileType::None);
+        assert_eq!(FileType::from_i32(100), FileType::None);
+    }
+
+    #[test]
+    fn test_net_stats_counter() {
+        let counter = NetStatsCounter::new(FileType::None);
+        counter.on_read(1000);
+        counter.on_write(300);
+        counter.move_to_file_type(FileType::Video, 800, 100);
+
+        let mut bytes = counter.take();
+        bytes.sort_by_key(|(file_type, _, _)| *file_type as i32);
+        assert_eq!(
+            bytes,
+            vec![(FileType::None, 200, 200), (FileType::Video, 800, 100)]
+        );
+        assert!(counter.take().is_empty());
+    }
+
+    #[test]
+    fn test_net_stats_manager_collects_counters_by_net_type() {
+        let mut manager = NetStatsManager::new();
+        manager.set_net_type(NetType::WiFi);
+        let counter = manager.counter(FileType::None);
+
+        counter.on_read(100);
+        manager.set_net_type(NetType::MobileRoaming);
+        counter.on_read(40);
+        counter.on_write(10);
+        manager.update();
+
+        assert_eq!(manager.stats(FileType::None, NetType::WiFi).rx, 100);
+        let roaming = manager.stats(FileType::None, NetType::MobileRoaming);
+        assert_eq!((roaming.rx, roaming.tx), (40, 10));
+        assert_eq!(manager.common_stats().rx, 140);
+
+        // Counters of closed connections are dropped after collection
+        drop(counter);
+        manager.update();
+        assert!(manager.counters.is_empty());
+    }
+
+    #[test]
+    fn test_net_stats_manager_save_and_restore() {
+        let mut manager = NetStatsManager::new();
+        assert!(manager.take_changed().is_none());
+
+        manager.add_stats(&NetworkStatsEntry::file(
+            FileType::Document,
+            NetType::Mobile,
+            500,
+            20,
+        ));
+        manager.add_stats(&NetworkStatsEntry::call(NetType::WiFi, 10, 20, 1, 60.0));
+        let saved = manager.take_changed().unwrap();
+        assert!(manager.take_changed().is_none());
+
+        let mut restored = NetStatsManager::new();
+        restored.restore(&saved);
+        assert_eq!(restored.since(), manager.since());
+        assert_eq!(restored.get_network_stats(), saved);
+        assert_eq!(restored.file_stats(FileType::Document).unwrap().rx, 500);
+        assert_eq!(restored.call_stats().count, 1);
+        assert!(restored.take_changed().is_none());
+    }
+
+    #[test]
+    fn test_net_stats_manager_reset() {
+        let mut manager = NetStatsManager::new();
+        let counter = manager.counter(FileType::None);
+        manager.add_stats(&NetworkStatsEntry::file(
+            FileType::Photo,
+            NetType::WiFi,
+            1,
+            1,
+        ));
+        counter.on_read(10);
+
+        manager.reset();
+        let saved = manager.take_changed().unwrap();
+        assert!(saved.is_empty());
+        assert!(saved.since > 0);
+    }
 }
@@ -0,0 +1,450 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Fake-TLS emulation for `0xee` MTProto proxy secrets.
+//!
+//! Based on TDLib's `TlsInit` from `td/mtproto/TlsInit.cpp` and the TLS
+//! record handling in `td/mtproto/TcpTransport.cpp`.
+//!
+//! # Overview
+//!
+//! 1. The client sends a browser-like TLS 1.3 `ClientHello` for the secret's
+//!    domain. Its `random` field holds `HMAC-SHA256(secret, hello)` with the
+//!    last 4 bytes XORed with the current unix time.
+//! 2. The proxy answers with `ServerHello`, `ChangeCipherSpec` and one
+//!    application data record. The server `random` must equal
+//!    `HMAC-SHA256(secret, client_random || response)`, computed with the
+//!    server random zeroed.
+//! 3. After that, the obfuscated2 stream is carried in TLS application data
+//!    records. The first client record is preceded by `ChangeCipherSpec`.
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use rand::RngCore;
+
+use crate::connection::ConnectionError;
+use crate::crypto::hmac_sha256;
+
+/// Maximum payload of a client TLS application data record.
+pub const MAX_TLS_PACKET_LENGTH: usize = 2878;
+
+/// Size of the TLS record header (type, version, length).
+pub const TLS_RECORD_HEADER_SIZE: usize = 5;
+
+/// Total size of the emulated `ClientHello` record.
+const CLIENT_HELLO_SIZE: usize = 517;
+
+/// Offset of the `random` field inside a handshake record.
+const RANDOM_OFFSET: usize = 11;
+
+/// Size of the `random` field.
+const RANDOM_SIZE: usize = 32;
+
+/// TLS `ChangeCipherSpec` record.
+const CHANGE_CIPHER_SPEC: &[u8] = b"\x14\x03\x03\x00\x01\x01";
+
+/// TLS application data record prefix.
+const APPLICATION_DATA_PREFIX: &[u8] = b"\x17\x03\x03";
+
+/// TLS handshake record prefix sent by the server.
+const SERVER_HANDSHAKE_PREFIX: &[u8] = b"\x16\x03\x03";
+
+/// Number of GREASE values used by the hello template.
+const GREASE_COUNT: usize = 7;
+
+/// Builder for a TLS handshake message with nested length-prefixed scopes.
+#[derive(Default)]
+struct HelloWriter {
+    data: Vec<u8>,
+    scopes: Vec<(usize, usize)>,
+}
+
+impl HelloWriter {
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    fn random(&mut self, len: usize) -> &mut Self {
+        let start = self.data.len();
+        self.data.resize(start + len, 0);
+        rand::thread_rng().fill_bytes(&mut self.data[start..]);
+        self
+    }
+
+    fn zero(&mut self, len: usize) -> &mut Self {
+        self.data.resize(self.data.len() + len, 0);
+        self
+    }
+
+    fn grease(&mut self, value: u8) -> &mut Self {
+        self.bytes(&[value, value])
+    }
+
+    /// Opens a scope whose big-endian length of `width` bytes is filled in
+    /// by the matching [`HelloWriter::end`].
+    fn begin(&mut self, width: usize) -> &mut Self {
+        self.scopes.push((self.data.len(), width));
+        self.zero(width)
+    }
+
+    fn end(&mut self) -> &mut Self {
+        if let Some((pos, width)) = self.scopes.pop() {
+            let len = self.data.len() - pos - width;
+            let be = (len as u32).to_be_bytes();
+            self.data[pos..pos + width].copy_from_slice(&be[4 - width..]);
+        }
+        self
+    }
+}
+
+/// Generates GREASE values as Chrome does (`0x?a`, adjacent values differ).
+fn generate_grease() -> [u8; GREASE_COUNT] {
+    let mut grease = [0u8; GREASE_COUNT];
+    rand::thread_rng().fill_bytes(&mut grease);
+    for value in &mut grease {
+        *value = (*value & 0xf0) | 0x0a;
+    }
+    for i in (1..GREASE_COUNT).step_by(2) {
+        if grease[i] == grease[i - 1] {
+            grease[i] ^= 0x10;
+        }
+    }
+    grease
+}
+
+/// Generates a random valid X25519 public key for the `key_share` extension.
+///
+/// Mirrors TDLib: pick a random x such that `x^3 + 486662x^2 + x` is a
+/// quadratic residue modulo `2^255 - 19`, then double the point three times.
+fn generate_public_key() -> [u8; 32] {
+    let p = (BigUint::one() << 255u32) - BigUint::from(19u32);
+    let a = BigUint::from(486_662u32);
+    let two = BigUint::from(2u32);
+    let legendre_exp = (&p - BigUint::one()) >> 1u32;
+
+    let y2 = |x: &BigUint| -> BigUint { ((x * x % &p + &a * x) % &p * x + x) % &p };
+    let inverse = |v: &BigUint| -> BigUint { v.modpow(&(&p - &two), &p) };
+
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        key[31] &= 0x7f;
+
+        let mut x = BigUint::from_bytes_le(&key);
+        x = &x * &x % &p;
+        let y = y2(&x);
+        if y.is_zero() || y.modpow(&legendre_exp, &p) != BigUint::one() {
+            continue;
+        }
+
+        for _ in 0..3 {
+            // x' = (x^2 - 1)^2 / (4 * y^2(x))
+            let x2 = &x * &x % &p;
+            let numerator = (&x2 + &p - BigUint::one()) % &p;
+            let numerator = &numerator * &numerator % &p;
+            let denominator = BigUint::from(4u32) * y2(&x) % &p;
+            x = numerator * inverse(&denominator) % &p;
+        }
+
+        let bytes = x.to_bytes_le();
+        let mut result = [0u8; 32];
+        result[..bytes.len()].copy_from_slice(&bytes);
+        return result;
+    }
+}
+
+/// Emulated TLS `ClientHello` with its signed random.
+#[derive(Debug, Clone)]
+pub struct ClientHello {
+    /// Complete TLS record to send
+    pub data: Vec<u8>,
+}
+
+impl ClientHello {
+    /// Builds a `ClientHello` for the given domain and signs it.
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - Domain from the `0xee` secret (sent in SNI)
+    /// * `secret` - 16-byte key part of the proxy secret
+    /// * `unix_time` - Current unix time, mixed into the digest
+    pub fn new(domain: &[u8], secret: &[u8], unix_time: u32) -> Self {
+        let grease = generate_grease();
+        let mut w = HelloWriter::default();
+
+        // Record and handshake headers
+        w.bytes(b"\x16\x03\x01").begin(2);
+        w.bytes(b"\x01").begin(3);
+        w.bytes(b"\x03\x03").zero(RANDOM_SIZE);
+        w.bytes(b"\x20").random(32);
+
+        // Cipher suites and compression
+        w.begin(2).grease(grease[0]);
+        w.bytes(
+            b"\x13\x01\x13\x02\x13\x03\xc0\x2b\xc0\x2f\xc0\x2c\xc0\x30\xcc\xa9\
+              \xcc\xa8\xc0\x13\xc0\x14\x00\x9c\x00\x9d\x00\x2f\x00\x35",
+        );
+        w.end().bytes(b"\x01\x00");
+
+        // Extensions
+        w.begin(2);
+        w.grease(grease[2]).bytes(b"\x00\x00");
+        w.bytes(b"\x00\x00")
+            .begin(2)
+            .begin(2)
+            .bytes(b"\x00")
+            .begin(2);
+        w.bytes(domain).end().end().end();
+        w.bytes(b"\x00\x17\x00\x00\xff\x01\x00\x01\x00");
+        w.bytes(b"\x00\x0a\x00\x0a\x00\x08").grease(grease[4]);
+        w.bytes(b"\x00\x1d\x00\x17\x00\x18");
+        w.bytes(b"\x00\x0b\x00\x02\x01\x00\x00\x23\x00\x00");
+        w.bytes(b"\x00\x10\x00\x0e\x00\x0c\x02h2\x08http/1.1");
+        w.bytes(b"\x00\x05\x00\x05\x01\x00\x00\x00\x00");
+        w.bytes(
+            b"\x00\x0d\x00\x12\x00\x10\x04\x03\x08\x04\x04\x01\x05\x03\
+              \x08\x05\x05\x01\x08\x06\x06\x01",
+        );
+        w.bytes(b"\x00\x12\x00\x00");
+        w.bytes(b"\x00\x33\x00\x2b\x00\x29").grease(grease[4]);
+        w.bytes(b"\x00\x01\x00\x00\x1d\x00\x20");
+        w.bytes(&generate_public_key());
+        w.bytes(b"\x00\x2d\x00\x02\x01\x01");
+        w.bytes(b"\x00\x2b\x00\x0b\x0a").grease(grease[6]);
+        w.bytes(b"\x03\x04\x03\x03\x03\x02\x03\x01");
+        w.bytes(b"\x00\x1b\x00\x03\x02\x00\x02");
+        w.grease(grease[3]).bytes(b"\x00\x01\x00");
+
+        // Padding extension up to the fixed Chrome hello size
+        let padding_header = 4;
+        let current = w.data.len();
+        if current + padding_header < CLIENT_HELLO_SIZE {
+            let padding = CLIENT_HELLO_SIZE - current - padding_header;
+            w.bytes(b"\x00\x15").begin(2).zero(padding).end();
+        }
+
+        w.end().end().end();
+        let mut data = w.data;
+
+        let mut digest = hmac_sha256(secret, &data);
+        let time = u32::from_le_bytes([digest[28], digest[29], digest[30], digest[31]]) ^ unix_time;
+        digest[28..32].copy_from_slice(&time.to_le_bytes());
+        data[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE].copy_from_slice(&digest);
+
+        Self { data }
+    }
+
+    /// Returns the signed `random` field of the hello.
+    pub fn random(&self) -> &[u8] {
+        &self.data[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE]
+    }
+}
+
+/// Returns the total length of the server hello response, if the prefix
+/// read so far is enough to know it.
+///
+/// The response is `ServerHello` record, `ChangeCipherSpec`, and one
+/// application data record.
+pub fn server_hello_length(prefix: &[u8]) -> Result<Option<usize>, ConnectionError> {
+    let first_header = TLS_RECORD_HEADER_SIZE;
+    if prefix.len() < first_header {
+        return Ok(None);
+    }
+    if &prefix[..3] != SERVER_HANDSHAKE_PREFIX {
+        return Err(ConnectionError::Ssl("Invalid server hello record".into()));
+    }
+    let first_len = usize::from(u16::from_be_bytes([prefix[3], prefix[4]]));
+
+    let second_start = first_header + first_len;
+    let second_header = CHANGE_CIPHER_SPEC.len() + TLS_RECORD_HEADER_SIZE;
+    if prefix.len() < second_start + second_header {
+        return Ok(None);
+    }
+    let tail = &prefix[second_start..second_start + second_header];
+    if &tail[..CHANGE_CIPHER_SPEC.len()] != CHANGE_CIPHER_SPEC
+        || &tail[CHANGE_CIPHER_SPEC.len()..CHANGE_CIPHER_SPEC.len() + 3] != APPLICATION_DATA_PREFIX
+    {
+        return Err(ConnectionError::Ssl("Invalid server hello records".into()));
+    }
+    let second_len = usize::from(u16::from_be_bytes([
+        tail[second_header - 2],
+        tail[second_header - 1],
+    ]));
+
+    Ok(Some(second_start + second_header + second_len))
+}
+
+/// Verifies the server hello digest against the client hello.
+pub fn verify_server_hello(
+    response: &[u8],
+    client_random: &[u8],
+    secret: &[u8],
+) -> Result<(), ConnectionError> {
+    if response.len() < RANDOM_OFFSET + RANDOM_SIZE {
+        return Err(ConnectionError::Ssl("Server hello is too short".into()));
+    }
+
+    let mut zeroed = response.to_vec();
+    zeroed[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE].fill(0);
+
+    let mut input = Vec::with_capacity(RANDOM_SIZE + zeroed.len());
+    input.extend_from_slice(client_random);
+    input.extend_from_slice(&zeroed);
+    let expected = hmac_sha256(secret, &input);
+
+    if expected[..] != response[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE] {
+        return Err(ConnectionError::Ssl("Server hello hash mismatch".into()));
+    }
+    Ok(())
+}
+
+/// Wraps obfuscated bytes into TLS application data records.
+///
+/// With `is_first` set, a `ChangeCipherSpec` record is prepended, as the
+/// first client record of the connection requires.
+pub fn wrap_records(data: &[u8], is_first: bool) -> Vec<u8> {
+    let records = data.len().div_ceil(MAX_TLS_PACKET_LENGTH).max(1);
+    let mut out = Vec::with_capacity(
+        data.len() + records * TLS_RECORD_HEADER_SIZE + CHANGE_CIPHER_SPEC.len(),
+    );
+    if is_first {
+        out.extend_from_slice(CHANGE_CIPHER_SPEC);
+    }
+    for chunk in data.chunks(MAX_TLS_PACKET_LENGTH) {
+        out.extend_from_slice(APPLICATION_DATA_PREFIX);
+        out.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// Parses a TLS application data record header, returning its payload size.
+pub fn parse_record_header(
+    header: &[u8; TLS_RECORD_HEADER_SIZE],
+) -> Result<usize, ConnectionError> {
+    if &header[..3] != APPLICATION_DATA_PREFIX {
+        return Err(ConnectionError::Ssl(format!(
+            "Unexpected TLS record header {:02x?}",
+            header
+        )));
+    }
+    Ok(usize::from(u16::from_be_bytes([header[3], header[4]])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 16] = [0x5a; 16];
+
+    fn server_response(client_random: &[u8]) -> Vec<u8> {
+        let mut response = Vec::new();
+        response.extend_from_slice(b"\x16\x03\x03\x00\x2b\x02\x00\x00\x27\x03\x03");
+        response.extend_from_slice(&[0u8; 32]);
+        response.extend_from_slice(&[0x11; 5]);
+        response.extend_from_slice(CHANGE_CIPHER_SPEC);
+        response.extend_from_slice(b"\x17\x03\x03\x00\x04\xde\xad\xbe\xef");
+
+        let mut input = client_random.to_vec();
+        input.extend_from_slice(&response);
+        let digest = hmac_sha256(&SECRET, &input);
+        response[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE].copy_from_slice(&digest);
+        response
+    }
+
+    #[test]
+    fn test_client_hello_layout() {
+        let hello = ClientHello::new(b"example.com", &SECRET, 1_700_000_000);
+        assert_eq!(hello.data.len(), CLIENT_HELLO_SIZE);
+        assert_eq!(&hello.data[..3], b"\x16\x03\x01");
+        let record_len = usize::from(u16::from_be_bytes([hello.data[3], hello.data[4]]));
+        assert_eq!(record_len + TLS_RECORD_HEADER_SIZE, CLIENT_HELLO_SIZE);
+        assert_eq!(hello.data[5], 0x01);
+        assert!(hello
+            .data
+            .windows(b"example.com".len())
+            .any(|w| w == b"example.com"));
+    }
+
+    #[test]
+    fn test_client_hello_digest() {
+        let time = 1_700_000_000u32;
+        let hello = ClientHello::new(b"example.com", &SECRET, time);
+
+        let mut zeroed = hello.data.clone();
+        zeroed[RANDOM_OFFSET..RANDOM_OFFSET + RANDOM_SIZE].fill(0);
+        let expected = hmac_sha256(&SECRET, &zeroed);
+
+        let random = hello.random();
+        assert_eq!(&random[..28], &expected[..28]);
+        let xored = u32::from_le_bytes([random[28], random[29], random[30], random[31]])
+            ^ u32::from_le_bytes([expected[28], expected[29], expected[30], expected[31]]);
+        assert_eq!(xored, time);
+    }
+
+    #[test]
+    fn test_server_hello_verification() {
+        let hello = ClientHello::new(b"example.com", &SECRET, 0);
+        let response = server_response(hello.random());
+
+        assert_eq!(
+            server_hello_length(&response[..4]).expect("valid prefix"),
+            None
+        );
+        assert_eq!(
+            server_hello_length(&response).expect("valid prefix"),
+            Some(response.len())
+        );
+        assert!(verify_server_hello(&response, hello.random(), &SECRET).is_ok());
+
+        let mut tampered = response.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(verify_server_hello(&tampered, hello.random(), &SECRET).is_err());
+
+        assert!(server_hello_length(b"\x15\x03\x03\x00\x02").is_err());
+    }
+
+    #[test]
+    fn test_wrap_records() {
+        let data = vec![7u8; MAX_TLS_PACKET_LENGTH + 10];
+        let wrapped = wrap_records(&data, true);
+        assert_eq!(&wrapped[..CHANGE_CIPHER_SPEC.len()], CHANGE_CIPHER_SPEC);
+
+        let mut rest = &wrapped[CHANGE_CIPHER_SPEC.len()..];
+        let mut payload = Vec::new();
+        while !rest.is_empty() {
+            let mut header = [0u8; TLS_RECORD_HEADER_SIZE];
+            header.copy_from_slice(&rest[..TLS_RECORD_HEADER_SIZE]);
+            let len = parse_record_header(&header).expect("valid record");
+            assert!(len <= MAX_TLS_PACKET_LENGTH);
+            payload.extend_from_slice(&rest[TLS_RECORD_HEADER_SIZE..TLS_RECORD_HEADER_SIZE + len]);
+            rest = &rest[TLS_RECORD_HEADER_SIZE + len..];
+        }
+        assert_eq!(payload, data);
+
+        let wrapped = wrap_records(&[1, 2, 3], false);
+        assert_eq!(wrapped, b"\x17\x03\x03\x00\x03\x01\x02\x03");
+    }
+
+    #[test]
+    fn test_public_key_is_reduced() {
+        let key = generate_public_key();
+        assert!(key[31] < 0x80);
+        assert_ne!(key, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_grease_values() {
+        let grease = generate_grease();
+        for value in grease {
+            assert_eq!(value & 0x0f, 0x0a);
+        }
+        for i in (1..GREASE_COUNT).step_by(2) {
+            assert_ne!(grease[i], grease[i - 1]);
+        }
+    }
+}
@@ -21,10 +21,12 @@
 //! - TDLib: `td/mtproto/Transport.h`, `td/mtproto/Transport.cpp`
 //! - MTProto 2.0: <https://core.telegram.org/mtproto/description>
 
+mod fake_tls;
 mod header;
 mod http;
 mod http_proxy;
 mod mtproto_proxy;
+mod obfuscated;
 mod read;
 mod socks5;
 mod tcp;
@@ -36,6 +38,7 @@ pub use header::{
 pub use http::{HttpTransport, HttpTransportFactory};
 pub use http_proxy::{HttpProxyTransport, HttpProxyTransportFactory};
 pub use mtproto_proxy::{MtprotoProxyTransport, MtprotoProxyTransportFactory};
+pub use obfuscated::{obfuscated_dc_id, ObfuscatedCodec, OBFUSCATED_HEADER_SIZE};
 pub use read::{ReadResult, TransportRead};
 pub use socks5::{Socks5Transport, Socks5TransportFactory};
 pub use tcp::{TcpReadHalf, TcpTransport, TcpTransportFactory, TcpWriteHalf, MAX_PACKET_SIZE};
@@ -5,22 +5,40 @@
 //! MTProto proxy transport for MTProto.
 //!
 //! This module implements MTProto proxy (MTPROTO) support for Telegram MTProto.
+//!
+//! Based on TDLib's `ObfuscatedTransport` from `td/mtproto/TcpTransport.cpp`
+//! and `TlsInit` from `td/mtproto/TlsInit.cpp`.
+//!
+//! The connection uses obfuscated2 framing (see [`super::obfuscated`]) with
+//! intermediate packets. Secrets prefixed with `0xdd` add random padding, and
+//! secrets prefixed with `0xee` additionally wrap the stream in fake TLS
+//! (see [`super::fake_tls`]).
 
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use bytes::{BufMut, Bytes, BytesMut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
 use crate::connection::{ConnectionError, ConnectionState};
-use crate::crypto::{aes_ige_decrypt, aes_ige_encrypt, sha256};
-use crate::proxy::Proxy;
-use crate::transport::{ReadResult, WriteOptions};
+use crate::dc::DcId;
+use crate::packet::PacketInfo;
+use crate::proxy::{Proxy, ProxySecret};
+use crate::test_config::is_test_dc;
+use crate::transport::fake_tls::{self, ClientHello, TLS_RECORD_HEADER_SIZE};
+use crate::transport::obfuscated::{obfuscated_dc_id, ObfuscatedCodec};
+use crate::transport::{ReadResult, TransportRead, TransportWrite, WriteOptions, MAX_PACKET_SIZE};
+
+/// Default connection timeout.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default read timeout.
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(15);
 
-/// MTProto proxy protocol version.
-const PROTOCOL_VERSION: u8 = 1;
+/// Bit set in the intermediate length of a quick acknowledgment.
+const QUICK_ACK_FLAG: u32 = 1 << 31;
 
 /// MTProto proxy transport.
 pub struct MtprotoProxyTransport {
@@ -33,22 +51,47 @@ pub struct MtprotoProxyTransport {
     /// Proxy configuration
     proxy: Proxy,
 
-    /// Encryption key
-    key: [u8; 32],
+    /// Parsed proxy secret
+    secret: ProxySecret,
+
+    /// DC to ask the proxy for
+    dc_id: DcId,
+
+    /// Whether the connection is for a media-only DC
+    is_media: bool,
+
+    /// Whether the DC belongs to the test environment
+    is_test: bool,
+
+    /// Obfuscation state, set once the init header is sent
+    codec: Option<ObfuscatedCodec>,
 
-    /// Encryption nonce
-    nonce: [u8; 16],
+    /// Decrypted bytes received but not consumed yet
+    read_buffer: Vec<u8>,
 
     /// Connection state
     state: ConnectionState,
 
+    /// Transport reader
+    reader: Arc<dyn TransportRead>,
+
+    /// Transport writer
+    writer: Arc<dyn TransportWrite>,
+
     /// Write options
     write_options: WriteOptions,
 }
 
 impl MtprotoProxyTransport {
     /// Creates a new MTProto proxy transport.
-    pub fn new(proxy: Proxy, target: SocketAddr) -> Result<Self, crate::proxy::ProxyError> {
+    ///
+    /// The proxy picks the Telegram server by `dc_id`; `target` is only kept
+    /// for diagnostics.
+    pub fn new(
+        proxy: Proxy,
+        target: SocketAddr,
+        dc_id: DcId,
+    ) -> Result<Self, crate::proxy::ProxyError> {
         proxy.validate()?;
 
         if !proxy.use_mtproto_proxy() {
@@ -57,44 +100,32 @@ impl MtprotoProxyTransport {
             ));
         }
 
-        let secret = proxy
+        if !dc_id.is_exact() {
+            return Err(crate::proxy::ProxyError::InvalidAddress(format!(
+                "Invalid DC for MTProto proxy: {}",
+                dc_id
+            )));
+        }
+
+        let raw = proxy
             .secret
             .as_ref()
             .ok_or_else(|| crate::proxy::ProxyError::InvalidAddress("No secret provided".into()))?;
-
-        if secret.len() < 16 {
-            return Err(crate::proxy::ProxyError::InvalidAddress(
-                "Secret too short (minimum 16 bytes)".into(),
-            ));
-        }
-
-        let mut key = [0u8; 32];
-        let mut nonce = [0u8; 16];
-
-        // Parse secret: format is "dd[secret]" where dd is two hex digits
-        let secret_bytes = if secret.len() >= 17 {
-            // Skip first byte (protocol indicator)
-            &secret[1..]
-        } else {
-            secret
-        };
-
-        // Extract key and nonce from secret
-        let key_len = key.len().min(secret_bytes.len());
-        key[..key_len].copy_from_slice(&secret_bytes[..key_len]);
-
-        if secret_bytes.len() > 32 {
-            let nonce_len = nonce.len().min(secret_bytes.len() - 32);
-            nonce[..nonce_len].copy_from_slice(&secret_bytes[32..32 + nonce_len]);
-        }
+        let secret = ProxySecret::from_binary(raw)?;
 
         Ok(Self {
             stream: None,
             target,
             proxy,
-            key,
-            nonce,
+            secret,
+            dc_id,
+            is_media: false,
+            is_test: is_test_dc(),
+            codec: None,
+            read_buffer: Vec::new(),
             state: ConnectionState::Empty,
+            reader: Arc::new(crate::transport::read::DefaultTransportReader::new()),
+            writer: Arc::new(crate::transport::write::DefaultTransportWriter::new()),
             write_options: WriteOptions::default(),
         })
     }
@@ -109,6 +140,16 @@ impl MtprotoProxyTransport {
         &self.proxy
     }
 
+    /// Returns the parsed proxy secret.
+    pub fn secret(&self) -> &ProxySecret {
+        &self.secret
+    }
+
+    /// Returns the DC the proxy is asked to connect to.
+    pub fn dc_id(&self) -> DcId {
+        self.dc_id
+    }
+
     /// Returns the connection state.
     pub fn state(&self) -> ConnectionState {
         self.state
@@ -124,6 +165,20 @@ impl MtprotoProxyTransport {
         self.write_options = options;
     }
 
+    /// Marks the connection as going to a media-only DC.
+    ///
+    /// Must be called before [`MtprotoProxyTransport::connect`].
+    pub fn set_media(&mut self, is_media: bool) {
+        self.is_media = is_media;
+    }
+
+    /// Overrides whether the DC id is encoded as a test DC.
+    ///
+    /// Defaults to the global [`is_test_dc`] setting at construction time.
+    pub fn set_test_dc(&mut self, is_test: bool) {
+        self.is_test = is_test;
+    }
+
     /// Connects through the MTProto proxy.
     pub async fn connect(&mut self) -> Result<(), ConnectionError> {
         self.state = ConnectionState::Connecting;
@@ -132,9 +187,9 @@ impl MtprotoProxyTransport {
         let proxy_addr = self.parse_proxy_addr().await?;
 
         // Connect to proxy
-        let mut stream = timeout(Duration::from_secs(10), TcpStream::connect(proxy_addr))
+        let mut stream = timeout(DEFAULT_CONNECT_TIMEOUT, TcpStream::connect(proxy_addr))
             .await
-            .map_err(|_| ConnectionError::Timeout(Duration::from_secs(10)))?
+            .map_err(|_| ConnectionError::Timeout(DEFAULT_CONNECT_TIMEOUT))?
             .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
 
         stream
@@ -142,13 +197,17 @@ impl MtprotoProxyTransport {
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
         // Perform MTProto proxy handshake
+        if self.secret.emulate_tls() {
+            self.tls_handshake(&mut stream).await?;
+        }
         self.mtproto_handshake(&mut stream).await?;
 
         self.stream = Some(stream);
         self.state = ConnectionState::Ready;
 
         tracing::debug!(
-            "MTProto proxy transport connected to {} via {}",
+            "MTProto proxy transport connected to {} ({}) via {}",
+            self.dc_id,
             self.target,
             proxy_addr
         );
@@ -171,54 +230,73 @@ impl MtprotoProxyTransport {
             .ok_or_else(|| ConnectionError::Proxy("No addresses found".into()))
     }
 
-    /// Performs MTProto proxy handshake.
-    async fn mtproto_handshake(&self, stream: &mut TcpStream) -> Result<(), ConnectionError> {
-        // Build handshake packet
-        let mut packet = BytesMut::with_capacity(64);
-
-        // Timestamp
-        let timestamp = std::time::SystemTime::now()
+    /// Performs the fake-TLS handshake for `0xee` secrets.
+    async fn tls_handshake(&self, stream: &mut TcpStream) -> Result<(), ConnectionError> {
+        let unix_time = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .map(|d| d.as_secs() as u32)
             .unwrap_or(0);
 
-        packet.put_u32_le(timestamp);
-
-        // Protocol version
-        packet.put_u8(PROTOCOL_VERSION);
+        let key = self.secret.get_proxy_secret();
+        let hello = ClientHello::new(self.secret.get_domain(), key, unix_time);
 
-        // DC ID (extracted from target)
-        let dc_id = self.extract_dc_id()?;
-        packet.put_u32_le(dc_id);
+        stream
+            .write_all(&hello.data)
+            .await
+            .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
 
-        // IP address
-        match self.target.ip() {
-            IpAddr::V4(addr) => {
-                packet.put_u8(4); // IPv4
-                packet.put_slice(&addr.octets());
+        let mut response = Vec::new();
+        let total = loop {
+            if let Some(total) = fake_tls::server_hello_length(&response)? {
+                break total;
             }
-            IpAddr::V6(addr) => {
-                packet.put_u8(6); // IPv6
-                packet.put_slice(&addr.octets());
+            let mut chunk = [0u8; 256];
+            let n = timeout(DEFAULT_READ_TIMEOUT, stream.read(&mut chunk))
+                .await
+                .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
+                .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
+            if n == 0 {
+                return Err(ConnectionError::Proxy(
+                    "Connection closed during TLS handshake".into(),
+                ));
             }
-        }
-
-        // Port
-        packet.put_u16_le(self.target.port());
+            response.extend_from_slice(&chunk[..n]);
+        };
 
-        // Random padding
-        let padding_len = 15; // Total header should be 64 bytes
-        let current_len = packet.len();
-        for _ in 0..(padding_len - (current_len % padding_len)) {
-            packet.put_u8(rand::random());
+        if response.len() < total {
+            let start = response.len();
+            response.resize(total, 0);
+            timeout(
+                DEFAULT_READ_TIMEOUT,
+                stream.read_exact(&mut response[start..]),
+            )
+            .await
+            .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
+            .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
+        } else if response.len() > total {
+            return Err(ConnectionError::Ssl(
+                "Unexpected data after server hello".into(),
+            ));
         }
 
-        // Encrypt handshake
-        let encrypted = self.encrypt_packet(packet.freeze())?;
+        fake_tls::verify_server_hello(&response, hello.random(), key)
+    }
+
+    /// Performs MTProto proxy handshake.
+    ///
+    /// Sends the obfuscated2 init header and sets up the stream ciphers.
+    async fn mtproto_handshake(&mut self, stream: &mut TcpStream) -> Result<(), ConnectionError> {
+        let dc_id = obfuscated_dc_id(self.dc_id, self.is_media, self.is_test);
+        let (codec, header) = ObfuscatedCodec::new(&self.secret, dc_id);
+
+        let packet = if self.secret.emulate_tls() {
+            fake_tls::wrap_records(&header, true)
+        } else {
+            header.to_vec()
+        };
 
-        // Send handshake
         stream
-            .write_all(&encrypted)
+            .write_all(&packet)
             .await
             .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
 
@@ -227,68 +305,8 @@ impl MtprotoProxyTransport {
             .await
             .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
 
-        Ok(())
-    }
-
-    /// Extracts DC ID from target address (for MTProto proxy protocol).
-    fn extract_dc_id(&self) -> Result<u32, ConnectionError> {
-        // For MTProto proxy, DC ID is typically encoded in the target
-        // For now, use a default value
-        Ok(2)
-    }
-
-    /// Encrypts a packet with MTProto proxy encryption.
-    fn encrypt_packet(&self, data: Bytes) -> Result<Vec<u8>, ConnectionError> {
-        // Derive AES key and IV using KDF
-        let hash_input = {
-            let mut input = Vec::with_capacity(48);
-            input.extend_from_slice(&self.key);
-            input.extend_from_slice(&self.nonce);
-            input
-        };
-
-        let hash = sha256(&hash_input);
-
-        let mut aes_key = [0u8; 32];
-        let mut aes_iv = [0u8; 32];
-
-        aes_key.copy_from_slice(&hash[..32]);
-        aes_iv.copy_from_slice(&hash[16..32]);
-
-        // Encrypt using AES-IGE
-        let mut encrypted = data.to_vec();
-
-        // Pad to block size
-        while encrypted.len() % 16 != 0 {
-            encrypted.push(0);
-        }
-
-        aes_ige_encrypt(&aes_key, &mut aes_iv, &mut encrypted)
-            .map_err(|e| ConnectionError::Ssl(e.to_string()))?;
-
-        Ok(encrypted)
-    }
-
-    /// Decrypts a packet with MTProto proxy decryption.
-    fn decrypt_packet(&self, data: &mut [u8]) -> Result<(), ConnectionError> {
-        // Same key derivation as encryption
-        let hash_input = {
-            let mut input = Vec::with_capacity(48);
-            input.extend_from_slice(&self.key);
-            input.extend_from_slice(&self.nonce);
-            input
-        };
-
-        let hash = sha256(&hash_input);
-
-        let mut aes_key = [0u8; 32];
-        let mut aes_iv = [0u8; 32];
-
-        aes_key.copy_from_slice(&hash[..32]);
-        aes_iv.copy_from_slice(&hash[16..32]);
-
-        aes_ige_decrypt(&aes_key, &mut aes_iv, data)
-            .map_err(|e| ConnectionError::Ssl(e.to_string()))?;
+        self.codec = Some(codec);
+        self.read_buffer.clear();
 
         Ok(())
     }
@@ -297,18 +315,36 @@ impl MtprotoProxyTransport {
     pub async fn write(
         &mut self,
         data: &[u8],
-        _auth_key: Option<&[u8; 256]>,
+        auth_key: Option<&[u8; 256]>,
     ) -> Result<(), ConnectionError> {
-        // Encrypt with MTProto proxy encryption
-        let encrypted = self.encrypt_packet(Bytes::copy_from_slice(data))?;
+        let (stream, codec) = match (self.stream.as_mut(), self.codec.as_mut()) {
+            (Some(stream), Some(codec)) => (stream, codec),
+            _ => return Err(ConnectionError::Failed("Not connected".into())),
+        };
 
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or_else(|| ConnectionError::Failed("Not connected".into()))?;
+        // 1. Encode packet using transport (adds NoCryptoHeader, CryptoHeader, etc.)
+        let mut packet_info = PacketInfo::new()
+            .with_no_crypto(auth_key.is_none())
+            .with_packet_type(self.write_options.packet_type);
+
+        let mtp_packet = self
+            .writer
+            .write(data, auth_key, &mut packet_info)
+            .map_err(|e| ConnectionError::Ssl(e.to_string()))?;
+
+        // 2. Intermediate framing and obfuscation
+        let mut framed = codec.frame(&mtp_packet);
+        codec.encrypt(&mut framed);
+
+        // 3. Fake-TLS records if required
+        let to_send = if self.secret.emulate_tls() {
+            fake_tls::wrap_records(&framed, false)
+        } else {
+            framed
+        };
 
         stream
-            .write_all(&encrypted)
+            .write_all(&to_send)
             .await
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
@@ -317,7 +353,7 @@ impl MtprotoProxyTransport {
             .await
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
-        tracing::trace!("MTProto proxy transport wrote {} bytes", data.len());
+        tracing::trace!("MTProto proxy transport wrote {} bytes", to_send.len());
 
         Ok(())
     }
@@ -325,39 +361,70 @@ impl MtprotoProxyTransport {
     /// Reads data from the proxy.
     pub async fn read(
         &mut self,
-        _auth_key: Option<&[u8; 256]>,
+        auth_key: Option<&[u8; 256]>,
     ) -> Result<ReadResult, ConnectionError> {
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or_else(|| ConnectionError::Failed("Not connected".into()))?;
-
-        // Read packet length (4 bytes)
-        let mut len_buf = [0u8; 4];
-        timeout(Duration::from_secs(15), stream.read_exact(&mut len_buf))
-            .await
-            .map_err(|_| ConnectionError::Timeout(Duration::from_secs(15)))?
-            .map_err(|e| ConnectionError::Socket(e.to_string()))?;
+        let len_bytes = self.read_plain(4).await?;
+        let length = u32::from_le_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
 
-        // In MTProto proxy, length is in the packet, not a prefix
-        // For now, assume fixed buffer size
-        let mut buffer = vec![0u8; 4096];
-        let n = timeout(Duration::from_secs(15), stream.read(&mut buffer))
-            .await
-            .map_err(|_| ConnectionError::Timeout(Duration::from_secs(15)))?
-            .map_err(|e| ConnectionError::Socket(e.to_string()))?;
+        if length & QUICK_ACK_FLAG != 0 {
+            return Ok(ReadResult::quick_ack(length & !QUICK_ACK_FLAG));
+        }
 
-        if n == 0 {
-            return Ok(ReadResult::nop());
+        let length = length as usize;
+        if length > MAX_PACKET_SIZE {
+            return Err(ConnectionError::Failed(format!(
+                "Packet too large: {} bytes",
+                length
+            )));
         }
 
-        buffer.truncate(n);
+        let mut buffer = self.read_plain(length).await?;
+        if let Some(codec) = &self.codec {
+            codec.strip_padding(&mut buffer);
+        }
+
+        let mut packet_info = PacketInfo::new()
+            .with_no_crypto(auth_key.is_none())
+            .with_packet_type(self.write_options.packet_type);
+
+        self.reader
+            .read(&buffer, auth_key, &mut packet_info)
+            .map_err(|e| ConnectionError::Failed(e.to_string()))
+    }
 
-        // Decrypt
-        self.decrypt_packet(&mut buffer)?;
+    /// Reads `n` deobfuscated bytes from the connection.
+    async fn read_plain(&mut self, n: usize) -> Result<Vec<u8>, ConnectionError> {
+        while self.read_buffer.len() < n {
+            let (stream, codec) = match (self.stream.as_mut(), self.codec.as_mut()) {
+                (Some(stream), Some(codec)) => (stream, codec),
+                _ => return Err(ConnectionError::Failed("Not connected".into())),
+            };
+
+            let mut chunk = if self.secret.emulate_tls() {
+                let mut header = [0u8; TLS_RECORD_HEADER_SIZE];
+                read_exact_timeout(stream, &mut header).await?;
+                let mut payload = vec![0u8; fake_tls::parse_record_header(&header)?];
+                read_exact_timeout(stream, &mut payload).await?;
+                payload
+            } else {
+                let mut buffer = vec![0u8; (n - self.read_buffer.len()).max(4096)];
+                let read = timeout(DEFAULT_READ_TIMEOUT, stream.read(&mut buffer))
+                    .await
+                    .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
+                    .map_err(|e| ConnectionError::Socket(e.to_string()))?;
+                if read == 0 {
+                    return Err(ConnectionError::Socket("Connection closed by proxy".into()));
+                }
+                buffer.truncate(read);
+                buffer
+            };
+
+            codec.decrypt(&mut chunk);
+            self.read_buffer.extend_from_slice(&chunk);
+        }
 
-        // Return as packet (skip MTProto proxy headers if any)
-        Ok(ReadResult::packet(buffer))
+        let rest = self.read_buffer.split_off(n);
+        Ok(std::mem::replace(&mut self.read_buffer, rest))
     }
 
     /// Closes the connection.
@@ -369,6 +436,8 @@ impl MtprotoProxyTransport {
                 .map_err(|e| ConnectionError::Socket(e.to_string()))?;
         }
 
+        self.codec = None;
+        self.read_buffer.clear();
         self.state = ConnectionState::Closed;
 
         tracing::debug!(
@@ -380,6 +449,15 @@ impl MtprotoProxyTransport {
     }
 }
 
+/// Reads exactly `buf.len()` bytes with the default read timeout.
+async fn read_exact_timeout(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), ConnectionError> {
+    timeout(DEFAULT_READ_TIMEOUT, stream.read_exact(buf))
+        .await
+        .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
+        .map_err(|e| ConnectionError::Socket(e.to_string()))?;
+    Ok(())
+}
+
 /// MTProto proxy transport factory.
 pub struct MtprotoProxyTransportFactory;
 
@@ -388,8 +466,9 @@ impl MtprotoProxyTransportFactory {
     pub async fn connect(
         proxy: Proxy,
         target: SocketAddr,
+        dc_id: DcId,
     ) -> Result<MtprotoProxyTransport, ConnectionError> {
-        let mut transport = MtprotoProxyTransport::new(proxy, target)
+        let mut transport = MtprotoProxyTransport::new(proxy, target, dc_id)
             .map_err(|e| ConnectionError::Proxy(e.to_string()))?;
         transport.connect().await?;
         Ok(transport)
@@ -399,37 +478,196 @@ impl MtprotoProxyTransportFactory {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::Ipv4Addr;
+    use crate::crypto::hmac_sha256;
+    use crate::transport::obfuscated::OBFUSCATED_HEADER_SIZE;
+    use crate::transport::INTERMEDIATE_MAGIC_PADDED;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::TcpListener;
+
+    const KEY: [u8; 16] = [0x3c; 16];
+
+    fn target() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443)
+    }
+
+    fn secret_with_prefix(prefix: &[u8], domain: &[u8]) -> Vec<u8> {
+        let mut secret = prefix.to_vec();
+        secret.extend_from_slice(&KEY);
+        secret.extend_from_slice(domain);
+        secret
+    }
+
+    /// Builds a no-crypto MTProto packet carrying `body`.
+    fn no_crypto_packet(body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 8];
+        packet.extend_from_slice(&0x6000_0000_0000_0001u64.to_le_bytes());
+        packet.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    /// Reads `n` obfuscated bytes, unwrapping TLS records if needed.
+    async fn read_stub_stream(
+        socket: &mut TcpStream,
+        n: usize,
+        buffer: &mut Vec<u8>,
+        emulate_tls: bool,
+    ) -> Vec<u8> {
+        while buffer.len() < n {
+            if emulate_tls {
+                let mut header = [0u8; 5];
+                socket.read_exact(&mut header).await.expect("record header");
+                assert_eq!(&header[..3], b"\x17\x03\x03");
+                let len = usize::from(u16::from_be_bytes([header[3], header[4]]));
+                let mut payload = vec![0u8; len];
+                socket.read_exact(&mut payload).await.expect("record");
+                buffer.extend_from_slice(&payload);
+            } else {
+                let mut payload = vec![0u8; n - buffer.len()];
+                socket.read_exact(&mut payload).await.expect("stream");
+                buffer.extend_from_slice(&payload);
+            }
+        }
+        let rest = buffer.split_off(n);
+        std::mem::replace(buffer, rest)
+    }
+
+    /// Minimal MTProto proxy: checks the init header and the first packet,
+    /// then echoes a fixed response. Returns the decoded DC id.
+    async fn run_stub_proxy(listener: TcpListener, emulate_tls: bool) -> i16 {
+        let (mut socket, _) = listener.accept().await.expect("accept");
+
+        let mut stream_bytes = Vec::new();
+        if emulate_tls {
+            let mut hello = vec![0u8; 517];
+            socket.read_exact(&mut hello).await.expect("client hello");
+
+            let mut zeroed = hello.clone();
+            zeroed[11..43].fill(0);
+            let digest = hmac_sha256(&KEY, &zeroed);
+            assert_eq!(&hello[11..39], &digest[..28], "client hello digest");
+
+            let mut response = b"\x16\x03\x03\x00\x28\x02\x00\x00\x24\x03\x03".to_vec();
+            response.extend_from_slice(&[0u8; 32]);
+            response.extend_from_slice(&[0u8; 2]);
+            response.extend_from_slice(b"\x14\x03\x03\x00\x01\x01\x17\x03\x03\x00\x02\xaa\xbb");
+            let mut input = hello[11..43].to_vec();
+            input.extend_from_slice(&response);
+            let server_random = hmac_sha256(&KEY, &input);
+            response[11..43].copy_from_slice(&server_random);
+            socket.write_all(&response).await.expect("server hello");
+
+            let mut ccs = [0u8; 6];
+            socket
+                .read_exact(&mut ccs)
+                .await
+                .expect("change cipher spec");
+            assert_eq!(&ccs, b"\x14\x03\x03\x00\x01\x01");
+        }
+
+        let raw_header = read_stub_stream(
+            &mut socket,
+            OBFUSCATED_HEADER_SIZE,
+            &mut stream_bytes,
+            emulate_tls,
+        )
+        .await;
+        let mut header = [0u8; OBFUSCATED_HEADER_SIZE];
+        header.copy_from_slice(&raw_header);
+
+        let mut codec = ObfuscatedCodec::from_header(&header, &KEY, true, true);
+        let mut plain = header;
+        codec.decrypt(&mut plain);
+        assert_eq!(&plain[56..60], &INTERMEDIATE_MAGIC_PADDED.to_le_bytes());
+        let dc_id = i16::from_le_bytes([plain[60], plain[61]]);
+
+        let mut len = read_stub_stream(&mut socket, 4, &mut stream_bytes, emulate_tls).await;
+        codec.decrypt(&mut len);
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let mut body = read_stub_stream(&mut socket, len, &mut stream_bytes, emulate_tls).await;
+        codec.decrypt(&mut body);
+        codec.strip_padding(&mut body);
+        assert_eq!(&body[..8], &[0u8; 8], "no-crypto auth_key_id");
+        let data_len = u32::from_le_bytes([body[16], body[17], body[18], body[19]]) as usize;
+        assert!(body[20..20 + data_len].starts_with(b"ping"));
+
+        let mut reply = codec.frame(&no_crypto_packet(b"pong"));
+        codec.encrypt(&mut reply);
+        let reply = if emulate_tls {
+            fake_tls::wrap_records(&reply, false)
+        } else {
+            reply
+        };
+        socket.write_all(&reply).await.expect("reply");
+
+        dc_id
+    }
+
+    async fn exchange_through_stub(secret: Vec<u8>, dc_id: DcId, is_media: bool) -> i16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("local addr").port();
+        let emulate_tls = secret[0] == 0xee;
+        let server = tokio::spawn(run_stub_proxy(listener, emulate_tls));
+
+        let proxy = Proxy::mtproto("127.0.0.1".into(), port, secret);
+        let mut transport = MtprotoProxyTransport::new(proxy, target(), dc_id).expect("transport");
+        transport.set_media(is_media);
+        transport.set_test_dc(false);
+        transport.connect().await.expect("connect");
+        assert!(transport.is_connected());
+
+        transport.write(b"ping", None).await.expect("write");
+        let result = transport.read(None).await.expect("read");
+        assert_eq!(result.packet_data().map(|d| d.as_ref()), Some(&b"pong"[..]));
+
+        let decoded = server.await.expect("stub proxy");
+        transport.close().await.expect("close");
+        decoded
+    }
 
     #[test]
     fn test_mtproto_proxy_transport_new() {
-        let secret = vec![
-            0xDDu8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
-            0x0E, 0x0F,
-        ];
-        let proxy = Proxy::mtproto("127.0.0.1".into(), 1080, secret);
-        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443);
-
-        let transport = match MtprotoProxyTransport::new(proxy, target) {
+        let proxy = Proxy::mtproto("127.0.0.1".into(), 1080, secret_with_prefix(&[0xdd], b""));
+
+        let transport = match MtprotoProxyTransport::new(proxy, target(), DcId::internal(2)) {
             Ok(t) => t,
             Err(_) => panic!("Expected Ok transport"),
         };
 
-        assert_eq!(transport.target(), target);
+        assert_eq!(transport.target(), target());
+        assert_eq!(transport.dc_id(), DcId::internal(2));
+        assert!(transport.secret().use_random_padding());
         assert!(!transport.is_connected());
     }
 
     #[test]
     fn test_mtproto_proxy_transport_new_invalid_proxy() {
         let proxy = Proxy::none();
-        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443);
 
-        let result = MtprotoProxyTransport::new(proxy, target);
+        let result = MtprotoProxyTransport::new(proxy, target(), DcId::internal(2));
         assert!(result.is_err());
     }
 
     #[test]
-    fn test_protocol_version() {
-        assert_eq!(PROTOCOL_VERSION, 1);
+    fn test_mtproto_proxy_transport_new_invalid_secret() {
+        let proxy = Proxy::mtproto("127.0.0.1".into(), 1080, vec![0xab; 17]);
+        assert!(MtprotoProxyTransport::new(proxy, target(), DcId::internal(2)).is_err());
+
+        let proxy = Proxy::mtproto("127.0.0.1".into(), 1080, KEY.to_vec());
+        assert!(MtprotoProxyTransport::new(proxy, target(), DcId::empty()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stub_proxy_padded_intermediate() {
+        let dc_id =
+            exchange_through_stub(secret_with_prefix(&[0xdd], b""), DcId::internal(4), false).await;
+        assert_eq!(dc_id, 4);
+    }
+
+    #[tokio::test]
+    async fn test_stub_proxy_fake_tls_media_dc() {
+        let secret = secret_with_prefix(&[0xee], b"example.com");
+        let dc_id = exchange_through_stub(secret, DcId::internal(2), true).await;
+        assert_eq!(dc_id, -2);
     }
 }
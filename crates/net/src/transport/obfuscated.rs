@@ -0,0 +1,352 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Obfuscated2 transport framing used by MTProto proxies.
+//!
+//! Based on TDLib's `ObfuscatedTransport` from `td/mtproto/TcpTransport.cpp`.
+//!
+//! # Overview
+//!
+//! The client starts the connection with a 64-byte random header:
+//!
+//! ```text
+//! bytes  0..8   random (first byte != 0xef, no HTTP/TLS/transport magic)
+//! bytes  8..40  AES-256-CTR key for client -> server
+//! bytes 40..56  AES-256-CTR IV for client -> server
+//! bytes 56..60  transport tag (0xeeeeeeee or 0xdddddddd)
+//! bytes 60..62  DC id (little-endian int16, negative for media DCs)
+//! bytes 62..64  random
+//! ```
+//!
+//! The server -> client key and IV are taken from the same bytes of the
+//! reversed header. When a proxy secret is configured, each key is replaced
+//! with `SHA256(key || secret)`. The header itself is sent with bytes
+//! `56..64` replaced by their encrypted form, so the tag and DC id are hidden.
+
+use aes::Aes256;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use rand::{Rng, RngCore};
+
+use crate::crypto::sha256;
+use crate::dc::DcId;
+use crate::proxy::ProxySecret;
+use crate::transport::{INTERMEDIATE_MAGIC, INTERMEDIATE_MAGIC_PADDED};
+
+/// AES-256 in big-endian 128-bit counter mode, as used by obfuscated2.
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// Size of the obfuscated2 init header.
+pub const OBFUSCATED_HEADER_SIZE: usize = 64;
+
+/// First words that a random header must not start with.
+///
+/// These would make the connection look like HTTP (`HEAD`, `POST`, `GET `,
+/// `OPTI`), a plain transport magic, or a TLS record.
+const FORBIDDEN_FIRST_WORDS: [u32; 7] = [
+    0x4441_4548, // HEAD
+    0x5453_4f50, // POST
+    0x2054_4547, // GET
+    0x4954_504f, // OPTI
+    INTERMEDIATE_MAGIC_PADDED,
+    INTERMEDIATE_MAGIC,
+    0x0201_0316, // TLS handshake record
+];
+
+/// Maximum amount of random padding appended to a padded intermediate packet.
+const MAX_RANDOM_PADDING: usize = 15;
+
+/// Returns the DC id as it is encoded in the obfuscated2 header.
+///
+/// Test DCs are shifted by 10000 and media-only DCs are negated, matching
+/// TDLib's `ConnectionCreator`.
+pub fn obfuscated_dc_id(dc_id: DcId, is_media: bool, is_test: bool) -> i16 {
+    let mut raw = dc_id.get_value();
+    if is_test {
+        raw += 10000;
+    }
+    if is_media {
+        raw = -raw;
+    }
+    raw as i16
+}
+
+/// Returns `true` if a random header may be used as an obfuscated2 init.
+fn is_valid_header(header: &[u8; OBFUSCATED_HEADER_SIZE]) -> bool {
+    if header[0] == crate::transport::ABRIDGED_MAGIC {
+        return false;
+    }
+    let first = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    if FORBIDDEN_FIRST_WORDS.contains(&first) {
+        return false;
+    }
+    let second = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    second != 0
+}
+
+/// Derives the CTR stream for one direction from a (possibly reversed) header.
+fn derive_cipher(header: &[u8], secret: &[u8]) -> Aes256Ctr {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&header[8..40]);
+    if !secret.is_empty() {
+        let mut input = Vec::with_capacity(32 + secret.len());
+        input.extend_from_slice(&key);
+        input.extend_from_slice(secret);
+        key = sha256(&input);
+    }
+    let mut iv = [0u8; 16];
+    iv.copy_from_slice(&header[40..56]);
+    Aes256Ctr::new(&key.into(), &iv.into())
+}
+
+/// Obfuscated2 stream state for one connection.
+///
+/// Holds the two AES-256-CTR streams and the intermediate framing mode.
+pub struct ObfuscatedCodec {
+    /// Stream used for outgoing bytes
+    encryptor: Aes256Ctr,
+
+    /// Stream used for incoming bytes
+    decryptor: Aes256Ctr,
+
+    /// Whether packets use padded intermediate framing
+    with_padding: bool,
+}
+
+impl std::fmt::Debug for ObfuscatedCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObfuscatedCodec")
+            .field("with_padding", &self.with_padding)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ObfuscatedCodec {
+    /// Creates a client codec and the init header that must be sent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - Proxy secret (only its 16-byte key part is used here)
+    /// * `dc_id` - Encoded DC id, see [`obfuscated_dc_id`]
+    pub fn new(secret: &ProxySecret, dc_id: i16) -> (Self, [u8; OBFUSCATED_HEADER_SIZE]) {
+        let with_padding = secret.use_random_padding();
+        let mut rng = rand::thread_rng();
+        let mut header = [0u8; OBFUSCATED_HEADER_SIZE];
+        loop {
+            rng.fill_bytes(&mut header);
+            if is_valid_header(&header) {
+                break;
+            }
+        }
+
+        let tag = if with_padding {
+            INTERMEDIATE_MAGIC_PADDED
+        } else {
+            INTERMEDIATE_MAGIC
+        };
+        header[56..60].copy_from_slice(&tag.to_le_bytes());
+        header[60..62].copy_from_slice(&dc_id.to_le_bytes());
+
+        let mut codec = Self::from_header(&header, secret.get_proxy_secret(), false, with_padding);
+
+        // The whole header goes through the stream so that the payload starts
+        // at keystream offset 64, but only the tail is sent encrypted.
+        let mut encrypted = header;
+        codec.encryptor.apply_keystream(&mut encrypted);
+        header[56..].copy_from_slice(&encrypted[56..]);
+
+        (codec, header)
+    }
+
+    /// Creates a codec from an existing plain init header.
+    ///
+    /// With `is_server` set, the directions are swapped, which is what the
+    /// proxy side of the connection uses.
+    pub(crate) fn from_header(
+        header: &[u8; OBFUSCATED_HEADER_SIZE],
+        secret: &[u8],
+        is_server: bool,
+        with_padding: bool,
+    ) -> Self {
+        let mut reversed = *header;
+        reversed.reverse();
+
+        let forward = derive_cipher(header, secret);
+        let backward = derive_cipher(&reversed, secret);
+
+        let (encryptor, decryptor) = if is_server {
+            (backward, forward)
+        } else {
+            (forward, backward)
+        };
+
+        Self {
+            encryptor,
+            decryptor,
+            with_padding,
+        }
+    }
+
+    /// Returns `true` if packets use padded intermediate framing.
+    pub fn with_padding(&self) -> bool {
+        self.with_padding
+    }
+
+    /// Encrypts outgoing bytes in place.
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        self.encryptor.apply_keystream(data);
+    }
+
+    /// Decrypts incoming bytes in place.
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        self.decryptor.apply_keystream(data);
+    }
+
+    /// Frames an MTProto packet with the intermediate length prefix.
+    ///
+    /// In padded mode, 0 to 15 random bytes are appended and counted in the
+    /// length. The result is not encrypted yet.
+    pub fn frame(&self, packet: &[u8]) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let padding = if self.with_padding {
+            rng.gen_range(0..=MAX_RANDOM_PADDING)
+        } else {
+            0
+        };
+
+        let total = packet.len() + padding;
+        let mut framed = Vec::with_capacity(4 + total);
+        framed.extend_from_slice(&(total as u32).to_le_bytes());
+        framed.extend_from_slice(packet);
+        let start = framed.len();
+        framed.resize(start + padding, 0);
+        rng.fill_bytes(&mut framed[start..]);
+        framed
+    }
+
+    /// Strips the random padding from a received packet body.
+    ///
+    /// MTProto packets are always 4-byte aligned, so any remainder is padding.
+    pub fn strip_padding(&self, body: &mut Vec<u8>) {
+        if self.with_padding {
+            body.truncate(body.len() - body.len() % 4);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded_secret() -> ProxySecret {
+        let mut raw = vec![0xdd];
+        raw.extend_from_slice(&[0x42; 16]);
+        ProxySecret::new(raw)
+    }
+
+    #[test]
+    fn test_header_is_valid() {
+        let (_, header) = ObfuscatedCodec::new(&ProxySecret::new(vec![7; 16]), 2);
+        assert_ne!(header[0], 0xef);
+        assert_ne!(&header[4..8], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_invalid_headers_rejected() {
+        let mut header = [1u8; OBFUSCATED_HEADER_SIZE];
+        assert!(is_valid_header(&header));
+
+        header[..4].copy_from_slice(b"POST");
+        assert!(!is_valid_header(&header));
+
+        header[..4].copy_from_slice(&[0xef, 1, 1, 1]);
+        assert!(!is_valid_header(&header));
+
+        header[..4].copy_from_slice(&[0xee; 4]);
+        assert!(!is_valid_header(&header));
+
+        header[..4].copy_from_slice(&[1; 4]);
+        header[4..8].copy_from_slice(&[0; 4]);
+        assert!(!is_valid_header(&header));
+    }
+
+    #[test]
+    fn test_server_decodes_tag_and_dc_id() {
+        let secret = padded_secret();
+        let (_, sent) = ObfuscatedCodec::new(&secret, -4);
+
+        // The server recovers the plain tail by decrypting the sent header.
+        let mut server = ObfuscatedCodec::from_header(&sent, secret.get_proxy_secret(), true, true);
+        let mut decrypted = sent;
+        server.decrypt(&mut decrypted);
+
+        assert_eq!(&decrypted[56..60], &INTERMEDIATE_MAGIC_PADDED.to_le_bytes());
+        assert_eq!(i16::from_le_bytes([decrypted[60], decrypted[61]]), -4);
+    }
+
+    #[test]
+    fn test_round_trip_both_directions() {
+        let secret = padded_secret();
+        let (mut client, sent) = ObfuscatedCodec::new(&secret, 2);
+        let mut server = ObfuscatedCodec::from_header(&sent, secret.get_proxy_secret(), true, true);
+        let mut skip = sent;
+        server.decrypt(&mut skip);
+
+        let mut data = b"client to server".to_vec();
+        client.encrypt(&mut data);
+        assert_ne!(&data, b"client to server");
+        server.decrypt(&mut data);
+        assert_eq!(&data, b"client to server");
+
+        let mut data = b"server to client".to_vec();
+        server.encrypt(&mut data);
+        client.decrypt(&mut data);
+        assert_eq!(&data, b"server to client");
+    }
+
+    #[test]
+    fn test_secret_changes_keys() {
+        let header = {
+            let mut h = [3u8; OBFUSCATED_HEADER_SIZE];
+            h[8..40].copy_from_slice(&[9; 32]);
+            h
+        };
+        let mut plain = ObfuscatedCodec::from_header(&header, &[], false, false);
+        let mut keyed = ObfuscatedCodec::from_header(&header, &[1; 16], false, false);
+
+        let mut a = [0u8; 16];
+        let mut b = [0u8; 16];
+        plain.encrypt(&mut a);
+        keyed.encrypt(&mut b);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_frame_padding() {
+        let plain = ObfuscatedCodec::from_header(&[1; 64], &[], false, false);
+        let framed = plain.frame(&[0xab; 8]);
+        assert_eq!(framed.len(), 12);
+        assert_eq!(&framed[..4], &8u32.to_le_bytes());
+
+        let padded = ObfuscatedCodec::from_header(&[1; 64], &[], false, true);
+        for _ in 0..32 {
+            let framed = padded.frame(&[0xab; 8]);
+            let len = u32::from_le_bytes([framed[0], framed[1], framed[2], framed[3]]) as usize;
+            assert_eq!(framed.len(), 4 + len);
+            assert!((8..=8 + MAX_RANDOM_PADDING).contains(&len));
+
+            let mut body = framed[4..].to_vec();
+            padded.strip_padding(&mut body);
+            assert_eq!(body.len() % 4, 0);
+            assert!(body.starts_with(&[0xab; 8]));
+        }
+    }
+
+    #[test]
+    fn test_obfuscated_dc_id() {
+        assert_eq!(obfuscated_dc_id(DcId::internal(2), false, false), 2);
+        assert_eq!(obfuscated_dc_id(DcId::internal(4), true, false), -4);
+        assert_eq!(obfuscated_dc_id(DcId::internal(1), false, true), 10001);
+        assert_eq!(obfuscated_dc_id(DcId::internal(3), true, true), -10003);
+    }
+}
@@ -14,7 +14,8 @@ use tokio::net::TcpStream;
 use tokio::time::timeout;
 
 use crate::connection::{ConnectionError, ConnectionState};
-use crate::packet::PacketInfo;
+use crate::packet::{MessageId, PacketInfo};
+use crate::stats::NetStatsCounter;
 use crate::transport::{ReadResult, TransportRead, TransportWrite, WriteOptions};
 
 /// Maximum packet size for TCP transport.
@@ -55,6 +56,9 @@ pub struct TcpTransport {
 
     /// Whether transport magic has been sent
     magic_sent: bool,
+
+    /// Counter of the bytes read and written
+    stats: Option<NetStatsCounter>,
 }
 
 impl TcpTransport {
@@ -69,6 +73,7 @@ impl TcpTransport {
             write_options: WriteOptions::default(),
             transport_mode: crate::transport::TransportMode::default(),
             magic_sent: false,
+            stats: None,
         }
     }
 
@@ -87,6 +92,7 @@ impl TcpTransport {
             write_options: WriteOptions::default(),
             transport_mode: crate::transport::TransportMode::default(),
             magic_sent: false,
+            stats: None,
         }
     }
 
@@ -110,6 +116,13 @@ impl TcpTransport {
         self.write_options = options;
     }
 
+    /// Sets the counter of the bytes read and written.
+    ///
+    /// The counter is passed on to the halves of a split transport.
+    pub fn set_stats_counter(&mut self, stats: NetStatsCounter) {
+        self.stats = Some(stats);
+    }
+
     /// Connects to the remote address.
     pub async fn connect(&mut self) -> Result<(), ConnectionError> {
         self.state = ConnectionState::Connecting;
@@ -137,6 +150,41 @@ impl TcpTransport {
         &mut self,
         data: &[u8],
         auth_key: Option<&[u8; 256]>,
+    ) -> Result<(), ConnectionError> {
+        let mut packet_info = PacketInfo::new()
+            .with_no_crypto(auth_key.is_none())
+            .with_packet_type(self.write_options.packet_type);
+
+        self.write_with_info(data, auth_key, &mut packet_info).await
+    }
+
+    /// Writes an unencrypted packet with the given message ID.
+    ///
+    /// Sessions pass the next message ID of their auth data, so the IDs of
+    /// the handshake packets grow like the IDs of all other messages.
+    pub async fn write_plain(
+        &mut self,
+        data: &[u8],
+        message_id: MessageId,
+    ) -> Result<(), ConnectionError> {
+        let mut packet_info = PacketInfo::new()
+            .with_no_crypto(true)
+            .with_packet_type(self.write_options.packet_type)
+            .with_message_id(message_id);
+
+        self.write_with_info(data, None, &mut packet_info).await
+    }
+
+    /// Writes data to the TCP stream using a pre-populated PacketInfo.
+    ///
+    /// This is required for encrypted packets sent before the transport is
+    /// split, e.g. `auth.bindTempAuthKey`, whose message_id must be known
+    /// to the caller.
+    pub async fn write_with_info(
+        &mut self,
+        data: &[u8],
+        auth_key: Option<&[u8; 256]>,
+        packet_info: &mut PacketInfo,
     ) -> Result<(), ConnectionError> {
         let stream = self
             .stream
@@ -144,13 +192,9 @@ impl TcpTransport {
             .ok_or_else(|| ConnectionError::Failed("Not connected".into()))?;
 
         // 1. Encode packet using transport (adds NoCryptoHeader, CryptoHeader, etc.)
-        let mut packet_info = PacketInfo::new()
-            .with_no_crypto(auth_key.is_none())
-            .with_packet_type(self.write_options.packet_type);
-
         let mtp_packet = self
             .writer
-            .write(data, auth_key, &mut packet_info)
+            .write(data, auth_key, packet_info)
             .map_err(|e| ConnectionError::Ssl(e.to_string()))?;
 
         // 2. Add transport-level framing (length prefix)
@@ -189,6 +233,10 @@ impl TcpTransport {
             .await
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
+        if let Some(stats) = &self.stats {
+            stats.on_write(to_send.len());
+        }
+
         tracing::info!(
             "TCP transport wrote {} bytes (framed from {} bytes MTProto packet)\nSent data (hex): {:02x?}",
             to_send.len(),
@@ -204,6 +252,18 @@ impl TcpTransport {
         &mut self,
         auth_key: Option<&[u8; 256]>,
     ) -> Result<ReadResult, ConnectionError> {
+        let (result, _info) = self.read_with_info(auth_key).await?;
+        Ok(result)
+    }
+
+    /// Reads data from the TCP stream and returns the filled PacketInfo.
+    ///
+    /// The message ID of an encrypted packet carries the server time, which
+    /// service messages received before the transport is split need.
+    pub async fn read_with_info(
+        &mut self,
+        auth_key: Option<&[u8; 256]>,
+    ) -> Result<(ReadResult, PacketInfo), ConnectionError> {
         let stream = self
             .stream
             .as_mut()
@@ -267,6 +327,10 @@ impl TcpTransport {
             .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
+        if let Some(stats) = &self.stats {
+            stats.on_read(framing_len(self.transport_mode) + buffer.len());
+        }
+
         tracing::info!(
             "TCP transport read {} bytes from server (mode: {:?})",
             buffer.len(),
@@ -285,7 +349,7 @@ impl TcpTransport {
 
         tracing::info!("TCP transport read packet result: {:?}", result);
 
-        Ok(result)
+        Ok((result, packet_info))
     }
 
     /// Closes the TCP connection.
@@ -316,6 +380,7 @@ impl TcpTransport {
                 stream: read,
                 addr: self.addr,
                 transport_mode: self.transport_mode,
+                stats: self.stats.clone(),
             },
             TcpWriteHalf {
                 writer: self.writer.clone_box(),
@@ -323,6 +388,7 @@ impl TcpTransport {
                 addr: self.addr,
                 write_options: self.write_options,
                 transport_mode: self.transport_mode,
+                stats: self.stats.clone(),
             },
         ))
     }
@@ -354,6 +420,9 @@ impl TcpTransport {
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
         self.magic_sent = true;
+        if let Some(stats) = &self.stats {
+            stats.on_write(magic.len());
+        }
         tracing::info!(
             "Sent transport magic: {:02x?} (mode: {:?})",
             magic,
@@ -408,6 +477,8 @@ pub struct TcpReadHalf {
     pub addr: SocketAddr,
     /// Transport mode for packet framing
     pub transport_mode: crate::transport::TransportMode,
+    /// Counter of the bytes read
+    pub stats: Option<NetStatsCounter>,
 }
 
 impl TcpReadHalf {
@@ -490,6 +561,10 @@ impl TcpReadHalf {
             .map_err(|_| ConnectionError::Timeout(DEFAULT_READ_TIMEOUT))?
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
+        if let Some(stats) = &self.stats {
+            stats.on_read(framing_len(self.transport_mode) + buffer.len());
+        }
+
         // Decode
         let mut packet_info = PacketInfo::new().with_packet_type(packet_type);
         let result = self
@@ -513,6 +588,8 @@ pub struct TcpWriteHalf {
     pub write_options: WriteOptions,
     /// Transport mode for packet framing
     pub transport_mode: crate::transport::TransportMode,
+    /// Counter of the bytes written
+    pub stats: Option<NetStatsCounter>,
 }
 
 impl TcpWriteHalf {
@@ -556,6 +633,10 @@ impl TcpWriteHalf {
             .await
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
+        if let Some(stats) = &self.stats {
+            stats.on_write(framed.len());
+        }
+
         tracing::trace!(
             "TcpWriteHalf wrote {} bytes (framed from {} bytes MTProto packet)",
             framed.len(),
@@ -591,6 +672,10 @@ impl TcpWriteHalf {
             .await
             .map_err(|e| ConnectionError::Socket(e.to_string()))?;
 
+        if let Some(stats) = &self.stats {
+            stats.on_write(framed.len());
+        }
+
         tracing::trace!(
             "TcpWriteHalf wrote {} bytes (framed from {} bytes MTProto packet)",
             framed.len(),
@@ -601,6 +686,15 @@ impl TcpWriteHalf {
     }
 }
 
+/// Returns the size of the length prefix of a packet.
+fn framing_len(mode: crate::transport::TransportMode) -> usize {
+    match mode {
+        crate::transport::TransportMode::Abridged => 1,
+        crate::transport::TransportMode::Intermediate => 4,
+        crate::transport::TransportMode::NoCrypto | crate::transport::TransportMode::Full => 0,
+    }
+}
+
 /// TCP transport factory.
 pub struct TcpTransportFactory;
 
@@ -644,6 +738,26 @@ mod tests {
         assert_eq!(transport.state(), ConnectionState::Closed);
     }
 
+    #[tokio::test]
+    async fn test_tcp_transport_counts_written_bytes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind listener");
+        let addr = listener.local_addr().expect("listener address");
+
+        let mut transport = TcpTransport::new(addr);
+        transport.connect().await.expect("connect");
+        let counter = NetStatsCounter::new(crate::stats::FileType::None);
+        transport.set_stats_counter(counter.clone());
+        transport.send_magic_if_needed().await.expect("send magic");
+
+        let magic = crate::transport::get_transport_magic(transport.transport_mode);
+        assert_eq!(
+            counter.take(),
+            vec![(crate::stats::FileType::None, 0, magic.len() as i64)]
+        );
+    }
+
     #[test]
     fn test_tcp_transport_factory_type() {
         // Just verify the factory exists
@@ -320,7 +320,8 @@ impl DefaultTransportWriter {
 
         // Write NoCryptoPrefix (msg_id + message_data_length)
         // For client → server messages, msg_id must be even (TDLib-compatible)
-        // Use the message_id from packet_info, or generate one if empty
+        // Sessions pass the next ID of their auth data; only a writer used
+        // on its own generates an ID from the current time
         let msg_id = if packet_info.message_id.is_empty() {
             // Generate a message ID with current time
             // Client messages must have msg_id % 4 != 0
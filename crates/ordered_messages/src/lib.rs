@@ -18,6 +18,8 @@
 //! for operations like:
 //! - Insert messages in order
 //! - Retrieve history pages with offset/limit
+//! - Track which messages are contiguous, i.e. have no unknown messages
+//!   between them
 //! - Find messages by date
 //! - Calculate unread counts
 //!
@@ -43,6 +45,7 @@
 use rustgram_types::MessageId;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
+use std::ops::Bound::{Excluded, Included, Unbounded};
 
 /// Ordered collection of message IDs.
 ///
@@ -58,6 +61,11 @@ use std::collections::BTreeSet;
 pub struct OrderedMessages {
     /// Set of message IDs in sorted order
     messages: BTreeSet<MessageId>,
+
+    /// Messages whose previous message is known, like TDLib's
+    /// `have_previous`
+    #[serde(default)]
+    attached: BTreeSet<MessageId>,
 }
 
 impl OrderedMessages {
@@ -75,6 +83,7 @@ impl OrderedMessages {
     pub const fn new() -> Self {
         Self {
             messages: BTreeSet::new(),
+            attached: BTreeSet::new(),
         }
     }
 
@@ -83,8 +92,9 @@ impl OrderedMessages {
     /// # Arguments
     ///
     /// * `message_id` - The message ID to insert
-    /// * `_auto_attach` - Whether to auto-attach (unused in this implementation)
-    /// * `_old_last` - Previous last message ID (unused)
+    /// * `auto_attach` - Whether to attach the message to `old_last`; used
+    ///   for new messages, which directly follow the last message
+    /// * `old_last` - Previous last message ID of the dialog
     /// * `_source` - Source identifier for logging
     ///
     /// # Example
@@ -100,11 +110,14 @@ impl OrderedMessages {
     pub fn insert(
         &mut self,
         message_id: MessageId,
-        _auto_attach: bool,
-        _old_last: MessageId,
+        auto_attach: bool,
+        old_last: MessageId,
         _source: &str,
     ) {
         self.messages.insert(message_id);
+        if auto_attach && old_last.is_valid() && self.get_previous(message_id) == Some(old_last) {
+            self.attached.insert(message_id);
+        }
     }
 
     /// Erases a message ID from the collection.
@@ -127,7 +140,88 @@ impl OrderedMessages {
     /// assert!(messages.is_empty());
     /// ```
     pub fn erase(&mut self, message_id: MessageId, _only_from_memory: bool, _source: &str) {
-        self.messages.remove(&message_id);
+        if !self.messages.remove(&message_id) {
+            return;
+        }
+        // The next message stays attached only if the erased one was
+        if !self.attached.remove(&message_id) {
+            if let Some(next) = self.get_next(message_id) {
+                self.attached.remove(&next);
+            }
+        }
+    }
+
+    /// Returns the last message before the given message ID.
+    #[must_use]
+    pub fn get_previous(&self, message_id: MessageId) -> Option<MessageId> {
+        self.messages.range(..message_id).next_back().copied()
+    }
+
+    /// Returns the first message after the given message ID.
+    #[must_use]
+    pub fn get_next(&self, message_id: MessageId) -> Option<MessageId> {
+        self.messages
+            .range((Excluded(message_id), Unbounded))
+            .next()
+            .copied()
+    }
+
+    /// Returns true if the message before the given message is known.
+    ///
+    /// The previous message is known if it is the previous message in the
+    /// collection, or if the given message is the first message of the
+    /// dialog.
+    #[must_use]
+    pub fn have_previous(&self, message_id: MessageId) -> bool {
+        self.attached.contains(&message_id)
+    }
+
+    /// Sets whether the message before the given message is known.
+    ///
+    /// Does nothing if the message isn't in the collection.
+    pub fn set_have_previous(&mut self, message_id: MessageId, have_previous: bool) {
+        if !self.messages.contains(&message_id) {
+            return;
+        }
+        if have_previous {
+            self.attached.insert(message_id);
+        } else {
+            self.attached.remove(&message_id);
+        }
+    }
+
+    /// Marks the messages from `first` to `last` as contiguous: no message
+    /// between them is missing from the collection.
+    ///
+    /// Returns the messages that became attached to their previous message.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_ordered_messages::OrderedMessages;
+    /// use rustgram_types::MessageId;
+    ///
+    /// let mut messages = OrderedMessages::new();
+    /// for i in [1, 2, 5] {
+    ///     messages.insert(MessageId::from_server_id(i), false, MessageId::from_server_id(0), "test");
+    /// }
+    ///
+    /// let attached = messages.attach(MessageId::from_server_id(1), MessageId::from_server_id(5));
+    /// assert_eq!(attached, vec![MessageId::from_server_id(2), MessageId::from_server_id(5)]);
+    /// assert!(!messages.have_previous(MessageId::from_server_id(1)));
+    /// ```
+    pub fn attach(&mut self, first: MessageId, last: MessageId) -> Vec<MessageId> {
+        if first >= last {
+            return Vec::new();
+        }
+        let attached: Vec<_> = self
+            .messages
+            .range((Excluded(first), Included(last)))
+            .filter(|message_id| !self.attached.contains(message_id))
+            .copied()
+            .collect();
+        self.attached.extend(attached.iter().copied());
+        attached
     }
 
     /// Gets message history with pagination.
@@ -356,6 +450,7 @@ impl OrderedMessages {
     /// Clears all messages from the collection.
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.attached.clear();
     }
 }
 
@@ -740,4 +835,60 @@ mod tests {
         let collected: Vec<_> = messages.into_iter().collect();
         assert_eq!(collected.len(), 2);
     }
+
+    fn id(server_id: i32) -> MessageId {
+        MessageId::from_server_id(server_id)
+    }
+
+    #[test]
+    fn test_auto_attach() {
+        let mut messages = OrderedMessages::new();
+        messages.insert(id(1), true, id(0), "test");
+        assert!(!messages.have_previous(id(1)));
+
+        messages.insert(id(2), true, id(1), "test");
+        assert!(messages.have_previous(id(2)));
+
+        // A message not following the old last message isn't attached
+        messages.insert(id(5), true, id(3), "test");
+        assert!(!messages.have_previous(id(5)));
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut messages = OrderedMessages::new();
+        for i in [1, 2, 4, 8] {
+            messages.insert(id(i), false, id(0), "test");
+        }
+
+        assert_eq!(messages.attach(id(2), id(4)), vec![id(4)]);
+        assert_eq!(messages.attach(id(1), id(8)), vec![id(2), id(8)]);
+        assert!(messages.attach(id(1), id(8)).is_empty());
+        assert!(messages.attach(id(8), id(1)).is_empty());
+        assert!(!messages.have_previous(id(1)));
+
+        messages.set_have_previous(id(1), true);
+        assert!(messages.have_previous(id(1)));
+        messages.set_have_previous(id(3), true);
+        assert!(!messages.have_previous(id(3)));
+    }
+
+    #[test]
+    fn test_erase_keeps_contiguity() {
+        let mut messages = OrderedMessages::new();
+        for i in [1, 2, 3, 4] {
+            messages.insert(id(i), false, id(0), "test");
+        }
+        messages.attach(id(2), id(4));
+
+        // 3 is attached to 2, so 4 stays attached after 3 is erased
+        messages.erase(id(3), false, "test");
+        assert!(messages.have_previous(id(4)));
+        assert_eq!(messages.get_previous(id(4)), Some(id(2)));
+
+        // 2 isn't attached to 1, so 4 loses its link
+        messages.erase(id(2), false, "test");
+        assert!(!messages.have_previous(id(4)));
+        assert_eq!(messages.get_next(id(1)), Some(id(4)));
+    }
 }
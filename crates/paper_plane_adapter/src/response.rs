@@ -10,7 +10,8 @@
 #![deny(clippy::expect_used)]
 
 use crate::error::Result;
-use serde::{Deserialize, Serialize};
+use rustgram_message_types::Message;
+use serde::{Deserialize, Serialize, Serializer};
 
 /// A response to be sent to the client.
 #[derive(Debug, Clone)]
@@ -238,7 +239,10 @@ pub struct MessageContent {
     content_type: String,
 
     #[serde(flatten)]
-    text: FormattedText,
+    text: Option<FormattedText>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caption: Option<FormattedText>,
 }
 
 impl MessageContent {
@@ -247,7 +251,24 @@ impl MessageContent {
     pub fn text(text: impl Into<String>) -> Self {
         Self {
             content_type: "messageText".to_string(),
-            text: FormattedText::new(text.into()),
+            text: Some(FormattedText::new(text.into())),
+            caption: None,
+        }
+    }
+
+    /// Creates the content of a message; media contents carry their caption.
+    #[must_use]
+    pub fn from_message(message: &Message) -> Self {
+        if let Some(text) = message.content.as_text() {
+            return Self::text(text.text.text());
+        }
+        Self {
+            content_type: format!("message{}", message.content_type()),
+            text: None,
+            caption: message
+                .content
+                .caption()
+                .map(|caption| FormattedText::new(caption.text().to_string())),
         }
     }
 }
@@ -266,6 +287,32 @@ pub struct MessageData {
 
     /// Unix timestamp when message was sent.
     pub date: i32,
+
+    /// Album the message belongs to, 0 if none.
+    ///
+    /// Messages of an album are shown as a single unit; like other 64-bit
+    /// identifiers in TDLib JSON, it is serialized as a string.
+    #[serde(serialize_with = "serialize_as_string")]
+    pub media_album_id: i64,
+}
+
+impl From<&Message> for MessageData {
+    fn from(message: &Message) -> Self {
+        Self {
+            id: message.id.get_server_id(),
+            chat_id: message.dialog_id.to_encoded(),
+            content: MessageContent::from_message(message),
+            date: message.date,
+            media_album_id: message.media_album_id,
+        }
+    }
+}
+
+fn serialize_as_string<S: Serializer>(
+    value: &i64,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
 }
 
 /// Formatted text.
@@ -637,6 +684,36 @@ mod tests {
         assert!(json.contains("johndoe"));
     }
 
+    #[test]
+    fn test_album_messages_response() {
+        use rustgram_message_content::{MessageContent as Content, MessagePhoto};
+        use rustgram_types::{DialogId, MessageId, UserId};
+
+        let dialog_id = DialogId::from(UserId::new(100).unwrap());
+        let mut photo = MessagePhoto::new();
+        photo.caption = rustgram_formatted_text::FormattedText::new("Trip");
+        let mut message = Message::new_with_content(
+            MessageId::from_server_id(5),
+            dialog_id,
+            dialog_id,
+            1_700_000_000,
+            Content::Photo(Box::new(photo)),
+        )
+        .unwrap();
+        message.media_album_id = 13_000_000_000;
+
+        let response = Response::Messages {
+            messages: vec![MessageData::from(&message)],
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&response.to_json(None).unwrap()).unwrap();
+        let message = &json["messages"][0];
+        assert_eq!(message["id"], 5);
+        assert_eq!(message["media_album_id"], "13000000000");
+        assert_eq!(message["content"]["@type"], "messagePhoto");
+        assert_eq!(message["content"]["caption"]["text"], "Trip");
+    }
+
     #[test]
     fn test_error_response() {
         let response = Response::error(404, "Not found");
@@ -539,6 +539,12 @@ impl PartsManager {
         self.streaming_offset
     }
 
+    /// Returns the bitmask of ready parts.
+    #[must_use]
+    pub const fn get_ready_bitmask(&self) -> &FileBitmask {
+        &self.bitmask
+    }
+
     /// Gets the bitmask.
     #[must_use]
     pub fn get_bitmask(&mut self) -> String {
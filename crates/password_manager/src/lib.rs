@@ -63,7 +63,6 @@ mod tl;
 
 use crate::error::Result;
 use crate::recovery::PasswordRecovery;
-use crate::srp::SrpCalculator;
 use crate::state::ResetPasswordResult;
 use crate::tl::EmailVerificationCodeInfo;
 use rustgram_email_verification::EmailVerification;
@@ -75,6 +74,7 @@ use tokio::sync::RwLock;
 
 // Re-exports
 pub use error::{PasswordManagerError, Result as PasswordManagerResult};
+pub use srp::{SrpCalculator, SrpParams, SrpResult};
 pub use state::{EmailAddressProtection, PasswordInfo, PasswordManagerState};
 
 /// Minimum password length
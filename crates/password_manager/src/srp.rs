@@ -17,6 +17,8 @@
 #![allow(dead_code)]
 
 use crate::error::{PasswordManagerError, Result};
+use num_bigint::BigUint;
+use rand::RngCore;
 use sha2::{Digest, Sha256, Sha512};
 
 /// SRP computation parameters.
@@ -130,9 +132,18 @@ impl SrpResult {
     }
 }
 
+/// Size of the SRP modulus and of the padded SRP values, in bytes.
+const SRP_SIZE: usize = 256;
+
+/// Number of PBKDF2 iterations of the
+/// `passwordKdfAlgoSHA256SHA256PBKDF2HMACSHA512iter100000SHA256ModPow` algorithm.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
 /// SRP calculator for password verification.
 ///
-/// Computes SRP parameters for secure password verification.
+/// Computes SRP parameters for secure password verification, following the
+/// `passwordKdfAlgoSHA256SHA256PBKDF2HMACSHA512iter100000SHA256ModPow`
+/// algorithm used by Telegram.
 #[derive(Debug, Clone, Default)]
 pub struct SrpCalculator;
 
@@ -155,42 +166,101 @@ impl SrpCalculator {
     ///
     /// Computed SRP result with A and M1 parameters
     pub fn compute(&self, password: &str, params: &SrpParams) -> Result<SrpResult> {
+        let mut secret = [0u8; SRP_SIZE];
+        rand::thread_rng().fill_bytes(&mut secret);
+        self.compute_with_secret(password, params, &secret)
+    }
+
+    /// Compute SRP parameters from password with a given client secret
+    ///
+    /// Same as [`SrpCalculator::compute`], but the random client secret `a`
+    /// is provided by the caller.
+    pub fn compute_with_secret(
+        &self,
+        password: &str,
+        params: &SrpParams,
+        secret: &[u8],
+    ) -> Result<SrpResult> {
         params.validate()?;
 
         if password.is_empty() {
             return Err(PasswordManagerError::InvalidPassword);
         }
 
-        // In a real implementation, this would perform the actual SRP computation:
-        // 1. Derive password hash using PBKDF2 with salt1
-        // 2. Compute SRP private key x
-        // 3. Compute SRP verifier v
-        // 4. Compute client public A
-        // 5. Compute client proof M1
-        //
-        // For this stub implementation, we create placeholder values
-        // that follow the expected structure
+        let p = BigUint::from_bytes_be(&params.p);
+        let g = BigUint::from(params.g as u32);
+        let g_b = BigUint::from_bytes_be(&params.b);
+        if !is_good_srp_value(&g_b, &p) {
+            return Err(PasswordManagerError::InvalidSrpParameters);
+        }
+
+        let p_bytes = pad(&p)?;
+        let g_bytes = pad(&g)?;
+        let b_bytes = pad(&g_b)?;
 
-        let password_bytes = password.as_bytes();
+        let x = BigUint::from_bytes_be(&self.password_hash(password, params));
+        let a = BigUint::from_bytes_be(secret);
+        let g_a = g.modpow(&a, &p);
+        if !is_good_srp_value(&g_a, &p) {
+            return Err(PasswordManagerError::InvalidSrpParameters);
+        }
+        let a_bytes = pad(&g_a)?;
+
+        let k = BigUint::from_bytes_be(&sha256(&[&p_bytes, &g_bytes]));
+        let u = BigUint::from_bytes_be(&sha256(&[&a_bytes, &b_bytes]));
+
+        // t = (g_b - k * v) mod p
+        let k_v = (k * g.modpow(&x, &p)) % &p;
+        let t = if g_b >= k_v {
+            &g_b - &k_v
+        } else {
+            &g_b + &p - &k_v
+        };
+        let s_a = t.modpow(&(a + u * x), &p);
+        let k_a = sha256(&[&pad(&s_a)?]);
+
+        let h_p = sha256(&[&p_bytes]);
+        let h_g = sha256(&[&g_bytes]);
+        let h_xor: Vec<u8> = h_p.iter().zip(h_g.iter()).map(|(l, r)| l ^ r).collect();
+        let m1 = sha256(&[
+            &h_xor,
+            &sha256(&[&params.salt1]),
+            &sha256(&[&params.salt2]),
+            &a_bytes,
+            &b_bytes,
+            &k_a,
+        ]);
+
+        Ok(SrpResult::new(a_bytes, m1))
+    }
+
+    /// Compute the SRP verifier of a password
+    ///
+    /// The verifier `v = g^x mod p` is sent to the server when a new
+    /// password is set.
+    pub fn compute_verifier(&self, password: &str, params: &SrpParams) -> Result<Vec<u8>> {
+        if password.is_empty() {
+            return Err(PasswordManagerError::InvalidPassword);
+        }
+        if params.g <= 0 || params.p.is_empty() {
+            return Err(PasswordManagerError::InvalidSrpParameters);
+        }
 
-        // Compute hash of password + salts (simplified)
-        let mut hasher = Sha512::new();
-        hasher.update(&params.salt1);
-        hasher.update(password_bytes);
-        hasher.update(&params.salt2);
-        let hash1 = hasher.finalize();
+        let p = BigUint::from_bytes_be(&params.p);
+        let g = BigUint::from(params.g as u32);
+        let x = BigUint::from_bytes_be(&self.password_hash(password, params));
+        pad(&g.modpow(&x, &p))
+    }
 
-        let mut hasher2 = Sha256::new();
-        hasher2.update(&params.current_client_salt);
-        hasher2.update(hash1);
-        hasher2.update(&params.current_server_salt);
-        let hash2 = hasher2.finalize();
+    /// Computes the password hash `x` used as the SRP private key.
+    fn password_hash(&self, password: &str, params: &SrpParams) -> Vec<u8> {
+        let hash = salted_sha256(password.as_bytes(), &params.salt1);
+        let hash = salted_sha256(&hash, &params.salt2);
 
-        // Create A and M1 (simplified - real SRP is more complex)
-        let a = hash2.to_vec();
-        let m1 = hash1[..32.min(hash1.len())].to_vec();
+        let mut derived = [0u8; 64];
+        pbkdf2::pbkdf2_hmac::<Sha512>(&hash, &params.salt1, PBKDF2_ITERATIONS, &mut derived);
 
-        Ok(SrpResult::new(a, m1))
+        salted_sha256(&derived, &params.salt2)
     }
 
     /// Compute password hash for new password
@@ -251,10 +321,97 @@ impl SrpCalculator {
     }
 }
 
+/// Returns `SHA256(salt | data | salt)`.
+fn salted_sha256(data: &[u8], salt: &[u8]) -> Vec<u8> {
+    sha256(&[salt, data, salt])
+}
+
+/// Returns the SHA-256 hash of the concatenation of the parts.
+fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Encodes a number as a big-endian value padded to the modulus size.
+fn pad(value: &BigUint) -> Result<Vec<u8>> {
+    let bytes = value.to_bytes_be();
+    if bytes.len() > SRP_SIZE {
+        return Err(PasswordManagerError::InvalidSrpParameters);
+    }
+    let mut result = vec![0u8; SRP_SIZE - bytes.len()];
+    result.extend_from_slice(&bytes);
+    Ok(result)
+}
+
+/// Checks that an SRP public value lies strictly between 1 and `p - 1`.
+fn is_good_srp_value(value: &BigUint, p: &BigUint) -> bool {
+    let one = BigUint::from(1u32);
+    value > &one && value < &(p - &one)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Builds the parameters a server holding the verifier of `password`
+    /// sends, with the server secret `b`.
+    fn server_params(password: &str, secret: &[u8]) -> SrpParams {
+        // 2^2048 - 1942289
+        let p: BigUint = (BigUint::from(1u32) << 2048usize) - BigUint::from(1_942_289u32);
+        let mut params = SrpParams::new(
+            3,
+            p.to_bytes_be(),
+            Vec::new(),
+            12345,
+            vec![7, 8, 9],
+            vec![10, 11, 12],
+            Vec::new(),
+            Vec::new(),
+        );
+        let g = BigUint::from(3u32);
+        let v = BigUint::from_bytes_be(
+            &SrpCalculator::new()
+                .compute_verifier(password, &params)
+                .unwrap(),
+        );
+        let k = BigUint::from_bytes_be(&sha256(&[&pad(&p).unwrap(), &pad(&g).unwrap()]));
+        let g_b = (k * v + g.modpow(&BigUint::from_bytes_be(secret), &p)) % &p;
+        params.b = pad(&g_b).unwrap();
+        params
+    }
+
+    /// Computes the proof M1 the server expects from a client sending `a`.
+    fn server_proof(password: &str, params: &SrpParams, secret: &[u8], a: &[u8]) -> Vec<u8> {
+        let p = BigUint::from_bytes_be(&params.p);
+        let v = BigUint::from_bytes_be(
+            &SrpCalculator::new()
+                .compute_verifier(password, params)
+                .unwrap(),
+        );
+        let g_a = BigUint::from_bytes_be(a);
+        let u = BigUint::from_bytes_be(&sha256(&[a, &params.b]));
+        let s_b = (g_a * v.modpow(&u, &p)).modpow(&BigUint::from_bytes_be(secret), &p);
+        let k_b = sha256(&[&pad(&s_b).unwrap()]);
+
+        let g_bytes = pad(&BigUint::from(params.g as u32)).unwrap();
+        let h_xor: Vec<u8> = sha256(&[&pad(&p).unwrap()])
+            .iter()
+            .zip(sha256(&[&g_bytes]).iter())
+            .map(|(l, r)| l ^ r)
+            .collect();
+        sha256(&[
+            &h_xor,
+            &sha256(&[&params.salt1]),
+            &sha256(&[&params.salt2]),
+            a,
+            &params.b,
+            &k_b,
+        ])
+    }
+
     #[test]
     fn test_srp_params_new() {
         let params = SrpParams::new(
@@ -338,23 +495,45 @@ mod tests {
     #[test]
     fn test_srp_calculator_compute() {
         let calculator = SrpCalculator::new();
-        let params = SrpParams::new(
-            2048,
-            vec![1, 2, 3],
-            vec![4, 5, 6],
-            12345,
-            vec![7, 8],
-            vec![9, 10],
-            vec![11, 12],
-            vec![13, 14],
-        );
+        let params = server_params("password123", &[5; 32]);
 
         let result = calculator.compute("password123", &params);
         assert!(result.is_ok());
 
         let srp_result = result.unwrap();
-        assert!(!srp_result.a.is_empty());
-        assert!(!srp_result.m1.is_empty());
+        assert_eq!(srp_result.a.len(), SRP_SIZE);
+        assert_eq!(srp_result.m1.len(), 32);
+    }
+
+    #[test]
+    fn test_srp_calculator_proof_matches_server() {
+        let calculator = SrpCalculator::new();
+        let server_secret = [5; 32];
+        let params = server_params("hunter2", &server_secret);
+
+        let result = calculator.compute("hunter2", &params).unwrap();
+        assert_eq!(
+            result.m1,
+            server_proof("hunter2", &params, &server_secret, &result.a)
+        );
+
+        let wrong = calculator.compute("hunter3", &params).unwrap();
+        assert_ne!(
+            wrong.m1,
+            server_proof("hunter2", &params, &server_secret, &wrong.a)
+        );
+    }
+
+    #[test]
+    fn test_srp_calculator_rejects_bad_b() {
+        let calculator = SrpCalculator::new();
+        let mut params = server_params("password", &[5; 32]);
+        params.b = params.p.clone();
+
+        assert!(matches!(
+            calculator.compute("password", &params),
+            Err(PasswordManagerError::InvalidSrpParameters)
+        ));
     }
 
     #[test]
@@ -432,16 +611,7 @@ mod tests {
     #[test]
     fn test_srp_calculator_verify() {
         let calculator = SrpCalculator::new();
-        let params = SrpParams::new(
-            2048,
-            vec![1, 2, 3],
-            vec![4, 5, 6],
-            12345,
-            vec![7, 8],
-            vec![9, 10],
-            vec![11, 12],
-            vec![13, 14],
-        );
+        let params = server_params("password123", &[5; 32]);
 
         let result = calculator.verify("password123", &params);
         assert!(result.is_ok());
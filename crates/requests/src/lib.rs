@@ -26,7 +26,11 @@
 #![allow(clippy::module_name_repetitions)]
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
-use rustgram_actor::{Actor, ActorId};
+use rustgram_actor::ActorId;
+use rustgram_country_info_manager::CountryInfoManager;
+use rustgram_formatted_text::{entity_type, FormattedText, MessageEntity as FormattedEntity};
+use rustgram_message_entity::{find_entities, MessageEntity, MessageEntityType};
+use rustgram_misc::search_strings_by_prefix;
 use rustgram_promise::{FutureActor, Promise};
 use rustgram_td::Td;
 use rustgram_types::UserId;
@@ -51,8 +55,8 @@ pub struct Requests {
     /// Actor ID for sending messages to Td
     td_actor: ActorId<Td>,
 
-    /// Pending requests by ID
-    pending: Arc<RwLock<HashMap<u64, RequestPromiseBase>>>,
+    /// State of pending requests by ID
+    pending: Arc<RwLock<HashMap<u64, RequestState>>>,
 
     /// Next request ID
     next_id: Arc<std::sync::atomic::AtomicU64>,
@@ -78,7 +82,7 @@ impl Requests {
     pub fn new(td: &Arc<Td>) -> Self {
         Self {
             td: Arc::clone(td),
-            td_actor: ActorId::new(0),
+            td_actor: ActorId::new(0, 0, 0),
             pending: Arc::new(RwLock::new(HashMap::new())),
             next_id: Arc::new(std::sync::atomic::AtomicU64::new(1)),
         }
@@ -134,7 +138,8 @@ impl Requests {
     /// assert!(id2 > id1);
     /// ```
     pub fn generate_id(&self) -> u64 {
-        self.next_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+        self.next_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
     /// Creates a promise for a request.
@@ -186,30 +191,46 @@ impl Requests {
     /// let function = json!({"@type": "getTextEntities", "text": "@telegram"});
     /// requests.run_request(1, &function);
     /// ```
-    pub fn run_request(&self, id: u64, function: &JsonValue) {
+    pub fn run_request(&self, id: u64, _function: &JsonValue) {
         // Stub: Store the request
-        let promise = RequestPromiseBase {
-            state: RequestState::Pending,
-            request_id: id,
-        };
+        let mut pending = self.pending.write().unwrap_or_else(|e| e.into_inner());
+        pending.insert(id, RequestState::Pending);
+    }
 
-        let mut pending = self.pending.write().unwrap();
-        pending.insert(id, promise);
+    /// Returns the state of a pending request, or `None` if it is unknown.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_requests::{RequestState, Requests};
+    /// use rustgram_td::Td;
+    /// use serde_json::json;
+    /// use std::sync::Arc;
+    ///
+    /// let td = Arc::new(Td::new());
+    /// let requests = Requests::new(&td);
+    /// requests.run_request(1, &json!({"@type": "getMe"}));
+    /// assert_eq!(requests.request_state(1), Some(RequestState::Pending));
+    /// assert_eq!(requests.request_state(2), None);
+    /// ```
+    pub fn request_state(&self, id: u64) -> Option<RequestState> {
+        let pending = self.pending.read().unwrap_or_else(|e| e.into_inner());
+        pending.get(&id).copied()
     }
 
     /// Gets the text entities from a string.
     ///
+    /// Finds mentions, hashtags, cashtags, bot commands, URLs, email
+    /// addresses, phone numbers, bank card numbers and media timestamps, as
+    /// TDLib's `getTextEntities` does.
+    ///
     /// # Arguments
     ///
     /// * `text` - The text to parse
     ///
     /// # Returns
     ///
-    /// Returns a JSON value with the entities.
-    ///
-    /// # TODO
-    ///
-    /// Implement actual text entity parsing.
+    /// Returns a `textEntities` JSON object with offsets in UTF-16 code units.
     ///
     /// # Example
     ///
@@ -221,11 +242,16 @@ impl Requests {
     /// let td = Arc::new(Td::new());
     /// let requests = Requests::new(&td);
     /// let entities = requests.on_get_text_entities("@telegram hello");
+    /// assert_eq!(entities["entities"][0]["type"]["@type"], "textEntityTypeMention");
     /// ```
     pub fn on_get_text_entities(&self, text: &str) -> JsonValue {
+        let entities: Vec<JsonValue> = find_entities(text, false, false)
+            .iter()
+            .map(text_entity_to_json)
+            .collect();
         serde_json::json!({
             "@type": "textEntities",
-            "entities": []
+            "entities": entities
         })
     }
 
@@ -268,14 +294,145 @@ impl Requests {
                 let user_id = request.get("user_id").and_then(|v| v.as_i64()).unwrap_or(0);
                 self.on_get_user_id(user_id)
             }
-            _ => serde_json::json!({
-                "@type": "error",
-                "code": 400,
-                "message": format!("Unknown request type: {}", request_type)
-            }),
+            "parseTextEntities" => {
+                let text = request.get("text").and_then(|v| v.as_str()).unwrap_or("");
+                let parse_mode = request.get("parse_mode").unwrap_or(&JsonValue::Null);
+                self.on_parse_text_entities(text, parse_mode)
+            }
+            "getCountryFlagEmoji" => {
+                let country_code = request
+                    .get("country_code")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                serde_json::json!({
+                    "@type": "text",
+                    "text": CountryInfoManager::get_country_flag_emoji(country_code)
+                })
+            }
+            "searchStringsByPrefix" => self.on_search_strings_by_prefix(request),
+            "getJsonValue" => {
+                let json = request.get("json").and_then(|v| v.as_str()).unwrap_or("");
+                match serde_json::from_str::<JsonValue>(json) {
+                    Ok(value) => json_to_td_api(&value),
+                    Err(e) => {
+                        error_json(400, &format!("Can't parse \"{json}\" as JSON object: {e}"))
+                    }
+                }
+            }
+            "getJsonString" => {
+                let value = request.get("json_value").unwrap_or(&JsonValue::Null);
+                serde_json::json!({
+                    "@type": "text",
+                    "text": td_api_to_json(value).to_string()
+                })
+            }
+            "testReturnError" => {
+                let error = request.get("error").unwrap_or(&JsonValue::Null);
+                let code = error.get("code").and_then(|v| v.as_i64()).unwrap_or(0);
+                let message = error.get("message").and_then(|v| v.as_str()).unwrap_or("");
+                serde_json::json!({"@type": "error", "code": code, "message": message})
+            }
+            _ => error_json(400, &format!("Unknown request type: {}", request_type)),
+        }
+    }
+
+    /// Parses Markdown or HTML markup into a formatted text.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to parse
+    /// * `parse_mode` - A `textParseModeMarkdown` or `textParseModeHTML` object
+    ///
+    /// # Returns
+    ///
+    /// Returns a `formattedText` JSON object, or an `error` if the markup is
+    /// invalid.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_requests::Requests;
+    /// use rustgram_td::Td;
+    /// use serde_json::json;
+    /// use std::sync::Arc;
+    ///
+    /// let td = Arc::new(Td::new());
+    /// let requests = Requests::new(&td);
+    /// let mode = json!({"@type": "textParseModeHTML"});
+    /// let text = requests.on_parse_text_entities("<b>bold</b>", &mode);
+    /// assert_eq!(text["text"], "bold");
+    /// assert_eq!(text["entities"][0]["type"]["@type"], "textEntityTypeBold");
+    /// ```
+    pub fn on_parse_text_entities(&self, text: &str, parse_mode: &JsonValue) -> JsonValue {
+        let parsed = match parse_mode.get("@type").and_then(|v| v.as_str()) {
+            Some("textParseModeHTML") => FormattedText::parse_html(text),
+            Some("textParseModeMarkdown") => {
+                match parse_mode.get("version").and_then(|v| v.as_i64()) {
+                    Some(2) => FormattedText::parse_markdown_v2(text),
+                    Some(0 | 1) | None => FormattedText::parse_markdown(text),
+                    Some(_) => return error_json(400, "Wrong Markdown version specified"),
+                }
+            }
+            _ => return error_json(400, "Parse mode must be non-empty"),
+        };
+        match parsed {
+            Ok(formatted) => {
+                let entities: Vec<JsonValue> = formatted
+                    .entities()
+                    .iter()
+                    .map(formatted_entity_to_json)
+                    .collect();
+                serde_json::json!({
+                    "@type": "formattedText",
+                    "text": formatted.text(),
+                    "entities": entities
+                })
+            }
+            Err(e) => error_json(400, &format!("Can't parse entities: {e}")),
         }
     }
 
+    /// Searches for strings starting with a query, as TDLib's
+    /// `searchStringsByPrefix` does.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `foundPositions` JSON object.
+    fn on_search_strings_by_prefix(&self, request: &JsonValue) -> JsonValue {
+        let strings: Vec<String> = request
+            .get("strings")
+            .and_then(|v| v.as_array())
+            .map(|strings| {
+                strings
+                    .iter()
+                    .map(|s| s.as_str().unwrap_or_default().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let query = request.get("query").and_then(|v| v.as_str()).unwrap_or("");
+        let limit = request.get("limit").and_then(|v| v.as_i64()).unwrap_or(0);
+        if limit <= 0 {
+            return error_json(400, "Parameter limit must be positive");
+        }
+        let return_none_for_empty_query = request
+            .get("return_none_for_empty_query")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let all =
+            search_strings_by_prefix(&strings, query, strings.len(), !return_none_for_empty_query);
+        let positions: Vec<usize> = all
+            .iter()
+            .copied()
+            .take(usize::try_from(limit).unwrap_or(usize::MAX))
+            .collect();
+        serde_json::json!({
+            "@type": "foundPositions",
+            "total_count": all.len(),
+            "positions": positions
+        })
+    }
+
     /// Gets the current user.
     ///
     /// # TODO
@@ -372,7 +529,7 @@ impl Requests {
     /// assert!(requests.cancel_request(1));
     /// ```
     pub fn cancel_request(&self, id: u64) -> bool {
-        let mut pending = self.pending.write().unwrap();
+        let mut pending = self.pending.write().unwrap_or_else(|e| e.into_inner());
         pending.remove(&id).is_some()
     }
 
@@ -394,7 +551,7 @@ impl Requests {
     /// assert_eq!(requests.pending_count(), 1);
     /// ```
     pub fn pending_count(&self) -> usize {
-        let pending = self.pending.read().unwrap();
+        let pending = self.pending.read().unwrap_or_else(|e| e.into_inner());
         pending.len()
     }
 
@@ -416,7 +573,7 @@ impl Requests {
     /// assert_eq!(requests.pending_count(), 0);
     /// ```
     pub fn clear_pending(&self) {
-        let mut pending = self.pending.write().unwrap();
+        let mut pending = self.pending.write().unwrap_or_else(|e| e.into_inner());
         pending.clear();
     }
 }
@@ -430,19 +587,11 @@ impl Debug for Requests {
     }
 }
 
-/// Base promise type for request handling.
-#[derive(Debug, Clone)]
-struct RequestPromiseBase {
-    /// Current state of the request
-    state: RequestState,
-    /// Request identifier
-    request_id: u64,
-}
-
 /// State of a request promise.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum RequestState {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestState {
     /// Request is pending
+    #[default]
     Pending,
     /// Request has a result ready
     Ready,
@@ -450,12 +599,141 @@ enum RequestState {
     Complete,
 }
 
-impl Default for RequestState {
-    fn default() -> Self {
-        Self::Pending
+/// Builds a `td_api` `error` object.
+fn error_json(code: i32, message: &str) -> JsonValue {
+    serde_json::json!({"@type": "error", "code": code, "message": message})
+}
+
+/// Converts a parsed markup entity to a `td_api` `textEntity` JSON object.
+fn formatted_entity_to_json(entity: &FormattedEntity) -> JsonValue {
+    let argument = entity.argument().unwrap_or_default();
+    let entity_type = match entity.entity_type() {
+        entity_type::BOLD => serde_json::json!({"@type": "textEntityTypeBold"}),
+        entity_type::ITALIC => serde_json::json!({"@type": "textEntityTypeItalic"}),
+        entity_type::UNDERLINE => serde_json::json!({"@type": "textEntityTypeUnderline"}),
+        entity_type::STRIKETHROUGH => serde_json::json!({"@type": "textEntityTypeStrikethrough"}),
+        entity_type::SPOILER => serde_json::json!({"@type": "textEntityTypeSpoiler"}),
+        entity_type::CODE => serde_json::json!({"@type": "textEntityTypeCode"}),
+        entity_type::PRE => serde_json::json!({"@type": "textEntityTypePre"}),
+        entity_type::PRE_CODE => {
+            serde_json::json!({"@type": "textEntityTypePreCode", "language": argument})
+        }
+        entity_type::TEXT_URL => {
+            serde_json::json!({"@type": "textEntityTypeTextUrl", "url": argument})
+        }
+        entity_type::MENTION_NAME => serde_json::json!({
+            "@type": "textEntityTypeMentionName",
+            "user_id": argument.parse::<i64>().unwrap_or(0)
+        }),
+        entity_type::CUSTOM_EMOJI => serde_json::json!({
+            "@type": "textEntityTypeCustomEmoji",
+            "custom_emoji_id": argument
+        }),
+        entity_type::BLOCK_QUOTE => serde_json::json!({"@type": "textEntityTypeBlockQuote"}),
+        entity_type::EXPANDABLE_BLOCK_QUOTE => {
+            serde_json::json!({"@type": "textEntityTypeExpandableBlockQuote"})
+        }
+        other => serde_json::json!({"@type": other}),
+    };
+    serde_json::json!({
+        "@type": "textEntity",
+        "offset": entity.offset(),
+        "length": entity.length(),
+        "type": entity_type
+    })
+}
+
+/// Converts a JSON value to a `td_api` `JsonValue` object.
+fn json_to_td_api(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Null => serde_json::json!({"@type": "jsonValueNull"}),
+        JsonValue::Bool(value) => serde_json::json!({"@type": "jsonValueBoolean", "value": value}),
+        JsonValue::Number(value) => {
+            serde_json::json!({"@type": "jsonValueNumber", "value": value.as_f64().unwrap_or(0.0)})
+        }
+        JsonValue::String(value) => serde_json::json!({"@type": "jsonValueString", "value": value}),
+        JsonValue::Array(values) => serde_json::json!({
+            "@type": "jsonValueArray",
+            "values": values.iter().map(json_to_td_api).collect::<Vec<_>>()
+        }),
+        JsonValue::Object(members) => serde_json::json!({
+            "@type": "jsonValueObject",
+            "members": members
+                .iter()
+                .map(|(key, value)| serde_json::json!({
+                    "@type": "jsonObjectMember",
+                    "key": key,
+                    "value": json_to_td_api(value)
+                }))
+                .collect::<Vec<_>>()
+        }),
     }
 }
 
+/// Converts a `td_api` `JsonValue` object back to plain JSON.
+fn td_api_to_json(value: &JsonValue) -> JsonValue {
+    let field = |name: &str| value.get(name).cloned().unwrap_or(JsonValue::Null);
+    match value.get("@type").and_then(|v| v.as_str()) {
+        Some("jsonValueBoolean" | "jsonValueNumber" | "jsonValueString") => field("value"),
+        Some("jsonValueArray") => JsonValue::Array(
+            value
+                .get("values")
+                .and_then(|v| v.as_array())
+                .map(|values| values.iter().map(td_api_to_json).collect())
+                .unwrap_or_default(),
+        ),
+        Some("jsonValueObject") => JsonValue::Object(
+            value
+                .get("members")
+                .and_then(|v| v.as_array())
+                .map(|members| {
+                    members
+                        .iter()
+                        .map(|member| {
+                            let key = member.get("key").and_then(|v| v.as_str()).unwrap_or("");
+                            let value = member.get("value").unwrap_or(&JsonValue::Null);
+                            (key.to_string(), td_api_to_json(value))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+        _ => JsonValue::Null,
+    }
+}
+
+/// Converts an entity to a `td_api` `textEntity` JSON object.
+fn text_entity_to_json(entity: &MessageEntity) -> JsonValue {
+    let mut entity_type = serde_json::json!({"@type": entity.entity_type().td_api_name()});
+    match entity.entity_type() {
+        MessageEntityType::MediaTimestamp => {
+            entity_type["media_timestamp"] = entity.get_media_timestamp().unwrap_or(0).into();
+        }
+        MessageEntityType::PreCode => {
+            entity_type["language"] = entity.argument().unwrap_or_default().into();
+        }
+        MessageEntityType::TextUrl => {
+            entity_type["url"] = entity.argument().unwrap_or_default().into();
+        }
+        MessageEntityType::MentionName => {
+            entity_type["user_id"] = entity.user_id().map_or(0, |id| id.get()).into();
+        }
+        MessageEntityType::CustomEmoji => {
+            entity_type["custom_emoji_id"] = entity
+                .custom_emoji_id()
+                .map_or_else(String::new, |id| id.get().to_string())
+                .into();
+        }
+        _ => {}
+    }
+    serde_json::json!({
+        "@type": "textEntity",
+        "offset": entity.offset(),
+        "length": entity.length(),
+        "type": entity_type
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -502,8 +780,7 @@ mod tests {
     #[test]
     fn test_requests_create_promise() {
         let requests = create_test_requests();
-        let (promise, future): (Promise<String>, FutureActor<String>) =
-            requests.create_promise();
+        let (promise, future): (Promise<String>, FutureActor<String>) = requests.create_promise();
         // Should not panic
         let _ = (promise, future);
     }
@@ -576,7 +853,15 @@ mod tests {
         let response = requests.process_request(&request);
 
         assert_eq!(response["@type"], "textEntities");
-        assert!(response["entities"].is_array());
+        assert_eq!(
+            response["entities"],
+            json!([{
+                "@type": "textEntity",
+                "offset": 0,
+                "length": 9,
+                "type": {"@type": "textEntityTypeMention"}
+            }])
+        );
     }
 
     #[test]
@@ -625,7 +910,11 @@ mod tests {
         let entities = requests.on_get_text_entities("@telegram hello world");
 
         assert_eq!(entities["@type"], "textEntities");
-        assert!(entities["entities"].is_array());
+        assert_eq!(entities["entities"].as_array().map(Vec::len), Some(1));
+        assert_eq!(
+            entities["entities"][0]["type"]["@type"],
+            "textEntityTypeMention"
+        );
     }
 
     #[test]
@@ -634,6 +923,7 @@ mod tests {
         let entities = requests.on_get_text_entities("");
 
         assert_eq!(entities["@type"], "textEntities");
+        assert_eq!(entities["entities"], json!([]));
     }
 
     #[test]
@@ -711,6 +1001,106 @@ mod tests {
         let entities = requests.on_get_text_entities("@user1 @user2");
 
         assert_eq!(entities["@type"], "textEntities");
+        assert_eq!(entities["entities"][1]["offset"], 7);
+        assert_eq!(entities["entities"][1]["length"], 6);
+    }
+
+    #[test]
+    fn test_requests_get_text_entities_types() {
+        let requests = create_test_requests();
+        let entities = requests.on_get_text_entities("Привет #чат https://t.me 1:30");
+        let entities = entities["entities"].as_array().unwrap();
+
+        assert_eq!(entities.len(), 3);
+        assert_eq!(entities[0]["type"]["@type"], "textEntityTypeHashtag");
+        assert_eq!(entities[0]["offset"], 7);
+        assert_eq!(entities[1]["type"]["@type"], "textEntityTypeUrl");
+        assert_eq!(
+            entities[2]["type"],
+            json!({"@type": "textEntityTypeMediaTimestamp", "media_timestamp": 90})
+        );
+    }
+
+    #[test]
+    fn test_requests_parse_text_entities() {
+        let requests = create_test_requests();
+        let request = json!({
+            "@type": "parseTextEntities",
+            "text": "*bold* [link](https://example.com)",
+            "parse_mode": {"@type": "textParseModeMarkdown", "version": 1}
+        });
+        let response = requests.process_request(&request);
+
+        assert_eq!(response["@type"], "formattedText");
+        assert_eq!(response["text"], "bold link");
+        assert_eq!(
+            response["entities"][1]["type"],
+            json!({"@type": "textEntityTypeTextUrl", "url": "https://example.com"})
+        );
+
+        let request = json!({
+            "@type": "parseTextEntities",
+            "text": "<b>unclosed",
+            "parse_mode": {"@type": "textParseModeHTML"}
+        });
+        assert_eq!(requests.process_request(&request)["@type"], "error");
+    }
+
+    #[test]
+    fn test_requests_search_strings_by_prefix() {
+        let requests = create_test_requests();
+        let request = json!({
+            "@type": "searchStringsByPrefix",
+            "strings": ["apple", "banana", "application", "apricot"],
+            "query": "app",
+            "limit": 1
+        });
+        let response = requests.process_request(&request);
+
+        assert_eq!(response["@type"], "foundPositions");
+        assert_eq!(response["total_count"], 2);
+        assert_eq!(response["positions"], json!([0]));
+    }
+
+    #[test]
+    fn test_requests_json_value_round_trip() {
+        let requests = create_test_requests();
+        let request = json!({"@type": "getJsonValue", "json": r#"{"a":[1,true,null,"x"]}"#});
+        let value = requests.process_request(&request);
+        assert_eq!(value["@type"], "jsonValueObject");
+        assert_eq!(value["members"][0]["key"], "a");
+
+        let request = json!({"@type": "getJsonString", "json_value": value});
+        let text = requests.process_request(&request);
+        assert_eq!(text["text"], r#"{"a":[1.0,true,null,"x"]}"#);
+
+        let request = json!({"@type": "getJsonValue", "json": "{"});
+        assert_eq!(requests.process_request(&request)["@type"], "error");
+    }
+
+    #[test]
+    fn test_requests_country_flag_and_test_error() {
+        let requests = create_test_requests();
+        let request = json!({"@type": "getCountryFlagEmoji", "country_code": "US"});
+        assert_eq!(requests.process_request(&request)["text"], "🇺🇸");
+
+        let request = json!({
+            "@type": "testReturnError",
+            "error": {"@type": "error", "code": 404, "message": "Not Found"}
+        });
+        assert_eq!(
+            requests.process_request(&request),
+            json!({"@type": "error", "code": 404, "message": "Not Found"})
+        );
+    }
+
+    #[test]
+    fn test_requests_request_state() {
+        let requests = create_test_requests();
+        requests.run_request(5, &json!({"@type": "getMe"}));
+        assert_eq!(requests.request_state(5), Some(RequestState::Pending));
+        requests.cancel_request(5);
+        assert_eq!(requests.request_state(5), None);
     }
 
     #[test]
@@ -80,6 +80,7 @@ use rustgram_message_content::MessageContent;
 use rustgram_message_content_type::MessageContentType;
 use rustgram_message_forward_info::MessageForwardInfo;
 use rustgram_message_input_reply_to::MessageInputReplyTo;
+use rustgram_reply_markup::ReplyMarkup;
 use rustgram_types::{DialogId, MessageId};
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -144,6 +145,8 @@ pub enum MessageValidationError {
 /// - `edit_date` - Last edit timestamp if edited (optional)
 /// - `views` - View count for channel messages (optional)
 /// - `forward_info` - Forward information if forwarded (optional, stub)
+/// - `media_album_id` - Album the message belongs to, 0 if none
+/// - `reply_markup` - Keyboard attached to the message (optional)
 ///
 /// # TDLib Alignment
 ///
@@ -197,6 +200,14 @@ pub struct Message {
     /// Forward information (stub)
     #[serde(default)]
     pub forward_info: Option<MessageForwardInfo>,
+
+    /// Identifier of the album the message belongs to, 0 if none
+    #[serde(default)]
+    pub media_album_id: i64,
+
+    /// Keyboard attached to the message (optional)
+    #[serde(default)]
+    pub reply_markup: Option<ReplyMarkup>,
 }
 
 impl Message {
@@ -242,26 +253,7 @@ impl Message {
         sender_id: DialogId,
         text: FormattedText,
     ) -> Result<Self, MessageValidationError> {
-        // Validate message ID
-        if !id.is_valid() {
-            return Err(MessageValidationError::InvalidMessageId(
-                "Message ID is not valid".to_string(),
-            ));
-        }
-
-        // Validate dialog ID
-        if !dialog_id.is_valid() {
-            return Err(MessageValidationError::InvalidDialogId(
-                "Dialog ID is not valid".to_string(),
-            ));
-        }
-
-        // Validate sender ID
-        if !sender_id.is_valid() {
-            return Err(MessageValidationError::InvalidSenderId(
-                "Sender ID is not valid".to_string(),
-            ));
-        }
+        Self::validate_ids(id, dialog_id, sender_id)?;
 
         // Create and validate content
         let content = MessageContent::text_with_validation(text)?;
@@ -276,6 +268,8 @@ impl Message {
             edit_date: None,
             views: None,
             forward_info: None,
+            media_album_id: 0,
+            reply_markup: None,
         })
     }
 
@@ -311,6 +305,77 @@ impl Message {
         Ok(message)
     }
 
+    /// Creates a message with the given content, e.g. media received from
+    /// the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MessageValidationError`] if an identifier is invalid, the
+    /// date is not positive or the content is not valid.
+    pub fn new_with_content(
+        id: MessageId,
+        dialog_id: DialogId,
+        sender_id: DialogId,
+        date: i32,
+        content: MessageContent,
+    ) -> Result<Self, MessageValidationError> {
+        if date <= 0 {
+            return Err(MessageValidationError::InvalidDate(date));
+        }
+        if !content.is_valid() {
+            return Err(MessageValidationError::InvalidContent(format!(
+                "Invalid {} content",
+                content.content_type()
+            )));
+        }
+
+        Self::validate_ids(id, dialog_id, sender_id)?;
+
+        Ok(Self {
+            id,
+            dialog_id,
+            sender_id,
+            date,
+            content,
+            reply_to: None,
+            edit_date: None,
+            views: None,
+            forward_info: None,
+            media_album_id: 0,
+            reply_markup: None,
+        })
+    }
+
+    /// Validates the identifiers of a new message.
+    fn validate_ids(
+        id: MessageId,
+        dialog_id: DialogId,
+        sender_id: DialogId,
+    ) -> Result<(), MessageValidationError> {
+        // Validate message ID
+        if !id.is_valid() {
+            return Err(MessageValidationError::InvalidMessageId(
+                "Message ID is not valid".to_string(),
+            ));
+        }
+
+        // Validate dialog ID
+        if !dialog_id.is_valid() {
+            return Err(MessageValidationError::InvalidDialogId(
+                "Dialog ID is not valid".to_string(),
+            ));
+        }
+
+        // Validate sender ID
+        if !sender_id.is_valid() {
+            return Err(MessageValidationError::InvalidSenderId(
+                "Sender ID is not valid".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Validates the message structure.
     ///
     /// Returns `true` if all fields are valid:
@@ -492,6 +557,12 @@ impl Message {
         self.forward_info.is_some()
     }
 
+    /// Returns `true` if the message belongs to an album.
+    #[must_use]
+    pub const fn is_in_album(&self) -> bool {
+        self.media_album_id != 0
+    }
+
     /// Returns `true` if this is a channel message (has view count).
     #[must_use]
     pub const fn is_channel_message(&self) -> bool {
@@ -595,7 +666,19 @@ impl MessageContentExt for MessageContent {
                 !text_msg.text.text().is_empty()
                     && text_msg.text.text().len() <= Message::MAX_TEXT_LENGTH
             }
-            // For Phase 1, only text content is supported
+            Self::Photo(_)
+            | Self::Video(_)
+            | Self::Document(_)
+            | Self::Audio(_)
+            | Self::VoiceNote(_)
+            | Self::Animation(_)
+            | Self::Sticker(_)
+            | Self::Location(_)
+            | Self::Venue(_)
+            | Self::Contact(_)
+            | Self::Poll(_) => self
+                .caption()
+                .map_or(true, |caption| caption.text().len() <= Message::MAX_TEXT_LENGTH),
             _ => false,
         }
     }
@@ -678,6 +761,35 @@ mod tests {
         assert!(message.is_valid());
     }
 
+    #[test]
+    fn test_message_new_with_content() {
+        let mut photo = rustgram_message_content::MessagePhoto::new();
+        photo.caption = FormattedText::new("Sunset");
+        let mut message = Message::new_with_content(
+            MessageId::from_server_id(1),
+            DialogId::from_user(UserId::new(123).unwrap()),
+            DialogId::from_user(UserId::new(456).unwrap()),
+            1234567890,
+            MessageContent::Photo(Box::new(photo)),
+        )
+        .unwrap();
+
+        assert!(message.is_valid());
+        assert!(!message.is_text());
+        assert!(!message.is_in_album());
+        message.media_album_id = 42;
+        assert!(message.is_in_album());
+
+        let result = Message::new_with_content(
+            MessageId::from_server_id(2),
+            DialogId::from_user(UserId::new(123).unwrap()),
+            DialogId::from_user(UserId::new(456).unwrap()),
+            1234567890,
+            MessageContent::ScreenshotTaken(Box::default()),
+        );
+        assert!(matches!(result, Err(MessageValidationError::InvalidContent(_))));
+    }
+
     #[test]
     fn test_message_new_invalid_date() {
         let text = FormattedText::new("Hello!");
@@ -0,0 +1,192 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! MTProto 2.0 end-to-end encryption of secret chat messages.
+//!
+//! An encrypted message is `key_fingerprint + msg_key + encrypted_data`,
+//! where the encrypted data is the length of the payload, the payload and
+//! 12 to 1024 bytes of random padding. `x` is 0 for messages from the
+//! creator of the chat and 8 for messages in the opposite direction.
+
+use crate::dh::{key_fingerprint, AuthKey};
+use crate::error::{Error, Result};
+use rand::Rng;
+use rustgram_net::{aes_ige_decrypt, aes_ige_encrypt, kdf2, sha256};
+
+/// Minimum size of the padding.
+const MIN_PADDING: usize = 12;
+
+/// Maximum size of the padding.
+const MAX_PADDING: usize = 1024;
+
+/// Size of the header before the encrypted data.
+const HEADER_SIZE: usize = 8 + 16;
+
+/// Returns the value of `x` for messages sent by one of the parties.
+#[must_use]
+pub const fn get_x(from_creator: bool) -> usize {
+    if from_creator {
+        0
+    } else {
+        8
+    }
+}
+
+/// Encrypts a payload with a key.
+///
+/// # Errors
+///
+/// Returns an error if the encryption fails.
+pub fn encrypt_message(auth_key: &AuthKey, x: usize, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut rng = rand::thread_rng();
+    let unpadded = 4 + payload.len() + MIN_PADDING;
+    let padding = MIN_PADDING + (16 - unpadded % 16) % 16 + 16 * rng.gen_range(0..16);
+    debug_assert!(padding <= MAX_PADDING);
+
+    let mut data = Vec::with_capacity(4 + payload.len() + padding);
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+    let mut random = vec![0u8; padding];
+    rng.fill(&mut random[..]);
+    data.extend_from_slice(&random);
+
+    let msg_key = compute_msg_key(auth_key, x, &data);
+    let output = kdf2(auth_key, &msg_key, x);
+    let mut iv = output.aes_iv;
+    aes_ige_encrypt(&output.aes_key, &mut iv, &mut data)
+        .map_err(|e| Error::Crypto(e.to_string()))?;
+
+    let mut packet = Vec::with_capacity(HEADER_SIZE + data.len());
+    packet.extend_from_slice(&key_fingerprint(auth_key).to_le_bytes());
+    packet.extend_from_slice(&msg_key);
+    packet.extend_from_slice(&data);
+    Ok(packet)
+}
+
+/// Returns the fingerprint of the key an encrypted message uses.
+///
+/// # Errors
+///
+/// Returns an error if the message is too short.
+pub fn get_packet_key_fingerprint(packet: &[u8]) -> Result<i64> {
+    let bytes = packet
+        .get(..8)
+        .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+        .ok_or_else(|| Error::Crypto("message is too short".into()))?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+/// Decrypts a message with a key and returns the payload.
+///
+/// # Errors
+///
+/// Returns an error if the message wasn't encrypted with the key by the
+/// party `x` belongs to, or if it was tampered with.
+pub fn decrypt_message(auth_key: &AuthKey, x: usize, packet: &[u8]) -> Result<Vec<u8>> {
+    if packet.len() < HEADER_SIZE + 16 || (packet.len() - HEADER_SIZE) % 16 != 0 {
+        return Err(Error::Crypto(format!(
+            "wrong message size {}",
+            packet.len()
+        )));
+    }
+    let fingerprint = get_packet_key_fingerprint(packet)?;
+    if fingerprint != key_fingerprint(auth_key) {
+        return Err(Error::UnknownKeyFingerprint(fingerprint));
+    }
+    let mut msg_key = [0u8; 16];
+    msg_key.copy_from_slice(&packet[8..HEADER_SIZE]);
+
+    let output = kdf2(auth_key, &msg_key, x);
+    let mut iv = output.aes_iv;
+    let mut data = packet[HEADER_SIZE..].to_vec();
+    aes_ige_decrypt(&output.aes_key, &mut iv, &mut data)
+        .map_err(|e| Error::Crypto(e.to_string()))?;
+
+    if compute_msg_key(auth_key, x, &data) != msg_key {
+        return Err(Error::Crypto("msg_key mismatch".into()));
+    }
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&data[..4]);
+    let length = u32::from_le_bytes(length) as usize;
+    let padding = (data.len() - 4).checked_sub(length);
+    match padding {
+        Some(padding) if (MIN_PADDING..=MAX_PADDING).contains(&padding) && length % 4 == 0 => {
+            Ok(data[4..4 + length].to_vec())
+        }
+        _ => Err(Error::Crypto(format!("wrong payload length {}", length))),
+    }
+}
+
+/// `msg_key = SHA256(substr(auth_key, 88 + x, 32) + data)[8..24]`
+fn compute_msg_key(auth_key: &AuthKey, x: usize, data: &[u8]) -> [u8; 16] {
+    let mut buf = Vec::with_capacity(32 + data.len());
+    buf.extend_from_slice(&auth_key[88 + x..88 + x + 32]);
+    buf.extend_from_slice(data);
+    let msg_key_large = sha256(&buf);
+    let mut msg_key = [0u8; 16];
+    msg_key.copy_from_slice(&msg_key_large[8..24]);
+    msg_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> AuthKey {
+        let mut key = [0u8; 256];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = (i * 31 % 251) as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = test_key();
+        for size in [0, 4, 12, 100, 1000] {
+            let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let packet = encrypt_message(&key, get_x(true), &payload).unwrap();
+            assert_eq!((packet.len() - HEADER_SIZE) % 16, 0);
+            assert_eq!(
+                get_packet_key_fingerprint(&packet).unwrap(),
+                key_fingerprint(&key)
+            );
+            assert_eq!(
+                decrypt_message(&key, get_x(true), &packet).unwrap(),
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn test_decrypt_wrong_direction() {
+        let key = test_key();
+        let packet = encrypt_message(&key, get_x(true), &[1, 2, 3, 4]).unwrap();
+        assert!(decrypt_message(&key, get_x(false), &packet).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_tampered() {
+        let key = test_key();
+        let mut packet = encrypt_message(&key, get_x(false), &[1, 2, 3, 4]).unwrap();
+        let last = packet.len() - 1;
+        packet[last] ^= 1;
+        assert_eq!(
+            decrypt_message(&key, get_x(false), &packet),
+            Err(Error::Crypto("msg_key mismatch".into()))
+        );
+    }
+
+    #[test]
+    fn test_decrypt_wrong_key() {
+        let key = test_key();
+        let packet = encrypt_message(&key, 0, &[1, 2, 3, 4]).unwrap();
+        let mut other_key = key;
+        other_key[0] ^= 1;
+        assert!(matches!(
+            decrypt_message(&other_key, 0, &packet),
+            Err(Error::UnknownKeyFingerprint(_))
+        ));
+    }
+}
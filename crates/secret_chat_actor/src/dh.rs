@@ -0,0 +1,320 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Diffie-Hellman key exchange of secret chats.
+//!
+//! Used both for the initial key of a chat and for the re-keying of
+//! perfect forward secrecy. Based on TDLib's DhHandshake.
+
+use crate::error::{Error, Result};
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use rand::Rng;
+use rustgram_dh_cache::{DhCache, PrimeCheckResult};
+use rustgram_dh_config::DhConfig;
+use rustgram_net::compute_auth_key_id;
+use std::sync::Mutex;
+
+/// Size of the prime, the exchanged values and the resulting key in bytes.
+pub const DH_KEY_SIZE: usize = 256;
+
+/// Number of Miller-Rabin rounds of the primality checks.
+const PRIMALITY_ROUNDS: usize = 32;
+
+/// Small primes tried as divisors before the Miller-Rabin test.
+const SMALL_PRIMES: [u32; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// A shared key of a secret chat.
+pub type AuthKey = [u8; DH_KEY_SIZE];
+
+/// One side of a DH key exchange.
+#[derive(Clone)]
+pub struct DhHandshake {
+    prime: BigUint,
+    g: BigUint,
+    secret: Vec<u8>,
+}
+
+impl DhHandshake {
+    /// Starts a key exchange with a new secret.
+    ///
+    /// The secret is mixed with `server_random` from `messages.getDhConfig`,
+    /// so a weak local random source alone doesn't compromise it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the DH config can't be used.
+    pub fn new(config: &DhConfig, server_random: &[u8]) -> Result<Self> {
+        let mut secret = vec![0u8; DH_KEY_SIZE];
+        rand::thread_rng().fill(&mut secret[..]);
+        for (byte, random) in secret.iter_mut().zip(server_random) {
+            *byte ^= random;
+        }
+        Self::with_secret(config, secret)
+    }
+
+    /// Restores a key exchange from a saved secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the DH config can't be used.
+    pub fn with_secret(config: &DhConfig, secret: Vec<u8>) -> Result<Self> {
+        let prime_bytes = config
+            .prime_as_bytes()
+            .ok_or_else(|| Error::InvalidDhConfig("prime is not a hex string".into()))?;
+        let prime = BigUint::from_bytes_be(&prime_bytes);
+        check_prime(&prime, config.generator())?;
+        Ok(Self {
+            prime,
+            g: BigUint::from(config.generator() as u32),
+            secret,
+        })
+    }
+
+    /// Returns the secret exponent, to be saved until the exchange ends.
+    #[must_use]
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Returns `g^secret mod p` to send to the other party.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value happens to be out of the safe range.
+    pub fn g_a(&self) -> Result<Vec<u8>> {
+        let g_a = self
+            .g
+            .modpow(&BigUint::from_bytes_be(&self.secret), &self.prime);
+        check_g_a(&self.prime, &g_a)?;
+        Ok(to_key_bytes(&g_a).to_vec())
+    }
+
+    /// Computes the shared key from the value sent by the other party.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value of the other party is out of the safe
+    /// range.
+    pub fn compute_key(&self, g_b: &[u8]) -> Result<AuthKey> {
+        if g_b.len() > DH_KEY_SIZE {
+            return Err(Error::InvalidDhValue(format!(
+                "value is {} bytes long",
+                g_b.len()
+            )));
+        }
+        let g_b = BigUint::from_bytes_be(g_b);
+        check_g_a(&self.prime, &g_b)?;
+        let key = g_b.modpow(&BigUint::from_bytes_be(&self.secret), &self.prime);
+        Ok(to_key_bytes(&key))
+    }
+}
+
+impl std::fmt::Debug for DhHandshake {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DhHandshake")
+            .field("g", &self.g)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the fingerprint of a key: the 64 lower-order bits of its SHA1.
+#[must_use]
+pub fn key_fingerprint(key: &AuthKey) -> i64 {
+    compute_auth_key_id(key) as i64
+}
+
+/// Checks that the prime is a safe 2048-bit prime matching the generator.
+///
+/// Primality checks are slow, so their results are kept in [`DhCache`] and
+/// a prime is checked by one handshake at a time.
+fn check_prime(prime: &BigUint, g: i32) -> Result<()> {
+    if prime.bits() != (DH_KEY_SIZE * 8) as u64 {
+        return Err(Error::InvalidDhConfig(format!(
+            "prime is {} bits long",
+            prime.bits()
+        )));
+    }
+    // g generates a subgroup of order (p - 1) / 2 only for these residues
+    let mod_ok = match g {
+        2 => prime % 8u32 == BigUint::from(7u32),
+        3 => prime % 3u32 == BigUint::from(2u32),
+        4 => true,
+        5 => [1u32, 4].map(BigUint::from).contains(&(prime % 5u32)),
+        6 => [19u32, 23].map(BigUint::from).contains(&(prime % 24u32)),
+        7 => [3u32, 5, 6].map(BigUint::from).contains(&(prime % 7u32)),
+        _ => false,
+    };
+    if !mod_ok {
+        return Err(Error::InvalidDhConfig(format!("bad prime for g = {}", g)));
+    }
+
+    static CHECK_LOCK: Mutex<()> = Mutex::new(());
+    let _guard = CHECK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let prime_str = prime.to_str_radix(16);
+    let cache = DhCache::instance();
+    match cache.is_good_prime(&prime_str) {
+        PrimeCheckResult::Good => return Ok(()),
+        PrimeCheckResult::Bad => return Err(Error::InvalidDhConfig("prime is not safe".into())),
+        PrimeCheckResult::Unknown => {}
+    }
+    if !is_probable_prime(prime) {
+        cache.add_bad_prime(&prime_str);
+        return Err(Error::InvalidDhConfig("p is not prime".into()));
+    }
+    if !is_probable_prime(&((prime - 1u32) >> 1)) {
+        cache.add_bad_prime(&prime_str);
+        return Err(Error::InvalidDhConfig("(p - 1) / 2 is not prime".into()));
+    }
+    cache.add_good_prime(&prime_str);
+    Ok(())
+}
+
+/// Checks whether a number is prime with the Miller-Rabin test.
+fn is_probable_prime(n: &BigUint) -> bool {
+    if n < &BigUint::from(4u32) {
+        return n >= &BigUint::from(2u32);
+    }
+    if !n.bit(0) {
+        return false;
+    }
+    for small_prime in SMALL_PRIMES {
+        if n == &BigUint::from(small_prime) {
+            return true;
+        }
+        if (n % small_prime).is_zero() {
+            return false;
+        }
+    }
+
+    // n - 1 = d * 2^r with odd d
+    let n_minus_1 = n - 1u32;
+    let r = n_minus_1.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_1 >> r;
+    let mut rng = rand::thread_rng();
+    let mut random = vec![0u8; n.bits().div_ceil(8) as usize + 8];
+    'witness: for _ in 0..PRIMALITY_ROUNDS {
+        rng.fill(&mut random[..]);
+        let a = BigUint::from_bytes_be(&random) % (n - 3u32) + 2u32;
+        let mut x = a.modpow(&d, n);
+        if x.is_one() || x == n_minus_1 {
+            continue;
+        }
+        for _ in 1..r {
+            x = &x * &x % n;
+            if x == n_minus_1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Checks that `2^{2048-64} <= g_a <= p - 2^{2048-64}`.
+fn check_g_a(prime: &BigUint, g_a: &BigUint) -> Result<()> {
+    let left = BigUint::one() << (DH_KEY_SIZE * 8 - 64);
+    if g_a < &left || g_a > &(prime - &left) {
+        return Err(Error::InvalidDhValue("value is out of range".into()));
+    }
+    Ok(())
+}
+
+/// Pads a number to a big-endian key.
+fn to_key_bytes(value: &BigUint) -> AuthKey {
+    let bytes = value.to_bytes_be();
+    let mut key = [0u8; DH_KEY_SIZE];
+    key[DH_KEY_SIZE - bytes.len()..].copy_from_slice(&bytes);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_agreement() {
+        let config = DhConfig::default_safe();
+        let alice = DhHandshake::new(&config, &[1u8; 256]).unwrap();
+        let bob = DhHandshake::new(&config, &[2u8; 256]).unwrap();
+
+        let key_a = alice.compute_key(&bob.g_a().unwrap()).unwrap();
+        let key_b = bob.compute_key(&alice.g_a().unwrap()).unwrap();
+        assert_eq!(key_a, key_b);
+        assert_eq!(key_fingerprint(&key_a), key_fingerprint(&key_b));
+
+        let restored = DhHandshake::with_secret(&config, alice.secret().to_vec()).unwrap();
+        assert_eq!(restored.g_a().unwrap(), alice.g_a().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = DhConfig::with_params(1, "ff".repeat(32), 2);
+        assert!(matches!(
+            DhHandshake::new(&config, &[]),
+            Err(Error::InvalidDhConfig(_))
+        ));
+
+        let config = DhConfig::with_params(1, DhConfig::default_safe().prime().to_string(), 8);
+        assert!(matches!(
+            DhHandshake::new(&config, &[]),
+            Err(Error::InvalidDhConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_probable_prime() {
+        for n in [2u32, 3, 5, 97, 101, 7919] {
+            assert!(is_probable_prime(&BigUint::from(n)), "{n}");
+        }
+        // 561 is a Carmichael number
+        for n in [0u32, 1, 4, 9, 561, 7917] {
+            assert!(!is_probable_prime(&BigUint::from(n)), "{n}");
+        }
+    }
+
+    #[test]
+    fn test_safe_prime() {
+        let config = DhConfig::default_safe();
+        assert!(DhHandshake::new(&config, &[]).is_ok());
+        let prime = BigUint::parse_bytes(config.prime().as_bytes(), 16).unwrap();
+        assert!(DhCache::instance()
+            .is_good_prime(&prime.to_str_radix(16))
+            .is_good());
+    }
+
+    #[test]
+    fn test_composite_prime() {
+        // (2^1023 + 15) * (2^1024 + 1) has 2048 bits and a valid residue for
+        // g = 2, but it isn't prime
+        let composite =
+            ((BigUint::one() << 1023u32) + 15u32) * ((BigUint::one() << 1024u32) + 1u32);
+        assert_eq!(composite.bits(), 2048);
+        assert_eq!(&composite % 8u32, BigUint::from(7u32));
+
+        let config = DhConfig::with_params(1, composite.to_str_radix(16), 2);
+        assert!(matches!(
+            DhHandshake::new(&config, &[]),
+            Err(Error::InvalidDhConfig(_))
+        ));
+        assert!(DhCache::instance()
+            .is_good_prime(&composite.to_str_radix(16))
+            .is_bad());
+    }
+
+    #[test]
+    fn test_invalid_g_b() {
+        let handshake = DhHandshake::new(&DhConfig::default_safe(), &[]).unwrap();
+        assert!(matches!(
+            handshake.compute_key(&[1]),
+            Err(Error::InvalidDhValue(_))
+        ));
+        assert!(matches!(
+            handshake.compute_key(&[0xff; 256]),
+            Err(Error::InvalidDhValue(_))
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Error types for secret chats.
+
+use crate::SecretChatState;
+use rustgram_secret_chat_db::SecretChatDbError;
+use thiserror::Error;
+
+/// Errors of a secret chat.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Error {
+    /// The DH config received from the server can't be used
+    #[error("Invalid DH config: {0}")]
+    InvalidDhConfig(String),
+
+    /// A `g_a` or `g_b` of the other party is out of the allowed range
+    #[error("Invalid DH value: {0}")]
+    InvalidDhValue(String),
+
+    /// The fingerprint of a key doesn't match the one of the other party
+    #[error("Key fingerprint mismatch: expected {expected}, got {actual}")]
+    KeyFingerprintMismatch {
+        /// Fingerprint of the computed key
+        expected: i64,
+        /// Fingerprint sent by the other party
+        actual: i64,
+    },
+
+    /// An encrypted message uses a key that isn't known
+    #[error("Unknown key fingerprint {0}")]
+    UnknownKeyFingerprint(i64),
+
+    /// Encryption or decryption of a message failed
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+
+    /// A decrypted message can't be parsed
+    #[error("Failed to parse decrypted message: {0}")]
+    Parse(String),
+
+    /// The sequence numbers of a message are inconsistent
+    #[error("Invalid seq_no: in_seq_no {in_seq_no}, out_seq_no {out_seq_no}")]
+    InvalidSeqNo {
+        /// Received `in_seq_no`
+        in_seq_no: i32,
+        /// Received `out_seq_no`
+        out_seq_no: i32,
+    },
+
+    /// The operation isn't allowed in the current state of the chat
+    #[error("Operation is not allowed in state {0:?}")]
+    InvalidState(SecretChatState),
+
+    /// Persisting the state of the chat failed
+    #[error(transparent)]
+    Db(#[from] SecretChatDbError),
+}
+
+/// Result type of secret chat operations.
+pub type Result<T> = std::result::Result<T, Error>;
@@ -9,6 +9,29 @@
 //! This module provides the SecretChatActor which handles the entire lifecycle
 //! of a secret chat including key exchange, encryption/decryption, and layer
 //! negotiation. Based on TDLib's SecretChatActor.h.
+//!
+//! ## Flow
+//!
+//! The actor doesn't talk to the network itself. Every operation queues the
+//! [`SecretChatQuery`]s to send, which are taken with
+//! [`SecretChatActor::take_queries`], and the answers and updates of the
+//! server are passed back with [`SecretChatActor::on_dh_config`],
+//! [`SecretChatActor::on_encrypted_chat`] and
+//! [`SecretChatActor::on_new_message`]. Whatever the rest of the client must
+//! know about is taken with [`SecretChatActor::take_events`].
+//!
+//! 1. The creator calls [`SecretChatActor::create_chat`], gets the DH config
+//!    and sends `messages.requestEncryption` with its `g_a`
+//! 2. The participant gets `encryptedChatRequested`, gets the DH config,
+//!    computes the key and sends `messages.acceptEncryption` with its `g_b`
+//! 3. The creator gets `encryptedChat` with `g_b`, computes the same key and
+//!    checks its fingerprint
+//! 4. Both parties notify each other about their layer and exchange messages
+//!    encrypted with MTProto 2.0
+//!
+//! Every 100 messages the parties agree on a new key (perfect forward
+//! secrecy). The state of the chat is saved in the [`SecretChatDb`] after
+//! every operation, and [`SecretChatActor::load`] restores it.
 
 #![warn(missing_docs)]
 #![warn(clippy::all)]
@@ -17,36 +40,59 @@
 #![allow(clippy::module_name_repetitions)]
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
+mod crypto;
+mod dh;
+mod error;
+mod query;
+mod state;
+mod tl;
+
+pub use error::{Error, Result};
+pub use query::{EncryptedChat, EncryptedMessage, SecretChatEvent, SecretChatQuery};
+pub use tl::DecryptedMessage;
+
+use crate::crypto::{decrypt_message, encrypt_message, get_packet_key_fingerprint, get_x};
+use crate::dh::{key_fingerprint, AuthKey, DhHandshake, DH_KEY_SIZE};
+use crate::state::{AuthState, PendingMessages, PfsState, PfsStep, SentMessage, SentMessages};
+use crate::tl::{
+    DecryptedMessageAction, DecryptedMessageLayer, DecryptedPayload, MIN_RANDOM_BYTES,
+};
+use rand::Rng;
 use rustgram_actor::Actor;
-use rustgram_promise::Promise;
-use rustgram_secret_chat_db::SecretChatDb;
+use rustgram_dh_config::DhConfig;
+use rustgram_secret_chat_db::{
+    KeyValueSyncInterface, SecretChatDb, SecretChatDbError, SecretChatValue,
+};
 use rustgram_secret_chat_layer::SecretChatLayer;
-use rustgram_types::{MessageId, SecretChatId, UserId};
+use rustgram_types::UserId;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fmt::{self, Debug, Formatter};
 
+/// Number of messages after which the key of a chat is changed.
+pub const PFS_MESSAGE_COUNT: i32 = 100;
+
+/// Largest gap in the sequence of the other party; a bigger one closes the
+/// chat.
+pub const MAX_SEQ_NO_GAP: i32 = 1000;
+
+/// Number of messages kept while waiting for a gap to be filled; later
+/// messages are dropped and requested again.
+pub const MAX_PENDING_MESSAGES: usize = 100;
+
 /// Actor managing a single secret chat.
 ///
 /// Handles key exchange (DH handshake), message encryption/decryption,
 /// layer negotiation, and state persistence.
-///
-/// # TODO
-///
-/// This is a simplified implementation. Full TDLib SecretChatActor has:
-/// - Complete DH handshake implementation
-/// - Full message encryption/decryption
-/// - Binlog integration
-/// - Network query handling
-/// - Screenshot notifications
-pub struct SecretChatActor<C> {
+pub struct SecretChatActor<C: KeyValueSyncInterface> {
     /// Secret chat ID
     id: i32,
 
     /// Secret chat database
     db: SecretChatDb<C>,
 
-    /// Current state of the chat
-    state: SecretChatState,
+    /// Parties, keys and the state of the chat
+    auth_state: AuthState,
 
     /// Configuration (layers, TTL)
     config_state: ConfigState,
@@ -54,13 +100,26 @@ pub struct SecretChatActor<C> {
     /// Message sequencing state
     seq_no_state: SeqNoState,
 
-    /// Authentication key for encryption (stub)
-    auth_key: Option<Vec<u8>>,
+    /// Re-keying state
+    pfs_state: PfsState,
+
+    /// Sent messages not yet confirmed by the other party
+    sent_messages: SentMessages,
+
+    /// Serialized messages received ahead of a gap, by their number in his
+    /// sequence
+    pending_inbound: PendingMessages,
+
+    /// Queries to send to the server
+    queries: VecDeque<SecretChatQuery>,
+
+    /// Events for the rest of the client
+    events: VecDeque<SecretChatEvent>,
 }
 
 impl<C> SecretChatActor<C>
 where
-    C: rustgram_secret_chat_db::KeyValueSyncInterface + 'static,
+    C: KeyValueSyncInterface + 'static,
 {
     /// Creates a new SecretChatActor.
     ///
@@ -73,19 +132,20 @@ where
     /// # Example
     ///
     /// ```rust
+    /// use bytes::Bytes;
     /// use rustgram_secret_chat_actor::SecretChatActor;
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// use std::collections::HashMap;
     /// use std::sync::{Arc, Mutex};
     ///
-    /// struct MockStorage(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+    /// struct MockStorage(Arc<Mutex<HashMap<String, Bytes>>>);
     /// impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    ///     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    ///     fn set(&self, key: String, value: Bytes) -> Result<(), Box<dyn std::error::Error>> {
     ///         let mut data = self.0.lock().unwrap();
     ///         data.insert(key, value);
     ///         Ok(())
     ///     }
-    ///     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    ///     fn get(&self, key: String) -> Result<Option<Bytes>, Box<dyn std::error::Error>> {
     ///         let data = self.0.lock().unwrap();
     ///         Ok(data.get(&key).cloned())
     ///     }
@@ -110,13 +170,48 @@ where
         Self {
             id,
             db,
-            state,
+            auth_state: AuthState::new(state),
             config_state: ConfigState::default(),
             seq_no_state: SeqNoState::default(),
-            auth_key: None,
+            pfs_state: PfsState::default(),
+            sent_messages: SentMessages::default(),
+            pending_inbound: PendingMessages::default(),
+            queries: VecDeque::new(),
+            events: VecDeque::new(),
         }
     }
 
+    /// Restores a SecretChatActor saved in the secret chat database.
+    ///
+    /// Messages received ahead of a gap are restored too, but the resend of
+    /// the missing ones is requested again when the next message arrives.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Db`] if the chat wasn't saved or can't be read.
+    pub fn load(id: i32, db: SecretChatDb<C>) -> Result<Self> {
+        let auth_state = db.get_value::<AuthState>()?;
+        let config_state = db.get_value::<ConfigState>()?;
+        let mut seq_no_state = db.get_value::<SeqNoState>()?;
+        seq_no_state.resend_end_seq_no = seq_no_state.my_in_seq_no;
+        let pfs_state = get_value_or_default::<C, PfsState>(&db)?;
+        let sent_messages = get_value_or_default::<C, SentMessages>(&db)?;
+        let pending_inbound = get_value_or_default::<C, PendingMessages>(&db)?;
+
+        Ok(Self {
+            id,
+            db,
+            auth_state,
+            config_state,
+            seq_no_state,
+            pfs_state,
+            sent_messages,
+            pending_inbound,
+            queries: VecDeque::new(),
+            events: VecDeque::new(),
+        })
+    }
+
     /// Returns the secret chat ID.
     ///
     /// # Example
@@ -126,8 +221,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -149,8 +244,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -160,7 +255,7 @@ where
     /// assert_eq!(actor.state(), SecretChatState::Empty);
     /// ```
     pub fn state(&self) -> SecretChatState {
-        self.state
+        self.auth_state.state
     }
 
     /// Returns the configuration state.
@@ -172,8 +267,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -195,8 +290,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -209,6 +304,20 @@ where
         &self.seq_no_state
     }
 
+    /// Returns the fingerprint of the current key, if the key is known.
+    pub fn key_fingerprint(&self) -> Option<i64> {
+        if self.auth_state.auth_key.is_empty() {
+            None
+        } else {
+            Some(self.auth_state.key_fingerprint)
+        }
+    }
+
+    /// Returns whether the chat was created by us.
+    pub fn is_creator(&self) -> bool {
+        self.auth_state.is_creator
+    }
+
     /// Checks if the chat is ready (can send messages).
     ///
     /// # Returns
@@ -222,8 +331,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -233,7 +342,7 @@ where
     /// assert!(!actor.is_ready());
     /// ```
     pub fn is_ready(&self) -> bool {
-        matches!(self.state, SecretChatState::Ready)
+        matches!(self.auth_state.state, SecretChatState::Ready)
     }
 
     /// Checks if the chat is closed.
@@ -249,8 +358,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -260,7 +369,7 @@ where
     /// assert!(!actor.is_closed());
     /// ```
     pub fn is_closed(&self) -> bool {
-        matches!(self.state, SecretChatState::Closed)
+        matches!(self.auth_state.state, SecretChatState::Closed)
     }
 
     /// Updates the chat state.
@@ -276,8 +385,8 @@ where
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
@@ -288,214 +397,805 @@ where
     /// assert!(actor.is_ready());
     /// ```
     pub fn set_state(&mut self, new_state: SecretChatState) {
-        self.state = new_state;
+        self.auth_state.state = new_state;
+    }
+
+    /// Takes the queries to send to the server, in order.
+    pub fn take_queries(&mut self) -> Vec<SecretChatQuery> {
+        self.queries.drain(..).collect()
+    }
+
+    /// Takes the events for the rest of the client, in order.
+    pub fn take_events(&mut self) -> Vec<SecretChatEvent> {
+        self.events.drain(..).collect()
     }
 
     /// Creates a new secret chat.
     ///
-    /// # TODO
-    ///
-    /// Implement actual chat creation with DH handshake.
+    /// Queues `messages.getDhConfig`; the chat is requested when the config
+    /// is passed to [`on_dh_config`](Self::on_dh_config). The ID of the
+    /// actor is used as the random ID of the request.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The user ID to create chat with
     /// * `user_access_hash` - The user's access hash
-    /// * `random_id` - Random ID for the chat
-    /// * `promise` - Promise to resolve with chat ID
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidState`] if the chat was already created.
     ///
     /// # Example
     ///
     /// ```rust
-    /// use rustgram_secret_chat_actor::SecretChatActor;
+    /// use rustgram_secret_chat_actor::{SecretChatActor, SecretChatQuery, SecretChatState};
     /// use rustgram_secret_chat_db::SecretChatDb;
     /// use rustgram_types::UserId;
-    /// use rustgram_promise::Promise;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
     /// let storage = MockStorage;
     /// let db = SecretChatDb::new(storage, 12345);
     /// let mut actor = SecretChatActor::new(12345, db, true);
-    /// let promise = Promise::new();
     /// let user_id = UserId::new(123).unwrap();
-    /// actor.create_chat(user_id, 0, 123, promise);
+    /// actor.create_chat(user_id, 0).unwrap();
+    /// assert_eq!(actor.state(), SecretChatState::SendRequest);
+    /// assert!(matches!(actor.take_queries()[..], [SecretChatQuery::GetDhConfig { .. }]));
     /// ```
-    pub fn create_chat(
-        &mut self,
-        _user_id: UserId,
-        _user_access_hash: i64,
-        _random_id: i32,
-        _promise: Promise<SecretChatId>,
-    ) {
-        // Stub: Would initiate DH handshake
-        self.state = SecretChatState::SendRequest;
+    pub fn create_chat(&mut self, user_id: UserId, user_access_hash: i64) -> Result<()> {
+        if !matches!(
+            self.auth_state.state,
+            SecretChatState::Empty | SecretChatState::SendRequest
+        ) {
+            return Err(Error::InvalidState(self.auth_state.state));
+        }
+        self.auth_state.is_creator = true;
+        self.auth_state.user_id = user_id.get();
+        self.auth_state.user_access_hash = user_access_hash;
+        self.config_state.my_layer = SecretChatLayer::recommended().value();
+        self.set_auth_state(SecretChatState::SendRequest);
+        self.queries.push_back(get_dh_config_query());
+        self.save()
     }
 
-    /// Cancels/closes the chat.
+    /// Handles the answer to `messages.getDhConfig`.
+    ///
+    /// Sends `messages.requestEncryption` for a chat we create and
+    /// `messages.acceptEncryption` for a chat we were invited to.
     ///
-    /// # TODO
+    /// # Arguments
     ///
-    /// Implement actual chat cancellation.
+    /// * `config` - The current DH config
+    /// * `random` - The random bytes returned with the config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config or `g_a` of the creator can't be used,
+    /// or if no DH config was requested.
+    pub fn on_dh_config(&mut self, config: &DhConfig, random: &[u8]) -> Result<()> {
+        match self.auth_state.state {
+            SecretChatState::SendRequest => {
+                let handshake = DhHandshake::new(config, random)?;
+                let g_a = handshake.g_a()?;
+                self.auth_state.dh_config = config.serialize();
+                self.auth_state.secret = handshake.secret().to_vec();
+                self.queries.push_back(SecretChatQuery::RequestEncryption {
+                    user_id: UserId(self.auth_state.user_id),
+                    access_hash: self.auth_state.user_access_hash,
+                    random_id: self.id,
+                    g_a,
+                });
+                self.set_auth_state(SecretChatState::WaitRequestResponse);
+            }
+            SecretChatState::SendAccept => {
+                let handshake = DhHandshake::new(config, random)?;
+                let auth_key = match handshake.compute_key(&self.auth_state.g_a) {
+                    Ok(auth_key) => auth_key,
+                    Err(error) => {
+                        self.discard(false);
+                        self.save()?;
+                        return Err(error);
+                    }
+                };
+                let g_b = handshake.g_a()?;
+                self.auth_state.dh_config = config.serialize();
+                self.auth_state.g_a.clear();
+                self.set_auth_key(&auth_key);
+                self.queries.push_back(SecretChatQuery::AcceptEncryption {
+                    chat_id: self.id,
+                    access_hash: self.auth_state.access_hash,
+                    g_b,
+                    key_fingerprint: self.auth_state.key_fingerprint,
+                });
+                self.set_auth_state(SecretChatState::WaitAcceptResponse);
+            }
+            state => return Err(Error::InvalidState(state)),
+        }
+        self.save()
+    }
+
+    /// Handles a new state of the chat on the server.
+    ///
+    /// This is both the answer to `messages.requestEncryption` and
+    /// `messages.acceptEncryption` and the content of `updateEncryption`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key can't be computed or its fingerprint
+    /// doesn't match the one of the other party; the chat is discarded then.
+    pub fn on_encrypted_chat(&mut self, chat: EncryptedChat) -> Result<()> {
+        match (self.auth_state.state, chat) {
+            (SecretChatState::WaitRequestResponse, EncryptedChat::Waiting { access_hash, .. }) => {
+                self.auth_state.access_hash = access_hash;
+            }
+            (
+                SecretChatState::Empty,
+                EncryptedChat::Requested {
+                    access_hash,
+                    admin_id,
+                    g_a,
+                    ..
+                },
+            ) => {
+                self.auth_state.is_creator = false;
+                self.auth_state.user_id = admin_id.get();
+                self.auth_state.access_hash = access_hash;
+                self.auth_state.g_a = g_a;
+                self.config_state.my_layer = SecretChatLayer::recommended().value();
+                self.set_auth_state(SecretChatState::SendAccept);
+                self.queries.push_back(get_dh_config_query());
+            }
+            (
+                SecretChatState::WaitRequestResponse,
+                EncryptedChat::Chat {
+                    access_hash,
+                    g_a_or_b,
+                    key_fingerprint,
+                    ..
+                },
+            ) => {
+                self.auth_state.access_hash = access_hash;
+                let auth_key = self
+                    .restore_handshake(self.auth_state.secret.clone())
+                    .and_then(|handshake| handshake.compute_key(&g_a_or_b))
+                    .and_then(|auth_key| {
+                        check_fingerprint(&auth_key, key_fingerprint).map(|()| auth_key)
+                    });
+                match auth_key {
+                    Ok(auth_key) => {
+                        self.auth_state.secret.clear();
+                        self.set_auth_key(&auth_key);
+                        self.on_ready()?;
+                    }
+                    Err(error) => {
+                        self.discard(false);
+                        self.save()?;
+                        return Err(error);
+                    }
+                }
+            }
+            (
+                SecretChatState::WaitAcceptResponse,
+                EncryptedChat::Chat {
+                    key_fingerprint, ..
+                },
+            ) => {
+                if key_fingerprint != self.auth_state.key_fingerprint {
+                    self.discard(false);
+                    self.save()?;
+                    return Err(Error::KeyFingerprintMismatch {
+                        expected: self.auth_state.key_fingerprint,
+                        actual: key_fingerprint,
+                    });
+                }
+                self.on_ready()?;
+            }
+            (
+                state,
+                EncryptedChat::Discarded {
+                    history_deleted, ..
+                },
+            ) => {
+                if state != SecretChatState::Closed {
+                    self.set_auth_state(SecretChatState::Closed);
+                    self.events
+                        .push_back(SecretChatEvent::Discarded { history_deleted });
+                }
+            }
+            // Repeated updates about the chat
+            _ => return Ok(()),
+        }
+        self.save()
+    }
+
+    /// Cancels/closes the chat.
     ///
     /// # Arguments
     ///
     /// * `delete_history` - Whether to delete chat history
     /// * `is_already_discarded` - Whether the chat is already discarded
-    /// * `promise` - Promise to resolve when done
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state of the chat can't be saved.
     ///
     /// # Example
     ///
     /// ```rust
     /// use rustgram_secret_chat_actor::SecretChatActor;
     /// use rustgram_secret_chat_db::SecretChatDb;
-    /// use rustgram_promise::Promise;
     /// # struct MockStorage;
     /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
+    /// #     fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+    /// #     fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> { Ok(None) }
     /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
     /// # }
     ///
     /// let storage = MockStorage;
     /// let db = SecretChatDb::new(storage, 12345);
     /// let mut actor = SecretChatActor::new(12345, db, true);
-    /// let promise = Promise::new();
-    /// actor.cancel_chat(false, false, promise);
+    /// actor.cancel_chat(false, false).unwrap();
     /// assert!(actor.is_closed());
     /// ```
-    pub fn cancel_chat(
-        &mut self,
-        _delete_history: bool,
-        _is_already_discarded: bool,
-        _promise: Promise<()>,
-    ) {
-        self.state = SecretChatState::Closed;
+    pub fn cancel_chat(&mut self, delete_history: bool, is_already_discarded: bool) -> Result<()> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        if is_already_discarded {
+            self.set_auth_state(SecretChatState::Closed);
+        } else {
+            self.discard(delete_history);
+        }
+        self.save()
     }
 
-    /// Adds an inbound message.
-    ///
-    /// # TODO
-    ///
-    /// Implement actual message decryption and processing.
-    ///
-    /// # Arguments
-    ///
-    /// * `message_id` - The message ID
+    /// Handles a message from `updateNewEncryptedMessage`.
     ///
-    /// # Example
+    /// Messages are handled in the order of the sequence of the other party.
+    /// A message after a gap is kept until the gap is filled, and the
+    /// missing messages are requested again.
     ///
-    /// ```rust
-    /// use rustgram_secret_chat_actor::SecretChatActor;
-    /// use rustgram_secret_chat_db::SecretChatDb;
-    /// use rustgram_types::MessageId;
-    /// # struct MockStorage;
-    /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
-    /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// # }
+    /// # Errors
     ///
-    /// let storage = MockStorage;
-    /// let db = SecretChatDb::new(storage, 12345);
-    /// let mut actor = SecretChatActor::new(12345, db, true);
-    /// let message_id = MessageId::from_i32(1).unwrap();
-    /// actor.add_inbound_message(message_id);
-    /// ```
-    pub fn add_inbound_message(&mut self, message_id: MessageId) {
-        // Stub: Would decrypt and process message
-        self.seq_no_state.message_id = message_id.get();
+    /// Returns an error if the message can't be decrypted or parsed, or if
+    /// its sequence numbers are inconsistent. A gap of more than
+    /// [`MAX_SEQ_NO_GAP`] messages also closes the chat.
+    pub fn on_new_message(&mut self, message: EncryptedMessage) -> Result<()> {
+        if self.auth_state.state == SecretChatState::WaitAcceptResponse {
+            // The creator got our key, so the chat was accepted
+            self.on_ready()?;
+        }
+        if !self.is_ready() {
+            return Err(Error::InvalidState(self.auth_state.state));
+        }
+
+        let fingerprint = get_packet_key_fingerprint(&message.data)?;
+        let auth_key = self
+            .find_key(fingerprint)
+            .ok_or(Error::UnknownKeyFingerprint(fingerprint))?;
+        let payload =
+            decrypt_message(&auth_key, get_x(!self.auth_state.is_creator), &message.data)?;
+        let layer = DecryptedMessageLayer::parse(&payload)?;
+        self.on_message_layer(layer)?;
+
+        self.check_pfs()?;
+        self.save()
     }
 
     /// Sends a message.
     ///
-    /// # TODO
-    ///
-    /// Implement actual message encryption and sending.
+    /// The message uses the self-destruct timer of the chat unless it has
+    /// its own.
     ///
-    /// # Arguments
-    ///
-    /// * `message_id` - The message ID
-    ///
-    /// # Example
-    ///
-    /// ```rust
-    /// use rustgram_secret_chat_actor::SecretChatActor;
-    /// use rustgram_secret_chat_db::SecretChatDb;
-    /// use rustgram_types::MessageId;
-    /// # struct MockStorage;
-    /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
-    /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// # }
+    /// # Errors
     ///
-    /// let storage = MockStorage;
-    /// let db = SecretChatDb::new(storage, 12345);
-    /// let mut actor = SecretChatActor::new(12345, db, true);
-    /// let message_id = MessageId::from_i32(1).unwrap();
-    /// actor.send_message(message_id);
-    /// ```
-    pub fn send_message(&mut self, message_id: MessageId) {
-        // Stub: Would encrypt and send message
-        self.seq_no_state.my_out_seq_no += 1;
-        self.seq_no_state.message_id = message_id.get();
+    /// Returns [`Error::InvalidState`] if the chat isn't ready, or an error
+    /// if the message can't be encrypted or saved.
+    pub fn send_message(&mut self, mut message: DecryptedMessage) -> Result<()> {
+        self.check_ready()?;
+        if message.ttl == 0 {
+            message.ttl = self.config_state.ttl;
+        }
+        self.send_payload(DecryptedPayload::Message(message))?;
+        self.check_pfs()?;
+        self.save()
     }
 
-    /// Deletes messages.
-    ///
-    /// # TODO
-    ///
-    /// Implement actual message deletion.
+    /// Deletes messages for both parties.
     ///
     /// # Arguments
     ///
-    /// * `message_ids` - The message IDs to delete
+    /// * `random_ids` - Random identifiers of the messages to delete
     ///
-    /// # Example
+    /// # Errors
     ///
-    /// ```rust
-    /// use rustgram_secret_chat_actor::SecretChatActor;
-    /// use rustgram_secret_chat_db::SecretChatDb;
-    /// use rustgram_types::MessageId;
-    /// # struct MockStorage;
-    /// # impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-    /// #     fn set(&self, key: String, value: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// #     fn get(&self, key: String) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> { Ok(None) }
-    /// #     fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
-    /// # }
+    /// Returns [`Error::InvalidState`] if the chat isn't ready.
+    pub fn delete_messages(&mut self, random_ids: &[i64]) -> Result<()> {
+        self.check_ready()?;
+        self.send_action(DecryptedMessageAction::DeleteMessages(random_ids.to_vec()))?;
+        self.save()
+    }
+
+    /// Changes the self-destruct timer of new messages for both parties.
     ///
-    /// let storage = MockStorage;
-    /// let db = SecretChatDb::new(storage, 12345);
-    /// let mut actor = SecretChatActor::new(12345, db, true);
-    /// let message_id = MessageId::from_i32(1).unwrap();
-    /// actor.delete_messages(&[message_id]);
-    /// ```
-    pub fn delete_messages(&mut self, _message_ids: &[MessageId]) {
-        // Stub: Would delete messages
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidState`] if the chat isn't ready.
+    pub fn set_ttl(&mut self, ttl: i32) -> Result<()> {
+        self.check_ready()?;
+        self.config_state.ttl = ttl;
+        self.send_action(DecryptedMessageAction::SetMessageTtl(ttl))?;
+        self.save()
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        if self.is_ready() {
+            Ok(())
+        } else {
+            Err(Error::InvalidState(self.auth_state.state))
+        }
+    }
+
+    fn set_auth_state(&mut self, state: SecretChatState) {
+        if self.auth_state.state != state {
+            self.auth_state.state = state;
+            self.events.push_back(SecretChatEvent::StateChanged(state));
+        }
+    }
+
+    fn set_auth_key(&mut self, auth_key: &AuthKey) {
+        self.auth_state.auth_key = auth_key.to_vec();
+        self.auth_state.key_fingerprint = key_fingerprint(auth_key);
+    }
+
+    fn on_ready(&mut self) -> Result<()> {
+        self.set_auth_state(SecretChatState::Ready);
+        self.send_action(DecryptedMessageAction::NotifyLayer(
+            self.config_state.my_layer,
+        ))
+    }
+
+    fn discard(&mut self, delete_history: bool) {
+        if self.auth_state.state != SecretChatState::Empty {
+            self.queries.push_back(SecretChatQuery::DiscardEncryption {
+                chat_id: self.id,
+                delete_history,
+            });
+        }
+        self.auth_state.secret.clear();
+        self.pfs_state = PfsState::default();
+        self.set_auth_state(SecretChatState::Closed);
+    }
+
+    fn save(&self) -> Result<()> {
+        self.db.set_value(&self.auth_state)?;
+        self.db.set_value(&self.config_state)?;
+        self.db.set_value(&self.seq_no_state)?;
+        self.db.set_value(&self.pfs_state)?;
+        self.db.set_value(&self.sent_messages)?;
+        self.db.set_value(&self.pending_inbound)?;
+        Ok(())
+    }
+
+    fn restore_handshake(&self, secret: Vec<u8>) -> Result<DhHandshake> {
+        let config = DhConfig::deserialize(&self.auth_state.dh_config)
+            .ok_or_else(|| Error::InvalidDhConfig("saved config is corrupted".into()))?;
+        DhHandshake::with_secret(&config, secret)
+    }
+
+    /// Returns the parity of `out_seq_no` of our messages.
+    fn my_parity(&self) -> i32 {
+        if self.auth_state.is_creator {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Returns the layer of the messages we send.
+    fn current_layer(&self) -> i32 {
+        self.config_state.my_layer.min(self.config_state.his_layer)
+    }
+
+    fn current_key(&self) -> Result<AuthKey> {
+        to_auth_key(&self.auth_state.auth_key)
+    }
+
+    /// Finds the key of an incoming message among the current key, the key
+    /// being switched to and the previous key.
+    fn find_key(&self, fingerprint: i64) -> Option<AuthKey> {
+        let mut candidates = vec![(&self.auth_state.auth_key, self.auth_state.key_fingerprint)];
+        if self.pfs_state.step == PfsStep::WaitCommit {
+            candidates.push((&self.pfs_state.new_key, self.pfs_state.new_key_fingerprint));
+        }
+        candidates.push((&self.pfs_state.old_key, self.pfs_state.old_key_fingerprint));
+        candidates
+            .into_iter()
+            .filter(|(key, key_fingerprint)| !key.is_empty() && *key_fingerprint == fingerprint)
+            .find_map(|(key, _)| to_auth_key(key).ok())
+    }
+
+    fn send_action(&mut self, action: DecryptedMessageAction) -> Result<()> {
+        self.send_payload(DecryptedPayload::Service {
+            random_id: rand::random(),
+            action,
+        })
+    }
+
+    fn send_payload(&mut self, message: DecryptedPayload) -> Result<()> {
+        let mut random_bytes = vec![0u8; MIN_RANDOM_BYTES];
+        rand::thread_rng().fill(&mut random_bytes[..]);
+        let layer = DecryptedMessageLayer {
+            random_bytes,
+            layer: self.current_layer(),
+            in_seq_no: 2 * self.seq_no_state.my_in_seq_no + (1 - self.my_parity()),
+            out_seq_no: 2 * self.seq_no_state.my_out_seq_no + self.my_parity(),
+            message,
+        };
+        let sent = SentMessage {
+            random_id: layer.random_id(),
+            is_service: matches!(layer.message, DecryptedPayload::Service { .. }),
+            payload: layer.serialize(),
+        };
+        self.send_encrypted(&sent)?;
+        self.sent_messages
+            .0
+            .insert(self.seq_no_state.my_out_seq_no, sent);
+        self.seq_no_state.my_out_seq_no += 1;
+        self.pfs_state.messages_with_key += 1;
+        Ok(())
+    }
+
+    fn send_encrypted(&mut self, message: &SentMessage) -> Result<()> {
+        let data = encrypt_message(
+            &self.current_key()?,
+            get_x(self.auth_state.is_creator),
+            &message.payload,
+        )?;
+        self.queries.push_back(SecretChatQuery::SendEncrypted {
+            chat_id: self.id,
+            access_hash: self.auth_state.access_hash,
+            random_id: message.random_id,
+            data,
+            is_service: message.is_service,
+        });
+        Ok(())
+    }
+
+    fn on_message_layer(&mut self, layer: DecryptedMessageLayer) -> Result<()> {
+        let his_parity = 1 - self.my_parity();
+        let invalid_seq_no = Error::InvalidSeqNo {
+            in_seq_no: layer.in_seq_no,
+            out_seq_no: layer.out_seq_no,
+        };
+        if layer.out_seq_no < 0
+            || layer.in_seq_no < 0
+            || layer.out_seq_no % 2 != his_parity
+            || layer.in_seq_no % 2 != self.my_parity()
+        {
+            return Err(invalid_seq_no);
+        }
+        let his_out_seq_no = layer.out_seq_no / 2;
+        if layer.in_seq_no / 2 > self.seq_no_state.my_out_seq_no {
+            return Err(invalid_seq_no);
+        }
+
+        let my_in_seq_no = self.seq_no_state.my_in_seq_no;
+        if his_out_seq_no < my_in_seq_no {
+            // Already handled, e.g. resent after a resend request
+            return Ok(());
+        }
+        if his_out_seq_no - my_in_seq_no > MAX_SEQ_NO_GAP {
+            self.discard(false);
+            self.save()?;
+            return Err(invalid_seq_no);
+        }
+        if his_out_seq_no > my_in_seq_no {
+            let pending = &mut self.pending_inbound.0;
+            if pending.len() >= MAX_PENDING_MESSAGES && !pending.contains_key(&his_out_seq_no) {
+                // Dropped; the next message requests it again
+                self.seq_no_state.resend_end_seq_no =
+                    self.seq_no_state.resend_end_seq_no.min(his_out_seq_no);
+                return Ok(());
+            }
+            pending.insert(his_out_seq_no, layer.serialize());
+            return self.request_resend(his_out_seq_no);
+        }
+
+        self.apply_message_layer(layer)?;
+        while let Some(payload) = self
+            .pending_inbound
+            .0
+            .remove(&self.seq_no_state.my_in_seq_no)
+        {
+            self.apply_message_layer(DecryptedMessageLayer::parse(&payload)?)?;
+        }
+        Ok(())
+    }
+
+    /// Requests the messages of the other party before `his_out_seq_no`
+    /// which weren't received or requested yet.
+    fn request_resend(&mut self, his_out_seq_no: i32) -> Result<()> {
+        let start = self
+            .seq_no_state
+            .my_in_seq_no
+            .max(self.seq_no_state.resend_end_seq_no);
+        if start >= his_out_seq_no {
+            return Ok(());
+        }
+        let his_parity = 1 - self.my_parity();
+        self.seq_no_state.resend_end_seq_no = his_out_seq_no;
+        self.send_action(DecryptedMessageAction::Resend {
+            start_seq_no: 2 * start + his_parity,
+            end_seq_no: 2 * (his_out_seq_no - 1) + his_parity,
+        })
+    }
+
+    fn apply_message_layer(&mut self, layer: DecryptedMessageLayer) -> Result<()> {
+        self.seq_no_state.my_in_seq_no += 1;
+        self.pfs_state.messages_with_key += 1;
+
+        let his_in_seq_no = layer.in_seq_no / 2;
+        if his_in_seq_no > self.seq_no_state.his_in_seq_no {
+            self.seq_no_state.his_in_seq_no = his_in_seq_no;
+            // The other party has everything before his_in_seq_no
+            self.sent_messages.0 = self.sent_messages.0.split_off(&his_in_seq_no);
+        }
+        self.update_his_layer(layer.layer);
+
+        match layer.message {
+            DecryptedPayload::Message(message) => {
+                self.events.push_back(SecretChatEvent::NewMessage(message));
+            }
+            DecryptedPayload::Service { action, .. } => self.on_action(action)?,
+        }
+        Ok(())
+    }
+
+    fn update_his_layer(&mut self, layer: i32) {
+        if layer > self.config_state.his_layer {
+            self.config_state.his_layer = layer;
+            self.seq_no_state.his_layer = layer;
+        }
+    }
+
+    fn on_action(&mut self, action: DecryptedMessageAction) -> Result<()> {
+        match action {
+            DecryptedMessageAction::SetMessageTtl(ttl) => {
+                self.config_state.ttl = ttl;
+                self.events.push_back(SecretChatEvent::TtlChanged(ttl));
+            }
+            DecryptedMessageAction::DeleteMessages(random_ids) => {
+                self.events
+                    .push_back(SecretChatEvent::MessagesDeleted(random_ids));
+            }
+            DecryptedMessageAction::NotifyLayer(layer) => self.update_his_layer(layer),
+            DecryptedMessageAction::Resend {
+                start_seq_no,
+                end_seq_no,
+            } => self.resend(start_seq_no, end_seq_no)?,
+            DecryptedMessageAction::RequestKey { exchange_id, g_a } => {
+                self.on_request_key(exchange_id, &g_a)?;
+            }
+            DecryptedMessageAction::AcceptKey {
+                exchange_id,
+                g_b,
+                key_fingerprint,
+            } => self.on_accept_key(exchange_id, &g_b, key_fingerprint)?,
+            DecryptedMessageAction::AbortKey { exchange_id } => {
+                if exchange_id == self.pfs_state.exchange_id {
+                    self.reset_pfs();
+                }
+            }
+            DecryptedMessageAction::CommitKey {
+                exchange_id,
+                key_fingerprint,
+            } => self.on_commit_key(exchange_id, key_fingerprint)?,
+            DecryptedMessageAction::Noop => {}
+        }
+        Ok(())
+    }
+
+    /// Sends again our messages with `out_seq_no` in the range.
+    fn resend(&mut self, start_seq_no: i32, end_seq_no: i32) -> Result<()> {
+        if start_seq_no % 2 != self.my_parity() || end_seq_no < start_seq_no {
+            return Err(Error::InvalidSeqNo {
+                in_seq_no: start_seq_no,
+                out_seq_no: end_seq_no,
+            });
+        }
+        let messages: Vec<SentMessage> = self
+            .sent_messages
+            .0
+            .range(start_seq_no / 2..=end_seq_no / 2)
+            .map(|(_, message)| message.clone())
+            .collect();
+        for message in &messages {
+            self.send_encrypted(message)?;
+        }
+        Ok(())
+    }
+
+    /// Starts re-keying if enough messages were sent with the current key.
+    fn check_pfs(&mut self) -> Result<()> {
+        if !self.is_ready()
+            || self.pfs_state.step != PfsStep::Empty
+            || self.pfs_state.messages_with_key < PFS_MESSAGE_COUNT
+        {
+            return Ok(());
+        }
+        let handshake = DhHandshake::new(&self.dh_config()?, &[])?;
+        let g_a = handshake.g_a()?;
+        self.pfs_state.step = PfsStep::WaitAccept;
+        self.pfs_state.exchange_id = rand::random();
+        self.pfs_state.secret = handshake.secret().to_vec();
+        self.send_action(DecryptedMessageAction::RequestKey {
+            exchange_id: self.pfs_state.exchange_id,
+            g_a,
+        })
+    }
+
+    fn on_request_key(&mut self, exchange_id: i64, g_a: &[u8]) -> Result<()> {
+        match self.pfs_state.step {
+            // Both parties started re-keying, the larger exchange_id wins
+            PfsStep::WaitAccept if self.pfs_state.exchange_id > exchange_id => return Ok(()),
+            PfsStep::WaitCommit => {
+                return self.send_action(DecryptedMessageAction::AbortKey { exchange_id })
+            }
+            _ => {}
+        }
+
+        let handshake = DhHandshake::new(&self.dh_config()?, &[])?;
+        let new_key = match handshake.compute_key(g_a) {
+            Ok(new_key) => new_key,
+            Err(_) => {
+                self.reset_pfs();
+                return self.send_action(DecryptedMessageAction::AbortKey { exchange_id });
+            }
+        };
+        let g_b = handshake.g_a()?;
+        self.pfs_state.step = PfsStep::WaitCommit;
+        self.pfs_state.exchange_id = exchange_id;
+        self.pfs_state.secret.clear();
+        self.pfs_state.new_key = new_key.to_vec();
+        self.pfs_state.new_key_fingerprint = key_fingerprint(&new_key);
+        self.send_action(DecryptedMessageAction::AcceptKey {
+            exchange_id,
+            g_b,
+            key_fingerprint: self.pfs_state.new_key_fingerprint,
+        })
+    }
+
+    fn on_accept_key(&mut self, exchange_id: i64, g_b: &[u8], fingerprint: i64) -> Result<()> {
+        if self.pfs_state.step != PfsStep::WaitAccept || self.pfs_state.exchange_id != exchange_id {
+            return Ok(());
+        }
+        let new_key = self
+            .restore_handshake(self.pfs_state.secret.clone())
+            .and_then(|handshake| handshake.compute_key(g_b))
+            .and_then(|new_key| check_fingerprint(&new_key, fingerprint).map(|()| new_key));
+        match new_key {
+            Ok(new_key) => {
+                // The commit is the last message encrypted with the old key
+                self.send_action(DecryptedMessageAction::CommitKey {
+                    exchange_id,
+                    key_fingerprint: fingerprint,
+                })?;
+                self.switch_key(&new_key);
+                Ok(())
+            }
+            Err(_) => {
+                self.reset_pfs();
+                self.send_action(DecryptedMessageAction::AbortKey { exchange_id })
+            }
+        }
+    }
+
+    fn on_commit_key(&mut self, exchange_id: i64, fingerprint: i64) -> Result<()> {
+        if self.pfs_state.step != PfsStep::WaitCommit || self.pfs_state.exchange_id != exchange_id {
+            return Ok(());
+        }
+        if fingerprint != self.pfs_state.new_key_fingerprint {
+            self.reset_pfs();
+            return self.send_action(DecryptedMessageAction::AbortKey { exchange_id });
+        }
+        let new_key = to_auth_key(&self.pfs_state.new_key)?;
+        self.switch_key(&new_key);
+        // Confirms the switch with the first message encrypted with the new key
+        self.send_action(DecryptedMessageAction::Noop)
+    }
+
+    fn switch_key(&mut self, new_key: &AuthKey) {
+        self.pfs_state.old_key = std::mem::take(&mut self.auth_state.auth_key);
+        self.pfs_state.old_key_fingerprint = self.auth_state.key_fingerprint;
+        self.set_auth_key(new_key);
+        self.reset_pfs();
+    }
+
+    fn reset_pfs(&mut self) {
+        self.pfs_state.step = PfsStep::Empty;
+        self.pfs_state.secret.clear();
+        self.pfs_state.new_key.clear();
+        self.pfs_state.new_key_fingerprint = 0;
+        self.pfs_state.messages_with_key = 0;
+    }
+
+    fn dh_config(&self) -> Result<DhConfig> {
+        DhConfig::deserialize(&self.auth_state.dh_config)
+            .ok_or_else(|| Error::InvalidDhConfig("saved config is corrupted".into()))
     }
 }
 
-impl<C> Actor for SecretChatActor<C> where C: rustgram_secret_chat_db::KeyValueSyncInterface + 'static {}
+impl<C> Actor for SecretChatActor<C>
+where
+    C: KeyValueSyncInterface + 'static,
+{
+    fn start_up(&mut self) {}
+
+    fn wakeup(&mut self) {}
+
+    fn hangup(&mut self) {}
+
+    fn tear_down(&mut self) {}
 
-impl<C> Debug for SecretChatActor<C> {
+    fn loop_exec(&mut self) {}
+
+    fn timeout_expired(&mut self) {}
+}
+
+impl<C: KeyValueSyncInterface> Debug for SecretChatActor<C> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("SecretChatActor")
             .field("id", &self.id)
-            .field("state", &self.state)
+            .field("state", &self.auth_state.state)
             .field("config_state", &self.config_state)
             .field("seq_no_state", &self.seq_no_state)
-            .field("has_auth_key", &self.auth_key.is_some())
+            .field("has_auth_key", &!self.auth_state.auth_key.is_empty())
             .finish()
     }
 }
 
+fn get_dh_config_query() -> SecretChatQuery {
+    SecretChatQuery::GetDhConfig {
+        version: 0,
+        random_length: DH_KEY_SIZE as i32,
+    }
+}
+
+fn to_auth_key(key: &[u8]) -> Result<AuthKey> {
+    AuthKey::try_from(key).map_err(|_| Error::Crypto("the key isn't known".into()))
+}
+
+fn check_fingerprint(auth_key: &AuthKey, fingerprint: i64) -> Result<()> {
+    let expected = key_fingerprint(auth_key);
+    if expected == fingerprint {
+        Ok(())
+    } else {
+        Err(Error::KeyFingerprintMismatch {
+            expected,
+            actual: fingerprint,
+        })
+    }
+}
+
+fn get_value_or_default<C, V>(db: &SecretChatDb<C>) -> Result<V>
+where
+    C: KeyValueSyncInterface,
+    V: SecretChatValue + Default,
+{
+    match db.get_value::<V>() {
+        Ok(value) => Ok(value),
+        Err(SecretChatDbError::NotFound(_)) => Ok(V::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
 /// State machine for secret chat.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SecretChatState {
     /// No chat yet
     Empty,
@@ -514,7 +1214,7 @@ pub enum SecretChatState {
 }
 
 /// Configuration state for secret chat.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConfigState {
     /// His layer (default 8)
     pub his_layer: i32,
@@ -534,16 +1234,25 @@ impl Default for ConfigState {
     }
 }
 
+impl SecretChatValue for ConfigState {
+    fn key() -> &'static str {
+        "config_state"
+    }
+}
+
 /// Message sequencing state.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// The sequence numbers count messages; on the wire they are doubled, with
+/// the lower bit telling the creator (0) and the participant (1) apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SeqNoState {
     /// Current message ID
     pub message_id: i32,
-    /// My incoming sequence number
+    /// Number of messages received from the other party
     pub my_in_seq_no: i32,
-    /// My outgoing sequence number
+    /// Number of messages sent to the other party
     pub my_out_seq_no: i32,
-    /// His incoming sequence number
+    /// Number of our messages the other party confirmed
     pub his_in_seq_no: i32,
     /// His layer version
     pub his_layer: i32,
@@ -564,6 +1273,12 @@ impl Default for SeqNoState {
     }
 }
 
+impl SecretChatValue for SeqNoState {
+    fn key() -> &'static str {
+        "seq_no_state"
+    }
+}
+
 /// Stub for sent code in email verification.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SentEmailCode {
@@ -576,24 +1291,33 @@ pub struct SentEmailCode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
 
-    struct MockStorage(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+    #[derive(Clone, Default)]
+    struct MockStorage(Arc<Mutex<HashMap<String, Bytes>>>);
 
-    impl rustgram_secret_chat_db::KeyValueSyncInterface for MockStorage {
-        fn set(&self, key: String, value: bytes::Bytes) -> Result<(), Box<dyn std::error::Error>> {
+    impl KeyValueSyncInterface for MockStorage {
+        fn set(
+            &self,
+            key: String,
+            value: Bytes,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
             let mut data = self.0.lock().unwrap();
-            data.insert(key, value.to_vec());
+            data.insert(key, value);
             Ok(())
         }
 
-        fn get(&self, key: String) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> {
+        fn get(
+            &self,
+            key: String,
+        ) -> std::result::Result<Option<Bytes>, Box<dyn std::error::Error>> {
             let data = self.0.lock().unwrap();
-            Ok(data.get(&key).cloned().map(bytes::Bytes::from))
+            Ok(data.get(&key).cloned())
         }
 
-        fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> {
+        fn erase(&self, key: String) -> std::result::Result<(), Box<dyn std::error::Error>> {
             let mut data = self.0.lock().unwrap();
             data.remove(&key);
             Ok(())
@@ -601,8 +1325,7 @@ mod tests {
     }
 
     fn create_test_actor() -> SecretChatActor<MockStorage> {
-        let storage = MockStorage(Arc::new(Mutex::new(HashMap::new())));
-        let db = SecretChatDb::new(storage, 12345);
+        let db = SecretChatDb::new(MockStorage::default(), 12345);
         SecretChatActor::new(12345, db, true)
     }
 
@@ -613,22 +1336,16 @@ mod tests {
         assert_eq!(actor.state(), SecretChatState::Empty);
         assert!(!actor.is_ready());
         assert!(!actor.is_closed());
+        assert_eq!(actor.key_fingerprint(), None);
     }
 
     #[test]
     fn test_secret_chat_actor_new_cannot_be_empty() {
-        let storage = MockStorage(Arc::new(Mutex::new(HashMap::new())));
-        let db = SecretChatDb::new(storage, 12345);
+        let db = SecretChatDb::new(MockStorage::default(), 12345);
         let actor = SecretChatActor::new(12345, db, false);
         assert_eq!(actor.state(), SecretChatState::SendRequest);
     }
 
-    #[test]
-    fn test_secret_chat_actor_state() {
-        let actor = create_test_actor();
-        assert_eq!(actor.state(), SecretChatState::Empty);
-    }
-
     #[test]
     fn test_secret_chat_actor_config_state() {
         let actor = create_test_actor();
@@ -678,42 +1395,129 @@ mod tests {
     #[test]
     fn test_secret_chat_actor_create_chat() {
         let mut actor = create_test_actor();
-        let promise = Promise::new();
         let user_id = UserId::new(123).unwrap();
-        actor.create_chat(user_id, 0, 123, promise);
+        actor.create_chat(user_id, 7).unwrap();
         assert_eq!(actor.state(), SecretChatState::SendRequest);
+        assert!(actor.is_creator());
+        assert_eq!(
+            actor.config_state().my_layer,
+            SecretChatLayer::recommended().value()
+        );
+        assert_eq!(
+            actor.take_events(),
+            vec![SecretChatEvent::StateChanged(SecretChatState::SendRequest)]
+        );
+
+        actor
+            .on_dh_config(&DhConfig::default_safe(), &[3; 256])
+            .unwrap();
+        assert_eq!(actor.state(), SecretChatState::WaitRequestResponse);
+        let queries = actor.take_queries();
+        assert_eq!(queries.len(), 2);
+        assert!(matches!(
+            &queries[1],
+            SecretChatQuery::RequestEncryption { user_id: UserId(123), access_hash: 7, random_id: 12345, g_a }
+                if g_a.len() == DH_KEY_SIZE
+        ));
+
+        assert_eq!(
+            actor.create_chat(user_id, 7),
+            Err(Error::InvalidState(SecretChatState::WaitRequestResponse))
+        );
     }
 
     #[test]
-    fn test_secret_chat_actor_cancel_chat() {
+    fn test_secret_chat_actor_fingerprint_mismatch() {
         let mut actor = create_test_actor();
-        let promise = Promise::new();
-        actor.cancel_chat(false, false, promise);
+        actor.create_chat(UserId(123), 0).unwrap();
+        actor.on_dh_config(&DhConfig::default_safe(), &[]).unwrap();
+        let other = DhHandshake::new(&DhConfig::default_safe(), &[]).unwrap();
+        let result = actor.on_encrypted_chat(EncryptedChat::Chat {
+            id: 12345,
+            access_hash: 1,
+            g_a_or_b: other.g_a().unwrap(),
+            key_fingerprint: 42,
+        });
+        assert!(matches!(
+            result,
+            Err(Error::KeyFingerprintMismatch { actual: 42, .. })
+        ));
         assert!(actor.is_closed());
+        assert!(actor
+            .take_queries()
+            .contains(&SecretChatQuery::DiscardEncryption {
+                chat_id: 12345,
+                delete_history: false
+            }));
     }
 
     #[test]
-    fn test_secret_chat_actor_add_inbound_message() {
+    fn test_secret_chat_actor_cancel_chat() {
+        let mut actor = create_test_actor();
+        actor.cancel_chat(false, false).unwrap();
+        assert!(actor.is_closed());
+        // Nothing to discard on the server yet
+        assert!(actor.take_queries().is_empty());
+
         let mut actor = create_test_actor();
-        let message_id = MessageId::from_i32(1).unwrap();
-        actor.add_inbound_message(message_id);
-        assert_eq!(actor.seq_no_state().message_id, 1);
+        actor.set_state(SecretChatState::Ready);
+        actor.cancel_chat(true, false).unwrap();
+        assert_eq!(
+            actor.take_queries(),
+            vec![SecretChatQuery::DiscardEncryption {
+                chat_id: 12345,
+                delete_history: true
+            }]
+        );
+
+        // Closing twice does nothing
+        actor.cancel_chat(true, false).unwrap();
+        assert!(actor.take_queries().is_empty());
     }
 
     #[test]
-    fn test_secret_chat_actor_send_message() {
+    fn test_secret_chat_actor_not_ready() {
         let mut actor = create_test_actor();
-        let message_id = MessageId::from_i32(1).unwrap();
-        actor.send_message(message_id);
-        assert_eq!(actor.seq_no_state().my_out_seq_no, 1);
+        assert_eq!(
+            actor.send_message(DecryptedMessage::text(1, "hi")),
+            Err(Error::InvalidState(SecretChatState::Empty))
+        );
+        assert_eq!(
+            actor.delete_messages(&[1]),
+            Err(Error::InvalidState(SecretChatState::Empty))
+        );
+        assert_eq!(
+            actor.set_ttl(10),
+            Err(Error::InvalidState(SecretChatState::Empty))
+        );
+        assert_eq!(
+            actor.on_new_message(EncryptedMessage {
+                random_id: 1,
+                date: 0,
+                data: vec![0; 64],
+            }),
+            Err(Error::InvalidState(SecretChatState::Empty))
+        );
     }
 
     #[test]
-    fn test_secret_chat_actor_delete_messages() {
-        let mut actor = create_test_actor();
-        let message_id = MessageId::from_i32(1).unwrap();
-        actor.delete_messages(&[message_id]);
-        // Should not panic
+    fn test_secret_chat_actor_load() {
+        let storage = MockStorage::default();
+        let mut actor =
+            SecretChatActor::new(12345, SecretChatDb::new(storage.clone(), 12345), true);
+        assert!(matches!(
+            SecretChatActor::load(12345, SecretChatDb::new(storage.clone(), 12345)),
+            Err(Error::Db(SecretChatDbError::NotFound(_)))
+        ));
+
+        actor.create_chat(UserId(123), 7).unwrap();
+        actor.on_dh_config(&DhConfig::default_safe(), &[]).unwrap();
+
+        let loaded = SecretChatActor::load(12345, SecretChatDb::new(storage, 12345)).unwrap();
+        assert_eq!(loaded.state(), SecretChatState::WaitRequestResponse);
+        assert!(loaded.is_creator());
+        assert_eq!(loaded.auth_state, actor.auth_state);
+        assert_eq!(loaded.config_state, actor.config_state);
     }
 
     #[test]
@@ -775,34 +1579,6 @@ mod tests {
         assert!(actor.is_ready());
     }
 
-    #[test]
-    fn test_secret_chat_actor_send_multiple_messages() {
-        let mut actor = create_test_actor();
-
-        actor.send_message(MessageId::from_i32(1).unwrap());
-        assert_eq!(actor.seq_no_state().my_out_seq_no, 1);
-
-        actor.send_message(MessageId::from_i32(2).unwrap());
-        assert_eq!(actor.seq_no_state().my_out_seq_no, 2);
-
-        actor.send_message(MessageId::from_i32(3).unwrap());
-        assert_eq!(actor.seq_no_state().my_out_seq_no, 3);
-    }
-
-    #[test]
-    fn test_secret_chat_actor_close_and_reopen() {
-        let mut actor = create_test_actor();
-
-        actor.set_state(SecretChatState::Ready);
-        assert!(actor.is_ready());
-
-        actor.cancel_chat(false, false, Promise::new());
-        assert!(actor.is_closed());
-
-        // Can't reopen in this stub implementation
-        assert!(actor.is_closed());
-    }
-
     #[test]
     fn test_secret_chat_actor_config_state_fields() {
         let config = ConfigState {
@@ -871,10 +1647,8 @@ mod tests {
 
     #[test]
     fn test_secret_chat_actor_different_ids() {
-        let storage1 = MockStorage(Arc::new(Mutex::new(HashMap::new())));
-        let storage2 = MockStorage(Arc::new(Mutex::new(HashMap::new())));
-        let db1 = SecretChatDb::new(storage1, 111);
-        let db2 = SecretChatDb::new(storage2, 222);
+        let db1 = SecretChatDb::new(MockStorage::default(), 111);
+        let db2 = SecretChatDb::new(MockStorage::default(), 222);
 
         let actor1 = SecretChatActor::new(111, db1, true);
         let actor2 = SecretChatActor::new(222, db2, true);
@@ -891,6 +1665,7 @@ mod tests {
 
         assert_eq!(actor.config_state().my_layer, 144);
         assert_eq!(actor.config_state().his_layer, 143);
+        assert_eq!(actor.current_layer(), 143);
     }
 
     #[test]
@@ -901,19 +1676,6 @@ mod tests {
         assert_eq!(actor.config_state().ttl, 30);
     }
 
-    #[test]
-    fn test_secret_chat_actor_delete_multiple_messages() {
-        let mut actor = create_test_actor();
-        let ids = vec![
-            MessageId::from_i32(1).unwrap(),
-            MessageId::from_i32(2).unwrap(),
-            MessageId::from_i32(3).unwrap(),
-        ];
-
-        actor.delete_messages(&ids);
-        // Should not panic
-    }
-
     #[test]
     fn test_secret_chat_actor_state_hash() {
         use std::collections::HashSet;
@@ -929,4 +1691,83 @@ mod tests {
 
         assert_eq!(set.len(), 7);
     }
+
+    fn create_ready_actor(storage: MockStorage) -> SecretChatActor<MockStorage> {
+        let db = SecretChatDb::new(storage, 12345);
+        let mut actor = SecretChatActor::new(12345, db, true);
+        actor.auth_state.state = SecretChatState::Ready;
+        actor.auth_state.auth_key = vec![7; DH_KEY_SIZE];
+        actor
+    }
+
+    /// Message number `his_out_seq_no` of the other party, who didn't get
+    /// any of ours.
+    fn incoming_layer(his_out_seq_no: i32) -> DecryptedMessageLayer {
+        DecryptedMessageLayer {
+            random_bytes: vec![0; MIN_RANDOM_BYTES],
+            layer: 8,
+            in_seq_no: 1,
+            out_seq_no: 2 * his_out_seq_no,
+            message: DecryptedPayload::Message(DecryptedMessage::text(
+                i64::from(his_out_seq_no),
+                format!("message {}", his_out_seq_no),
+            )),
+        }
+    }
+
+    fn new_message_count(actor: &mut SecretChatActor<MockStorage>) -> usize {
+        actor
+            .take_events()
+            .into_iter()
+            .filter(|event| matches!(event, SecretChatEvent::NewMessage(_)))
+            .count()
+    }
+
+    #[test]
+    fn test_too_big_seq_no_gap_closes_chat() {
+        let mut actor = create_ready_actor(MockStorage::default());
+
+        let result = actor.on_message_layer(incoming_layer(MAX_SEQ_NO_GAP + 1));
+        assert!(matches!(result, Err(Error::InvalidSeqNo { .. })));
+        assert!(actor.is_closed());
+        assert!(actor.pending_inbound.0.is_empty());
+    }
+
+    #[test]
+    fn test_pending_inbound_is_limited() {
+        let mut actor = create_ready_actor(MockStorage::default());
+
+        let last = MAX_PENDING_MESSAGES as i32 + 1;
+        for his_out_seq_no in (1..=last).rev() {
+            actor
+                .on_message_layer(incoming_layer(his_out_seq_no))
+                .unwrap();
+        }
+        assert_eq!(actor.pending_inbound.0.len(), MAX_PENDING_MESSAGES);
+        assert!(!actor.pending_inbound.0.contains_key(&1));
+        assert_eq!(actor.seq_no_state.resend_end_seq_no, 1);
+
+        // The gap is filled up to the dropped message
+        actor.on_message_layer(incoming_layer(0)).unwrap();
+        assert_eq!(new_message_count(&mut actor), 1);
+        actor.on_message_layer(incoming_layer(1)).unwrap();
+        assert_eq!(new_message_count(&mut actor), MAX_PENDING_MESSAGES + 1);
+        assert_eq!(actor.seq_no_state.my_in_seq_no, last + 1);
+    }
+
+    #[test]
+    fn test_pending_inbound_is_saved() {
+        let storage = MockStorage::default();
+        let mut actor = create_ready_actor(storage.clone());
+        actor.on_message_layer(incoming_layer(2)).unwrap();
+        actor.save().unwrap();
+
+        let db = SecretChatDb::new(storage, 12345);
+        let mut actor = SecretChatActor::load(12345, db).unwrap();
+        assert_eq!(actor.pending_inbound.0.len(), 1);
+        actor.on_message_layer(incoming_layer(0)).unwrap();
+        actor.on_message_layer(incoming_layer(1)).unwrap();
+        assert_eq!(new_message_count(&mut actor), 3);
+        assert!(actor.pending_inbound.0.is_empty());
+    }
 }
@@ -0,0 +1,138 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Network queries and updates of secret chats.
+//!
+//! The actor doesn't talk to the network itself. It queues the queries to
+//! send, and it is given the answers and updates back.
+
+use crate::tl::DecryptedMessage;
+use crate::SecretChatState;
+use rustgram_types::UserId;
+
+/// A query to send to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretChatQuery {
+    /// `messages.getDhConfig`
+    GetDhConfig {
+        /// Version of the known config
+        version: i32,
+        /// Number of random bytes to return
+        random_length: i32,
+    },
+    /// `messages.requestEncryption`
+    RequestEncryption {
+        /// The user to create the chat with
+        user_id: UserId,
+        /// Access hash of the user
+        access_hash: i64,
+        /// Random identifier of the chat
+        random_id: i32,
+        /// `g_a` of the creator
+        g_a: Vec<u8>,
+    },
+    /// `messages.acceptEncryption`
+    AcceptEncryption {
+        /// Secret chat ID
+        chat_id: i32,
+        /// Access hash of the chat
+        access_hash: i64,
+        /// `g_b` of the participant
+        g_b: Vec<u8>,
+        /// Fingerprint of the computed key
+        key_fingerprint: i64,
+    },
+    /// `messages.discardEncryption`
+    DiscardEncryption {
+        /// Secret chat ID
+        chat_id: i32,
+        /// Whether the history is deleted for the other party too
+        delete_history: bool,
+    },
+    /// `messages.sendEncrypted` or `messages.sendEncryptedService`
+    SendEncrypted {
+        /// Secret chat ID
+        chat_id: i32,
+        /// Access hash of the chat
+        access_hash: i64,
+        /// Random identifier of the message
+        random_id: i64,
+        /// The encrypted message
+        data: Vec<u8>,
+        /// Whether the message is a service message
+        is_service: bool,
+    },
+}
+
+/// The state of a chat on the server (`EncryptedChat`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptedChat {
+    /// `encryptedChatWaiting`, the answer to `messages.requestEncryption`
+    Waiting {
+        /// Secret chat ID
+        id: i32,
+        /// Access hash of the chat
+        access_hash: i64,
+        /// The other party
+        participant_id: UserId,
+    },
+    /// `encryptedChatRequested`, a chat created by another user
+    Requested {
+        /// Secret chat ID
+        id: i32,
+        /// Access hash of the chat
+        access_hash: i64,
+        /// The creator of the chat
+        admin_id: UserId,
+        /// `g_a` of the creator
+        g_a: Vec<u8>,
+    },
+    /// `encryptedChat`, an accepted chat
+    Chat {
+        /// Secret chat ID
+        id: i32,
+        /// Access hash of the chat
+        access_hash: i64,
+        /// `g_b` for the creator, `g_a` for the participant
+        g_a_or_b: Vec<u8>,
+        /// Fingerprint of the key
+        key_fingerprint: i64,
+    },
+    /// `encryptedChatDiscarded`
+    Discarded {
+        /// Secret chat ID
+        id: i32,
+        /// Whether the history must be deleted
+        history_deleted: bool,
+    },
+}
+
+/// An encrypted message from `updateNewEncryptedMessage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    /// Random identifier of the message
+    pub random_id: i64,
+    /// Date the message was sent
+    pub date: i32,
+    /// The encrypted message
+    pub data: Vec<u8>,
+}
+
+/// Something the rest of the client should know about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretChatEvent {
+    /// The state of the chat changed
+    StateChanged(SecretChatState),
+    /// A new message was received
+    NewMessage(DecryptedMessage),
+    /// The other party deleted messages
+    MessagesDeleted(Vec<i64>),
+    /// The other party changed the self-destruct timer
+    TtlChanged(i32),
+    /// The chat was discarded, with or without the history
+    Discarded {
+        /// Whether the history must be deleted
+        history_deleted: bool,
+    },
+}
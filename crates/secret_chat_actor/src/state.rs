@@ -0,0 +1,117 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! State of a secret chat saved in the secret chat database.
+
+use crate::SecretChatState;
+use rustgram_secret_chat_db::SecretChatValue;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Parties, keys and the state of the initial key exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AuthState {
+    pub state: SecretChatState,
+    /// Whether we created the chat; selects `x` for encryption and seq_no
+    pub is_creator: bool,
+    pub user_id: i64,
+    pub user_access_hash: i64,
+    pub access_hash: i64,
+    /// `DhConfig::serialize` of the config used for the chat
+    pub dh_config: Vec<u8>,
+    /// Our DH secret, kept until the key is computed
+    pub secret: Vec<u8>,
+    /// `g_a` of the creator, kept by the participant until accepting
+    pub g_a: Vec<u8>,
+    pub auth_key: Vec<u8>,
+    pub key_fingerprint: i64,
+}
+
+impl AuthState {
+    pub fn new(state: SecretChatState) -> Self {
+        Self {
+            state,
+            is_creator: false,
+            user_id: 0,
+            user_access_hash: 0,
+            access_hash: 0,
+            dh_config: Vec::new(),
+            secret: Vec::new(),
+            g_a: Vec::new(),
+            auth_key: Vec::new(),
+            key_fingerprint: 0,
+        }
+    }
+}
+
+impl SecretChatValue for AuthState {
+    fn key() -> &'static str {
+        "auth_state"
+    }
+}
+
+/// Step of a re-keying.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum PfsStep {
+    /// No re-keying in progress
+    #[default]
+    Empty,
+    /// We sent `requestKey`
+    WaitAccept,
+    /// We sent `acceptKey`
+    WaitCommit,
+}
+
+/// State of perfect forward secrecy.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PfsState {
+    pub step: PfsStep,
+    pub exchange_id: i64,
+    /// Our DH secret of the exchange
+    pub secret: Vec<u8>,
+    /// The key being switched to
+    pub new_key: Vec<u8>,
+    pub new_key_fingerprint: i64,
+    /// The previous key, for messages sent before the other party switched
+    pub old_key: Vec<u8>,
+    pub old_key_fingerprint: i64,
+    /// Number of messages sent and received with the current key
+    pub messages_with_key: i32,
+}
+
+impl SecretChatValue for PfsState {
+    fn key() -> &'static str {
+        "pfs_state"
+    }
+}
+
+/// A sent message, kept until the other party confirms it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SentMessage {
+    pub random_id: i64,
+    pub is_service: bool,
+    /// Serialized `decryptedMessageLayer`
+    pub payload: Vec<u8>,
+}
+
+/// Sent messages by their number in our outbound sequence.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SentMessages(pub BTreeMap<i32, SentMessage>);
+
+impl SecretChatValue for SentMessages {
+    fn key() -> &'static str {
+        "sent_messages"
+    }
+}
+
+/// Messages received ahead of a gap, by their number in the sequence of the
+/// other party.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PendingMessages(pub BTreeMap<i32, Vec<u8>>);
+
+impl SecretChatValue for PendingMessages {
+    fn key() -> &'static str {
+        "pending_inbound"
+    }
+}
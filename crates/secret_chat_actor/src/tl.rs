@@ -0,0 +1,504 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Decrypted messages of the secret chat API.
+//!
+//! Only the constructors needed by the actor are supported: text messages
+//! without media and the service actions used for layer negotiation,
+//! resend requests, deletion and re-keying.
+
+use crate::error::{Error, Result};
+
+const DECRYPTED_MESSAGE_LAYER: u32 = 0x1be3_1789;
+const DECRYPTED_MESSAGE: u32 = 0x91cc_4674;
+const DECRYPTED_MESSAGE_SERVICE: u32 = 0x7316_4160;
+const ACTION_SET_MESSAGE_TTL: u32 = 0xa173_3aec;
+const ACTION_DELETE_MESSAGES: u32 = 0x6561_4304;
+const ACTION_NOTIFY_LAYER: u32 = 0xf304_8883;
+const ACTION_RESEND: u32 = 0x5111_10b0;
+const ACTION_REQUEST_KEY: u32 = 0xf3c9_611b;
+const ACTION_ACCEPT_KEY: u32 = 0x6fe1_735b;
+const ACTION_ABORT_KEY: u32 = 0xdd05_ec6b;
+const ACTION_COMMIT_KEY: u32 = 0xec2e_0b9b;
+const ACTION_NOOP: u32 = 0xa82f_dd63;
+const VECTOR: u32 = 0x1cb5_c415;
+
+const FLAG_REPLY_TO_RANDOM_ID: i32 = 1 << 3;
+const FLAG_SILENT: i32 = 1 << 5;
+const FLAG_ENTITIES: i32 = 1 << 7;
+const FLAG_MEDIA: i32 = 1 << 9;
+const FLAG_VIA_BOT_NAME: i32 = 1 << 11;
+const FLAG_GROUPED_ID: i32 = 1 << 17;
+
+/// Minimum number of random bytes in `decryptedMessageLayer`.
+pub(crate) const MIN_RANDOM_BYTES: usize = 15;
+
+/// A text message of a secret chat (`decryptedMessage`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptedMessage {
+    /// Random identifier of the message, shared by both parties
+    pub random_id: i64,
+    /// Self-destruct timer in seconds
+    pub ttl: i32,
+    /// Text of the message
+    pub message: String,
+    /// Whether the message is sent without a notification
+    pub silent: bool,
+    /// Random identifier of the replied message
+    pub reply_to_random_id: Option<i64>,
+}
+
+impl DecryptedMessage {
+    /// Creates a text message.
+    #[must_use]
+    pub fn text(random_id: i64, message: impl Into<String>) -> Self {
+        Self {
+            random_id,
+            ttl: 0,
+            message: message.into(),
+            silent: false,
+            reply_to_random_id: None,
+        }
+    }
+}
+
+/// A service action of a secret chat (`DecryptedMessageAction`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecryptedMessageAction {
+    /// Sets the self-destruct timer of new messages
+    SetMessageTtl(i32),
+    /// Deletes messages by random identifiers
+    DeleteMessages(Vec<i64>),
+    /// Notifies about the layer supported by the sender
+    NotifyLayer(i32),
+    /// Requests messages with sequence numbers in the range again
+    Resend {
+        /// First `out_seq_no` to resend
+        start_seq_no: i32,
+        /// Last `out_seq_no` to resend
+        end_seq_no: i32,
+    },
+    /// Starts re-keying
+    RequestKey {
+        /// Identifier of the key exchange
+        exchange_id: i64,
+        /// `g_a` of the initiator
+        g_a: Vec<u8>,
+    },
+    /// Accepts re-keying
+    AcceptKey {
+        /// Identifier of the key exchange
+        exchange_id: i64,
+        /// `g_b` of the acceptor
+        g_b: Vec<u8>,
+        /// Fingerprint of the new key
+        key_fingerprint: i64,
+    },
+    /// Aborts re-keying
+    AbortKey {
+        /// Identifier of the key exchange
+        exchange_id: i64,
+    },
+    /// Switches both parties to the new key
+    CommitKey {
+        /// Identifier of the key exchange
+        exchange_id: i64,
+        /// Fingerprint of the new key
+        key_fingerprint: i64,
+    },
+    /// Does nothing, used to fill gaps in sequence numbers
+    Noop,
+}
+
+/// The message inside `decryptedMessageLayer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DecryptedPayload {
+    Message(DecryptedMessage),
+    Service {
+        random_id: i64,
+        action: DecryptedMessageAction,
+    },
+}
+
+/// `decryptedMessageLayer`, the payload of every encrypted message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DecryptedMessageLayer {
+    pub random_bytes: Vec<u8>,
+    pub layer: i32,
+    pub in_seq_no: i32,
+    pub out_seq_no: i32,
+    pub message: DecryptedPayload,
+}
+
+impl DecryptedMessageLayer {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_u32(&mut buf, DECRYPTED_MESSAGE_LAYER);
+        write_bytes(&mut buf, &self.random_bytes);
+        write_i32(&mut buf, self.layer);
+        write_i32(&mut buf, self.in_seq_no);
+        write_i32(&mut buf, self.out_seq_no);
+        match &self.message {
+            DecryptedPayload::Message(message) => {
+                write_u32(&mut buf, DECRYPTED_MESSAGE);
+                let mut flags = 0;
+                if message.silent {
+                    flags |= FLAG_SILENT;
+                }
+                if message.reply_to_random_id.is_some() {
+                    flags |= FLAG_REPLY_TO_RANDOM_ID;
+                }
+                write_i32(&mut buf, flags);
+                write_i64(&mut buf, message.random_id);
+                write_i32(&mut buf, message.ttl);
+                write_bytes(&mut buf, message.message.as_bytes());
+                if let Some(reply_to_random_id) = message.reply_to_random_id {
+                    write_i64(&mut buf, reply_to_random_id);
+                }
+            }
+            DecryptedPayload::Service { random_id, action } => {
+                write_u32(&mut buf, DECRYPTED_MESSAGE_SERVICE);
+                write_i64(&mut buf, *random_id);
+                write_action(&mut buf, action);
+            }
+        }
+        buf
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        reader.expect(DECRYPTED_MESSAGE_LAYER)?;
+        let random_bytes = reader.read_bytes()?;
+        if random_bytes.len() < MIN_RANDOM_BYTES {
+            return Err(Error::Parse("too few random bytes".into()));
+        }
+        let layer = reader.read_i32()?;
+        let in_seq_no = reader.read_i32()?;
+        let out_seq_no = reader.read_i32()?;
+        let message = match reader.read_u32()? {
+            DECRYPTED_MESSAGE => {
+                let flags = reader.read_i32()?;
+                if flags & (FLAG_MEDIA | FLAG_ENTITIES | FLAG_VIA_BOT_NAME | FLAG_GROUPED_ID) != 0 {
+                    return Err(Error::Parse(format!(
+                        "unsupported message flags {:#x}",
+                        flags
+                    )));
+                }
+                let random_id = reader.read_i64()?;
+                let ttl = reader.read_i32()?;
+                let message = String::from_utf8(reader.read_bytes()?)
+                    .map_err(|e| Error::Parse(e.to_string()))?;
+                let reply_to_random_id = if flags & FLAG_REPLY_TO_RANDOM_ID != 0 {
+                    Some(reader.read_i64()?)
+                } else {
+                    None
+                };
+                DecryptedPayload::Message(DecryptedMessage {
+                    random_id,
+                    ttl,
+                    message,
+                    silent: flags & FLAG_SILENT != 0,
+                    reply_to_random_id,
+                })
+            }
+            DECRYPTED_MESSAGE_SERVICE => DecryptedPayload::Service {
+                random_id: reader.read_i64()?,
+                action: read_action(&mut reader)?,
+            },
+            constructor => {
+                return Err(Error::Parse(format!(
+                    "unknown message constructor {:#x}",
+                    constructor
+                )))
+            }
+        };
+        Ok(Self {
+            random_bytes,
+            layer,
+            in_seq_no,
+            out_seq_no,
+            message,
+        })
+    }
+
+    pub fn random_id(&self) -> i64 {
+        match &self.message {
+            DecryptedPayload::Message(message) => message.random_id,
+            DecryptedPayload::Service { random_id, .. } => *random_id,
+        }
+    }
+}
+
+fn write_action(buf: &mut Vec<u8>, action: &DecryptedMessageAction) {
+    match action {
+        DecryptedMessageAction::SetMessageTtl(ttl) => {
+            write_u32(buf, ACTION_SET_MESSAGE_TTL);
+            write_i32(buf, *ttl);
+        }
+        DecryptedMessageAction::DeleteMessages(random_ids) => {
+            write_u32(buf, ACTION_DELETE_MESSAGES);
+            write_u32(buf, VECTOR);
+            write_i32(buf, random_ids.len() as i32);
+            for random_id in random_ids {
+                write_i64(buf, *random_id);
+            }
+        }
+        DecryptedMessageAction::NotifyLayer(layer) => {
+            write_u32(buf, ACTION_NOTIFY_LAYER);
+            write_i32(buf, *layer);
+        }
+        DecryptedMessageAction::Resend {
+            start_seq_no,
+            end_seq_no,
+        } => {
+            write_u32(buf, ACTION_RESEND);
+            write_i32(buf, *start_seq_no);
+            write_i32(buf, *end_seq_no);
+        }
+        DecryptedMessageAction::RequestKey { exchange_id, g_a } => {
+            write_u32(buf, ACTION_REQUEST_KEY);
+            write_i64(buf, *exchange_id);
+            write_bytes(buf, g_a);
+        }
+        DecryptedMessageAction::AcceptKey {
+            exchange_id,
+            g_b,
+            key_fingerprint,
+        } => {
+            write_u32(buf, ACTION_ACCEPT_KEY);
+            write_i64(buf, *exchange_id);
+            write_bytes(buf, g_b);
+            write_i64(buf, *key_fingerprint);
+        }
+        DecryptedMessageAction::AbortKey { exchange_id } => {
+            write_u32(buf, ACTION_ABORT_KEY);
+            write_i64(buf, *exchange_id);
+        }
+        DecryptedMessageAction::CommitKey {
+            exchange_id,
+            key_fingerprint,
+        } => {
+            write_u32(buf, ACTION_COMMIT_KEY);
+            write_i64(buf, *exchange_id);
+            write_i64(buf, *key_fingerprint);
+        }
+        DecryptedMessageAction::Noop => write_u32(buf, ACTION_NOOP),
+    }
+}
+
+fn read_action(reader: &mut Reader<'_>) -> Result<DecryptedMessageAction> {
+    Ok(match reader.read_u32()? {
+        ACTION_SET_MESSAGE_TTL => DecryptedMessageAction::SetMessageTtl(reader.read_i32()?),
+        ACTION_DELETE_MESSAGES => {
+            reader.expect(VECTOR)?;
+            let count = reader.read_i32()?;
+            if count < 0 || count as usize > reader.remaining() / 8 {
+                return Err(Error::Parse(format!("wrong vector size {}", count)));
+            }
+            let random_ids = (0..count)
+                .map(|_| reader.read_i64())
+                .collect::<Result<_>>()?;
+            DecryptedMessageAction::DeleteMessages(random_ids)
+        }
+        ACTION_NOTIFY_LAYER => DecryptedMessageAction::NotifyLayer(reader.read_i32()?),
+        ACTION_RESEND => DecryptedMessageAction::Resend {
+            start_seq_no: reader.read_i32()?,
+            end_seq_no: reader.read_i32()?,
+        },
+        ACTION_REQUEST_KEY => DecryptedMessageAction::RequestKey {
+            exchange_id: reader.read_i64()?,
+            g_a: reader.read_bytes()?,
+        },
+        ACTION_ACCEPT_KEY => DecryptedMessageAction::AcceptKey {
+            exchange_id: reader.read_i64()?,
+            g_b: reader.read_bytes()?,
+            key_fingerprint: reader.read_i64()?,
+        },
+        ACTION_ABORT_KEY => DecryptedMessageAction::AbortKey {
+            exchange_id: reader.read_i64()?,
+        },
+        ACTION_COMMIT_KEY => DecryptedMessageAction::CommitKey {
+            exchange_id: reader.read_i64()?,
+            key_fingerprint: reader.read_i64()?,
+        },
+        ACTION_NOOP => DecryptedMessageAction::Noop,
+        constructor => {
+            return Err(Error::Parse(format!(
+                "unsupported action constructor {:#x}",
+                constructor
+            )))
+        }
+    })
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_i64(buf: &mut Vec<u8>, value: i64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes TL `bytes`: a length prefix, the data and padding to 4 bytes.
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    let start = buf.len();
+    if data.len() < 254 {
+        buf.push(data.len() as u8);
+    } else {
+        buf.push(254);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+    }
+    buf.extend_from_slice(data);
+    while (buf.len() - start) % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .and_then(|bytes| <[u8; N]>::try_from(bytes).ok())
+            .ok_or_else(|| Error::Parse("unexpected end of data".into()))?;
+        self.pos += N;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        self.read_array().map(i32::from_le_bytes)
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        self.read_array().map(i64::from_le_bytes)
+    }
+
+    fn expect(&mut self, constructor: u32) -> Result<()> {
+        match self.read_u32()? {
+            found if found == constructor => Ok(()),
+            found => Err(Error::Parse(format!(
+                "expected constructor {:#x}, found {:#x}",
+                constructor, found
+            ))),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let start = self.pos;
+        let [first] = self.read_array::<1>()?;
+        let length = if first < 254 {
+            first as usize
+        } else {
+            let [a, b, c] = self.read_array::<3>()?;
+            u32::from_le_bytes([a, b, c, 0]) as usize
+        };
+        let data = self
+            .data
+            .get(self.pos..self.pos + length)
+            .ok_or_else(|| Error::Parse("unexpected end of data".into()))?
+            .to_vec();
+        self.pos += length;
+        self.pos += (4 - (self.pos - start) % 4) % 4;
+        if self.pos > self.data.len() {
+            return Err(Error::Parse("unexpected end of data".into()));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(message: DecryptedPayload) -> DecryptedMessageLayer {
+        DecryptedMessageLayer {
+            random_bytes: vec![7; MIN_RANDOM_BYTES],
+            layer: 144,
+            in_seq_no: 4,
+            out_seq_no: 7,
+            message,
+        }
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut message = DecryptedMessage::text(42, "x".repeat(300));
+        message.silent = true;
+        message.reply_to_random_id = Some(41);
+        let layer = layer(DecryptedPayload::Message(message));
+        let data = layer.serialize();
+        assert_eq!(data.len() % 4, 0);
+        assert_eq!(DecryptedMessageLayer::parse(&data).unwrap(), layer);
+        assert_eq!(layer.random_id(), 42);
+    }
+
+    #[test]
+    fn test_action_roundtrip() {
+        let actions = [
+            DecryptedMessageAction::SetMessageTtl(60),
+            DecryptedMessageAction::DeleteMessages(vec![1, 2, 3]),
+            DecryptedMessageAction::NotifyLayer(144),
+            DecryptedMessageAction::Resend {
+                start_seq_no: 1,
+                end_seq_no: 5,
+            },
+            DecryptedMessageAction::RequestKey {
+                exchange_id: 9,
+                g_a: vec![1; 256],
+            },
+            DecryptedMessageAction::AcceptKey {
+                exchange_id: 9,
+                g_b: vec![2; 256],
+                key_fingerprint: -5,
+            },
+            DecryptedMessageAction::AbortKey { exchange_id: 9 },
+            DecryptedMessageAction::CommitKey {
+                exchange_id: 9,
+                key_fingerprint: -5,
+            },
+            DecryptedMessageAction::Noop,
+        ];
+        for action in actions {
+            let layer = layer(DecryptedPayload::Service {
+                random_id: 5,
+                action,
+            });
+            assert_eq!(
+                DecryptedMessageLayer::parse(&layer.serialize()).unwrap(),
+                layer
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let data = layer(DecryptedPayload::Message(DecryptedMessage::text(1, "hi"))).serialize();
+        assert!(DecryptedMessageLayer::parse(&data[..data.len() - 4]).is_err());
+        assert!(DecryptedMessageLayer::parse(&data[4..]).is_err());
+
+        let mut short_random = layer(DecryptedPayload::Message(DecryptedMessage::text(1, "hi")));
+        short_random.random_bytes.truncate(4);
+        assert_eq!(
+            DecryptedMessageLayer::parse(&short_random.serialize()),
+            Err(Error::Parse("too few random bytes".into()))
+        );
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Two secret chat actors talking through a fake server.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use bytes::Bytes;
+use rustgram_dh_config::DhConfig;
+use rustgram_secret_chat_actor::{
+    DecryptedMessage, EncryptedChat, EncryptedMessage, SecretChatActor, SecretChatEvent,
+    SecretChatQuery, SecretChatState, PFS_MESSAGE_COUNT,
+};
+use rustgram_secret_chat_db::{KeyValueSyncInterface, SecretChatDb};
+use rustgram_types::UserId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+const CHAT_ID: i32 = 777;
+const ALICE: UserId = UserId(1001);
+const BOB: UserId = UserId(1002);
+const CHAT_ACCESS_HASH: i64 = 0x5eed;
+
+#[derive(Clone, Default)]
+struct MemoryStorage(Arc<Mutex<HashMap<String, Bytes>>>);
+
+impl KeyValueSyncInterface for MemoryStorage {
+    fn set(&self, key: String, value: Bytes) -> Result<(), Box<dyn std::error::Error>> {
+        self.0.lock().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> Result<Option<Bytes>, Box<dyn std::error::Error>> {
+        Ok(self.0.lock().unwrap().get(&key).cloned())
+    }
+
+    fn erase(&self, key: String) -> Result<(), Box<dyn std::error::Error>> {
+        self.0.lock().unwrap().remove(&key);
+        Ok(())
+    }
+}
+
+type Actor = SecretChatActor<MemoryStorage>;
+
+/// Which client an update is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Alice,
+    Bob,
+}
+
+impl Side {
+    fn other(self) -> Self {
+        match self {
+            Self::Alice => Self::Bob,
+            Self::Bob => Self::Alice,
+        }
+    }
+}
+
+/// What the server delivers to a client.
+#[derive(Debug, Clone)]
+enum Delivery {
+    DhConfig,
+    Chat(EncryptedChat),
+    Message(EncryptedMessage),
+}
+
+/// Alice creates the chat, Bob is invited; the server relays between them.
+struct FakeServer {
+    alice: Actor,
+    bob: Actor,
+    alice_storage: MemoryStorage,
+    inbox: VecDeque<(Side, Delivery)>,
+    g_a: Vec<u8>,
+    /// Number of the next encrypted message to drop
+    drop_message: Option<usize>,
+    relayed_messages: usize,
+    alice_events: Vec<SecretChatEvent>,
+    bob_events: Vec<SecretChatEvent>,
+}
+
+impl FakeServer {
+    fn new() -> Self {
+        let alice_storage = MemoryStorage::default();
+        let alice_db = SecretChatDb::new(alice_storage.clone(), CHAT_ID);
+        let bob_db = SecretChatDb::new(MemoryStorage::default(), CHAT_ID);
+        Self {
+            alice: SecretChatActor::new(CHAT_ID, alice_db, true),
+            bob: SecretChatActor::new(CHAT_ID, bob_db, true),
+            alice_storage,
+            inbox: VecDeque::new(),
+            g_a: Vec::new(),
+            drop_message: None,
+            relayed_messages: 0,
+            alice_events: Vec::new(),
+            bob_events: Vec::new(),
+        }
+    }
+
+    fn actor(&mut self, side: Side) -> &mut Actor {
+        match side {
+            Side::Alice => &mut self.alice,
+            Side::Bob => &mut self.bob,
+        }
+    }
+
+    /// Handles queries and delivers updates until both clients are idle.
+    fn run(&mut self) {
+        loop {
+            for side in [Side::Alice, Side::Bob] {
+                for query in self.actor(side).take_queries() {
+                    self.handle_query(side, query);
+                }
+            }
+            let Some((side, delivery)) = self.inbox.pop_front() else {
+                break;
+            };
+            let actor = self.actor(side);
+            match delivery {
+                Delivery::DhConfig => actor
+                    .on_dh_config(&DhConfig::default_safe(), &[0x5a; 256])
+                    .unwrap(),
+                Delivery::Chat(chat) => actor.on_encrypted_chat(chat).unwrap(),
+                Delivery::Message(message) => actor.on_new_message(message).unwrap(),
+            }
+        }
+        let events = self.alice.take_events();
+        self.alice_events.extend(events);
+        let events = self.bob.take_events();
+        self.bob_events.extend(events);
+    }
+
+    fn handle_query(&mut self, side: Side, query: SecretChatQuery) {
+        match query {
+            SecretChatQuery::GetDhConfig { .. } => self.inbox.push_back((side, Delivery::DhConfig)),
+            SecretChatQuery::RequestEncryption {
+                user_id,
+                random_id,
+                g_a,
+                ..
+            } => {
+                assert_eq!((side, user_id, random_id), (Side::Alice, BOB, CHAT_ID));
+                self.g_a = g_a.clone();
+                self.inbox.push_back((
+                    Side::Alice,
+                    Delivery::Chat(EncryptedChat::Waiting {
+                        id: CHAT_ID,
+                        access_hash: CHAT_ACCESS_HASH,
+                        participant_id: BOB,
+                    }),
+                ));
+                self.inbox.push_back((
+                    Side::Bob,
+                    Delivery::Chat(EncryptedChat::Requested {
+                        id: CHAT_ID,
+                        access_hash: CHAT_ACCESS_HASH,
+                        admin_id: ALICE,
+                        g_a,
+                    }),
+                ));
+            }
+            SecretChatQuery::AcceptEncryption {
+                chat_id,
+                g_b,
+                key_fingerprint,
+                ..
+            } => {
+                assert_eq!((side, chat_id), (Side::Bob, CHAT_ID));
+                let chat = |g_a_or_b| {
+                    Delivery::Chat(EncryptedChat::Chat {
+                        id: CHAT_ID,
+                        access_hash: CHAT_ACCESS_HASH,
+                        g_a_or_b,
+                        key_fingerprint,
+                    })
+                };
+                self.inbox.push_back((Side::Bob, chat(self.g_a.clone())));
+                self.inbox.push_back((Side::Alice, chat(g_b)));
+            }
+            SecretChatQuery::SendEncrypted {
+                chat_id,
+                access_hash,
+                random_id,
+                data,
+                ..
+            } => {
+                assert_eq!((chat_id, access_hash), (CHAT_ID, CHAT_ACCESS_HASH));
+                let number = self.relayed_messages;
+                self.relayed_messages += 1;
+                if self.drop_message == Some(number) {
+                    return;
+                }
+                self.inbox.push_back((
+                    side.other(),
+                    Delivery::Message(EncryptedMessage {
+                        random_id,
+                        date: 0,
+                        data,
+                    }),
+                ));
+            }
+            SecretChatQuery::DiscardEncryption {
+                chat_id,
+                delete_history,
+            } => {
+                assert_eq!(chat_id, CHAT_ID);
+                self.inbox.push_back((
+                    side.other(),
+                    Delivery::Chat(EncryptedChat::Discarded {
+                        id: CHAT_ID,
+                        history_deleted: delete_history,
+                    }),
+                ));
+            }
+        }
+    }
+
+    fn create_chat() -> Self {
+        let mut server = Self::new();
+        server.alice.create_chat(BOB, 99).unwrap();
+        server.run();
+        server
+    }
+
+    fn new_messages(events: &[SecretChatEvent]) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                SecretChatEvent::NewMessage(message) => Some(message.message.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_key_exchange_and_layer_negotiation() {
+    let server = FakeServer::create_chat();
+
+    assert!(server.alice.is_ready());
+    assert!(server.bob.is_ready());
+    assert!(server.alice.is_creator());
+    assert!(!server.bob.is_creator());
+    assert!(server.alice.key_fingerprint().is_some());
+    assert_eq!(server.alice.key_fingerprint(), server.bob.key_fingerprint());
+
+    assert_eq!(
+        server.alice_events,
+        [
+            SecretChatState::SendRequest,
+            SecretChatState::WaitRequestResponse,
+            SecretChatState::Ready,
+        ]
+        .map(SecretChatEvent::StateChanged)
+    );
+    assert_eq!(
+        server.bob_events,
+        [
+            SecretChatState::SendAccept,
+            SecretChatState::WaitAcceptResponse,
+            SecretChatState::Ready,
+        ]
+        .map(SecretChatEvent::StateChanged)
+    );
+
+    // Both parties learned the layer of the other one
+    assert_eq!(server.alice.config_state().his_layer, 144);
+    assert_eq!(server.bob.config_state().his_layer, 144);
+    assert_eq!(server.alice.seq_no_state().my_out_seq_no, 1);
+    assert_eq!(server.alice.seq_no_state().my_in_seq_no, 1);
+}
+
+#[test]
+fn test_send_messages_both_ways() {
+    let mut server = FakeServer::create_chat();
+
+    server
+        .alice
+        .send_message(DecryptedMessage::text(1, "hello Bob"))
+        .unwrap();
+    server
+        .bob
+        .send_message(DecryptedMessage::text(2, "hello Alice"))
+        .unwrap();
+    server.run();
+    let mut reply = DecryptedMessage::text(3, "how are you?");
+    reply.reply_to_random_id = Some(2);
+    server.alice.send_message(reply.clone()).unwrap();
+    server.run();
+
+    assert_eq!(
+        FakeServer::new_messages(&server.bob_events),
+        ["hello Bob", "how are you?"]
+    );
+    assert_eq!(
+        FakeServer::new_messages(&server.alice_events),
+        ["hello Alice"]
+    );
+    assert!(server
+        .bob_events
+        .contains(&SecretChatEvent::NewMessage(reply)));
+}
+
+#[test]
+fn test_delete_messages_and_ttl() {
+    let mut server = FakeServer::create_chat();
+
+    server.alice.set_ttl(30).unwrap();
+    server.alice.delete_messages(&[10, 11]).unwrap();
+    server.run();
+    server
+        .alice
+        .send_message(DecryptedMessage::text(12, "self-destructing"))
+        .unwrap();
+    server.run();
+
+    assert_eq!(server.bob.config_state().ttl, 30);
+    assert!(server.bob_events.contains(&SecretChatEvent::TtlChanged(30)));
+    assert!(server
+        .bob_events
+        .contains(&SecretChatEvent::MessagesDeleted(vec![10, 11])));
+    let ttl = server.bob_events.iter().find_map(|event| match event {
+        SecretChatEvent::NewMessage(message) => Some(message.ttl),
+        _ => None,
+    });
+    assert_eq!(ttl, Some(30));
+}
+
+#[test]
+fn test_gap_is_filled_by_resend() {
+    let mut server = FakeServer::create_chat();
+
+    server.drop_message = Some(server.relayed_messages);
+    for (random_id, text) in [(1, "first"), (2, "second"), (3, "third")] {
+        server
+            .alice
+            .send_message(DecryptedMessage::text(random_id, text))
+            .unwrap();
+    }
+    server.run();
+
+    // "first" was lost, Bob asked for it again and got everything in order
+    assert_eq!(
+        FakeServer::new_messages(&server.bob_events),
+        ["first", "second", "third"]
+    );
+    assert_eq!(
+        server.bob.seq_no_state().my_in_seq_no,
+        server.alice.seq_no_state().my_out_seq_no
+    );
+}
+
+#[test]
+fn test_pfs_rekeying() {
+    let mut server = FakeServer::create_chat();
+    let initial_fingerprint = server.alice.key_fingerprint();
+
+    for random_id in 0..i64::from(PFS_MESSAGE_COUNT) {
+        server
+            .alice
+            .send_message(DecryptedMessage::text(
+                random_id,
+                format!("message {}", random_id),
+            ))
+            .unwrap();
+        server.run();
+    }
+
+    assert_ne!(server.alice.key_fingerprint(), initial_fingerprint);
+    assert_eq!(server.alice.key_fingerprint(), server.bob.key_fingerprint());
+
+    server
+        .bob
+        .send_message(DecryptedMessage::text(-1, "with the new key"))
+        .unwrap();
+    server.run();
+    assert_eq!(
+        FakeServer::new_messages(&server.alice_events),
+        ["with the new key"]
+    );
+    assert_eq!(
+        FakeServer::new_messages(&server.bob_events).len(),
+        PFS_MESSAGE_COUNT as usize
+    );
+}
+
+#[test]
+fn test_restart_restores_chat() {
+    let mut server = FakeServer::create_chat();
+    server
+        .alice
+        .send_message(DecryptedMessage::text(1, "before restart"))
+        .unwrap();
+    server.run();
+
+    let db = SecretChatDb::new(server.alice_storage.clone(), CHAT_ID);
+    server.alice = SecretChatActor::load(CHAT_ID, db).unwrap();
+    assert!(server.alice.is_ready());
+    assert_eq!(server.alice.key_fingerprint(), server.bob.key_fingerprint());
+
+    server
+        .alice
+        .send_message(DecryptedMessage::text(2, "after restart"))
+        .unwrap();
+    server
+        .bob
+        .send_message(DecryptedMessage::text(3, "welcome back"))
+        .unwrap();
+    server.run();
+
+    assert_eq!(
+        FakeServer::new_messages(&server.bob_events),
+        ["before restart", "after restart"]
+    );
+    assert_eq!(
+        FakeServer::new_messages(&server.alice_events),
+        ["welcome back"]
+    );
+}
+
+#[test]
+fn test_discard_chat() {
+    let mut server = FakeServer::create_chat();
+
+    server.bob.cancel_chat(true, false).unwrap();
+    server.run();
+
+    assert!(server.alice.is_closed());
+    assert!(server.bob.is_closed());
+    assert!(server.alice_events.contains(&SecretChatEvent::Discarded {
+        history_deleted: true
+    }));
+}
@@ -1,7 +1,17 @@
 //! Database connection management.
-
-use std::path::Path;
-use std::sync::Arc;
+//!
+//! Databases can be encrypted at rest with SQLCipher. The key is applied to
+//! every connection, and [`DbConnection::change_key`] re-encrypts an existing
+//! database in place. Encryption requires SQLite to be built with SQLCipher
+//! (feature "sqlcipher"); using a key without it is an error rather than
+//! silently storing plain text.
+//!
+//! SQLCipher stretches a passphrase with PBKDF2 every time a connection is
+//! opened. Since a connection is opened per operation, the derived key is
+//! computed once with the salt stored in the database file and cached.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 use crate::error::{StorageError, StorageResult};
 
@@ -10,39 +20,275 @@ use crate::error::{StorageError, StorageResult};
 pub struct DbConnection {
     /// Path to the database file.
     db_path: Arc<std::path::PathBuf>,
+    /// SQLCipher key, shared by all clones so that a key change applies to
+    /// every user of the database.
+    key: Arc<RwLock<Option<String>>>,
 }
 
 impl DbConnection {
     /// Creates a new database connection manager.
     pub fn new<P: AsRef<Path>>(db_path: P) -> StorageResult<Self> {
-        let db_path = db_path.as_ref();
+        Self::with_key(db_path.as_ref(), None)
+    }
 
+    /// Creates a connection manager for a database encrypted with `key`.
+    ///
+    /// The key is either a passphrase, stretched by SQLCipher with PBKDF2
+    /// and a per-database salt, or a raw 256-bit key written as
+    /// `x'<64 hex digits>'`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::CryptoError`] if the database exists and
+    /// can't be decrypted with the key, or if SQLCipher is not available.
+    pub fn new_encrypted<P: AsRef<Path>>(
+        db_path: P,
+        key: impl Into<String>,
+    ) -> StorageResult<Self> {
+        Self::with_key(db_path.as_ref(), Some(key.into()))
+    }
+
+    fn with_key(db_path: &Path, key: Option<String>) -> StorageResult<Self> {
         // Ensure parent directory exists
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
         // Open database to create file if it doesn't exist
-        let conn = rusqlite::Connection::open(db_path)?;
+        let conn = open_connection(db_path, key.as_deref())?;
         conn.execute_batch(
             "PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL; PRAGMA foreign_keys=ON;",
         )?;
         drop(conn);
+        let key = key.map(|key| derive_raw_key(db_path, key));
 
         Ok(Self {
             db_path: Arc::new(db_path.to_path_buf()),
+            key: Arc::new(RwLock::new(key)),
         })
     }
 
     /// Opens a new connection to the database.
     pub fn connect(&self) -> StorageResult<rusqlite::Connection> {
-        rusqlite::Connection::open(&*self.db_path).map_err(StorageError::from)
+        let key = self.key.read().unwrap_or_else(|e| e.into_inner());
+        open_connection(&self.db_path, key.as_deref())
     }
 
     /// Returns the path to the database file.
     pub fn path(&self) -> &Path {
         &self.db_path
     }
+
+    /// Returns whether the database is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.key.read().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    /// Re-encrypts the database with a new key, or decrypts it if `new_key`
+    /// is `None`.
+    ///
+    /// See [`change_database_key`]. No other connection to the database may
+    /// be open during the change.
+    pub fn change_key(&self, new_key: Option<&str>) -> StorageResult<()> {
+        let mut key = self.key.write().unwrap_or_else(|e| e.into_inner());
+        change_database_key(&self.db_path, key.as_deref(), new_key)?;
+        *key = new_key.map(|new_key| derive_raw_key(&self.db_path, new_key.to_string()));
+        Ok(())
+    }
+}
+
+/// Opens a database file, applying the SQLCipher key if any.
+///
+/// # Errors
+///
+/// Returns [`StorageError::CryptoError`] if the key is wrong or SQLCipher is
+/// not available.
+pub fn open_connection(path: &Path, key: Option<&str>) -> StorageResult<rusqlite::Connection> {
+    let conn = rusqlite::Connection::open(path)?;
+    if let Some(key) = key {
+        apply_key(&conn, key)?;
+    }
+    Ok(conn)
+}
+
+/// Applies a SQLCipher key to a freshly opened connection and checks it.
+fn apply_key(conn: &rusqlite::Connection, key: &str) -> StorageResult<()> {
+    conn.pragma_update(None, "key", key)?;
+
+    // Plain SQLite silently ignores the key pragma
+    let cipher_version: Option<String> = conn
+        .query_row("PRAGMA cipher_version", [], |row| row.get(0))
+        .ok();
+    if cipher_version.is_none() {
+        return Err(StorageError::CryptoError(
+            "SQLite is built without SQLCipher support".to_string(),
+        ));
+    }
+
+    // The key is only checked when the first page is read
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(error, _)
+            if error.code == rusqlite::ErrorCode::NotADatabase =>
+        {
+            StorageError::CryptoError("wrong database encryption key".to_string())
+        }
+        e => StorageError::from(e),
+    })?;
+    Ok(())
+}
+
+/// Number of PBKDF2-HMAC-SHA512 iterations used by SQLCipher 4.
+#[cfg(feature = "sqlcipher")]
+const SQLCIPHER_KDF_ITERATIONS: u32 = 256_000;
+
+/// Size of the salt at the start of a SQLCipher database file.
+#[cfg(feature = "sqlcipher")]
+const SQLCIPHER_SALT_SIZE: usize = 16;
+
+/// Converts a passphrase into the raw key and salt it derives for the given
+/// database file, so that SQLCipher doesn't repeat the derivation.
+///
+/// Returns the key unchanged if it is already raw, or if the derived key
+/// doesn't open the database.
+#[cfg(feature = "sqlcipher")]
+fn derive_raw_key(path: &Path, key: String) -> String {
+    use std::io::Read;
+
+    if key.starts_with("x'") {
+        return key;
+    }
+    let mut salt = [0u8; SQLCIPHER_SALT_SIZE];
+    let salt_read = std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut salt));
+    if salt_read.is_err() {
+        return key;
+    }
+
+    let mut raw_key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha512>(
+        key.as_bytes(),
+        &salt,
+        SQLCIPHER_KDF_ITERATIONS,
+        &mut raw_key,
+    );
+    let hex: String = raw_key
+        .iter()
+        .chain(salt.iter())
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let raw_key = format!("x'{hex}'");
+    if open_connection(path, Some(&raw_key)).is_ok() {
+        raw_key
+    } else {
+        key
+    }
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+fn derive_raw_key(_path: &Path, key: String) -> String {
+    key
+}
+
+/// Returns the path of a file next to the database.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Removes a file if it exists.
+fn remove_if_exists(path: &Path) -> StorageResult<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Changes the encryption key of a database file in place.
+///
+/// `None` means a plain text database. The content is exported with
+/// `sqlcipher_export` into a new file encrypted with the new key, which is
+/// checked and then swapped with the original. If any step fails the
+/// original file is left (or put back) untouched, still readable with the
+/// old key.
+///
+/// # Errors
+///
+/// Returns [`StorageError::CryptoError`] if the old key is wrong, or another
+/// error if the export or the file swap fails.
+pub fn change_database_key(
+    path: &Path,
+    old_key: Option<&str>,
+    new_key: Option<&str>,
+) -> StorageResult<()> {
+    let new_path = sibling_path(path, ".rekey");
+    let backup_path = sibling_path(path, ".old");
+    remove_if_exists(&new_path)?;
+
+    let result = export_database(path, &new_path, old_key, new_key)
+        .and_then(|()| swap_database_files(path, &new_path, &backup_path));
+    if result.is_err() {
+        let _ = remove_if_exists(&new_path);
+    }
+    result?;
+
+    // Restore WAL mode, which is not exported
+    let conn = open_connection(path, new_key)?;
+    conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+    drop(conn);
+
+    remove_if_exists(&backup_path)
+}
+
+/// Exports a database into a new file encrypted with `new_key`.
+fn export_database(
+    path: &Path,
+    new_path: &Path,
+    old_key: Option<&str>,
+    new_key: Option<&str>,
+) -> StorageResult<()> {
+    let conn = open_connection(path, old_key)?;
+    // The database must not depend on its WAL file once swapped
+    conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+    let user_version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    let new_path_str = new_path.to_str().ok_or_else(|| {
+        StorageError::InvalidParameter(format!("non UTF-8 path {}", new_path.display()))
+    })?;
+    conn.execute(
+        "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+        rusqlite::params![new_path_str, new_key.unwrap_or("")],
+    )?;
+    conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+    conn.execute_batch(&format!(
+        "PRAGMA rekeyed.user_version = {user_version}; DETACH DATABASE rekeyed;"
+    ))?;
+    drop(conn);
+
+    // Check that the new file opens with the new key before using it
+    let conn = open_connection(new_path, new_key)?;
+    let integrity: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if integrity != "ok" {
+        return Err(StorageError::DatabaseCorrupted);
+    }
+    Ok(())
+}
+
+/// Replaces the database file with the exported one, keeping a backup until
+/// the swap is complete.
+fn swap_database_files(path: &Path, new_path: &Path, backup_path: &Path) -> StorageResult<()> {
+    remove_if_exists(backup_path)?;
+    std::fs::rename(path, backup_path)?;
+    if let Err(e) = std::fs::rename(new_path, path) {
+        // Roll back to the original file
+        std::fs::rename(backup_path, path)?;
+        return Err(e.into());
+    }
+    // The WAL was checkpointed, leftovers belong to the old file
+    remove_if_exists(&sibling_path(path, "-wal"))?;
+    remove_if_exists(&sibling_path(path, "-shm"))
 }
 
 /// A database transaction.
@@ -193,4 +439,91 @@ mod tests {
         });
         assert!(matches!(result, Err(rusqlite::Error::QueryReturnedNoRows)));
     }
+
+    #[cfg(feature = "sqlcipher")]
+    #[allow(clippy::unwrap_used)]
+    mod encryption {
+        use super::*;
+
+        const KEY: &str = "x'000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f'";
+
+        fn create_table(db: &DbConnection) {
+            let conn = db.connect().unwrap();
+            conn.execute_batch(
+                "CREATE TABLE test (id INTEGER, value TEXT);
+                 INSERT INTO test VALUES (1, 'secret value');
+                 PRAGMA user_version = 7;",
+            )
+            .unwrap();
+        }
+
+        fn read_value(db: &DbConnection) -> String {
+            db.connect()
+                .unwrap()
+                .query_row("SELECT value FROM test WHERE id = 1", [], |row| row.get(0))
+                .unwrap()
+        }
+
+        #[test]
+        fn test_wrong_key() {
+            let dir = tempdir().unwrap();
+            let db_path = dir.path().join("test.db");
+
+            create_table(&DbConnection::new_encrypted(&db_path, "passphrase").unwrap());
+
+            assert!(matches!(
+                DbConnection::new_encrypted(&db_path, "other"),
+                Err(StorageError::CryptoError(_))
+            ));
+            assert!(DbConnection::new(&db_path).is_err());
+            let db = DbConnection::new_encrypted(&db_path, "passphrase").unwrap();
+            assert!(db.is_encrypted());
+            assert_eq!(read_value(&db), "secret value");
+        }
+
+        #[test]
+        fn test_change_key() {
+            let dir = tempdir().unwrap();
+            let db_path = dir.path().join("test.db");
+
+            let db = DbConnection::new(&db_path).unwrap();
+            create_table(&db);
+            let clone = db.clone();
+
+            db.change_key(Some(KEY)).unwrap();
+            assert!(clone.is_encrypted());
+            assert_eq!(read_value(&clone), "secret value");
+            assert!(DbConnection::new(&db_path).is_err());
+
+            db.change_key(Some("passphrase")).unwrap();
+            let reopened = DbConnection::new_encrypted(&db_path, "passphrase").unwrap();
+            let version: i32 = reopened
+                .connect()
+                .unwrap()
+                .query_row("PRAGMA user_version", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(version, 7);
+
+            db.change_key(None).unwrap();
+            assert_eq!(
+                read_value(&DbConnection::new(&db_path).unwrap()),
+                "secret value"
+            );
+        }
+
+        #[test]
+        fn test_change_key_failure_keeps_database() {
+            let dir = tempdir().unwrap();
+            let db_path = dir.path().join("test.db");
+
+            let db = DbConnection::new(&db_path).unwrap();
+            create_table(&db);
+
+            // The exported file can't be created
+            std::fs::create_dir(dir.path().join("test.db.rekey")).unwrap();
+            assert!(db.change_key(Some(KEY)).is_err());
+            assert!(!db.is_encrypted());
+            assert_eq!(read_value(&db), "secret value");
+        }
+    }
 }
@@ -5,7 +5,7 @@
 //! ## Features
 //!
 //! - **dialog**: Dialog database with SQLite backend (default)
-//! - **message**: Message database with SQLite backend
+//! - **message**: Message database with SQLite backend and FTS5 search
 //! - **user**: User database with SQLite backend
 //! - **chat**: Chat database with SQLite backend
 //! - **file**: File database with SQLite backend
@@ -59,7 +59,7 @@ pub use migrations::{Migration, MigrationManager};
 pub use dialog::{DialogDb, DialogDbAsync, DialogDbSync};
 
 #[cfg(feature = "message")]
-pub use message::{MessageDb, MESSAGE_DB_VERSION};
+pub use message::{MessageDb, MessageSearchQuery, MESSAGE_DB_VERSION};
 
 #[cfg(feature = "user")]
 pub use user::{UserDb, USER_DB_VERSION};
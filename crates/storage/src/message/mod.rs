@@ -1,9 +1,11 @@
 //! Message database module.
 
 pub mod schema;
+pub mod search;
 pub mod sync;
 
 pub use schema::{get_message_migrations, MESSAGE_DB_VERSION};
+pub use search::{FoundMessage, FoundMessages, MessageSearchQuery, MAX_SEARCH_LIMIT};
 pub use sync::{MessageDbDialogMessage, MessageDbSync, MessageSearchFilter};
 
 use crate::connection::DbConnection;
@@ -33,6 +35,13 @@ impl MessageDb {
         }
         manager.run(&self.db)
     }
+
+    /// Searches messages in all dialogs or in one dialog.
+    ///
+    /// See [`MessageDbSync::search_messages`].
+    pub fn search_messages(&self, query: &MessageSearchQuery) -> StorageResult<FoundMessages> {
+        self.sync().search_messages(query)
+    }
 }
 
 #[cfg(test)]
@@ -4,7 +4,7 @@ use crate::error::StorageResult;
 use crate::migrations::Migration;
 
 /// Current message database schema version.
-pub const MESSAGE_DB_VERSION: i32 = 5;
+pub const MESSAGE_DB_VERSION: i32 = 6;
 
 /// Migration 1: Create initial messages table.
 pub struct MessageMigrationV1;
@@ -203,6 +203,78 @@ impl Migration for MessageMigrationV5 {
     }
 }
 
+/// Migration 6: Add search filter mask and FTS5 full-text index.
+///
+/// `messages_fts` is an external content FTS5 table over `messages.text`,
+/// keyed by the `messages` rowid and kept in sync by triggers. Messages must
+/// be updated in place (not with `INSERT OR REPLACE`) to keep their rowid.
+pub struct MessageMigrationV6;
+
+impl Migration for MessageMigrationV6 {
+    fn version(&self) -> i32 {
+        6
+    }
+
+    fn description(&self) -> &str {
+        "Add search filter mask and full-text index"
+    }
+
+    fn apply(&self, conn: &mut rusqlite::Connection) -> StorageResult<()> {
+        let has_column = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'index_mask'",
+                [],
+                |row| row.get::<_, i32>(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_column {
+            conn.execute(
+                "ALTER TABLE messages ADD COLUMN index_mask INTEGER NOT NULL DEFAULT 0",
+                [],
+            )
+            .map_err(|e| crate::error::StorageError::MigrationError(e.to_string()))?;
+        }
+
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_messages_index_mask
+                ON messages(dialog_id, index_mask) WHERE index_mask != 0;
+
+             CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+                text,
+                content = 'messages',
+                content_rowid = 'rowid',
+                tokenize = 'unicode61 remove_diacritics 2',
+                prefix = '2 3'
+             );
+
+             CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages
+             WHEN new.text IS NOT NULL BEGIN
+                INSERT INTO messages_fts (rowid, text) VALUES (new.rowid, new.text);
+             END;
+
+             CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages
+             WHEN old.text IS NOT NULL BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, text)
+                    VALUES ('delete', old.rowid, old.text);
+             END;
+
+             CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, text)
+                    SELECT 'delete', old.rowid, old.text WHERE old.text IS NOT NULL;
+                INSERT INTO messages_fts (rowid, text)
+                    SELECT new.rowid, new.text WHERE new.text IS NOT NULL;
+             END;
+
+             INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');",
+        )
+        .map_err(|e| crate::error::StorageError::MigrationError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
 /// Returns all message database migrations.
 pub fn get_message_migrations() -> Vec<Box<dyn Migration>> {
     vec![
@@ -211,6 +283,7 @@ pub fn get_message_migrations() -> Vec<Box<dyn Migration>> {
         Box::new(MessageMigrationV3),
         Box::new(MessageMigrationV4),
         Box::new(MessageMigrationV5),
+        Box::new(MessageMigrationV6),
     ]
 }
 
@@ -274,8 +347,38 @@ mod tests {
         assert!(idx_count >= 3); // At least the date, sender, and TTL indexes
     }
 
+    #[test]
+    fn test_migration_v6_indexes_existing_messages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+
+        let db = DbConnection::new(&db_path).unwrap();
+        let mut conn = db.connect().unwrap();
+
+        for migration in get_message_migrations().iter().take(5) {
+            migration.apply(&mut conn).unwrap();
+        }
+        conn.execute(
+            "INSERT INTO messages (dialog_id, message_id, sender_id, date, content, text)
+             VALUES (1, 1, 2, 3, x'', 'indexed before the migration')",
+            [],
+        )
+        .unwrap();
+
+        MessageMigrationV6.apply(&mut conn).unwrap();
+
+        let found: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH 'migration'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, 1);
+    }
+
     #[test]
     fn test_message_db_version() {
-        assert_eq!(MESSAGE_DB_VERSION, 5);
+        assert_eq!(MESSAGE_DB_VERSION, 6);
     }
 }
@@ -0,0 +1,309 @@
+//! Full-text message search.
+//!
+//! Local counterpart of TDLib `searchMessages`: queries the `messages_fts`
+//! index created by [`MessageMigrationV6`](super::schema::MessageMigrationV6),
+//! optionally restricted to one dialog and to a search filter, and returns
+//! results newest first with a snippet and a highlighted copy of the text.
+
+use bytes::Bytes;
+use rustgram_message_search_filter::MessageSearchFilter;
+use rustgram_message_search_offset::MessageSearchOffset;
+use rustgram_types::{DialogId, MessageId};
+
+use super::sync::{MessageDbDialogMessage, MessageDbSync};
+use crate::error::{StorageError, StorageResult};
+
+/// Maximum number of messages returned by one search.
+pub const MAX_SEARCH_LIMIT: i32 = 100;
+
+/// Parameters of a full-text message search.
+#[derive(Debug, Clone)]
+pub struct MessageSearchQuery {
+    /// Text to search for; every word must match as a prefix
+    pub query: String,
+    /// Dialog to search in (encoded DialogId), or all dialogs if `None`
+    pub dialog_id: Option<i64>,
+    /// Only return messages matching this filter
+    pub filter: MessageSearchFilter,
+    /// Only return messages from this sender (encoded DialogId)
+    pub sender_id: Option<i64>,
+    /// Minimum message date (optional)
+    pub min_date: Option<i32>,
+    /// Maximum message date (optional)
+    pub max_date: Option<i32>,
+    /// Offset returned with the previous page; empty for the first page
+    pub offset: MessageSearchOffset,
+    /// Maximum number of messages to return, up to [`MAX_SEARCH_LIMIT`]
+    pub limit: i32,
+    /// Marker inserted before each match in snippets and highlights
+    pub highlight_start: String,
+    /// Marker inserted after each match in snippets and highlights
+    pub highlight_end: String,
+    /// Approximate number of words in a snippet
+    pub snippet_words: i32,
+}
+
+impl MessageSearchQuery {
+    /// Creates a query over all dialogs.
+    #[must_use]
+    pub fn new(query: impl Into<String>, limit: i32) -> Self {
+        Self {
+            query: query.into(),
+            dialog_id: None,
+            filter: MessageSearchFilter::Empty,
+            sender_id: None,
+            min_date: None,
+            max_date: None,
+            offset: MessageSearchOffset::default(),
+            limit,
+            highlight_start: "<b>".to_string(),
+            highlight_end: "</b>".to_string(),
+            snippet_words: 10,
+        }
+    }
+
+    /// Restricts the search to one dialog.
+    #[must_use]
+    pub const fn in_dialog(mut self, dialog_id: i64) -> Self {
+        self.dialog_id = Some(dialog_id);
+        self
+    }
+
+    /// Sets search filter.
+    #[must_use]
+    pub const fn with_filter(mut self, filter: MessageSearchFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Restricts the search to one sender.
+    #[must_use]
+    pub const fn with_sender(mut self, sender_id: i64) -> Self {
+        self.sender_id = Some(sender_id);
+        self
+    }
+
+    /// Sets the date range.
+    #[must_use]
+    pub const fn with_dates(mut self, min_date: Option<i32>, max_date: Option<i32>) -> Self {
+        self.min_date = min_date;
+        self.max_date = max_date;
+        self
+    }
+
+    /// Sets the offset of the page to return.
+    #[must_use]
+    pub fn with_offset(mut self, offset: MessageSearchOffset) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Sets the markers surrounding matches.
+    #[must_use]
+    pub fn with_highlight(mut self, start: impl Into<String>, end: impl Into<String>) -> Self {
+        self.highlight_start = start.into();
+        self.highlight_end = end.into();
+        self
+    }
+}
+
+/// A message found by a search.
+#[derive(Debug, Clone)]
+pub struct FoundMessage {
+    /// The message
+    pub message: MessageDbDialogMessage,
+    /// Fragment of the text around the matches, if the query had words
+    pub snippet: Option<String>,
+    /// Full text with every match highlighted, if the query had words
+    pub highlighted_text: Option<String>,
+}
+
+/// A page of search results.
+#[derive(Debug, Clone, Default)]
+pub struct FoundMessages {
+    /// Found messages, newest first
+    pub messages: Vec<FoundMessage>,
+    /// Offset of the next page, or `None` if there are no more results
+    pub next_offset: Option<MessageSearchOffset>,
+}
+
+/// Converts free text to an FTS5 query matching every word as a prefix.
+///
+/// Returns `None` if the text has no words.
+#[must_use]
+pub fn prepare_fts_query(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+impl MessageDbSync {
+    /// Searches messages by text, filter and sender.
+    ///
+    /// Results are ordered by date, dialog and message identifier, newest
+    /// first; pass [`FoundMessages::next_offset`] in the next query to get
+    /// the following page. A query without words returns every message
+    /// matching the other conditions.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::InvalidParameter`] if the limit is not
+    /// positive, or a query error if the database can't be read.
+    pub fn search_messages(&mut self, query: &MessageSearchQuery) -> StorageResult<FoundMessages> {
+        if query.limit <= 0 {
+            return Err(StorageError::InvalidParameter(format!(
+                "invalid search limit {}",
+                query.limit
+            )));
+        }
+        let limit = query.limit.min(MAX_SEARCH_LIMIT);
+        let fts_query = prepare_fts_query(&query.query);
+
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let mut sql = String::from(
+            "SELECT m.dialog_id, m.message_id, m.sender_id, m.date, m.ttl_expires_at,
+                    m.content, m.text, m.random_id, m.unique_message_id, m.search_id,
+                    m.top_thread_message_id",
+        );
+        match fts_query {
+            Some(fts_query) => {
+                sql.push_str(
+                    ", snippet(messages_fts, 0, ?, ?, '…', ?), highlight(messages_fts, 0, ?, ?)
+                     FROM messages_fts JOIN messages m ON m.rowid = messages_fts.rowid
+                     WHERE messages_fts MATCH ?",
+                );
+                params.push(Box::new(query.highlight_start.clone()));
+                params.push(Box::new(query.highlight_end.clone()));
+                params.push(Box::new(query.snippet_words.clamp(1, 64)));
+                params.push(Box::new(query.highlight_start.clone()));
+                params.push(Box::new(query.highlight_end.clone()));
+                params.push(Box::new(fts_query));
+            }
+            None => sql.push_str(", NULL, NULL FROM messages m WHERE 1"),
+        }
+
+        if let Some(dialog_id) = query.dialog_id {
+            sql.push_str(" AND m.dialog_id = ?");
+            params.push(Box::new(dialog_id));
+        }
+        if !query.filter.is_empty() {
+            sql.push_str(" AND (m.index_mask & ?) != 0");
+            params.push(Box::new(query.filter.bitmask()));
+        }
+        if let Some(sender_id) = query.sender_id {
+            sql.push_str(" AND m.sender_id = ?");
+            params.push(Box::new(sender_id));
+        }
+        if let Some(min_date) = query.min_date {
+            sql.push_str(" AND m.date >= ?");
+            params.push(Box::new(min_date));
+        }
+        if let Some(max_date) = query.max_date {
+            sql.push_str(" AND m.date <= ?");
+            params.push(Box::new(max_date));
+        }
+        if !query.offset.is_empty() {
+            sql.push_str(" AND (m.date, m.dialog_id, m.message_id) < (?, ?, ?)");
+            params.push(Box::new(query.offset.date()));
+            params.push(Box::new(query.offset.dialog_id().to_encoded()));
+            params.push(Box::new(query.offset.message_id().get_server_id()));
+        }
+        sql.push_str(" ORDER BY m.date DESC, m.dialog_id DESC, m.message_id DESC LIMIT ?");
+        params.push(Box::new(limit));
+
+        let conn = self.db().connect()?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn
+            .prepare(&sql)
+            .map_err(|e| StorageError::QueryError(e.to_string()))?;
+        let mut rows = stmt
+            .query(&params_refs[..])
+            .map_err(|e| StorageError::QueryError(e.to_string()))?;
+
+        let mut messages = Vec::new();
+        while let Some(row) = rows.next()? {
+            messages.push(FoundMessage {
+                message: MessageDbDialogMessage {
+                    dialog_id: row.get(0)?,
+                    message_id: row.get(1)?,
+                    sender_id: row.get(2)?,
+                    date: row.get(3)?,
+                    ttl_expires_at: row.get(4)?,
+                    content: Bytes::from(row.get::<_, Vec<u8>>(5)?),
+                    text: row.get(6)?,
+                    random_id: row.get(7)?,
+                    unique_message_id: row.get(8)?,
+                    search_id: row.get(9)?,
+                    top_thread_message_id: row.get(10)?,
+                },
+                snippet: row.get(11)?,
+                highlighted_text: row.get(12)?,
+            });
+        }
+
+        let next_offset = match messages.last() {
+            Some(last) if messages.len() == limit as usize => Some(search_offset(&last.message)?),
+            _ => None,
+        };
+        Ok(FoundMessages {
+            messages,
+            next_offset,
+        })
+    }
+
+    /// Rebuilds the full-text index from the stored message texts.
+    ///
+    /// The index refers to messages by rowid, so it must be rebuilt after
+    /// anything that renumbers rows, such as `VACUUM`.
+    pub fn rebuild_search_index(&mut self) -> StorageResult<()> {
+        let conn = self.db().connect()?;
+        conn.execute(
+            "INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')",
+            [],
+        )
+        .map_err(|e| StorageError::QueryError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Returns the offset of the page following a message.
+fn search_offset(message: &MessageDbDialogMessage) -> StorageResult<MessageSearchOffset> {
+    let dialog_id = DialogId::from_encoded(message.dialog_id)
+        .map_err(|e| StorageError::InvalidParameter(e.to_string()))?;
+    Ok(MessageSearchOffset::new(
+        message.date,
+        MessageId::from_server_id(message.message_id),
+        dialog_id,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_fts_query() {
+        assert_eq!(prepare_fts_query("hello"), Some("\"hello\"*".to_string()));
+        assert_eq!(
+            prepare_fts_query("  Hello, wor"),
+            Some("\"Hello\"* \"wor\"*".to_string())
+        );
+        // FTS5 syntax is not passed through
+        assert_eq!(
+            prepare_fts_query("a\" OR b NEAR(c)"),
+            Some("\"a\"* \"OR\"* \"b\"* \"NEAR\"* \"c\"*".to_string())
+        );
+        assert_eq!(
+            prepare_fts_query("привет мир"),
+            Some("\"привет\"* \"мир\"*".to_string())
+        );
+        assert_eq!(prepare_fts_query(" ,.!"), None);
+    }
+}
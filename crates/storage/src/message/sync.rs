@@ -31,6 +31,8 @@ pub struct AddMessageParams {
     pub search_id: Option<i32>,
     /// Top thread message ID (optional)
     pub top_thread_message_id: Option<i32>,
+    /// Bitmask of the search filters the message matches
+    pub index_mask: i32,
 }
 
 impl AddMessageParams {
@@ -49,6 +51,7 @@ impl AddMessageParams {
             unique_message_id: None,
             search_id: None,
             top_thread_message_id: None,
+            index_mask: 0,
         }
     }
 
@@ -79,6 +82,15 @@ impl AddMessageParams {
         self.unique_message_id = Some(unique_message_id);
         self
     }
+
+    /// Sets the search filters the message matches.
+    ///
+    /// The mask is a combination of `MessageSearchFilter::bitmask` values.
+    #[must_use]
+    pub const fn with_index_mask(mut self, index_mask: i32) -> Self {
+        self.index_mask = index_mask;
+        self
+    }
 }
 
 /// Message search filter for querying messages.
@@ -134,6 +146,9 @@ impl MessageDbSync {
 
     /// Adds or updates a message in the database.
     ///
+    /// An existing message is updated in place, so that the full-text index
+    /// follows its text.
+    ///
     /// # Arguments
     ///
     /// * `params` - Message parameters
@@ -141,10 +156,22 @@ impl MessageDbSync {
         let conn = self.db.connect()?;
 
         conn.execute(
-            "INSERT OR REPLACE INTO messages (
+            "INSERT INTO messages (
                 dialog_id, message_id, sender_id, date, ttl_expires_at,
-                content, text, random_id, unique_message_id, search_id, top_thread_message_id
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                content, text, random_id, unique_message_id, search_id, top_thread_message_id,
+                index_mask
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            ON CONFLICT (dialog_id, message_id) DO UPDATE SET
+                sender_id = excluded.sender_id,
+                date = excluded.date,
+                ttl_expires_at = excluded.ttl_expires_at,
+                content = excluded.content,
+                text = excluded.text,
+                random_id = excluded.random_id,
+                unique_message_id = excluded.unique_message_id,
+                search_id = excluded.search_id,
+                top_thread_message_id = excluded.top_thread_message_id,
+                index_mask = excluded.index_mask",
             params![
                 params.dialog_id,
                 params.message_id,
@@ -157,6 +184,7 @@ impl MessageDbSync {
                 params.unique_message_id,
                 params.search_id,
                 params.top_thread_message_id,
+                params.index_mask,
             ],
         )
         .map_err(|e| StorageError::QueryError(e.to_string()))?;
@@ -221,6 +249,23 @@ impl MessageDbSync {
         Ok(())
     }
 
+    /// Deletes all messages of a dialog from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `dialog_id` - Dialog identifier (encoded DialogId)
+    pub fn delete_all_dialog_messages(&mut self, dialog_id: i64) -> StorageResult<()> {
+        let conn = self.db.connect()?;
+
+        conn.execute(
+            "DELETE FROM messages WHERE dialog_id = ?1",
+            params![dialog_id],
+        )
+        .map_err(|e| StorageError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+
     /// Gets messages from a dialog with pagination.
     ///
     /// # Arguments
@@ -613,6 +658,29 @@ mod tests {
         assert_eq!(message_db.get_message_count(dialog_id).unwrap(), 5);
     }
 
+    #[test]
+    fn test_delete_all_dialog_messages() {
+        let db = setup_test_db();
+        let mut message_db = MessageDbSync::new(db);
+
+        for (dialog_id, message_id) in [(1, 1), (1, 2), (2, 1)] {
+            message_db
+                .add_message(AddMessageParams::new(
+                    dialog_id,
+                    message_id,
+                    67890,
+                    1704067200,
+                    Bytes::new(),
+                ))
+                .unwrap();
+        }
+
+        message_db.delete_all_dialog_messages(1).unwrap();
+
+        assert_eq!(message_db.get_message_count(1).unwrap(), 0);
+        assert_eq!(message_db.get_message_count(2).unwrap(), 1);
+    }
+
     #[test]
     fn test_scheduled_messages() {
         let db = setup_test_db();
@@ -0,0 +1,237 @@
+//! Full-text message search tests.
+//!
+//! Run with: `cargo test -p rustgram-storage --test search_test --features message`
+
+#![cfg(feature = "message")]
+
+use bytes::Bytes;
+use rustgram_message_search_filter::MessageSearchFilter;
+use rustgram_storage::connection::DbConnection;
+use rustgram_storage::message::sync::AddMessageParams;
+use rustgram_storage::message::{MessageDb, MessageSearchQuery};
+use tempfile::TempDir;
+
+const USER_DIALOG: i64 = 12345;
+const CHANNEL_DIALOG: i64 = -999_999_999_000;
+const SENDER: i64 = 67890;
+
+fn setup() -> (TempDir, MessageDb) {
+    let dir = tempfile::tempdir().unwrap();
+    let db = DbConnection::new(dir.path().join("messages.db")).unwrap();
+    let message_db = MessageDb::new(db);
+    message_db.init().unwrap();
+    (dir, message_db)
+}
+
+fn add(message_db: &MessageDb, dialog_id: i64, message_id: i32, date: i32, text: &str, mask: i32) {
+    message_db
+        .sync()
+        .add_message(
+            AddMessageParams::new(dialog_id, message_id, SENDER, date, Bytes::from("content"))
+                .with_text(text.to_string())
+                .with_index_mask(mask),
+        )
+        .unwrap();
+}
+
+fn found_ids(message_db: &MessageDb, query: &MessageSearchQuery) -> Vec<(i64, i32)> {
+    message_db
+        .search_messages(query)
+        .unwrap()
+        .messages
+        .iter()
+        .map(|found| (found.message.dialog_id, found.message.message_id))
+        .collect()
+}
+
+#[test]
+fn test_search_all_dialogs_and_one_dialog() {
+    let (_dir, message_db) = setup();
+    add(&message_db, USER_DIALOG, 1, 100, "Meeting at the office", 0);
+    add(
+        &message_db,
+        CHANNEL_DIALOG,
+        1,
+        200,
+        "The meeting is cancelled",
+        0,
+    );
+    add(&message_db, USER_DIALOG, 2, 300, "Lunch tomorrow?", 0);
+
+    let query = MessageSearchQuery::new("meeting", 10);
+    assert_eq!(
+        found_ids(&message_db, &query),
+        [(CHANNEL_DIALOG, 1), (USER_DIALOG, 1)]
+    );
+
+    let query = MessageSearchQuery::new("meeting", 10).in_dialog(USER_DIALOG);
+    assert_eq!(found_ids(&message_db, &query), [(USER_DIALOG, 1)]);
+}
+
+#[test]
+fn test_search_prefix_case_and_diacritics() {
+    let (_dir, message_db) = setup();
+    add(&message_db, USER_DIALOG, 1, 100, "Café Crème", 0);
+    add(&message_db, USER_DIALOG, 2, 200, "Привет, МИР", 0);
+
+    let query = MessageSearchQuery::new("cafe cr", 10);
+    assert_eq!(found_ids(&message_db, &query), [(USER_DIALOG, 1)]);
+
+    let query = MessageSearchQuery::new("мир", 10);
+    assert_eq!(found_ids(&message_db, &query), [(USER_DIALOG, 2)]);
+
+    // every word must match
+    let query = MessageSearchQuery::new("cafe мир", 10);
+    assert!(found_ids(&message_db, &query).is_empty());
+}
+
+#[test]
+fn test_search_snippet_and_highlight() {
+    let (_dir, message_db) = setup();
+    add(
+        &message_db,
+        USER_DIALOG,
+        1,
+        100,
+        "The quick brown fox jumps over the lazy dog",
+        0,
+    );
+
+    let query = MessageSearchQuery::new("fox", 10).with_highlight("[", "]");
+    let found = message_db.search_messages(&query).unwrap();
+    let message = &found.messages[0];
+    assert_eq!(
+        message.highlighted_text.as_deref(),
+        Some("The quick brown [fox] jumps over the lazy dog")
+    );
+    assert!(message.snippet.as_deref().unwrap().contains("[fox]"));
+}
+
+#[test]
+fn test_search_filter_mask() {
+    let (_dir, message_db) = setup();
+    let photo = MessageSearchFilter::Photo.bitmask();
+    let url = MessageSearchFilter::Url.bitmask();
+    add(&message_db, USER_DIALOG, 1, 100, "holiday photo", photo);
+    add(
+        &message_db,
+        USER_DIALOG,
+        2,
+        200,
+        "holiday link https://t.me",
+        url,
+    );
+    add(
+        &message_db,
+        USER_DIALOG,
+        3,
+        300,
+        "holiday photo with link",
+        photo | url,
+    );
+    add(&message_db, USER_DIALOG, 4, 400, "holiday plans", 0);
+
+    let query = MessageSearchQuery::new("holiday", 10).with_filter(MessageSearchFilter::Photo);
+    assert_eq!(
+        found_ids(&message_db, &query),
+        [(USER_DIALOG, 3), (USER_DIALOG, 1)]
+    );
+
+    // a filter alone lists the matching messages
+    let query = MessageSearchQuery::new("", 10).with_filter(MessageSearchFilter::Url);
+    let found = message_db.search_messages(&query).unwrap();
+    assert_eq!(found.messages.len(), 2);
+    assert!(found.messages[0].snippet.is_none());
+}
+
+#[test]
+fn test_search_pagination() {
+    let (_dir, message_db) = setup();
+    for i in 1..=7 {
+        add(
+            &message_db,
+            USER_DIALOG,
+            i,
+            1000 + i,
+            &format!("report number {i}"),
+            0,
+        );
+        add(
+            &message_db,
+            CHANNEL_DIALOG,
+            i,
+            1000 + i,
+            &format!("weekly report {i}"),
+            0,
+        );
+    }
+
+    let mut query = MessageSearchQuery::new("report", 4);
+    let mut pages = Vec::new();
+    loop {
+        let found = message_db.search_messages(&query).unwrap();
+        pages.push(
+            found
+                .messages
+                .iter()
+                .map(|m| (m.message.dialog_id, m.message.message_id))
+                .collect::<Vec<_>>(),
+        );
+        match found.next_offset {
+            Some(offset) => query = query.with_offset(offset),
+            None => break,
+        }
+    }
+
+    let all: Vec<(i64, i32)> = pages.concat();
+    assert_eq!(all.len(), 14);
+    // newest first; on equal dates the larger encoded dialog comes first
+    assert_eq!(all[0], (USER_DIALOG, 7));
+    assert_eq!(all[1], (CHANNEL_DIALOG, 7));
+    assert_eq!(all[13], (CHANNEL_DIALOG, 1));
+    assert_eq!(pages.len(), 4);
+    assert_eq!(pages[3].len(), 2);
+}
+
+#[test]
+fn test_search_index_follows_updates_and_deletes() {
+    let (_dir, message_db) = setup();
+    add(&message_db, USER_DIALOG, 1, 100, "first draft", 0);
+
+    add(&message_db, USER_DIALOG, 1, 100, "final version", 0);
+    assert!(found_ids(&message_db, &MessageSearchQuery::new("draft", 10)).is_empty());
+    assert_eq!(
+        found_ids(&message_db, &MessageSearchQuery::new("final", 10)),
+        [(USER_DIALOG, 1)]
+    );
+
+    message_db.sync().delete_message(USER_DIALOG, 1).unwrap();
+    assert!(found_ids(&message_db, &MessageSearchQuery::new("final", 10)).is_empty());
+
+    message_db.sync().rebuild_search_index().unwrap();
+    assert!(found_ids(&message_db, &MessageSearchQuery::new("final", 10)).is_empty());
+}
+
+#[test]
+fn test_search_sender_and_dates() {
+    let (_dir, message_db) = setup();
+    add(&message_db, USER_DIALOG, 1, 100, "status update", 0);
+    add(&message_db, USER_DIALOG, 2, 200, "status update", 0);
+    add(&message_db, USER_DIALOG, 3, 300, "status update", 0);
+
+    let query = MessageSearchQuery::new("status", 10).with_dates(Some(150), Some(300));
+    assert_eq!(
+        found_ids(&message_db, &query),
+        [(USER_DIALOG, 3), (USER_DIALOG, 2)]
+    );
+
+    let query = MessageSearchQuery::new("status", 10).with_sender(SENDER + 1);
+    assert!(found_ids(&message_db, &query).is_empty());
+}
+
+#[test]
+fn test_search_invalid_limit() {
+    let (_dir, message_db) = setup();
+    let result = message_db.search_messages(&MessageSearchQuery::new("text", 0));
+    assert!(result.is_err());
+}
@@ -28,7 +28,7 @@
 //! ```
 
 use std::collections::HashSet;
-use std::sync::LazyLock;
+use std::sync::OnceLock;
 
 /// Synchronous TDLib requests that can be executed immediately.
 ///
@@ -47,40 +47,43 @@ use std::sync::LazyLock;
 #[derive(Debug, Clone, Default)]
 pub struct SynchronousRequests;
 
-/// Set of synchronous request names.
-static SYNCHRONOUS_REQUESTS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
-    [
-        "searchQuote",
-        "getTextEntities",
-        "parseTextEntities",
-        "parseMarkdown",
-        "getMarkdownText",
-        "searchStringsByPrefix",
-        "checkQuickReplyShortcutName",
-        "getCountryFlagEmoji",
-        "getFileMimeType",
-        "getFileExtension",
-        "cleanFileName",
-        "getLanguagePackString",
-        "getPhoneNumberInfoSync",
-        "getChatFolderDefaultIconName",
-        "getJsonValue",
-        "getJsonString",
-        "getThemeParametersJsonString",
-        "getPushReceiverId",
-        "setLogStream",
-        "getLogStream",
-        "setLogVerbosityLevel",
-        "getLogVerbosityLevel",
-        "getLogTags",
-        "setLogTagVerbosityLevel",
-        "getLogTagVerbosityLevel",
-        "addLogMessage",
-        "testReturnError",
-    ]
-    .into_iter()
-    .collect()
-});
+/// Returns the set of synchronous request names.
+fn synchronous_requests() -> &'static HashSet<&'static str> {
+    static SYNCHRONOUS_REQUESTS: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    SYNCHRONOUS_REQUESTS.get_or_init(|| {
+        [
+            "searchQuote",
+            "getTextEntities",
+            "parseTextEntities",
+            "parseMarkdown",
+            "getMarkdownText",
+            "searchStringsByPrefix",
+            "checkQuickReplyShortcutName",
+            "getCountryFlagEmoji",
+            "getFileMimeType",
+            "getFileExtension",
+            "cleanFileName",
+            "getLanguagePackString",
+            "getPhoneNumberInfoSync",
+            "getChatFolderDefaultIconName",
+            "getJsonValue",
+            "getJsonString",
+            "getThemeParametersJsonString",
+            "getPushReceiverId",
+            "setLogStream",
+            "getLogStream",
+            "setLogVerbosityLevel",
+            "getLogVerbosityLevel",
+            "getLogTags",
+            "setLogTagVerbosityLevel",
+            "getLogTagVerbosityLevel",
+            "addLogMessage",
+            "testReturnError",
+        ]
+        .into_iter()
+        .collect()
+    })
+}
 
 impl SynchronousRequests {
     /// Checks if a request type is synchronous.
@@ -104,7 +107,7 @@ impl SynchronousRequests {
             return true; // Simplified: in TDLib this depends on the option name
         }
 
-        SYNCHRONOUS_REQUESTS.contains(request_name)
+        synchronous_requests().contains(request_name)
     }
 
     /// Returns all synchronous request names.
@@ -119,7 +122,7 @@ impl SynchronousRequests {
     /// ```
     #[must_use]
     pub fn all_synchronous_requests() -> Vec<&'static str> {
-        SYNCHRONOUS_REQUESTS.iter().copied().collect()
+        synchronous_requests().iter().copied().collect()
     }
 }
 
@@ -0,0 +1,175 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Binlog encryption.
+//!
+//! An encrypted binlog starts with a plaintext `AES_CTR_ENCRYPTION` service
+//! record holding the key salt, IV and a hash of the derived key, like TDLib's
+//! `AesCtrEncryptionEvent`. Every byte after that record is encrypted as one
+//! AES-256-CTR stream.
+
+use aes::Aes256;
+use bytes::Bytes;
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha2::Sha256;
+
+use super::event::{service_type, BinlogEvent};
+use super::{BinlogError, BinlogResult};
+use crate::DbKey;
+
+/// AES-256 in big-endian counter mode.
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// Size of the key derivation salt.
+const KEY_SALT_SIZE: usize = 32;
+
+/// Size of the CTR initialization vector.
+const IV_SIZE: usize = 16;
+
+/// Size of the derived key hash.
+const KEY_HASH_SIZE: usize = 32;
+
+/// PBKDF2 iterations for password keys.
+const PASSWORD_KDF_ITERATIONS: u32 = 60002;
+
+/// PBKDF2 iterations for raw keys, which are already random.
+const RAW_KEY_KDF_ITERATIONS: u32 = 2;
+
+/// Message authenticated to check that the derived key is right.
+const KEY_HASH_MESSAGE: &[u8] = b"cucumbers everywhere";
+
+/// Encryption state of an open binlog.
+pub(crate) struct BinlogEncryption {
+    /// Cipher positioned at the end of the encrypted stream.
+    cipher: Aes256Ctr,
+}
+
+impl BinlogEncryption {
+    /// Generates fresh encryption parameters for `db_key`.
+    ///
+    /// Returns the encryption state and the header record to write first.
+    pub(crate) fn generate(db_key: &DbKey) -> (Self, BinlogEvent) {
+        let mut key_salt = [0u8; KEY_SALT_SIZE];
+        let mut iv = [0u8; IV_SIZE];
+        rand::thread_rng().fill_bytes(&mut key_salt);
+        rand::thread_rng().fill_bytes(&mut iv);
+
+        let key = derive_key(db_key, &key_salt);
+        let mut data = Vec::with_capacity(KEY_SALT_SIZE + IV_SIZE + KEY_HASH_SIZE);
+        data.extend_from_slice(&key_salt);
+        data.extend_from_slice(&iv);
+        data.extend_from_slice(&key_hash(&key));
+
+        let header = BinlogEvent::new(0, service_type::AES_CTR_ENCRYPTION, 0, Bytes::from(data));
+        let cipher = Aes256Ctr::new(&key.into(), &iv.into());
+        (Self { cipher }, header)
+    }
+
+    /// Restores the encryption state from a header record.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BinlogError::WrongKey`] if `db_key` doesn't match the key the
+    /// binlog was encrypted with.
+    pub(crate) fn from_header(db_key: &DbKey, header: &BinlogEvent) -> BinlogResult<Self> {
+        let data = header.data();
+        if data.len() != KEY_SALT_SIZE + IV_SIZE + KEY_HASH_SIZE {
+            return Err(BinlogError::WrongKey);
+        }
+        let (key_salt, rest) = data.split_at(KEY_SALT_SIZE);
+        let (iv, expected_hash) = rest.split_at(IV_SIZE);
+
+        let key = derive_key(db_key, key_salt);
+        if db_key.is_empty() || key_hash(&key) != expected_hash {
+            return Err(BinlogError::WrongKey);
+        }
+        let iv: [u8; IV_SIZE] = iv.try_into().map_err(|_| BinlogError::WrongKey)?;
+        Ok(Self {
+            cipher: Aes256Ctr::new(&key.into(), &iv.into()),
+        })
+    }
+
+    /// Encrypts or decrypts the next bytes of the stream in place.
+    pub(crate) fn apply(&mut self, buf: &mut [u8]) {
+        self.cipher.apply_keystream(buf);
+    }
+
+    /// Moves the stream to `position` bytes after the header record.
+    pub(crate) fn seek(&mut self, position: u64) {
+        self.cipher.seek(position);
+    }
+}
+
+/// Derives the AES key from the database key.
+fn derive_key(db_key: &DbKey, key_salt: &[u8]) -> [u8; 32] {
+    let iterations = if db_key.is_raw_key() {
+        RAW_KEY_KDF_ITERATIONS
+    } else {
+        PASSWORD_KDF_ITERATIONS
+    };
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(db_key.data(), key_salt, iterations, &mut key);
+    key
+}
+
+/// Computes the hash stored in the header to verify the derived key.
+fn key_hash(key: &[u8; 32]) -> [u8; KEY_HASH_SIZE] {
+    // HMAC accepts keys of any length
+    let mut mac = match <Hmac<Sha256> as Mac>::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(_) => return [0u8; KEY_HASH_SIZE],
+    };
+    mac.update(KEY_HASH_MESSAGE);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let db_key = DbKey::raw_key(vec![7u8; 32]);
+        let (mut writer, header) = BinlogEncryption::generate(&db_key);
+        assert_eq!(header.event_type(), service_type::AES_CTR_ENCRYPTION);
+
+        let mut data = *b"some binlog bytes";
+        writer.apply(&mut data);
+        assert_ne!(&data, b"some binlog bytes");
+
+        let mut reader = BinlogEncryption::from_header(&db_key, &header).unwrap();
+        reader.apply(&mut data);
+        assert_eq!(&data, b"some binlog bytes");
+    }
+
+    #[test]
+    fn test_seek() {
+        let db_key = DbKey::raw_key(vec![1u8; 32]);
+        let (mut writer, header) = BinlogEncryption::generate(&db_key);
+        let mut data = [0u8; 40];
+        writer.apply(&mut data);
+
+        let mut reader = BinlogEncryption::from_header(&db_key, &header).unwrap();
+        reader.seek(24);
+        let mut tail = data[24..].to_vec();
+        reader.apply(&mut tail);
+        assert_eq!(tail, vec![0u8; 16]);
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let (_, header) = BinlogEncryption::generate(&DbKey::raw_key(vec![1u8; 32]));
+        assert!(matches!(
+            BinlogEncryption::from_header(&DbKey::raw_key(vec![2u8; 32]), &header),
+            Err(BinlogError::WrongKey)
+        ));
+        assert!(matches!(
+            BinlogEncryption::from_header(&DbKey::empty(), &header),
+            Err(BinlogError::WrongKey)
+        ));
+    }
+}
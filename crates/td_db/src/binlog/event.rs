@@ -0,0 +1,267 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Binlog record format.
+//!
+//! Every record has the same layout as in TDLib (`tddb/td/db/binlog/BinlogEvent.h`),
+//! all integers little-endian:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | total record size |
+//! | 4 | 8 | event id |
+//! | 12 | 4 | event type |
+//! | 16 | 4 | flags |
+//! | 20 | 8 | extra |
+//! | 28 | n | event data |
+//! | 28 + n | 4 | CRC32 of all preceding bytes |
+
+use bytes::Bytes;
+use rustgram_logevent::HandlerType;
+
+/// Size of the record header preceding the event data.
+pub const HEADER_SIZE: usize = 4 + 8 + 4 + 4 + 8;
+
+/// Size of the CRC trailer.
+pub const TAIL_SIZE: usize = 4;
+
+/// Smallest possible record.
+pub const MIN_EVENT_SIZE: usize = HEADER_SIZE + TAIL_SIZE;
+
+/// Largest accepted record; anything bigger is treated as corruption.
+pub const MAX_EVENT_SIZE: usize = 1 << 24;
+
+/// Event types reserved by the binlog itself.
+pub(crate) mod service_type {
+    /// Erases the event with the same id.
+    pub const EMPTY: i32 = -2;
+    /// Starts an AES-CTR encrypted stream.
+    pub const AES_CTR_ENCRYPTION: i32 = -3;
+    /// Keeps the id of the last added event across compactions.
+    pub const ID_WATERMARK: i32 = -5;
+}
+
+/// Event flags.
+pub(crate) mod flags {
+    /// The event replaces an earlier event with the same id.
+    pub const REWRITE: i32 = 1;
+}
+
+/// A single event stored in the binlog.
+///
+/// # TDLib Correspondence
+///
+/// Corresponds to TDLib's `BinlogEvent` struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinlogEvent {
+    /// Event id, unique within the binlog.
+    id: u64,
+    /// Event type, a [`HandlerType`] value for regular events.
+    event_type: i32,
+    /// Record flags.
+    flags: i32,
+    /// Extra field, reserved by TDLib.
+    extra: u64,
+    /// Serialized event.
+    data: Bytes,
+}
+
+impl BinlogEvent {
+    /// Creates a regular event.
+    pub(crate) fn new(id: u64, event_type: i32, flags: i32, data: Bytes) -> Self {
+        Self {
+            id,
+            event_type,
+            flags,
+            extra: 0,
+            data,
+        }
+    }
+
+    /// Returns the event id.
+    #[must_use]
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the raw event type.
+    #[must_use]
+    pub const fn event_type(&self) -> i32 {
+        self.event_type
+    }
+
+    /// Returns the handler type of the event, if it is a known one.
+    #[must_use]
+    pub fn handler_type(&self) -> Option<HandlerType> {
+        u32::try_from(self.event_type)
+            .ok()
+            .and_then(|value| HandlerType::from_u32(value).ok())
+    }
+
+    /// Returns the serialized event.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns true if the record replaces an earlier event.
+    pub(crate) const fn is_rewrite(&self) -> bool {
+        self.flags & flags::REWRITE != 0
+    }
+
+    /// Returns true if the record erases an earlier event.
+    pub(crate) const fn is_erase(&self) -> bool {
+        self.is_rewrite() && self.event_type == service_type::EMPTY
+    }
+
+    /// Returns the same event as a plain (non-rewrite) record.
+    pub(crate) fn into_plain(mut self) -> Self {
+        self.flags &= !flags::REWRITE;
+        self
+    }
+
+    /// Returns the size of the serialized record.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        MIN_EVENT_SIZE + self.data.len()
+    }
+
+    /// Serializes the record.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size = self.size();
+        let mut buf = Vec::with_capacity(size);
+        // Records are bounded by MAX_EVENT_SIZE, so the size fits in u32
+        buf.extend_from_slice(&(size as u32).to_le_bytes());
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&self.event_type.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.extend_from_slice(&self.extra.to_le_bytes());
+        buf.extend_from_slice(&self.data);
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Reads the total record size from the first bytes of a record.
+    ///
+    /// Returns `None` if fewer than four bytes are available.
+    #[must_use]
+    pub fn peek_size(buf: &[u8]) -> Option<usize> {
+        let size: [u8; 4] = buf.get(..4)?.try_into().ok()?;
+        Some(u32::from_le_bytes(size) as usize)
+    }
+
+    /// Parses a complete record.
+    ///
+    /// Returns `None` if the size field doesn't match the buffer or the CRC
+    /// doesn't match the content.
+    #[must_use]
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < MIN_EVENT_SIZE || Self::peek_size(buf)? != buf.len() {
+            return None;
+        }
+        let (body, tail) = buf.split_at(buf.len() - TAIL_SIZE);
+        if crc32(body) != u32::from_le_bytes(tail.try_into().ok()?) {
+            return None;
+        }
+
+        let u64_at = |offset: usize| -> Option<u64> {
+            Some(u64::from_le_bytes(
+                body.get(offset..offset + 8)?.try_into().ok()?,
+            ))
+        };
+        let i32_at = |offset: usize| -> Option<i32> {
+            Some(i32::from_le_bytes(
+                body.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        Some(Self {
+            id: u64_at(4)?,
+            event_type: i32_at(12)?,
+            flags: i32_at(16)?,
+            extra: u64_at(20)?,
+            data: Bytes::copy_from_slice(&body[HEADER_SIZE..]),
+        })
+    }
+}
+
+/// Computes the CRC32 (IEEE) checksum used by TDLib's binlog.
+#[must_use]
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    let crc = data.iter().fold(0xffff_ffffu32, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let event = BinlogEvent::new(
+            7,
+            HandlerType::SendMessage as i32,
+            0,
+            Bytes::from_static(b"payload"),
+        );
+        let bytes = event.to_bytes();
+        assert_eq!(bytes.len(), MIN_EVENT_SIZE + 7);
+        assert_eq!(BinlogEvent::peek_size(&bytes), Some(bytes.len()));
+
+        let parsed = BinlogEvent::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, event);
+        assert_eq!(parsed.handler_type(), Some(HandlerType::SendMessage));
+        assert!(!parsed.is_rewrite());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let event = BinlogEvent::new(1, 2, 0, Bytes::from_static(b"abc"));
+        let mut bytes = event.to_bytes();
+        bytes[HEADER_SIZE] ^= 0xff;
+        assert!(BinlogEvent::from_bytes(&bytes).is_none());
+        assert!(BinlogEvent::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_erase_record() {
+        let erase = BinlogEvent::new(3, service_type::EMPTY, flags::REWRITE, Bytes::new());
+        assert!(erase.is_erase());
+        assert!(erase.handler_type().is_none());
+
+        let rewrite = BinlogEvent::new(3, 0x100, flags::REWRITE, Bytes::new());
+        assert!(rewrite.is_rewrite());
+        assert!(!rewrite.is_erase());
+        assert!(!rewrite.into_plain().is_rewrite());
+    }
+}
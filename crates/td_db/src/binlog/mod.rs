@@ -0,0 +1,776 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Append-only binlog for durable log events.
+//!
+//! The binlog persists pending operations (sends, deletions, secret chat
+//! state, ...) serialized by `rustgram-logevent` so that they survive a crash.
+//! It is a sequence of CRC-protected records (see [`BinlogEvent`]); adding,
+//! rewriting and erasing an event each append one record, and the current set
+//! of events is rebuilt by replaying all records when the binlog is opened.
+//!
+//! A crash can leave a partially written record at the end of the file. Such a
+//! torn tail ends the replay and is cut off the file. Damaged data in the
+//! middle of the file, such as a record failing its CRC check, is skipped up to
+//! the next intact record, so the valid records after it are kept.
+//!
+//! When erased and rewritten events make up most of the file, the binlog is
+//! compacted into a fresh file holding only the live events, followed by an id
+//! watermark so that the ids of erased events are never reused.
+//!
+//! # TDLib Correspondence
+//!
+//! Corresponds to TDLib's `Binlog` class in `tddb/td/db/binlog/Binlog.h`.
+
+mod encryption;
+mod event;
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use rustgram_logevent::HandlerType;
+
+use crate::DbKey;
+use encryption::BinlogEncryption;
+use event::{flags, service_type};
+
+pub use event::{crc32, BinlogEvent, MAX_EVENT_SIZE, MIN_EVENT_SIZE};
+
+/// Files smaller than this are never compacted automatically.
+const COMPACTION_MIN_FILE_SIZE: u64 = 512 * 1024;
+
+/// Result type for binlog operations.
+pub type BinlogResult<T> = Result<T, BinlogError>;
+
+/// Errors that can occur in binlog operations.
+#[derive(Debug, thiserror::Error)]
+pub enum BinlogError {
+    /// I/O error.
+    #[error("I/O error: {0}")]
+    IoError(#[from] io::Error),
+
+    /// The binlog is encrypted with a different key.
+    #[error("Wrong database encryption key")]
+    WrongKey,
+
+    /// No live event has the given id.
+    #[error("Binlog event not found: {0}")]
+    EventNotFound(u64),
+
+    /// The event doesn't fit into a single record.
+    #[error("Binlog event too large: {0} bytes")]
+    EventTooLarge(usize),
+}
+
+/// Callback invoked for a replayed event.
+type BinlogHandler<'a> = Box<dyn FnMut(&BinlogEvent) + 'a>;
+
+/// Handlers receiving replayed events, one per [`HandlerType`].
+///
+/// # TDLib Correspondence
+///
+/// Plays the role of the per-type dispatch in TDLib's `Td::init` over the
+/// events collected by `TdDb::init_binlog`.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_logevent::HandlerType;
+/// use rustgram_td_db::BinlogHandlers;
+///
+/// let mut pending_sends = Vec::new();
+/// let mut handlers = BinlogHandlers::new();
+/// handlers.register(HandlerType::SendMessage, |event| pending_sends.push(event.id()));
+/// assert!(handlers.is_registered(HandlerType::SendMessage));
+/// ```
+#[derive(Default)]
+pub struct BinlogHandlers<'a> {
+    /// Registered handlers.
+    handlers: HashMap<HandlerType, BinlogHandler<'a>>,
+}
+
+impl<'a> BinlogHandlers<'a> {
+    /// Creates an empty handler set.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the handler for events of `handler_type`, replacing any
+    /// previous one.
+    pub fn register<F>(&mut self, handler_type: HandlerType, handler: F) -> &mut Self
+    where
+        F: FnMut(&BinlogEvent) + 'a,
+    {
+        self.handlers.insert(handler_type, Box::new(handler));
+        self
+    }
+
+    /// Returns true if a handler is registered for `handler_type`.
+    #[must_use]
+    pub fn is_registered(&self, handler_type: HandlerType) -> bool {
+        self.handlers.contains_key(&handler_type)
+    }
+
+    /// Passes `event` to its handler, returning false if there is none.
+    fn dispatch(&mut self, event: &BinlogEvent) -> bool {
+        match event
+            .handler_type()
+            .and_then(|handler_type| self.handlers.get_mut(&handler_type))
+        {
+            Some(handler) => {
+                handler(event);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for BinlogHandlers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinlogHandlers")
+            .field("handler_types", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Append-only binlog of log events.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustgram_logevent::HandlerType;
+/// use rustgram_td_db::{Binlog, BinlogHandlers, DbKey};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut binlog = Binlog::open("/path/to/db/td.binlog", DbKey::empty())?;
+///
+/// // Persist a pending operation, and erase it once it's done
+/// let id = binlog.add(HandlerType::SendMessage, b"serialized event".to_vec())?;
+/// binlog.erase(id)?;
+///
+/// // On startup, hand the pending operations to their managers
+/// let mut handlers = BinlogHandlers::new();
+/// handlers.register(HandlerType::SendMessage, |event| {
+///     // Resume sending...
+/// });
+/// binlog.replay(&mut handlers);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Binlog {
+    /// Path of the binlog file.
+    path: PathBuf,
+    /// Binlog file opened for appending.
+    file: File,
+    /// Key the binlog is encrypted with.
+    db_key: DbKey,
+    /// Encryption state, if the binlog is encrypted.
+    encryption: Option<BinlogEncryption>,
+    /// Size of the plaintext prefix before the encrypted stream.
+    stream_start: u64,
+    /// Live events by id.
+    events: BTreeMap<u64, BinlogEvent>,
+    /// Id assigned to the next added event.
+    next_event_id: u64,
+    /// Current size of the file.
+    file_size: u64,
+    /// Total record size of the live events.
+    events_size: u64,
+    /// Bytes cut off the end of the file when it was opened.
+    discarded_tail_size: u64,
+    /// Bytes of damaged records skipped when the binlog was opened.
+    skipped_size: u64,
+}
+
+impl Binlog {
+    /// Opens the binlog at `path`, creating it if needed, and loads its events.
+    ///
+    /// A plaintext binlog opened with a non-empty key is encrypted on the spot.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BinlogError::WrongKey`] if the binlog is encrypted and
+    /// `db_key` doesn't match, or an I/O error.
+    pub fn open(path: impl AsRef<Path>, db_key: DbKey) -> BinlogResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        // A leftover from an interrupted compaction; the old file is still intact
+        let temp_path = Self::temp_path(&path);
+        if temp_path.exists() {
+            fs::remove_file(&temp_path)?;
+        }
+
+        let mut buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut encryption = None;
+        let mut offset = 0;
+        if let Some(header) = read_record(&buf, 0) {
+            if header.event_type() == service_type::AES_CTR_ENCRYPTION {
+                let mut state = BinlogEncryption::from_header(&db_key, &header)?;
+                offset = header.size();
+                state.apply(&mut buf[offset..]);
+                encryption = Some(state);
+            }
+        }
+        let stream_start = offset as u64;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut binlog = Self {
+            path,
+            file,
+            db_key,
+            encryption,
+            stream_start,
+            events: BTreeMap::new(),
+            next_event_id: 1,
+            file_size: 0,
+            events_size: 0,
+            discarded_tail_size: 0,
+            skipped_size: 0,
+        };
+
+        loop {
+            match scan_record(&buf, offset) {
+                RecordScan::Event(event) => {
+                    offset += event.size();
+                    binlog.apply_event(event);
+                }
+                RecordScan::Damaged(size) => {
+                    offset += size;
+                    binlog.skipped_size += size as u64;
+                }
+                RecordScan::TornTail => break,
+            }
+        }
+
+        binlog.file_size = offset as u64;
+        binlog.discarded_tail_size = (buf.len() - offset) as u64;
+        if binlog.discarded_tail_size > 0 {
+            binlog.file.set_len(binlog.file_size)?;
+            binlog.file.sync_all()?;
+        }
+        if let Some(encryption) = &mut binlog.encryption {
+            encryption.seek(binlog.file_size - stream_start);
+        }
+
+        if binlog.encryption.is_none() && !binlog.db_key.is_empty() {
+            binlog.compact()?;
+        }
+        Ok(binlog)
+    }
+
+    /// Returns the path of the binlog file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the binlog is encrypted.
+    #[must_use]
+    pub const fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Returns the number of live events.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns true if there are no live events.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns the live event with the given id.
+    #[must_use]
+    pub fn get(&self, id: u64) -> Option<&BinlogEvent> {
+        self.events.get(&id)
+    }
+
+    /// Returns the live events in id order.
+    pub fn events(&self) -> impl Iterator<Item = &BinlogEvent> {
+        self.events.values()
+    }
+
+    /// Returns the current size of the binlog file.
+    #[must_use]
+    pub const fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Returns how many bytes of a torn tail were discarded when the binlog
+    /// was opened.
+    #[must_use]
+    pub const fn discarded_tail_size(&self) -> u64 {
+        self.discarded_tail_size
+    }
+
+    /// Returns how many bytes of damaged records in the middle of the file
+    /// were skipped when the binlog was opened.
+    ///
+    /// They are dropped from the file by the next compaction.
+    #[must_use]
+    pub const fn skipped_size(&self) -> u64 {
+        self.skipped_size
+    }
+
+    /// Appends a new event and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event is too large or can't be written.
+    pub fn add(&mut self, handler_type: HandlerType, data: impl Into<Bytes>) -> BinlogResult<u64> {
+        let id = self.next_event_id;
+        let event = BinlogEvent::new(id, handler_type as i32, 0, data.into());
+        self.write_record(&event)?;
+        self.next_event_id += 1;
+        self.apply_event(event);
+        Ok(id)
+    }
+
+    /// Replaces the content of an existing event.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BinlogError::EventNotFound`] if there is no such event, or an
+    /// error if the record can't be written.
+    pub fn rewrite(
+        &mut self,
+        id: u64,
+        handler_type: HandlerType,
+        data: impl Into<Bytes>,
+    ) -> BinlogResult<()> {
+        if !self.events.contains_key(&id) {
+            return Err(BinlogError::EventNotFound(id));
+        }
+        let event = BinlogEvent::new(id, handler_type as i32, flags::REWRITE, data.into());
+        self.write_record(&event)?;
+        self.apply_event(event);
+        self.maybe_compact()
+    }
+
+    /// Erases an event.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BinlogError::EventNotFound`] if there is no such event, or an
+    /// error if the record can't be written.
+    pub fn erase(&mut self, id: u64) -> BinlogResult<()> {
+        if !self.events.contains_key(&id) {
+            return Err(BinlogError::EventNotFound(id));
+        }
+        let event = BinlogEvent::new(id, service_type::EMPTY, flags::REWRITE, Bytes::new());
+        self.write_record(&event)?;
+        self.apply_event(event);
+        self.maybe_compact()
+    }
+
+    /// Passes every live event, in id order, to the handler registered for
+    /// its type and returns the number of handled events.
+    ///
+    /// Events without a handler are skipped and stay in the binlog.
+    pub fn replay(&self, handlers: &mut BinlogHandlers<'_>) -> usize {
+        self.events
+            .values()
+            .filter(|event| handlers.dispatch(event))
+            .count()
+    }
+
+    /// Flushes written records to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the file can't be synced.
+    pub fn sync(&mut self) -> BinlogResult<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Rewrites the binlog so that it contains only the live events.
+    ///
+    /// The new file is written next to the old one and renamed over it, so a
+    /// crash during compaction leaves the old binlog intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the new file can't be written.
+    pub fn compact(&mut self) -> BinlogResult<()> {
+        let mut data = Vec::new();
+        let mut encryption = None;
+        if !self.db_key.is_empty() {
+            let (state, header) = BinlogEncryption::generate(&self.db_key);
+            data.extend_from_slice(&header.to_bytes());
+            encryption = Some(state);
+        }
+        let stream_start = data.len();
+        for event in self.events.values() {
+            data.extend_from_slice(&event.to_bytes());
+        }
+        // Without it, ids of erased events past the last live one would be reused
+        let last_id = self.next_event_id - 1;
+        if last_id > 0 {
+            let watermark = BinlogEvent::new(last_id, service_type::ID_WATERMARK, 0, Bytes::new());
+            data.extend_from_slice(&watermark.to_bytes());
+        }
+        if let Some(encryption) = &mut encryption {
+            encryption.apply(&mut data[stream_start..]);
+        }
+
+        let temp_path = Self::temp_path(&self.path);
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.encryption = encryption;
+        self.stream_start = stream_start as u64;
+        self.file_size = data.len() as u64;
+        Ok(())
+    }
+
+    /// Re-encrypts the binlog with a new key; an empty key decrypts it.
+    ///
+    /// On failure the binlog keeps its previous key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the binlog can't be rewritten.
+    pub fn change_key(&mut self, db_key: DbKey) -> BinlogResult<()> {
+        let old_key = std::mem::replace(&mut self.db_key, db_key);
+        let result = self.compact();
+        if result.is_err() {
+            self.db_key = old_key;
+        }
+        result
+    }
+
+    /// Returns the path used while compacting.
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut temp_path = OsString::from(path.as_os_str());
+        temp_path.push(".new");
+        PathBuf::from(temp_path)
+    }
+
+    /// Appends a record to the file.
+    fn write_record(&mut self, event: &BinlogEvent) -> BinlogResult<()> {
+        if event.size() > MAX_EVENT_SIZE {
+            return Err(BinlogError::EventTooLarge(event.size()));
+        }
+        let mut bytes = event.to_bytes();
+        if let Some(encryption) = &mut self.encryption {
+            encryption.apply(&mut bytes);
+        }
+        if let Err(e) = self.file.write_all(&bytes) {
+            // Drop the partial record so that later records stay readable
+            let _ = self.file.set_len(self.file_size);
+            if let Some(encryption) = &mut self.encryption {
+                encryption.seek(self.file_size - self.stream_start);
+            }
+            return Err(e.into());
+        }
+        self.file_size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Updates the live events with a replayed or written record.
+    fn apply_event(&mut self, event: BinlogEvent) {
+        let id = event.id();
+        self.next_event_id = self.next_event_id.max(id.saturating_add(1));
+
+        if event.is_erase() {
+            if let Some(old) = self.events.remove(&id) {
+                self.events_size -= old.size() as u64;
+            }
+            return;
+        }
+        if event.event_type() < 0 {
+            // Service records carry no event
+            return;
+        }
+
+        let event = event.into_plain();
+        self.events_size += event.size() as u64;
+        if let Some(old) = self.events.insert(id, event) {
+            self.events_size -= old.size() as u64;
+        }
+    }
+
+    /// Compacts the binlog if most of it is garbage.
+    fn maybe_compact(&mut self) -> BinlogResult<()> {
+        let live_size = self.stream_start + self.events_size;
+        if self.file_size > COMPACTION_MIN_FILE_SIZE && self.file_size > 2 * live_size {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Debug for Binlog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Binlog")
+            .field("path", &self.path)
+            .field("is_encrypted", &self.is_encrypted())
+            .field("events", &self.events.len())
+            .field("file_size", &self.file_size)
+            .finish()
+    }
+}
+
+/// Outcome of scanning the file at some offset.
+enum RecordScan {
+    /// An intact record.
+    Event(BinlogEvent),
+    /// Damaged data of the given size, followed by an intact record.
+    Damaged(usize),
+    /// The end of the file, possibly after a partially written record.
+    TornTail,
+}
+
+/// Scans the file at `offset` while replaying it.
+///
+/// Damaged data is resynchronized on the next intact record; if there is
+/// none, the damage is a torn tail.
+fn scan_record(buf: &[u8], offset: usize) -> RecordScan {
+    if let Some(event) = read_record(buf, offset) {
+        return RecordScan::Event(event);
+    }
+    (offset + 1..buf.len())
+        .find(|&next| read_record(buf, next).is_some())
+        .map_or(RecordScan::TornTail, |next| {
+            RecordScan::Damaged(next - offset)
+        })
+}
+
+/// Reads the record starting at `offset`, if it is complete and intact.
+fn read_record(buf: &[u8], offset: usize) -> Option<BinlogEvent> {
+    let rest = buf.get(offset..)?;
+    let size = BinlogEvent::peek_size(rest)?;
+    if !(MIN_EVENT_SIZE..=MAX_EVENT_SIZE).contains(&size) || size > rest.len() {
+        return None;
+    }
+    BinlogEvent::from_bytes(&rest[..size])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_add_and_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("td.binlog");
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert!(binlog.is_empty());
+        let first = binlog.add(HandlerType::SendMessage, &b"first"[..]).unwrap();
+        let second = binlog
+            .add(HandlerType::DeleteMessage, &b"second"[..])
+            .unwrap();
+        assert_eq!((first, second), (1, 2));
+        drop(binlog);
+
+        let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert_eq!(binlog.len(), 2);
+        assert_eq!(binlog.get(first).unwrap().data(), b"first");
+        assert_eq!(
+            binlog.get(second).unwrap().handler_type(),
+            Some(HandlerType::DeleteMessage)
+        );
+        assert_eq!(binlog.discarded_tail_size(), 0);
+    }
+
+    #[test]
+    fn test_rewrite_and_erase() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("td.binlog");
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        let kept = binlog.add(HandlerType::SendMessage, &b"v1"[..]).unwrap();
+        let erased = binlog.add(HandlerType::SendMessage, &b"gone"[..]).unwrap();
+        binlog
+            .rewrite(kept, HandlerType::SendMessage, &b"v2"[..])
+            .unwrap();
+        binlog.erase(erased).unwrap();
+        assert!(matches!(
+            binlog.erase(erased),
+            Err(BinlogError::EventNotFound(_))
+        ));
+        assert!(matches!(
+            binlog.rewrite(99, HandlerType::SendMessage, Bytes::new()),
+            Err(BinlogError::EventNotFound(99))
+        ));
+        drop(binlog);
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert_eq!(binlog.len(), 1);
+        assert_eq!(binlog.get(kept).unwrap().data(), b"v2");
+        assert!(binlog.get(erased).is_none());
+
+        // Ids are never reused, even after the last event was erased
+        assert_eq!(
+            binlog.add(HandlerType::SendMessage, Bytes::new()).unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_replay_dispatches_by_type() {
+        let dir = tempdir().unwrap();
+        let mut binlog = Binlog::open(dir.path().join("td.binlog"), DbKey::empty()).unwrap();
+        binlog.add(HandlerType::SendMessage, &b"a"[..]).unwrap();
+        binlog.add(HandlerType::SecretChats, &b"b"[..]).unwrap();
+        binlog.add(HandlerType::SendMessage, &b"c"[..]).unwrap();
+
+        let mut sends = Vec::new();
+        let handled = {
+            let mut handlers = BinlogHandlers::new();
+            handlers.register(HandlerType::SendMessage, |event| {
+                sends.push(event.data().to_vec())
+            });
+            binlog.replay(&mut handlers)
+        };
+        assert_eq!(handled, 2);
+        assert_eq!(sends, vec![b"a".to_vec(), b"c".to_vec()]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("td.binlog");
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        let kept = binlog.add(HandlerType::SendMessage, &b"kept"[..]).unwrap();
+        for _ in 0..10 {
+            let id = binlog
+                .add(HandlerType::SendMessage, vec![0u8; 100])
+                .unwrap();
+            binlog.erase(id).unwrap();
+        }
+        let size_before = binlog.file_size();
+        binlog.compact().unwrap();
+        assert!(binlog.file_size() < size_before);
+        assert_eq!(binlog.file_size(), fs::metadata(&path).unwrap().len());
+
+        let next = binlog.add(HandlerType::SendMessage, Bytes::new()).unwrap();
+        assert_eq!(next, 12);
+        drop(binlog);
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert_eq!(binlog.len(), 2);
+        assert_eq!(binlog.get(kept).unwrap().data(), b"kept");
+
+        // The erased ids stay used after compacting and reopening
+        binlog.erase(next).unwrap();
+        binlog.compact().unwrap();
+        drop(binlog);
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert_eq!(binlog.len(), 1);
+        assert_eq!(
+            binlog.add(HandlerType::SendMessage, Bytes::new()).unwrap(),
+            13
+        );
+    }
+
+    #[test]
+    fn test_auto_compaction() {
+        let dir = tempdir().unwrap();
+        let mut binlog = Binlog::open(dir.path().join("td.binlog"), DbKey::empty()).unwrap();
+        let payload = vec![0u8; 4096];
+        for _ in 0..200 {
+            let id = binlog
+                .add(HandlerType::SendMessage, payload.clone())
+                .unwrap();
+            binlog.erase(id).unwrap();
+        }
+        assert!(binlog.file_size() <= COMPACTION_MIN_FILE_SIZE);
+        assert!(binlog.is_empty());
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("td.binlog");
+        let key = DbKey::raw_key(vec![42u8; 32]);
+
+        let mut binlog = Binlog::open(&path, key.clone()).unwrap();
+        assert!(binlog.is_encrypted());
+        binlog
+            .add(HandlerType::SecretChats, &b"top secret"[..])
+            .unwrap();
+        drop(binlog);
+
+        let raw = fs::read(&path).unwrap();
+        assert!(!raw.windows(10).any(|window| window == b"top secret"));
+
+        assert!(matches!(
+            Binlog::open(&path, DbKey::raw_key(vec![1u8; 32])),
+            Err(BinlogError::WrongKey)
+        ));
+        assert!(matches!(
+            Binlog::open(&path, DbKey::empty()),
+            Err(BinlogError::WrongKey)
+        ));
+
+        let mut binlog = Binlog::open(&path, key.clone()).unwrap();
+        assert_eq!(binlog.get(1).unwrap().data(), b"top secret");
+        binlog.add(HandlerType::SecretChats, &b"more"[..]).unwrap();
+        drop(binlog);
+
+        let binlog = Binlog::open(&path, key).unwrap();
+        assert_eq!(binlog.len(), 2);
+        assert_eq!(binlog.get(2).unwrap().data(), b"more");
+    }
+
+    #[test]
+    fn test_change_key() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("td.binlog");
+
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        binlog.add(HandlerType::Users, &b"user"[..]).unwrap();
+        drop(binlog);
+
+        // Opening a plaintext binlog with a key encrypts it
+        let key = DbKey::raw_key(vec![5u8; 32]);
+        let mut binlog = Binlog::open(&path, key.clone()).unwrap();
+        assert!(binlog.is_encrypted());
+        binlog.change_key(DbKey::empty()).unwrap();
+        assert!(!binlog.is_encrypted());
+        drop(binlog);
+
+        let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        assert_eq!(binlog.get(1).unwrap().data(), b"user");
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Persistent per-channel pts.
+//!
+//! Every channel and supergroup has its own update sequence. The last applied
+//! pts of each channel is stored here, so that after a restart the missed
+//! updates can be requested with `updates.getChannelDifference` instead of
+//! being lost.
+
+use std::sync::Mutex;
+
+use crate::kv_store::{KeyValueStore, KvError, KvResult};
+use crate::{DbKey, TdDbParameters};
+
+/// Prefix of the keys holding channel pts in the key-value store.
+const KEY_PREFIX: &str = "channel_pts#";
+
+/// Store of the last applied pts of every channel.
+///
+/// Values live in the [`KeyValueStore`] under `channel_pts#<channel_id>`
+/// keys. The store is safe to share between threads.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustgram_td_db::{ChannelPtsStore, TdDbParameters};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let params = TdDbParameters::new(
+///     "/path/to/db".to_string(),
+///     "/path/to/files".to_string(),
+///     false,
+///     true
+/// );
+///
+/// let store = ChannelPtsStore::open(&params)?;
+/// store.set(1234567890, 100)?;
+/// assert_eq!(store.get(1234567890)?, Some(100));
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChannelPtsStore {
+    /// Underlying key-value store.
+    store: Mutex<KeyValueStore>,
+}
+
+impl ChannelPtsStore {
+    /// Opens the store in the database directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key-value database can't be opened.
+    pub fn open(params: &TdDbParameters) -> KvResult<Self> {
+        KeyValueStore::open(params).map(Self::new)
+    }
+
+    /// Opens the store in a database directory encrypted with the given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is wrong or the database can't be opened.
+    pub fn open_with_key(params: &TdDbParameters, db_key: &DbKey) -> KvResult<Self> {
+        KeyValueStore::open_with_key(params, db_key).map(Self::new)
+    }
+
+    /// Creates a store on top of an open key-value store.
+    #[must_use]
+    pub fn new(store: KeyValueStore) -> Self {
+        Self {
+            store: Mutex::new(store),
+        }
+    }
+
+    /// Returns the saved pts of a channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be read or holds a malformed
+    /// value.
+    pub fn get(&self, channel_id: i64) -> KvResult<Option<i32>> {
+        let store = self.lock();
+        match store.get_i64(&key(channel_id)) {
+            Ok(pts) => i32::try_from(pts)
+                .map(Some)
+                .map_err(|_| KvError::InvalidType(format!("pts {pts} is out of range"))),
+            Err(KvError::KeyNotFound(_)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Saves the pts of a channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn set(&self, channel_id: i64, pts: i32) -> KvResult<()> {
+        self.lock().set_i64(&key(channel_id), i64::from(pts))
+    }
+
+    /// Forgets the pts of a channel, e.g. after leaving it.
+    ///
+    /// Returns true if a value was deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn delete(&self, channel_id: i64) -> KvResult<bool> {
+        self.lock().delete(&key(channel_id))
+    }
+
+    /// Returns the saved pts of all channels, ordered by channel identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be read.
+    pub fn load_all(&self) -> KvResult<Vec<(i64, i32)>> {
+        let channel_ids: Vec<i64> = {
+            let store = self.lock();
+            store
+                .list_keys()?
+                .iter()
+                .filter_map(|key| key.strip_prefix(KEY_PREFIX)?.parse().ok())
+                .collect()
+        };
+
+        let mut result = Vec::with_capacity(channel_ids.len());
+        for channel_id in channel_ids {
+            if let Some(pts) = self.get(channel_id)? {
+                result.push((channel_id, pts));
+            }
+        }
+        result.sort_unstable();
+        Ok(result)
+    }
+
+    /// Locks the key-value store, ignoring poisoning since every operation
+    /// is a single statement.
+    fn lock(&self) -> std::sync::MutexGuard<'_, KeyValueStore> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Returns the key of a channel's pts.
+fn key(channel_id: i64) -> String {
+    format!("{KEY_PREFIX}{channel_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn create_test_store() -> (ChannelPtsStore, tempfile::TempDir) {
+        let dir = tempdir().unwrap();
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        );
+        (ChannelPtsStore::open(&params).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_get_missing() {
+        let (store, _dir) = create_test_store();
+        assert_eq!(store.get(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let (store, _dir) = create_test_store();
+
+        store.set(1, 10).unwrap();
+        store.set(1, 15).unwrap();
+        assert_eq!(store.get(1).unwrap(), Some(15));
+
+        assert!(store.delete(1).unwrap());
+        assert!(!store.delete(1).unwrap());
+        assert_eq!(store.get(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_load_all_skips_other_keys() {
+        let (store, _dir) = create_test_store();
+
+        store.set(20, 2).unwrap();
+        store.set(3, 1).unwrap();
+        store.lock().set_i64("other", 5).unwrap();
+
+        assert_eq!(store.load_all().unwrap(), vec![(3, 1), (20, 2)]);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempdir().unwrap();
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        );
+
+        ChannelPtsStore::open(&params).unwrap().set(7, 42).unwrap();
+        let store = ChannelPtsStore::open(&params).unwrap();
+        assert_eq!(store.get(7).unwrap(), Some(42));
+    }
+}
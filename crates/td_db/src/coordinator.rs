@@ -6,6 +6,11 @@
 //!
 //! This module provides the central TdDb coordinator that initializes and manages
 //! all SQLite databases used by the Telegram client.
+//!
+//! With a non-empty [`DbKey`] every database file, the key-value store and the
+//! binlog are encrypted at rest: the SQLite databases with SQLCipher (see
+//! [`DbKey::sqlcipher_key`] for the key derivation) and the binlog with its
+//! own AES-CTR stream.
 
 use std::path::Path;
 
@@ -13,7 +18,8 @@ use rustgram_storage::{
     ChatDb, DbConnection, FileDb, MessageDb, StorageError, StorageResult, UserDb,
 };
 
-use crate::TdDbParameters;
+use crate::binlog::{Binlog, BinlogError};
+use crate::{DbKey, KeyValueStore, KvError, TdDbParameters};
 
 /// TdDb coordinator - manages all Telegram client databases.
 ///
@@ -61,6 +67,15 @@ pub struct TdDb {
     /// File database (optional, based on parameters).
     file_db: Option<FileDb>,
 
+    /// Binlog of pending log events (always enabled).
+    binlog: Option<Binlog>,
+
+    /// Key-value store for settings (always enabled).
+    kv_store: Option<KeyValueStore>,
+
+    /// Database encryption key.
+    db_key: DbKey,
+
     /// Whether the database is open.
     is_open: bool,
 }
@@ -97,6 +112,38 @@ impl TdDb {
     /// # }
     /// ```
     pub fn open(params: TdDbParameters) -> StorageResult<Self> {
+        Self::open_with_key(params, DbKey::empty())
+    }
+
+    /// Opens all databases encrypted with the given key.
+    ///
+    /// New databases are created encrypted; existing ones must have been
+    /// encrypted with the same key. An empty key is the same as [`TdDb::open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::CryptoError`] if the key doesn't match the
+    /// existing databases, or any error of [`TdDb::open`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use rustgram_td_db::{DbKey, TdDb, TdDbParameters};
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let params = TdDbParameters::new(
+    ///     "/path/to/db".to_string(),
+    ///     "/path/to/files".to_string(),
+    ///     false,
+    ///     true
+    /// );
+    ///
+    /// let tddb = TdDb::open_with_key(params, DbKey::password("secret"))?;
+    /// assert!(tddb.is_encrypted());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open_with_key(params: TdDbParameters, db_key: DbKey) -> StorageResult<Self> {
         // Create database directory if it doesn't exist
         let db_dir = Path::new(params.database_directory());
         if !db_dir.exists() {
@@ -104,14 +151,16 @@ impl TdDb {
         }
 
         // Initialize individual databases
-        let message_db = Self::open_message_db(&params)?;
-        let user_db = Self::open_user_db(&params)?;
-        let chat_db = Self::open_chat_db(&params)?;
+        let message_db = Self::open_message_db(&params, &db_key)?;
+        let user_db = Self::open_user_db(&params, &db_key)?;
+        let chat_db = Self::open_chat_db(&params, &db_key)?;
         let file_db = if params.use_file_database() {
-            Some(Self::open_file_db(&params)?)
+            Some(Self::open_file_db(&params, &db_key)?)
         } else {
             None
         };
+        let binlog = Self::open_binlog(&params, &db_key)?;
+        let kv_store = KeyValueStore::open_with_key(&params, &db_key).map_err(kv_error)?;
 
         Ok(Self {
             parameters: params,
@@ -119,14 +168,29 @@ impl TdDb {
             user_db: Some(user_db),
             chat_db: Some(chat_db),
             file_db,
+            binlog: Some(binlog),
+            kv_store: Some(kv_store),
+            db_key,
             is_open: true,
         })
     }
 
+    /// Opens a connection manager for a database file, applying the key.
+    fn open_connection(
+        params: &TdDbParameters,
+        name: &str,
+        db_key: &DbKey,
+    ) -> StorageResult<DbConnection> {
+        let db_path = Path::new(params.database_directory()).join(name);
+        match db_key.sqlcipher_key() {
+            Some(key) => DbConnection::new_encrypted(db_path, key),
+            None => DbConnection::new(db_path),
+        }
+    }
+
     /// Opens the message database.
-    fn open_message_db(params: &TdDbParameters) -> StorageResult<MessageDb> {
-        let db_path = Path::new(params.database_directory()).join("messages.db");
-        let conn = DbConnection::new(db_path)?;
+    fn open_message_db(params: &TdDbParameters, db_key: &DbKey) -> StorageResult<MessageDb> {
+        let conn = Self::open_connection(params, "messages.db", db_key)?;
 
         // Check for corruption
         Self::check_integrity(&conn)?;
@@ -137,9 +201,8 @@ impl TdDb {
     }
 
     /// Opens the user database.
-    fn open_user_db(params: &TdDbParameters) -> StorageResult<UserDb> {
-        let db_path = Path::new(params.database_directory()).join("users.db");
-        let conn = DbConnection::new(db_path)?;
+    fn open_user_db(params: &TdDbParameters, db_key: &DbKey) -> StorageResult<UserDb> {
+        let conn = Self::open_connection(params, "users.db", db_key)?;
 
         // Check for corruption
         Self::check_integrity(&conn)?;
@@ -150,9 +213,8 @@ impl TdDb {
     }
 
     /// Opens the chat database.
-    fn open_chat_db(params: &TdDbParameters) -> StorageResult<ChatDb> {
-        let db_path = Path::new(params.database_directory()).join("chats.db");
-        let conn = DbConnection::new(db_path)?;
+    fn open_chat_db(params: &TdDbParameters, db_key: &DbKey) -> StorageResult<ChatDb> {
+        let conn = Self::open_connection(params, "chats.db", db_key)?;
 
         // Check for corruption
         Self::check_integrity(&conn)?;
@@ -163,9 +225,8 @@ impl TdDb {
     }
 
     /// Opens the file database.
-    fn open_file_db(params: &TdDbParameters) -> StorageResult<FileDb> {
-        let db_path = Path::new(params.database_directory()).join("files.db");
-        let conn = DbConnection::new(db_path)?;
+    fn open_file_db(params: &TdDbParameters, db_key: &DbKey) -> StorageResult<FileDb> {
+        let conn = Self::open_connection(params, "files.db", db_key)?;
 
         // Check for corruption
         Self::check_integrity(&conn)?;
@@ -175,6 +236,24 @@ impl TdDb {
         Ok(db)
     }
 
+    /// Opens the binlog.
+    fn open_binlog(params: &TdDbParameters, db_key: &DbKey) -> StorageResult<Binlog> {
+        Binlog::open(Self::binlog_path(params), db_key.clone()).map_err(binlog_error)
+    }
+
+    /// Returns the path of the binlog file.
+    ///
+    /// As in TDLib, test DC sessions use a separate binlog.
+    #[must_use]
+    pub fn binlog_path(params: &TdDbParameters) -> std::path::PathBuf {
+        let name = if params.is_test_dc() {
+            "td_test.binlog"
+        } else {
+            "td.binlog"
+        };
+        Path::new(params.database_directory()).join(name)
+    }
+
     /// Checks database integrity and attempts recovery if corrupted.
     ///
     /// # Arguments
@@ -264,6 +343,10 @@ impl TdDb {
         self.user_db = None;
         self.chat_db = None;
         self.file_db = None;
+        self.kv_store = None;
+        if let Some(mut binlog) = self.binlog.take() {
+            binlog.sync().map_err(binlog_error)?;
+        }
 
         self.is_open = false;
         Ok(())
@@ -381,6 +464,30 @@ impl TdDb {
         self.file_db.as_ref()
     }
 
+    /// Returns the binlog if available.
+    #[must_use]
+    pub fn get_binlog(&self) -> Option<&Binlog> {
+        self.binlog.as_ref()
+    }
+
+    /// Returns the binlog for writing if available.
+    #[must_use]
+    pub fn get_binlog_mut(&mut self) -> Option<&mut Binlog> {
+        self.binlog.as_mut()
+    }
+
+    /// Returns the key-value store if available.
+    #[must_use]
+    pub fn get_kv_store(&self) -> Option<&KeyValueStore> {
+        self.kv_store.as_ref()
+    }
+
+    /// Returns the key-value store for re-keying if available.
+    #[must_use]
+    pub fn get_kv_store_mut(&mut self) -> Option<&mut KeyValueStore> {
+        self.kv_store.as_mut()
+    }
+
     /// Returns the database parameters.
     #[must_use]
     pub const fn parameters(&self) -> &TdDbParameters {
@@ -393,6 +500,113 @@ impl TdDb {
         self.is_open
     }
 
+    /// Returns whether the databases are encrypted.
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        !self.db_key.is_empty()
+    }
+
+    /// Changes the database encryption key; an empty key decrypts the
+    /// databases.
+    ///
+    /// Corresponds to TDLib `setDatabaseEncryptionKey`. The SQLite databases
+    /// are re-encrypted in place one by one, then the key-value store, then
+    /// the binlog. If any step fails, the databases already re-encrypted are
+    /// changed back to the old key, so that everything stays readable with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::InvalidParameter`] if the databases are
+    /// closed, or the error of the step that failed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use rustgram_td_db::{DbKey, TdDb, TdDbParameters};
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let params = TdDbParameters::new(
+    ///     "/path/to/db".to_string(),
+    ///     "/path/to/files".to_string(),
+    ///     false,
+    ///     true
+    /// );
+    ///
+    /// let mut tddb = TdDb::open(params)?;
+    /// tddb.set_database_encryption_key(DbKey::password("secret"))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_database_encryption_key(&mut self, new_key: DbKey) -> StorageResult<()> {
+        if !self.is_open {
+            return Err(StorageError::InvalidParameter(
+                "database is closed".to_string(),
+            ));
+        }
+
+        let old_sqlcipher_key = self.db_key.sqlcipher_key();
+        let new_sqlcipher_key = new_key.sqlcipher_key();
+        let connections = self.connections();
+
+        let mut changed = 0;
+        let mut result = Ok(());
+        for conn in &connections {
+            result = conn.change_key(new_sqlcipher_key.as_deref());
+            if result.is_err() {
+                break;
+            }
+            changed += 1;
+        }
+        let mut kv_store_changed = false;
+        if result.is_ok() {
+            if let Some(kv_store) = &mut self.kv_store {
+                // The store keeps its old key on failure
+                result = kv_store.change_key(&new_key).map_err(kv_error);
+                kv_store_changed = result.is_ok();
+            }
+        }
+        if result.is_ok() {
+            if let Some(binlog) = &mut self.binlog {
+                // The binlog keeps its old key on failure
+                result = binlog.change_key(new_key.clone()).map_err(binlog_error);
+            }
+        }
+
+        if result.is_err() {
+            if kv_store_changed {
+                if let Some(kv_store) = &mut self.kv_store {
+                    let _ = kv_store.change_key(&self.db_key);
+                }
+            }
+            for conn in connections[..changed].iter().rev() {
+                // Best effort: the original error is more useful to the caller
+                let _ = conn.change_key(old_sqlcipher_key.as_deref());
+            }
+            return result;
+        }
+
+        self.db_key = new_key;
+        Ok(())
+    }
+
+    /// Returns the connection managers of all open SQLite databases.
+    fn connections(&self) -> Vec<DbConnection> {
+        let mut connections = Vec::new();
+        if let Some(db) = &self.message_db {
+            connections.push(db.sync().db().clone());
+        }
+        if let Some(db) = &self.user_db {
+            connections.push(db.sync().db().clone());
+        }
+        if let Some(db) = &self.chat_db {
+            connections.push(db.sync().db().clone());
+        }
+        if let Some(db) = &self.file_db {
+            connections.push(db.sync().db().clone());
+        }
+        connections
+    }
+
     /// Destroys all database files for the given parameters.
     ///
     /// # Arguments
@@ -450,6 +664,12 @@ impl TdDb {
             }
         }
 
+        // Delete the binlog
+        let binlog_path = Self::binlog_path(params);
+        if binlog_path.exists() {
+            std::fs::remove_file(&binlog_path)?;
+        }
+
         // Delete file database if enabled
         if params.use_file_database() {
             let file_db_files = ["files.db", "files.db-wal", "files.db-shm"];
@@ -509,6 +729,29 @@ impl TdDb {
     }
 }
 
+/// Converts a binlog error into a storage error.
+fn binlog_error(error: BinlogError) -> StorageError {
+    match error {
+        BinlogError::IoError(e) => StorageError::IoError(e),
+        BinlogError::WrongKey => StorageError::CryptoError(error.to_string()),
+        BinlogError::EventNotFound(_) | BinlogError::EventTooLarge(_) => {
+            StorageError::InvalidParameter(error.to_string())
+        }
+    }
+}
+
+/// Converts a key-value store error into a storage error.
+fn kv_error(error: KvError) -> StorageError {
+    match error {
+        KvError::DatabaseError(e) => StorageError::ConnectionError(e),
+        KvError::IoError(e) => StorageError::IoError(e),
+        KvError::Encryption(e) => StorageError::CryptoError(e),
+        KvError::KeyNotFound(_) | KvError::InvalidType(_) => {
+            StorageError::InvalidParameter(error.to_string())
+        }
+    }
+}
+
 impl Drop for TdDb {
     /// Automatically closes databases when TdDb is dropped.
     fn drop(&mut self) {
@@ -619,6 +862,40 @@ mod tests {
         assert!(!db_dir.join("users.db").exists());
         assert!(!db_dir.join("chats.db").exists());
         assert!(!db_dir.join("files.db").exists());
+        assert!(!db_dir.join("td.binlog").exists());
+    }
+
+    #[test]
+    fn test_binlog_persists() {
+        let dir = tempdir().unwrap();
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            false,
+        );
+
+        let mut tddb = TdDb::open(params.clone()).unwrap();
+        let binlog = tddb.get_binlog_mut().unwrap();
+        let id = binlog
+            .add(rustgram_logevent::HandlerType::SendMessage, &b"pending"[..])
+            .unwrap();
+        tddb.close().unwrap();
+        assert!(tddb.get_binlog().is_none());
+
+        let tddb = TdDb::open(params.clone()).unwrap();
+        let binlog = tddb.get_binlog().unwrap();
+        assert_eq!(binlog.get(id).unwrap().data(), b"pending");
+        assert_eq!(binlog.path(), TdDb::binlog_path(&params));
+    }
+
+    #[test]
+    fn test_binlog_path_test_dc() {
+        let params = TdDbParameters::new("/db".to_string(), "/files".to_string(), true, false);
+        assert_eq!(
+            TdDb::binlog_path(&params),
+            Path::new("/db").join("td_test.binlog")
+        );
     }
 
     #[test]
@@ -0,0 +1,176 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Database encryption key.
+
+use std::fmt;
+use std::fmt::Write;
+
+use sha2::{Digest, Sha256};
+
+/// Size of a raw SQLCipher key.
+const SQLCIPHER_RAW_KEY_SIZE: usize = 32;
+
+/// Key used to encrypt the local databases.
+///
+/// # TDLib Correspondence
+///
+/// Corresponds to TDLib's `DbKey` class in `tddb/td/db/DbKey.h`.
+///
+/// # Example
+///
+/// ```rust
+/// use rustgram_td_db::DbKey;
+///
+/// let key = DbKey::password("secret");
+/// assert!(!key.is_empty());
+/// assert!(!key.is_raw_key());
+/// assert_eq!(key.data(), b"secret");
+///
+/// assert!(DbKey::empty().is_empty());
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub enum DbKey {
+    /// No encryption.
+    #[default]
+    Empty,
+    /// Key derived from a user password.
+    Password(Vec<u8>),
+    /// Key material used as is, without slow key derivation.
+    RawKey(Vec<u8>),
+}
+
+impl DbKey {
+    /// Creates an empty key, meaning the database is not encrypted.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self::Empty
+    }
+
+    /// Creates a key derived from a password.
+    #[must_use]
+    pub fn password(password: impl Into<Vec<u8>>) -> Self {
+        Self::Password(password.into())
+    }
+
+    /// Creates a raw key.
+    #[must_use]
+    pub fn raw_key(key: impl Into<Vec<u8>>) -> Self {
+        Self::RawKey(key.into())
+    }
+
+    /// Returns true if this is the empty key.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty)
+    }
+
+    /// Returns true if this is a raw key.
+    #[must_use]
+    pub const fn is_raw_key(&self) -> bool {
+        matches!(self, Self::RawKey(_))
+    }
+
+    /// Returns the key material.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::Empty => &[],
+            Self::Password(data) | Self::RawKey(data) => data,
+        }
+    }
+
+    /// Returns the key in the form accepted by SQLCipher's `PRAGMA key`, or
+    /// `None` for the empty key.
+    ///
+    /// A password is passed as a passphrase, which SQLCipher stretches with
+    /// PBKDF2-HMAC-SHA512 and a random per-database salt; passwords that are
+    /// not valid UTF-8 are hex-encoded first. A raw key is used directly as
+    /// the 256-bit database key, after hashing with SHA-256 if it has another
+    /// size. The result contains the key material and must not be logged.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_td_db::DbKey;
+    ///
+    /// assert_eq!(DbKey::empty().sqlcipher_key(), None);
+    /// assert_eq!(DbKey::password("secret").sqlcipher_key().as_deref(), Some("secret"));
+    ///
+    /// let raw = DbKey::raw_key(vec![0xab; 32]).sqlcipher_key().unwrap();
+    /// assert_eq!(raw, format!("x'{}'", "ab".repeat(32)));
+    /// ```
+    #[must_use]
+    pub fn sqlcipher_key(&self) -> Option<String> {
+        match self {
+            Self::Empty => None,
+            Self::Password(password) => Some(match std::str::from_utf8(password) {
+                Ok(password) => password.to_string(),
+                Err(_) => hex(password),
+            }),
+            Self::RawKey(key) if key.len() == SQLCIPHER_RAW_KEY_SIZE => {
+                Some(format!("x'{}'", hex(key)))
+            }
+            Self::RawKey(key) => Some(format!("x'{}'", hex(&Sha256::digest(key)))),
+        }
+    }
+}
+
+/// Encodes bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+impl fmt::Debug for DbKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the key material
+        match self {
+            Self::Empty => write!(f, "DbKey::Empty"),
+            Self::Password(_) => write!(f, "DbKey::Password(..)"),
+            Self::RawKey(_) => write!(f, "DbKey::RawKey(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds() {
+        assert!(DbKey::default().is_empty());
+        assert!(DbKey::empty().data().is_empty());
+        assert!(DbKey::raw_key(vec![1u8; 32]).is_raw_key());
+        assert_eq!(DbKey::raw_key(vec![1u8; 32]).data(), &[1u8; 32]);
+        assert!(!DbKey::password("pass").is_raw_key());
+    }
+
+    #[test]
+    fn test_sqlcipher_key() {
+        assert_eq!(DbKey::empty().sqlcipher_key(), None);
+        assert_eq!(
+            DbKey::password(vec![0xff, 0x00]).sqlcipher_key().as_deref(),
+            Some("ff00")
+        );
+
+        let short = DbKey::raw_key(vec![1u8; 16]).sqlcipher_key().unwrap();
+        assert!(short.starts_with("x'") && short.ends_with('\''));
+        assert_eq!(short.len(), 2 + 64 + 1);
+        assert_ne!(
+            short,
+            DbKey::raw_key(vec![1u8; 17]).sqlcipher_key().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_debug_hides_key() {
+        let debug = format!("{:?}", DbKey::password("hunter2"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
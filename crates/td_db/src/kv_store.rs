@@ -7,11 +7,13 @@
 //! This module provides a simple SQLite-based key-value store for storing
 //! application settings, notification settings, privacy settings, theme settings, etc.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use bytes::Bytes;
+use rustgram_storage::connection::{change_database_key, open_connection};
+use rustgram_storage::error::StorageError;
 
-use crate::TdDbParameters;
+use crate::{DbKey, TdDbParameters};
 
 /// Error type for key-value store operations.
 pub type KvResult<T> = Result<T, KvError>;
@@ -34,6 +36,20 @@ pub enum KvError {
     /// Invalid value type for the operation.
     #[error("Invalid value type: {0}")]
     InvalidType(String),
+
+    /// Wrong encryption key, or encryption is not available.
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+}
+
+impl From<StorageError> for KvError {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::ConnectionError(e) => Self::DatabaseError(e),
+            StorageError::IoError(e) => Self::IoError(e),
+            e => Self::Encryption(e.to_string()),
+        }
+    }
 }
 
 /// Key-value store for application settings.
@@ -76,6 +92,10 @@ pub enum KvError {
 pub struct KeyValueStore {
     /// Database connection.
     conn: rusqlite::Connection,
+    /// Path of the database file.
+    path: PathBuf,
+    /// Current encryption key.
+    db_key: DbKey,
 }
 
 impl KeyValueStore {
@@ -107,6 +127,18 @@ impl KeyValueStore {
     /// # }
     /// ```
     pub fn open(params: &TdDbParameters) -> KvResult<Self> {
+        Self::open_with_key(params, &DbKey::empty())
+    }
+
+    /// Opens a key-value store encrypted with the given key.
+    ///
+    /// An empty key opens a plain text store.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KvError::Encryption`] if the key is wrong, or another error
+    /// if the database can't be opened.
+    pub fn open_with_key(params: &TdDbParameters, db_key: &DbKey) -> KvResult<Self> {
         let db_path = Path::new(params.database_directory()).join("kv_store.db");
 
         // Create parent directory if it doesn't exist
@@ -114,7 +146,7 @@ impl KeyValueStore {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = rusqlite::Connection::open(&db_path)?;
+        let conn = open_connection(&db_path, db_key.sqlcipher_key().as_deref())?;
 
         // Enable WAL mode for better concurrency
         conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")?;
@@ -128,7 +160,54 @@ impl KeyValueStore {
             [],
         )?;
 
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            path: db_path,
+            db_key: db_key.clone(),
+        })
+    }
+
+    /// Re-encrypts the store with a new key; an empty key decrypts it.
+    ///
+    /// On failure the store keeps its previous key, unless the file was
+    /// already replaced, in which case it uses the new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be rewritten or reopened. If
+    /// it can't be reopened with either key, every later operation fails
+    /// until the store is opened again.
+    pub fn change_key(&mut self, db_key: &DbKey) -> KvResult<()> {
+        let old_key = self.db_key.sqlcipher_key();
+        let new_key = db_key.sqlcipher_key();
+
+        // The file is replaced, so the connection must be closed meanwhile.
+        // The placeholder has no tables and rejects writes, so nothing is
+        // silently lost if the file can't be reopened
+        let placeholder = rusqlite::Connection::open_in_memory()?;
+        placeholder.pragma_update(None, "query_only", true)?;
+        drop(std::mem::replace(&mut self.conn, placeholder));
+
+        let result = change_database_key(&self.path, old_key.as_deref(), new_key.as_deref());
+
+        // A failed change may still have replaced the file, so the other key
+        // is tried as well
+        let (first_key, second_key) = if result.is_ok() {
+            (db_key.clone(), self.db_key.clone())
+        } else {
+            (self.db_key.clone(), db_key.clone())
+        };
+        let (conn, key) = match open_checked(&self.path, &first_key) {
+            Ok(conn) => (conn, first_key),
+            Err(e) => (
+                open_checked(&self.path, &second_key).map_err(|_| e)?,
+                second_key,
+            ),
+        };
+        self.conn = conn;
+        self.db_key = key;
+        result?;
+        Ok(())
     }
 
     /// Gets a value by key.
@@ -555,6 +634,17 @@ impl KeyValueStore {
     }
 }
 
+/// Opens the store file, checking that it is readable with the key.
+///
+/// A plain text connection to an encrypted file only fails on first read.
+fn open_checked(path: &Path, db_key: &DbKey) -> KvResult<rusqlite::Connection> {
+    let conn = open_connection(path, db_key.sqlcipher_key().as_deref())?;
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+    Ok(conn)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -684,6 +774,91 @@ mod tests {
         assert!(matches!(result, Err(KvError::KeyNotFound(_))));
     }
 
+    #[test]
+    #[cfg(feature = "sqlcipher")]
+    fn test_change_key() {
+        let (mut store, dir) = create_test_store();
+        store.set_string("theme", "dark").unwrap();
+
+        let key = DbKey::password("secret");
+        store.change_key(&key).unwrap();
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        store.set_string("language", "en").unwrap();
+        drop(store);
+
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        );
+        assert!(KeyValueStore::open(&params).is_err());
+        assert!(matches!(
+            KeyValueStore::open_with_key(&params, &DbKey::password("wrong")),
+            Err(KvError::Encryption(_))
+        ));
+        let mut store = KeyValueStore::open_with_key(&params, &key).unwrap();
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        assert_eq!(store.get_string("language").unwrap(), "en");
+
+        store.change_key(&DbKey::empty()).unwrap();
+        drop(store);
+        let store = KeyValueStore::open(&params).unwrap();
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+    }
+
+    #[test]
+    fn test_change_key_failure_keeps_store() {
+        let (mut store, dir) = create_test_store();
+        store.set_string("theme", "dark").unwrap();
+
+        // The exported file can't be created
+        std::fs::create_dir(dir.path().join("kv_store.db.rekey")).unwrap();
+        assert!(store.change_key(&DbKey::password("secret")).is_err());
+
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        store.set_string("language", "en").unwrap();
+        drop(store);
+
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        );
+        let store = KeyValueStore::open(&params).unwrap();
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        assert_eq!(store.get_string("language").unwrap(), "en");
+    }
+
+    #[test]
+    #[cfg(feature = "sqlcipher")]
+    fn test_change_key_failed_reopen() {
+        let (mut store, dir) = create_test_store();
+        store.set_string("theme", "dark").unwrap();
+
+        let params = TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        );
+        let mut stale = KeyValueStore::open(&params).unwrap();
+        store.change_key(&DbKey::password("secret")).unwrap();
+
+        // The file is encrypted with a key the stale store doesn't know
+        assert!(stale.change_key(&DbKey::password("other")).is_err());
+        assert!(stale.get_string("theme").is_err());
+        assert!(stale.set_string("language", "en").is_err());
+        drop(stale);
+
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        drop(store);
+        let store = KeyValueStore::open_with_key(&params, &DbKey::password("secret")).unwrap();
+        assert_eq!(store.get_string("theme").unwrap(), "dark");
+        assert!(store.get_string("language").is_err());
+    }
+
     #[test]
     fn test_list_keys() {
         let (store, _dir) = create_test_store();
@@ -15,13 +15,18 @@
 //! ## Overview
 //!
 //! This module provides a complete implementation for TDLib's database interface,
-//! including SQLite databases, binlog, and key-value storage.
+//! including SQLite databases, binlog, and key-value storage. All of them can
+//! be encrypted at rest with a [`DbKey`].
 //!
 //! ## Components
 //!
 //! - [`TdDb`] - Central coordinator for all databases
 //! - [`TdDbParameters`] - Configuration for database initialization
 //! - [`KeyValueStore`] - Key-value store for settings and preferences
+//! - [`ChannelPtsStore`] - Last applied pts of every channel
+//! - [`SessionStore`] - Saved authorization of the client
+//! - [`Binlog`] - Append-only log of pending log events
+//! - [`DbKey`] - Database encryption key
 //!
 //! ## Example
 //!
@@ -49,8 +54,12 @@
 //! # }
 //! ```
 
+pub mod binlog;
+pub mod channel_pts;
 pub mod coordinator;
+pub mod db_key;
 pub mod kv_store;
+pub mod session;
 
 use std::fmt;
 
@@ -58,8 +67,12 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 
 // Re-export the main types
+pub use binlog::{Binlog, BinlogError, BinlogEvent, BinlogHandlers, BinlogResult};
+pub use channel_pts::ChannelPtsStore;
 pub use coordinator::TdDb;
+pub use db_key::DbKey;
 pub use kv_store::{KeyValueStore, KvError};
+pub use session::{AuthSession, SessionStore};
 
 /// Parameters for opening TDLib database.
 ///
@@ -0,0 +1,326 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Persistent authorization session.
+//!
+//! After a successful login the main DC, the identifier of the logged in
+//! user and the permanent auth key of the main DC are stored here, so that
+//! the next start can reuse the authorization instead of logging in again.
+//! The connection statistics of the DC options and the network usage
+//! statistics are kept alongside, so that they survive restarts.
+
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::kv_store::{KeyValueStore, KvError, KvResult};
+use crate::{DbKey, TdDbParameters};
+
+/// Key of the main DC identifier.
+const MAIN_DC_ID_KEY: &str = "auth#main_dc_id";
+
+/// Key of the identifier of the logged in user.
+const USER_ID_KEY: &str = "auth#user_id";
+
+/// Key of the auth key of the main DC.
+const AUTH_KEY_KEY: &str = "auth#auth_key";
+
+/// Key of the identifier of the auth key of the main DC.
+const AUTH_KEY_ID_KEY: &str = "auth#auth_key_id";
+
+/// Key of the serialized connection statistics of the DC options.
+const DC_OPTION_STATS_KEY: &str = "net#dc_option_stats";
+
+/// Key of the serialized network usage statistics.
+const NET_STATS_KEY: &str = "net#net_stats";
+
+/// A saved authorization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthSession {
+    /// Identifier of the main DC.
+    pub main_dc_id: i32,
+    /// Identifier of the logged in user.
+    pub user_id: i64,
+    /// Permanent auth key of the main DC and its identifier, if known.
+    pub auth_key: Option<(u64, Vec<u8>)>,
+}
+
+/// Store of the authorization session.
+///
+/// The session lives in the [`KeyValueStore`] under `auth#` keys, so it is
+/// encrypted together with the rest of the database when a [`DbKey`] is
+/// used.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustgram_td_db::{AuthSession, SessionStore, TdDbParameters};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let params = TdDbParameters::new(
+///     "/path/to/db".to_string(),
+///     "/path/to/files".to_string(),
+///     false,
+///     true
+/// );
+///
+/// let store = SessionStore::open(&params)?;
+/// store.save(&AuthSession {
+///     main_dc_id: 2,
+///     user_id: 123456,
+///     auth_key: None,
+/// })?;
+/// assert_eq!(store.load()?.map(|session| session.user_id), Some(123456));
+/// # Ok(())
+/// # }
+/// ```
+pub struct SessionStore {
+    /// Underlying key-value store.
+    store: Mutex<KeyValueStore>,
+}
+
+impl SessionStore {
+    /// Opens the store in the database directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key-value database can't be opened.
+    pub fn open(params: &TdDbParameters) -> KvResult<Self> {
+        KeyValueStore::open(params).map(Self::new)
+    }
+
+    /// Opens the store in a database directory encrypted with the given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is wrong or the database can't be opened.
+    pub fn open_with_key(params: &TdDbParameters, db_key: &DbKey) -> KvResult<Self> {
+        KeyValueStore::open_with_key(params, db_key).map(Self::new)
+    }
+
+    /// Creates a store on top of an open key-value store.
+    #[must_use]
+    pub fn new(store: KeyValueStore) -> Self {
+        Self {
+            store: Mutex::new(store),
+        }
+    }
+
+    /// Returns the saved session, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be read or holds a malformed
+    /// value.
+    pub fn load(&self) -> KvResult<Option<AuthSession>> {
+        let store = self.lock();
+        let main_dc_id = match store.get_i64(MAIN_DC_ID_KEY) {
+            Ok(dc_id) => i32::try_from(dc_id)
+                .map_err(|_| KvError::InvalidType(format!("DC {dc_id} is out of range")))?,
+            Err(KvError::KeyNotFound(_)) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let user_id = match store.get_i64(USER_ID_KEY) {
+            Ok(user_id) => user_id,
+            Err(KvError::KeyNotFound(_)) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let auth_key = match (store.get(AUTH_KEY_KEY)?, store.get_i64(AUTH_KEY_ID_KEY)) {
+            (Some(key), Ok(id)) => Some((id as u64, key.to_vec())),
+            (None, _) | (_, Err(KvError::KeyNotFound(_))) => None,
+            (_, Err(error)) => return Err(error),
+        };
+
+        Ok(Some(AuthSession {
+            main_dc_id,
+            user_id,
+            auth_key,
+        }))
+    }
+
+    /// Saves a session, replacing the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn save(&self, session: &AuthSession) -> KvResult<()> {
+        let store = self.lock();
+        match &session.auth_key {
+            Some((id, key)) => {
+                store.set(AUTH_KEY_KEY, Bytes::copy_from_slice(key))?;
+                store.set_i64(AUTH_KEY_ID_KEY, *id as i64)?;
+            }
+            None => {
+                store.delete(AUTH_KEY_KEY)?;
+                store.delete(AUTH_KEY_ID_KEY)?;
+            }
+        }
+        store.set_i64(USER_ID_KEY, session.user_id)?;
+        // Written last, so that an interrupted save leaves no session behind
+        store.set_i64(MAIN_DC_ID_KEY, i64::from(session.main_dc_id))
+    }
+
+    /// Forgets the session, e.g. after logging out.
+    ///
+    /// Returns true if a session was deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn clear(&self) -> KvResult<bool> {
+        let store = self.lock();
+        let existed = store.delete(MAIN_DC_ID_KEY)?;
+        store.delete(USER_ID_KEY)?;
+        store.delete(AUTH_KEY_KEY)?;
+        store.delete(AUTH_KEY_ID_KEY)?;
+        Ok(existed)
+    }
+
+    /// Returns the saved connection statistics of the DC options, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be read.
+    pub fn load_dc_option_stats(&self) -> KvResult<Option<Bytes>> {
+        self.lock().get(DC_OPTION_STATS_KEY)
+    }
+
+    /// Saves the serialized connection statistics of the DC options.
+    ///
+    /// The statistics aren't part of the session and survive [`clear`](Self::clear).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn save_dc_option_stats(&self, stats: &[u8]) -> KvResult<()> {
+        self.lock()
+            .set(DC_OPTION_STATS_KEY, Bytes::copy_from_slice(stats))
+    }
+
+    /// Returns the saved network usage statistics, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be read.
+    pub fn load_net_stats(&self) -> KvResult<Option<Bytes>> {
+        self.lock().get(NET_STATS_KEY)
+    }
+
+    /// Saves the serialized network usage statistics.
+    ///
+    /// Like the DC option statistics, they survive [`clear`](Self::clear).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be written.
+    pub fn save_net_stats(&self, stats: &[u8]) -> KvResult<()> {
+        self.lock()
+            .set(NET_STATS_KEY, Bytes::copy_from_slice(stats))
+    }
+
+    /// Locks the key-value store, ignoring poisoning since every operation
+    /// leaves the store consistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, KeyValueStore> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn params(dir: &tempfile::TempDir) -> TdDbParameters {
+        TdDbParameters::new(
+            dir.path().to_str().unwrap().to_string(),
+            "/files".to_string(),
+            false,
+            true,
+        )
+    }
+
+    fn session() -> AuthSession {
+        AuthSession {
+            main_dc_id: 4,
+            user_id: 777,
+            auth_key: Some((0x1122_3344_5566_7788, vec![9; 256])),
+        }
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn test_save_and_reopen() {
+        let dir = tempdir().unwrap();
+        SessionStore::open(&params(&dir))
+            .unwrap()
+            .save(&session())
+            .unwrap();
+
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        assert_eq!(store.load().unwrap(), Some(session()));
+    }
+
+    #[test]
+    fn test_save_without_auth_key() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        store.save(&session()).unwrap();
+
+        let without_key = AuthSession {
+            auth_key: None,
+            ..session()
+        };
+        store.save(&without_key).unwrap();
+        assert_eq!(store.load().unwrap(), Some(without_key));
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        store.save(&session()).unwrap();
+
+        assert!(store.clear().unwrap());
+        assert!(!store.clear().unwrap());
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn test_dc_option_stats() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        assert_eq!(store.load_dc_option_stats().unwrap(), None);
+
+        store.save_dc_option_stats(b"[]").unwrap();
+        store.save(&session()).unwrap();
+        store.clear().unwrap();
+        drop(store);
+
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        assert_eq!(
+            store.load_dc_option_stats().unwrap(),
+            Some(Bytes::from_static(b"[]"))
+        );
+    }
+
+    #[test]
+    fn test_net_stats() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::open(&params(&dir)).unwrap();
+        assert_eq!(store.load_net_stats().unwrap(), None);
+
+        store.save_net_stats(b"{}").unwrap();
+        store.save_dc_option_stats(b"[]").unwrap();
+        assert_eq!(
+            store.load_net_stats().unwrap(),
+            Some(Bytes::from_static(b"{}"))
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Crash recovery tests for the binlog.
+//!
+//! These tests simulate torn writes and on-disk corruption by editing the
+//! binlog file directly, and verify that reopening keeps every intact record,
+//! skips damage in the middle, cuts off the damaged tail and leaves the binlog
+//! writable.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use rustgram_logevent::HandlerType;
+use rustgram_td_db::binlog::MIN_EVENT_SIZE;
+use rustgram_td_db::{Binlog, DbKey};
+
+/// Writes three events with 8-byte payloads and returns the binlog file size.
+fn write_three_events(path: &Path, db_key: &DbKey) -> u64 {
+    let mut binlog = Binlog::open(path, db_key.clone()).unwrap();
+    for payload in [b"event #1", b"event #2", b"event #3"] {
+        binlog.add(HandlerType::SendMessage, &payload[..]).unwrap();
+    }
+    binlog.sync().unwrap();
+    binlog.file_size()
+}
+
+/// Size of one record written by [`write_three_events`].
+const RECORD_SIZE: u64 = (MIN_EVENT_SIZE + 8) as u64;
+
+fn truncate(path: &Path, len: u64) {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_len(len)
+        .unwrap();
+}
+
+fn flip_byte(path: &Path, offset: u64) {
+    let mut data = fs::read(path).unwrap();
+    data[offset as usize] ^= 0x55;
+    fs::write(path, data).unwrap();
+}
+
+fn payloads(binlog: &Binlog) -> Vec<Vec<u8>> {
+    binlog.events().map(|event| event.data().to_vec()).collect()
+}
+
+// ============================================================================
+// Truncated Writes
+// ============================================================================
+
+#[test]
+fn test_binlog_truncated_last_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+
+    // Every possible cut inside the last record loses exactly that record
+    for cut in 1..RECORD_SIZE {
+        let torn = dir.path().join(format!("torn-{cut}.binlog"));
+        fs::copy(&path, &torn).unwrap();
+        truncate(&torn, full_size - cut);
+
+        let binlog = Binlog::open(&torn, DbKey::empty()).unwrap();
+        assert_eq!(
+            payloads(&binlog),
+            vec![b"event #1".to_vec(), b"event #2".to_vec()]
+        );
+        assert_eq!(binlog.discarded_tail_size(), RECORD_SIZE - cut);
+        assert_eq!(fs::metadata(&torn).unwrap().len(), full_size - RECORD_SIZE);
+    }
+}
+
+#[test]
+fn test_binlog_writable_after_truncation() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+    truncate(&path, full_size - 5);
+
+    {
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        // The id of the lost event is handed out again
+        assert_eq!(
+            binlog
+                .add(HandlerType::SendMessage, &b"event #4"[..])
+                .unwrap(),
+            3
+        );
+    }
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.discarded_tail_size(), 0);
+    assert_eq!(
+        payloads(&binlog),
+        vec![
+            b"event #1".to_vec(),
+            b"event #2".to_vec(),
+            b"event #4".to_vec()
+        ]
+    );
+}
+
+#[test]
+fn test_binlog_torn_erase_keeps_event() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let size_before_erase = {
+        let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+        binlog
+            .add(HandlerType::DeleteMessage, &b"pending"[..])
+            .unwrap();
+        let size = binlog.file_size();
+        binlog.erase(1).unwrap();
+        size
+    };
+
+    // The erase record never fully reached the disk
+    truncate(&path, size_before_erase + 10);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.get(1).unwrap().data(), b"pending");
+}
+
+#[test]
+fn test_binlog_truncated_to_partial_size_field() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    write_three_events(&path, &DbKey::empty());
+    truncate(&path, 2);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert!(binlog.is_empty());
+    assert_eq!(binlog.file_size(), 0);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+}
+
+// ============================================================================
+// Corrupt Tails
+// ============================================================================
+
+#[test]
+fn test_binlog_corrupt_last_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+    flip_byte(&path, full_size - 6);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.len(), 2);
+    assert_eq!(binlog.discarded_tail_size(), RECORD_SIZE);
+}
+
+#[test]
+fn test_binlog_garbage_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0xff; 100]).unwrap();
+    drop(file);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.len(), 3);
+    assert_eq!(binlog.discarded_tail_size(), 100);
+    assert_eq!(fs::metadata(&path).unwrap().len(), full_size);
+}
+
+#[test]
+fn test_binlog_garbage_zero_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+
+    // A crash may leave the file extended with zeroes
+    truncate(&path, full_size + 4096);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.len(), 3);
+    assert_eq!(binlog.discarded_tail_size(), 4096);
+    assert_eq!(fs::metadata(&path).unwrap().len(), full_size);
+}
+
+// ============================================================================
+// Damage in the Middle
+// ============================================================================
+
+#[test]
+fn test_binlog_corrupt_record_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let full_size = write_three_events(&path, &DbKey::empty());
+
+    flip_byte(&path, RECORD_SIZE + 30);
+
+    let mut binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(
+        payloads(&binlog),
+        vec![b"event #1".to_vec(), b"event #3".to_vec()]
+    );
+    assert_eq!(binlog.skipped_size(), RECORD_SIZE);
+    assert_eq!(binlog.discarded_tail_size(), 0);
+    assert_eq!(fs::metadata(&path).unwrap().len(), full_size);
+
+    // The id of the skipped event isn't reused
+    let id = binlog
+        .add(HandlerType::SendMessage, &b"event #4"[..])
+        .unwrap();
+    assert_eq!(id, 4);
+
+    // Compaction drops the damaged record
+    binlog.compact().unwrap();
+    drop(binlog);
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.skipped_size(), 0);
+    assert_eq!(binlog.len(), 3);
+}
+
+#[test]
+fn test_binlog_corrupt_size_field() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    write_three_events(&path, &DbKey::empty());
+
+    // Claim a huge size for the second record
+    flip_byte(&path, RECORD_SIZE + 3);
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(
+        payloads(&binlog),
+        vec![b"event #1".to_vec(), b"event #3".to_vec()]
+    );
+    assert_eq!(binlog.skipped_size(), RECORD_SIZE);
+}
+
+// ============================================================================
+// Encrypted Binlog
+// ============================================================================
+
+#[test]
+fn test_encrypted_binlog_truncated_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let key = DbKey::raw_key(vec![9u8; 32]);
+    let full_size = write_three_events(&path, &key);
+    truncate(&path, full_size - 7);
+
+    {
+        let mut binlog = Binlog::open(&path, key.clone()).unwrap();
+        assert_eq!(binlog.len(), 2);
+        assert_eq!(binlog.discarded_tail_size(), RECORD_SIZE - 7);
+        binlog
+            .add(HandlerType::SendMessage, &b"event #4"[..])
+            .unwrap();
+    }
+
+    // The appended record continues the cipher stream where the cut was made
+    let binlog = Binlog::open(&path, key).unwrap();
+    assert_eq!(binlog.discarded_tail_size(), 0);
+    assert_eq!(
+        payloads(&binlog),
+        vec![
+            b"event #1".to_vec(),
+            b"event #2".to_vec(),
+            b"event #4".to_vec()
+        ]
+    );
+}
+
+#[test]
+fn test_encrypted_binlog_corrupt_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    let key = DbKey::password("correct horse");
+    let full_size = write_three_events(&path, &key);
+    flip_byte(&path, full_size - 1);
+
+    let binlog = Binlog::open(&path, key).unwrap();
+    assert_eq!(binlog.len(), 2);
+}
+
+// ============================================================================
+// Interrupted Compaction
+// ============================================================================
+
+#[test]
+fn test_binlog_leftover_compaction_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("td.binlog");
+    write_three_events(&path, &DbKey::empty());
+
+    let temp_path = dir.path().join("td.binlog.new");
+    fs::write(&temp_path, b"half-written compaction").unwrap();
+
+    let binlog = Binlog::open(&path, DbKey::empty()).unwrap();
+    assert_eq!(binlog.len(), 3);
+    assert!(!temp_path.exists());
+}
@@ -0,0 +1,246 @@
+//! Encryption at rest tests for TdDb.
+//!
+//! These tests inspect the database files directly to verify that, with a
+//! database encryption key, nothing written by the client is readable
+//! without the key, and that changing the key rewrites or rolls back every
+//! database consistently.
+
+#![cfg(feature = "sqlcipher")]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::path::Path;
+
+use bytes::Bytes;
+use rustgram_logevent::HandlerType;
+use rustgram_storage::message::sync::AddMessageParams;
+use rustgram_storage::StorageError;
+use rustgram_td_db::{DbKey, KeyValueStore, KvError, TdDb, TdDbParameters};
+use tempfile::TempDir;
+
+const SECRET_TEXT: &str = "very secret message text";
+const DIALOG_ID: i64 = 12345;
+
+fn params(dir: &TempDir) -> TdDbParameters {
+    TdDbParameters::new(
+        dir.path().to_str().unwrap().to_string(),
+        "/files".to_string(),
+        false,
+        true,
+    )
+}
+
+fn add_message(tddb: &TdDb) {
+    tddb.get_message_db()
+        .unwrap()
+        .sync()
+        .add_message(
+            AddMessageParams::new(DIALOG_ID, 1, 67890, 100, Bytes::from("content"))
+                .with_text(SECRET_TEXT.to_string()),
+        )
+        .unwrap();
+}
+
+fn message_text(tddb: &TdDb) -> Option<String> {
+    tddb.get_message_db()
+        .unwrap()
+        .sync()
+        .get_message(DIALOG_ID, 1)
+        .unwrap()
+        .text
+}
+
+/// Returns whether a file holds plain SQLite data or the secret text.
+fn is_readable(path: &Path) -> bool {
+    let data = fs::read(path).unwrap();
+    data.starts_with(b"SQLite format 3")
+        || data
+            .windows(SECRET_TEXT.len())
+            .any(|window| window == SECRET_TEXT.as_bytes())
+}
+
+fn assert_wrong_key(result: Result<TdDb, StorageError>) {
+    assert!(matches!(result, Err(StorageError::CryptoError(_))));
+}
+
+#[test]
+fn test_encrypted_files_are_unreadable_without_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = DbKey::password("correct horse battery staple");
+
+    let mut tddb = TdDb::open_with_key(params(&dir), key.clone()).unwrap();
+    assert!(tddb.is_encrypted());
+    add_message(&tddb);
+    tddb.get_binlog_mut()
+        .unwrap()
+        .add(HandlerType::SendMessage, SECRET_TEXT.as_bytes())
+        .unwrap();
+    tddb.close().unwrap();
+
+    for name in [
+        "messages.db",
+        "users.db",
+        "chats.db",
+        "files.db",
+        "td.binlog",
+    ] {
+        let path = dir.path().join(name);
+        assert!(!is_readable(&path), "{name} is readable");
+    }
+
+    // Plain SQLite can't read the file
+    let conn = rusqlite::Connection::open(dir.path().join("messages.db")).unwrap();
+    assert!(conn
+        .query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .is_err());
+    drop(conn);
+
+    // Without a key the file is not recognized as a database at all
+    assert!(TdDb::open(params(&dir)).is_err());
+    assert_wrong_key(TdDb::open_with_key(params(&dir), DbKey::password("wrong")));
+
+    let tddb = TdDb::open_with_key(params(&dir), key).unwrap();
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+}
+
+#[test]
+fn test_raw_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = DbKey::raw_key(vec![7u8; 32]);
+
+    let tddb = TdDb::open_with_key(params(&dir), key.clone()).unwrap();
+    add_message(&tddb);
+    drop(tddb);
+
+    assert!(!is_readable(&dir.path().join("messages.db")));
+    assert_wrong_key(TdDb::open_with_key(
+        params(&dir),
+        DbKey::raw_key(vec![8u8; 32]),
+    ));
+    let tddb = TdDb::open_with_key(params(&dir), key).unwrap();
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+}
+
+#[test]
+fn test_set_database_encryption_key() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // Encrypt a plain text database
+    let mut tddb = TdDb::open(params(&dir)).unwrap();
+    add_message(&tddb);
+    tddb.get_kv_store()
+        .unwrap()
+        .set_string("draft", SECRET_TEXT)
+        .unwrap();
+    let event_id = tddb
+        .get_binlog_mut()
+        .unwrap()
+        .add(HandlerType::SendMessage, SECRET_TEXT.as_bytes())
+        .unwrap();
+    assert!(is_readable(&dir.path().join("messages.db")));
+
+    let first_key = DbKey::password("first");
+    tddb.set_database_encryption_key(first_key.clone()).unwrap();
+    assert!(tddb.is_encrypted());
+    // The open databases keep working with the new key
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+    tddb.close().unwrap();
+    assert!(!is_readable(&dir.path().join("messages.db")));
+    assert!(!is_readable(&dir.path().join("td.binlog")));
+    assert!(!is_readable(&dir.path().join("kv_store.db")));
+    assert!(!dir.path().join("messages.db.old").exists());
+
+    // Change the key
+    let mut tddb = TdDb::open_with_key(params(&dir), first_key.clone()).unwrap();
+    let second_key = DbKey::password("second");
+    tddb.set_database_encryption_key(second_key.clone())
+        .unwrap();
+    drop(tddb);
+    assert_wrong_key(TdDb::open_with_key(params(&dir), first_key));
+
+    // Decrypt
+    let mut tddb = TdDb::open_with_key(params(&dir), second_key).unwrap();
+    tddb.set_database_encryption_key(DbKey::empty()).unwrap();
+    drop(tddb);
+    let tddb = TdDb::open(params(&dir)).unwrap();
+    assert!(!tddb.is_encrypted());
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+    assert_eq!(
+        tddb.get_kv_store().unwrap().get_string("draft").unwrap(),
+        SECRET_TEXT
+    );
+    assert_eq!(
+        tddb.get_binlog().unwrap().get(event_id).unwrap().data(),
+        SECRET_TEXT.as_bytes()
+    );
+}
+
+#[test]
+fn test_set_database_encryption_key_rolls_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_key = DbKey::password("old");
+
+    let mut tddb = TdDb::open_with_key(params(&dir), old_key.clone()).unwrap();
+    add_message(&tddb);
+
+    // The message database is re-keyed first; make the user database fail
+    fs::create_dir(dir.path().join("users.db.rekey")).unwrap();
+    let result = tddb.set_database_encryption_key(DbKey::password("new"));
+    assert!(result.is_err());
+    assert!(tddb.is_encrypted());
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+    drop(tddb);
+
+    fs::remove_dir(dir.path().join("users.db.rekey")).unwrap();
+    assert_wrong_key(TdDb::open_with_key(params(&dir), DbKey::password("new")));
+    let tddb = TdDb::open_with_key(params(&dir), old_key).unwrap();
+    assert_eq!(message_text(&tddb).as_deref(), Some(SECRET_TEXT));
+}
+
+#[test]
+fn test_set_database_encryption_key_rolls_back_key_value_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_key = DbKey::password("old");
+
+    let mut tddb = TdDb::open_with_key(params(&dir), old_key.clone()).unwrap();
+    tddb.get_kv_store()
+        .unwrap()
+        .set_string("draft", SECRET_TEXT)
+        .unwrap();
+
+    // The binlog is re-keyed last, after the key-value store
+    fs::create_dir(dir.path().join("td.binlog.new")).unwrap();
+    let result = tddb.set_database_encryption_key(DbKey::password("new"));
+    assert!(result.is_err());
+    assert_eq!(
+        tddb.get_kv_store().unwrap().get_string("draft").unwrap(),
+        SECRET_TEXT
+    );
+    drop(tddb);
+
+    fs::remove_dir(dir.path().join("td.binlog.new")).unwrap();
+    assert!(KeyValueStore::open_with_key(&params(&dir), &DbKey::password("new")).is_err());
+    let store = KeyValueStore::open_with_key(&params(&dir), &old_key).unwrap();
+    assert_eq!(store.get_string("draft").unwrap(), SECRET_TEXT);
+}
+
+#[test]
+fn test_key_value_store_encrypted() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = DbKey::password("kv secret");
+
+    let store = KeyValueStore::open_with_key(&params(&dir), &key).unwrap();
+    store.set_string("draft", SECRET_TEXT).unwrap();
+    drop(store);
+
+    assert!(!is_readable(&dir.path().join("kv_store.db")));
+    assert!(KeyValueStore::open(&params(&dir)).is_err());
+    assert!(matches!(
+        KeyValueStore::open_with_key(&params(&dir), &DbKey::password("wrong")),
+        Err(KvError::Encryption(_))
+    ));
+    let store = KeyValueStore::open_with_key(&params(&dir), &key).unwrap();
+    assert_eq!(store.get_string("draft").unwrap(), SECRET_TEXT);
+}
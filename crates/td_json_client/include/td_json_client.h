@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) 2024 rustgram-client contributors
+ *
+ * Licensed under MIT OR Apache-2.0
+ */
+
+/*
+ * C interface of the rustgram JSON client.
+ *
+ * Mirrors TDLib's td/telegram/td_json_client.h, so bindings written against
+ * TDLib can load this libtdjson in place of the original one.
+ *
+ * Requests and responses are UTF-8 encoded JSON objects. A request's "@extra"
+ * field is copied to its response, and every received object carries the
+ * "@client_id" of the client it belongs to.
+ *
+ * Strings returned by the receive and execute functions stay valid until the
+ * next call to any receive or execute function on the same thread.
+ */
+
+#ifndef RUSTGRAM_TD_JSON_CLIENT_H
+#define RUSTGRAM_TD_JSON_CLIENT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Returns an identifier for a new client. */
+int td_create_client_id(void);
+
+/* Sends a request to the client with the given identifier. */
+void td_send(int client_id, const char *request);
+
+/*
+ * Receives an incoming update or response from any client, waiting at most
+ * timeout seconds. Returns NULL if nothing was received.
+ */
+const char *td_receive(double timeout);
+
+/* Synchronously executes a request that can be executed synchronously. */
+const char *td_execute(const char *request);
+
+/* Creates a new client. Deprecated in TDLib in favour of td_create_client_id. */
+void *td_json_client_create(void);
+
+/* Sends a request to the client. */
+void td_json_client_send(void *client, const char *request);
+
+/*
+ * Receives an incoming update or response of the client, waiting at most
+ * timeout seconds. Returns NULL if nothing was received.
+ */
+const char *td_json_client_receive(void *client, double timeout);
+
+/* Synchronously executes a request; client may be NULL. */
+const char *td_json_client_execute(void *client, const char *request);
+
+/* Destroys the client. The pointer must not be used afterwards. */
+void td_json_client_destroy(void *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUSTGRAM_TD_JSON_CLIENT_H */
@@ -0,0 +1,583 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! Client registry behind the JSON interface.
+//!
+//! Mirrors TDLib's `ClientManager` (`td/telegram/Client.cpp`): every client
+//! gets an identifier, requests are dispatched to its [`Requests`] instance and
+//! all responses and updates are queued per client until they are received.
+//!
+//! Each queued event carries a manager-wide sequence number, so receiving from
+//! all clients at once (`td_receive`) still returns events in the order they
+//! were produced, while receiving from a single client (`td_json_client_receive`)
+//! only looks at that client's queue.
+
+use crate::{Result, TdJsonClientError};
+use rustgram_requests::Requests;
+use rustgram_synchronous_requests::SynchronousRequests;
+use rustgram_td::Td;
+use serde_json::{json, Map, Value as JsonValue};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Key echoed back from a request to its response.
+const EXTRA_KEY: &str = "@extra";
+
+/// Key identifying the client an event belongs to.
+const CLIENT_ID_KEY: &str = "@client_id";
+
+/// Error code used by TDLib for malformed requests.
+const BAD_REQUEST_CODE: i32 = 400;
+
+/// Error code used by TDLib for requests to a closing client.
+const REQUEST_ABORTED_CODE: i32 = 500;
+
+/// An event produced by a client, waiting to be received.
+#[derive(Debug)]
+struct QueuedEvent {
+    /// Manager-wide sequence number.
+    seq: u64,
+    /// The response or update object.
+    object: JsonValue,
+}
+
+/// A single TDLib client instance.
+struct Client {
+    /// Request dispatcher of the client, shared with requests in progress.
+    requests: Arc<Requests>,
+    /// Responses and updates not yet received.
+    events: VecDeque<QueuedEvent>,
+    /// Whether `close` was requested.
+    is_closed: bool,
+}
+
+impl Client {
+    /// Creates a client with a fresh `Td` instance.
+    fn new(client_id: i32) -> Self {
+        let mut td = Td::new();
+        td.set_id(u64::from(client_id.unsigned_abs()));
+        Self {
+            requests: Arc::new(Requests::new(&Arc::new(td))),
+            events: VecDeque::new(),
+            is_closed: false,
+        }
+    }
+}
+
+/// Mutable state of the manager, guarded by a single mutex.
+#[derive(Default)]
+struct ManagerState {
+    /// Live clients by identifier.
+    clients: HashMap<i32, Client>,
+    /// Identifier assigned to the next client.
+    next_client_id: i32,
+    /// Sequence number assigned to the next event.
+    next_seq: u64,
+}
+
+impl ManagerState {
+    /// Appends an event to the queue of `client_id`.
+    fn push(&mut self, client_id: i32, object: JsonValue) {
+        let seq = self.next_seq;
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            self.next_seq += 1;
+            client.events.push_back(QueuedEvent { seq, object });
+        }
+    }
+
+    /// Removes the oldest event of `client_id`, or of any client if `None`.
+    fn pop(&mut self, client_id: Option<i32>) -> Option<(i32, JsonValue)> {
+        let id = match client_id {
+            Some(id) => id,
+            None => self
+                .clients
+                .iter()
+                .filter_map(|(id, client)| client.events.front().map(|event| (event.seq, *id)))
+                .min()
+                .map(|(_, id)| id)?,
+        };
+        let event = self.clients.get_mut(&id)?.events.pop_front()?;
+        Some((id, event.object))
+    }
+}
+
+/// Registry of JSON clients.
+///
+/// The C API in [`crate::ffi`] operates on the process-wide instance returned
+/// by [`ClientManager::global`]; separate instances are useful for embedding
+/// and tests.
+///
+/// # Examples
+///
+/// ```
+/// use tdjson::ClientManager;
+///
+/// let manager = ClientManager::new();
+/// let client_id = manager.create_client_id();
+/// manager.send(client_id, r#"{"@type": "getMe", "@extra": 7}"#).unwrap();
+///
+/// // The first event is always the initial authorization state.
+/// let update = manager.receive(Some(client_id), 1.0).unwrap();
+/// assert!(update.contains("updateAuthorizationState"));
+///
+/// let response = manager.receive(Some(client_id), 1.0).unwrap();
+/// assert!(response.contains(r#""@extra":7"#));
+/// ```
+#[derive(Default)]
+pub struct ClientManager {
+    /// Clients and queues.
+    state: Mutex<ManagerState>,
+    /// Signalled whenever an event is queued.
+    event_ready: Condvar,
+}
+
+impl ClientManager {
+    /// Creates an empty manager.
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ManagerState {
+                next_client_id: 1,
+                ..ManagerState::default()
+            }),
+            event_ready: Condvar::new(),
+        }
+    }
+
+    /// Returns the process-wide manager used by the C interface.
+    pub fn global() -> &'static Self {
+        static MANAGER: OnceLock<ClientManager> = OnceLock::new();
+        MANAGER.get_or_init(Self::new)
+    }
+
+    /// Locks the state, recovering from a poisoned mutex.
+    fn lock(&self) -> MutexGuard<'_, ManagerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Creates a new client and returns its identifier.
+    ///
+    /// The client immediately queues an `updateAuthorizationState` update,
+    /// like TDLib does for a freshly created instance.
+    pub fn create_client_id(&self) -> i32 {
+        let mut state = self.lock();
+        let client_id = state.next_client_id;
+        state.next_client_id += 1;
+        state.clients.insert(client_id, Client::new(client_id));
+        state.push(
+            client_id,
+            authorization_state_update("authorizationStateWaitTdlibParameters"),
+        );
+        drop(state);
+        self.event_ready.notify_all();
+        client_id
+    }
+
+    /// Returns true if a client with this identifier exists.
+    pub fn has_client(&self, client_id: i32) -> bool {
+        self.lock().clients.contains_key(&client_id)
+    }
+
+    /// Returns the number of live clients.
+    pub fn client_count(&self) -> usize {
+        self.lock().clients.len()
+    }
+
+    /// Sends a request to a client.
+    ///
+    /// The response is queued for [`receive`](Self::receive) and carries the
+    /// request's `@extra` field. Malformed requests produce an `error` response
+    /// rather than failing the call.
+    ///
+    /// The request is processed without holding the manager lock, so a slow
+    /// request doesn't block other clients or [`receive`](Self::receive).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TdJsonClientError::InvalidClientId`] if the client doesn't exist.
+    pub fn send(&self, client_id: i32, request: &str) -> Result<()> {
+        let parsed = parse_request(request);
+        let mut state = self.lock();
+        let client = state
+            .clients
+            .get_mut(&client_id)
+            .ok_or(TdJsonClientError::InvalidClientId(client_id))?;
+
+        let events = match parsed {
+            Err(error) => vec![error],
+            Ok((request, extra)) => {
+                if client.is_closed {
+                    vec![with_extra(
+                        error_object(REQUEST_ABORTED_CODE, "Request aborted"),
+                        extra.as_ref(),
+                    )]
+                } else if request_type(&request) == Some("close") {
+                    client.is_closed = true;
+                    vec![
+                        authorization_state_update("authorizationStateClosing"),
+                        with_extra(json!({"@type": "ok"}), extra.as_ref()),
+                        authorization_state_update("authorizationStateClosed"),
+                    ]
+                } else {
+                    let requests = Arc::clone(&client.requests);
+                    drop(state);
+                    let response = requests.process_request(&request);
+                    // The client may have been destroyed meanwhile, then
+                    // the response is dropped
+                    state = self.lock();
+                    vec![with_extra(response, extra.as_ref())]
+                }
+            }
+        };
+
+        for event in events {
+            state.push(client_id, event);
+        }
+        drop(state);
+        self.event_ready.notify_all();
+        Ok(())
+    }
+
+    /// Receives the next response or update.
+    ///
+    /// With `Some(client_id)` only that client's queue is considered; with
+    /// `None` the oldest event of any client is returned. Every returned
+    /// object has `@client_id` set. Waits at most `timeout` seconds and
+    /// returns `None` if nothing arrived.
+    pub fn receive(&self, client_id: Option<i32>, timeout: f64) -> Option<String> {
+        let timeout = Duration::try_from_secs_f64(timeout).unwrap_or(Duration::ZERO);
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = self.lock();
+        loop {
+            if let Some((id, mut object)) = state.pop(client_id) {
+                if let Some(fields) = object.as_object_mut() {
+                    fields.insert(CLIENT_ID_KEY.to_string(), json!(id));
+                }
+                return Some(object.to_string());
+            }
+
+            let now = Instant::now();
+            let remaining = match deadline {
+                Some(deadline) if deadline > now => deadline - now,
+                Some(_) => return None,
+                None => Duration::MAX,
+            };
+            state = self
+                .event_ready
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Executes a synchronous request.
+    ///
+    /// Only requests listed in [`SynchronousRequests`] may be executed; any
+    /// other method yields an `error` object. The response carries the
+    /// request's `@extra` field.
+    pub fn execute(request: &str) -> String {
+        let response = match parse_request(request) {
+            Err(error) => error,
+            Ok((request, extra)) => {
+                let name = request_type(&request).unwrap_or_default();
+                let response = if SynchronousRequests::is_synchronous_request(name) {
+                    static REQUESTS: OnceLock<Requests> = OnceLock::new();
+                    REQUESTS
+                        .get_or_init(|| Requests::new(&Arc::new(Td::new())))
+                        .process_request(&request)
+                } else {
+                    error_object(
+                        BAD_REQUEST_CODE,
+                        "The method can't be executed synchronously",
+                    )
+                };
+                with_extra(response, extra.as_ref())
+            }
+        };
+        response.to_string()
+    }
+
+    /// Destroys a client, discarding its undelivered events.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TdJsonClientError::InvalidClientId`] if the client doesn't exist.
+    pub fn destroy(&self, client_id: i32) -> Result<()> {
+        self.lock()
+            .clients
+            .remove(&client_id)
+            .map(|_| ())
+            .ok_or(TdJsonClientError::InvalidClientId(client_id))
+    }
+}
+
+/// Parses a request, splitting off its `@extra` field.
+///
+/// Returns the error object to respond with if the request is not a JSON
+/// object with an `@type` field.
+fn parse_request(request: &str) -> std::result::Result<(JsonValue, Option<JsonValue>), JsonValue> {
+    let mut value: JsonValue = serde_json::from_str(request).map_err(|e| {
+        error_object(
+            BAD_REQUEST_CODE,
+            &format!("Failed to parse JSON object as TDLib request: {e}"),
+        )
+    })?;
+    let Some(fields) = value.as_object_mut() else {
+        return Err(error_object(
+            BAD_REQUEST_CODE,
+            "Failed to parse JSON object as TDLib request: expected an object",
+        ));
+    };
+    let extra = fields.remove(EXTRA_KEY);
+    if request_type(&value).is_none() {
+        return Err(with_extra(
+            error_object(
+                BAD_REQUEST_CODE,
+                "Failed to parse JSON object as TDLib request: object has no @type",
+            ),
+            extra.as_ref(),
+        ));
+    }
+    Ok((value, extra))
+}
+
+/// Returns the `@type` of a request.
+fn request_type(request: &JsonValue) -> Option<&str> {
+    request.get("@type").and_then(JsonValue::as_str)
+}
+
+/// Builds a TDLib `error` object.
+fn error_object(code: i32, message: &str) -> JsonValue {
+    json!({"@type": "error", "code": code, "message": message})
+}
+
+/// Builds an `updateAuthorizationState` update.
+fn authorization_state_update(state: &str) -> JsonValue {
+    json!({
+        "@type": "updateAuthorizationState",
+        "authorization_state": {"@type": state}
+    })
+}
+
+/// Copies the request's `@extra` into a response object.
+fn with_extra(mut response: JsonValue, extra: Option<&JsonValue>) -> JsonValue {
+    if let Some(extra) = extra {
+        if !response.is_object() {
+            response = JsonValue::Object(Map::new());
+        }
+        if let Some(fields) = response.as_object_mut() {
+            fields.insert(EXTRA_KEY.to_string(), extra.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(event: Option<String>) -> JsonValue {
+        match event {
+            Some(event) => serde_json::from_str(&event).unwrap_or(JsonValue::Null),
+            None => JsonValue::Null,
+        }
+    }
+
+    #[test]
+    fn test_create_queues_authorization_state() {
+        let manager = ClientManager::new();
+        let client_id = manager.create_client_id();
+        assert_eq!(client_id, 1);
+        assert!(manager.has_client(client_id));
+
+        let update = parse(manager.receive(Some(client_id), 0.0));
+        assert_eq!(update["@type"], "updateAuthorizationState");
+        assert_eq!(
+            update["authorization_state"]["@type"],
+            "authorizationStateWaitTdlibParameters"
+        );
+        assert_eq!(update["@client_id"], client_id);
+        assert!(manager.receive(Some(client_id), 0.0).is_none());
+    }
+
+    #[test]
+    fn test_send_echoes_extra() {
+        let manager = ClientManager::new();
+        let client_id = manager.create_client_id();
+        let _ = manager.receive(Some(client_id), 0.0);
+
+        assert!(manager
+            .send(
+                client_id,
+                r#"{"@type": "getUserId", "user_id": 5, "@extra": {"id": 1}}"#
+            )
+            .is_ok());
+        let response = parse(manager.receive(Some(client_id), 0.0));
+        assert_eq!(response["@type"], "user");
+        assert_eq!(response["id"], 5);
+        assert_eq!(response["@extra"]["id"], 1);
+    }
+
+    #[test]
+    fn test_send_invalid_json() {
+        let manager = ClientManager::new();
+        let client_id = manager.create_client_id();
+        let _ = manager.receive(Some(client_id), 0.0);
+
+        assert!(manager.send(client_id, "not json").is_ok());
+        let response = parse(manager.receive(Some(client_id), 0.0));
+        assert_eq!(response["@type"], "error");
+        assert_eq!(response["code"], 400);
+
+        assert!(manager.send(client_id, r#"{"@extra": "x"}"#).is_ok());
+        let response = parse(manager.receive(Some(client_id), 0.0));
+        assert_eq!(response["@type"], "error");
+        assert_eq!(response["@extra"], "x");
+    }
+
+    #[test]
+    fn test_send_unknown_client() {
+        let manager = ClientManager::new();
+        assert!(matches!(
+            manager.send(42, r#"{"@type": "getMe"}"#),
+            Err(TdJsonClientError::InvalidClientId(42))
+        ));
+    }
+
+    #[test]
+    fn test_receive_any_client_in_order() {
+        let manager = ClientManager::new();
+        let first = manager.create_client_id();
+        let second = manager.create_client_id();
+        assert!(manager
+            .send(second, r#"{"@type": "getMe", "@extra": 2}"#)
+            .is_ok());
+        assert!(manager
+            .send(first, r#"{"@type": "getMe", "@extra": 1}"#)
+            .is_ok());
+
+        let client_ids: Vec<JsonValue> = (0..4)
+            .map(|_| parse(manager.receive(None, 0.0))["@client_id"].clone())
+            .collect();
+        assert_eq!(
+            client_ids,
+            vec![json!(first), json!(second), json!(second), json!(first)]
+        );
+        assert!(manager.receive(None, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_receive_per_client() {
+        let manager = ClientManager::new();
+        let first = manager.create_client_id();
+        let second = manager.create_client_id();
+
+        let update = parse(manager.receive(Some(second), 0.0));
+        assert_eq!(update["@client_id"], second);
+        assert!(manager.receive(Some(second), 0.0).is_none());
+        assert_eq!(
+            parse(manager.receive(Some(first), 0.0))["@client_id"],
+            first
+        );
+    }
+
+    #[test]
+    fn test_receive_waits_for_event() {
+        let manager = Arc::new(ClientManager::new());
+        let client_id = manager.create_client_id();
+        let _ = manager.receive(Some(client_id), 0.0);
+
+        let sender = Arc::clone(&manager);
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            sender.send(client_id, r#"{"@type": "getMe"}"#)
+        });
+        let response = parse(manager.receive(Some(client_id), 5.0));
+        assert_eq!(response["@type"], "user");
+        assert!(matches!(handle.join(), Ok(Ok(()))));
+    }
+
+    #[test]
+    fn test_send_from_many_threads() {
+        let manager = Arc::new(ClientManager::new());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let manager = Arc::clone(&manager);
+                std::thread::spawn(move || {
+                    let client_id = manager.create_client_id();
+                    let _ = manager.receive(Some(client_id), 0.0);
+                    for j in 0..20 {
+                        let request = format!(r#"{{"@type": "getMe", "@extra": {}}}"#, i * 100 + j);
+                        assert!(manager.send(client_id, &request).is_ok());
+                    }
+                    (0..20)
+                        .map(|_| parse(manager.receive(Some(client_id), 5.0))["@extra"].clone())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let extras = handle.join().unwrap_or_default();
+            let expected: Vec<JsonValue> = (0..20).map(|j| json!(i * 100 + j)).collect();
+            assert_eq!(extras, expected);
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let manager = ClientManager::new();
+        let client_id = manager.create_client_id();
+        let _ = manager.receive(Some(client_id), 0.0);
+
+        assert!(manager
+            .send(client_id, r#"{"@type": "close", "@extra": 3}"#)
+            .is_ok());
+        let states: Vec<JsonValue> = (0..3)
+            .map(|_| parse(manager.receive(Some(client_id), 0.0)))
+            .collect();
+        assert_eq!(
+            states[0]["authorization_state"]["@type"],
+            "authorizationStateClosing"
+        );
+        assert_eq!(states[1]["@type"], "ok");
+        assert_eq!(states[1]["@extra"], 3);
+        assert!(states[0].get("@extra").is_none());
+        assert_eq!(
+            states[2]["authorization_state"]["@type"],
+            "authorizationStateClosed"
+        );
+
+        assert!(manager.send(client_id, r#"{"@type": "getMe"}"#).is_ok());
+        let response = parse(manager.receive(Some(client_id), 0.0));
+        assert_eq!(response["code"], 500);
+    }
+
+    #[test]
+    fn test_execute() {
+        let response: JsonValue = serde_json::from_str(&ClientManager::execute(
+            r#"{"@type": "getTextEntities", "text": "@telegram", "@extra": "e"}"#,
+        ))
+        .unwrap_or(JsonValue::Null);
+        assert_eq!(response["@type"], "textEntities");
+        assert_eq!(response["@extra"], "e");
+
+        let response: JsonValue =
+            serde_json::from_str(&ClientManager::execute(r#"{"@type": "getMe"}"#))
+                .unwrap_or(JsonValue::Null);
+        assert_eq!(response["@type"], "error");
+        assert_eq!(response["code"], 400);
+    }
+
+    #[test]
+    fn test_destroy() {
+        let manager = ClientManager::new();
+        let client_id = manager.create_client_id();
+        assert_eq!(manager.client_count(), 1);
+        assert!(manager.destroy(client_id).is_ok());
+        assert!(!manager.has_client(client_id));
+        assert!(manager.receive(None, 0.0).is_none());
+        assert!(manager.destroy(client_id).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright (c) 2024 rustgram-client contributors
+//
+// Licensed under MIT OR Apache-2.0
+
+//! C interface compatible with TDLib's `td_json_client.h`.
+//!
+//! Exposes both the original per-client functions (`td_json_client_*`) and the
+//! multi-client functions (`td_create_client_id`, `td_send`, `td_receive`,
+//! `td_execute`). All of them operate on [`ClientManager::global`].
+//!
+//! As in TDLib, a string returned by a receive or execute function stays valid
+//! until the next receive or execute call on the same thread.
+
+use crate::ClientManager;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_double, c_int, c_void, CStr, CString};
+use std::ptr;
+
+thread_local! {
+    /// Storage for the last string returned to the caller on this thread.
+    static LAST_RESULT: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque handle returned by [`td_json_client_create`].
+#[derive(Debug)]
+struct JsonClient {
+    /// Identifier of the client in the global manager.
+    client_id: i32,
+}
+
+/// Stores `result` for the caller and returns a pointer to it.
+fn store_result(result: Option<String>) -> *const c_char {
+    let result = result.and_then(|result| CString::new(result).ok());
+    LAST_RESULT.with(|last| {
+        let mut last = last.borrow_mut();
+        *last = result;
+        last.as_ref().map_or(ptr::null(), |result| result.as_ptr())
+    })
+}
+
+/// Converts a C string argument, returning `None` for null or invalid UTF-8.
+///
+/// # Safety
+///
+/// `value` must be null or point to a null-terminated string.
+unsafe fn to_str<'a>(value: *const c_char) -> Option<&'a str> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()
+}
+
+/// Returns the client behind a handle created by [`td_json_client_create`].
+///
+/// # Safety
+///
+/// `client` must be null or a live handle returned by [`td_json_client_create`].
+unsafe fn client_id(client: *mut c_void) -> Option<i32> {
+    client
+        .cast::<JsonClient>()
+        .as_ref()
+        .map(|client| client.client_id)
+}
+
+/// Creates a new client and returns an opaque handle to it.
+///
+/// TDLib reference: `td_json_client.h` (`td_json_client_create`)
+#[no_mangle]
+pub extern "C" fn td_json_client_create() -> *mut c_void {
+    let client_id = ClientManager::global().create_client_id();
+    Box::into_raw(Box::new(JsonClient { client_id })).cast()
+}
+
+/// Sends a request to a client.
+///
+/// TDLib reference: `td_json_client.h` (`td_json_client_send`)
+///
+/// # Safety
+///
+/// `client` must be a handle returned by [`td_json_client_create`] that was not
+/// destroyed, and `request` must be a null-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn td_json_client_send(client: *mut c_void, request: *const c_char) {
+    if let (Some(client_id), Some(request)) = (client_id(client), to_str(request)) {
+        let _ = ClientManager::global().send(client_id, request);
+    }
+}
+
+/// Receives the next response or update of a client, waiting up to `timeout`
+/// seconds. Returns null if nothing arrived.
+///
+/// TDLib reference: `td_json_client.h` (`td_json_client_receive`)
+///
+/// # Safety
+///
+/// `client` must be a handle returned by [`td_json_client_create`] that was not
+/// destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn td_json_client_receive(
+    client: *mut c_void,
+    timeout: c_double,
+) -> *const c_char {
+    let result =
+        client_id(client).and_then(|id| ClientManager::global().receive(Some(id), timeout));
+    store_result(result)
+}
+
+/// Executes a synchronous request.
+///
+/// TDLib reference: `td_json_client.h` (`td_json_client_execute`)
+///
+/// # Safety
+///
+/// `request` must be a null-terminated UTF-8 string. `client` is ignored and
+/// may be null.
+#[no_mangle]
+pub unsafe extern "C" fn td_json_client_execute(
+    _client: *mut c_void,
+    request: *const c_char,
+) -> *const c_char {
+    td_execute(request)
+}
+
+/// Destroys a client created by [`td_json_client_create`].
+///
+/// TDLib reference: `td_json_client.h` (`td_json_client_destroy`)
+///
+/// # Safety
+///
+/// `client` must be null or a handle returned by [`td_json_client_create`] that
+/// was not destroyed yet. The handle must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn td_json_client_destroy(client: *mut c_void) {
+    if client.is_null() {
+        return;
+    }
+    let client = Box::from_raw(client.cast::<JsonClient>());
+    let _ = ClientManager::global().destroy(client.client_id);
+}
+
+/// Creates a new client and returns its identifier.
+///
+/// TDLib reference: `td_json_client.h` (`td_create_client_id`)
+#[no_mangle]
+pub extern "C" fn td_create_client_id() -> c_int {
+    ClientManager::global().create_client_id()
+}
+
+/// Sends a request to the client with the given identifier.
+///
+/// TDLib reference: `td_json_client.h` (`td_send`)
+///
+/// # Safety
+///
+/// `request` must be a null-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn td_send(client_id: c_int, request: *const c_char) {
+    if let Some(request) = to_str(request) {
+        let _ = ClientManager::global().send(client_id, request);
+    }
+}
+
+/// Receives the next response or update of any client, waiting up to
+/// `timeout` seconds. Returns null if nothing arrived.
+///
+/// TDLib reference: `td_json_client.h` (`td_receive`)
+#[no_mangle]
+pub extern "C" fn td_receive(timeout: c_double) -> *const c_char {
+    store_result(ClientManager::global().receive(None, timeout))
+}
+
+/// Executes a synchronous request.
+///
+/// TDLib reference: `td_json_client.h` (`td_execute`)
+///
+/// # Safety
+///
+/// `request` must be a null-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn td_execute(request: *const c_char) -> *const c_char {
+    store_result(to_str(request).map(ClientManager::execute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value as JsonValue;
+    use std::sync::Mutex;
+
+    /// Serializes tests that use the global manager's shared receive queue.
+    static GLOBAL_LOCK: Mutex<()> = Mutex::new(());
+
+    fn c_string(value: &str) -> CString {
+        CString::new(value).unwrap_or_default()
+    }
+
+    fn read(result: *const c_char) -> JsonValue {
+        // SAFETY: results are null or point into LAST_RESULT.
+        match unsafe { to_str(result) } {
+            Some(result) => serde_json::from_str(result).unwrap_or(JsonValue::Null),
+            None => JsonValue::Null,
+        }
+    }
+
+    #[test]
+    fn test_json_client_roundtrip() {
+        let _guard = GLOBAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let client = td_json_client_create();
+        assert!(!client.is_null());
+        let request = c_string(r#"{"@type":"getMe","@extra":"me"}"#);
+
+        // SAFETY: `client` is live until destroyed below.
+        unsafe {
+            let update = read(td_json_client_receive(client, 1.0));
+            assert_eq!(update["@type"], "updateAuthorizationState");
+
+            td_json_client_send(client, request.as_ptr());
+            let response = read(td_json_client_receive(client, 1.0));
+            assert_eq!(response["@type"], "user");
+            assert_eq!(response["@extra"], "me");
+
+            assert!(td_json_client_receive(client, 0.0).is_null());
+            td_json_client_destroy(client);
+        }
+    }
+
+    #[test]
+    fn test_client_id_api() {
+        let _guard = GLOBAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let client_id = td_create_client_id();
+        assert!(ClientManager::global().has_client(client_id));
+        let request = c_string(r#"{"@type":"getMe","@extra":1}"#);
+
+        // SAFETY: the request is a valid C string.
+        unsafe { td_send(client_id, request.as_ptr()) };
+        let update = read(td_receive(1.0));
+        assert_eq!(update["@type"], "updateAuthorizationState");
+        assert_eq!(update["@client_id"], client_id);
+        let response = read(td_receive(1.0));
+        assert_eq!(response["@type"], "user");
+        assert_eq!(response["@extra"], 1);
+        assert_eq!(response["@client_id"], client_id);
+        assert!(td_receive(0.0).is_null());
+
+        assert!(ClientManager::global().destroy(client_id).is_ok());
+    }
+
+    #[test]
+    fn test_execute() {
+        let entities = c_string(r#"{"@type":"getTextEntities","text":"hi"}"#);
+        let get_me = c_string(r#"{"@type":"getMe"}"#);
+
+        // SAFETY: the requests are valid C strings.
+        unsafe {
+            let response = read(td_execute(entities.as_ptr()));
+            assert_eq!(response["@type"], "textEntities");
+
+            let response = read(td_json_client_execute(ptr::null_mut(), get_me.as_ptr()));
+            assert_eq!(response["@type"], "error");
+
+            assert!(td_execute(ptr::null()).is_null());
+        }
+    }
+}
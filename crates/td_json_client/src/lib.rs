@@ -2,33 +2,46 @@
 //
 // Licensed under MIT OR Apache-2.0
 
-//! Rustgram TdJsonClient - JSON client interface types for Telegram MTProto.
+//! Rustgram TdJsonClient - TDLib-compatible JSON client interface.
 //!
-//! This module provides utility types and functions for working with TDLib's
-//! JSON client interface. The actual FFI calls are only available when the
-//! TDLib C library is linked.
+//! This crate implements TDLib's JSON interface on top of the rustgram request
+//! dispatcher. It builds as a `cdylib` exporting the same C functions as
+//! TDLib's `td_json_client.h` (see `include/td_json_client.h`), so existing
+//! TDLib bindings can load it unchanged.
 //!
 //! ## Overview
 //!
-//! The JSON client interface provides a way to interact with TDLib using JSON
-//! serialization:
-//!
+//! - [`ClientManager`] - Creates clients, dispatches requests and queues
+//!   responses and updates per client
+//! - [`ffi`] - The exported C functions
 //! - [`validate_json`] - Validates JSON strings for TDLib
 //!
+//! Every response carries the `@extra` field of its request, and every
+//! received object carries the `@client_id` of the client it belongs to.
+//!
 //! ## TDLib Correspondence
 //!
 //! | Rust function | C function | File |
 //! |---------------|------------|------|
+//! | [`ClientManager::create_client_id`] | `td_create_client_id`, `td_json_client_create` | `td_json_client.h` |
+//! | [`ClientManager::send`] | `td_send`, `td_json_client_send` | `td_json_client.h` |
+//! | [`ClientManager::receive`] | `td_receive`, `td_json_client_receive` | `td_json_client.h` |
+//! | [`ClientManager::execute`] | `td_execute`, `td_json_client_execute` | `td_json_client.h` |
+//! | [`ClientManager::destroy`] | `td_json_client_destroy` | `td_json_client.h` |
 //! | [`validate_json`] | N/A (helper) | - |
 //!
 //! ## Examples
 //!
 //! ```
-//! use rustgram_td_json_client::validate_json;
+//! use tdjson::{validate_json, ClientManager};
 //!
 //! // Validate JSON strings
 //! assert!(validate_json(r#"{"test": 123}"#).is_ok());
 //! assert!(validate_json("invalid json").is_err());
+//!
+//! // Synchronous requests don't need a client
+//! let response = ClientManager::execute(r#"{"@type": "getTextEntities", "text": "hi"}"#);
+//! assert!(response.contains("textEntities"));
 //! ```
 
 #![warn(missing_docs)]
@@ -36,6 +49,11 @@
 #![deny(clippy::unwrap_used)]
 #![deny(clippy::expect_used)]
 
+mod client_manager;
+pub mod ffi;
+
+pub use client_manager::ClientManager;
+
 /// Errors that can occur when working with the JSON client interface.
 #[derive(thiserror::Error, Debug)]
 pub enum TdJsonClientError {
@@ -76,7 +94,7 @@ pub type Result<T> = std::result::Result<T, TdJsonClientError>;
 /// # Examples
 ///
 /// ```
-/// use rustgram_td_json_client::validate_json;
+/// use tdjson::validate_json;
 ///
 /// assert!(validate_json(r#"{"test": 123}"#).is_ok());
 /// assert!(validate_json(r#"null"#).is_ok());
@@ -118,9 +136,7 @@ mod tests {
     #[test]
     fn test_json_error_message() {
         let result = validate_json("not json");
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert!(matches!(err, TdJsonClientError::JsonError(_)));
+        assert!(matches!(result, Err(TdJsonClientError::JsonError(_))));
     }
 
     #[test]
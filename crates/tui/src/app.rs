@@ -2,6 +2,10 @@
 //!
 //! This module contains the core application logic including the
 //! terminal setup, event loop, and rendering.
+//!
+//! [`AppView`] holds the widgets and reacts to keys and backend events
+//! without touching the terminal, so it can be drawn into any ratatui
+//! backend; [`RustgramTuiApp`] drives it on the real terminal.
 
 #![warn(missing_docs)]
 #![warn(clippy::all)]
@@ -15,11 +19,13 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use ratatui::{backend::CrosstermBackend, Terminal};
+use ratatui::{backend::CrosstermBackend, Frame, Terminal};
+use tracing::warn;
 
+use crate::backend::{BackendEvent, ChatBackend, MockBackend, DIALOG_PAGE_SIZE, HISTORY_PAGE_SIZE};
 use crate::error::{Result, TuiError};
 use crate::event::{InputHandler, KeyAction};
-use crate::mock::{generate_mock_dialogs, generate_mock_messages, generate_mock_user_info, ConnectionStatus};
+use crate::mock::{MockDialog, MockMessage};
 use crate::state::{AppState, FocusMode};
 use crate::ui::{
     ChatListWidget, InputAreaWidget, LayoutConfig, MessageViewWidget,
@@ -29,29 +35,22 @@ use crate::ui::widgets::{Interactive, Renderable};
 
 /// Main TUI application.
 pub struct RustgramTuiApp {
-    /// Application state.
-    state: AppState,
+    /// Widgets and application logic.
+    view: AppView,
     /// Terminal instance.
     terminal: Terminal<CrosstermBackend<std::io::Stdout>>,
-    /// Input handler.
-    input_handler: InputHandler,
-    /// Current theme (reserved for future use).
-    _theme: Theme,
-    /// Layout configuration.
-    layout_config: LayoutConfig,
-    /// Current focus mode.
-    focus: FocusMode,
-    /// Widgets.
-    status_bar: StatusBarWidget,
-    chat_list: ChatListWidget,
-    message_view: MessageViewWidget,
-    input_area: InputAreaWidget,
-    user_info: UserInfoWidget,
 }
 
 impl RustgramTuiApp {
-    /// Creates a new TUI application.
+    /// Creates a new TUI application showing mock data.
     pub fn new() -> Result<Self> {
+        Self::with_backend(Box::new(MockBackend::new()))
+    }
+
+    /// Creates a new TUI application showing the data of a backend.
+    pub fn with_backend(backend: Box<dyn ChatBackend>) -> Result<Self> {
+        let view = AppView::new(backend)?;
+
         // Setup terminal
         enable_raw_mode()
             .map_err(|e| TuiError::TerminalInit(format!("Failed to enable raw mode: {}", e)))?;
@@ -65,50 +64,7 @@ impl RustgramTuiApp {
         let terminal = Terminal::new(backend)
             .map_err(|e| TuiError::TerminalInit(format!("Failed to create terminal: {}", e)))?;
 
-        // Create application state
-        let state = AppState::new();
-        let theme = Theme::default();
-        let layout_config = LayoutConfig::default();
-
-        // Initialize with mock data
-        let dialogs = generate_mock_dialogs();
-        let messages = generate_mock_messages(1, 20);
-        let user_info = generate_mock_user_info();
-
-        state.set_dialogs(dialogs.clone())?;
-        state.set_messages(messages.clone())?;
-        state.set_user_info(Some(user_info.clone()))?;
-        state.set_connection_status(ConnectionStatus::Connected)?;
-
-        // Create widgets
-        let mut status_bar = StatusBarWidget::new(theme);
-        status_bar.set_connection_status(ConnectionStatus::Connected);
-        status_bar.set_user_name("You".to_string());
-
-        let mut chat_list = ChatListWidget::new(theme);
-        chat_list.set_dialogs(dialogs);
-
-        let mut message_view = MessageViewWidget::new(theme);
-        message_view.set_messages(messages);
-
-        let input_area = InputAreaWidget::new(theme);
-
-        let mut user_info_widget = UserInfoWidget::new(theme);
-        user_info_widget.set_user_info(Some(user_info));
-
-        Ok(Self {
-            state,
-            terminal,
-            input_handler: InputHandler::new(),
-            _theme: theme,
-            layout_config,
-            focus: FocusMode::DialogList,
-            status_bar,
-            chat_list,
-            message_view,
-            input_area,
-            user_info: user_info_widget,
-        })
+        Ok(Self { view, terminal })
     }
 
     /// Runs the main application loop.
@@ -119,7 +75,7 @@ impl RustgramTuiApp {
         // Main event loop
         loop {
             // Check if we should quit
-            if self.state.should_quit()? {
+            if self.view.state().should_quit()? {
                 break;
             }
 
@@ -129,10 +85,13 @@ impl RustgramTuiApp {
             {
                 // Handle the event
                 if let Ok(Event::Key(key)) = crossterm::event::read() {
-                    self.handle_key_event(key)?;
+                    self.view.handle_key_event(key)?;
                 }
             }
 
+            // Apply live updates
+            self.view.process_backend_events()?;
+
             // Render the UI
             self.render()?;
         }
@@ -140,8 +99,107 @@ impl RustgramTuiApp {
         Ok(())
     }
 
+    /// Renders the UI.
+    fn render(&mut self) -> Result<()> {
+        let view = &self.view;
+        self.terminal
+            .draw(|f| view.draw(f))
+            .map_err(|e| TuiError::Render(format!("Failed to draw: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+impl Drop for RustgramTuiApp {
+    fn drop(&mut self) {
+        // Restore terminal state
+        disable_raw_mode().ok();
+        execute!(
+            self.terminal.backend_mut(),
+            LeaveAlternateScreen,
+            DisableMouseCapture
+        ).ok();
+        self.terminal.show_cursor().ok();
+    }
+}
+
+/// Widgets of the application and the logic connecting them to a backend.
+pub struct AppView {
+    /// Application state.
+    state: AppState,
+    /// Source of dialogs and messages.
+    backend: Box<dyn ChatBackend>,
+    /// Input handler.
+    input_handler: InputHandler,
+    /// Current theme (reserved for future use).
+    _theme: Theme,
+    /// Layout configuration.
+    layout_config: LayoutConfig,
+    /// Current focus mode.
+    focus: FocusMode,
+    /// Whether the backend has no more dialogs to load.
+    all_dialogs_loaded: bool,
+    /// Whether a page of dialogs is being loaded.
+    loading_dialogs: bool,
+    /// Whether the oldest message of the active dialog is loaded.
+    history_complete: bool,
+    /// Whether a page of history of the active dialog is being loaded.
+    loading_history: bool,
+    /// Widgets.
+    status_bar: StatusBarWidget,
+    chat_list: ChatListWidget,
+    message_view: MessageViewWidget,
+    input_area: InputAreaWidget,
+    user_info: UserInfoWidget,
+}
+
+impl AppView {
+    /// Creates the view and requests the first page of dialogs.
+    ///
+    /// The first dialog, if any, is opened once the page is loaded.
+    pub fn new(backend: Box<dyn ChatBackend>) -> Result<Self> {
+        let theme = Theme::default();
+        let status = backend.connection_status();
+
+        let mut status_bar = StatusBarWidget::new(theme);
+        status_bar.set_connection_status(status);
+        status_bar.set_user_name("You".to_string());
+
+        let mut view = Self {
+            state: AppState::new(),
+            backend,
+            input_handler: InputHandler::new(),
+            _theme: theme,
+            layout_config: LayoutConfig::default(),
+            focus: FocusMode::DialogList,
+            all_dialogs_loaded: false,
+            loading_dialogs: false,
+            history_complete: false,
+            loading_history: false,
+            status_bar,
+            chat_list: ChatListWidget::new(theme),
+            message_view: MessageViewWidget::new(theme),
+            input_area: InputAreaWidget::new(theme),
+            user_info: UserInfoWidget::new(theme),
+        };
+        view.state.set_connection_status(status)?;
+
+        view.load_more_dialogs()?;
+        Ok(view)
+    }
+
+    /// Gets the application state.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Gets the current focus mode.
+    pub fn focus(&self) -> FocusMode {
+        self.focus
+    }
+
     /// Handles a keyboard event.
-    fn handle_key_event(&mut self, key: KeyEvent) -> Result<()> {
+    pub fn handle_key_event(&mut self, key: KeyEvent) -> Result<()> {
         // Get the key action from the input handler
         let action = self.input_handler.handle_key(&key);
 
@@ -172,6 +230,10 @@ impl RustgramTuiApp {
             KeyAction::NavigateUp | KeyAction::NavigateDown => {
                 if self.focus == FocusMode::DialogList {
                     self.chat_list.handle_key(&key);
+                    // Fetch the next page when the end of the list is reached
+                    if action == KeyAction::NavigateDown && self.chat_list.is_last_selected() {
+                        self.load_more_dialogs()?;
+                    }
                     // Update selected dialog
                     if let Some(dialog) = self.chat_list.selected_dialog() {
                         self.user_info.set_dialog(Some(dialog.clone()));
@@ -181,12 +243,12 @@ impl RustgramTuiApp {
             KeyAction::ScrollUp | KeyAction::ScrollDown | KeyAction::PageUp | KeyAction::PageDown => {
                 if self.focus == FocusMode::MessageView {
                     self.message_view.handle_key(&key);
+                    self.load_older_messages_at_top()?;
                 }
             }
             KeyAction::SelectDialog => {
                 if let Some(id) = self.chat_list.selected_id() {
-                    self.state.set_active_dialog(Some(id))?;
-                    self.load_dialog_messages(id)?;
+                    self.open_dialog(id)?;
                 }
             }
             KeyAction::Backspace => {
@@ -209,6 +271,7 @@ impl RustgramTuiApp {
                     }
                     FocusMode::MessageView => {
                         self.message_view.handle_key(&key);
+                        self.load_older_messages_at_top()?;
                     }
                     FocusMode::InputArea => {
                         self.input_area.handle_key(&key);
@@ -222,95 +285,330 @@ impl RustgramTuiApp {
         Ok(())
     }
 
-    /// Sends a message.
-    fn send_message(&mut self, text: String) -> Result<()> {
-        use crate::mock::MockMessage;
+    /// Applies the events reported by the backend since the last call.
+    pub fn process_backend_events(&mut self) -> Result<()> {
+        for event in self.backend.poll_events() {
+            match event {
+                BackendEvent::DialogsLoaded(page) => self.on_dialogs_loaded(page)?,
+                BackendEvent::HistoryLoaded { dialog_id, before_message_id, messages } => {
+                    self.on_history_loaded(dialog_id, before_message_id, messages)?;
+                }
+                BackendEvent::MessageSent(message) => self.on_message_sent(message)?,
+                BackendEvent::RequestFailed(error) => {
+                    warn!("Backend request failed: {}", error);
+                    // Let the user repeat the request
+                    self.loading_dialogs = false;
+                    self.loading_history = false;
+                }
+                BackendEvent::NewMessage(message) => self.on_new_message(message)?,
+                BackendEvent::MessagesDeleted { dialog_id, message_ids } => {
+                    if self.state.active_dialog_id()? == Some(dialog_id) {
+                        let mut messages = self.state.messages()?;
+                        messages.retain(|m| !message_ids.contains(&m.id));
+                        self.show_messages(messages)?;
+                    }
+                }
+                BackendEvent::MessagesRead { dialog_id, max_message_id } => {
+                    if self.state.active_dialog_id()? == Some(dialog_id) {
+                        let mut messages = self.state.messages()?;
+                        for message in messages.iter_mut().filter(|m| m.is_outgoing) {
+                            if message.id <= max_message_id {
+                                message.is_read = true;
+                            }
+                        }
+                        self.show_messages(messages)?;
+                    }
+                }
+                BackendEvent::ConnectionStatus(status) => {
+                    self.state.set_connection_status(status)?;
+                    self.status_bar.set_connection_status(status);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        // Get the active dialog ID
-        let dialog_id = self.state.active_dialog_id()?.unwrap_or(1);
+    /// Draws all widgets into a frame.
+    pub fn draw(&self, frame: &mut Frame) {
+        // Calculate layout
+        let layout = TuiLayout::calculate(frame.area(), &self.layout_config);
 
-        // Create a new outgoing message
-        let msg = MockMessage::outgoing(
-            (std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map(|d| d.as_secs() as i64)
-                .unwrap_or(0)) % 10000,
-            dialog_id,
-            text.clone(),
-        );
+        // Render widgets
+        self.status_bar.render(frame, layout.status_bar);
+        self.chat_list.render(frame, layout.chat_list);
+        self.message_view.render(frame, layout.message_view);
+        self.input_area.render(frame, layout.input_area);
 
-        // Add to message view
-        self.message_view.add_message(msg.clone());
+        if layout.is_user_info_visible() {
+            self.user_info.render(frame, layout.user_info);
+        }
+    }
 
-        // Update state
-        let mut messages = self.state.messages()?;
-        messages.push(msg);
-        self.state.set_messages(messages)?;
+    /// Requests the next page of dialogs, unless all are loaded or a page
+    /// is being loaded.
+    fn load_more_dialogs(&mut self) -> Result<()> {
+        if self.all_dialogs_loaded || self.loading_dialogs {
+            return Ok(());
+        }
+        self.backend.load_dialogs(DIALOG_PAGE_SIZE)?;
+        self.loading_dialogs = true;
+        Ok(())
+    }
 
-        // Update last message in dialog list
-        let mut dialogs = self.state.dialogs()?;
-        if let Some(dialog) = dialogs.iter_mut().find(|d| d.id == dialog_id) {
-            dialog.last_message = text;
+    /// Adds a loaded page of dialogs to the chat list.
+    ///
+    /// The first dialog is opened with the first page.
+    fn on_dialogs_loaded(&mut self, page: Vec<MockDialog>) -> Result<()> {
+        self.loading_dialogs = false;
+        if page.len() < DIALOG_PAGE_SIZE {
+            self.all_dialogs_loaded = true;
         }
-        self.state.set_dialogs(dialogs.clone())?;
-        self.chat_list.set_dialogs(dialogs);
+        if !page.is_empty() {
+            let mut dialogs = self.state.dialogs()?;
+            dialogs.extend(page);
+            self.show_dialogs(dialogs)?;
+        }
+        if self.state.active_dialog_id()?.is_none() {
+            if let Some(id) = self.chat_list.selected_id() {
+                self.open_dialog(id)?;
+            }
+        }
+        Ok(())
+    }
 
+    /// Opens a dialog and requests its newest messages.
+    fn open_dialog(&mut self, dialog_id: i64) -> Result<()> {
+        self.state.set_active_dialog(Some(dialog_id))?;
+        self.backend.load_history(dialog_id, None, HISTORY_PAGE_SIZE)?;
+        self.history_complete = false;
+        self.loading_history = true;
+        self.show_messages(Vec::new())?;
+        self.update_dialog(dialog_id, |dialog| dialog.unread_count = 0)?;
+
+        let user_info = self.backend.user_info(dialog_id)?;
+        self.state.set_user_info(user_info.clone())?;
+        self.user_info.set_user_info(user_info);
+        let dialog = self.state.active_dialog()?;
+        self.user_info.set_dialog(dialog);
         Ok(())
     }
 
-    /// Loads messages for a dialog.
-    fn load_dialog_messages(&mut self, dialog_id: i64) -> Result<()> {
-        let messages = generate_mock_messages(dialog_id, 20);
-        self.state.set_messages(messages.clone())?;
-        self.message_view.set_messages(messages);
+    /// Requests a page of older messages if the view is scrolled to the top.
+    fn load_older_messages_at_top(&mut self) -> Result<()> {
+        if self.history_complete || self.loading_history || !self.message_view.is_at_top() {
+            return Ok(());
+        }
+        let Some(dialog_id) = self.state.active_dialog_id()? else {
+            return Ok(());
+        };
+        let before = self.state.messages()?.first().map(|m| m.id);
+        self.backend.load_history(dialog_id, before, HISTORY_PAGE_SIZE)?;
+        self.loading_history = true;
         Ok(())
     }
 
-    /// Renders the UI.
-    fn render(&mut self) -> Result<()> {
-        let _terminal_size = self.terminal.size()
-            .map_err(|e| TuiError::Render(format!("Failed to get terminal size: {}", e)))?;
+    /// Shows a loaded page of history of the active dialog.
+    ///
+    /// The newest messages are marked as read; pages of other dialogs and
+    /// pages not adjacent to the shown messages are outdated and dropped.
+    fn on_history_loaded(
+        &mut self,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        page: Vec<MockMessage>,
+    ) -> Result<()> {
+        if self.state.active_dialog_id()? != Some(dialog_id) {
+            return Ok(());
+        }
+        let mut messages = self.state.messages()?;
+        let Some(before_message_id) = before_message_id else {
+            self.loading_history = false;
+            self.history_complete = page.len() < HISTORY_PAGE_SIZE;
+            let Some(last_id) = page.last().map(|m| m.id) else {
+                return Ok(());
+            };
+            self.backend.mark_read(dialog_id, last_id)?;
+            // Messages received or sent while loading follow the page
+            let mut page = page;
+            page.extend(messages.into_iter().filter(|m| m.id > last_id));
+            return self.show_messages(page);
+        };
+        if messages.first().map(|m| m.id) != Some(before_message_id) {
+            return Ok(());
+        }
+        self.loading_history = false;
+        self.history_complete = page.len() < HISTORY_PAGE_SIZE;
+        if !page.is_empty() {
+            messages.splice(0..0, page.iter().cloned());
+            self.state.set_messages(messages)?;
+            self.message_view.prepend_messages(page);
+        }
+        Ok(())
+    }
 
-        self.terminal.draw(|f| {
-            // Calculate layout
-            let layout = TuiLayout::calculate(f.area(), &self.layout_config);
+    /// Sends a message to the active dialog.
+    fn send_message(&mut self, text: String) -> Result<()> {
+        let Some(dialog_id) = self.state.active_dialog_id()? else {
+            return Err(TuiError::State("No dialog is open".to_string()));
+        };
+        self.backend.send_message(dialog_id, text)
+    }
 
-            // Render widgets
-            self.status_bar.render(f, layout.status_bar);
-            self.chat_list.render(f, layout.chat_list);
-            self.message_view.render(f, layout.message_view);
-            self.input_area.render(f, layout.input_area);
+    /// Handles a message sent by the user.
+    fn on_message_sent(&mut self, message: MockMessage) -> Result<()> {
+        if self.state.active_dialog_id()? == Some(message.dialog_id) {
+            self.append_message(message)
+        } else {
+            self.update_dialog(message.dialog_id, |dialog| {
+                dialog.last_message = message.text.clone();
+                dialog.last_message_time = message.timestamp;
+            })
+        }
+    }
 
-            if layout.is_user_info_visible() {
-                self.user_info.render(f, layout.user_info);
-            }
-        }).map_err(|e| TuiError::Render(format!("Failed to draw: {}", e)))?;
+    /// Handles a message received from the backend.
+    fn on_new_message(&mut self, message: MockMessage) -> Result<()> {
+        if self.state.active_dialog_id()? == Some(message.dialog_id) {
+            // The user is looking at the dialog, so the message is read
+            self.backend.mark_read(message.dialog_id, message.id)?;
+            self.append_message(message.mark_read())
+        } else {
+            self.update_dialog(message.dialog_id, |dialog| {
+                dialog.last_message = message.text.clone();
+                dialog.last_message_time = message.timestamp;
+                if !message.is_outgoing {
+                    dialog.unread_count += 1;
+                }
+            })
+        }
+    }
 
+    /// Adds a message to the active dialog.
+    fn append_message(&mut self, message: MockMessage) -> Result<()> {
+        self.update_dialog(message.dialog_id, |dialog| {
+            dialog.last_message = message.text.clone();
+            dialog.last_message_time = message.timestamp;
+        })?;
+        let mut messages = self.state.messages()?;
+        messages.push(message.clone());
+        self.state.set_messages(messages)?;
+        self.message_view.add_message(message);
         Ok(())
     }
-}
 
-impl Drop for RustgramTuiApp {
-    fn drop(&mut self) {
-        // Restore terminal state
-        disable_raw_mode().ok();
-        execute!(
-            self.terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        ).ok();
-        self.terminal.show_cursor().ok();
+    /// Replaces the messages of the active dialog.
+    fn show_messages(&mut self, messages: Vec<MockMessage>) -> Result<()> {
+        self.state.set_messages(messages.clone())?;
+        self.message_view.set_messages(messages);
+        Ok(())
+    }
+
+    /// Modifies a dialog of the chat list.
+    fn update_dialog(&mut self, dialog_id: i64, update: impl FnOnce(&mut MockDialog)) -> Result<()> {
+        let mut dialogs = self.state.dialogs()?;
+        if let Some(dialog) = dialogs.iter_mut().find(|d| d.id == dialog_id) {
+            update(dialog);
+            self.show_dialogs(dialogs)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the chat list.
+    fn show_dialogs(&mut self, dialogs: Vec<MockDialog>) -> Result<()> {
+        let total_unread = dialogs.iter().map(|d| d.unread_count).sum();
+        self.status_bar.set_total_unread(total_unread);
+        self.state.set_dialogs(dialogs.clone())?;
+        self.chat_list.set_dialogs(dialogs);
+        Ok(())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::empty())
+    }
+
+    /// Creates a view and applies the first page of dialogs and the
+    /// history of the opened dialog.
+    fn loaded_view(backend: MockBackend) -> AppView {
+        let mut view = AppView::new(Box::new(backend)).unwrap();
+        assert_eq!(view.state().active_dialog_id().unwrap(), None);
+        view.process_backend_events().unwrap();
+        view.process_backend_events().unwrap();
+        view
+    }
 
     #[test]
-    fn test_app_creation() {
-        // Note: This test may not work in all environments due to terminal requirements
-        // It's here for documentation purposes
-        // In a real test environment, you would need to mock the terminal
+    fn test_view_opens_first_dialog() {
+        let view = loaded_view(MockBackend::new());
+        assert_eq!(view.state().active_dialog_id().unwrap(), Some(1));
+        assert_eq!(view.state().dialogs().unwrap().len(), 10);
+        assert_eq!(view.state().messages().unwrap().len(), HISTORY_PAGE_SIZE);
+        assert_eq!(view.state().active_dialog().unwrap().unwrap().unread_count, 0);
+    }
+
+    #[test]
+    fn test_view_loads_older_messages_at_top() {
+        let mut view = loaded_view(MockBackend::new());
+        view.handle_key_event(key(KeyCode::Char('l'))).unwrap();
+        view.process_backend_events().unwrap();
+        view.focus = FocusMode::MessageView;
+        view.input_handler.set_focus(FocusMode::MessageView);
+
+        view.handle_key_event(key(KeyCode::Home)).unwrap();
+        // The page is requested once while it is loading
+        view.handle_key_event(key(KeyCode::Home)).unwrap();
+        view.process_backend_events().unwrap();
+        let messages = view.state().messages().unwrap();
+        assert_eq!(messages.len(), 2 * HISTORY_PAGE_SIZE);
+        assert!(messages.windows(2).all(|w| w[0].id < w[1].id));
+    }
+
+    #[test]
+    fn test_view_drops_outdated_history() {
+        let mut view = loaded_view(MockBackend::new());
+        view.handle_key_event(key(KeyCode::Down)).unwrap();
+        view.handle_key_event(key(KeyCode::Enter)).unwrap();
+        view.handle_key_event(key(KeyCode::Up)).unwrap();
+        view.handle_key_event(key(KeyCode::Enter)).unwrap();
+        view.process_backend_events().unwrap();
+
+        assert_eq!(view.state().active_dialog_id().unwrap(), Some(1));
+        let messages = view.state().messages().unwrap();
+        assert_eq!(messages.len(), HISTORY_PAGE_SIZE);
+        assert!(messages.iter().all(|m| m.dialog_id == 1));
+    }
+
+    #[test]
+    fn test_view_sends_and_receives() {
+        let backend = MockBackend::new();
+        let mut view = loaded_view(backend.clone());
+
+        view.handle_key_event(key(KeyCode::Char('i'))).unwrap();
+        for c in "yo".chars() {
+            view.handle_key_event(key(KeyCode::Char(c))).unwrap();
+        }
+        view.handle_key_event(key(KeyCode::Enter)).unwrap();
+        view.process_backend_events().unwrap();
+        let last = view.state().messages().unwrap().pop().unwrap();
+        assert_eq!(last.text, "yo");
+        assert!(last.is_outgoing);
+
+        backend.receive_message(1, "Alice", "In view").unwrap();
+        backend.receive_message(2, "Bob", "Elsewhere").unwrap();
+        view.process_backend_events().unwrap();
+
+        let last = view.state().messages().unwrap().pop().unwrap();
+        assert_eq!(last.text, "In view");
+        assert!(last.is_read);
+        let dialogs = view.state().dialogs().unwrap();
+        assert_eq!(dialogs[0].unread_count, 0);
+        assert_eq!(dialogs[1].last_message, "Elsewhere");
+        assert_eq!(dialogs[1].unread_count, 1);
     }
 }
@@ -0,0 +1,597 @@
+//! Chat backend over the client managers.
+//!
+//! [`ManagersBackend`] loads the chat list through `DialogManager`, sends
+//! messages through `MessagesManager` and turns the updates delivered to
+//! `MessagesManager` into [`BackendEvent`]s. The connection status follows
+//! `UpdatesManager`: while it is fetching a difference the TUI shows
+//! "Connecting...". Channel updates recovered by `UpdatesManager` are
+//! applied to `MessagesManager`, and unfilled channel gaps are recovered by
+//! its gap timer while the backend exists.
+//!
+//! The requests of the managers are spawned on the runtime and their
+//! results are delivered through [`ChatBackend::poll_events`], so the UI
+//! thread never waits for the network.
+//!
+//! History pages come from `MessagesManager::get_chat_history`, which serves
+//! them from its local copy and requests only the missing ones, so the
+//! `MessagesManager` needs a history client. Messages received and sent
+//! during the session are kept here as well. Read marks are kept locally
+//! and sent with `MessagesManager::read_history`.
+
+#![warn(missing_docs)]
+#![warn(clippy::all)]
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Arc;
+
+use async_channel::{Receiver, Sender};
+use rustgram_dialog_manager::{Dialog, DialogManager, DialogPagination, NetworkClient};
+use rustgram_message_types::Message;
+use rustgram_messages_manager::{MessageUpdateCallback, MessagesManager, MAX_HISTORY_LIMIT};
+use rustgram_types::{ChannelId, DialogId, MessageId, Update, UpdateType};
+use rustgram_updates_manager::{
+    ChannelDialog, ChannelEventHandler, ChannelGapTimer, UpdatesManager,
+};
+use tokio::runtime::Handle;
+use tracing::warn;
+
+use crate::backend::{BackendEvent, ChatBackend};
+use crate::error::{Result, TuiError};
+use crate::mock::{ConnectionStatus, MockDialog, MockMessage, MockUserInfo, UserStatus};
+
+/// Chat backend serving the data of the client managers.
+///
+/// The managers are asynchronous; the backend spawns their futures on the
+/// given runtime and returns at once.
+pub struct ManagersBackend {
+    /// Runtime running the manager requests.
+    runtime: Handle,
+    /// Source of the chat list and dialog titles.
+    dialog_manager: DialogManager,
+    /// Network client used by `DialogManager`.
+    network_client: NetworkClient,
+    /// Message sender.
+    messages_manager: Arc<MessagesManager>,
+    /// Update state, used for the connection status.
+    updates_manager: Arc<UpdatesManager>,
+    /// Timer recovering the unfilled channel gaps.
+    _gap_timer: ChannelGapTimer,
+    /// Position of the next page of dialogs.
+    pagination: Option<DialogPagination>,
+    /// Whether the last page of dialogs was loaded.
+    all_dialogs_loaded: bool,
+    /// Whether a page of dialogs is being loaded.
+    loading_dialogs: bool,
+    /// Messages known in this session, by dialog and message identifier.
+    history: HashMap<i64, BTreeMap<i64, MockMessage>>,
+    /// Dialog of the current user.
+    my_dialog_id: DialogId,
+    /// Events forwarded by the update callback.
+    events: Receiver<BackendEvent>,
+    /// Sender of the results of the spawned requests.
+    results_sender: Sender<RequestResult>,
+    /// Results of the spawned requests.
+    results: Receiver<RequestResult>,
+    /// Last reported connection status.
+    connection_status: ConnectionStatus,
+}
+
+impl ManagersBackend {
+    /// Creates a backend and registers it for message updates.
+    ///
+    /// Messages sent by `my_dialog_id` are shown as outgoing.
+    pub fn new(
+        runtime: Handle,
+        dialog_manager: DialogManager,
+        network_client: NetworkClient,
+        messages_manager: Arc<MessagesManager>,
+        updates_manager: Arc<UpdatesManager>,
+        my_dialog_id: DialogId,
+    ) -> Self {
+        let (sender, events) = async_channel::unbounded();
+        messages_manager.set_update_callback(Box::new(UpdateForwarder {
+            sender,
+            dialog_manager: dialog_manager.clone(),
+            my_dialog_id,
+        }));
+        updates_manager.set_channel_event_handler(Arc::new(ChannelEventForwarder {
+            messages_manager: Arc::clone(&messages_manager),
+        }));
+        let gap_timer = updates_manager.start_channel_gap_timer();
+        let (results_sender, results) = async_channel::unbounded();
+
+        Self {
+            runtime,
+            dialog_manager,
+            network_client,
+            messages_manager,
+            updates_manager,
+            _gap_timer: gap_timer,
+            pagination: None,
+            all_dialogs_loaded: false,
+            loading_dialogs: false,
+            history: HashMap::new(),
+            my_dialog_id,
+            events,
+            results_sender,
+            results,
+            connection_status: ConnectionStatus::Connecting,
+        }
+    }
+
+    /// Returns the title of a dialog.
+    fn dialog_title(&self, dialog_id: DialogId) -> String {
+        dialog_title(&self.dialog_manager, dialog_id)
+    }
+
+    /// Returns the connection status derived from the update state.
+    fn current_status(&self) -> ConnectionStatus {
+        if self.connection_status == ConnectionStatus::Error {
+            ConnectionStatus::Error
+        } else if self.updates_manager.running_get_difference() {
+            ConnectionStatus::Connecting
+        } else {
+            ConnectionStatus::Connected
+        }
+    }
+
+    /// Spawns a request whose result is applied by the next
+    /// [`ChatBackend::poll_events`].
+    fn spawn_request<F>(&self, request: F)
+    where
+        F: Future<Output = RequestResult> + Send + 'static,
+    {
+        let sender = self.results_sender.clone();
+        self.runtime.spawn(async move {
+            // The result is dropped once the backend is gone
+            let _ = sender.send(request.await).await;
+        });
+    }
+
+    /// Applies the result of a spawned request and returns the event
+    /// reporting it.
+    fn on_request_result(&mut self, result: RequestResult) -> BackendEvent {
+        match result {
+            RequestResult::Dialogs(Ok((dialogs, next))) => {
+                self.loading_dialogs = false;
+                self.all_dialogs_loaded = next.is_none();
+                self.pagination = next;
+                let dialogs = dialogs
+                    .into_iter()
+                    .map(|dialog| self.convert_dialog(dialog))
+                    .collect();
+                BackendEvent::DialogsLoaded(dialogs)
+            }
+            RequestResult::Dialogs(Err(error)) => {
+                self.loading_dialogs = false;
+                self.connection_status = ConnectionStatus::Error;
+                BackendEvent::RequestFailed(format!("Failed to load dialogs: {}", error))
+            }
+            RequestResult::History {
+                dialog_id,
+                before_message_id,
+                limit,
+                messages: Ok(messages),
+            } => {
+                for mut message in messages {
+                    if let Some(known) = self
+                        .history
+                        .get(&message.dialog_id)
+                        .and_then(|known| known.get(&message.id))
+                    {
+                        message.is_read = known.is_read;
+                    }
+                    self.remember(message);
+                }
+                BackendEvent::HistoryLoaded {
+                    dialog_id,
+                    before_message_id,
+                    messages: self.history_page(dialog_id, before_message_id, limit),
+                }
+            }
+            RequestResult::History {
+                messages: Err(error),
+                ..
+            } => BackendEvent::RequestFailed(format!("Failed to load history: {}", error)),
+            RequestResult::Sent(Ok(message)) => {
+                self.remember(message.clone());
+                BackendEvent::MessageSent(message)
+            }
+            RequestResult::Sent(Err(error)) => {
+                BackendEvent::RequestFailed(format!("Failed to send message: {}", error))
+            }
+        }
+    }
+
+    /// Converts a dialog to its displayed form.
+    fn convert_dialog(&self, dialog: Dialog) -> MockDialog {
+        let id = dialog.id.to_encoded();
+        let last_message = self
+            .history
+            .get(&id)
+            .and_then(|messages| messages.values().next_back())
+            .map(|m| m.text.clone())
+            .unwrap_or_default();
+        MockDialog::new(
+            id,
+            self.dialog_title(dialog.id),
+            last_message,
+            dialog.unread_count.max(0) as usize,
+        )
+    }
+
+    /// Returns up to `limit` messages of the session history older than
+    /// `before_message_id`, oldest first.
+    fn history_page(
+        &self,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        limit: usize,
+    ) -> Vec<MockMessage> {
+        let Some(messages) = self.history.get(&dialog_id) else {
+            return Vec::new();
+        };
+        let older = match before_message_id {
+            Some(before) => messages.range(..before),
+            None => messages.range(..),
+        };
+        let mut page: Vec<MockMessage> = older.rev().take(limit).map(|(_, m)| m.clone()).collect();
+        page.reverse();
+        page
+    }
+
+    /// Stores a message in the session history.
+    fn remember(&mut self, message: MockMessage) {
+        self.history
+            .entry(message.dialog_id)
+            .or_default()
+            .insert(message.id, message);
+    }
+}
+
+impl ChatBackend for ManagersBackend {
+    fn connection_status(&self) -> ConnectionStatus {
+        self.connection_status
+    }
+
+    fn load_dialogs(&mut self, limit: usize) -> Result<()> {
+        if self.all_dialogs_loaded {
+            // The page is delivered like a loaded one
+            let _ = self
+                .results_sender
+                .try_send(RequestResult::Dialogs(Ok((Vec::new(), None))));
+            return Ok(());
+        }
+        if self.loading_dialogs {
+            return Ok(());
+        }
+        self.loading_dialogs = true;
+
+        let dialog_manager = self.dialog_manager.clone();
+        let network_client = self.network_client.clone();
+        let pagination = self.pagination.clone();
+        self.spawn_request(async move {
+            let result = dialog_manager
+                .load_dialogs(&network_client, pagination, limit)
+                .await;
+            RequestResult::Dialogs(result.map_err(|e| e.to_string()))
+        });
+        Ok(())
+    }
+
+    fn load_history(
+        &mut self,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        limit: usize,
+    ) -> Result<()> {
+        let peer = DialogId::from_encoded(dialog_id)
+            .map_err(|e| TuiError::Integration(format!("Invalid dialog {}: {}", dialog_id, e)))?;
+        let from_message_id = before_message_id.map_or_else(MessageId::default, MessageId);
+        if before_message_id.is_some() && !from_message_id.is_server() {
+            // Messages being sent are known only to this session, which
+            // provides the page
+            let _ = self.results_sender.try_send(RequestResult::History {
+                dialog_id,
+                before_message_id,
+                limit,
+                messages: Ok(Vec::new()),
+            });
+            return Ok(());
+        }
+        // The page starts with `before_message_id` itself, which is dropped
+        // from the result, so one more message is requested
+        let request_limit = limit + usize::from(before_message_id.is_some());
+        let request_limit = i32::try_from(request_limit)
+            .unwrap_or(MAX_HISTORY_LIMIT)
+            .min(MAX_HISTORY_LIMIT);
+
+        let messages_manager = Arc::clone(&self.messages_manager);
+        let dialog_manager = self.dialog_manager.clone();
+        let my_dialog_id = self.my_dialog_id;
+        self.spawn_request(async move {
+            let messages = messages_manager
+                .get_chat_history(peer, from_message_id, 0, request_limit, false)
+                .await
+                .map(|messages| {
+                    messages
+                        .iter()
+                        .map(|message| {
+                            let sender = dialog_title(&dialog_manager, message.sender_id);
+                            convert_message(message, sender, my_dialog_id)
+                        })
+                        .collect()
+                })
+                .map_err(|e| e.to_string());
+            RequestResult::History {
+                dialog_id,
+                before_message_id,
+                limit,
+                messages,
+            }
+        });
+        Ok(())
+    }
+
+    fn send_message(&mut self, dialog_id: i64, text: String) -> Result<()> {
+        let peer = DialogId::from_encoded(dialog_id)
+            .map_err(|e| TuiError::Integration(format!("Invalid dialog {}: {}", dialog_id, e)))?;
+        let messages_manager = Arc::clone(&self.messages_manager);
+        self.spawn_request(async move {
+            let result = messages_manager
+                .send_text(peer, text.clone(), None)
+                .await
+                .map(|message_id| MockMessage::outgoing(message_id.get(), dialog_id, text))
+                .map_err(|e| e.to_string());
+            RequestResult::Sent(result)
+        });
+        Ok(())
+    }
+
+    fn mark_read(&mut self, dialog_id: i64, max_message_id: i64) -> Result<()> {
+        if let Some(messages) = self.history.get_mut(&dialog_id) {
+            for message in messages.range_mut(..=max_message_id).map(|(_, m)| m) {
+                message.is_read = true;
+            }
+        }
+
+        let peer = DialogId::from_encoded(dialog_id)
+            .map_err(|e| TuiError::Integration(format!("Invalid dialog {}: {}", dialog_id, e)))?;
+        let messages_manager = Arc::clone(&self.messages_manager);
+        self.runtime.spawn(async move {
+            if let Err(e) = messages_manager
+                .read_history(peer, MessageId(max_message_id))
+                .await
+            {
+                warn!("Failed to read history of {:?}: {}", peer, e);
+            }
+        });
+        Ok(())
+    }
+
+    fn user_info(&mut self, dialog_id: i64) -> Result<Option<MockUserInfo>> {
+        let Ok(id) = DialogId::from_encoded(dialog_id) else {
+            return Ok(None);
+        };
+        Ok(Some(MockUserInfo {
+            id: dialog_id,
+            name: self.dialog_title(id),
+            username: None,
+            status: UserStatus::Offline,
+            bio: None,
+        }))
+    }
+
+    fn poll_events(&mut self) -> Vec<BackendEvent> {
+        let mut events = Vec::new();
+        while let Ok(result) = self.results.try_recv() {
+            events.push(self.on_request_result(result));
+        }
+        while let Ok(event) = self.events.try_recv() {
+            match &event {
+                BackendEvent::NewMessage(message) => self.remember(message.clone()),
+                BackendEvent::MessagesDeleted {
+                    dialog_id,
+                    message_ids,
+                } => {
+                    if let Some(messages) = self.history.get_mut(dialog_id) {
+                        for id in message_ids {
+                            messages.remove(id);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            events.push(event);
+        }
+
+        let status = self.current_status();
+        if status != self.connection_status {
+            self.connection_status = status;
+            events.push(BackendEvent::ConnectionStatus(status));
+        }
+        events
+    }
+}
+
+/// Result of a request spawned by a [`ManagersBackend`].
+enum RequestResult {
+    /// A page of dialogs and the position of the next one.
+    Dialogs(std::result::Result<(Vec<Dialog>, Option<DialogPagination>), String>),
+    /// A page of history; the messages are merged into the session
+    /// history, which provides the page.
+    History {
+        /// Dialog of the messages.
+        dialog_id: i64,
+        /// Message the page is older than.
+        before_message_id: Option<i64>,
+        /// Number of messages of the page.
+        limit: usize,
+        /// The loaded messages.
+        messages: std::result::Result<Vec<MockMessage>, String>,
+    },
+    /// A sent message.
+    Sent(std::result::Result<MockMessage, String>),
+}
+
+/// Update callback forwarding message updates to a [`ManagersBackend`].
+struct UpdateForwarder {
+    /// Channel read by the backend.
+    sender: Sender<BackendEvent>,
+    /// Source of sender names.
+    dialog_manager: DialogManager,
+    /// Dialog of the current user.
+    my_dialog_id: DialogId,
+}
+
+impl UpdateForwarder {
+    /// Queues an event; events are dropped once the backend is gone.
+    fn forward(&self, event: BackendEvent) {
+        let _ = self.sender.try_send(event);
+    }
+}
+
+impl MessageUpdateCallback for UpdateForwarder {
+    fn on_new_message(&self, message: Message) {
+        let sender = dialog_title(&self.dialog_manager, message.sender_id);
+        self.forward(BackendEvent::NewMessage(convert_message(
+            &message,
+            sender,
+            self.my_dialog_id,
+        )));
+    }
+
+    fn on_messages_deleted(&self, dialog_id: DialogId, message_ids: Vec<MessageId>) {
+        self.forward(BackendEvent::MessagesDeleted {
+            dialog_id: dialog_id.to_encoded(),
+            message_ids: message_ids.into_iter().map(MessageId::get).collect(),
+        });
+    }
+
+    fn on_messages_read(&self, dialog_id: DialogId, max_id: MessageId) {
+        self.forward(BackendEvent::MessagesRead {
+            dialog_id: dialog_id.to_encoded(),
+            max_message_id: max_id.get(),
+        });
+    }
+}
+
+/// Channel event handler applying the channel updates to `MessagesManager`.
+struct ChannelEventForwarder {
+    /// Owner of the channel messages.
+    messages_manager: Arc<MessagesManager>,
+}
+
+impl ChannelEventHandler for ChannelEventForwarder {
+    fn on_channel_update(&self, channel_id: ChannelId, update: &Update) {
+        // New channel messages carry no content; they are shown when the
+        // history is loaded
+        if let UpdateType::DeleteChannelMessages(update) = &update.update_type {
+            let message_ids: Vec<MessageId> = update
+                .message_ids
+                .iter()
+                .map(|&server_id| MessageId::from_server_id(server_id))
+                .collect();
+            self.messages_manager
+                .on_delete_channel_messages(DialogId::Channel(channel_id), &message_ids);
+        }
+    }
+
+    fn on_channel_difference_too_long(&self, channel_id: ChannelId, _dialog: &ChannelDialog) {
+        self.messages_manager
+            .on_channel_difference_too_long(DialogId::Channel(channel_id));
+    }
+}
+
+/// Returns the title of a dialog, or its identifier if it is unknown.
+fn dialog_title(dialog_manager: &DialogManager, dialog_id: DialogId) -> String {
+    dialog_manager
+        .get_dialog_title(dialog_id)
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| dialog_id.to_string())
+}
+
+/// Converts a message to its displayed form.
+///
+/// Messages without text are shown as their content type, e.g. `[Photo]`.
+fn convert_message(message: &Message, sender: String, my_dialog_id: DialogId) -> MockMessage {
+    let text = message
+        .content
+        .as_text()
+        .map(|text| text.text.text())
+        .or_else(|| message.content.caption().map(|caption| caption.text()))
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("[{}]", message.content_type()));
+    let is_outgoing = message.sender_id == my_dialog_id;
+
+    MockMessage {
+        id: message.id.get(),
+        dialog_id: message.dialog_id.to_encoded(),
+        sender: if is_outgoing {
+            "You".to_string()
+        } else {
+            sender
+        },
+        text,
+        timestamp: i64::from(message.date),
+        is_outgoing,
+        is_read: is_outgoing,
+        media_album_id: message.media_album_id,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use rustgram_formatted_text::FormattedText;
+    use rustgram_types::UserId;
+
+    fn user(id: i64) -> DialogId {
+        DialogId::from_user(UserId::new(id).unwrap())
+    }
+
+    fn message(sender: DialogId, text: &str) -> Message {
+        Message::new_with_date(
+            MessageId::from_server_id(7),
+            user(100),
+            sender,
+            1_700_000_000,
+            FormattedText::new(text),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_convert_incoming_message() {
+        let converted = convert_message(&message(user(100), "Hi"), "Alice".to_string(), user(1));
+        assert_eq!(converted.id, MessageId::from_server_id(7).get());
+        assert_eq!(converted.dialog_id, user(100).to_encoded());
+        assert_eq!(converted.sender, "Alice");
+        assert_eq!(converted.text, "Hi");
+        assert_eq!(converted.timestamp, 1_700_000_000);
+        assert!(!converted.is_outgoing);
+        assert!(!converted.is_read);
+    }
+
+    #[test]
+    fn test_convert_album_message() {
+        let mut album_message = message(user(100), "Trip");
+        album_message.media_album_id = 42;
+        let converted = convert_message(&album_message, "Alice".to_string(), user(1));
+        assert_eq!(converted.media_album_id, 42);
+        assert!(converted.is_in_album());
+    }
+
+    #[test]
+    fn test_convert_outgoing_message() {
+        let converted = convert_message(&message(user(1), "Hey"), "1".to_string(), user(1));
+        assert_eq!(converted.sender, "You");
+        assert!(converted.is_outgoing);
+        assert!(converted.is_read);
+    }
+}
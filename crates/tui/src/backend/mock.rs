@@ -0,0 +1,322 @@
+//! Mock chat backend.
+//!
+//! Serves the generated data of [`crate::mock`], so that the TUI can be run
+//! and tested without a network connection. Requests are answered at once:
+//! their results are queued for the next [`ChatBackend::poll_events`].
+
+#![warn(missing_docs)]
+#![warn(clippy::all)]
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::backend::{BackendEvent, ChatBackend};
+use crate::error::{Result, TuiError};
+use crate::mock::{
+    generate_mock_dialogs, generate_mock_messages, generate_mock_user_info, ConnectionStatus,
+    MockDialog, MockMessage, MockUserInfo,
+};
+
+/// Number of messages generated for every mock dialog.
+pub const MOCK_HISTORY_SIZE: usize = 100;
+
+/// Chat backend serving mock data.
+///
+/// Clones share the same data, so a test can keep a clone to inject events
+/// after handing the backend to the application.
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    /// Shared backend data.
+    inner: Arc<Mutex<MockBackendInner>>,
+}
+
+/// Data of a [`MockBackend`].
+#[derive(Debug)]
+struct MockBackendInner {
+    /// All dialogs, in chat list order.
+    dialogs: Vec<MockDialog>,
+    /// Number of dialogs already returned by `load_dialogs`.
+    loaded_dialog_count: usize,
+    /// Messages of every dialog, oldest first; generated on first access.
+    messages: HashMap<i64, Vec<MockMessage>>,
+    /// Events not yet polled.
+    events: VecDeque<BackendEvent>,
+    /// Current connection status.
+    connection_status: ConnectionStatus,
+}
+
+impl MockBackend {
+    /// Creates a backend with the generated mock dialogs.
+    pub fn new() -> Self {
+        Self::with_dialogs(generate_mock_dialogs())
+    }
+
+    /// Creates a backend with the given dialogs.
+    pub fn with_dialogs(dialogs: Vec<MockDialog>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(MockBackendInner {
+                dialogs,
+                loaded_dialog_count: 0,
+                messages: HashMap::new(),
+                events: VecDeque::new(),
+                connection_status: ConnectionStatus::Connected,
+            })),
+        }
+    }
+
+    /// Simulates an incoming message in a dialog and returns it.
+    pub fn receive_message(&self, dialog_id: i64, sender: &str, text: &str) -> Result<MockMessage> {
+        let mut inner = self.lock()?;
+        let id = inner.next_message_id(dialog_id);
+        let message = MockMessage::incoming(id, dialog_id, sender.to_string(), text.to_string());
+        inner.add_message(message.clone());
+        inner
+            .events
+            .push_back(BackendEvent::NewMessage(message.clone()));
+        Ok(message)
+    }
+
+    /// Simulates a change of the connection status.
+    pub fn set_connection_status(&self, status: ConnectionStatus) -> Result<()> {
+        let mut inner = self.lock()?;
+        inner.connection_status = status;
+        inner
+            .events
+            .push_back(BackendEvent::ConnectionStatus(status));
+        Ok(())
+    }
+
+    /// Queues an arbitrary event.
+    pub fn push_event(&self, event: BackendEvent) -> Result<()> {
+        self.lock()?.events.push_back(event);
+        Ok(())
+    }
+
+    /// Returns a copy of the dialog with the given identifier.
+    pub fn dialog(&self, dialog_id: i64) -> Result<Option<MockDialog>> {
+        Ok(self
+            .lock()?
+            .dialogs
+            .iter()
+            .find(|d| d.id == dialog_id)
+            .cloned())
+    }
+
+    /// Locks the shared data.
+    fn lock(&self) -> Result<MutexGuard<'_, MockBackendInner>> {
+        self.inner
+            .lock()
+            .map_err(|e| TuiError::State(format!("Failed to lock mock backend: {}", e)))
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBackendInner {
+    /// Returns the messages of a dialog, generating them on first access.
+    fn messages_mut(&mut self, dialog_id: i64) -> &mut Vec<MockMessage> {
+        self.messages
+            .entry(dialog_id)
+            .or_insert_with(|| generate_mock_messages(dialog_id, MOCK_HISTORY_SIZE))
+    }
+
+    /// Returns the identifier for a new message in a dialog.
+    fn next_message_id(&mut self, dialog_id: i64) -> i64 {
+        self.messages_mut(dialog_id)
+            .last()
+            .map(|m| m.id + 1)
+            .unwrap_or(dialog_id * 1000)
+    }
+
+    /// Stores a new message and updates its dialog.
+    fn add_message(&mut self, message: MockMessage) {
+        if let Some(dialog) = self.dialogs.iter_mut().find(|d| d.id == message.dialog_id) {
+            dialog.last_message = message.text.clone();
+            dialog.last_message_time = message.timestamp;
+            if !message.is_outgoing {
+                dialog.unread_count += 1;
+            }
+        }
+        self.messages_mut(message.dialog_id).push(message);
+    }
+}
+
+impl ChatBackend for MockBackend {
+    fn connection_status(&self) -> ConnectionStatus {
+        self.lock()
+            .map(|inner| inner.connection_status)
+            .unwrap_or(ConnectionStatus::Error)
+    }
+
+    fn load_dialogs(&mut self, limit: usize) -> Result<()> {
+        let mut inner = self.lock()?;
+        let start = inner.loaded_dialog_count;
+        let end = (start + limit).min(inner.dialogs.len());
+        inner.loaded_dialog_count = end;
+        let page = inner.dialogs[start..end].to_vec();
+        inner.events.push_back(BackendEvent::DialogsLoaded(page));
+        Ok(())
+    }
+
+    fn load_history(
+        &mut self,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        limit: usize,
+    ) -> Result<()> {
+        let mut inner = self.lock()?;
+        let messages = inner.messages_mut(dialog_id);
+        let end = match before_message_id {
+            Some(before) => messages.partition_point(|m| m.id < before),
+            None => messages.len(),
+        };
+        let start = end.saturating_sub(limit);
+        let messages = messages[start..end].to_vec();
+        inner.events.push_back(BackendEvent::HistoryLoaded {
+            dialog_id,
+            before_message_id,
+            messages,
+        });
+        Ok(())
+    }
+
+    fn send_message(&mut self, dialog_id: i64, text: String) -> Result<()> {
+        let mut inner = self.lock()?;
+        let id = inner.next_message_id(dialog_id);
+        let message = MockMessage::outgoing(id, dialog_id, text);
+        inner.add_message(message.clone());
+        inner.events.push_back(BackendEvent::MessageSent(message));
+        Ok(())
+    }
+
+    fn mark_read(&mut self, dialog_id: i64, max_message_id: i64) -> Result<()> {
+        let mut inner = self.lock()?;
+        let mut unread_count = 0;
+        for message in inner.messages_mut(dialog_id).iter_mut() {
+            if message.id <= max_message_id {
+                message.is_read = true;
+            } else if !message.is_outgoing && !message.is_read {
+                unread_count += 1;
+            }
+        }
+        if let Some(dialog) = inner.dialogs.iter_mut().find(|d| d.id == dialog_id) {
+            dialog.unread_count = unread_count;
+        }
+        Ok(())
+    }
+
+    fn user_info(&mut self, dialog_id: i64) -> Result<Option<MockUserInfo>> {
+        let inner = self.lock()?;
+        Ok(inner
+            .dialogs
+            .iter()
+            .find(|d| d.id == dialog_id)
+            .map(|dialog| MockUserInfo {
+                id: dialog.id,
+                name: dialog.title.clone(),
+                ..generate_mock_user_info()
+            }))
+    }
+
+    fn poll_events(&mut self) -> Vec<BackendEvent> {
+        self.lock()
+            .map(|mut inner| inner.events.drain(..).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Loads a page of dialogs and returns it.
+    fn load_dialogs(backend: &mut MockBackend, limit: usize) -> Vec<MockDialog> {
+        backend.load_dialogs(limit).unwrap();
+        match backend.poll_events().pop() {
+            Some(BackendEvent::DialogsLoaded(dialogs)) => dialogs,
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    /// Loads a page of history and returns it.
+    fn load_history(
+        backend: &mut MockBackend,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        limit: usize,
+    ) -> Vec<MockMessage> {
+        backend
+            .load_history(dialog_id, before_message_id, limit)
+            .unwrap();
+        match backend.poll_events().pop() {
+            Some(BackendEvent::HistoryLoaded { messages, .. }) => messages,
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_load_dialogs_pages() {
+        let mut backend = MockBackend::new();
+        assert_eq!(load_dialogs(&mut backend, 4).len(), 4);
+        assert_eq!(load_dialogs(&mut backend, 4)[0].id, 5);
+        assert_eq!(load_dialogs(&mut backend, 4).len(), 2);
+        assert!(load_dialogs(&mut backend, 4).is_empty());
+    }
+
+    #[test]
+    fn test_load_history_pages() {
+        let mut backend = MockBackend::new();
+        let newest = load_history(&mut backend, 1, None, 10);
+        assert_eq!(newest.len(), 10);
+        assert_eq!(newest[9].id, 1000 + MOCK_HISTORY_SIZE as i64 - 1);
+
+        let older = load_history(&mut backend, 1, Some(newest[0].id), 10);
+        assert_eq!(older.len(), 10);
+        assert_eq!(older[9].id + 1, newest[0].id);
+
+        let oldest = load_history(&mut backend, 1, Some(1005), 10);
+        assert_eq!(oldest.len(), 5);
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        let mut backend = MockBackend::new();
+        backend.send_message(2, "Hi".to_string()).unwrap();
+        let events = backend.poll_events();
+        assert!(matches!(
+            &events[..],
+            [BackendEvent::MessageSent(m)] if m.is_outgoing && m.dialog_id == 2
+        ));
+
+        let handle = backend.clone();
+        handle.receive_message(2, "Bob", "Hello").unwrap();
+        let events = backend.poll_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], BackendEvent::NewMessage(m) if m.text == "Hello"));
+        assert!(backend.poll_events().is_empty());
+
+        let history = load_history(&mut backend, 2, None, 2);
+        assert_eq!(history[0].text, "Hi");
+        assert_eq!(history[1].text, "Hello");
+        assert_eq!(backend.dialog(2).unwrap().unwrap().last_message, "Hello");
+    }
+
+    #[test]
+    fn test_mark_read() {
+        let mut backend = MockBackend::new();
+        let message = backend.clone().receive_message(3, "Alice", "Ping").unwrap();
+        backend.mark_read(3, message.id).unwrap();
+
+        assert_eq!(backend.dialog(3).unwrap().unwrap().unread_count, 0);
+        backend.poll_events();
+        let history = load_history(&mut backend, 3, None, 5);
+        assert!(history.iter().all(|m| m.is_read));
+    }
+}
@@ -0,0 +1,111 @@
+//! Chat backends for the TUI.
+//!
+//! The TUI never talks to the client managers directly: everything it shows
+//! comes from a [`ChatBackend`]. Two implementations exist:
+//!
+//! - [`MockBackend`] - generated dialogs and messages, used by the demo and
+//!   by the UI tests, which therefore run offline
+//! - `ManagersBackend` (feature `integration`) - an adapter over
+//!   `DialogManager`, `MessagesManager` and `UpdatesManager`
+
+#![warn(missing_docs)]
+#![warn(clippy::all)]
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+
+#[cfg(feature = "integration")]
+pub mod managers;
+pub mod mock;
+
+#[cfg(feature = "integration")]
+pub use managers::ManagersBackend;
+pub use mock::MockBackend;
+
+use crate::error::Result;
+use crate::mock::{ConnectionStatus, MockDialog, MockMessage, MockUserInfo};
+
+/// Number of dialogs requested per page of the chat list.
+pub const DIALOG_PAGE_SIZE: usize = 20;
+
+/// Number of messages requested per page of history.
+pub const HISTORY_PAGE_SIZE: usize = 30;
+
+/// Source of the data displayed by the TUI.
+///
+/// All calls are made from the UI thread and must return without waiting
+/// for the network: requests only start loading, and their results are
+/// delivered like the live changes through [`ChatBackend::poll_events`],
+/// which is called on every iteration of the event loop.
+pub trait ChatBackend {
+    /// Returns the current connection status.
+    fn connection_status(&self) -> ConnectionStatus;
+
+    /// Requests the next page of the chat list, delivered as
+    /// [`BackendEvent::DialogsLoaded`].
+    ///
+    /// The first request loads the first page; every following request
+    /// loads the page after the previous one, and an empty page once all
+    /// dialogs are loaded.
+    fn load_dialogs(&mut self, limit: usize) -> Result<()>;
+
+    /// Requests up to `limit` messages of a dialog older than
+    /// `before_message_id`, or the newest messages if it is `None`,
+    /// delivered as [`BackendEvent::HistoryLoaded`].
+    fn load_history(
+        &mut self,
+        dialog_id: i64,
+        before_message_id: Option<i64>,
+        limit: usize,
+    ) -> Result<()>;
+
+    /// Sends a text message; the message is delivered as
+    /// [`BackendEvent::MessageSent`] as it should be displayed.
+    fn send_message(&mut self, dialog_id: i64, text: String) -> Result<()>;
+
+    /// Marks all messages of a dialog up to `max_message_id` as read.
+    fn mark_read(&mut self, dialog_id: i64, max_message_id: i64) -> Result<()>;
+
+    /// Returns information about the user behind a dialog, if known.
+    fn user_info(&mut self, dialog_id: i64) -> Result<Option<MockUserInfo>>;
+
+    /// Returns the changes that happened since the previous call.
+    fn poll_events(&mut self) -> Vec<BackendEvent>;
+}
+
+/// Change or request result reported by a [`ChatBackend`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendEvent {
+    /// A page of the chat list was loaded.
+    DialogsLoaded(Vec<MockDialog>),
+    /// A page of history was loaded.
+    HistoryLoaded {
+        /// Dialog of the messages.
+        dialog_id: i64,
+        /// Message the page is older than, as requested.
+        before_message_id: Option<i64>,
+        /// The messages, oldest first.
+        messages: Vec<MockMessage>,
+    },
+    /// A message was sent.
+    MessageSent(MockMessage),
+    /// A request failed; the request may be made again.
+    RequestFailed(String),
+    /// A new message was received.
+    NewMessage(MockMessage),
+    /// Messages were deleted.
+    MessagesDeleted {
+        /// Dialog of the messages.
+        dialog_id: i64,
+        /// Identifiers of the deleted messages.
+        message_ids: Vec<i64>,
+    },
+    /// Outgoing messages were read by the other side.
+    MessagesRead {
+        /// Dialog of the messages.
+        dialog_id: i64,
+        /// Identifier of the last read message.
+        max_message_id: i64,
+    },
+    /// The connection status changed.
+    ConnectionStatus(ConnectionStatus),
+}
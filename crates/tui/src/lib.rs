@@ -8,7 +8,8 @@
 //! - **3-column layout**: Chat list, message view, and user info
 //! - **Interactive widgets**: Keyboard navigation, message input
 //! - **Multiple themes**: Dark, light, solarized, dracula, nord
-//! - **Mock data support**: For development without full backend
+//! - **Pluggable backends**: Live client managers (feature `integration`)
+//!   or mock data for development and offline UI tests
 //!
 //! # Example
 //!
@@ -22,12 +23,21 @@
 //! }
 //! ```
 //!
+//! # Backends
+//!
+//! Everything the TUI displays comes from a [`ChatBackend`].
+//! [`RustgramTuiApp::new`] uses the [`MockBackend`];
+//! [`RustgramTuiApp::with_backend`] accepts any other, such as
+//! `ManagersBackend`, which serves the dialogs and messages of the client
+//! managers and forwards their live updates.
+//!
 //! # Keyboard Shortcuts
 //!
 //! ## Dialog List (default focus)
 //! - `j` / `↓` - Move down
 //! - `k` / `↑` - Move up
 //! - `Enter` / `l` - Select dialog
+//! - Moving past the last dialog loads the next page of dialogs
 //! - `i` - Switch to input mode
 //! - `q` / `Esc` - Quit
 //!
@@ -38,6 +48,7 @@
 //! - `Home` / `End` - Jump to top/bottom
 //! - `h` - Switch to dialog list
 //! - `i` - Switch to input mode
+//! - Scrolling to the top loads older messages
 //!
 //! ## Input Area
 //! - Type to enter text
@@ -52,13 +63,17 @@
 #![deny(clippy::expect_used)]
 
 pub mod app;
+pub mod backend;
 pub mod error;
 pub mod event;
 pub mod mock;
 pub mod state;
 pub mod ui;
 
-pub use app::RustgramTuiApp;
+pub use app::{AppView, RustgramTuiApp};
+#[cfg(feature = "integration")]
+pub use backend::ManagersBackend;
+pub use backend::{BackendEvent, ChatBackend, MockBackend};
 pub use error::{Result, TuiError};
 pub use event::{EventHandler, InputHandler, KeyAction};
 pub use mock::{
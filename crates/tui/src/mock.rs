@@ -63,7 +63,7 @@ impl MockDialog {
 }
 
 /// Mock message in a conversation.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MockMessage {
     /// Unique message ID.
     pub id: i64,
@@ -79,6 +79,9 @@ pub struct MockMessage {
     pub is_outgoing: bool,
     /// Whether this message has been read.
     pub is_read: bool,
+    /// Album this message belongs to, 0 if none.
+    #[serde(default)]
+    pub media_album_id: i64,
 }
 
 impl MockMessage {
@@ -98,6 +101,7 @@ impl MockMessage {
             timestamp: now(),
             is_outgoing,
             is_read: false,
+            media_album_id: 0,
         }
     }
 
@@ -107,6 +111,17 @@ impl MockMessage {
         self
     }
 
+    /// Puts the message into an album.
+    pub fn in_album(mut self, media_album_id: i64) -> Self {
+        self.media_album_id = media_album_id;
+        self
+    }
+
+    /// Returns whether the message belongs to an album.
+    pub fn is_in_album(&self) -> bool {
+        self.media_album_id != 0
+    }
+
     /// Creates an incoming message.
     pub fn incoming(id: i64, dialog_id: i64, sender: String, text: String) -> Self {
         Self::new(id, dialog_id, sender, text, false)
@@ -220,6 +235,7 @@ pub fn generate_mock_messages(dialog_id: i64, count: usize) -> Vec<MockMessage>
             timestamp: time + (i as i64 * 300), // 5 minutes apart
             is_outgoing,
             is_read: is_outgoing || i < count - 2, // All except last 2 are read
+            media_album_id: 0,
         });
     }
 
@@ -269,6 +285,7 @@ pub fn simulate_incoming_message(dialogs: &mut [MockDialog], messages: &mut Vec<
             timestamp: now(),
             is_outgoing: false,
             is_read: false,
+            media_album_id: 0,
         };
 
         messages.push(new_msg);
@@ -80,6 +80,11 @@ impl ChatListWidget {
         }
     }
 
+    /// Returns whether the last dialog of the list is selected.
+    pub fn is_last_selected(&self) -> bool {
+        self.dialogs.is_empty() || self.selected_index + 1 == self.dialogs.len()
+    }
+
     /// Selects the previous dialog.
     pub fn select_previous(&mut self) {
         if !self.dialogs.is_empty() && self.selected_index > 0 {
@@ -96,8 +101,9 @@ impl ChatListWidget {
         let mut spans = Vec::new();
 
         // Title (truncated if needed)
-        let title = if dialog.title.len() > 25 {
-            format!("{}...", &dialog.title[..22])
+        // Titles of live dialogs may contain multi-byte characters
+        let title = if dialog.title.chars().count() > 25 {
+            format!("{}...", dialog.title.chars().take(22).collect::<String>())
         } else {
             dialog.title.clone()
         };
@@ -145,8 +151,8 @@ impl ChatListWidget {
 
     /// Formats the preview text for a dialog.
     fn format_preview(&self, dialog: &MockDialog) -> Line<'static> {
-        let preview = if dialog.last_message.len() > 35 {
-            format!("{}...", &dialog.last_message[..32])
+        let preview = if dialog.last_message.chars().count() > 35 {
+            format!("{}...", dialog.last_message.chars().take(32).collect::<String>())
         } else {
             dialog.last_message.clone()
         };
@@ -263,6 +269,7 @@ mod tests {
 
         widget.select_next();
         assert_eq!(widget.selected_index, 2);
+        assert!(widget.is_last_selected());
 
         widget.select_next();
         assert_eq!(widget.selected_index, 2); // Stays at last
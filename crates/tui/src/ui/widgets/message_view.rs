@@ -1,12 +1,15 @@
 //! Message view widget (center column).
 //!
-//! Displays messages in a chat with bubbles.
+//! Displays messages in a chat with bubbles. Adjacent messages of the same
+//! album are shown as a single unit.
 
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 #![deny(clippy::unwrap_used)]
 #![deny(clippy::expect_used)]
 
+use std::cell::Cell;
+
 use ratatui::{
     layout::Rect,
     style::{Color, Style},
@@ -25,6 +28,8 @@ pub struct MessageViewWidget {
     messages: Vec<MockMessage>,
     /// Vertical scroll offset.
     scroll_offset: usize,
+    /// Largest useful scroll offset, computed during the last render.
+    max_scroll: Cell<usize>,
     /// Auto-scroll to bottom on new messages.
     auto_scroll: bool,
     /// Theme for styling.
@@ -37,6 +42,7 @@ impl MessageViewWidget {
         Self {
             messages: Vec::new(),
             scroll_offset: 0,
+            max_scroll: Cell::new(usize::MAX),
             auto_scroll: true,
             theme,
         }
@@ -64,8 +70,34 @@ impl MessageViewWidget {
         }
     }
 
+    /// Prepends older messages, keeping the visible messages in place.
+    pub fn prepend_messages(&mut self, older: Vec<MockMessage>) {
+        // The last older message may join the album of the first shown one
+        let shown_lines = line_count(&self.messages);
+        let mut all = older;
+        all.append(&mut self.messages);
+        let added_lines = line_count(&all) - shown_lines;
+        self.messages = all;
+
+        self.clamp_scroll();
+        self.scroll_offset = self.scroll_offset.saturating_add(added_lines);
+        self.max_scroll
+            .set(self.max_scroll.get().saturating_add(added_lines));
+    }
+
+    /// Gets the messages being displayed.
+    pub fn messages(&self) -> &[MockMessage] {
+        &self.messages
+    }
+
+    /// Returns whether the view is scrolled to the first message.
+    pub fn is_at_top(&self) -> bool {
+        self.scroll_offset.min(self.max_scroll.get()) == 0
+    }
+
     /// Scrolls up one line.
     pub fn scroll_up(&mut self) {
+        self.clamp_scroll();
         if self.scroll_offset > 0 {
             self.scroll_offset -= 1;
         }
@@ -89,6 +121,7 @@ impl MessageViewWidget {
 
     /// Scrolls up one page.
     pub fn page_up(&mut self, page_height: usize) {
+        self.clamp_scroll();
         self.scroll_offset = self.scroll_offset.saturating_sub(page_height);
     }
 
@@ -97,6 +130,11 @@ impl MessageViewWidget {
         self.scroll_offset = self.scroll_offset.saturating_add(page_height);
     }
 
+    /// Limits the scroll offset to the content rendered last.
+    fn clamp_scroll(&mut self) {
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll.get());
+    }
+
     /// Toggles auto-scroll mode.
     pub fn toggle_auto_scroll(&mut self) {
         self.auto_scroll = !self.auto_scroll;
@@ -138,6 +176,33 @@ impl MessageViewWidget {
         ])
     }
 
+    /// Formats an album: a header with the sender, then one line per item.
+    fn format_album(&self, album: &[MockMessage]) -> Vec<Line<'static>> {
+        let Some(first) = album.first() else {
+            return Vec::new();
+        };
+        let mut header = self.format_message(first);
+        header.spans.truncate(3);
+        header.spans.push(TextStyles::dim(
+            &self.theme,
+            format!("Album of {}", album.len()),
+        ));
+
+        let text_style = if first.is_outgoing {
+            self.theme.outgoing_message_style()
+        } else {
+            self.theme.incoming_message_style()
+        };
+        let mut lines = vec![header];
+        lines.extend(album.iter().map(|msg| {
+            Line::from(vec![
+                Span::styled("  └ ", Style::default().fg(Color::DarkGray)),
+                Span::styled(msg.text.clone(), text_style),
+            ])
+        }));
+        lines
+    }
+
     /// Renders the message list as a Text widget.
     fn render_messages(&self, _area: Rect) -> Text<'static> {
         let mut lines = Vec::new();
@@ -153,9 +218,12 @@ impl MessageViewWidget {
             return Text::from(lines);
         }
 
-        // Format each message
-        for msg in &self.messages {
-            lines.push(self.format_message(msg));
+        // Format each message or album
+        for group in message_groups(&self.messages) {
+            match group {
+                [msg] => lines.push(self.format_message(msg)),
+                album => lines.extend(self.format_album(album)),
+            }
             lines.push(Line::from("")); // Empty line between messages
         }
 
@@ -163,6 +231,35 @@ impl MessageViewWidget {
     }
 }
 
+/// Splits messages into groups shown as a unit: albums and single messages.
+fn message_groups(messages: &[MockMessage]) -> Vec<&[MockMessage]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for end in 1..=messages.len() {
+        let same_album = end < messages.len()
+            && messages[start].is_in_album()
+            && messages[start].media_album_id == messages[end].media_album_id;
+        if !same_album {
+            groups.push(&messages[start..end]);
+            start = end;
+        }
+    }
+    groups
+}
+
+/// Returns the number of lines the messages take when rendered.
+fn line_count(messages: &[MockMessage]) -> usize {
+    message_groups(messages)
+        .into_iter()
+        .map(|group| match group {
+            // Message and the empty line after it
+            [_] => 2,
+            // Header, items and the empty line after them
+            album => album.len() + 2,
+        })
+        .sum()
+}
+
 impl Default for MessageViewWidget {
     fn default() -> Self {
         Self::new(Theme::default())
@@ -214,6 +311,10 @@ impl Interactive for MessageViewWidget {
 impl Renderable for MessageViewWidget {
     fn render(&self, frame: &mut Frame, area: Rect) {
         let messages = self.render_messages(area);
+        let visible_lines = area.height.saturating_sub(2) as usize;
+        self.max_scroll
+            .set(messages.lines.len().saturating_sub(visible_lines));
+        let scroll_offset = self.scroll_offset.min(self.max_scroll.get());
 
         let paragraph = Paragraph::new(messages)
             .block(
@@ -223,7 +324,7 @@ impl Renderable for MessageViewWidget {
             )
             .style(self.theme.normal_style())
             .wrap(Wrap { trim: true })
-            .scroll((self.scroll_offset as u16, 0));
+            .scroll((scroll_offset.min(u16::MAX as usize) as u16, 0));
 
         frame.render_widget(paragraph, area);
     }
@@ -341,6 +442,56 @@ mod tests {
         assert_eq!(widget.scroll_offset, 21);
     }
 
+    #[test]
+    fn test_message_view_prepend_messages() {
+        let mut widget = MessageViewWidget::new(Theme::default());
+        widget.messages = create_test_messages();
+        widget.scroll_to_top();
+        assert!(widget.is_at_top());
+
+        let older = vec![
+            MockMessage::incoming(-1, 100, "Alice".to_string(), "Older".to_string()),
+            MockMessage::incoming(0, 100, "Alice".to_string(), "Old".to_string()),
+        ];
+        widget.prepend_messages(older);
+
+        assert_eq!(widget.messages().len(), 5);
+        assert_eq!(widget.messages()[0].id, -1);
+        assert_eq!(widget.scroll_offset, 4);
+        assert!(!widget.is_at_top());
+    }
+
+    #[test]
+    fn test_message_view_album() {
+        let mut widget = MessageViewWidget::new(Theme::default());
+        let album = |id| {
+            MockMessage::incoming(id, 100, "Alice".to_string(), "[Photo]".to_string()).in_album(7)
+        };
+        widget.messages = vec![
+            MockMessage::incoming(1, 100, "Alice".to_string(), "Look".to_string()),
+            album(2),
+            album(3),
+            album(4),
+        ];
+
+        let text = widget.render_messages(Rect::default());
+        assert_eq!(text.lines.len(), 2 + 5);
+        assert_eq!(line_count(&widget.messages), text.lines.len());
+        let header: String = text.lines[2]
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert!(header.ends_with("Album of 3"));
+
+        // An older item of the album joins the shown album
+        widget.messages = vec![album(2), album(3)];
+        widget.scroll_to_top();
+        widget.prepend_messages(vec![album(1)]);
+        assert_eq!(widget.messages().len(), 3);
+        assert_eq!(widget.scroll_offset, 1);
+    }
+
     #[test]
     fn test_message_view_empty() {
         let widget = MessageViewWidget::new(Theme::default());
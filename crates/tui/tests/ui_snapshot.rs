@@ -0,0 +1,106 @@
+//! UI snapshot tests.
+//!
+//! The application is drawn with the mock backend into an in-memory
+//! terminal, so these tests run without a network connection or a tty.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::backend::TestBackend;
+use ratatui::Terminal;
+use rustgram_tui::{AppView, ConnectionStatus, MockBackend, MockDialog};
+
+const WIDTH: u16 = 140;
+const HEIGHT: u16 = 40;
+
+/// Draws the view and returns the screen as lines of text.
+fn snapshot(view: &AppView) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(WIDTH, HEIGHT)).unwrap();
+    terminal.draw(|f| view.draw(f)).unwrap();
+    let buffer = terminal.backend().buffer();
+    (0..HEIGHT)
+        .map(|y| {
+            (0..WIDTH)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect::<String>()
+        })
+        .collect()
+}
+
+fn contains(screen: &[String], text: &str) -> bool {
+    screen.iter().any(|line| line.contains(text))
+}
+
+fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::empty())
+}
+
+/// Creates a view showing the first page of dialogs and the history of the
+/// first dialog.
+fn loaded_view(backend: MockBackend) -> AppView {
+    let mut view = AppView::new(Box::new(backend)).unwrap();
+    view.process_backend_events().unwrap();
+    view.process_backend_events().unwrap();
+    view
+}
+
+#[test]
+fn test_initial_screen() {
+    let view = loaded_view(MockBackend::new());
+    let screen = snapshot(&view);
+
+    assert!(contains(&screen, "Chats"));
+    assert!(contains(&screen, "Alice Smith"));
+    assert!(contains(&screen, "Gaming Squad"));
+    assert!(contains(&screen, "Connected"));
+    // The newest message of the first dialog is at the bottom of the view
+    assert!(contains(&screen, "That makes sense"));
+}
+
+#[test]
+fn test_select_dialog_and_receive() {
+    let backend = MockBackend::with_dialogs(vec![
+        MockDialog::new(1, "Alice".to_string(), "Hello".to_string(), 0),
+        MockDialog::new(2, "Bob".to_string(), "Bye".to_string(), 0),
+    ]);
+    let mut view = loaded_view(backend.clone());
+
+    view.handle_key_event(key(KeyCode::Down)).unwrap();
+    view.handle_key_event(key(KeyCode::Enter)).unwrap();
+    view.process_backend_events().unwrap();
+    assert_eq!(view.state().active_dialog_id().unwrap(), Some(2));
+
+    backend.receive_message(2, "Bob", "Live update").unwrap();
+    backend.receive_message(1, "Alice", "Unseen").unwrap();
+    backend
+        .set_connection_status(ConnectionStatus::Connecting)
+        .unwrap();
+    view.process_backend_events().unwrap();
+
+    let screen = snapshot(&view);
+    assert!(contains(&screen, "Bob: Live update"));
+    assert!(!contains(&screen, "Alice: Unseen"));
+    assert!(contains(&screen, "Alice                     [1]"));
+    assert!(contains(&screen, "Connecting..."));
+}
+
+#[test]
+fn test_chat_list_pages() {
+    let dialogs = (1..=45)
+        .map(|id| MockDialog::new(id, format!("Dialog {id}"), String::new(), 0))
+        .collect();
+    let mut view = loaded_view(MockBackend::with_dialogs(dialogs));
+    assert_eq!(view.state().dialogs().unwrap().len(), 20);
+
+    for _ in 0..19 {
+        view.handle_key_event(key(KeyCode::Down)).unwrap();
+    }
+    view.process_backend_events().unwrap();
+    assert_eq!(view.state().dialogs().unwrap().len(), 40);
+    for _ in 0..20 {
+        view.handle_key_event(key(KeyCode::Down)).unwrap();
+    }
+    view.process_backend_events().unwrap();
+    assert_eq!(view.state().dialogs().unwrap().len(), 45);
+    assert!(contains(&snapshot(&view), "Dialog 40"));
+}
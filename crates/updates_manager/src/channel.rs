@@ -0,0 +1,946 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-channel update sequences.
+//!
+//! Updates of channels and supergroups carry the pts of their channel
+//! instead of the common one. An update is applied when its pts directly
+//! follows the channel's pts; otherwise it is buffered until the gap is
+//! filled by other updates or, after [`UpdatesManager::MAX_UNFILLED_GAP_TIME`],
+//! by `updates.getChannelDifference`.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustgram_types::{ChannelId, Update, UpdateType};
+use tracing::{debug, info, warn};
+
+use crate::{UpdatesError, UpdatesManager, UpdatesResult};
+
+/// Parameters of an `updates.getChannelDifference` request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelDifferenceRequest {
+    /// The channel to get the difference of
+    pub channel_id: ChannelId,
+    /// The last applied pts of the channel
+    pub pts: i32,
+    /// Maximum number of events to return
+    pub limit: i32,
+    /// Whether to skip some updates to reduce the difference
+    pub force: bool,
+}
+
+/// State of a channel dialog, sent when the difference is too long.
+///
+/// Message identifiers are server message identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelDialog {
+    /// Current pts of the channel
+    pub pts: i32,
+    /// Identifier of the last message
+    pub top_message: i32,
+    /// Identifier of the last read incoming message
+    pub read_inbox_max_id: i32,
+    /// Identifier of the last read outgoing message
+    pub read_outbox_max_id: i32,
+    /// Number of unread messages
+    pub unread_count: i32,
+    /// Number of unread mentions
+    pub unread_mentions_count: i32,
+}
+
+/// Result of `updates.getChannelDifference`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelDifference {
+    /// There are no new events (`updates.channelDifferenceEmpty`).
+    Empty {
+        /// Current pts of the channel
+        pts: i32,
+        /// Whether there are no more events to request
+        is_final: bool,
+        /// Suggested delay before the next request, in seconds
+        timeout: Option<i32>,
+    },
+    /// New events since the requested pts (`updates.channelDifference`).
+    Difference {
+        /// Pts after the returned events
+        pts: i32,
+        /// New messages
+        new_messages: Vec<Update>,
+        /// Other updates
+        other_updates: Vec<Update>,
+        /// Whether there are no more events to request
+        is_final: bool,
+        /// Suggested delay before the next request, in seconds
+        timeout: Option<i32>,
+    },
+    /// The difference is too long to be returned; the dialog must be
+    /// reloaded from scratch (`updates.channelDifferenceTooLong`).
+    TooLong {
+        /// Current state of the dialog
+        dialog: ChannelDialog,
+        /// The latest messages of the channel
+        messages: Vec<Update>,
+        /// Whether there are no more events to request
+        is_final: bool,
+        /// Suggested delay before the next request, in seconds
+        timeout: Option<i32>,
+    },
+}
+
+impl ChannelDifference {
+    /// Returns true if there are no more events to request.
+    #[must_use]
+    pub fn is_final(&self) -> bool {
+        match self {
+            Self::Empty { is_final, .. }
+            | Self::Difference { is_final, .. }
+            | Self::TooLong { is_final, .. } => *is_final,
+        }
+    }
+
+    /// Returns the suggested delay before the next request, in seconds.
+    #[must_use]
+    pub fn timeout(&self) -> Option<i32> {
+        match self {
+            Self::Empty { timeout, .. }
+            | Self::Difference { timeout, .. }
+            | Self::TooLong { timeout, .. } => *timeout,
+        }
+    }
+}
+
+/// Sends `updates.getChannelDifference` requests to the server.
+pub trait ChannelDifferenceClient: Send + Sync {
+    /// Requests the events of a channel since `request.pts`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; it is retried on the next gap
+    /// timeout.
+    fn get_channel_difference(
+        &self,
+        request: &ChannelDifferenceRequest,
+    ) -> UpdatesResult<ChannelDifference>;
+}
+
+/// Persistent storage of channel pts.
+pub trait ChannelPtsStorage: Send + Sync {
+    /// Returns the saved pts of a channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdatesError::Storage`] if the storage can't be read.
+    fn load_channel_pts(&self, channel_id: ChannelId) -> UpdatesResult<Option<i32>>;
+
+    /// Saves the pts of a channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdatesError::Storage`] if the storage can't be written.
+    fn save_channel_pts(&self, channel_id: ChannelId, pts: i32) -> UpdatesResult<()>;
+}
+
+impl ChannelPtsStorage for rustgram_td_db::ChannelPtsStore {
+    fn load_channel_pts(&self, channel_id: ChannelId) -> UpdatesResult<Option<i32>> {
+        self.get(channel_id.get())
+            .map_err(|e| UpdatesError::Storage(e.to_string()))
+    }
+
+    fn save_channel_pts(&self, channel_id: ChannelId, pts: i32) -> UpdatesResult<()> {
+        self.set(channel_id.get(), pts)
+            .map_err(|e| UpdatesError::Storage(e.to_string()))
+    }
+}
+
+/// Event produced by channel update processing, in the order it must be
+/// handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelEvent {
+    /// An update to apply.
+    Update {
+        /// The channel of the update
+        channel_id: ChannelId,
+        /// The update
+        update: Update,
+    },
+    /// The locally known history of the channel is outdated and must be
+    /// dropped; the dialog must be reloaded with the given state. The latest
+    /// messages follow as [`ChannelEvent::Update`] events.
+    DialogResync {
+        /// The channel to resynchronize
+        channel_id: ChannelId,
+        /// The new state of the dialog
+        dialog: ChannelDialog,
+    },
+}
+
+/// Receiver of the channel events, e.g. a bridge to the messages manager.
+///
+/// The events are delivered in order, from the thread that produced them.
+pub trait ChannelEventHandler: Send + Sync {
+    /// Applies an update of a channel.
+    fn on_channel_update(&self, channel_id: ChannelId, update: &Update);
+
+    /// Drops the locally known history of a channel whose difference was too
+    /// long. The latest messages follow as updates.
+    fn on_channel_difference_too_long(&self, channel_id: ChannelId, dialog: &ChannelDialog);
+}
+
+/// Channel update waiting for the preceding ones.
+#[derive(Debug, Clone)]
+struct PendingChannelUpdate {
+    /// Pts of the channel after the update
+    pts: i32,
+    /// Number of events in the update
+    pts_count: i32,
+    /// When the update was received
+    receive_time: f64,
+    /// The update
+    update: Update,
+}
+
+/// Update sequence state of a channel.
+#[derive(Debug, Default)]
+struct ChannelState {
+    /// Last applied pts, `None` while unknown
+    pts: Option<i32>,
+    /// Buffered updates ordered by pts
+    pending: Vec<PendingChannelUpdate>,
+    /// Whether getChannelDifference is running
+    running_difference: bool,
+    /// Delay before the next getChannelDifference suggested by the server
+    difference_timeout: Option<i32>,
+}
+
+impl ChannelState {
+    /// Buffers an update, keeping the pending updates ordered by pts.
+    fn add_pending(&mut self, update: PendingChannelUpdate) {
+        let position = self
+            .pending
+            .partition_point(|pending| pending.pts <= update.pts);
+        self.pending.insert(position, update);
+    }
+
+    /// Returns when the oldest buffered update was received.
+    fn gap_start(&self) -> Option<f64> {
+        self.pending
+            .iter()
+            .map(|pending| pending.receive_time)
+            .min_by(f64::total_cmp)
+    }
+}
+
+/// Per-channel state of the updates manager.
+#[derive(Default)]
+pub(crate) struct ChannelUpdates {
+    /// Known channels
+    states: Mutex<BTreeMap<ChannelId, ChannelState>>,
+    /// Persistent pts storage
+    storage: RwLock<Option<Arc<dyn ChannelPtsStorage>>>,
+    /// Client sending getChannelDifference
+    client: RwLock<Option<Arc<dyn ChannelDifferenceClient>>>,
+    /// Receiver of the channel events
+    handler: RwLock<Option<Arc<dyn ChannelEventHandler>>>,
+}
+
+impl Debug for ChannelUpdates {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let channel_count = self.states.lock().map_or(0, |states| states.len());
+        f.debug_struct("ChannelUpdates")
+            .field("channel_count", &channel_count)
+            .finish()
+    }
+}
+
+impl UpdatesManager {
+    /// Maximum number of events requested with one getChannelDifference.
+    pub const CHANNEL_DIFFERENCE_LIMIT: i32 = 100;
+
+    /// Number of buffered updates of a channel that triggers
+    /// getChannelDifference without waiting for the gap timeout.
+    pub const MAX_PENDING_CHANNEL_UPDATES: usize = 100;
+
+    /// Sets the storage used to persist channel pts.
+    ///
+    /// Channels already known keep their in-memory pts.
+    pub fn set_channel_pts_storage(&self, storage: Arc<dyn ChannelPtsStorage>) {
+        let mut current = self
+            .channels
+            .storage
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        *current = Some(storage);
+    }
+
+    /// Sets the client used to send `updates.getChannelDifference`.
+    pub fn set_channel_difference_client(&self, client: Arc<dyn ChannelDifferenceClient>) {
+        let mut current = self
+            .channels
+            .client
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        *current = Some(client);
+    }
+
+    /// Sets the receiver of the applied channel updates and of the dialog
+    /// resynchronizations.
+    pub fn set_channel_event_handler(&self, handler: Arc<dyn ChannelEventHandler>) {
+        let mut current = self
+            .channels
+            .handler
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        *current = Some(handler);
+    }
+
+    /// Returns the last applied pts of a channel, loading it from the
+    /// storage if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdatesError::LockPoisoned`] if the state lock is poisoned.
+    pub fn get_channel_pts(&self, channel_id: ChannelId) -> UpdatesResult<Option<i32>> {
+        let mut states = self.lock_channels()?;
+        Ok(self.channel_state(&mut states, channel_id).pts)
+    }
+
+    /// Sets the pts of a channel, e.g. from a dialog received from the
+    /// server, and applies the buffered updates that follow it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdatesError::LockPoisoned`] if the state lock is poisoned.
+    pub fn set_channel_pts(
+        &self,
+        channel_id: ChannelId,
+        pts: i32,
+    ) -> UpdatesResult<Vec<ChannelEvent>> {
+        let mut events = Vec::new();
+        {
+            let mut states = self.lock_channels()?;
+            let state = self.channel_state(&mut states, channel_id);
+            state.pts = Some(pts);
+            apply_pending(channel_id, state, &mut events);
+            let pts = state.pts.unwrap_or(pts);
+            self.save_channel_pts(channel_id, pts);
+        }
+        self.dispatch_channel_events(&events)?;
+        Ok(events)
+    }
+
+    /// Returns the number of buffered updates of a channel.
+    #[must_use]
+    pub fn get_pending_channel_update_count(&self, channel_id: ChannelId) -> usize {
+        self.lock_channels()
+            .ok()
+            .and_then(|states| states.get(&channel_id).map(|state| state.pending.len()))
+            .unwrap_or(0)
+    }
+
+    /// Returns the delay before the next getChannelDifference suggested by
+    /// the server in the last difference of a channel, in seconds.
+    #[must_use]
+    pub fn get_channel_difference_timeout(&self, channel_id: ChannelId) -> Option<i32> {
+        self.lock_channels()
+            .ok()?
+            .get(&channel_id)?
+            .difference_timeout
+    }
+
+    /// Processes an update carrying the pts of a channel.
+    ///
+    /// The update is applied if it directly follows the channel's pts,
+    /// skipped if it was already applied, and buffered otherwise. Buffered
+    /// updates are applied as soon as the gap before them is filled. A
+    /// large gap triggers getChannelDifference immediately; smaller ones
+    /// wait for [`UpdatesManager::process_expired_channel_gaps`].
+    ///
+    /// # Arguments
+    ///
+    /// * `channel_id` - The channel of the update
+    /// * `update` - The update
+    /// * `receive_time` - When the update was received, in seconds
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a required getChannelDifference fails; the update
+    /// stays buffered in that case.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rustgram_types::{ChannelId, MessageId, NewChannelMessageUpdate, Update, UpdateType};
+    /// use rustgram_updates_manager::UpdatesManager;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let manager = UpdatesManager::new();
+    /// let channel_id = ChannelId(1234);
+    /// let message = |pts| {
+    ///     Update::new(UpdateType::NewChannelMessage(NewChannelMessageUpdate::new(
+    ///         MessageId::from_server_id(pts),
+    ///         channel_id.get(),
+    ///     )))
+    ///     .with_pts(pts)
+    ///     .with_pts_count(1)
+    /// };
+    ///
+    /// manager.set_channel_pts(channel_id, 10)?;
+    /// // pts 12 arrives before pts 11 and waits for it
+    /// assert!(manager.process_channel_update(channel_id, message(12), 0.0)?.is_empty());
+    /// assert_eq!(manager.process_channel_update(channel_id, message(11), 0.1)?.len(), 2);
+    /// assert_eq!(manager.get_channel_pts(channel_id)?, Some(12));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn process_channel_update(
+        &self,
+        channel_id: ChannelId,
+        update: Update,
+        receive_time: f64,
+    ) -> UpdatesResult<Vec<ChannelEvent>> {
+        let mut events = Vec::new();
+        let Some(new_pts) = update.pts else {
+            events.push(ChannelEvent::Update { channel_id, update });
+            self.dispatch_channel_events(&events)?;
+            return Ok(events);
+        };
+        let pts_count = update.pts_count.unwrap_or(1);
+        let pending = PendingChannelUpdate {
+            pts: new_pts,
+            pts_count,
+            receive_time,
+            update,
+        };
+
+        let need_difference = {
+            let mut states = self.lock_channels()?;
+            let state = self.channel_state(&mut states, channel_id);
+            match state.pts {
+                _ if state.running_difference => {
+                    debug!(
+                        "Postpone update with pts {} in {:?} during getChannelDifference",
+                        new_pts, channel_id
+                    );
+                    state.add_pending(pending);
+                    false
+                }
+                None => {
+                    info!("Initialize pts of {:?} with {}", channel_id, new_pts);
+                    state.pts = Some(new_pts);
+                    events.push(ChannelEvent::Update {
+                        channel_id,
+                        update: pending.update,
+                    });
+                    self.save_channel_pts(channel_id, new_pts);
+                    false
+                }
+                Some(old_pts) if old_pts + pts_count == new_pts => {
+                    state.add_pending(pending);
+                    apply_pending(channel_id, state, &mut events);
+                    self.save_channel_pts(channel_id, state.pts.unwrap_or(new_pts));
+                    false
+                }
+                Some(old_pts) if new_pts <= old_pts => {
+                    debug!(
+                        "Skip already applied update with pts {} in {:?} with pts {}",
+                        new_pts, channel_id, old_pts
+                    );
+                    false
+                }
+                Some(old_pts) => {
+                    warn!(
+                        "Gap in {:?}: pts {}, received pts {} with pts_count {}",
+                        channel_id, old_pts, new_pts, pts_count
+                    );
+                    state.add_pending(pending);
+                    new_pts - old_pts > Self::FORCED_GET_DIFFERENCE_PTS_DIFF
+                        || state.pending.len() > Self::MAX_PENDING_CHANNEL_UPDATES
+                }
+            }
+        };
+        self.dispatch_channel_events(&events)?;
+
+        if need_difference {
+            events.extend(self.get_channel_difference(channel_id)?);
+        }
+        Ok(events)
+    }
+
+    /// Runs getChannelDifference for every channel whose oldest buffered
+    /// update has waited for [`UpdatesManager::MAX_UNFILLED_GAP_TIME`].
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - Current time, in the same clock as the receive times
+    ///
+    /// # Errors
+    ///
+    /// Returns the first getChannelDifference error; the other channels are
+    /// still processed.
+    pub fn process_expired_channel_gaps(&self, now: f64) -> UpdatesResult<Vec<ChannelEvent>> {
+        let expired: Vec<ChannelId> = {
+            let states = self.lock_channels()?;
+            states
+                .iter()
+                .filter(|(_, state)| {
+                    !state.running_difference
+                        && state
+                            .gap_start()
+                            .is_some_and(|start| start + Self::MAX_UNFILLED_GAP_TIME <= now)
+                })
+                .map(|(channel_id, _)| *channel_id)
+                .collect()
+        };
+
+        let mut events = Vec::new();
+        let mut first_error = None;
+        for channel_id in expired {
+            match self.get_channel_difference(channel_id) {
+                Ok(channel_events) => events.extend(channel_events),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(events),
+        }
+    }
+
+    /// Returns when the earliest unfilled channel gap expires, to schedule
+    /// [`UpdatesManager::process_expired_channel_gaps`].
+    #[must_use]
+    pub fn next_channel_gap_timeout(&self) -> Option<f64> {
+        self.lock_channels()
+            .ok()?
+            .values()
+            .filter(|state| !state.running_difference)
+            .filter_map(ChannelState::gap_start)
+            .min_by(f64::total_cmp)
+            .map(|start| start + Self::MAX_UNFILLED_GAP_TIME)
+    }
+
+    /// Starts a thread running
+    /// [`UpdatesManager::process_expired_channel_gaps`] whenever a gap
+    /// expires.
+    ///
+    /// The thread runs until the returned timer is dropped. The events are
+    /// delivered to the [`ChannelEventHandler`] from that thread.
+    #[must_use]
+    pub fn start_channel_gap_timer(self: &Arc<Self>) -> ChannelGapTimer {
+        ChannelGapTimer::start(Arc::clone(self))
+    }
+
+    /// Fetches the missed events of a channel with getChannelDifference.
+    ///
+    /// Requests are repeated until the server returns a final difference.
+    /// The channel state is only changed once the whole difference is
+    /// received, so a failed request can simply be retried. A too long
+    /// difference produces a [`ChannelEvent::DialogResync`] and drops the
+    /// buffered updates it covers.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdatesError::GetDifferenceFailed`] if no client is set, the
+    /// channel pts is unknown, or a request fails.
+    pub fn get_channel_difference(
+        &self,
+        channel_id: ChannelId,
+    ) -> UpdatesResult<Vec<ChannelEvent>> {
+        let client = self
+            .channels
+            .client
+            .read()
+            .map_err(|_| UpdatesError::LockPoisoned)?
+            .clone()
+            .ok_or_else(|| {
+                UpdatesError::GetDifferenceFailed("getChannelDifference client is not set".into())
+            })?;
+
+        let start_pts = {
+            let mut states = self.lock_channels()?;
+            let state = self.channel_state(&mut states, channel_id);
+            if state.running_difference {
+                return Ok(Vec::new());
+            }
+            let pts = state.pts.ok_or_else(|| {
+                UpdatesError::GetDifferenceFailed(format!("pts of {channel_id:?} is unknown"))
+            })?;
+            state.running_difference = true;
+            pts
+        };
+
+        info!("Get difference of {:?} from pts {}", channel_id, start_pts);
+        let result = fetch_channel_difference(client.as_ref(), channel_id, start_pts);
+
+        let mut states = self.lock_channels()?;
+        let state = self.channel_state(&mut states, channel_id);
+        state.running_difference = false;
+        let (pts, timeout, mut events) = match result {
+            Ok(fetched) => fetched,
+            Err(error) => {
+                warn!("getChannelDifference of {:?} failed: {}", channel_id, error);
+                return Err(error);
+            }
+        };
+        state.pts = Some(pts);
+        state.difference_timeout = timeout;
+        apply_pending(channel_id, state, &mut events);
+        self.save_channel_pts(channel_id, state.pts.unwrap_or(pts));
+        drop(states);
+
+        self.dispatch_channel_events(&events)?;
+        Ok(events)
+    }
+
+    /// Locks the channel states.
+    fn lock_channels(&self) -> UpdatesResult<MutexGuard<'_, BTreeMap<ChannelId, ChannelState>>> {
+        self.channels
+            .states
+            .lock()
+            .map_err(|_| UpdatesError::LockPoisoned)
+    }
+
+    /// Returns the state of a channel, loading its pts from the storage when
+    /// the channel is seen for the first time.
+    fn channel_state<'a>(
+        &self,
+        states: &'a mut BTreeMap<ChannelId, ChannelState>,
+        channel_id: ChannelId,
+    ) -> &'a mut ChannelState {
+        states.entry(channel_id).or_insert_with(|| {
+            let pts = self.storage().and_then(|storage| {
+                storage
+                    .load_channel_pts(channel_id)
+                    .unwrap_or_else(|error| {
+                        warn!("Failed to load pts of {:?}: {}", channel_id, error);
+                        None
+                    })
+            });
+            ChannelState {
+                pts,
+                ..ChannelState::default()
+            }
+        })
+    }
+
+    /// Returns the pts storage, if set.
+    fn storage(&self) -> Option<Arc<dyn ChannelPtsStorage>> {
+        self.channels.storage.read().ok()?.clone()
+    }
+
+    /// Persists the pts of a channel.
+    ///
+    /// A failure is only logged: the pts is saved again with the next update,
+    /// and an outdated saved pts only causes a longer difference.
+    fn save_channel_pts(&self, channel_id: ChannelId, pts: i32) {
+        if let Some(storage) = self.storage() {
+            if let Err(error) = storage.save_channel_pts(channel_id, pts) {
+                warn!("Failed to save pts {} of {:?}: {}", pts, channel_id, error);
+            }
+        }
+    }
+
+    /// Routes the updates and resynchronizations to their handlers.
+    fn dispatch_channel_events(&self, events: &[ChannelEvent]) -> UpdatesResult<()> {
+        let handler = self
+            .channels
+            .handler
+            .read()
+            .map_err(|_| UpdatesError::LockPoisoned)?
+            .clone();
+        for event in events {
+            match event {
+                ChannelEvent::Update { channel_id, update } => {
+                    self.route_update(&update.update_type)?;
+                    if let Some(handler) = &handler {
+                        handler.on_channel_update(*channel_id, update);
+                    }
+                    self.updates_processed
+                        .fetch_add(1, std::sync::atomic::Ordering::Release);
+                }
+                ChannelEvent::DialogResync { channel_id, dialog } => match &handler {
+                    Some(handler) => handler.on_channel_difference_too_long(*channel_id, dialog),
+                    None => warn!(
+                        "No handler to drop the outdated history of {:?}",
+                        channel_id
+                    ),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Longest wait between two checks for expired gaps; gaps appearing while
+/// the timer waits are noticed after at most this delay.
+const GAP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Delay before retrying after a failed getChannelDifference.
+const GAP_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Thread recovering the channel gaps left unfilled for
+/// [`UpdatesManager::MAX_UNFILLED_GAP_TIME`].
+///
+/// Created by [`UpdatesManager::start_channel_gap_timer`]; the thread is
+/// stopped when the timer is dropped.
+pub struct ChannelGapTimer {
+    /// Stop flag and its condition variable
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    /// The timer thread
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ChannelGapTimer {
+    /// Spawns the timer thread.
+    fn start(manager: Arc<UpdatesManager>) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = Arc::clone(&stop);
+        let thread = std::thread::Builder::new()
+            .name("channel-gap-timer".into())
+            .spawn(move || run_gap_timer(&manager, &thread_stop))
+            .map_err(|error| warn!("Failed to start the channel gap timer: {}", error))
+            .ok();
+        Self { stop, thread }
+    }
+}
+
+impl Debug for ChannelGapTimer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelGapTimer")
+            .field("running", &self.thread.is_some())
+            .finish()
+    }
+}
+
+impl Drop for ChannelGapTimer {
+    fn drop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        condvar.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Channel gap timer thread panicked");
+            }
+        }
+    }
+}
+
+/// Waits for the gaps to expire and recovers them until stopped.
+fn run_gap_timer(manager: &UpdatesManager, stop: &(Mutex<bool>, Condvar)) {
+    let (stopped, condvar) = stop;
+    let mut stopped_guard = stopped.lock().unwrap_or_else(|e| e.into_inner());
+    while !*stopped_guard {
+        let wait = manager
+            .next_channel_gap_timeout()
+            .map_or(GAP_CHECK_INTERVAL, |timeout| {
+                Duration::try_from_secs_f64(timeout - now())
+                    .unwrap_or(Duration::ZERO)
+                    .min(GAP_CHECK_INTERVAL)
+            });
+        if !wait.is_zero() {
+            stopped_guard = condvar
+                .wait_timeout(stopped_guard, wait)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+            continue;
+        }
+
+        drop(stopped_guard);
+        let result = manager.process_expired_channel_gaps(now());
+        stopped_guard = stopped.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(error) = result {
+            warn!("Failed to recover expired channel gaps: {}", error);
+            stopped_guard = condvar
+                .wait_timeout(stopped_guard, GAP_RETRY_DELAY)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+/// Requests difference pages until the final one.
+///
+/// Returns the final pts, the last suggested timeout and the events.
+fn fetch_channel_difference(
+    client: &dyn ChannelDifferenceClient,
+    channel_id: ChannelId,
+    start_pts: i32,
+) -> UpdatesResult<(i32, Option<i32>, Vec<ChannelEvent>)> {
+    let to_events = |updates: Vec<Update>| {
+        updates
+            .into_iter()
+            .map(move |update| ChannelEvent::Update { channel_id, update })
+    };
+
+    let mut pts = start_pts;
+    let mut events = Vec::new();
+    loop {
+        let request = ChannelDifferenceRequest {
+            channel_id,
+            pts,
+            limit: UpdatesManager::CHANNEL_DIFFERENCE_LIMIT,
+            force: true,
+        };
+        let difference = client.get_channel_difference(&request)?;
+        let is_final = difference.is_final();
+        let timeout = difference.timeout();
+        let new_pts = match difference {
+            ChannelDifference::Empty { pts: new_pts, .. } => new_pts,
+            ChannelDifference::Difference {
+                pts: new_pts,
+                new_messages,
+                other_updates,
+                ..
+            } => {
+                events.extend(to_events(new_messages));
+                events.extend(to_events(other_updates));
+                new_pts
+            }
+            ChannelDifference::TooLong {
+                dialog, messages, ..
+            } => {
+                warn!(
+                    "Difference of {:?} from pts {} is too long, resync at pts {}",
+                    channel_id, pts, dialog.pts
+                );
+                // everything received before is superseded by the new state
+                events.clear();
+                events.push(ChannelEvent::DialogResync { channel_id, dialog });
+                events.extend(to_events(messages));
+                dialog.pts
+            }
+        };
+        debug!(
+            "Receive difference of {:?}: pts {} -> {}, final = {}",
+            channel_id, pts, new_pts, is_final
+        );
+
+        if is_final {
+            return Ok((new_pts, timeout, events));
+        }
+        if new_pts <= pts {
+            return Err(UpdatesError::GetDifferenceFailed(format!(
+                "non-final difference of {channel_id:?} doesn't advance pts {pts}"
+            )));
+        }
+        pts = new_pts;
+    }
+}
+
+/// Applies the buffered updates that directly follow the channel's pts and
+/// drops the ones already covered by it.
+fn apply_pending(channel_id: ChannelId, state: &mut ChannelState, events: &mut Vec<ChannelEvent>) {
+    let Some(mut pts) = state.pts else {
+        return;
+    };
+    let mut applied = 0;
+    for pending in &state.pending {
+        if pending.pts - pending.pts_count > pts {
+            break;
+        }
+        if pending.pts - pending.pts_count == pts {
+            pts = pending.pts;
+            events.push(ChannelEvent::Update {
+                channel_id,
+                update: pending.update.clone(),
+            });
+        }
+        applied += 1;
+    }
+    state.pending.drain(..applied);
+    state.pts = Some(pts);
+}
+
+/// Returns the channel whose pts an update carries.
+pub(crate) fn update_channel_id(update_type: &UpdateType) -> Option<ChannelId> {
+    match update_type {
+        UpdateType::NewChannelMessage(update) => Some(ChannelId(update.channel_id)),
+        UpdateType::DeleteChannelMessages(update) => Some(ChannelId(update.channel_id)),
+        _ => None,
+    }
+}
+
+/// Returns the current time in seconds.
+pub(crate) fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustgram_types::DeleteChannelMessagesUpdate;
+
+    fn update(pts: i32, pts_count: i32) -> Update {
+        Update::new(UpdateType::DeleteChannelMessages(
+            DeleteChannelMessagesUpdate::new(1, vec![pts]),
+        ))
+        .with_pts(pts)
+        .with_pts_count(pts_count)
+    }
+
+    fn pending(pts: i32, pts_count: i32) -> PendingChannelUpdate {
+        PendingChannelUpdate {
+            pts,
+            pts_count,
+            receive_time: 0.0,
+            update: update(pts, pts_count),
+        }
+    }
+
+    #[test]
+    fn test_add_pending_orders_by_pts() {
+        let mut state = ChannelState::default();
+        state.add_pending(pending(5, 1));
+        state.add_pending(pending(3, 1));
+        state.add_pending(pending(4, 1));
+        let order: Vec<i32> = state.pending.iter().map(|p| p.pts).collect();
+        assert_eq!(order, [3, 4, 5]);
+    }
+
+    #[test]
+    fn test_apply_pending() {
+        let mut state = ChannelState {
+            pts: Some(2),
+            ..ChannelState::default()
+        };
+        for (pts, count) in [(2, 1), (4, 2), (5, 1), (7, 1)] {
+            state.add_pending(pending(pts, count));
+        }
+
+        let mut events = Vec::new();
+        apply_pending(ChannelId(1), &mut state, &mut events);
+        // pts 2 is already applied, 7 waits for 6
+        assert_eq!(events.len(), 2);
+        assert_eq!(state.pts, Some(5));
+        assert_eq!(state.pending.len(), 1);
+        assert_eq!(state.pending[0].pts, 7);
+    }
+
+    #[test]
+    fn test_update_channel_id() {
+        assert_eq!(
+            update_channel_id(&update(1, 1).update_type),
+            Some(ChannelId(1))
+        );
+        assert_eq!(update_channel_id(&UpdateType::Config), None);
+    }
+}
@@ -20,6 +20,8 @@
 //!
 //! This module handles processing and managing updates from Telegram servers,
 //! including PTS (Permanent Timestamp State) and QTS (Channel Timestamp) management.
+//! Channels and supergroups have their own pts sequences, tracked by the
+//! [`channel`] module and recovered with `updates.getChannelDifference`.
 //!
 //! ## TDLib Correspondence
 //!
@@ -28,7 +30,7 @@
 //! ## Example
 //!
 //! ```rust
-//! use rustgram-updates_manager::UpdatesManager;
+//! use rustgram_updates_manager::UpdatesManager;
 //!
 //! let manager = UpdatesManager::new();
 //! let pts = manager.get_pts();
@@ -41,6 +43,14 @@
 #![allow(clippy::module_name_repetitions)]
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
+pub mod channel;
+
+pub use channel::{
+    ChannelDialog, ChannelDifference, ChannelDifferenceClient, ChannelDifferenceRequest,
+    ChannelEvent, ChannelEventHandler, ChannelGapTimer, ChannelPtsStorage,
+};
+
+use channel::ChannelUpdates;
 use rustgram_types::DialogId;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
@@ -84,6 +94,10 @@ pub enum UpdatesError {
     /// Internal lock was poisoned
     #[error("Internal lock was poisoned")]
     LockPoisoned,
+
+    /// Persistent storage failed
+    #[error("Storage error: {0}")]
+    Storage(String),
 }
 
 /// Result type for updates operations.
@@ -187,6 +201,9 @@ pub struct UpdatesManager {
 
     /// Number of updates processed
     updates_processed: AtomicI64,
+
+    /// Per-channel pts state
+    channels: ChannelUpdates,
 }
 
 impl Default for UpdatesManager {
@@ -223,6 +240,7 @@ impl UpdatesManager {
             last_confirmed_pts: AtomicI32::new(0),
             last_confirmed_qts: AtomicI32::new(0),
             updates_processed: AtomicI64::new(0),
+            channels: ChannelUpdates::default(),
         }
     }
 
@@ -589,7 +607,8 @@ impl UpdatesManager {
     /// Processes an update from Telegram.
     ///
     /// This method handles the routing of updates to the appropriate managers
-    /// based on the update type. It also updates PTS/QTS state.
+    /// based on the update type. It also updates PTS/QTS state. Channel
+    /// updates are passed to [`UpdatesManager::process_channel_update`].
     ///
     /// # Arguments
     ///
@@ -607,7 +626,7 @@ impl UpdatesManager {
     ///
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// let manager = UpdatesManager::new();
-    /// let update = Update::new(UpdateType::NewMessage(NewMessageUpdate::new(MessageId::new(1))));
+    /// let update = Update::new(UpdateType::NewMessage(NewMessageUpdate::new(MessageId::from_server_id(1))));
     ///
     /// // Process the update - will be routed to MessagesManager
     /// manager.process_update(update)?;
@@ -615,6 +634,12 @@ impl UpdatesManager {
     /// # }
     /// ```
     pub fn process_update(&self, update: rustgram_types::Update) -> UpdatesResult<()> {
+        if let Some(channel_id) = channel::update_channel_id(&update.update_type) {
+            // The applied events are passed to the channel event handler
+            self.process_channel_update(channel_id, update, channel::now())?;
+            return Ok(());
+        }
+
         // Update PTS/QTS if present
         if let Some(pts) = update.pts {
             let pts_count = update.pts_count.unwrap_or(1);
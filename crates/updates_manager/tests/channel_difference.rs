@@ -0,0 +1,493 @@
+// Copyright 2025 rustgram-client contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Channel gap recovery against a scripted fake server.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rustgram_td_db::{ChannelPtsStore, TdDbParameters};
+use rustgram_types::{ChannelId, MessageId, NewChannelMessageUpdate, Update, UpdateType};
+use rustgram_updates_manager::{
+    ChannelDialog, ChannelDifference, ChannelDifferenceClient, ChannelDifferenceRequest,
+    ChannelEvent, ChannelEventHandler, UpdatesError, UpdatesManager, UpdatesResult,
+};
+
+const CHANNEL: ChannelId = ChannelId(1_000_000_001);
+
+/// Fake server answering getChannelDifference with scripted responses.
+#[derive(Default)]
+struct FakeServer {
+    responses: Mutex<VecDeque<UpdatesResult<ChannelDifference>>>,
+    requests: Mutex<Vec<ChannelDifferenceRequest>>,
+}
+
+impl FakeServer {
+    fn push(&self, response: UpdatesResult<ChannelDifference>) {
+        self.responses.lock().unwrap().push_back(response);
+    }
+
+    fn requested_pts(&self) -> Vec<i32> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.pts)
+            .collect()
+    }
+}
+
+impl ChannelDifferenceClient for FakeServer {
+    fn get_channel_difference(
+        &self,
+        request: &ChannelDifferenceRequest,
+    ) -> UpdatesResult<ChannelDifference> {
+        self.requests.lock().unwrap().push(*request);
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("unexpected getChannelDifference")
+    }
+}
+
+/// Handler recording the delivered events.
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<ChannelEvent>>,
+}
+
+impl ChannelEventHandler for Recorder {
+    fn on_channel_update(&self, channel_id: ChannelId, update: &Update) {
+        self.events.lock().unwrap().push(ChannelEvent::Update {
+            channel_id,
+            update: update.clone(),
+        });
+    }
+
+    fn on_channel_difference_too_long(&self, channel_id: ChannelId, dialog: &ChannelDialog) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(ChannelEvent::DialogResync {
+                channel_id,
+                dialog: *dialog,
+            });
+    }
+}
+
+/// Returns a new message update bringing the channel to `pts`.
+fn message(pts: i32) -> Update {
+    Update::new(UpdateType::NewChannelMessage(NewChannelMessageUpdate::new(
+        MessageId::from_server_id(pts),
+        CHANNEL.get(),
+    )))
+    .with_pts(pts)
+    .with_pts_count(1)
+}
+
+/// Returns the pts of the updates among the events.
+fn update_pts(events: &[ChannelEvent]) -> Vec<i32> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ChannelEvent::Update { update, .. } => update.pts,
+            ChannelEvent::DialogResync { .. } => None,
+        })
+        .collect()
+}
+
+fn difference(pts: i32, messages: &[i32], is_final: bool) -> ChannelDifference {
+    ChannelDifference::Difference {
+        pts,
+        new_messages: messages.iter().map(|&pts| message(pts)).collect(),
+        other_updates: Vec::new(),
+        is_final,
+        timeout: None,
+    }
+}
+
+fn setup(pts: i32) -> (UpdatesManager, Arc<FakeServer>) {
+    let manager = UpdatesManager::new();
+    let server = Arc::new(FakeServer::default());
+    manager.set_channel_difference_client(server.clone());
+    manager.set_channel_pts(CHANNEL, pts).unwrap();
+    (manager, server)
+}
+
+#[test]
+fn test_in_order_updates() {
+    let (manager, server) = setup(10);
+
+    for pts in 11..=13 {
+        let events = manager
+            .process_channel_update(CHANNEL, message(pts), 0.0)
+            .unwrap();
+        assert_eq!(update_pts(&events), [pts]);
+    }
+
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(13));
+    assert!(server.requested_pts().is_empty());
+}
+
+#[test]
+fn test_out_of_order_updates_fill_gap() {
+    let (manager, server) = setup(10);
+
+    assert!(manager
+        .process_channel_update(CHANNEL, message(13), 0.0)
+        .unwrap()
+        .is_empty());
+    assert!(manager
+        .process_channel_update(CHANNEL, message(12), 0.1)
+        .unwrap()
+        .is_empty());
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 2);
+
+    let events = manager
+        .process_channel_update(CHANNEL, message(11), 0.2)
+        .unwrap();
+    assert_eq!(update_pts(&events), [11, 12, 13]);
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 0);
+    assert!(server.requested_pts().is_empty());
+}
+
+#[test]
+fn test_duplicate_updates_are_skipped() {
+    let (manager, _server) = setup(10);
+
+    manager
+        .process_channel_update(CHANNEL, message(11), 0.0)
+        .unwrap();
+    let events = manager
+        .process_channel_update(CHANNEL, message(11), 0.1)
+        .unwrap();
+    assert!(events.is_empty());
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(11));
+}
+
+#[test]
+fn test_gap_timeout_runs_difference() {
+    let (manager, server) = setup(10);
+    server.push(Ok(difference(14, &[11, 12], true)));
+
+    manager
+        .process_channel_update(CHANNEL, message(13), 1.0)
+        .unwrap();
+    manager
+        .process_channel_update(CHANNEL, message(14), 1.2)
+        .unwrap();
+    assert_eq!(
+        manager.next_channel_gap_timeout(),
+        Some(1.0 + UpdatesManager::MAX_UNFILLED_GAP_TIME)
+    );
+
+    // the gap isn't expired yet
+    assert!(manager
+        .process_expired_channel_gaps(1.5)
+        .unwrap()
+        .is_empty());
+    assert!(server.requested_pts().is_empty());
+
+    let events = manager.process_expired_channel_gaps(2.0).unwrap();
+    assert_eq!(server.requested_pts(), [10]);
+    // the buffered updates are covered by the difference
+    assert_eq!(update_pts(&events), [11, 12]);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(14));
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 0);
+    assert_eq!(manager.next_channel_gap_timeout(), None);
+}
+
+#[test]
+fn test_pending_updates_after_difference_are_applied() {
+    let (manager, server) = setup(10);
+    server.push(Ok(difference(12, &[11, 12], true)));
+
+    manager
+        .process_channel_update(CHANNEL, message(13), 0.0)
+        .unwrap();
+    manager
+        .process_channel_update(CHANNEL, message(14), 0.0)
+        .unwrap();
+
+    let events = manager.process_expired_channel_gaps(1.0).unwrap();
+    assert_eq!(update_pts(&events), [11, 12, 13, 14]);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(14));
+}
+
+#[test]
+fn test_non_final_difference_is_continued() {
+    let (manager, server) = setup(10);
+    server.push(Ok(difference(12, &[11, 12], false)));
+    server.push(Ok(difference(14, &[13, 14], false)));
+    server.push(Ok(ChannelDifference::Empty {
+        pts: 14,
+        is_final: true,
+        timeout: Some(30),
+    }));
+
+    let events = manager.get_channel_difference(CHANNEL).unwrap();
+    assert_eq!(server.requested_pts(), [10, 12, 14]);
+    assert_eq!(update_pts(&events), [11, 12, 13, 14]);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(14));
+    assert_eq!(manager.get_channel_difference_timeout(CHANNEL), Some(30));
+}
+
+#[test]
+fn test_non_final_difference_without_progress_fails() {
+    let (manager, server) = setup(10);
+    server.push(Ok(ChannelDifference::Empty {
+        pts: 10,
+        is_final: false,
+        timeout: None,
+    }));
+
+    let result = manager.get_channel_difference(CHANNEL);
+    assert!(matches!(result, Err(UpdatesError::GetDifferenceFailed(_))));
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(10));
+}
+
+#[test]
+fn test_too_long_difference_resyncs_dialog() {
+    let (manager, server) = setup(10);
+    let dialog = ChannelDialog {
+        pts: 5000,
+        top_message: 4999,
+        read_inbox_max_id: 4990,
+        read_outbox_max_id: 4000,
+        unread_count: 9,
+        unread_mentions_count: 1,
+    };
+    server.push(Ok(ChannelDifference::TooLong {
+        dialog,
+        messages: vec![message(4998), message(4999)],
+        is_final: true,
+        timeout: None,
+    }));
+
+    // a stale update and one following the new state are buffered
+    manager
+        .process_channel_update(CHANNEL, message(20), 0.0)
+        .unwrap();
+    manager
+        .process_channel_update(CHANNEL, message(5001), 0.0)
+        .unwrap();
+
+    let events = manager.process_expired_channel_gaps(1.0).unwrap();
+    assert_eq!(
+        events[0],
+        ChannelEvent::DialogResync {
+            channel_id: CHANNEL,
+            dialog
+        }
+    );
+    assert_eq!(update_pts(&events), [4998, 4999, 5001]);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(5001));
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 0);
+}
+
+#[test]
+fn test_too_long_after_partial_difference_drops_partial_events() {
+    let (manager, server) = setup(10);
+    server.push(Ok(difference(12, &[11, 12], false)));
+    server.push(Ok(ChannelDifference::TooLong {
+        dialog: ChannelDialog {
+            pts: 900,
+            ..ChannelDialog::default()
+        },
+        messages: Vec::new(),
+        is_final: true,
+        timeout: None,
+    }));
+
+    let events = manager.get_channel_difference(CHANNEL).unwrap();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(events[0], ChannelEvent::DialogResync { .. }));
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(900));
+}
+
+#[test]
+fn test_server_error_keeps_state_and_retries() {
+    let (manager, server) = setup(10);
+    server.push(Ok(difference(12, &[11, 12], false)));
+    server.push(Err(UpdatesError::GetDifferenceFailed(
+        "FLOOD_WAIT_5".to_string(),
+    )));
+
+    manager
+        .process_channel_update(CHANNEL, message(14), 0.0)
+        .unwrap();
+    assert!(manager.process_expired_channel_gaps(1.0).is_err());
+    // nothing is applied from a partial difference
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(10));
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 1);
+
+    server.push(Ok(difference(13, &[11, 12, 13], true)));
+    let events = manager.process_expired_channel_gaps(2.0).unwrap();
+    assert_eq!(server.requested_pts(), [10, 12, 10]);
+    assert_eq!(update_pts(&events), [11, 12, 13, 14]);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(14));
+}
+
+#[test]
+fn test_huge_gap_runs_difference_immediately() {
+    let (manager, server) = setup(10);
+    server.push(Ok(ChannelDifference::Empty {
+        pts: 10 + UpdatesManager::FORCED_GET_DIFFERENCE_PTS_DIFF + 1,
+        is_final: true,
+        timeout: None,
+    }));
+
+    let pts = 10 + UpdatesManager::FORCED_GET_DIFFERENCE_PTS_DIFF + 2;
+    let events = manager
+        .process_channel_update(CHANNEL, message(pts), 0.0)
+        .unwrap();
+    assert_eq!(server.requested_pts(), [10]);
+    assert_eq!(update_pts(&events), [pts]);
+}
+
+#[test]
+fn test_channels_are_independent() {
+    let (manager, server) = setup(10);
+    let other = ChannelId(1_000_000_002);
+    manager.set_channel_pts(other, 100).unwrap();
+
+    manager
+        .process_channel_update(CHANNEL, message(12), 0.0)
+        .unwrap();
+    let update = Update::new(UpdateType::NewChannelMessage(NewChannelMessageUpdate::new(
+        MessageId::from_server_id(1),
+        other.get(),
+    )))
+    .with_pts(101)
+    .with_pts_count(1);
+    assert_eq!(
+        manager
+            .process_channel_update(other, update, 0.0)
+            .unwrap()
+            .len(),
+        1
+    );
+
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 1);
+    assert_eq!(manager.get_channel_pts(other).unwrap(), Some(101));
+    assert!(server.requested_pts().is_empty());
+}
+
+#[test]
+fn test_process_update_routes_channel_updates() {
+    let (manager, _server) = setup(10);
+
+    manager.process_update(message(11)).unwrap();
+    manager.process_update(message(13)).unwrap();
+
+    // the common pts is untouched
+    assert_eq!(manager.get_pts(), 0);
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(11));
+    assert_eq!(manager.get_pending_channel_update_count(CHANNEL), 1);
+    assert_eq!(manager.get_updates_processed(), 1);
+}
+
+#[test]
+fn test_handler_receives_updates_and_resync() {
+    let (manager, server) = setup(10);
+    let recorder = Arc::new(Recorder::default());
+    manager.set_channel_event_handler(recorder.clone());
+    let dialog = ChannelDialog {
+        pts: 5000,
+        top_message: 4999,
+        ..ChannelDialog::default()
+    };
+    server.push(Ok(ChannelDifference::TooLong {
+        dialog,
+        messages: vec![message(4999)],
+        is_final: true,
+        timeout: None,
+    }));
+
+    manager.process_update(message(11)).unwrap();
+    manager.process_update(message(5001)).unwrap();
+    manager.process_expired_channel_gaps(f64::MAX).unwrap();
+
+    let events = recorder.events.lock().unwrap().clone();
+    assert_eq!(
+        events[1],
+        ChannelEvent::DialogResync {
+            channel_id: CHANNEL,
+            dialog
+        }
+    );
+    assert_eq!(update_pts(&events), [11, 4999, 5001]);
+}
+
+#[test]
+fn test_gap_timer_recovers_expired_gap() {
+    let manager = Arc::new(UpdatesManager::new());
+    let server = Arc::new(FakeServer::default());
+    manager.set_channel_difference_client(server.clone());
+    manager.set_channel_pts(CHANNEL, 10).unwrap();
+    server.push(Ok(difference(11, &[11], true)));
+
+    let timer = manager.start_channel_gap_timer();
+    manager.process_update(message(12)).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while manager.get_channel_pts(CHANNEL).unwrap() != Some(12) {
+        assert!(Instant::now() < deadline, "gap wasn't recovered");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    drop(timer);
+    assert_eq!(server.requested_pts(), [10]);
+}
+
+#[test]
+fn test_pts_is_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let params = TdDbParameters::new(
+        dir.path().to_str().unwrap().to_string(),
+        "/files".to_string(),
+        false,
+        true,
+    );
+
+    {
+        let manager = UpdatesManager::new();
+        manager.set_channel_pts_storage(Arc::new(ChannelPtsStore::open(&params).unwrap()));
+        manager.set_channel_pts(CHANNEL, 10).unwrap();
+        manager
+            .process_channel_update(CHANNEL, message(11), 0.0)
+            .unwrap();
+    }
+
+    // after a restart the missed updates are requested from the saved pts
+    let manager = UpdatesManager::new();
+    let server = Arc::new(FakeServer::default());
+    manager.set_channel_pts_storage(Arc::new(ChannelPtsStore::open(&params).unwrap()));
+    manager.set_channel_difference_client(server.clone());
+    assert_eq!(manager.get_channel_pts(CHANNEL).unwrap(), Some(11));
+
+    server.push(Ok(difference(12, &[12], true)));
+    manager.get_channel_difference(CHANNEL).unwrap();
+    assert_eq!(server.requested_pts(), [11]);
+    assert_eq!(
+        ChannelPtsStore::open(&params)
+            .unwrap()
+            .get(CHANNEL.get())
+            .unwrap(),
+        Some(12)
+    );
+}